@@ -1,7 +1,9 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 
-use vanrijn::colour::{ColourRgbF, NamedColour};
-use vanrijn::materials::ReflectiveMaterial;
+use vanrijn::camera::{Lens, PerspectiveCamera, RenderSettings};
+use vanrijn::colour::{ColourRgbF, NamedColour, Spectrum};
+use vanrijn::lights::SkyGradient;
+use vanrijn::materials::{MaterialLibrary, ReflectiveMaterial};
 use vanrijn::math::Vec3;
 use vanrijn::mesh::load_obj;
 use vanrijn::partial_render_scene;
@@ -21,12 +23,17 @@ fn simple_scene(bencher: &mut Criterion) {
 
     bencher.bench_function("simple_scene", |b| {
         let scene = Scene {
-            camera_location: Vec3::new(-2.0, 1.0, -5.0),
+            camera: Box::new(PerspectiveCamera::new(
+                Vec3::new(-2.0, 1.0, -5.0),
+                Lens::Pinhole,
+            )),
             objects: vec![Box::new(BoundingVolumeHierarchy::build(
                 load_obj(
                     &model_file_path,
                     Arc::new(ReflectiveMaterial {
-                        colour: ColourRgbF::from_named(NamedColour::Yellow),
+                        colour: Spectrum::reflection_from_linear_rgb(&ColourRgbF::from_named(
+                            NamedColour::Yellow,
+                        )),
                         diffuse_strength: 0.05,
                         reflection_strength: 0.9,
                     }),
@@ -34,6 +41,12 @@ fn simple_scene(bencher: &mut Criterion) {
                 .unwrap()
                 .as_mut_slice(),
             ))],
+            environment: Box::new(SkyGradient::new()),
+            lights: vec![],
+            portals: vec![],
+            medium: None,
+            clipping_planes: vec![],
+            materials: MaterialLibrary::new(),
         };
         b.iter(|| {
             let tile = Tile {
@@ -42,7 +55,14 @@ fn simple_scene(bencher: &mut Criterion) {
                 start_row: 0,
                 end_row: image_height,
             };
-            partial_render_scene(&scene, tile, image_height, image_width);
+            partial_render_scene(
+                &scene,
+                tile,
+                image_height,
+                image_width,
+                0,
+                &RenderSettings::default(),
+            );
         })
     });
 }
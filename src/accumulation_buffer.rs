@@ -1,7 +1,25 @@
-use crate::colour::{ColourXyz, Photon};
-use crate::image::{ImageRgbU8, ToneMapper};
+use crate::colour::{ColourRgbU8, ColourXyz, Photon};
+use crate::image::{ImageRgbF, ImageRgbU8, ToneMapper};
+use crate::math::Vec3;
 use crate::util::{Array2D, Tile};
 
+use std::io::{Error, ErrorKind, Read, Write};
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"VRACCUM2";
+
+/// The colour that [mark_invalid_pixels()](AccumulationBuffer::mark_invalid_pixels) paints
+/// pixels with
+const INVALID_PIXEL_COLOUR: ColourRgbU8 = ColourRgbU8 {
+    values: [255, 0, 255],
+};
+
+/// Something that the renderer can add weighted samples of light to, pixel by pixel
+pub trait PhotonAccumulator {
+    /// Add `photon` to the pixel at `row` and `column`, with its intensity already divided
+    /// by the probability density of its wavelength
+    fn update_pixel(&mut self, row: usize, column: usize, photon: &Photon, weight: f64);
+}
+
 #[derive(Clone, Debug)]
 pub struct AccumulationBuffer {
     colour_buffer: Array2D<ColourXyz>,
@@ -9,6 +27,9 @@ pub struct AccumulationBuffer {
     colour_bias_buffer: Array2D<ColourXyz>,
     weight_buffer: Array2D<f64>,
     weight_bias_buffer: Array2D<f64>,
+
+    /// Number of samples dropped from each pixel because they were NaN or infinite
+    invalid_sample_buffer: Array2D<u32>,
 }
 
 impl AccumulationBuffer {
@@ -18,12 +39,14 @@ impl AccumulationBuffer {
         let colour_bias_buffer = Array2D::new(width, height);
         let weight_buffer = Array2D::new(width, height);
         let weight_bias_buffer = Array2D::new(width, height);
+        let invalid_sample_buffer = Array2D::new(width, height);
         AccumulationBuffer {
             colour_buffer,
             colour_sum_buffer,
             colour_bias_buffer,
             weight_buffer,
             weight_bias_buffer,
+            invalid_sample_buffer,
         }
     }
 
@@ -41,22 +64,132 @@ impl AccumulationBuffer {
         result
     }
 
-    pub fn update_pixel(&mut self, row: usize, column: usize, photon: &Photon, weight: f64) {
-        let buffer_colour = &mut self.colour_buffer[row][column];
-        let buffer_colour_sum = &mut self.colour_sum_buffer[row][column];
-        let buffer_colour_bias = &mut self.colour_bias_buffer[row][column];
-        let buffer_weight = &mut self.weight_buffer[row][column];
-        let buffer_weight_bias = &mut self.weight_bias_buffer[row][column];
-        let photon_colour = ColourXyz::from_photon(photon);
-        let weight_sum_y = weight - *buffer_weight_bias;
-        let weight_sum_t = *buffer_weight + weight_sum_y;
-        *buffer_weight_bias = (weight_sum_t - *buffer_weight) - weight_sum_y;
-        *buffer_weight = weight_sum_t;
-        let colour_sum_y = photon_colour.values * weight - buffer_colour_bias.values;
-        let colour_sum_t = buffer_colour_sum.values + colour_sum_y;
-        buffer_colour_bias.values = (colour_sum_t - buffer_colour_sum.values) - colour_sum_y;
-        buffer_colour_sum.values = colour_sum_t;
-        buffer_colour.values = buffer_colour_sum.values * (1.0 / *buffer_weight);
+    /// The image in linear RGB, without any tone mapping
+    pub fn to_image_rgb_f(&self) -> ImageRgbF {
+        let mut result = ImageRgbF::new(self.width(), self.height());
+        for row in 0..self.height() {
+            for column in 0..self.width() {
+                result.set_colour(row, column, self.colour_buffer[row][column].to_linear_rgb());
+            }
+        }
+        result
+    }
+
+    /// The mean colour of the samples added to the pixel
+    pub fn colour(&self, row: usize, column: usize) -> ColourXyz {
+        self.colour_buffer[row][column].clone()
+    }
+
+    /// The total weight of the samples added to the pixel
+    pub fn weight(&self, row: usize, column: usize) -> f64 {
+        self.weight_buffer[row][column]
+    }
+
+    /// The number of samples that were dropped from the pixel because their intensity or
+    /// weight was NaN or infinite
+    pub fn invalid_samples(&self, row: usize, column: usize) -> u32 {
+        self.invalid_sample_buffer[row][column]
+    }
+
+    /// The number of samples dropped from the whole image
+    pub fn total_invalid_samples(&self) -> u64 {
+        (0..self.height())
+            .flat_map(|row| (0..self.width()).map(move |column| (row, column)))
+            .map(|(row, column)| self.invalid_sample_buffer[row][column] as u64)
+            .sum()
+    }
+
+    /// Paint every pixel that had samples dropped from it magenta, to show where the
+    /// renderer is producing NaNs
+    ///
+    /// `image` must be the same size as the buffer, such as one returned by
+    /// [to_image_rgb_u8()](AccumulationBuffer::to_image_rgb_u8).
+    pub fn mark_invalid_pixels(&self, image: &mut ImageRgbU8) {
+        for row in 0..self.height() {
+            for column in 0..self.width() {
+                if self.invalid_sample_buffer[row][column] > 0 {
+                    image.set_colour(row, column, INVALID_PIXEL_COLOUR);
+                }
+            }
+        }
+    }
+
+    /// The mean colour of the samples added to each pixel
+    pub fn colours(&self) -> &Array2D<ColourXyz> {
+        &self.colour_buffer
+    }
+
+    /// Write the full state of the buffer to `writer`
+    ///
+    /// The buffer can be restored exactly with [read_from()](AccumulationBuffer::read_from),
+    /// so that more samples can be accumulated into it later.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&(self.width() as u64).to_le_bytes())?;
+        writer.write_all(&(self.height() as u64).to_le_bytes())?;
+        for row in 0..self.height() {
+            for column in 0..self.width() {
+                for colour in [
+                    &self.colour_buffer[row][column],
+                    &self.colour_sum_buffer[row][column],
+                    &self.colour_bias_buffer[row][column],
+                ]
+                .iter()
+                {
+                    for value in colour.values.coords.iter() {
+                        writer.write_all(&value.to_le_bytes())?;
+                    }
+                }
+                writer.write_all(&self.weight_buffer[row][column].to_le_bytes())?;
+                writer.write_all(&self.weight_bias_buffer[row][column].to_le_bytes())?;
+                writer.write_all(&self.invalid_sample_buffer[row][column].to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a buffer previously written with [write_to()](AccumulationBuffer::write_to)
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<AccumulationBuffer, Error> {
+        let (width, height) = read_header(reader)?;
+        AccumulationBuffer::read_pixels(reader, width, height)
+    }
+
+    /// As [read_from()](AccumulationBuffer::read_from), for a buffer that must be
+    /// `width` by `height` pixels
+    ///
+    /// A buffer of any other size is rejected without being read, so the size written by
+    /// an untrusted source can't be used to allocate an arbitrary amount of memory.
+    pub fn read_from_expecting<R: Read>(
+        reader: &mut R,
+        width: usize,
+        height: usize,
+    ) -> Result<AccumulationBuffer, Error> {
+        if read_header(reader)? != (width, height) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Accumulation buffer is the wrong size.",
+            ));
+        }
+        AccumulationBuffer::read_pixels(reader, width, height)
+    }
+
+    fn read_pixels<R: Read>(
+        reader: &mut R,
+        width: usize,
+        height: usize,
+    ) -> Result<AccumulationBuffer, Error> {
+        let mut result = AccumulationBuffer::new(width, height);
+        for row in 0..height {
+            for column in 0..width {
+                result.colour_buffer[row][column] = read_colour(reader)?;
+                result.colour_sum_buffer[row][column] = read_colour(reader)?;
+                result.colour_bias_buffer[row][column] = read_colour(reader)?;
+                result.weight_buffer[row][column] = read_f64(reader)?;
+                result.weight_bias_buffer[row][column] = read_f64(reader)?;
+                result.invalid_sample_buffer[row][column] = read_u32(reader)?;
+            }
+        }
+        Ok(result)
     }
 
     pub fn merge_tile(&mut self, tile: &Tile, src: &AccumulationBuffer) {
@@ -64,6 +197,13 @@ impl AccumulationBuffer {
         assert!(tile.height() == src.height());
         for i in 0..tile.height() {
             for j in 0..tile.width() {
+                self.invalid_sample_buffer[tile.start_row + i][tile.start_column + j] +=
+                    src.invalid_sample_buffer[i][j];
+                if src.weight_buffer[i][j] == 0.0 {
+                    // Nothing was added to this pixel, which can happen near the edges of a
+                    // tile rendered with a reconstruction filter
+                    continue;
+                }
                 let dst_colour = &mut self.colour_buffer[tile.start_row + i][tile.start_column + j];
                 let dst_weight = &mut self.weight_buffer[tile.start_row + i][tile.start_column + j];
                 *dst_colour = blend(
@@ -78,6 +218,70 @@ impl AccumulationBuffer {
     }
 }
 
+impl PhotonAccumulator for AccumulationBuffer {
+    fn update_pixel(&mut self, row: usize, column: usize, photon: &Photon, weight: f64) {
+        if !photon.intensity.is_finite() || !weight.is_finite() {
+            // A single NaN would stay in the pixel's mean forever, so the sample is dropped
+            // and counted instead
+            self.invalid_sample_buffer[row][column] += 1;
+            return;
+        }
+        let buffer_colour = &mut self.colour_buffer[row][column];
+        let buffer_colour_sum = &mut self.colour_sum_buffer[row][column];
+        let buffer_colour_bias = &mut self.colour_bias_buffer[row][column];
+        let buffer_weight = &mut self.weight_buffer[row][column];
+        let buffer_weight_bias = &mut self.weight_bias_buffer[row][column];
+        let photon_colour = ColourXyz::from_photon(photon);
+        let weight_sum_y = weight - *buffer_weight_bias;
+        let weight_sum_t = *buffer_weight + weight_sum_y;
+        *buffer_weight_bias = (weight_sum_t - *buffer_weight) - weight_sum_y;
+        *buffer_weight = weight_sum_t;
+        let colour_sum_y = photon_colour.values * weight - buffer_colour_bias.values;
+        let colour_sum_t = buffer_colour_sum.values + colour_sum_y;
+        buffer_colour_bias.values = (colour_sum_t - buffer_colour_sum.values) - colour_sum_y;
+        buffer_colour_sum.values = colour_sum_t;
+        buffer_colour.values = buffer_colour_sum.values * (1.0 / *buffer_weight);
+    }
+}
+
+/// Read the magic number and size at the start of a buffer written with
+/// [write_to()](AccumulationBuffer::write_to)
+fn read_header<R: Read>(reader: &mut R) -> Result<(usize, usize), Error> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != CHECKPOINT_MAGIC {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Not an accumulation buffer checkpoint.",
+        ));
+    }
+    let width = read_u64(reader)? as usize;
+    let height = read_u64(reader)? as usize;
+    Ok((width, height))
+}
+
+pub(crate) fn read_u64<R: Read>(reader: &mut R) -> Result<u64, Error> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, Error> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> Result<f64, Error> {
+    Ok(f64::from_bits(read_u64(reader)?))
+}
+
+fn read_colour<R: Read>(reader: &mut R) -> Result<ColourXyz, Error> {
+    Ok(ColourXyz {
+        values: Vec3::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?),
+    })
+}
+
 fn blend(colour1: &ColourXyz, weight1: f64, colour2: &ColourXyz, weight2: f64) -> ColourXyz {
     ColourXyz {
         values: (colour1.values * weight1 + colour2.values * weight2) * (1.0 / (weight1 + weight2)),
@@ -135,7 +339,8 @@ mod tests {
         let column = 5;
         let weight = 0.8;
         target.update_pixel(row, column, &photon, weight);
-        assert!(target.colour_buffer[row][column] == ColourXyz::from_photon(&photon));
+        let expected = ColourXyz::from_photon(&photon);
+        assert!((target.colour_buffer[row][column].values - expected.values).norm() < 1e-12);
         assert!(target.weight_buffer[row][column] == weight);
     }
 
@@ -325,4 +530,84 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn non_finite_samples_are_dropped_and_counted() {
+        let mut target = AccumulationBuffer::new(2, 2);
+        let photon = Photon {
+            wavelength: 500.0,
+            intensity: 1.0,
+        };
+        target.update_pixel(0, 1, &photon, 1.0);
+        target.update_pixel(0, 1, &photon.scale_intensity(f64::NAN), 1.0);
+        target.update_pixel(1, 0, &photon, f64::INFINITY);
+        assert!(target.colour(0, 1) == ColourXyz::from_photon(&photon));
+        assert!(target.weight(1, 0) == 0.0);
+        assert!(target.invalid_samples(0, 1) == 1);
+        assert!(target.total_invalid_samples() == 2);
+        let mut image = ImageRgbU8::new(2, 2);
+        target.mark_invalid_pixels(&mut image);
+        assert!(image.get_colour(0, 1).values == INVALID_PIXEL_COLOUR.values);
+        assert!(image.get_colour(0, 0).values != INVALID_PIXEL_COLOUR.values);
+    }
+
+    #[test]
+    fn buffer_read_from_checkpoint_matches_original() {
+        let mut original = AccumulationBuffer::new(5, 3);
+        for i in 0..3 {
+            for j in 0..5 {
+                let photon = Photon {
+                    wavelength: 400.0 + (i * 5 + j) as f64 * 10.0,
+                    intensity: 0.5 + i as f64,
+                };
+                original.update_pixel(i, j, &photon, 0.5 + j as f64 * 0.1);
+                original.update_pixel(i, j, &photon.scale_intensity(2.0), 1.0);
+            }
+        }
+        let mut checkpoint = Vec::new();
+        original.write_to(&mut checkpoint).unwrap();
+        let mut target = AccumulationBuffer::read_from(&mut checkpoint.as_slice()).unwrap();
+        assert!(target.width() == 5);
+        assert!(target.height() == 3);
+        let photon = Photon {
+            wavelength: 500.0,
+            intensity: 1.0,
+        };
+        original.update_pixel(1, 2, &photon, 1.0);
+        target.update_pixel(1, 2, &photon, 1.0);
+        for i in 0..3 {
+            for j in 0..5 {
+                assert!(target.colour_buffer[i][j] == original.colour_buffer[i][j]);
+                assert!(target.weight_buffer[i][j] == original.weight_buffer[i][j]);
+            }
+        }
+    }
+
+    #[test]
+    fn read_from_rejects_other_data() {
+        let data = b"not a checkpoint at all";
+        assert!(AccumulationBuffer::read_from(&mut &data[..]).is_err());
+    }
+
+    #[test]
+    fn read_from_expecting_rejects_other_sizes_before_allocating() {
+        let mut header = CHECKPOINT_MAGIC.to_vec();
+        header.extend_from_slice(&u64::MAX.to_le_bytes());
+        header.extend_from_slice(&u64::MAX.to_le_bytes());
+        let error = AccumulationBuffer::read_from_expecting(&mut header.as_slice(), 5, 3)
+            .err()
+            .unwrap();
+        assert!(error.kind() == ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn read_from_expecting_reads_buffers_of_expected_size() {
+        let mut checkpoint = Vec::new();
+        AccumulationBuffer::new(5, 3)
+            .write_to(&mut checkpoint)
+            .unwrap();
+        let target = AccumulationBuffer::read_from_expecting(&mut checkpoint.as_slice(), 5, 3);
+        assert!(target.is_ok());
+        assert!(AccumulationBuffer::read_from_expecting(&mut checkpoint.as_slice(), 3, 5).is_err());
+    }
 }
@@ -0,0 +1,180 @@
+//! Images of what each pixel sees, rendered alongside the image itself
+//!
+//! [ID buffers](IdBuffers) tell a compositor which object and material each pixel shows,
+//! and the [denoising AOVs](DenoisingAovs) let a [denoiser](crate::denoiser) tell noise
+//! from detail. Both are found from a single ray through the centre of each pixel.
+
+use crate::camera::{pixel_rng, ImageSampler};
+use crate::colour::cie_1931::colour_matching_functions;
+use crate::colour::{ColourXyz, Photon, LONGEST_VISIBLE_WAVELENGTH, SHORTEST_VISIBLE_WAVELENGTH};
+use crate::image::ImageGreyU16;
+use crate::math::Vec3;
+use crate::sampler::{Sampler, SceneSampler};
+use crate::scene::Scene;
+use crate::util::Array2D;
+
+use std::f64::consts::PI;
+
+/// Images giving the object and material seen through the centre of each pixel
+///
+/// These are meant to be written alongside the rendered image, so that a compositor can
+/// build a mask for any object or material.
+#[derive(Debug)]
+pub struct IdBuffers {
+    /// The [object ID](crate::raycasting::WithObjectId) of the nearest object
+    pub object_ids: ImageGreyU16,
+
+    /// The [ID](crate::materials::MaterialLibrary::id) of the nearest object's material in
+    /// the scene's material library
+    pub material_ids: ImageGreyU16,
+}
+
+/// Render the [ID buffers](IdBuffers) for `scene` at `width` by `height` pixels
+///
+/// Pixels that see the background, or an object or material without an ID, are zero. IDs
+/// too large for 16 bits are clamped to the largest that fits.
+pub fn render_id_buffers(scene: &Scene, width: usize, height: usize) -> IdBuffers {
+    let mut result = IdBuffers {
+        object_ids: ImageGreyU16::new(width, height),
+        material_ids: ImageGreyU16::new(width, height),
+    };
+    let image_sampler = ImageSampler::new(width, height, scene.camera.as_ref());
+    let sampler = SceneSampler { scene };
+    let to_u16 = |id: u32| id.min(u16::MAX as u32) as u16;
+    for row in 0..height {
+        for column in 0..width {
+            let mut rng = pixel_rng(0, row, column);
+            let ray = image_sampler.pixel_centre_ray(row, column, &mut rng);
+            if let Some(info) = sampler.sample(&ray) {
+                result
+                    .object_ids
+                    .set_value(row, column, to_u16(info.object_id));
+                let material_id = scene.materials.id(&info.material).unwrap_or(0);
+                result
+                    .material_ids
+                    .set_value(row, column, to_u16(material_id));
+            }
+        }
+    }
+    result
+}
+
+/// Auxiliary images of the surfaces seen by each pixel, which [denoisers](crate::denoiser)
+/// use to tell noise from detail
+#[derive(Clone, Debug)]
+pub struct DenoisingAovs {
+    /// The colour of the nearest surface, as it would appear under white light
+    ///
+    /// This is the BSDF for light arriving and leaving along the normal, scaled by π, which
+    /// is the surface's reflectance for diffuse materials. A perfectly white surface has a
+    /// Y of one.
+    pub albedo: Array2D<ColourXyz>,
+
+    /// The world-space surface normal of the nearest surface
+    pub normal: Array2D<Vec3>,
+}
+
+/// Render the [denoising AOVs](DenoisingAovs) for `scene` at `width` by `height` pixels
+///
+/// Pixels that see the background have a black albedo and a zero normal.
+pub fn render_denoising_aovs(scene: &Scene, width: usize, height: usize) -> DenoisingAovs {
+    let mut result = DenoisingAovs {
+        albedo: Array2D::new(height, width),
+        normal: Array2D::new(height, width),
+    };
+    let image_sampler = ImageSampler::new(width, height, scene.camera.as_ref());
+    let sampler = SceneSampler { scene };
+    let first = SHORTEST_VISIBLE_WAVELENGTH as usize;
+    let last = LONGEST_VISIBLE_WAVELENGTH as usize;
+    let white_y: f64 = (first..=last)
+        .step_by(5)
+        .map(|wavelength| colour_matching_functions(wavelength as f64).y())
+        .sum();
+    for row in 0..height {
+        for column in 0..width {
+            let mut rng = pixel_rng(0, row, column);
+            let ray = image_sampler.pixel_centre_ray(row, column, &mut rng);
+            if let Some(info) = sampler.sample(&ray) {
+                let bsdf = info.bsdf();
+                let albedo = (first..=last)
+                    .step_by(5)
+                    .map(|wavelength| {
+                        let photon = Photon {
+                            wavelength: wavelength as f64,
+                            intensity: 1.0,
+                        };
+                        let reflected = bsdf(&Vec3::unit_z(), &Vec3::unit_z(), &photon);
+                        colour_matching_functions(photon.wavelength) * reflected.intensity
+                    })
+                    .fold(Vec3::zeros(), |a, b| a + b);
+                result.albedo[row][column] = ColourXyz {
+                    values: albedo * (PI / white_y),
+                };
+                result.normal[row][column] = info.normal;
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+    use crate::colour::Spectrum;
+
+    use crate::materials::LambertianMaterial;
+    use crate::raycasting::{Primitive, Sphere, WithObjectId};
+
+    use std::sync::Arc;
+
+    #[test]
+    fn pixels_record_object_and_material_ids() {
+        let material: Arc<dyn crate::materials::Material> =
+            Arc::new(LambertianMaterial::new_dummy());
+        let sphere: Box<dyn Primitive> = Box::new(Sphere::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            1.0,
+            Arc::clone(&material),
+        ));
+        let scene = Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, -3.0),
+            Lens::Pinhole,
+        )))
+        .with_material("unused", Arc::new(LambertianMaterial::new_dummy()))
+        .with_material("sphere", material)
+        .with_object(Box::new(WithObjectId::new(vec![sphere], 3)))
+        .build();
+        let target = render_id_buffers(&scene, 8, 6);
+        assert!(target.object_ids.get_width() == 8);
+        assert!(target.object_ids.get_height() == 6);
+        assert!(target.object_ids.get_value(3, 4) == 3);
+        assert!(target.material_ids.get_value(3, 4) == 2);
+        assert!(target.object_ids.get_value(0, 0) == 0);
+        assert!(target.material_ids.get_value(0, 0) == 0);
+    }
+
+    #[test]
+    fn pixels_record_albedo_and_normal() {
+        let material = Arc::new(LambertianMaterial {
+            colour: Spectrum::grey(0.5),
+            diffuse_strength: 1.0,
+        });
+        let scene = Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, -3.0),
+            Lens::Pinhole,
+        )))
+        .with_object(Box::new(vec![
+            Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material)) as Box<dyn Primitive>,
+        ]))
+        .build();
+        let target = render_denoising_aovs(&scene, 8, 6);
+        assert!(target.albedo.get_width() == 8);
+        assert!(target.albedo.get_height() == 6);
+        assert!((target.albedo[3][4].y() - 0.5).abs() < 0.01);
+        assert!(target.normal[3][4].z() < -0.9);
+        assert!(target.albedo[0][0].y() == 0.0);
+        assert!(target.normal[0][0] == Vec3::zeros());
+    }
+}
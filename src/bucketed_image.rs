@@ -0,0 +1,250 @@
+use rayon::prelude::*;
+
+use crate::accumulation_buffer::AccumulationBuffer;
+use crate::camera::{filter_footprint, partial_render_scene, RenderSettings};
+use crate::colour::ColourXyz;
+use crate::error::VanrijnError;
+use crate::image::{ImageRgbU8, ToneMapper};
+use crate::math::Vec3;
+use crate::scene::Scene;
+use crate::util::{Array2D, Tile, TileOrder, TileScheduler};
+
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Error, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Weighted X, Y and Z sums and the total weight, as 32-bit floats
+const BYTES_PER_PIXEL: usize = 16;
+
+/// An image kept in a scratch file rather than in memory, for renders too large to hold
+/// in [AccumulationBuffers](AccumulationBuffer)
+///
+/// Tiles are rendered one at a time and [merged](BucketedImage::merge_tile) straight
+/// into the file, so only the tiles being rendered are in memory. Each pixel takes 16
+/// bytes of the file, holding its weighted sum of colours and the sum of the weights, so
+/// the samples that reconstruction filters spread over neighbouring tiles are combined
+/// correctly whichever tile is merged first.
+#[derive(Debug)]
+pub struct BucketedImage {
+    file: Mutex<File>,
+    width: usize,
+    height: usize,
+}
+
+impl BucketedImage {
+    /// Create a black image of `width` by `height` pixels in the scratch file `filename`,
+    /// replacing anything that was there
+    pub fn create(
+        filename: &Path,
+        width: usize,
+        height: usize,
+    ) -> Result<BucketedImage, VanrijnError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(filename)?;
+        // The file is extended with zeros, which most filesystems don't store
+        file.set_len((width * height * BYTES_PER_PIXEL) as u64)?;
+        Ok(BucketedImage {
+            file: Mutex::new(file),
+            width,
+            height,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn row_offset(&self, row: usize, start_column: usize) -> u64 {
+        ((row * self.width + start_column) * BYTES_PER_PIXEL) as u64
+    }
+
+    /// Read the sums for `length` pixels of `row` starting at `start_column`
+    fn read_sums(
+        &self,
+        file: &mut File,
+        row: usize,
+        start_column: usize,
+        length: usize,
+    ) -> Result<Vec<[f32; 4]>, Error> {
+        let mut bytes = vec![0u8; length * BYTES_PER_PIXEL];
+        file.seek(SeekFrom::Start(self.row_offset(row, start_column)))?;
+        file.read_exact(&mut bytes)?;
+        Ok(bytes
+            .chunks_exact(BYTES_PER_PIXEL)
+            .map(|pixel| {
+                let value =
+                    |i: usize| f32::from_le_bytes(pixel[4 * i..4 * i + 4].try_into().unwrap());
+                [value(0), value(1), value(2), value(3)]
+            })
+            .collect())
+    }
+
+    /// Add `src`, which covers `tile` of this image, to the image
+    ///
+    /// See [AccumulationBuffer::merge_tile()].
+    pub fn merge_tile(&self, tile: &Tile, src: &AccumulationBuffer) -> Result<(), VanrijnError> {
+        assert!(tile.width() == src.width());
+        assert!(tile.height() == src.height());
+        assert!(tile.end_column <= self.width && tile.end_row <= self.height);
+        let mut file = self.file.lock().expect("Scratch file lock poisoned.");
+        for i in 0..tile.height() {
+            let row = tile.start_row + i;
+            let sums = self.read_sums(&mut file, row, tile.start_column, tile.width())?;
+            let mut bytes = Vec::with_capacity(tile.width() * BYTES_PER_PIXEL);
+            for (j, sum) in sums.iter().enumerate() {
+                let weight = src.weight(i, j);
+                let colour = src.colour(i, j).values * weight;
+                for (k, value) in [colour.x(), colour.y(), colour.z(), weight]
+                    .iter()
+                    .enumerate()
+                {
+                    bytes.extend_from_slice(&(sum[k] + *value as f32).to_le_bytes());
+                }
+            }
+            file.seek(SeekFrom::Start(self.row_offset(row, tile.start_column)))?;
+            file.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    /// The mean colour of the samples added to each pixel of `row`
+    pub fn row(&self, row: usize) -> Result<Vec<ColourXyz>, VanrijnError> {
+        let mut file = self.file.lock().expect("Scratch file lock poisoned.");
+        Ok(self
+            .read_sums(&mut file, row, 0, self.width)?
+            .iter()
+            .map(|&[x, y, z, weight]| {
+                if weight == 0.0 {
+                    ColourXyz::default()
+                } else {
+                    ColourXyz {
+                        values: Vec3::new(x as f64, y as f64, z as f64) * (1.0 / weight as f64),
+                    }
+                }
+            })
+            .collect())
+    }
+
+    /// Tone map the image and write it to `filename` as a PNG, a row at a time
+    ///
+    /// Only one row is ever in memory, so `tone_mapper` is applied to each row on its own;
+    /// tone mappers that adapt to the whole image, as with
+    /// [auto exposure](crate::image::Exposure::auto_exposure), will adapt to each row
+    /// differently.
+    pub fn write_png<T: ToneMapper<ColourXyz>>(
+        &self,
+        filename: &Path,
+        tone_mapper: &T,
+    ) -> Result<(), VanrijnError> {
+        let image_error = |error: Error| VanrijnError::image(filename)(error);
+        let file = File::create(filename).map_err(image_error)?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|error| image_error(error.into()))?;
+        let mut stream = writer.stream_writer();
+        let mut row_colours = Array2D::new(1, self.width);
+        let mut row_image = ImageRgbU8::new(self.width, 1);
+        for row in 0..self.height {
+            row_colours[0].clone_from_slice(&self.row(row)?);
+            tone_mapper.apply_tone_mapping(&row_colours, &mut row_image);
+            stream
+                .write_all(row_image.get_pixel_data())
+                .map_err(image_error)?;
+        }
+        stream.finish().map_err(|error| image_error(error.into()))?;
+        Ok(())
+    }
+}
+
+/// Render `scene` into `image`, taking `settings.samples_per_pixel` samples for each pixel
+///
+/// This is a single [pass](crate::progressive_renderer::ProgressiveRenderer::render_pass)
+/// in tiles of `settings.tile_size`, rendered in parallel, with each tile written to the scratch
+/// file as soon as it's finished.
+pub fn render_bucketed(
+    scene: &Scene,
+    image: &BucketedImage,
+    settings: &RenderSettings,
+) -> Result<(), VanrijnError> {
+    let (width, height) = (image.width(), image.height());
+    let scheduler = TileScheduler::new(width, height, settings.tile_size, TileOrder::default());
+    (0..rayon::current_num_threads())
+        .into_par_iter()
+        .try_for_each(|_| {
+            for tile in &scheduler {
+                let rendered_tile =
+                    partial_render_scene(scene, tile, height, width, settings.seed, settings);
+                let footprint = filter_footprint(&tile, width, height, settings.filter.as_ref());
+                image.merge_tile(&footprint, &rendered_tile)?;
+            }
+            Ok(())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+    use crate::filters::TentFilter;
+
+    use crate::progressive_renderer::ProgressiveRenderer;
+
+    use std::sync::Arc;
+
+    fn scratch_file(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("vanrijn-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn bucketed_render_matches_progressive_pass() {
+        let scene = Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Lens::Pinhole,
+        )))
+        .build();
+        // A wide filter spreads samples across tile boundaries
+        let settings = RenderSettings {
+            filter: Arc::new(TentFilter::default()),
+            tile_size: 4,
+            seed: 5,
+            ..RenderSettings::default()
+        };
+        let filename = scratch_file("bucketed-render");
+        let target = BucketedImage::create(&filename, 10, 7).unwrap();
+        render_bucketed(&scene, &target, &settings).unwrap();
+        let expected = Arc::new(Mutex::new(AccumulationBuffer::new(10, 7)));
+        ProgressiveRenderer::new(&scene, Arc::clone(&expected), settings).render_pass();
+        let expected = expected.lock().unwrap();
+        for row in 0..7 {
+            let actual = target.row(row).unwrap();
+            for (column, colour) in actual.iter().enumerate() {
+                let expected = expected.colour(row, column).values;
+                assert!((colour.values - expected).norm() <= 0.000_01 * expected.norm());
+            }
+        }
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn unrendered_pixels_are_black() {
+        let filename = scratch_file("unrendered");
+        let target = BucketedImage::create(&filename, 3, 2).unwrap();
+        assert!(target.row(1).unwrap() == vec![ColourXyz::default(); 3]);
+        std::fs::remove_file(filename).unwrap();
+    }
+}
@@ -1,72 +1,839 @@
-use crate::math::Vec3;
+use crate::math::{Mat3, Vec2, Vec3};
 
-use super::accumulation_buffer::AccumulationBuffer;
-use super::colour::Photon;
+use super::accumulation_buffer::{AccumulationBuffer, PhotonAccumulator};
+use super::colour::{PhotonPacket, Spectrum};
+use super::filters::{BoxFilter, Filter};
 use super::integrators::{Integrator, SimpleRandomIntegrator};
-use super::raycasting::Ray;
-use super::sampler::Sampler;
+use super::random_distributions::{RandomDistribution, UnitDisc};
+use super::raycasting::{BoundingBox, Ray, RayDifferential, RAY_PACKET_WIDTH};
+use super::sampler::SceneSampler;
 use super::scene::Scene;
-use super::util::Tile;
+use super::spectral_accumulation_buffer::SpectralAccumulationBuffer;
+use super::util::{CancellationToken, Tile};
 
-use rand::random;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
 
-struct ImageSampler {
-    image_height_pixels: usize,
-    image_width_pixels: usize,
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// The optical system used to focus light from the scene onto the film
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Lens {
+    /// An ideal pinhole camera; everything is in perfect focus
+    #[default]
+    Pinhole,
+
+    /// A thin lens, which produces depth-of-field effects
+    ///
+    /// Points at `focus_distance` from the camera (measured along the view direction) are
+    /// in perfect focus, and points nearer or further away become progressively more
+    /// blurred. A larger `aperture_radius` gives a shallower depth of field.
+    ThinLens {
+        aperture_radius: f64,
+        focus_distance: f64,
+    },
+}
+
+/// Turns points on the film into rays leaving the camera
+///
+/// Film points have coordinates between zero and one, with x increasing to the right and y
+/// increasing upwards. The film has the same aspect ratio (width divided by height) as the
+/// image being rendered.
+pub trait Camera: Send + Sync {
+    fn ray(&self, film_point: &Vec2, aspect_ratio: f64, rng: &mut dyn RngCore) -> Ray;
+
+    /// As [ray()](Camera::ray), but the ray also carries a
+    /// [RayDifferential](RayDifferential) for the points `film_step` away across and up the
+    /// film, usually the size of a pixel
+    ///
+    /// All three rays must be chosen with the same random choices, such as the point on the
+    /// lens, so cameras that use `rng` have to provide this themselves. The default returns
+    /// a ray without a differential.
+    fn ray_with_differential(
+        &self,
+        film_point: &Vec2,
+        _film_step: &Vec2,
+        aspect_ratio: f64,
+        rng: &mut dyn RngCore,
+    ) -> Ray {
+        self.ray(film_point, aspect_ratio, rng)
+    }
+}
+
+/// A differential made from the rays through points a step across and up the film from
+/// the main ray
+fn differential_from(x_ray: Ray, y_ray: Ray) -> RayDifferential {
+    RayDifferential {
+        x_origin: x_ray.origin,
+        x_direction: x_ray.direction,
+        y_origin: y_ray.origin,
+        y_direction: y_ray.direction,
+    }
+}
 
-    film_width: f64,
-    film_height: f64,
-    camera_location: Vec3,
-    film_distance: f64,
+/// The points `film_step` across and up the film from `film_point`
+fn film_neighbours(film_point: &Vec2, film_step: &Vec2) -> (Vec2, Vec2) {
+    (
+        Vec2::new(film_point.x() + film_step.x(), film_point.y()),
+        Vec2::new(film_point.x(), film_point.y() + film_step.y()),
+    )
 }
 
-impl ImageSampler {
-    pub fn new(width: usize, height: usize, camera_location: Vec3) -> ImageSampler {
-        let (film_width, film_height) = {
-            let width = width as f64;
-            let height = height as f64;
-            let film_size = 1.0;
-            if width > height {
-                (width / height, film_size)
-            } else {
-                (film_size, width / height)
+/// The rotation from camera space to world space for a camera at `location` looking at
+/// `target`
+///
+/// In camera space the view direction is +Z, +Y is up and +X is to the right. The camera is
+/// turned so that `up` points as nearly upwards as possible; it must not be parallel to the
+/// view direction.
+pub fn look_at(location: &Vec3, target: &Vec3, up: &Vec3) -> Mat3 {
+    let forward = (target - location).normalize();
+    let right = up.cross(&forward).normalize();
+    let up = forward.cross(&right);
+    Mat3::from_rows(&right, &up, &forward).transpose()
+}
+
+/// A conventional camera, in which parallel lines converge towards the horizon
+#[derive(Debug)]
+pub struct PerspectiveCamera {
+    pub location: Vec3,
+
+    /// Rotation from camera space, in which the camera looks along +Z, to world space
+    pub orientation: Mat3,
+
+    /// Angle between the top and bottom edges of the image, in radians
+    pub vertical_fov: f64,
+
+    pub lens: Lens,
+    aperture_distribution: UnitDisc,
+}
+
+impl PerspectiveCamera {
+    /// A camera looking along the +Z axis with
+    /// [DEFAULT_VERTICAL_FOV](PerspectiveCamera::DEFAULT_VERTICAL_FOV)
+    pub fn new(location: Vec3, lens: Lens) -> PerspectiveCamera {
+        PerspectiveCamera::looking_at(
+            location,
+            &(location + Vec3::unit_z()),
+            &Vec3::unit_y(),
+            PerspectiveCamera::DEFAULT_VERTICAL_FOV,
+            lens,
+        )
+    }
+
+    /// A camera at `location` looking towards `target`; see [look_at()](look_at)
+    pub fn looking_at(
+        location: Vec3,
+        target: &Vec3,
+        up: &Vec3,
+        vertical_fov: f64,
+        lens: Lens,
+    ) -> PerspectiveCamera {
+        PerspectiveCamera {
+            location,
+            orientation: look_at(&location, target, up),
+            vertical_fov,
+            lens,
+            aperture_distribution: UnitDisc::new(),
+        }
+    }
+
+    /// A camera looking along `view_direction`, as close as it can be while still showing
+    /// everything in `bounds` in an image with the given aspect ratio
+    ///
+    /// This is handy for viewing models without knowing where they are or how big. The
+    /// camera fits a sphere around the box, so there's usually a little space around the
+    /// edges. `up` is as for [look_at()](look_at).
+    ///
+    /// # Panics
+    ///
+    /// If `bounds` is empty or infinite.
+    pub fn framing(
+        bounds: &BoundingBox,
+        view_direction: &Vec3,
+        up: &Vec3,
+        vertical_fov: f64,
+        aspect_ratio: f64,
+        lens: Lens,
+    ) -> PerspectiveCamera {
+        let diagonal = bounds.diagonal();
+        assert!(
+            diagonal
+                .coords
+                .iter()
+                .all(|size| size.is_finite() && *size >= 0.0),
+            "Can't frame an empty or infinite bounding box."
+        );
+        let radius = 0.5 * diagonal.norm();
+        let half_fov = (0.5 * vertical_fov).min(((0.5 * vertical_fov).tan() * aspect_ratio).atan());
+        let target = bounds.centre();
+        let location = target - view_direction.normalize() * (radius / half_fov.sin());
+        PerspectiveCamera::looking_at(location, &target, up, vertical_fov, lens)
+    }
+
+    /// About 53 degrees, which is a little longer than a standard lens
+    pub const DEFAULT_VERTICAL_FOV: f64 = 0.927_295_218_001_612_2;
+
+    const FILM_DISTANCE: f64 = 1.0;
+
+    /// The width and height of the film, which sits `FILM_DISTANCE` in front of the camera
+    fn film_size(&self, aspect_ratio: f64) -> (f64, f64) {
+        let film_height = 2.0 * PerspectiveCamera::FILM_DISTANCE * (self.vertical_fov / 2.0).tan();
+        (film_height * aspect_ratio, film_height)
+    }
+
+    /// A random point on the lens, in camera space
+    fn sample_lens(&self, rng: &mut dyn RngCore) -> Vec3 {
+        match self.lens {
+            Lens::Pinhole => Vec3::zeros(),
+            Lens::ThinLens {
+                aperture_radius, ..
+            } => {
+                let lens_sample = self.aperture_distribution.value(rng) * aperture_radius;
+                Vec3::new(lens_sample.x(), lens_sample.y(), 0.0)
+            }
+        }
+    }
+
+    /// A ray from `lens_point` through `film_point`, which are both given in camera space
+    fn ray_through_film_point(&self, film_point: &Vec3, lens_point: &Vec3) -> Ray {
+        match self.lens {
+            Lens::Pinhole => Ray::new(self.location, self.orientation * film_point),
+            Lens::ThinLens { focus_distance, .. } => {
+                // All rays leaving the film point, regardless of where they pass through the
+                // lens, converge on the same point on the plane of focus.
+                let focus_point = film_point * (focus_distance / film_point.z());
+                Ray::new(
+                    self.location + self.orientation * lens_point,
+                    self.orientation * (focus_point - *lens_point),
+                )
             }
+        }
+    }
+
+    /// `film_point` converted to camera space
+    fn film_point_in_camera_space(&self, film_point: &Vec2, aspect_ratio: f64) -> Vec3 {
+        let (film_width, film_height) = self.film_size(aspect_ratio);
+        Vec3::new(
+            (film_point.x() - 0.5) * film_width,
+            (film_point.y() - 0.5) * film_height,
+            PerspectiveCamera::FILM_DISTANCE,
+        )
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn ray(&self, film_point: &Vec2, aspect_ratio: f64, rng: &mut dyn RngCore) -> Ray {
+        self.ray_through_film_point(
+            &self.film_point_in_camera_space(film_point, aspect_ratio),
+            &self.sample_lens(rng),
+        )
+    }
+
+    fn ray_with_differential(
+        &self,
+        film_point: &Vec2,
+        film_step: &Vec2,
+        aspect_ratio: f64,
+        rng: &mut dyn RngCore,
+    ) -> Ray {
+        let lens_point = self.sample_lens(rng);
+        let ray_through = |film_point: &Vec2| {
+            self.ray_through_film_point(
+                &self.film_point_in_camera_space(film_point, aspect_ratio),
+                &lens_point,
+            )
+        };
+        let (x_point, y_point) = film_neighbours(film_point, film_step);
+        ray_through(film_point).with_differential(differential_from(
+            ray_through(&x_point),
+            ray_through(&y_point),
+        ))
+    }
+}
+
+/// A camera without perspective, in which parallel lines stay parallel
+///
+/// All rays travel in the view direction, from points on a rectangle centred on
+/// `location`. This is useful for technical drawings, such as architectural elevations.
+#[derive(Clone, Copy, Debug)]
+pub struct OrthographicCamera {
+    pub location: Vec3,
+
+    /// Rotation from camera space, in which the camera looks along +Z, to world space
+    pub orientation: Mat3,
+
+    /// Height of the region of the scene that is visible, in world units
+    pub view_height: f64,
+}
+
+impl OrthographicCamera {
+    /// A camera looking along the +Z axis
+    pub fn new(location: Vec3, view_height: f64) -> OrthographicCamera {
+        OrthographicCamera {
+            location,
+            orientation: Mat3::identity(),
+            view_height,
+        }
+    }
+
+    /// A camera at `location` looking towards `target`; see [look_at()](look_at)
+    pub fn looking_at(
+        location: Vec3,
+        target: &Vec3,
+        up: &Vec3,
+        view_height: f64,
+    ) -> OrthographicCamera {
+        OrthographicCamera {
+            location,
+            orientation: look_at(&location, target, up),
+            view_height,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn ray(&self, film_point: &Vec2, aspect_ratio: f64, _rng: &mut dyn RngCore) -> Ray {
+        let offset = Vec3::new(
+            (film_point.x() - 0.5) * self.view_height * aspect_ratio,
+            (film_point.y() - 0.5) * self.view_height,
+            0.0,
+        );
+        Ray::new(
+            self.location + self.orientation * offset,
+            self.orientation * Vec3::unit_z(),
+        )
+    }
+
+    fn ray_with_differential(
+        &self,
+        film_point: &Vec2,
+        film_step: &Vec2,
+        aspect_ratio: f64,
+        rng: &mut dyn RngCore,
+    ) -> Ray {
+        let (x_point, y_point) = film_neighbours(film_point, film_step);
+        self.ray(film_point, aspect_ratio, rng)
+            .with_differential(differential_from(
+                self.ray(&x_point, aspect_ratio, rng),
+                self.ray(&y_point, aspect_ratio, rng),
+            ))
+    }
+}
+
+/// A 360 degree camera that captures every direction around `location`
+///
+/// The image is an equirectangular projection: longitude varies linearly across the image
+/// and latitude varies linearly from the bottom to the top. In camera space the centre of
+/// the image looks along the +Z axis, and the top and bottom look along the +Y and -Y axes
+/// respectively. Images should usually have an aspect ratio of 2:1.
+#[derive(Clone, Copy, Debug)]
+pub struct EquirectangularCamera {
+    pub location: Vec3,
+
+    /// Rotation from camera space to world space
+    pub orientation: Mat3,
+}
+
+impl EquirectangularCamera {
+    pub fn new(location: Vec3) -> EquirectangularCamera {
+        EquirectangularCamera {
+            location,
+            orientation: Mat3::identity(),
+        }
+    }
+
+    /// A camera at `location` with the centre of the image towards `target`; see
+    /// [look_at()](look_at)
+    pub fn looking_at(location: Vec3, target: &Vec3, up: &Vec3) -> EquirectangularCamera {
+        EquirectangularCamera {
+            location,
+            orientation: look_at(&location, target, up),
+        }
+    }
+}
+
+/// The direction in camera space seen at `film_point` in an equirectangular image, as
+/// described for [EquirectangularCamera](EquirectangularCamera)
+fn equirectangular_direction(film_point: &Vec2) -> Vec3 {
+    let longitude = (film_point.x() - 0.5) * 2.0 * PI;
+    let latitude = (film_point.y() - 0.5) * PI;
+    Vec3::new(
+        latitude.cos() * longitude.sin(),
+        latitude.sin(),
+        latitude.cos() * longitude.cos(),
+    )
+}
+
+impl Camera for EquirectangularCamera {
+    fn ray(&self, film_point: &Vec2, _aspect_ratio: f64, _rng: &mut dyn RngCore) -> Ray {
+        Ray::new(
+            self.location,
+            self.orientation * equirectangular_direction(film_point),
+        )
+    }
+
+    fn ray_with_differential(
+        &self,
+        film_point: &Vec2,
+        film_step: &Vec2,
+        aspect_ratio: f64,
+        rng: &mut dyn RngCore,
+    ) -> Ray {
+        let (x_point, y_point) = film_neighbours(film_point, film_step);
+        self.ray(film_point, aspect_ratio, rng)
+            .with_differential(differential_from(
+                self.ray(&x_point, aspect_ratio, rng),
+                self.ray(&y_point, aspect_ratio, rng),
+            ))
+    }
+}
+
+/// How the views from the two eyes of a stereo camera share the image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// The left eye's view in the left half of the image, and the right eye's in the right
+    SideBySide,
+
+    /// The left eye's view in the top half of the image, and the right eye's in the bottom
+    TopBottom,
+}
+
+impl StereoLayout {
+    /// Which eye sees `film_point`, as -1 for the left and 1 for the right, with the point
+    /// on that eye's own film and that film's aspect ratio
+    fn eye_film_point(&self, film_point: &Vec2, aspect_ratio: f64) -> (f64, Vec2, f64) {
+        match self {
+            StereoLayout::SideBySide => {
+                let eye = if film_point.x() < 0.5 { -1.0 } else { 1.0 };
+                let x = 2.0 * film_point.x() - if eye < 0.0 { 0.0 } else { 1.0 };
+                (eye, Vec2::new(x, film_point.y()), 0.5 * aspect_ratio)
+            }
+            StereoLayout::TopBottom => {
+                let eye = if film_point.y() >= 0.5 { -1.0 } else { 1.0 };
+                let y = 2.0 * film_point.y() - if eye < 0.0 { 1.0 } else { 0.0 };
+                (eye, Vec2::new(film_point.x(), y), 2.0 * aspect_ratio)
+            }
+        }
+    }
+
+    /// `film_step` converted to a step on one eye's film
+    fn eye_film_step(&self, film_step: &Vec2) -> Vec2 {
+        match self {
+            StereoLayout::SideBySide => Vec2::new(2.0 * film_step.x(), film_step.y()),
+            StereoLayout::TopBottom => Vec2::new(film_step.x(), 2.0 * film_step.y()),
+        }
+    }
+}
+
+/// A pair of pinhole cameras, one for each eye, rendered into the same image
+///
+/// The eyes are `interpupillary_distance` apart along the camera's X axis, either side of
+/// `location`, and look in parallel. Rather than toeing them in, which gives vertical
+/// parallax towards the edges, each eye's view is shifted so that they both frame the same
+/// rectangle at `convergence_distance`; objects there appear at the depth of the screen,
+/// nearer ones in front of it and further ones behind.
+///
+/// Each eye gets half of the image, as given by `layout`, with `vertical_fov` as for
+/// [PerspectiveCamera](PerspectiveCamera).
+#[derive(Clone, Copy, Debug)]
+pub struct StereoCamera {
+    pub location: Vec3,
+
+    /// Rotation from camera space, in which the camera looks along +Z, to world space
+    pub orientation: Mat3,
+
+    /// Angle between the top and bottom edges of each eye's view, in radians
+    pub vertical_fov: f64,
+
+    pub interpupillary_distance: f64,
+    pub convergence_distance: f64,
+    pub layout: StereoLayout,
+}
+
+impl StereoCamera {
+    /// The ray from the eye at `eye` (-1 for the left and 1 for the right) through the point
+    /// on its film at `film_point`
+    fn eye_ray(&self, eye: f64, film_point: &Vec2, aspect_ratio: f64) -> Ray {
+        let film_height = 2.0 * (0.5 * self.vertical_fov).tan();
+        let screen_point = Vec3::new(
+            (film_point.x() - 0.5) * film_height * aspect_ratio,
+            (film_point.y() - 0.5) * film_height,
+            1.0,
+        ) * self.convergence_distance;
+        let eye_point = Vec3::new(0.5 * eye * self.interpupillary_distance, 0.0, 0.0);
+        Ray::new(
+            self.location + self.orientation * eye_point,
+            self.orientation * (screen_point - eye_point),
+        )
+    }
+}
+
+impl Camera for StereoCamera {
+    fn ray(&self, film_point: &Vec2, aspect_ratio: f64, _rng: &mut dyn RngCore) -> Ray {
+        let (eye, eye_point, eye_aspect_ratio) =
+            self.layout.eye_film_point(film_point, aspect_ratio);
+        self.eye_ray(eye, &eye_point, eye_aspect_ratio)
+    }
+
+    /// The neighbouring rays are from the same eye, even at the edge of its half of the
+    /// image
+    fn ray_with_differential(
+        &self,
+        film_point: &Vec2,
+        film_step: &Vec2,
+        aspect_ratio: f64,
+        _rng: &mut dyn RngCore,
+    ) -> Ray {
+        let (eye, eye_point, eye_aspect_ratio) =
+            self.layout.eye_film_point(film_point, aspect_ratio);
+        let (x_point, y_point) = film_neighbours(&eye_point, &self.layout.eye_film_step(film_step));
+        self.eye_ray(eye, &eye_point, eye_aspect_ratio)
+            .with_differential(differential_from(
+                self.eye_ray(eye, &x_point, eye_aspect_ratio),
+                self.eye_ray(eye, &y_point, eye_aspect_ratio),
+            ))
+    }
+}
+
+/// A 360 degree stereo camera, for panoramas that can be looked around in VR
+///
+/// Each eye's view is an equirectangular projection, as for
+/// [EquirectangularCamera](EquirectangularCamera), given half of the image by `layout`;
+/// VR players usually expect [TopBottom](StereoLayout::TopBottom) with a square image.
+/// This is omni-directional stereo: rather than coming from two fixed points, the rays for
+/// each direction start from where the eyes would be with the head turned to face it, on a
+/// circle `interpupillary_distance` across around `location`. The stereo effect is
+/// correct for whichever way the viewer looks, as long as they keep their head level.
+#[derive(Clone, Copy, Debug)]
+pub struct OmniDirectionalStereoCamera {
+    pub location: Vec3,
+
+    /// Rotation from camera space to world space
+    pub orientation: Mat3,
+
+    pub interpupillary_distance: f64,
+    pub layout: StereoLayout,
+}
+
+impl OmniDirectionalStereoCamera {
+    /// The ray from the eye at `eye` (-1 for the left and 1 for the right) through the point
+    /// on its film at `film_point`
+    fn eye_ray(&self, eye: f64, film_point: &Vec2) -> Ray {
+        let direction = equirectangular_direction(film_point);
+        // To the right of the direction, in the horizontal plane
+        let right = Vec3::new(direction.z(), 0.0, -direction.x());
+        let right = if right.norm() > 0.0 {
+            right.normalize()
+        } else {
+            // Straight up or down the eyes are where they were for the centre of the image
+            let longitude = (film_point.x() - 0.5) * 2.0 * PI;
+            Vec3::new(longitude.cos(), 0.0, -longitude.sin())
         };
+        Ray::new(
+            self.location + self.orientation * (right * (0.5 * eye * self.interpupillary_distance)),
+            self.orientation * direction,
+        )
+    }
+}
+
+impl Camera for OmniDirectionalStereoCamera {
+    fn ray(&self, film_point: &Vec2, aspect_ratio: f64, _rng: &mut dyn RngCore) -> Ray {
+        let (eye, eye_point, _) = self.layout.eye_film_point(film_point, aspect_ratio);
+        self.eye_ray(eye, &eye_point)
+    }
+
+    fn ray_with_differential(
+        &self,
+        film_point: &Vec2,
+        film_step: &Vec2,
+        aspect_ratio: f64,
+        _rng: &mut dyn RngCore,
+    ) -> Ray {
+        let (eye, eye_point, _) = self.layout.eye_film_point(film_point, aspect_ratio);
+        let (x_point, y_point) = film_neighbours(&eye_point, &self.layout.eye_film_step(film_step));
+        self.eye_ray(eye, &eye_point)
+            .with_differential(differential_from(
+                self.eye_ray(eye, &x_point),
+                self.eye_ray(eye, &y_point),
+            ))
+    }
+}
+
+/// When each row of the image is exposed
+///
+/// With a global shutter every row is exposed over the same interval. A rolling shutter
+/// starts each row's exposure a little after the one above it, as a sensor read out a row
+/// at a time does, so that anything moving across the image is skewed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Shutter {
+    /// The time, in seconds, at which the top row of the image starts its exposure
+    pub open: f64,
+
+    /// How long, in seconds, each row is exposed for
+    ///
+    /// Rays are all at the start of the exposure if this is zero.
+    pub exposure: f64,
+
+    /// How long after the top row the bottom row starts its exposure, in seconds
+    ///
+    /// This is zero for a global shutter.
+    pub rolling_time: f64,
+}
+
+impl Shutter {
+    /// The time at which the exposure of row `row` of an image `height` rows high starts
+    fn row_open(&self, row: usize, height: usize) -> f64 {
+        self.open + self.rolling_time * (row as f64 / height as f64)
+    }
+
+    /// A random time during the exposure of row `row` of an image `height` rows high
+    pub fn sample_time(&self, row: usize, height: usize, rng: &mut dyn RngCore) -> f64 {
+        let open = self.row_open(row, height);
+        if self.exposure > 0.0 {
+            open + self.exposure * rng.gen::<f64>()
+        } else {
+            open
+        }
+    }
+
+    /// The middle of the exposure of row `row` of an image `height` rows high
+    pub fn mid_time(&self, row: usize, height: usize) -> f64 {
+        self.row_open(row, height) + 0.5 * self.exposure
+    }
+}
+
+pub(crate) struct ImageSampler<'a> {
+    image_height_pixels: usize,
+    image_width_pixels: usize,
+    camera: &'a dyn Camera,
+    shutter: Shutter,
+}
+
+impl<'a> ImageSampler<'a> {
+    pub fn new(width: usize, height: usize, camera: &'a dyn Camera) -> ImageSampler<'a> {
         ImageSampler {
             image_height_pixels: height,
             image_width_pixels: width,
-            film_distance: 1.0,
-            film_width,
-            film_height,
-            camera_location,
+            camera,
+            shutter: Shutter::default(),
         }
     }
 
-    fn scale(i: usize, n: usize, l: f64) -> f64 {
+    pub fn with_shutter(self, shutter: Shutter) -> ImageSampler<'a> {
+        ImageSampler { shutter, ..self }
+    }
+
+    fn scale(i: usize, n: usize, l: f64, rng: &mut dyn RngCore) -> f64 {
         let n = n as f64;
         let i = i as f64;
         let pixel_size = l * (1.0 / n);
-        (i + random::<f64>()) * pixel_size
+        (i + rng.gen::<f64>()) * pixel_size
     }
 
-    fn ray_for_pixel(&self, row: usize, column: usize) -> Ray {
-        Ray::new(
-            self.camera_location,
-            Vec3::new(
-                Self::scale(column, self.image_width_pixels, self.film_width)
-                    - self.film_width * 0.5,
-                Self::scale(
-                    self.image_height_pixels - (row + 1),
-                    self.image_height_pixels,
-                    self.film_height,
-                ) - self.film_height * 0.5,
-                self.film_distance,
-            ),
-        )
+    /// A ray through a random point in the pixel at `row` and `column`, at a random time
+    /// during the row's exposure
+    ///
+    /// Also returns the position of the point, in pixels from the top-left corner of the
+    /// image.
+    pub(crate) fn sample_pixel(
+        &self,
+        row: usize,
+        column: usize,
+        rng: &mut dyn RngCore,
+    ) -> (Ray, Vec2) {
+        let film_point = Vec2::new(
+            Self::scale(column, self.image_width_pixels, 1.0, rng),
+            1.0 - Self::scale(row, self.image_height_pixels, 1.0, rng),
+        );
+        let position = Vec2::new(
+            film_point.x() * self.image_width_pixels as f64,
+            (1.0 - film_point.y()) * self.image_height_pixels as f64,
+        );
+        let aspect_ratio = self.image_width_pixels as f64 / self.image_height_pixels as f64;
+        let film_step = Vec2::new(
+            1.0 / self.image_width_pixels as f64,
+            1.0 / self.image_height_pixels as f64,
+        );
+        let ray = self
+            .camera
+            .ray_with_differential(&film_point, &film_step, aspect_ratio, rng);
+        let time = self.shutter.sample_time(row, self.image_height_pixels, rng);
+        (ray.with_time(time), position)
+    }
+
+    /// A ray through the centre of the pixel at `row` and `column`, in the middle of the
+    /// row's exposure
+    ///
+    /// `rng` is only used by cameras that choose rays at random, such as those with a thin
+    /// lens.
+    pub(crate) fn pixel_centre_ray(&self, row: usize, column: usize, rng: &mut dyn RngCore) -> Ray {
+        let film_point = Vec2::new(
+            (column as f64 + 0.5) / self.image_width_pixels as f64,
+            1.0 - (row as f64 + 0.5) / self.image_height_pixels as f64,
+        );
+        let aspect_ratio = self.image_width_pixels as f64 / self.image_height_pixels as f64;
+        self.camera
+            .ray(&film_point, aspect_ratio, rng)
+            .with_time(self.shutter.mid_time(row, self.image_height_pixels))
     }
 }
 
-const RECURSION_LIMIT: u16 = 128;
+/// Options that trade rendering quality against speed
+#[derive(Clone)]
+pub struct RenderSettings {
+    /// The algorithm used to compute the light arriving along each camera ray
+    pub integrator: Arc<dyn Integrator>,
+
+    /// Number of samples taken for each pixel in each call to
+    /// [partial_render_scene()](partial_render_scene)
+    pub samples_per_pixel: usize,
+
+    /// Maximum number of times a path can bounce before it's terminated
+    pub max_depth: u16,
+
+    /// Width and height, in pixels, of the tiles that the image is divided into
+    pub tile_size: usize,
+
+    /// Seed for all the randomness used while rendering
+    ///
+    /// Renderers derive the seed they pass to [partial_render_scene()](partial_render_scene)
+    /// from this, so rendering the same scene with the same seed always produces the same
+    /// image.
+    pub seed: u64,
+
+    /// How samples are spread over the pixels near them
+    pub filter: Arc<dyn Filter>,
+
+    /// Largest radiance that a single sample may contribute, at any wavelength
+    ///
+    /// Rare paths that find a small, bright light can carry enormous radiance, which shows
+    /// up as bright speckles ("fireflies") that take a very long time to average out.
+    /// Clamping them removes the speckles at the cost of making the image slightly darker
+    /// than it should be. `None` disables clamping.
+    pub max_radiance: Option<f64>,
+
+    /// The fraction of the photons arriving at each wavelength that the camera's sensor
+    /// records, or `None` for a sensor that records them all
+    ///
+    /// Real sensors respond less to deep blue and red light than the eye does, which shifts
+    /// the colours of the image, and darkens it unless the exposure is raised to match.
+    pub quantum_efficiency: Option<Arc<Spectrum>>,
+
+    /// Checked while rendering, so that a render can be stopped early
+    ///
+    /// Once it's cancelled, tiles are returned with only the samples taken so far.
+    pub cancellation: CancellationToken,
+
+    /// When each row of the image is exposed, which sets the [time](Ray::time) of the
+    /// camera rays
+    pub shutter: Shutter,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            integrator: Arc::new(SimpleRandomIntegrator {}),
+            samples_per_pixel: 1,
+            max_depth: 128,
+            tile_size: 64,
+            seed: 0,
+            filter: Arc::new(BoxFilter::default()),
+            max_radiance: None,
+            quantum_efficiency: None,
+            cancellation: CancellationToken::new(),
+            shutter: Shutter::default(),
+        }
+    }
+}
+
+/// SplitMix64 finalizer, which maps nearby integers to unrelated ones
+fn mix_bits(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A random number generator for one sample of one pixel
+///
+/// The generator depends only on `seed` and the pixel's position in the full image, so
+/// it doesn't matter which tile the pixel is rendered in, or in what order tiles are
+/// rendered.
+pub(crate) fn pixel_rng(seed: u64, row: usize, column: usize) -> StdRng {
+    StdRng::seed_from_u64(mix_bits(
+        mix_bits(seed) ^ ((row as u64) << 32 | column as u64),
+    ))
+}
+
+/// The part of the image that samples taken in `tile` can contribute to
+///
+/// This is `tile` grown by the radius of `filter`, clipped to the edges of the image.
+pub fn filter_footprint(tile: &Tile, width: usize, height: usize, filter: &dyn Filter) -> Tile {
+    let margin = (filter.radius() - 0.5).max(0.0).ceil() as usize;
+    Tile {
+        start_column: tile.start_column.saturating_sub(margin),
+        end_column: (tile.end_column + margin).min(width),
+        start_row: tile.start_row.saturating_sub(margin),
+        end_row: (tile.end_row + margin).min(height),
+    }
+}
+
+/// The pixels, between `start` and `end`, whose centres are within `radius` of `position`
+fn splat_range(position: f64, radius: f64, start: usize, end: usize) -> std::ops::Range<usize> {
+    let first = (position - 0.5 - radius).ceil().max(start as f64) as usize;
+    let last = ((position - 0.5 + radius).floor() + 1.0).max(0.0) as usize;
+    first..last.min(end)
+}
+
+/// Pixels are traced in square blocks of this size, which fill a ray packet
+const PIXEL_BLOCK_SIZE: usize = 2;
+const _: () = assert!(PIXEL_BLOCK_SIZE * PIXEL_BLOCK_SIZE <= RAY_PACKET_WIDTH);
+
+/// The most pixels whose paths are [traced together](Integrator::integrate_batch)
+const PIXELS_PER_WAVE: usize = 4096;
+
+/// Spread the light found by a sample at `position` over the nearby pixels of `output`,
+/// which covers `footprint`
+fn splat_sample<A: PhotonAccumulator>(
+    output: &mut A,
+    footprint: &Tile,
+    filter: &dyn Filter,
+    position: &Vec2,
+    packet: &PhotonPacket,
+) {
+    let rows = splat_range(
+        position.y(),
+        filter.radius(),
+        footprint.start_row,
+        footprint.end_row,
+    );
+    let columns = splat_range(
+        position.x(),
+        filter.radius(),
+        footprint.start_column,
+        footprint.end_column,
+    );
+    for splat_row in rows {
+        for splat_column in columns.clone() {
+            let weight = filter.evaluate(
+                position.x() - (splat_column as f64 + 0.5),
+                position.y() - (splat_row as f64 + 0.5),
+            );
+            if weight == 0.0 {
+                continue;
+            }
+            for photon in packet.photons() {
+                output.update_pixel(
+                    splat_row - footprint.start_row,
+                    splat_column - footprint.start_column,
+                    &photon.scale_intensity(1.0 / PhotonPacket::wavelength_pdf(photon.wavelength)),
+                    weight,
+                );
+            }
+        }
+    }
+}
 
 /// Render a rectangular section of the image.
 ///
@@ -76,6 +843,16 @@ const RECURSION_LIMIT: u16 = 128;
 /// defined by `tile` is rendered and returned. Rendering a tile at a time allows a partially-
 /// rendered image to be displayed to the user.
 ///
+/// The integrator, number of samples per pixel, path length and reconstruction filter are
+/// taken from `settings`. The filter spreads each sample over the nearby pixels, so the
+/// result covers [filter_footprint()](filter_footprint) rather than just `tile`, and should
+/// be merged into the image with [merge_tile()](AccumulationBuffer::merge_tile) using the
+/// footprint.
+///
+/// All randomness is derived from `seed`, so rendering the same tile with the same seed
+/// always produces the same result. Use a different seed for each pass when accumulating
+/// several passes.
+///
 /// # Examples
 //
 /// ```
@@ -83,12 +860,19 @@ const RECURSION_LIMIT: u16 = 128;
 /// # use vanrijn::scene::Scene;
 /// # use vanrijn::util::TileIterator;
 /// # use vanrijn::partial_render_scene;
-/// # let scene = Scene { camera_location: Vec3::new(0.0, 0.0, 0.0), objects: vec![] };
+/// # use vanrijn::camera::{Lens, PerspectiveCamera, RenderSettings};
+/// # let scene = Scene::builder(Box::new(PerspectiveCamera::new(
+/// #     Vec3::new(0.0, 0.0, 0.0),
+/// #     Lens::Pinhole,
+/// # )))
+/// # .build();
 /// let image_width = 640;
 /// let image_height = 480;
 /// let time_size = 32;
 /// for tile in TileIterator::new(640, 480, 32) {
-///     let tile_image = partial_render_scene( &scene, tile, image_height, image_width );
+///     let tile_image = partial_render_scene(
+///         &scene, tile, image_height, image_width, 0, &RenderSettings::default()
+///     );
 ///     // display and/or save tile_image
 /// }
 /// ```
@@ -97,36 +881,124 @@ pub fn partial_render_scene(
     tile: Tile,
     height: usize,
     width: usize,
+    seed: u64,
+    settings: &RenderSettings,
 ) -> AccumulationBuffer {
-    let mut output_image_tile = AccumulationBuffer::new(tile.width(), tile.height());
-    let image_sampler = ImageSampler::new(width, height, scene.camera_location);
-    let integrator = SimpleRandomIntegrator {};
-    let sampler = Sampler { scene };
-    for column in 0..tile.width() {
-        for row in 0..tile.height() {
-            let ray = image_sampler.ray_for_pixel(tile.start_row + row, tile.start_column + column);
-            let hit = sampler.sample(&ray);
-            let photon = match hit {
-                None => Photon {
-                    wavelength: 0.0,
-                    intensity: 0.0,
+    let footprint = filter_footprint(&tile, width, height, settings.filter.as_ref());
+    let mut output_image_tile = AccumulationBuffer::new(footprint.width(), footprint.height());
+    render_tile_into(
+        scene,
+        &tile,
+        &footprint,
+        height,
+        width,
+        seed,
+        settings,
+        &mut output_image_tile,
+    );
+    output_image_tile
+}
+
+/// As [partial_render_scene()](partial_render_scene), but keeping the light in
+/// `band_count` wavelength bands for a hyperspectral image
+pub fn partial_render_scene_spectral(
+    scene: &Scene,
+    tile: Tile,
+    height: usize,
+    width: usize,
+    seed: u64,
+    settings: &RenderSettings,
+    band_count: usize,
+) -> SpectralAccumulationBuffer {
+    let footprint = filter_footprint(&tile, width, height, settings.filter.as_ref());
+    let mut output_image_tile =
+        SpectralAccumulationBuffer::new(footprint.width(), footprint.height(), band_count);
+    render_tile_into(
+        scene,
+        &tile,
+        &footprint,
+        height,
+        width,
+        seed,
+        settings,
+        &mut output_image_tile,
+    );
+    output_image_tile
+}
+
+/// Render the samples for `tile` into `output_image_tile`, which covers `footprint`
+#[allow(clippy::too_many_arguments)]
+fn render_tile_into<A: PhotonAccumulator>(
+    scene: &Scene,
+    tile: &Tile,
+    footprint: &Tile,
+    height: usize,
+    width: usize,
+    seed: u64,
+    settings: &RenderSettings,
+    output_image_tile: &mut A,
+) {
+    let filter = settings.filter.as_ref();
+    let image_sampler =
+        ImageSampler::new(width, height, scene.camera.as_ref()).with_shutter(settings.shutter);
+    let integrator = settings.integrator.as_ref();
+    let sampler = SceneSampler { scene };
+    // Neighbouring pixels are traced together, in square blocks of one packet each, and
+    // the blocks are traced in waves, so that integrators can follow many paths at once.
+    // Each pixel still has its own random numbers, so the result doesn't depend on either.
+    let pixels: Vec<(usize, usize)> = (0..tile.width())
+        .step_by(PIXEL_BLOCK_SIZE)
+        .flat_map(|block_column| {
+            (0..tile.height())
+                .step_by(PIXEL_BLOCK_SIZE)
+                .map(move |block_row| (block_row, block_column))
+        })
+        .flat_map(|(block_row, block_column)| {
+            (block_column..(block_column + PIXEL_BLOCK_SIZE).min(tile.width())).flat_map(
+                move |column| {
+                    (block_row..(block_row + PIXEL_BLOCK_SIZE).min(tile.height()))
+                        .map(move |row| (tile.start_row + row, tile.start_column + column))
                 },
-                Some(intersection_info) => integrator.integrate(
-                    &sampler,
-                    &intersection_info,
-                    &Photon::random_wavelength(),
-                    RECURSION_LIMIT,
-                ),
-            };
-            output_image_tile.update_pixel(
-                row,
-                column,
-                &photon.scale_intensity(Photon::random_wavelength_pdf(photon.wavelength)),
-                1.0,
+            )
+        })
+        .collect();
+    for wave in pixels.chunks(PIXELS_PER_WAVE) {
+        let mut rngs: Vec<StdRng> = wave
+            .iter()
+            .map(|&(image_row, image_column)| pixel_rng(seed, image_row, image_column))
+            .collect();
+        for _ in 0..settings.samples_per_pixel {
+            if settings.cancellation.is_cancelled() {
+                return;
+            }
+            let mut rays = Vec::with_capacity(wave.len());
+            let mut positions = Vec::with_capacity(wave.len());
+            let mut packets = Vec::with_capacity(wave.len());
+            for (&(image_row, image_column), rng) in wave.iter().zip(rngs.iter_mut()) {
+                let (ray, position) = image_sampler.sample_pixel(image_row, image_column, rng);
+                rays.push(ray);
+                positions.push(position);
+                packets.push(PhotonPacket::random_wavelengths(rng));
+            }
+            let results = integrator.integrate_batch(
+                &sampler,
+                &rays,
+                &packets,
+                settings.max_depth,
+                &mut rngs,
             );
+            for (mut packet, position) in results.into_iter().zip(positions.iter()) {
+                if let Some(max_radiance) = settings.max_radiance {
+                    packet = packet
+                        .map(|photon| photon.set_intensity(photon.intensity.min(max_radiance)));
+                }
+                if let Some(quantum_efficiency) = &settings.quantum_efficiency {
+                    packet = packet.map(|photon| quantum_efficiency.scale_photon(photon));
+                }
+                splat_sample(output_image_tile, footprint, filter, position, &packet);
+            }
         }
     }
-    output_image_tile
 }
 
 #[cfg(test)]
@@ -143,42 +1015,651 @@ mod tests {
         #[test]
         fn scale_returns_correct_value_for_zero() {
             let correct_value = (3.0 / 10.0) / 2.0;
-            assert!((ImageSampler::scale(0, 10, 3.0f64) - correct_value).abs() < 0.5)
+            let mut rng = StdRng::seed_from_u64(0);
+            assert!((ImageSampler::scale(0, 10, 3.0f64, &mut rng) - correct_value).abs() < 0.5)
         }
 
         #[test]
         fn scale_returns_correct_value_for_last_pixel() {
             let correct_value = 3.0 - (3.0 / 10.0) / 2.0;
-            assert!((ImageSampler::scale(9, 10, 3.0f64) - correct_value).abs() < 0.5)
+            let mut rng = StdRng::seed_from_u64(0);
+            assert!((ImageSampler::scale(9, 10, 3.0f64, &mut rng) - correct_value).abs() < 0.5)
+        }
+
+        #[test]
+        fn sample_pixel_ray_footprint_matches_pixel_size() {
+            let camera = PerspectiveCamera::new(Vec3::new(0.0, 0.0, 0.0), Lens::Pinhole);
+            let target = ImageSampler::new(800, 600, &camera);
+            let (ray, _) = target.sample_pixel(300, 400, &mut StdRng::seed_from_u64(0));
+            let footprint_width_at = |distance: f64| {
+                let plane = Plane::new(
+                    Vec3::new(0.0, 0.0, 1.0),
+                    distance,
+                    Arc::new(LambertianMaterial::new_dummy()),
+                );
+                let info = plane.intersect(&ray).unwrap();
+                ray.differential
+                    .unwrap()
+                    .footprint(&info.location, &info.normal)
+                    .unwrap()
+                    .width()
+            };
+            let (film_width, _) = camera.film_size(800.0 / 600.0);
+            let pixel_width = film_width / 800.0 / PerspectiveCamera::FILM_DISTANCE;
+            assert!((footprint_width_at(1.0) - pixel_width).abs() < pixel_width * 0.01);
+            assert!((footprint_width_at(10.0) - 10.0 * pixel_width).abs() < pixel_width * 0.1);
         }
 
         #[test]
-        fn ray_for_pixel_returns_value_that_intersects_film_plane_at_expected_location() {
-            let target = ImageSampler::new(800, 600, Vec3::new(0.0, 0.0, 0.0));
-            let ray = target.ray_for_pixel(100, 200);
+        fn sample_pixel_returns_ray_that_intersects_film_plane_at_expected_location() {
+            let camera = PerspectiveCamera::new(Vec3::new(0.0, 0.0, 0.0), Lens::Pinhole);
+            let target = ImageSampler::new(800, 600, &camera);
+            let (ray, _) = target.sample_pixel(100, 200, &mut StdRng::seed_from_u64(0));
             let film_plane = Plane::new(
                 Vec3::new(0.0, 0.0, 1.0),
-                target.film_distance,
+                PerspectiveCamera::FILM_DISTANCE,
                 Arc::new(LambertianMaterial::new_dummy()),
             );
             let point_on_film_plane = match film_plane.intersect(&ray) {
                 Some(IntersectionInfo {
                     location,
+                    location_error: _,
                     distance: _,
                     normal: _,
                     tangent: _,
                     cotangent: _,
                     retro: _,
+                    uv: _,
                     material: _,
+                    object_id: _,
+                    footprint: _,
+                    time: _,
                 }) => location,
                 None => panic!(),
             };
-            let expected_x: f64 =
-                ImageSampler::scale(200, 800, target.film_width) - target.film_width * 0.5;
-            assert!((point_on_film_plane.x() - expected_x).abs() < 0.5 / 200.0);
-            let expected_y =
-                -ImageSampler::scale(100, 600, target.film_height) + target.film_height * 0.5;
-            assert!((point_on_film_plane.y() - expected_y).abs() < 0.5 / 800.0);
+            // The ray may pass through any point of the pixel's footprint on the film
+            let (film_width, film_height) = camera.film_size(800.0 / 600.0);
+            let pixel_width = film_width / 800.0;
+            let pixel_height = film_height / 600.0;
+            let left = 200.0 * pixel_width - film_width * 0.5;
+            let top = film_height * 0.5 - 100.0 * pixel_height;
+            assert!(point_on_film_plane.x() >= left - 0.0000001);
+            assert!(point_on_film_plane.x() <= left + pixel_width + 0.0000001);
+            assert!(point_on_film_plane.y() <= top + 0.0000001);
+            assert!(point_on_film_plane.y() >= top - pixel_height - 0.0000001);
+        }
+
+        #[test]
+        fn thin_lens_rays_converge_on_plane_of_focus() {
+            let focus_distance = 4.0;
+            let target = PerspectiveCamera::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                Lens::ThinLens {
+                    aperture_radius: 0.5,
+                    focus_distance,
+                },
+            );
+            let focus_plane = Plane::new(
+                Vec3::new(0.0, 0.0, 1.0),
+                focus_distance,
+                Arc::new(LambertianMaterial::new_dummy()),
+            );
+            let film_point = Vec3::new(0.25, -0.125, PerspectiveCamera::FILM_DISTANCE);
+            let pinhole_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), film_point);
+            let expected_point = focus_plane.intersect(&pinhole_ray).unwrap().location;
+            let mut rng = StdRng::seed_from_u64(0);
+            for _ in 0..100 {
+                let ray = target.ray_through_film_point(&film_point, &target.sample_lens(&mut rng));
+                let point_on_focus_plane = focus_plane.intersect(&ray).unwrap().location;
+                assert!((point_on_focus_plane - expected_point).norm() < 0.0000001);
+            }
+        }
+
+        #[test]
+        fn thin_lens_ray_origins_lie_within_aperture() {
+            let aperture_radius = 0.25;
+            let camera_location = Vec3::new(1.0, 2.0, 3.0);
+            let camera = PerspectiveCamera::new(
+                camera_location,
+                Lens::ThinLens {
+                    aperture_radius,
+                    focus_distance: 2.0,
+                },
+            );
+            let target = ImageSampler::new(800, 600, &camera);
+            let mut rng = StdRng::seed_from_u64(0);
+            for _ in 0..100 {
+                let (ray, _) = target.sample_pixel(100, 200, &mut rng);
+                let offset = ray.origin - camera_location;
+                assert!(offset.z() == 0.0);
+                assert!(offset.norm() <= aperture_radius + 0.0000001);
+            }
+        }
+
+        #[test]
+        fn rolling_shutter_exposes_rows_in_turn() {
+            let camera = PerspectiveCamera::new(Vec3::zeros(), Lens::Pinhole);
+            let shutter = Shutter {
+                open: 2.0,
+                exposure: 0.01,
+                rolling_time: 0.03,
+            };
+            let target = ImageSampler::new(800, 600, &camera).with_shutter(shutter);
+            let mut rng = StdRng::seed_from_u64(0);
+            for row in [0, 300, 599] {
+                let row_open = 2.0 + 0.03 * (row as f64 / 600.0);
+                let times: Vec<f64> = (0..100)
+                    .map(|_| target.sample_pixel(row, 400, &mut rng).0.time)
+                    .collect();
+                assert!(times
+                    .iter()
+                    .all(|&time| time >= row_open && time < row_open + 0.01));
+                assert!(times.iter().any(|&time| time != times[0]));
+                let centre_time = target.pixel_centre_ray(row, 400, &mut rng).time;
+                assert!((centre_time - (row_open + 0.005)).abs() < 0.0000001);
+            }
+        }
+
+        #[test]
+        fn default_shutter_leaves_rays_at_time_zero() {
+            let camera = PerspectiveCamera::new(Vec3::zeros(), Lens::Pinhole);
+            let target = ImageSampler::new(800, 600, &camera);
+            let mut rng = StdRng::seed_from_u64(0);
+            assert!(target.sample_pixel(599, 400, &mut rng).0.time == 0.0);
+            assert!(target.pixel_centre_ray(599, 400, &mut rng).time == 0.0);
+        }
+    }
+
+    mod perspective_camera {
+        use super::*;
+
+        #[test]
+        fn look_at_turns_forward_towards_target() {
+            let location = Vec3::new(1.0, 2.0, 3.0);
+            let target = Vec3::new(-2.0, 0.0, 5.0);
+            let orientation = look_at(&location, &target, &Vec3::unit_y());
+            let forward = orientation * Vec3::unit_z();
+            assert!((forward - (target - location).normalize()).norm() < 0.0000001);
+            let up = orientation * Vec3::unit_y();
+            let right = orientation * Vec3::unit_x();
+            assert!(up.y() > 0.0);
+            assert!(up.dot(&forward).abs() < 0.0000001);
+            assert!(right.dot(&forward).abs() < 0.0000001);
+            assert!((right.cross(&up) - forward).norm() < 0.0000001);
+        }
+
+        #[test]
+        fn look_at_identity_for_default_view() {
+            let location = Vec3::new(1.0, 2.0, 3.0);
+            let orientation = look_at(&location, &(location + Vec3::unit_z()), &Vec3::unit_y());
+            let identity = Mat3::identity();
+            for row in 0..3 {
+                assert!((orientation.get_row(row) - identity.get_row(row)).norm() < 0.0000001);
+            }
+        }
+
+        #[test]
+        fn centre_of_image_looks_at_target() {
+            let location = Vec3::new(1.0, 2.0, 3.0);
+            let target = Vec3::new(4.0, -1.0, 0.0);
+            let camera = PerspectiveCamera::looking_at(
+                location,
+                &target,
+                &Vec3::unit_y(),
+                1.0,
+                Lens::Pinhole,
+            );
+            let ray = camera.ray(&Vec2::new(0.5, 0.5), 1.5, &mut StdRng::seed_from_u64(0));
+            assert!((ray.direction - (target - location).normalize()).norm() < 0.0000001);
+        }
+
+        #[test]
+        fn vertical_fov_spans_top_to_bottom_of_image() {
+            for &aspect_ratio in [0.5, 1.0, 2.0].iter() {
+                let mut camera = PerspectiveCamera::new(Vec3::zeros(), Lens::Pinhole);
+                camera.vertical_fov = 60.0f64.to_radians();
+                let mut rng = StdRng::seed_from_u64(0);
+                let top = camera.ray(&Vec2::new(0.5, 1.0), aspect_ratio, &mut rng);
+                let bottom = camera.ray(&Vec2::new(0.5, 0.0), aspect_ratio, &mut rng);
+                let angle = top.direction.dot(&bottom.direction).acos();
+                assert!((angle - camera.vertical_fov).abs() < 0.0000001);
+            }
+        }
+
+        #[test]
+        fn framing_camera_sees_whole_box() {
+            let bounds =
+                BoundingBox::from_corners(Vec3::new(5.0, 1.0, -3.0), Vec3::new(9.0, 2.0, 0.0));
+            for &aspect_ratio in [0.5, 1.0, 2.0].iter() {
+                let camera = PerspectiveCamera::framing(
+                    &bounds,
+                    &Vec3::new(1.0, -1.0, 1.0),
+                    &Vec3::unit_y(),
+                    1.0,
+                    aspect_ratio,
+                    Lens::Pinhole,
+                );
+                let (film_width, film_height) = camera.film_size(aspect_ratio);
+                let to_camera = camera.orientation.transpose();
+                for corner in 0..8 {
+                    let point = Vec3::new(
+                        if corner & 1 == 0 { 5.0 } else { 9.0 },
+                        if corner & 2 == 0 { 1.0 } else { 2.0 },
+                        if corner & 4 == 0 { -3.0 } else { 0.0 },
+                    );
+                    let in_camera = to_camera * (point - camera.location);
+                    assert!(in_camera.z() > 0.0);
+                    assert!((in_camera.x() / in_camera.z()).abs() <= 0.5 * film_width);
+                    assert!((in_camera.y() / in_camera.z()).abs() <= 0.5 * film_height);
+                }
+            }
+        }
+    }
+
+    mod orthographic_camera {
+        use super::*;
+
+        #[test]
+        fn rays_are_parallel() {
+            let target = OrthographicCamera::new(Vec3::new(1.0, 2.0, 3.0), 4.0);
+            let mut rng = StdRng::seed_from_u64(0);
+            for &(x, y) in [(0.0, 0.0), (0.3, 0.9), (1.0, 0.5)].iter() {
+                let ray = target.ray(&Vec2::new(x, y), 1.5, &mut rng);
+                assert!(ray.direction == Vec3::unit_z());
+            }
+        }
+
+        #[test]
+        fn ray_origins_cover_view() {
+            let location = Vec3::new(1.0, 2.0, 3.0);
+            let target = OrthographicCamera::new(location, 4.0);
+            let mut rng = StdRng::seed_from_u64(0);
+            let centre = target.ray(&Vec2::new(0.5, 0.5), 1.5, &mut rng);
+            assert!((centre.origin - location).norm() < 0.0000001);
+            let corner = target.ray(&Vec2::new(1.0, 1.0), 1.5, &mut rng);
+            assert!((corner.origin - (location + Vec3::new(3.0, 2.0, 0.0))).norm() < 0.0000001);
+        }
+    }
+
+    mod equirectangular_camera {
+        use super::*;
+
+        fn direction_at(x: f64, y: f64) -> Vec3 {
+            let target = EquirectangularCamera::new(Vec3::new(1.0, 2.0, 3.0));
+            let ray = target.ray(&Vec2::new(x, y), 2.0, &mut StdRng::seed_from_u64(0));
+            assert!(ray.origin == Vec3::new(1.0, 2.0, 3.0));
+            ray.direction
+        }
+
+        #[test]
+        fn centre_looks_forward() {
+            assert!((direction_at(0.5, 0.5) - Vec3::unit_z()).norm() < 0.0000001);
+        }
+
+        #[test]
+        fn top_and_bottom_look_up_and_down() {
+            assert!((direction_at(0.3, 1.0) - Vec3::unit_y()).norm() < 0.0000001);
+            assert!((direction_at(0.7, 0.0) + Vec3::unit_y()).norm() < 0.0000001);
+        }
+
+        #[test]
+        fn edges_look_backward() {
+            assert!((direction_at(0.0, 0.5) + Vec3::unit_z()).norm() < 0.0000001);
+            assert!((direction_at(1.0, 0.5) + Vec3::unit_z()).norm() < 0.0000001);
+            assert!((direction_at(0.75, 0.5) - Vec3::unit_x()).norm() < 0.0000001);
+        }
+    }
+
+    mod stereo_camera {
+        use super::*;
+
+        fn camera(layout: StereoLayout) -> StereoCamera {
+            StereoCamera {
+                location: Vec3::new(1.0, 2.0, 3.0),
+                orientation: Mat3::identity(),
+                vertical_fov: 1.0,
+                interpupillary_distance: 0.1,
+                convergence_distance: 4.0,
+                layout,
+            }
+        }
+
+        #[test]
+        fn side_by_side_has_left_eye_on_left() {
+            let target = camera(StereoLayout::SideBySide);
+            let mut rng = StdRng::seed_from_u64(0);
+            let left = target.ray(&Vec2::new(0.25, 0.5), 2.0, &mut rng);
+            let right = target.ray(&Vec2::new(0.75, 0.5), 2.0, &mut rng);
+            assert!((left.origin - Vec3::new(0.95, 2.0, 3.0)).norm() < 0.0000001);
+            assert!((right.origin - Vec3::new(1.05, 2.0, 3.0)).norm() < 0.0000001);
+        }
+
+        #[test]
+        fn top_bottom_has_left_eye_on_top() {
+            let target = camera(StereoLayout::TopBottom);
+            let mut rng = StdRng::seed_from_u64(0);
+            let left = target.ray(&Vec2::new(0.5, 0.75), 0.5, &mut rng);
+            let right = target.ray(&Vec2::new(0.5, 0.25), 0.5, &mut rng);
+            assert!(left.origin.x() < 1.0 && right.origin.x() > 1.0);
+        }
+
+        #[test]
+        fn eyes_see_same_point_at_convergence_distance() {
+            let target = camera(StereoLayout::SideBySide);
+            let mut rng = StdRng::seed_from_u64(0);
+            for &(x, y) in [(0.1, 0.2), (0.25, 0.5), (0.45, 0.9)].iter() {
+                let left = target.ray(&Vec2::new(x, y), 2.0, &mut rng);
+                let right = target.ray(&Vec2::new(x + 0.5, y), 2.0, &mut rng);
+                let at_screen =
+                    |ray: &Ray| ray.point_at((7.0 - ray.origin.z()) / ray.direction.z());
+                assert!((at_screen(&left) - at_screen(&right)).norm() < 0.0000001);
+            }
+        }
+
+        #[test]
+        fn differential_stays_with_same_eye() {
+            let target = camera(StereoLayout::SideBySide);
+            let ray = target.ray_with_differential(
+                &Vec2::new(0.499, 0.5),
+                &Vec2::new(0.01, 0.01),
+                2.0,
+                &mut StdRng::seed_from_u64(0),
+            );
+            assert!(ray.differential.unwrap().x_origin == ray.origin);
+        }
+    }
+
+    mod omni_directional_stereo_camera {
+        use super::*;
+
+        fn camera() -> OmniDirectionalStereoCamera {
+            OmniDirectionalStereoCamera {
+                location: Vec3::new(1.0, 2.0, 3.0),
+                orientation: Mat3::identity(),
+                interpupillary_distance: 0.1,
+                layout: StereoLayout::TopBottom,
+            }
+        }
+
+        #[test]
+        fn eyes_look_in_same_directions_as_equirectangular_camera() {
+            let target = camera();
+            let reference = EquirectangularCamera::new(Vec3::new(1.0, 2.0, 3.0));
+            let mut rng = StdRng::seed_from_u64(0);
+            for &(x, y) in [(0.5, 0.5), (0.3, 0.1), (0.9, 0.8)].iter() {
+                let expected = reference.ray(&Vec2::new(x, y), 2.0, &mut rng).direction;
+                let left = target.ray(&Vec2::new(x, 0.5 + 0.5 * y), 1.0, &mut rng);
+                let right = target.ray(&Vec2::new(x, 0.5 * y), 1.0, &mut rng);
+                assert!((left.direction - expected).norm() < 0.0000001);
+                assert!((right.direction - expected).norm() < 0.0000001);
+            }
+        }
+
+        #[test]
+        fn eyes_are_either_side_of_view_direction() {
+            let target = camera();
+            let mut rng = StdRng::seed_from_u64(0);
+            for &x in [0.0, 0.3, 0.5, 0.75].iter() {
+                let left = target.ray(&Vec2::new(x, 0.75), 1.0, &mut rng);
+                let right = target.ray(&Vec2::new(x, 0.25), 1.0, &mut rng);
+                let offset = right.origin - left.origin;
+                assert!((offset.norm() - 0.1).abs() < 0.0000001);
+                assert!(offset.dot(&left.direction).abs() < 0.0000001);
+                // The right eye is to the right of the view direction
+                assert!(Vec3::unit_y().cross(&left.direction).dot(&offset) > 0.0);
+            }
+        }
+    }
+
+    mod partial_render_scene {
+        use super::*;
+
+        use crate::filters::{GaussianFilter, TentFilter};
+        use crate::image::ClampingToneMapper;
+
+        use crate::raycasting::Sphere;
+
+        fn test_scene() -> Scene {
+            Scene::builder(Box::new(PerspectiveCamera::new(
+                Vec3::new(0.0, 0.0, -3.0),
+                Lens::ThinLens {
+                    aperture_radius: 0.1,
+                    focus_distance: 3.0,
+                },
+            )))
+            .with_object(Box::new(vec![Box::new(Sphere::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                1.0,
+                Arc::new(LambertianMaterial {
+                    colour: crate::colour::Spectrum::grey(0.5),
+                    diffuse_strength: 1.0,
+                }),
+            ))
+                as Box<dyn crate::raycasting::Primitive>]))
+            .build()
+        }
+
+        fn render(scene: &Scene, tile: Tile, seed: u64) -> Vec<u8> {
+            render_with_settings(scene, tile, seed, &RenderSettings::default())
+        }
+
+        fn render_with_settings(
+            scene: &Scene,
+            tile: Tile,
+            seed: u64,
+            settings: &RenderSettings,
+        ) -> Vec<u8> {
+            partial_render_scene(scene, tile, 16, 16, seed, settings)
+                .to_image_rgb_u8(&ClampingToneMapper::default())
+                .get_pixel_data()
+                .to_vec()
+        }
+
+        fn whole_image() -> Tile {
+            Tile {
+                start_column: 0,
+                end_column: 16,
+                start_row: 0,
+                end_row: 16,
+            }
+        }
+
+        #[test]
+        fn same_seed_produces_same_image() {
+            let scene = test_scene();
+            assert!(render(&scene, whole_image(), 7) == render(&scene, whole_image(), 7));
+        }
+
+        #[test]
+        fn spectral_render_has_same_colours() {
+            let scene = test_scene();
+            // The bands are noisier than XYZ, so it takes more samples to compare them
+            let settings = RenderSettings {
+                samples_per_pixel: 256,
+                ..RenderSettings::default()
+            };
+            let xyz = partial_render_scene(&scene, whole_image(), 16, 16, 7, &settings);
+            let spectral =
+                partial_render_scene_spectral(&scene, whole_image(), 16, 16, 7, &settings, 36);
+            for &(row, column) in [(4, 4), (8, 8), (12, 3)].iter() {
+                let expected = xyz.colour(row, column).values;
+                let actual = spectral.colour(row, column).values;
+                assert!((actual - expected).norm() <= 0.05 * expected.norm());
+            }
+        }
+
+        #[test]
+        fn different_seeds_produce_different_images() {
+            let scene = test_scene();
+            assert!(render(&scene, whole_image(), 7) != render(&scene, whole_image(), 8));
+        }
+
+        #[test]
+        fn pixels_do_not_depend_on_tiling() {
+            let scene = test_scene();
+            let whole = render(&scene, whole_image(), 7);
+            let tile = Tile {
+                start_column: 8,
+                end_column: 16,
+                start_row: 4,
+                end_row: 12,
+            };
+            let part = render(&scene, tile, 7);
+            let channels = crate::image::ImageRgbU8::num_channels();
+            for row in 0..8 {
+                let start = ((row + 4) * 16 + 8) * channels;
+                assert!(
+                    part[row * 8 * channels..(row + 1) * 8 * channels]
+                        == whole[start..start + 8 * channels]
+                );
+            }
+        }
+
+        #[test]
+        fn footprint_grows_tile_by_filter_radius() {
+            let tile = Tile {
+                start_column: 0,
+                end_column: 8,
+                start_row: 4,
+                end_row: 12,
+            };
+            let box_footprint = filter_footprint(&tile, 16, 16, &BoxFilter::default());
+            assert!(box_footprint == tile);
+            let footprint = filter_footprint(&tile, 16, 16, &TentFilter::new(2.0));
+            assert!(footprint.start_column == 0 && footprint.end_column == 10);
+            assert!(footprint.start_row == 2 && footprint.end_row == 14);
+        }
+
+        #[test]
+        fn filtered_pixels_do_not_depend_on_tiling() {
+            let scene = test_scene();
+            let settings = RenderSettings {
+                filter: Arc::new(TentFilter::new(1.5)),
+                ..RenderSettings::default()
+            };
+            let render_tiles = |tiles: &[Tile]| {
+                let mut image = AccumulationBuffer::new(16, 16);
+                for tile in tiles {
+                    let footprint = filter_footprint(tile, 16, 16, settings.filter.as_ref());
+                    let rendered = partial_render_scene(&scene, *tile, 16, 16, 7, &settings);
+                    image.merge_tile(&footprint, &rendered);
+                }
+                image
+                    .to_image_rgb_u8(&ClampingToneMapper::default())
+                    .get_pixel_data()
+                    .to_vec()
+            };
+            let quarters: Vec<Tile> = crate::util::TileIterator::new(16, 16, 8).collect();
+            assert!(quarters.len() == 4);
+            assert!(render_tiles(&[whole_image()]) == render_tiles(&quarters));
+        }
+
+        #[test]
+        fn wide_filter_blurs_image() {
+            let scene = test_scene();
+            let settings = RenderSettings {
+                filter: Arc::new(GaussianFilter::default()),
+                ..RenderSettings::default()
+            };
+            assert!(
+                render(&scene, whole_image(), 7)
+                    != render_with_settings(&scene, whole_image(), 7, &settings)
+            );
+        }
+
+        #[test]
+        fn clamping_radiance_darkens_image() {
+            let scene = test_scene();
+            let settings = RenderSettings {
+                max_radiance: Some(0.001),
+                ..RenderSettings::default()
+            };
+            let brightness = |image: Vec<u8>| image.iter().map(|&c| c as u64).sum::<u64>();
+            assert!(
+                brightness(render_with_settings(&scene, whole_image(), 7, &settings))
+                    < brightness(render(&scene, whole_image(), 7))
+            );
+        }
+
+        #[test]
+        fn sensor_blind_to_red_light_makes_image_bluer() {
+            let scene = test_scene();
+            let settings = RenderSettings {
+                quantum_efficiency: Some(Arc::new(Spectrum::from_function(73, |wavelength| {
+                    if wavelength < 550.0 {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                }))),
+                ..RenderSettings::default()
+            };
+            let channel = |image: &[u8], channel: usize| {
+                image
+                    .iter()
+                    .skip(channel)
+                    .step_by(3)
+                    .map(|&c| c as f64)
+                    .sum::<f64>()
+            };
+            let (blind, ideal) = (
+                render_with_settings(&scene, whole_image(), 7, &settings),
+                render(&scene, whole_image(), 7),
+            );
+            assert!(
+                channel(&blind, 0) / channel(&ideal, 0) < channel(&blind, 2) / channel(&ideal, 2)
+            );
+        }
+
+        #[test]
+        fn clamping_above_brightest_sample_leaves_image_unchanged() {
+            let scene = test_scene();
+            let settings = RenderSettings {
+                max_radiance: Some(1.0e12),
+                ..RenderSettings::default()
+            };
+            assert!(
+                render(&scene, whole_image(), 7)
+                    == render_with_settings(&scene, whole_image(), 7, &settings)
+            );
+        }
+
+        #[test]
+        fn more_samples_per_pixel_change_image() {
+            let scene = test_scene();
+            let settings = RenderSettings {
+                samples_per_pixel: 4,
+                ..RenderSettings::default()
+            };
+            assert!(
+                render(&scene, whole_image(), 7)
+                    != render_with_settings(&scene, whole_image(), 7, &settings)
+            );
+        }
+
+        #[test]
+        fn cancelled_render_takes_no_samples() {
+            let scene = test_scene();
+            let settings = RenderSettings::default();
+            settings.cancellation.cancel();
+            let image = render_with_settings(&scene, whole_image(), 7, &settings);
+            assert!(image.iter().all(|&c| c == 0));
+        }
+
+        #[test]
+        fn zero_max_depth_leaves_objects_black() {
+            let scene = test_scene();
+            let settings = RenderSettings {
+                max_depth: 0,
+                ..RenderSettings::default()
+            };
+            let image = render_with_settings(&scene, whole_image(), 7, &settings);
+            let channels = crate::image::ImageRgbU8::num_channels();
+            let centre = (8 * 16 + 8) * channels;
+            assert!(image[centre..centre + channels].iter().all(|&c| c == 0));
+            assert!(render(&scene, whole_image(), 7)[centre..centre + channels]
+                .iter()
+                .any(|&c| c > 0));
         }
     }
 }
@@ -0,0 +1,229 @@
+//! RGB colour spaces, and conversion between them and CIE XYZ
+//!
+//! Each space is defined by the chromaticities of its red, green and blue primaries, the
+//! chromaticity of its white point, and the transfer function used to encode linear
+//! values for storage. Rendered XYZ values are relative to a D65 white, which is the
+//! white point of sRGB, so spaces with other white points are converted with a Bradford
+//! chromatic adaptation, which keeps D65 white looking white.
+
+use crate::math::{Mat3, Vec2, Vec3};
+
+use super::{ColourRgbF, ColourXyz};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColourSpace {
+    /// The usual space for images on computer displays and the web
+    #[default]
+    Srgb,
+
+    /// The HDTV space, with the same primaries as sRGB but a different transfer function
+    Rec709,
+
+    /// The ACES working space for rendering and compositing, which has a wider gamut than
+    /// sRGB and is always linear
+    AcesCg,
+}
+
+/// The chromaticity of the CIE D65 illuminant, the white point of sRGB
+pub fn d65_white_point() -> Vec2 {
+    Vec2::new(0.3127, 0.3290)
+}
+
+/// The XYZ colour with chromaticity `xy` and a luminance of one
+fn xyz_from_chromaticity(xy: &Vec2) -> Vec3 {
+    Vec3::new(xy.x() / xy.y(), 1.0, (1.0 - xy.x() - xy.y()) / xy.y())
+}
+
+/// The transformation of XYZ colours that makes white at `from_white` appear as white at
+/// `to_white`, using the Bradford cone response
+pub fn bradford_adaptation(from_white: &Vec2, to_white: &Vec2) -> Mat3 {
+    let bradford = Mat3::from_rows(
+        &Vec3::new(0.8951, 0.2664, -0.1614),
+        &Vec3::new(-0.7502, 1.7135, 0.0367),
+        &Vec3::new(0.0389, -0.0685, 1.0296),
+    );
+    let from_cone = bradford * xyz_from_chromaticity(from_white);
+    let to_cone = bradford * xyz_from_chromaticity(to_white);
+    let scale = Mat3::new(
+        to_cone.x() / from_cone.x(),
+        0.0,
+        0.0,
+        0.0,
+        to_cone.y() / from_cone.y(),
+        0.0,
+        0.0,
+        0.0,
+        to_cone.z() / from_cone.z(),
+    );
+    bradford.try_inverse().unwrap() * scale * bradford
+}
+
+impl ColourSpace {
+    /// Chromaticities of the red, green and blue primaries
+    fn primaries(&self) -> [Vec2; 3] {
+        match self {
+            ColourSpace::Srgb | ColourSpace::Rec709 => [
+                Vec2::new(0.64, 0.33),
+                Vec2::new(0.30, 0.60),
+                Vec2::new(0.15, 0.06),
+            ],
+            ColourSpace::AcesCg => [
+                Vec2::new(0.713, 0.293),
+                Vec2::new(0.165, 0.830),
+                Vec2::new(0.128, 0.044),
+            ],
+        }
+    }
+
+    pub fn white_point(&self) -> Vec2 {
+        match self {
+            ColourSpace::Srgb | ColourSpace::Rec709 => d65_white_point(),
+            // The ACES white, which is close to D60
+            ColourSpace::AcesCg => Vec2::new(0.32168, 0.33767),
+        }
+    }
+
+    /// The matrix converting linear RGB values in this space to D65-relative XYZ
+    pub fn to_xyz_matrix(&self) -> Mat3 {
+        let [red, green, blue] = self.primaries().map(|xy| xyz_from_chromaticity(&xy));
+        let primaries = Mat3::from_rows(&red, &green, &blue).transpose();
+        // Scale the primaries so that they add up to the white point
+        let scale = primaries.try_inverse().unwrap() * xyz_from_chromaticity(&self.white_point());
+        let to_native_xyz = Mat3::from_rows(
+            &(red * scale.x()),
+            &(green * scale.y()),
+            &(blue * scale.z()),
+        )
+        .transpose();
+        bradford_adaptation(&self.white_point(), &d65_white_point()) * to_native_xyz
+    }
+
+    /// The matrix converting D65-relative XYZ values to linear RGB in this space
+    pub fn from_xyz_matrix(&self) -> Mat3 {
+        self.to_xyz_matrix().try_inverse().unwrap()
+    }
+
+    pub fn to_xyz(&self, colour: &ColourRgbF) -> ColourXyz {
+        ColourXyz {
+            values: self.to_xyz_matrix() * colour.values,
+        }
+    }
+
+    pub fn from_xyz(&self, colour: &ColourXyz) -> ColourRgbF {
+        ColourRgbF::from_vec3(&(self.from_xyz_matrix() * colour.values))
+    }
+
+    /// Apply the space's transfer function to a linear value, as when writing an image
+    pub fn encode(&self, linear: f64) -> f64 {
+        match self {
+            ColourSpace::Srgb => {
+                if linear <= 0.003_130_8 {
+                    12.92 * linear
+                } else {
+                    1.055 * linear.powf(1.0 / 2.4) - 0.055
+                }
+            }
+            ColourSpace::Rec709 => {
+                if linear < 0.018 {
+                    4.5 * linear
+                } else {
+                    1.099 * linear.powf(0.45) - 0.099
+                }
+            }
+            ColourSpace::AcesCg => linear,
+        }
+    }
+
+    /// Undo the space's transfer function, as when reading an image
+    pub fn decode(&self, encoded: f64) -> f64 {
+        match self {
+            ColourSpace::Srgb => {
+                if encoded <= 0.040_45 {
+                    encoded / 12.92
+                } else {
+                    ((encoded + 0.055) / 1.055).powf(2.4)
+                }
+            }
+            ColourSpace::Rec709 => {
+                if encoded < 0.081 {
+                    encoded / 4.5
+                } else {
+                    ((encoded + 0.099) / 1.099).powf(1.0 / 0.45)
+                }
+            }
+            ColourSpace::AcesCg => encoded,
+        }
+    }
+
+    /// Encode each channel of `colour`; see [encode()](ColourSpace::encode)
+    pub fn encode_rgb(&self, colour: &ColourRgbF) -> ColourRgbF {
+        ColourRgbF::new(
+            self.encode(colour.red()),
+            self.encode(colour.green()),
+            self.encode(colour.blue()),
+        )
+    }
+
+    /// Decode each channel of `colour`; see [decode()](ColourSpace::decode)
+    pub fn decode_rgb(&self, colour: &ColourRgbF) -> ColourRgbF {
+        ColourRgbF::new(
+            self.decode(colour.red()),
+            self.decode(colour.green()),
+            self.decode(colour.blue()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_SPACES: [ColourSpace; 3] =
+        [ColourSpace::Srgb, ColourSpace::Rec709, ColourSpace::AcesCg];
+
+    #[test]
+    fn srgb_matrix_matches_standard() {
+        let matrix = ColourSpace::Srgb.to_xyz_matrix();
+        let expected = Mat3::from_rows(
+            &Vec3::new(0.4124, 0.3576, 0.1805),
+            &Vec3::new(0.2126, 0.7152, 0.0722),
+            &Vec3::new(0.0193, 0.1192, 0.9505),
+        );
+        for row in 0..3 {
+            assert!((matrix.get_row(row) - expected.get_row(row)).norm() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn d65_white_is_white_in_every_space() {
+        let white = ColourXyz {
+            values: xyz_from_chromaticity(&d65_white_point()),
+        };
+        for space in ALL_SPACES.iter() {
+            let rgb = space.from_xyz(&white);
+            assert!((rgb.values - Vec3::new(1.0, 1.0, 1.0)).norm() < 0.000_001);
+        }
+    }
+
+    #[test]
+    fn adaptation_maps_white_to_white() {
+        let d50 = Vec2::new(0.3457, 0.3585);
+        let adapted = bradford_adaptation(&d50, &d65_white_point()) * xyz_from_chromaticity(&d50);
+        assert!((adapted - xyz_from_chromaticity(&d65_white_point())).norm() < 0.000_001);
+    }
+
+    #[test]
+    fn encoding_roundtrips() {
+        for space in ALL_SPACES.iter() {
+            for &value in [0.0, 0.001, 0.01, 0.2, 0.5, 1.0].iter() {
+                assert!((space.decode(space.encode(value)) - value).abs() < 0.000_000_1);
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_encoding_brightens_mid_grey() {
+        assert!((ColourSpace::Srgb.encode(0.5) - 0.7354).abs() < 0.0001);
+        assert!((ColourSpace::Srgb.encode(1.0) - 1.0).abs() < 0.000_000_1);
+    }
+}
@@ -1,6 +1,10 @@
-use crate::math::{Mat3, Vec3};
+use crate::math::Vec3;
 
-use super::{ColourRgbF, Photon};
+use super::cie_1931::colour_matching_functions;
+use super::{
+    ColourRgbF, ColourSpace, Photon, Spectrum, LONGEST_VISIBLE_WAVELENGTH,
+    SHORTEST_VISIBLE_WAVELENGTH,
+};
 
 /// A CIE XYZ Colour Value
 #[derive(Clone, Debug, Default, PartialEq)]
@@ -18,14 +22,31 @@ impl ColourXyz {
 
     /// Calculate the XYZ colour of a laser light with the given wavelength
     ///
-    /// The wavelength is in nanometres.
+    /// The wavelength is in nanometres. This is the
+    /// [CIE 1931 standard observer](super::cie_1931)'s response to it.
     pub fn for_wavelength(wavelength: f64) -> ColourXyz {
-        let values = Vec3::new(
-            colour_matching_function_x(wavelength),
-            colour_matching_function_y(wavelength),
-            colour_matching_function_z(wavelength),
-        );
-        ColourXyz { values }
+        ColourXyz {
+            values: colour_matching_functions(wavelength),
+        }
+    }
+
+    /// The colour of `spectrum`, integrated over the visible wavelengths
+    ///
+    /// This is the mean of [for_wavelength()](ColourXyz::for_wavelength) weighted by the
+    /// spectrum, so it's the colour that photons with wavelengths chosen uniformly across
+    /// the visible range average out to.
+    pub fn from_spectrum(spectrum: &Spectrum) -> ColourXyz {
+        let first = SHORTEST_VISIBLE_WAVELENGTH as usize;
+        let last = LONGEST_VISIBLE_WAVELENGTH as usize;
+        let total = (first..=last)
+            .map(|wavelength| {
+                let wavelength = wavelength as f64;
+                colour_matching_functions(wavelength) * spectrum.intensity_at_wavelength(wavelength)
+            })
+            .fold(Vec3::zeros(), |a, b| a + b);
+        ColourXyz {
+            values: total * (1.0 / (last - first + 1) as f64),
+        }
     }
 
     pub fn from_photon(photon: &Photon) -> ColourXyz {
@@ -46,62 +67,22 @@ impl ColourXyz {
         self.values.z()
     }
 
+    /// The colour in linear sRGB
     pub fn to_linear_rgb(&self) -> ColourRgbF {
-        let transform = Mat3::from_rows(
-            &Vec3::new(3.24096994, -1.53738318, -0.49861076),
-            &Vec3::new(-0.96924364, 1.87596750, 0.04155506),
-            &Vec3::new(0.05563008, -0.20397696, 1.05697151),
-        );
-        ColourRgbF::from_vec3(&(transform * self.values))
+        ColourSpace::Srgb.from_xyz(self)
     }
 
+    /// The XYZ colour of `rgb`, in linear sRGB
     pub fn from_linear_rgb(rgb: &ColourRgbF) -> ColourXyz {
-        let transform = Mat3::from_rows(
-            &Vec3::new(0.41239080, 0.35758434, 0.18048079),
-            &Vec3::new(0.21263901, 0.71516868, 0.07219232),
-            &Vec3::new(0.01933082, 0.11919478, 0.95053215),
-        );
-        ColourXyz {
-            values: transform * rgb.values,
-        }
+        ColourSpace::Srgb.to_xyz(rgb)
     }
 
+    /// The colour in gamma-encoded sRGB, ready to be written to an image
     pub fn to_srgb(&self) -> ColourRgbF {
-        let mut srgb = self.to_linear_rgb();
-        for element in srgb.values.coords.iter_mut() {
-            *element = srgb_gamma(*element);
-        }
-        srgb
-    }
-}
-
-fn srgb_gamma(u: f64) -> f64 {
-    if u <= 0.0031308 {
-        12.98 * u
-    } else {
-        1.005 * u.powf(1.0 / 2.4) - 0.055
+        ColourSpace::Srgb.encode_rgb(&self.to_linear_rgb())
     }
 }
 
-fn gaussian(wavelength: f64, alpha: f64, mu: f64, sigma1: f64, sigma2: f64) -> f64 {
-    let denominator = 2.0 * (if wavelength < mu { sigma1 } else { sigma2 }).powi(2);
-    alpha * (-(wavelength - mu).powi(2) / denominator).exp()
-}
-
-fn colour_matching_function_x(wavelength: f64) -> f64 {
-    gaussian(wavelength, 1.056, 599.8, 37.9, 31.0)
-        + gaussian(wavelength, 0.362, 442.0, 16.0, 26.7)
-        + gaussian(wavelength, -0.065, 501.1, 20.4, 26.2)
-}
-
-fn colour_matching_function_y(wavelength: f64) -> f64 {
-    gaussian(wavelength, 0.821, 568.8, 46.9, 40.5) + gaussian(wavelength, 0.286, 530.9, 16.3, 31.1)
-}
-
-fn colour_matching_function_z(wavelength: f64) -> f64 {
-    gaussian(wavelength, 1.217, 437.0, 11.8, 36.0) + gaussian(wavelength, 0.681, 459.0, 26.0, 13.8)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,4 +112,12 @@ mod tests {
         let xyz = ColourXyz::from_linear_rgb(&rgb);
         assert!((target.values - xyz.values).norm() < 0.00000001);
     }
+
+    #[test]
+    fn white_light_spectrum_is_white_in_srgb() {
+        let spectrum = Spectrum::emission_from_linear_rgb(&ColourRgbF::new(1.0, 1.0, 1.0));
+        let rgb = ColourXyz::from_spectrum(&spectrum).to_linear_rgb();
+        assert!((rgb.red() / rgb.green() - 1.0).abs() < 0.05);
+        assert!((rgb.blue() / rgb.green() - 1.0).abs() < 0.05);
+    }
 }
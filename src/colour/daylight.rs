@@ -0,0 +1,126 @@
+//! The CIE daylight model, which describes the spectrum of natural daylight
+//!
+//! Daylight spectra are combinations of three basis functions, weighted according to the
+//! chromaticity of the light. The CIE standard illuminants D50, D65 and so on are all
+//! daylight spectra.
+
+pub const DAYLIGHT_SHORTEST_WAVELENGTH: f64 = 380.0;
+pub const DAYLIGHT_WAVELENGTH_STEP: f64 = 10.0;
+
+/// CIE daylight basis functions S0, S1 and S2, at 10nm intervals from 380nm to 780nm
+///
+/// Any daylight spectrum is approximately a combination of these, with weights determined
+/// by its chromaticity.
+const DAYLIGHT_BASIS: [[f64; 3]; 41] = [
+    [63.4, 38.5, 3.0],
+    [65.8, 35.0, 1.2],
+    [94.8, 43.4, -1.1],
+    [104.8, 46.3, -0.5],
+    [105.9, 43.9, -0.7],
+    [96.8, 37.1, -1.2],
+    [113.9, 36.7, -2.6],
+    [125.6, 35.9, -2.9],
+    [125.5, 32.6, -2.8],
+    [121.3, 27.9, -2.6],
+    [121.3, 24.3, -2.6],
+    [113.5, 20.1, -1.8],
+    [113.1, 16.2, -1.5],
+    [110.8, 13.2, -1.3],
+    [106.5, 8.6, -1.2],
+    [108.8, 6.1, -1.0],
+    [105.3, 4.2, -0.5],
+    [104.4, 1.9, -0.3],
+    [100.0, 0.0, 0.0],
+    [96.0, -1.6, 0.2],
+    [95.1, -3.5, 0.5],
+    [89.1, -3.5, 2.1],
+    [90.5, -5.8, 3.2],
+    [90.3, -7.2, 4.1],
+    [88.4, -8.6, 4.7],
+    [84.0, -9.5, 5.1],
+    [85.1, -10.9, 6.7],
+    [81.9, -10.7, 7.3],
+    [82.6, -12.0, 8.6],
+    [84.9, -14.0, 9.8],
+    [81.3, -13.6, 10.2],
+    [71.9, -12.0, 8.3],
+    [74.3, -13.3, 9.6],
+    [76.4, -12.9, 8.5],
+    [63.3, -10.6, 7.0],
+    [71.7, -11.6, 7.6],
+    [77.0, -12.2, 8.0],
+    [65.2, -10.2, 6.7],
+    [47.7, -7.8, 5.2],
+    [68.6, -11.2, 7.4],
+    [65.0, -10.4, 6.8],
+];
+
+/// The daylight basis functions at `wavelength`, linearly interpolated
+pub fn daylight_basis(wavelength: f64) -> [f64; 3] {
+    let position = (wavelength - DAYLIGHT_SHORTEST_WAVELENGTH) / DAYLIGHT_WAVELENGTH_STEP;
+    if position < 0.0 || position > (DAYLIGHT_BASIS.len() - 1) as f64 {
+        return [0.0; 3];
+    }
+    let index = (position as usize).min(DAYLIGHT_BASIS.len() - 2);
+    let ratio = position - index as f64;
+    let mut result = [0.0; 3];
+    for (i, value) in result.iter_mut().enumerate() {
+        *value = DAYLIGHT_BASIS[index][i] * (1.0 - ratio) + DAYLIGHT_BASIS[index + 1][i] * ratio;
+    }
+    result
+}
+
+/// Weights of the daylight basis functions for a daylight spectrum of chromaticity (x, y)
+pub fn daylight_weights(x: f64, y: f64) -> [f64; 3] {
+    let denominator = 0.0241 + 0.2562 * x - 0.7341 * y;
+    let m1 = (-1.3515 - 1.7703 * x + 5.9114 * y) / denominator;
+    let m2 = (0.0300 - 31.4424 * x + 30.0717 * y) / denominator;
+    [1.0, m1, m2]
+}
+
+/// Relative spectral power at `wavelength` of the daylight spectrum with chromaticity
+/// (x, y), scaled to 100 at 560nm
+pub fn daylight(x: f64, y: f64, wavelength: f64) -> f64 {
+    daylight_weights(x, y)
+        .iter()
+        .zip(daylight_basis(wavelength).iter())
+        .map(|(weight, basis)| weight * basis)
+        .sum()
+}
+
+/// Relative spectral power of CIE standard illuminant D65 at `wavelength`
+///
+/// The white point of sRGB is D65, so this is the spectrum of an sRGB white light source.
+/// It is scaled to 100 at 560nm.
+pub fn d65(wavelength: f64) -> f64 {
+    daylight(0.31271, 0.32902, wavelength)
+}
+
+/// Relative spectral power of CIE standard illuminant D50 at `wavelength`, scaled to 100
+/// at 560nm
+///
+/// This is the usual white point for printing.
+pub fn d50(wavelength: f64) -> f64 {
+    daylight(0.34567, 0.35850, wavelength)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daylight_basis_interpolates_table() {
+        assert!(daylight_basis(560.0) == [100.0, 0.0, 0.0]);
+        let between = daylight_basis(385.0);
+        assert!((between[0] - 64.6).abs() < 0.000000001);
+        assert!(daylight_basis(800.0) == [0.0; 3]);
+    }
+
+    #[test]
+    fn d65_matches_standard_values() {
+        // Tabulated values of D65 at 10nm intervals
+        for &(wavelength, expected) in [(400.0, 82.75), (500.0, 109.35), (600.0, 90.01)].iter() {
+            assert!((d65(wavelength) - expected).abs() < 0.5);
+        }
+    }
+}
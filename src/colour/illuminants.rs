@@ -0,0 +1,134 @@
+//! CIE standard illuminants
+//!
+//! These are the reference light sources used to specify colours: A for incandescent light,
+//! the D series for daylight and the F series for fluorescent lamps. F2, F7 and F11 are
+//! the representative cool white, broadband daylight and narrow triband lamps.
+
+use super::daylight::{d50, d65};
+
+pub const FLUORESCENT_SHORTEST_WAVELENGTH: f64 = 380.0;
+pub const FLUORESCENT_WAVELENGTH_STEP: f64 = 5.0;
+
+/// Relative spectral power of F2 at 5nm intervals from 380nm to 780nm
+const F2: [f64; 81] = [
+    1.18, 1.48, 1.84, 2.15, 3.44, 15.69, 3.85, 3.74, 4.19, 4.62, 5.06, 34.98, 11.81, 6.27, 6.63,
+    6.93, 7.19, 7.40, 7.54, 7.62, 7.65, 7.62, 7.62, 7.45, 7.28, 7.15, 7.05, 7.04, 7.16, 7.47, 8.04,
+    8.88, 10.01, 24.88, 16.64, 14.59, 16.16, 17.56, 18.62, 21.47, 22.79, 19.29, 18.66, 17.73,
+    16.54, 15.21, 13.80, 12.36, 10.95, 9.65, 8.40, 7.32, 6.31, 5.43, 4.68, 4.02, 3.45, 2.96, 2.55,
+    2.19, 1.89, 1.64, 1.53, 1.27, 1.10, 0.99, 0.88, 0.76, 0.68, 0.61, 0.56, 0.54, 0.51, 0.47, 0.47,
+    0.43, 0.46, 0.47, 0.40, 0.33, 0.27,
+];
+
+/// Relative spectral power of F7 at 5nm intervals from 380nm to 780nm
+const F7: [f64; 81] = [
+    2.56, 3.18, 3.84, 4.53, 6.15, 19.37, 7.37, 7.05, 7.71, 8.41, 9.15, 44.14, 17.52, 11.35, 12.00,
+    12.58, 13.08, 13.45, 13.71, 13.88, 13.95, 13.93, 13.82, 13.64, 13.43, 13.25, 13.08, 12.93,
+    12.78, 12.60, 12.44, 12.33, 12.26, 29.52, 17.05, 12.44, 12.58, 12.72, 12.83, 15.46, 16.75,
+    12.83, 12.67, 12.45, 12.19, 11.89, 11.60, 11.35, 11.12, 10.95, 10.76, 10.42, 10.11, 10.04,
+    10.02, 10.11, 9.87, 8.65, 7.27, 6.44, 5.83, 5.41, 5.04, 4.57, 4.12, 3.77, 3.46, 3.08, 2.73,
+    2.47, 2.25, 2.06, 1.90, 1.75, 1.62, 1.54, 1.45, 1.32, 1.17, 0.99, 0.81,
+];
+
+/// Relative spectral power of F11 at 5nm intervals from 380nm to 780nm
+const F11: [f64; 81] = [
+    0.91, 0.63, 0.46, 0.37, 1.29, 12.68, 1.59, 1.79, 2.46, 3.33, 4.49, 33.94, 12.13, 6.95, 7.19,
+    7.12, 6.72, 6.13, 5.46, 4.79, 5.66, 14.29, 14.96, 8.97, 4.72, 2.33, 1.47, 1.10, 0.89, 0.83,
+    1.18, 4.90, 39.59, 72.84, 32.61, 7.52, 2.83, 1.96, 1.67, 4.43, 11.28, 14.76, 12.73, 9.74, 7.33,
+    9.72, 55.27, 42.58, 13.18, 13.16, 12.26, 5.11, 2.07, 2.34, 3.58, 3.01, 2.48, 2.14, 1.54, 1.33,
+    1.46, 1.94, 2.00, 1.20, 1.35, 4.10, 5.58, 2.51, 0.57, 0.27, 0.23, 0.21, 0.24, 0.24, 0.20, 0.24,
+    0.32, 0.26, 0.16, 0.12, 0.09,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StandardIlluminant {
+    /// A tungsten filament lamp, as a black body at about 2856K
+    A,
+
+    /// Horizon daylight, with a colour temperature of about 5003K
+    D50,
+
+    /// Noon daylight, with a colour temperature of about 6504K, and the white of sRGB
+    D65,
+
+    F2,
+    F7,
+    F11,
+}
+
+impl StandardIlluminant {
+    /// Relative spectral power at `wavelength`, in nanometres
+    ///
+    /// Illuminants A and D are scaled to 100 at 560nm; the F series are as tabulated by
+    /// the CIE. All are zero outside 380nm to 780nm.
+    pub fn value(&self, wavelength: f64) -> f64 {
+        match self {
+            StandardIlluminant::A => {
+                if !(380.0..=780.0).contains(&wavelength) {
+                    return 0.0;
+                }
+                // Planck's law with the second radiation constant as it was when A was
+                // defined, in nanometre-kelvins
+                let c2_over_t: f64 = 1.435e7 / 2848.0;
+                100.0 * (560.0 / wavelength).powi(5) * ((c2_over_t / 560.0).exp() - 1.0)
+                    / ((c2_over_t / wavelength).exp() - 1.0)
+            }
+            StandardIlluminant::D50 => d50(wavelength),
+            StandardIlluminant::D65 => d65(wavelength),
+            StandardIlluminant::F2 => fluorescent(&F2, wavelength),
+            StandardIlluminant::F7 => fluorescent(&F7, wavelength),
+            StandardIlluminant::F11 => fluorescent(&F11, wavelength),
+        }
+    }
+}
+
+/// The tabulated fluorescent spectrum `table` at `wavelength`, linearly interpolated
+fn fluorescent(table: &[f64; 81], wavelength: f64) -> f64 {
+    let position = (wavelength - FLUORESCENT_SHORTEST_WAVELENGTH) / FLUORESCENT_WAVELENGTH_STEP;
+    if !(0.0..=(table.len() - 1) as f64).contains(&position) {
+        return 0.0;
+    }
+    let index = (position as usize).min(table.len() - 2);
+    let ratio = position - index as f64;
+    table[index] * (1.0 - ratio) + table[index + 1] * ratio
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::cie_1931::colour_matching_functions;
+
+    fn chromaticity(illuminant: StandardIlluminant) -> (f64, f64) {
+        let xyz = (380..=780)
+            .map(|wavelength| {
+                let wavelength = wavelength as f64;
+                colour_matching_functions(wavelength) * illuminant.value(wavelength)
+            })
+            .fold(crate::math::Vec3::zeros(), |a, b| a + b);
+        let total = xyz.x() + xyz.y() + xyz.z();
+        (xyz.x() / total, xyz.y() / total)
+    }
+
+    #[test]
+    fn illuminants_have_standard_chromaticities() {
+        for &(illuminant, x, y) in [
+            (StandardIlluminant::A, 0.4476, 0.4074),
+            (StandardIlluminant::D50, 0.3457, 0.3585),
+            (StandardIlluminant::D65, 0.3127, 0.3290),
+            (StandardIlluminant::F2, 0.3721, 0.3751),
+            (StandardIlluminant::F7, 0.3129, 0.3292),
+            (StandardIlluminant::F11, 0.3805, 0.3769),
+        ]
+        .iter()
+        {
+            let (actual_x, actual_y) = chromaticity(illuminant);
+            assert!((actual_x - x).abs() < 0.001);
+            assert!((actual_y - y).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn a_is_100_at_560nm() {
+        assert!((StandardIlluminant::A.value(560.0) - 100.0).abs() < 0.000_000_001);
+    }
+}
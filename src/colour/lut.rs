@@ -0,0 +1,349 @@
+//! Colour lookup tables, read from files in the .cube format
+//!
+//! The format is Adobe's: keywords such as `LUT_1D_SIZE` or `LUT_3D_SIZE` and
+//! `DOMAIN_MIN`, then one line of red, green and blue for each entry, with `#` starting a
+//! comment. The `LUT_1D_INPUT_RANGE` and `LUT_3D_INPUT_RANGE` keywords written by DaVinci
+//! Resolve are also understood.
+
+use crate::error::VanrijnError;
+use crate::math::Vec3;
+
+use super::ColourRgbF;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+
+/// The contents of a .cube file
+struct CubeFile {
+    size_1d: Option<usize>,
+    size_3d: Option<usize>,
+    domain_min: Vec3,
+    domain_max: Vec3,
+    entries: Vec<Vec3>,
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn parse_numbers(values: &[&str]) -> Result<Vec<f64>, Error> {
+    values
+        .iter()
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| invalid_data(&format!("Bad number \"{}\" in LUT.", value)))
+        })
+        .collect()
+}
+
+fn parse_vec3(values: &[&str]) -> Result<Vec3, Error> {
+    match parse_numbers(values)?[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(invalid_data("Expected three values in LUT.")),
+    }
+}
+
+fn read_cube_file<R: Read>(reader: R) -> Result<CubeFile, Error> {
+    let mut result = CubeFile {
+        size_1d: None,
+        size_3d: None,
+        domain_min: Vec3::zeros(),
+        domain_max: Vec3::new(1.0, 1.0, 1.0),
+        entries: vec![],
+    };
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or("").trim();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.split_first() {
+            None => {}
+            Some((&"TITLE", _)) => {}
+            Some((&"LUT_1D_SIZE", [size])) => {
+                result.size_1d = Some(
+                    size.parse()
+                        .map_err(|_| invalid_data("Bad LUT_1D_SIZE in LUT."))?,
+                )
+            }
+            Some((&"LUT_3D_SIZE", [size])) => {
+                result.size_3d = Some(
+                    size.parse()
+                        .map_err(|_| invalid_data("Bad LUT_3D_SIZE in LUT."))?,
+                )
+            }
+            Some((&"DOMAIN_MIN", values)) => result.domain_min = parse_vec3(values)?,
+            Some((&"DOMAIN_MAX", values)) => result.domain_max = parse_vec3(values)?,
+            Some((&"LUT_1D_INPUT_RANGE", values)) | Some((&"LUT_3D_INPUT_RANGE", values)) => {
+                match parse_numbers(values)?[..] {
+                    [min, max] => {
+                        result.domain_min = Vec3::new(min, min, min);
+                        result.domain_max = Vec3::new(max, max, max);
+                    }
+                    _ => return Err(invalid_data("Expected two values for the input range.")),
+                }
+            }
+            Some((keyword, _)) if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                return Err(invalid_data(&format!(
+                    "Unsupported LUT keyword {}.",
+                    keyword
+                )))
+            }
+            Some(_) => result.entries.push(parse_vec3(&words)?),
+        }
+    }
+    if result.size_1d.is_some() && result.size_3d.is_some() {
+        return Err(invalid_data(
+            "LUTs with both 1D and 3D tables aren't supported.",
+        ));
+    }
+    Ok(result)
+}
+
+/// Where `value` is between `min` and `max`, as a position along `size` entries, and the
+/// index of the entry at or before it
+fn entry_position(value: f64, min: f64, max: f64, size: usize) -> (usize, f64) {
+    let position = ((value - min) / (max - min)).clamp(0.0, 1.0) * (size - 1) as f64;
+    let index = (position as usize).min(size - 2);
+    (index, position - index as f64)
+}
+
+/// A separate curve for each of the red, green and blue channels
+#[derive(Clone, Debug)]
+pub struct Lut1d {
+    domain_min: Vec3,
+    domain_max: Vec3,
+    entries: Vec<Vec3>,
+}
+
+impl Lut1d {
+    /// A LUT with `entries` spread evenly from zero to one
+    pub fn new(entries: Vec<Vec3>) -> Lut1d {
+        assert!(entries.len() >= 2);
+        Lut1d {
+            domain_min: Vec3::zeros(),
+            domain_max: Vec3::new(1.0, 1.0, 1.0),
+            entries,
+        }
+    }
+
+    pub fn read_cube(filename: &Path) -> Result<Lut1d, VanrijnError> {
+        File::open(filename)
+            .and_then(Lut1d::read_cube_from)
+            .map_err(VanrijnError::colour_data(filename))
+    }
+
+    pub fn read_cube_from<R: Read>(reader: R) -> Result<Lut1d, Error> {
+        let cube = read_cube_file(reader)?;
+        let size = cube
+            .size_1d
+            .ok_or_else(|| invalid_data("Not a 1D LUT; LUT_1D_SIZE is missing."))?;
+        if size < 2 || cube.entries.len() != size {
+            return Err(invalid_data("Wrong number of entries in LUT."));
+        }
+        Ok(Lut1d {
+            domain_min: cube.domain_min,
+            domain_max: cube.domain_max,
+            entries: cube.entries,
+        })
+    }
+
+    /// The curve for `channel`, of 0 for red, 1 for green and 2 for blue, at `value`
+    ///
+    /// Values outside the domain are clamped to it, and values between entries are
+    /// interpolated linearly.
+    pub fn apply(&self, channel: usize, value: f64) -> f64 {
+        let (index, ratio) = entry_position(
+            value,
+            self.domain_min.coords[channel],
+            self.domain_max.coords[channel],
+            self.entries.len(),
+        );
+        self.entries[index].coords[channel] * (1.0 - ratio)
+            + self.entries[index + 1].coords[channel] * ratio
+    }
+}
+
+/// A table that maps each colour to another, as used to give images a particular look
+#[derive(Debug)]
+pub struct Lut3d {
+    size: usize,
+    domain_min: Vec3,
+    domain_max: Vec3,
+    /// Red changes fastest, then green, then blue
+    entries: Vec<Vec3>,
+}
+
+impl Lut3d {
+    pub fn read_cube(filename: &Path) -> Result<Lut3d, VanrijnError> {
+        File::open(filename)
+            .and_then(Lut3d::read_cube_from)
+            .map_err(VanrijnError::colour_data(filename))
+    }
+
+    pub fn read_cube_from<R: Read>(reader: R) -> Result<Lut3d, Error> {
+        let cube = read_cube_file(reader)?;
+        let size = cube
+            .size_3d
+            .ok_or_else(|| invalid_data("Not a 3D LUT; LUT_3D_SIZE is missing."))?;
+        if size < 2 || cube.entries.len() != size * size * size {
+            return Err(invalid_data("Wrong number of entries in LUT."));
+        }
+        Ok(Lut3d {
+            size,
+            domain_min: cube.domain_min,
+            domain_max: cube.domain_max,
+            entries: cube.entries,
+        })
+    }
+
+    fn entry(&self, red: usize, green: usize, blue: usize) -> Vec3 {
+        self.entries[red + self.size * (green + self.size * blue)]
+    }
+
+    /// The colour that `colour` becomes
+    ///
+    /// Colours outside the domain are clamped to it, and colours between entries are
+    /// interpolated trilinearly.
+    pub fn apply(&self, colour: &ColourRgbF) -> ColourRgbF {
+        let [(red, red_ratio), (green, green_ratio), (blue, blue_ratio)] = [0, 1, 2].map(|i| {
+            entry_position(
+                colour.values.coords[i],
+                self.domain_min.coords[i],
+                self.domain_max.coords[i],
+                self.size,
+            )
+        });
+        let lerp = |a: Vec3, b: Vec3, ratio: f64| a * (1.0 - ratio) + b * ratio;
+        let along_red = |green, blue| {
+            lerp(
+                self.entry(red, green, blue),
+                self.entry(red + 1, green, blue),
+                red_ratio,
+            )
+        };
+        let along_green = |blue| {
+            lerp(
+                along_red(green, blue),
+                along_red(green + 1, blue),
+                green_ratio,
+            )
+        };
+        ColourRgbF::from_vec3(&lerp(along_green(blue), along_green(blue + 1), blue_ratio))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_1d_cube_file() {
+        let data = "TITLE \"Test\"\n\
+                    # A comment\n\
+                    LUT_1D_SIZE 3\n\
+                    DOMAIN_MIN 0 0 0\n\
+                    DOMAIN_MAX 2 2 1\n\
+                    \n\
+                    0 0 0\n\
+                    0.5 0.25 1 # Halfway\n\
+                    1 1 0\n";
+        let target = Lut1d::read_cube_from(data.as_bytes()).unwrap();
+        assert!(target.apply(0, 1.0) == 0.5);
+        assert!(target.apply(1, 1.5) == 0.625);
+        assert!(target.apply(2, 0.25) == 0.5);
+        assert!(target.apply(2, 0.75) == 0.5);
+    }
+
+    #[test]
+    fn clamps_to_domain() {
+        let target = Lut1d::new(vec![Vec3::new(0.1, 0.2, 0.3), Vec3::new(0.6, 0.7, 0.8)]);
+        assert!(target.apply(0, -1.0) == 0.1);
+        assert!(target.apply(2, 5.0) == 0.8);
+    }
+
+    #[test]
+    fn reads_resolve_input_range() {
+        let data = "LUT_1D_SIZE 2\nLUT_1D_INPUT_RANGE 0.0 4.0\n0 0 0\n1 1 1\n";
+        let target = Lut1d::read_cube_from(data.as_bytes()).unwrap();
+        assert!(target.apply(1, 1.0) == 0.25);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        for data in [
+            "0 0 0\n1 1 1\n",
+            "LUT_1D_SIZE 3\n0 0 0\n1 1 1\n",
+            "LUT_1D_SIZE 2\n0 0\n1 1 1\n",
+            "LUT_1D_SIZE 2\n0 0 zero\n1 1 1\n",
+            "LUT_1D_SIZE 2\nSHAPER 4\n0 0 0\n1 1 1\n",
+        ]
+        .iter()
+        {
+            assert!(Lut1d::read_cube_from(data.as_bytes()).is_err());
+        }
+    }
+
+    /// A 3D LUT of `size` entries along each side, where each colour becomes `f(colour)`
+    fn cube_3d(size: usize, f: impl Fn(Vec3) -> Vec3) -> String {
+        let mut result = format!("LUT_3D_SIZE {}\n", size);
+        let step = |index: usize| index as f64 / (size - 1) as f64;
+        for blue in 0..size {
+            for green in 0..size {
+                for red in 0..size {
+                    let entry = f(Vec3::new(step(red), step(green), step(blue)));
+                    result += &format!("{} {} {}\n", entry.x(), entry.y(), entry.z());
+                }
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn identity_3d_lut_leaves_colours_unchanged() {
+        let target = Lut3d::read_cube_from(cube_3d(5, |colour| colour).as_bytes()).unwrap();
+        for &colour in [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(0.3, 0.6, 0.9),
+            Vec3::new(0.125, 0.5, 0.0),
+        ]
+        .iter()
+        {
+            let result = target.apply(&ColourRgbF::from_vec3(&colour));
+            assert!((result.values - colour).norm() < 0.000000001);
+        }
+    }
+
+    #[test]
+    fn lut_3d_entries_are_ordered_with_red_fastest() {
+        let swap = |colour: Vec3| Vec3::new(colour.z(), colour.y(), colour.x());
+        let target = Lut3d::read_cube_from(cube_3d(2, swap).as_bytes()).unwrap();
+        let result = target.apply(&ColourRgbF::new(1.0, 0.0, 0.0));
+        assert!(result.values == Vec3::new(0.0, 0.0, 1.0));
+        let result = target.apply(&ColourRgbF::new(0.25, 0.5, 0.75));
+        assert!((result.values - Vec3::new(0.75, 0.5, 0.25)).norm() < 0.000000001);
+    }
+
+    #[test]
+    fn lut_3d_clamps_to_domain() {
+        let data = format!("DOMAIN_MAX 2 2 2\n{}", cube_3d(3, |colour| colour * 2.0));
+        let target = Lut3d::read_cube_from(data.as_bytes()).unwrap();
+        let result = target.apply(&ColourRgbF::new(-1.0, 1.0, 3.0));
+        assert!((result.values - Vec3::new(0.0, 1.0, 2.0)).norm() < 0.000000001);
+    }
+
+    #[test]
+    fn rejects_bad_3d_files() {
+        for data in [
+            "LUT_1D_SIZE 2\n0 0 0\n1 1 1\n".to_string(),
+            "LUT_3D_SIZE 2\n0 0 0\n1 1 1\n".to_string(),
+            format!("LUT_1D_SIZE 2\n{}", cube_3d(2, |colour| colour)),
+        ]
+        .iter()
+        {
+            assert!(Lut3d::read_cube_from(data.as_bytes()).is_err());
+        }
+        assert!(Lut1d::read_cube_from(cube_3d(2, |colour| colour).as_bytes()).is_err());
+    }
+}
@@ -4,11 +4,33 @@ pub use colour_rgb::{ColourRgbF, ColourRgbU8, NamedColour};
 pub mod photon;
 pub use photon::Photon;
 
+pub mod photon_packet;
+pub use photon_packet::PhotonPacket;
+
+pub mod polarization;
+pub use polarization::{MuellerMatrix, Stokes};
+
 pub mod colour_xyz;
 pub use colour_xyz::ColourXyz;
 
+pub mod colour_space;
+pub use colour_space::ColourSpace;
+
+pub mod cie_1931;
+
+pub mod daylight;
+
+pub mod illuminants;
+pub use illuminants::StandardIlluminant;
+
 pub mod spectrum;
 pub use spectrum::Spectrum;
 
+pub mod lut;
+pub use lut::{Lut1d, Lut3d};
+
+pub mod response_curve;
+pub use response_curve::ResponseCurve;
+
 pub const SHORTEST_VISIBLE_WAVELENGTH: f64 = 380.0;
 pub const LONGEST_VISIBLE_WAVELENGTH: f64 = 740.0;
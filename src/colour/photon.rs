@@ -1,6 +1,49 @@
+use crate::colour::cie_1931::colour_matching_functions;
 use crate::colour::{LONGEST_VISIBLE_WAVELENGTH, SHORTEST_VISIBLE_WAVELENGTH};
 
-use rand::random;
+use rand::{Rng, RngCore};
+
+use std::sync::OnceLock;
+
+/// Width, in nanometres, of the bands that luminous efficiency is averaged over for
+/// [Photon::luminous_wavelength()](Photon::luminous_wavelength)
+const LUMINOUS_BAND_WIDTH: f64 = 5.0;
+
+/// The share of [luminous wavelengths](Photon::luminous_wavelength) spread uniformly
+/// across the spectrum
+///
+/// Blue light contributes much more to Z than to Y, so sampling purely by luminous
+/// efficiency would leave the blues with few, very heavily weighted, samples. Keeping a
+/// quarter uniform means no wavelength's weight is more than four times its uniform weight.
+const UNIFORM_WAVELENGTH_SHARE: f64 = 0.25;
+
+/// The cumulative probability of [Photon::luminous_wavelength()](Photon::luminous_wavelength)
+/// choosing each band, starting from zero
+fn luminous_band_cdf() -> &'static [f64] {
+    static CDF: OnceLock<Vec<f64>> = OnceLock::new();
+    CDF.get_or_init(|| {
+        let band_count = ((LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH)
+            / LUMINOUS_BAND_WIDTH)
+            .round() as usize;
+        let efficiency = |band: usize| {
+            colour_matching_functions(
+                SHORTEST_VISIBLE_WAVELENGTH + band as f64 * LUMINOUS_BAND_WIDTH,
+            )
+            .y()
+        };
+        let bands: Vec<f64> = (0..band_count)
+            .map(|band| 0.5 * (efficiency(band) + efficiency(band + 1)))
+            .collect();
+        let total: f64 = bands.iter().sum();
+        let mut cdf = vec![0.0];
+        for band in bands {
+            let probability = (1.0 - UNIFORM_WAVELENGTH_SHARE) * band / total
+                + UNIFORM_WAVELENGTH_SHARE / band_count as f64;
+            cdf.push(cdf.last().unwrap() + probability);
+        }
+        cdf
+    })
+}
 
 /// A quantum of light with a given wavelength and intensity
 #[derive(Clone, Default, Debug)]
@@ -15,10 +58,10 @@ pub struct Photon {
 }
 
 impl Photon {
-    pub fn random_wavelength() -> Photon {
+    pub fn random_wavelength(rng: &mut dyn RngCore) -> Photon {
         Photon {
             wavelength: SHORTEST_VISIBLE_WAVELENGTH
-                + (LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH) * random::<f64>(),
+                + (LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH) * rng.gen::<f64>(),
             intensity: 0.0,
         }
     }
@@ -27,6 +70,39 @@ impl Photon {
         LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH
     }
 
+    /// A random visible wavelength, chosen mostly in proportion to the luminous efficiency
+    /// function, CIE 1931 ȳ
+    ///
+    /// More of the samples go to the wavelengths the eye is most sensitive to, which
+    /// reduces the perceived noise in colour compared with
+    /// [random_wavelength()](Photon::random_wavelength).
+    pub fn luminous_wavelength(rng: &mut dyn RngCore) -> Photon {
+        let cdf = luminous_band_cdf();
+        let u = rng.gen::<f64>() * cdf[cdf.len() - 1];
+        let band = (cdf.partition_point(|&p| p <= u) - 1).min(cdf.len() - 2);
+        let ratio = (u - cdf[band]) / (cdf[band + 1] - cdf[band]);
+        Photon {
+            wavelength: (SHORTEST_VISIBLE_WAVELENGTH + (band as f64 + ratio) * LUMINOUS_BAND_WIDTH)
+                .min(LONGEST_VISIBLE_WAVELENGTH),
+            intensity: 0.0,
+        }
+    }
+
+    /// The probability density, per nanometre, of
+    /// [luminous_wavelength()](Photon::luminous_wavelength) choosing `wavelength`
+    ///
+    /// Unlike [random_wavelength_pdf()](Photon::random_wavelength_pdf), this is the
+    /// density itself, so intensities are divided by it.
+    pub fn luminous_wavelength_pdf(wavelength: f64) -> f64 {
+        let cdf = luminous_band_cdf();
+        let position = (wavelength - SHORTEST_VISIBLE_WAVELENGTH) / LUMINOUS_BAND_WIDTH;
+        if !(0.0..=(cdf.len() - 1) as f64).contains(&position) {
+            return 0.0;
+        }
+        let band = (position as usize).min(cdf.len() - 2);
+        (cdf[band + 1] - cdf[band]) / (cdf[cdf.len() - 1] * LUMINOUS_BAND_WIDTH)
+    }
+
     pub fn scale_intensity(&self, scale_factor: f64) -> Photon {
         Photon {
             wavelength: self.wavelength,
@@ -41,3 +117,57 @@ impl Photon {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn luminous_wavelength_pdf_integrates_to_one() {
+        let steps = 3600;
+        let step = (LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH) / steps as f64;
+        let total: f64 = (0..steps)
+            .map(|i| {
+                Photon::luminous_wavelength_pdf(
+                    SHORTEST_VISIBLE_WAVELENGTH + (i as f64 + 0.5) * step,
+                ) * step
+            })
+            .sum();
+        assert!((total - 1.0).abs() < 0.000001);
+        assert!(Photon::luminous_wavelength_pdf(SHORTEST_VISIBLE_WAVELENGTH - 1.0) == 0.0);
+        assert!(Photon::luminous_wavelength_pdf(LONGEST_VISIBLE_WAVELENGTH + 1.0) == 0.0);
+    }
+
+    #[test]
+    fn luminous_wavelengths_favour_green() {
+        let range = LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH;
+        assert!(Photon::luminous_wavelength_pdf(555.0) > 2.0 / range);
+        // Even the dimmest wavelengths keep some of the uniform density
+        assert!(Photon::luminous_wavelength_pdf(SHORTEST_VISIBLE_WAVELENGTH) >= 0.25 / range);
+        assert!(Photon::luminous_wavelength_pdf(LONGEST_VISIBLE_WAVELENGTH) >= 0.25 / range);
+    }
+
+    #[test]
+    fn luminous_wavelengths_follow_their_pdf() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let samples = 100000;
+        let (low, high) = (540.0, 570.0);
+        let mut count = 0;
+        for _ in 0..samples {
+            let wavelength = Photon::luminous_wavelength(&mut rng).wavelength;
+            assert!(
+                (SHORTEST_VISIBLE_WAVELENGTH..=LONGEST_VISIBLE_WAVELENGTH).contains(&wavelength)
+            );
+            if (low..high).contains(&wavelength) {
+                count += 1;
+            }
+        }
+        let expected: f64 = (0..30)
+            .map(|i| Photon::luminous_wavelength_pdf(low + i as f64 + 0.5))
+            .sum();
+        assert!((count as f64 / samples as f64 - expected).abs() < 0.01);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::colour::{Photon, LONGEST_VISIBLE_WAVELENGTH, SHORTEST_VISIBLE_WAVELENGTH};
+
+use rand::RngCore;
+
+/// Number of wavelengths traced together along each path
+pub const PACKET_SIZE: usize = 4;
+
+/// Several [Photons](Photon) of different wavelengths that follow the same path
+///
+/// This implements hero wavelength sampling. The first photon, the hero, is chosen at
+/// random and the others are spaced evenly across the visible spectrum from it. Directions
+/// are chosen using the hero wavelength, but because most materials scatter all
+/// wavelengths the same way, the path carries useful light at every wavelength. This
+/// greatly reduces colour noise compared with tracing a single wavelength per path.
+///
+/// Where the direction of a path does depend on wavelength, such as at a
+/// [dispersive](crate::materials::Material::is_dispersive) material, the packet must be
+/// collapsed to just the hero with [hero_only()](PhotonPacket::hero_only).
+#[derive(Clone, Debug)]
+pub struct PhotonPacket {
+    photons: [Photon; PACKET_SIZE],
+    len: usize,
+}
+
+impl PhotonPacket {
+    /// A packet with a random hero wavelength, and zero intensity
+    ///
+    /// The hero is chosen by [Photon::luminous_wavelength()](Photon::luminous_wavelength).
+    /// The light found at each wavelength in the packet should be divided by
+    /// [wavelength_pdf()](PhotonPacket::wavelength_pdf).
+    pub fn random_wavelengths(rng: &mut dyn RngCore) -> PhotonPacket {
+        let hero = Photon::luminous_wavelength(rng);
+        PhotonPacket {
+            photons: std::array::from_fn(|i| Photon {
+                wavelength: rotate_wavelength(hero.wavelength, i),
+                intensity: 0.0,
+            }),
+            len: PACKET_SIZE,
+        }
+    }
+
+    /// The probability density, per nanometre, of each wavelength in a packet from
+    /// [random_wavelengths()](PhotonPacket::random_wavelengths)
+    ///
+    /// Any photon in the packet could have been the hero, so this is the average of the
+    /// hero's density over the wavelengths of the packet `wavelength` would be in. Dividing
+    /// by it weights the photons by the balance heuristic, so a wavelength the hero rarely
+    /// lands on is still covered when it's one of the others.
+    pub fn wavelength_pdf(wavelength: f64) -> f64 {
+        (0..PACKET_SIZE)
+            .map(|i| Photon::luminous_wavelength_pdf(rotate_wavelength(wavelength, i)))
+            .sum::<f64>()
+            / PACKET_SIZE as f64
+    }
+
+    /// A packet containing only `photon`
+    pub fn from_photon(photon: &Photon) -> PhotonPacket {
+        PhotonPacket {
+            photons: std::array::from_fn(|_| photon.clone()),
+            len: 1,
+        }
+    }
+
+    /// The photon used to choose directions
+    pub fn hero(&self) -> &Photon {
+        &self.photons[0]
+    }
+
+    pub fn photons(&self) -> &[Photon] {
+        &self.photons[..self.len]
+    }
+
+    pub fn is_single_wavelength(&self) -> bool {
+        self.len == 1
+    }
+
+    /// Apply `f` to each photon in the packet
+    pub fn map<F: FnMut(&Photon) -> Photon>(&self, mut f: F) -> PhotonPacket {
+        let len = self.len;
+        PhotonPacket {
+            photons: std::array::from_fn(|i| {
+                if i < len {
+                    f(&self.photons[i])
+                } else {
+                    self.photons[i].clone()
+                }
+            }),
+            len,
+        }
+    }
+
+    /// Add the intensities of corresponding photons in two packets of the same wavelengths
+    pub fn add(&self, other: &PhotonPacket) -> PhotonPacket {
+        assert!(self.len == other.len);
+        let mut result = self.clone();
+        for (photon, other) in result.photons.iter_mut().zip(other.photons.iter()) {
+            photon.intensity += other.intensity;
+        }
+        result
+    }
+
+    pub fn scale_intensity(&self, scale_factor: f64) -> PhotonPacket {
+        self.map(|photon| photon.scale_intensity(scale_factor))
+    }
+
+    pub fn set_intensity(&self, intensity: f64) -> PhotonPacket {
+        self.map(|photon| photon.set_intensity(intensity))
+    }
+
+    /// A packet containing only the hero photon
+    pub fn hero_only(&self) -> PhotonPacket {
+        PhotonPacket::from_photon(self.hero())
+    }
+
+    /// Spread the result of tracing [hero_only()](PhotonPacket::hero_only) back over the
+    /// full packet
+    ///
+    /// The other wavelengths get no light, so the hero carries the light for the whole
+    /// packet. Its intensity is scaled by [hero_weight()](PhotonPacket::hero_weight) to
+    /// keep the average over the packet unbiased.
+    pub fn expand_hero(&self, hero: &PhotonPacket) -> PhotonPacket {
+        let mut result = self.set_intensity(0.0);
+        result.photons[0] = hero.hero().scale_intensity(self.hero_weight());
+        result
+    }
+
+    /// How much the hero's intensity is scaled by when it carries the light for the whole
+    /// packet
+    ///
+    /// Once the others are dropped, the hero is just a sample of
+    /// [Photon::luminous_wavelength()](Photon::luminous_wavelength), so this undoes the
+    /// [wavelength_pdf()](PhotonPacket::wavelength_pdf) it will be divided by and the
+    /// averaging over the packet.
+    pub fn hero_weight(&self) -> f64 {
+        if self.is_single_wavelength() {
+            return 1.0;
+        }
+        let wavelength = self.hero().wavelength;
+        self.len as f64 * PhotonPacket::wavelength_pdf(wavelength)
+            / Photon::luminous_wavelength_pdf(wavelength)
+    }
+}
+
+/// The `index`th wavelength of the evenly spaced packet that `wavelength` is the hero of
+fn rotate_wavelength(wavelength: f64, index: usize) -> f64 {
+    let range = LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH;
+    let offset = wavelength - SHORTEST_VISIBLE_WAVELENGTH;
+    SHORTEST_VISIBLE_WAVELENGTH + (offset + range * index as f64 / PACKET_SIZE as f64) % range
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn random_wavelengths_are_evenly_spaced_and_visible() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let range = LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH;
+        for _ in 0..100 {
+            let target = PhotonPacket::random_wavelengths(&mut rng);
+            assert!(target.photons().len() == PACKET_SIZE);
+            let mut wavelengths: Vec<f64> = target.photons().iter().map(|p| p.wavelength).collect();
+            assert!(wavelengths
+                .iter()
+                .all(|&w| (SHORTEST_VISIBLE_WAVELENGTH..LONGEST_VISIBLE_WAVELENGTH).contains(&w)));
+            wavelengths.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            for pair in wavelengths.windows(2) {
+                assert!((pair[1] - pair[0] - range / PACKET_SIZE as f64).abs() < 0.000001);
+            }
+        }
+    }
+
+    #[test]
+    fn map_applies_to_every_photon() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let target = PhotonPacket::random_wavelengths(&mut rng)
+            .map(|photon| photon.set_intensity(photon.wavelength * 2.0));
+        assert!(target
+            .photons()
+            .iter()
+            .all(|photon| photon.intensity == photon.wavelength * 2.0));
+    }
+
+    /// The average over the packet of each photon's intensity divided by its density, as
+    /// the renderer weights them
+    fn weighted_mean(packet: &PhotonPacket) -> f64 {
+        packet
+            .photons()
+            .iter()
+            .map(|p| p.intensity / PhotonPacket::wavelength_pdf(p.wavelength))
+            .sum::<f64>()
+            / packet.photons().len() as f64
+    }
+
+    #[test]
+    fn expanded_hero_is_weighted_as_single_wavelength_sample() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let packet = PhotonPacket::random_wavelengths(&mut rng);
+            let hero = packet.hero_only().set_intensity(1.5);
+            assert!(hero.is_single_wavelength());
+            let target = packet.expand_hero(&hero);
+            assert!(target.hero().wavelength == packet.hero().wavelength);
+            let expected = 1.5 / Photon::luminous_wavelength_pdf(packet.hero().wavelength);
+            assert!((weighted_mean(&target) - expected).abs() < 0.000001 * expected);
+        }
+    }
+
+    #[test]
+    fn weighted_packets_estimate_spectral_integral() {
+        // Their integrals over the visible spectrum are 360 and 2 * 360 * 560
+        let spectra: [fn(f64) -> f64; 2] = [|_| 1.0, |wavelength| 2.0 * wavelength];
+        for (spectrum, expected) in spectra.iter().zip([360.0, 403200.0]) {
+            let mut rng = StdRng::seed_from_u64(0);
+            let samples = 20000;
+            let total: f64 = (0..samples)
+                .map(|_| {
+                    weighted_mean(
+                        &PhotonPacket::random_wavelengths(&mut rng)
+                            .map(|photon| photon.set_intensity(spectrum(photon.wavelength))),
+                    )
+                })
+                .sum();
+            assert!((total / samples as f64 - expected).abs() < 0.01 * expected);
+        }
+    }
+}
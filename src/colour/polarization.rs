@@ -0,0 +1,329 @@
+//! Polarized light, for the [PolarizedIntegrator](crate::integrators::PolarizedIntegrator)
+//!
+//! The polarization of light is described by its Stokes vector, (I, Q, U, V). I is the
+//! intensity; Q and U describe the linear polarization, measured against a reference
+//! direction perpendicular to the direction the light travels; V is the circular
+//! polarization. Surfaces change the Stokes vector by multiplying it by a Mueller matrix.
+//!
+//! A Stokes vector means nothing without its reference direction, so one measured against
+//! one direction has to be [rotated](MuellerMatrix::rotate_reference) to the reference of a
+//! Mueller matrix before the matrix can be applied. Angles are measured from the reference
+//! direction, `x`, towards `direction.cross(x)`, where `direction` is the way the light
+//! travels.
+
+use crate::math::{Complex, Mat4, Vec3, Vec4};
+
+use std::ops::Mul;
+
+/// The polarization state and intensity of light
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stokes {
+    pub values: Vec4,
+}
+
+impl Stokes {
+    pub fn new(i: f64, q: f64, u: f64, v: f64) -> Stokes {
+        Stokes {
+            values: Vec4::new(i, q, u, v),
+        }
+    }
+
+    pub fn unpolarized(intensity: f64) -> Stokes {
+        Stokes::new(intensity, 0.0, 0.0, 0.0)
+    }
+
+    /// Light linearly polarized at `angle` radians from the reference direction
+    pub fn linear(intensity: f64, angle: f64) -> Stokes {
+        Stokes::new(
+            intensity,
+            intensity * (2.0 * angle).cos(),
+            intensity * (2.0 * angle).sin(),
+            0.0,
+        )
+    }
+
+    pub fn intensity(&self) -> f64 {
+        self.values.x()
+    }
+
+    /// The fraction of the light that's polarized, from zero to one
+    pub fn degree_of_polarization(&self) -> f64 {
+        if self.intensity() <= 0.0 {
+            return 0.0;
+        }
+        let [_, q, u, v] = self.values.coords;
+        (q * q + u * u + v * v).sqrt() / self.intensity()
+    }
+}
+
+/// How a surface, or a filter, changes the [Stokes vector](Stokes) of light
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MuellerMatrix {
+    pub values: Mat4,
+}
+
+impl MuellerMatrix {
+    pub fn identity() -> MuellerMatrix {
+        MuellerMatrix {
+            values: Mat4::identity(),
+        }
+    }
+
+    /// An ideal depolarizer, which passes `transmittance` of the light and leaves it
+    /// unpolarized
+    pub fn depolarizer(transmittance: f64) -> MuellerMatrix {
+        MuellerMatrix {
+            values: Mat4::new(
+                transmittance,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+            ),
+        }
+    }
+
+    /// An ideal linear polarizer, whose transmission axis is at `angle` radians from the
+    /// reference direction
+    pub fn linear_polarizer(angle: f64) -> MuellerMatrix {
+        let (c, s) = ((2.0 * angle).cos(), (2.0 * angle).sin());
+        MuellerMatrix {
+            values: Mat4::new(
+                0.5,
+                0.5 * c,
+                0.5 * s,
+                0.0,
+                0.5 * c,
+                0.5 * c * c,
+                0.5 * c * s,
+                0.0,
+                0.5 * s,
+                0.5 * c * s,
+                0.5 * s * s,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+            ),
+        }
+    }
+
+    /// Converts the Stokes vector of light travelling along `direction` from being measured
+    /// against `from` to being measured against `to`
+    ///
+    /// Both should be perpendicular to `direction`.
+    pub fn rotate_reference(from: &Vec3, to: &Vec3, direction: &Vec3) -> MuellerMatrix {
+        let angle = from.cross(to).dot(direction).atan2(from.dot(to));
+        let (c, s) = ((2.0 * angle).cos(), (2.0 * angle).sin());
+        MuellerMatrix {
+            values: Mat4::new(
+                1.0, 0.0, 0.0, 0.0, 0.0, c, s, 0.0, 0.0, -s, c, 0.0, 0.0, 0.0, 0.0, 1.0,
+            ),
+        }
+    }
+
+    /// Reflection with the Fresnel amplitude coefficients `r_s` and `r_p`, from
+    /// [fresnel_amplitudes()](fresnel_amplitudes)
+    ///
+    /// The incident and reflected light are both measured against the s direction, normal
+    /// to the plane of incidence.
+    pub fn fresnel_reflection(r_s: Complex, r_p: Complex) -> MuellerMatrix {
+        let (reflectance_s, reflectance_p) = (r_s.norm_squared(), r_p.norm_squared());
+        let cross = r_s * r_p.conjugate();
+        let (a, b) = (
+            0.5 * (reflectance_s + reflectance_p),
+            0.5 * (reflectance_s - reflectance_p),
+        );
+        MuellerMatrix {
+            values: Mat4::new(
+                a, b, 0.0, 0.0, b, a, 0.0, 0.0, 0.0, 0.0, cross.re, cross.im, 0.0, 0.0, -cross.im,
+                cross.re,
+            ),
+        }
+    }
+
+    /// Transmission through a boundary between dielectrics, which passes
+    /// `transmittance_s` of the s-polarized light and `transmittance_p` of the p-polarized
+    ///
+    /// As for [fresnel_reflection()](MuellerMatrix::fresnel_reflection), both sides are
+    /// measured against the s direction.
+    pub fn fresnel_transmission(transmittance_s: f64, transmittance_p: f64) -> MuellerMatrix {
+        let (a, b) = (
+            0.5 * (transmittance_s + transmittance_p),
+            0.5 * (transmittance_s - transmittance_p),
+        );
+        let c = (transmittance_s * transmittance_p).max(0.0).sqrt();
+        MuellerMatrix {
+            values: Mat4::new(
+                a, b, 0.0, 0.0, b, a, 0.0, 0.0, 0.0, 0.0, c, 0.0, 0.0, 0.0, 0.0, c,
+            ),
+        }
+    }
+}
+
+impl Mul<MuellerMatrix> for MuellerMatrix {
+    type Output = MuellerMatrix;
+
+    fn mul(self, rhs: MuellerMatrix) -> MuellerMatrix {
+        MuellerMatrix {
+            values: self.values * rhs.values,
+        }
+    }
+}
+
+impl Mul<Stokes> for MuellerMatrix {
+    type Output = Stokes;
+
+    fn mul(self, rhs: Stokes) -> Stokes {
+        Stokes {
+            values: self.values * rhs.values,
+        }
+    }
+}
+
+impl Mul<f64> for MuellerMatrix {
+    type Output = MuellerMatrix;
+
+    fn mul(self, rhs: f64) -> MuellerMatrix {
+        MuellerMatrix {
+            values: self.values * rhs,
+        }
+    }
+}
+
+/// The Fresnel amplitude reflection coefficients, `(r_s, r_p)`, for light arriving at an
+/// angle with cosine `cos_theta` to the normal, at a boundary where the index of
+/// refraction changes by the factor `eta`
+///
+/// `eta` is complex for conductors, whose imaginary part is the extinction coefficient.
+/// Beyond the critical angle of a dielectric, the coefficients have a magnitude of one and
+/// only their phases differ.
+pub fn fresnel_amplitudes(cos_theta: f64, eta: Complex) -> (Complex, Complex) {
+    let cos_theta = cos_theta.clamp(0.0, 1.0);
+    let sin2_theta = 1.0 - cos_theta * cos_theta;
+    let cos_i = Complex::real(cos_theta);
+    let cos_t = (Complex::real(1.0) - Complex::real(sin2_theta) / (eta * eta)).sqrt();
+    (
+        (cos_i - eta * cos_t) / (cos_i + eta * cos_t),
+        (eta * cos_i - cos_t) / (eta * cos_i + cos_t),
+    )
+}
+
+/// The s direction for light leaving a surface towards `w`, in BSDF space
+///
+/// This is normal to the plane of incidence, which contains `w` and the surface normal.
+/// At normal incidence any direction will do, so it's the tangent.
+pub fn s_direction(w: &Vec3) -> Vec3 {
+    let s = Vec3::unit_z().cross(w);
+    if s.norm_squared() < 0.0000000001 {
+        Vec3::unit_x()
+    } else {
+        s.normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::f64::consts::PI;
+
+    fn is_close(a: &Stokes, b: &Stokes) -> bool {
+        (0..4).all(|i| (a.values.coords[i] - b.values.coords[i]).abs() < 0.000000001)
+    }
+
+    #[test]
+    fn polarizer_obeys_malus_law() {
+        let light = Stokes::linear(2.0, 0.3);
+        for i in 0..8 {
+            let angle = i as f64 * 0.4;
+            let passed = MuellerMatrix::linear_polarizer(angle) * light;
+            assert!((passed.intensity() - 2.0 * (angle - 0.3).cos().powi(2)).abs() < 0.000000001);
+        }
+        let unpolarized = MuellerMatrix::linear_polarizer(1.0) * Stokes::unpolarized(2.0);
+        assert!((unpolarized.intensity() - 1.0).abs() < 0.000000001);
+        assert!((unpolarized.degree_of_polarization() - 1.0).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn rotating_reference_changes_measured_angle() {
+        let direction = Vec3::unit_z();
+        let light = Stokes::linear(1.0, 0.0);
+        let to_y = MuellerMatrix::rotate_reference(&Vec3::unit_x(), &Vec3::unit_y(), &direction);
+        // Polarized along x, which is a quarter turn back from y
+        assert!(is_close(&(to_y * light), &Stokes::linear(1.0, -0.5 * PI)));
+        let diagonal = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let to_diagonal = MuellerMatrix::rotate_reference(&Vec3::unit_x(), &diagonal, &direction);
+        assert!(is_close(
+            &(to_diagonal * light),
+            &Stokes::linear(1.0, -0.25 * PI)
+        ));
+        let back = MuellerMatrix::rotate_reference(&diagonal, &Vec3::unit_x(), &direction);
+        assert!(is_close(&(back * to_diagonal * light), &light));
+    }
+
+    #[test]
+    fn reflection_at_brewster_angle_is_s_polarized() {
+        let eta: f64 = 1.5;
+        let cos_theta = eta.atan().cos();
+        let (r_s, r_p) = fresnel_amplitudes(cos_theta, Complex::real(eta));
+        assert!(r_p.norm() < 0.000000001);
+        let reflected = MuellerMatrix::fresnel_reflection(r_s, r_p) * Stokes::unpolarized(1.0);
+        assert!((reflected.degree_of_polarization() - 1.0).abs() < 0.000000001);
+        // Polarized along the reference direction, which is s
+        assert!((reflected.values.y() - reflected.intensity()).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn normal_reflectance_matches_fresnel() {
+        let (r_s, r_p) = fresnel_amplitudes(1.0, Complex::real(1.5));
+        let reflected = MuellerMatrix::fresnel_reflection(r_s, r_p) * Stokes::unpolarized(1.0);
+        assert!((reflected.intensity() - 0.04).abs() < 0.000000001);
+        assert!(reflected.degree_of_polarization() < 0.000000001);
+    }
+
+    #[test]
+    fn total_internal_reflection_shifts_phase_without_loss() {
+        let (r_s, r_p) = fresnel_amplitudes(0.3, Complex::real(1.0 / 1.5));
+        assert!((r_s.norm() - 1.0).abs() < 0.000000001);
+        assert!((r_p.norm() - 1.0).abs() < 0.000000001);
+        let light = Stokes::linear(1.0, 0.25 * PI);
+        let reflected = MuellerMatrix::fresnel_reflection(r_s, r_p) * light;
+        assert!((reflected.intensity() - 1.0).abs() < 0.000000001);
+        assert!((reflected.degree_of_polarization() - 1.0).abs() < 0.000000001);
+        // Some of the linear polarization has become circular
+        assert!(reflected.values.w().abs() > 0.1);
+    }
+
+    #[test]
+    fn transmission_conserves_energy_with_reflection() {
+        let (r_s, r_p) = fresnel_amplitudes(0.6, Complex::real(1.5));
+        let (t_s, t_p) = (1.0 - r_s.norm_squared(), 1.0 - r_p.norm_squared());
+        for light in &[Stokes::linear(1.0, 0.0), Stokes::linear(1.0, 0.5 * PI)] {
+            let reflected = MuellerMatrix::fresnel_reflection(r_s, r_p) * *light;
+            let transmitted = MuellerMatrix::fresnel_transmission(t_s, t_p) * *light;
+            assert!((reflected.intensity() + transmitted.intensity() - 1.0).abs() < 0.000000001);
+        }
+    }
+
+    #[test]
+    fn s_direction_is_normal_to_plane_of_incidence() {
+        let w = Vec3::new(0.3, -0.4, 0.5);
+        let s = s_direction(&w);
+        assert!(s.dot(&w).abs() < 0.000000001 && s.z().abs() < 0.000000001);
+        assert!((s.norm() - 1.0).abs() < 0.000000001);
+        assert!(s_direction(&Vec3::unit_z()) == Vec3::unit_x());
+    }
+}
@@ -0,0 +1,101 @@
+use super::{ColourRgbF, ColourSpace, Lut1d};
+
+/// How a camera turns the light reaching each pixel into the values stored in the image
+///
+/// The curve is applied to each channel of the exposed, linear colour in the output colour
+/// space, and gives the encoded value that's written, so it takes the place of the colour
+/// space's transfer function.
+#[derive(Clone, Debug, Default)]
+pub enum ResponseCurve {
+    /// The transfer function of the output colour space, so the image records the light
+    /// exactly, up to the brightest value it can store
+    #[default]
+    TransferFunction,
+
+    /// The sRGB transfer function, whatever the output colour space
+    Srgb,
+
+    /// Krzysztof Narkowicz's fit to the ACES filmic tone curve, followed by the transfer
+    /// function of the output colour space
+    ///
+    /// Highlights roll off smoothly towards white instead of being clipped, and shadows
+    /// get a little more contrast, much as with film.
+    Aces,
+
+    /// A measured or designed response, usually read from a
+    /// [.cube file](Lut1d::read_cube)
+    Lut(Lut1d),
+}
+
+impl ResponseCurve {
+    /// The value stored for `linear`, in channel `channel` of an image in `colour_space`
+    pub fn apply(&self, colour_space: ColourSpace, channel: usize, linear: f64) -> f64 {
+        match self {
+            ResponseCurve::TransferFunction => colour_space.encode(linear),
+            ResponseCurve::Srgb => ColourSpace::Srgb.encode(linear),
+            ResponseCurve::Aces => {
+                // The fit matches ACES for an exposure 0.6 times as bright
+                let x = linear.max(0.0) * 0.6;
+                colour_space.encode(
+                    ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0),
+                )
+            }
+            ResponseCurve::Lut(lut) => lut.apply(channel, linear),
+        }
+    }
+
+    /// Apply the curve to each channel of `linear`; see [apply()](ResponseCurve::apply)
+    pub fn apply_rgb(&self, colour_space: ColourSpace, linear: &ColourRgbF) -> ColourRgbF {
+        ColourRgbF::new(
+            self.apply(colour_space, 0, linear.red()),
+            self.apply(colour_space, 1, linear.green()),
+            self.apply(colour_space, 2, linear.blue()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::math::Vec3;
+
+    #[test]
+    fn default_curve_is_transfer_function() {
+        for &value in [0.0, 0.002, 0.18, 0.5, 1.0].iter() {
+            assert!(
+                ResponseCurve::default().apply(ColourSpace::Rec709, 1, value)
+                    == ColourSpace::Rec709.encode(value)
+            );
+            assert!(
+                ResponseCurve::Srgb.apply(ColourSpace::AcesCg, 1, value)
+                    == ColourSpace::Srgb.encode(value)
+            );
+        }
+    }
+
+    #[test]
+    fn aces_curve_rolls_off_highlights() {
+        let target = |value| ResponseCurve::Aces.apply(ColourSpace::AcesCg, 0, value);
+        assert!(target(0.0) == 0.0);
+        // The fit reaches white at about 12
+        let mut previous = 0.0;
+        for step in 1..24 {
+            let value = target(step as f64 * 0.5);
+            assert!(value > previous && value < 1.0);
+            previous = value;
+        }
+        // Bright values that would clip are still told apart
+        assert!(target(4.0) < target(8.0));
+    }
+
+    #[test]
+    fn lut_curves_are_per_channel() {
+        let target = ResponseCurve::Lut(Lut1d::new(vec![
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.5),
+        ]));
+        let colour = target.apply_rgb(ColourSpace::Srgb, &ColourRgbF::new(0.5, 0.25, 1.0));
+        assert!(colour.values == Vec3::new(0.5, 0.75, 0.5));
+    }
+}
@@ -1,6 +1,15 @@
-use crate::colour::{ColourRgbF, Photon, LONGEST_VISIBLE_WAVELENGTH, SHORTEST_VISIBLE_WAVELENGTH};
+use crate::colour::daylight::d65;
+use crate::colour::{
+    ColourRgbF, ColourXyz, Photon, StandardIlluminant, LONGEST_VISIBLE_WAVELENGTH,
+    SHORTEST_VISIBLE_WAVELENGTH,
+};
 
-use itertools::izip;
+use crate::error::VanrijnError;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+use std::sync::OnceLock;
 
 #[derive(Debug)]
 pub struct Spectrum {
@@ -26,27 +35,107 @@ impl Spectrum {
         }
     }
 
+    /// The index of refraction of a transparent material with the Sellmeier equation
+    ///
+    /// Each of `coefficients` is a term `(B, C)` of
+    /// `n² = 1 + Σ B λ² / (λ² - C)`, with the wavelength λ in micrometres, as
+    /// manufacturers and handbooks usually give them.
+    pub fn sellmeier(coefficients: &[(f64, f64)]) -> Spectrum {
+        Spectrum::from_function(73, |wavelength| {
+            let wavelength_squared = (wavelength / 1000.0).powi(2);
+            coefficients
+                .iter()
+                .fold(1.0, |sum, &(b, c)| {
+                    sum + b * wavelength_squared / (wavelength_squared - c)
+                })
+                .sqrt()
+        })
+    }
+
+    /// Schott N-BK7, the most common optical glass, from Schott's data sheet
+    pub fn bk7_index_of_refraction() -> Spectrum {
+        Spectrum::sellmeier(&[
+            (1.03961212, 0.00600069867),
+            (0.231792344, 0.0200179144),
+            (1.01046945, 103.560653),
+        ])
+    }
+
+    /// Fused silica, from I. H. Malitson, "Interspecimen Comparison of the Refractive
+    /// Index of Fused Silica", 1965
+    pub fn fused_silica_index_of_refraction() -> Spectrum {
+        Spectrum::sellmeier(&[
+            (0.6961663, 0.004679148),
+            (0.4079426, 0.01351206),
+            (0.8974794, 97.934),
+        ])
+    }
+
+    /// Liquid water at 20°C, from M. Daimon and A. Masumura, "Measurement of the
+    /// refractive index of distilled water from the near-infrared region to the ultraviolet
+    /// region", 2007
+    pub fn water_index_of_refraction() -> Spectrum {
+        Spectrum::sellmeier(&[
+            (0.5684027565, 0.005101829712),
+            (0.1726177391, 0.01821153936),
+            (0.02086189578, 0.02620722293),
+            (0.1130748688, 10.69792721),
+        ])
+    }
+
+    /// Diamond, from F. Peter, "Über Brechungsindizes und Absorptionskonstanten des
+    /// Diamanten zwischen 644 und 226 mμ", 1923
     pub fn diamond_index_of_refraction() -> Spectrum {
+        Spectrum::sellmeier(&[(0.3306, 0.030625), (4.3356, 0.011236)])
+    }
+
+    /// A measured optical constant of a metal, sampled every 50nm from 350nm to 750nm
+    ///
+    /// The constants of gold, silver and copper are from P. B. Johnson and R. W. Christy,
+    /// "Optical Constants of the Noble Metals", 1972, and those of aluminium from A. D.
+    /// Rakić, "Algorithm for the Determination of Intrinsic Optical Constants of Metal
+    /// Films", 1995, rounded to a few digits. The 350nm samples repeat the 400nm ones.
+    fn measured_metal(samples: Vec<f64>) -> Spectrum {
+        assert!(samples.len() == 9);
         Spectrum {
-            shortest_wavelength: 326.27,
-            longest_wavelength: 774.9,
-            samples: vec![
-                2.505813241,
-                2.487866556,
-                2.473323675,
-                2.464986815,
-                2.455051934,
-                2.441251728,
-                2.431478974,
-                2.427076431,
-                2.420857286,
-                2.411429037,
-                2.406543164,
-                2.406202402,
-            ],
+            shortest_wavelength: 350.0,
+            longest_wavelength: 750.0,
+            samples,
         }
     }
 
+    pub fn gold_index_of_refraction() -> Spectrum {
+        Spectrum::measured_metal(vec![1.658, 1.658, 1.5, 0.97, 0.43, 0.25, 0.17, 0.16, 0.16])
+    }
+
+    pub fn gold_extinction_coefficient() -> Spectrum {
+        Spectrum::measured_metal(vec![1.956, 1.956, 1.88, 1.87, 2.45, 2.98, 3.46, 3.95, 4.4])
+    }
+
+    pub fn silver_index_of_refraction() -> Spectrum {
+        Spectrum::measured_metal(vec![0.05, 0.05, 0.04, 0.05, 0.06, 0.06, 0.05, 0.04, 0.04])
+    }
+
+    pub fn silver_extinction_coefficient() -> Spectrum {
+        Spectrum::measured_metal(vec![2.1, 2.1, 2.65, 3.13, 3.59, 4.01, 4.48, 4.84, 5.2])
+    }
+
+    pub fn copper_index_of_refraction() -> Spectrum {
+        Spectrum::measured_metal(vec![1.18, 1.18, 1.16, 1.12, 1.02, 0.3, 0.21, 0.21, 0.21])
+    }
+
+    pub fn copper_extinction_coefficient() -> Spectrum {
+        Spectrum::measured_metal(vec![2.21, 2.21, 2.46, 2.57, 2.58, 3.33, 3.67, 4.21, 4.6])
+    }
+
+    pub fn aluminium_index_of_refraction() -> Spectrum {
+        Spectrum::measured_metal(vec![0.49, 0.49, 0.62, 0.77, 0.96, 1.2, 1.49, 1.83, 2.4])
+    }
+
+    pub fn aluminium_extinction_coefficient() -> Spectrum {
+        Spectrum::measured_metal(vec![4.86, 4.86, 5.47, 6.08, 6.69, 7.26, 7.82, 8.31, 8.5])
+    }
+
     fn wavelength_range(&self) -> f64 {
         self.longest_wavelength - self.shortest_wavelength
     }
@@ -79,88 +168,223 @@ impl Spectrum {
     }
 
     pub fn reflection_from_linear_rgb(colour: &ColourRgbF) -> Spectrum {
+        let weights = Spectrum::rgb_reference_weights(colour);
         Spectrum {
             shortest_wavelength: rgb_reference_spectrum::SHORTEST_WAVELENGTH,
             longest_wavelength: rgb_reference_spectrum::LONGEST_WAVELENGTH,
-            samples: if colour.red() <= colour.green() && colour.red() <= colour.blue() {
-                if colour.green() <= colour.blue() {
-                    izip![
-                        rgb_reference_spectrum::reflection::WHITE.iter(),
-                        rgb_reference_spectrum::reflection::CYAN.iter(),
-                        rgb_reference_spectrum::reflection::BLUE.iter()
-                    ]
-                    .map(|(white, cyan, blue)| {
-                        colour.red() * white
-                            + (colour.green() - colour.red()) * cyan
-                            + (colour.blue() - colour.green()) * blue
-                    })
-                    .collect()
-                } else {
-                    izip![
-                        rgb_reference_spectrum::reflection::WHITE.iter(),
-                        rgb_reference_spectrum::reflection::CYAN.iter(),
-                        rgb_reference_spectrum::reflection::GREEN.iter()
-                    ]
-                    .map(|(white, cyan, green)| {
-                        colour.red() * white
-                            + (colour.blue() - colour.red()) * cyan
-                            + (colour.green() - colour.blue()) * green
-                    })
-                    .collect()
+            samples: (0..rgb_reference_spectrum::reflection::WHITE.len())
+                .map(|index| {
+                    weights
+                        .iter()
+                        .zip(rgb_reference_spectrum::reflection::ALL.iter())
+                        .map(|(weight, reference)| weight * reference[index])
+                        .sum()
+                })
+                .collect(),
+        }
+    }
+
+    /// The colours whose spectra [reflection_from_linear_rgb()](Spectrum::reflection_from_linear_rgb)
+    /// mixes: white, cyan, magenta, yellow, red, green and blue
+    pub fn rgb_reference_colours() -> [ColourRgbF; 7] {
+        [
+            ColourRgbF::new(1.0, 1.0, 1.0),
+            ColourRgbF::new(0.0, 1.0, 1.0),
+            ColourRgbF::new(1.0, 0.0, 1.0),
+            ColourRgbF::new(1.0, 1.0, 0.0),
+            ColourRgbF::new(1.0, 0.0, 0.0),
+            ColourRgbF::new(0.0, 1.0, 0.0),
+            ColourRgbF::new(0.0, 0.0, 1.0),
+        ]
+    }
+
+    /// How much of each of the [reference colours](Spectrum::rgb_reference_colours) goes
+    /// into `colour`
+    ///
+    /// At most three of the weights are non-zero. The reflection and emission spectra of
+    /// `colour` are the same weighted sums of those of the reference colours, so a caller
+    /// converting many colours can keep the weights rather than a spectrum for each.
+    pub fn rgb_reference_weights(colour: &ColourRgbF) -> [f64; 7] {
+        let (red, green, blue) = (colour.red(), colour.green(), colour.blue());
+        if red <= green && red <= blue {
+            if green <= blue {
+                [red, green - red, 0.0, 0.0, 0.0, 0.0, blue - green]
+            } else {
+                [red, blue - red, 0.0, 0.0, 0.0, green - blue, 0.0]
+            }
+        } else if green <= red && green < blue {
+            if red <= blue {
+                [green, 0.0, red - green, 0.0, 0.0, 0.0, blue - red]
+            } else {
+                [green, 0.0, blue - green, 0.0, red - blue, 0.0, 0.0]
+            }
+        } else if red <= green {
+            [blue, 0.0, 0.0, red - blue, 0.0, green - red, 0.0]
+        } else {
+            [blue, 0.0, 0.0, green - blue, red - green, 0.0, 0.0]
+        }
+    }
+
+    /// The spectrum of a light source with the given linear sRGB colour
+    ///
+    /// Unlike [reflection_from_linear_rgb()](Spectrum::reflection_from_linear_rgb), this
+    /// accounts for sRGB white being the colour of the D65 illuminant rather than of a flat
+    /// spectrum, so that `(1.0, 1.0, 1.0)` gives a light that appears white. The luminance
+    /// is roughly that of [grey(1.0)](Spectrum::grey) scaled by the brightness of the
+    /// colour.
+    pub fn emission_from_linear_rgb(colour: &ColourRgbF) -> Spectrum {
+        let reflection = Spectrum::reflection_from_linear_rgb(colour);
+        let scale = d65_emission_scale();
+        let samples = reflection
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(index, sample)| sample * d65(reflection.wavelength_at_index(index)) * scale)
+            .collect();
+        Spectrum {
+            samples,
+            ..reflection
+        }
+    }
+
+    /// The spectrum of a black body at `temperature`, in kelvin
+    ///
+    /// Only the colour follows Planck's law; the spectrum is scaled to have the same
+    /// luminance as [grey(brightness)](Spectrum::grey).
+    pub fn blackbody(temperature: f64, brightness: f64) -> Spectrum {
+        let planck = |wavelength: f64| {
+            // hc/k is the second radiation constant, in metre-kelvins
+            let wavelength = wavelength * 1e-9;
+            1.0 / (wavelength.powi(5) * ((0.014_387_77 / (wavelength * temperature)).exp() - 1.0))
+        };
+        let scale = brightness * mean_luminance(&|_| 1.0) / mean_luminance(&planck);
+        let sample_count = 73;
+        let mut result = Spectrum {
+            shortest_wavelength: SHORTEST_VISIBLE_WAVELENGTH,
+            longest_wavelength: LONGEST_VISIBLE_WAVELENGTH,
+            samples: vec![0.0; sample_count],
+        };
+        for index in 0..sample_count {
+            result.samples[index] = planck(result.wavelength_at_index(index)) * scale;
+        }
+        result
+    }
+
+    /// The spectrum of a CIE standard illuminant, scaled to have the same luminance as
+    /// [grey(brightness)](Spectrum::grey)
+    pub fn illuminant(illuminant: StandardIlluminant, brightness: f64) -> Spectrum {
+        let value = |wavelength: f64| illuminant.value(wavelength);
+        let scale = brightness * mean_luminance(&|_| 1.0) / mean_luminance(&value);
+        // Fine enough to keep the narrow peaks of fluorescent lamps
+        let sample_count = 361;
+        let mut result = Spectrum {
+            shortest_wavelength: SHORTEST_VISIBLE_WAVELENGTH,
+            longest_wavelength: LONGEST_VISIBLE_WAVELENGTH,
+            samples: vec![0.0; sample_count],
+        };
+        for index in 0..sample_count {
+            result.samples[index] = value(result.wavelength_at_index(index)) * scale;
+        }
+        result
+    }
+
+    /// A spectrum with the intensity `f(wavelength)`, sampled at `sample_count` evenly
+    /// spaced wavelengths across the visible range
+    pub fn from_function<F: Fn(f64) -> f64>(sample_count: usize, f: F) -> Spectrum {
+        let last = sample_count.max(2) - 1;
+        let range = LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH;
+        Spectrum {
+            shortest_wavelength: SHORTEST_VISIBLE_WAVELENGTH,
+            longest_wavelength: LONGEST_VISIBLE_WAVELENGTH,
+            samples: (0..=last)
+                .map(|index| f(SHORTEST_VISIBLE_WAVELENGTH + index as f64 / last as f64 * range))
+                .collect(),
+        }
+    }
+
+    /// A spectrum measured at the wavelengths in `samples`, which are pairs of a
+    /// wavelength and the intensity there
+    ///
+    /// Intensities between the samples are interpolated linearly, and those beyond the
+    /// first or last are the same as it.
+    pub fn from_samples(samples: &[(f64, f64)]) -> Spectrum {
+        assert!(!samples.is_empty());
+        let mut samples = samples.to_vec();
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Spectrum::from_function(73, |wavelength| {
+            let after = samples.partition_point(|&(sample, _)| sample < wavelength);
+            match (samples.get(after.wrapping_sub(1)), samples.get(after)) {
+                (Some(&(wavelength0, value0)), Some(&(wavelength1, value1))) => {
+                    let ratio = (wavelength - wavelength0) / (wavelength1 - wavelength0);
+                    value0 * (1.0 - ratio) + value1 * ratio
                 }
-            } else if colour.green() <= colour.red() && colour.green() < colour.blue() {
-                if colour.red() <= colour.blue() {
-                    izip![
-                        rgb_reference_spectrum::reflection::WHITE.iter(),
-                        rgb_reference_spectrum::reflection::MAGENTA.iter(),
-                        rgb_reference_spectrum::reflection::BLUE.iter()
-                    ]
-                    .map(|(white, magenta, blue)| {
-                        colour.green() * white
-                            + (colour.red() - colour.green()) * magenta
-                            + (colour.blue() - colour.red()) * blue
-                    })
-                    .collect()
-                } else {
-                    izip![
-                        rgb_reference_spectrum::reflection::WHITE.iter(),
-                        rgb_reference_spectrum::reflection::MAGENTA.iter(),
-                        rgb_reference_spectrum::reflection::RED.iter()
-                    ]
-                    .map(|(white, magenta, red)| {
-                        colour.green() * white
-                            + (colour.blue() - colour.green()) * magenta
-                            + (colour.red() - colour.blue()) * red
-                    })
-                    .collect()
+                (Some(&(_, value)), None) | (None, Some(&(_, value))) => value,
+                (None, None) => unreachable!(),
+            }
+        })
+    }
+
+    /// Read a measured spectrum from the text file `filename`, such as a sensor's quantum
+    /// efficiency; see [read_from()](Spectrum::read_from)
+    pub fn read(filename: &Path) -> Result<Spectrum, VanrijnError> {
+        File::open(filename)
+            .and_then(Spectrum::read_from)
+            .map_err(VanrijnError::colour_data(filename))
+    }
+
+    /// Read a measured spectrum, with a wavelength in nanometres and an intensity on each
+    /// line, separated by a comma or spaces
+    ///
+    /// `#` starts a comment, and the first line is skipped if it's a header rather than a
+    /// sample.
+    pub fn read_from<R: Read>(reader: R) -> Result<Spectrum, Error> {
+        let mut samples = vec![];
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let values: Vec<f64> = match line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse())
+                .collect()
+            {
+                Ok(values) => values,
+                Err(_) if index == 0 => continue,
+                Err(_) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Bad spectrum sample \"{}\".", line),
+                    ))
                 }
-            } else {
-                if colour.red() <= colour.green() {
-                    izip![
-                        rgb_reference_spectrum::reflection::WHITE.iter(),
-                        rgb_reference_spectrum::reflection::YELLOW.iter(),
-                        rgb_reference_spectrum::reflection::GREEN.iter()
-                    ]
-                    .map(|(white, yellow, green)| {
-                        colour.blue() * white
-                            + (colour.red() - colour.blue()) * yellow
-                            + (colour.green() - colour.red()) * green
-                    })
-                    .collect()
-                } else {
-                    izip![
-                        rgb_reference_spectrum::reflection::WHITE.iter(),
-                        rgb_reference_spectrum::reflection::YELLOW.iter(),
-                        rgb_reference_spectrum::reflection::RED.iter()
-                    ]
-                    .map(|(white, yellow, red)| {
-                        colour.blue() * white
-                            + (colour.green() - colour.blue()) * yellow
-                            + (colour.red() - colour.green()) * red
-                    })
-                    .collect()
+            };
+            match values[..] {
+                [wavelength, value] => samples.push((wavelength, value)),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Expected a wavelength and a value on each line of the spectrum.",
+                    ))
                 }
-            },
+            }
+        }
+        if samples.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Spectrum has no samples.",
+            ));
+        }
+        Ok(Spectrum::from_samples(&samples))
+    }
+
+    /// A spectrum with `f` applied to the intensity at every wavelength
+    pub fn map<F: Fn(f64) -> f64>(&self, f: F) -> Spectrum {
+        Spectrum {
+            shortest_wavelength: self.shortest_wavelength,
+            longest_wavelength: self.longest_wavelength,
+            samples: self.samples.iter().map(|&sample| f(sample)).collect(),
         }
     }
 
@@ -175,6 +399,26 @@ impl Spectrum {
     }
 }
 
+/// The scale that gives [D65](d65) the same luminance as a flat spectrum of one, for
+/// [Spectrum::emission_from_linear_rgb()](Spectrum::emission_from_linear_rgb)
+fn d65_emission_scale() -> f64 {
+    static SCALE: OnceLock<f64> = OnceLock::new();
+    *SCALE.get_or_init(|| mean_luminance(&|_| 1.0) / mean_luminance(&d65))
+}
+
+/// The mean over the visible wavelengths of `spectrum` weighted by the luminosity function
+fn mean_luminance(spectrum: &dyn Fn(f64) -> f64) -> f64 {
+    let first = SHORTEST_VISIBLE_WAVELENGTH as usize;
+    let last = LONGEST_VISIBLE_WAVELENGTH as usize;
+    (first..=last)
+        .map(|wavelength| {
+            let wavelength = wavelength as f64;
+            spectrum(wavelength) * ColourXyz::for_wavelength(wavelength).y()
+        })
+        .sum::<f64>()
+        / (last - first + 1) as f64
+}
+
 mod rgb_reference_spectrum {
     pub const SHORTEST_WAVELENGTH: f64 = 380.0;
     pub const LONGEST_WAVELENGTH: f64 = 720.0;
@@ -417,6 +661,9 @@ mod rgb_reference_spectrum {
             6.9596532104356399e-03,
             4.1733649330980525e-03,
         ];
+
+        /// Every reference spectrum, in the order of `Spectrum::rgb_reference_colours()`
+        pub const ALL: [[f64; 32]; 7] = [WHITE, CYAN, MAGENTA, YELLOW, RED, GREEN, BLUE];
     }
 }
 
@@ -486,4 +733,112 @@ mod tests {
         };
         assert!(target.intensity_at_wavelength(700.0001) == 0.0);
     }
+
+    /// CIE xy chromaticity and luminance of `spectrum`
+    fn chromaticity_and_luminance(spectrum: &Spectrum) -> (f64, f64, f64) {
+        let xyz = ColourXyz::from_spectrum(spectrum).values;
+        let total = xyz.x() + xyz.y() + xyz.z();
+        (xyz.x() / total, xyz.y() / total, xyz.y())
+    }
+
+    #[test]
+    fn illuminant_spectrum_keeps_chromaticity() {
+        let target = Spectrum::illuminant(StandardIlluminant::F11, 1.0);
+        let (x, y, luminance) = chromaticity_and_luminance(&target);
+        assert!((x - 0.3805).abs() < 0.005);
+        assert!((y - 0.3769).abs() < 0.005);
+        let (_, _, expected) = chromaticity_and_luminance(&Spectrum::grey(1.0));
+        assert!((luminance / expected - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn white_emission_has_chromaticity_of_d65() {
+        let target = Spectrum::emission_from_linear_rgb(&ColourRgbF::new(1.0, 1.0, 1.0));
+        let (x, y, _) = chromaticity_and_luminance(&target);
+        assert!((x - 0.3127).abs() < 0.01);
+        assert!((y - 0.3290).abs() < 0.01);
+    }
+
+    #[test]
+    fn white_emission_has_luminance_of_grey() {
+        let target = Spectrum::emission_from_linear_rgb(&ColourRgbF::new(1.0, 1.0, 1.0));
+        let (_, _, luminance) = chromaticity_and_luminance(&target);
+        let (_, _, expected) = chromaticity_and_luminance(&Spectrum::grey(1.0));
+        assert!((luminance / expected - 1.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn emission_is_weighted_sum_of_reference_colour_emission() {
+        let colour = ColourRgbF::new(0.8, 0.3, 0.5);
+        let target = Spectrum::emission_from_linear_rgb(&colour);
+        let weights = Spectrum::rgb_reference_weights(&colour);
+        let references: Vec<Spectrum> = Spectrum::rgb_reference_colours()
+            .iter()
+            .map(Spectrum::emission_from_linear_rgb)
+            .collect();
+        for wavelength in [400.0, 475.0, 550.0, 625.0, 700.0] {
+            let expected: f64 = weights
+                .iter()
+                .zip(references.iter())
+                .map(|(weight, reference)| weight * reference.intensity_at_wavelength(wavelength))
+                .sum();
+            assert!((target.intensity_at_wavelength(wavelength) - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn blackbody_at_6504_kelvin_is_close_to_d65() {
+        let target = Spectrum::blackbody(6504.0, 1.0);
+        let (x, y, _) = chromaticity_and_luminance(&target);
+        assert!((x - 0.3135).abs() < 0.005);
+        assert!((y - 0.3237).abs() < 0.005);
+    }
+
+    #[test]
+    fn cooler_blackbody_is_redder() {
+        let warm = Spectrum::blackbody(2700.0, 1.0);
+        let cool = Spectrum::blackbody(10000.0, 1.0);
+        assert!(warm.intensity_at_wavelength(700.0) > warm.intensity_at_wavelength(450.0));
+        assert!(cool.intensity_at_wavelength(700.0) < cool.intensity_at_wavelength(450.0));
+    }
+
+    #[test]
+    fn blackbody_has_luminance_of_grey_with_same_brightness() {
+        let target = Spectrum::blackbody(3500.0, 2.5);
+        let (_, _, luminance) = chromaticity_and_luminance(&target);
+        let (_, _, expected) = chromaticity_and_luminance(&Spectrum::grey(2.5));
+        assert!((luminance / expected - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn sellmeier_materials_match_catalogue_indices() {
+        // At the sodium D line
+        for (spectrum, expected) in [
+            (Spectrum::bk7_index_of_refraction(), 1.5168),
+            (Spectrum::fused_silica_index_of_refraction(), 1.4585),
+            (Spectrum::water_index_of_refraction(), 1.3330),
+            (Spectrum::diamond_index_of_refraction(), 2.4175),
+        ]
+        .iter()
+        {
+            assert!((spectrum.intensity_at_wavelength(589.3) - expected).abs() < 0.0005);
+            // Normal dispersion bends blue light more than red
+            assert!(
+                spectrum.intensity_at_wavelength(450.0) > spectrum.intensity_at_wavelength(650.0)
+            );
+        }
+    }
+
+    #[test]
+    fn reads_measured_spectrum() {
+        let data = "wavelength,efficiency\n# Comment\n400, 0.5\n\n600,1.0\n500 0.25\n";
+        let target = Spectrum::read_from(data.as_bytes()).unwrap();
+        assert!((target.intensity_at_wavelength(450.0) - 0.375).abs() < 0.000000001);
+        assert!((target.intensity_at_wavelength(550.0) - 0.625).abs() < 0.000000001);
+        assert!(target.intensity_at_wavelength(380.0) == 0.5);
+        assert!(target.intensity_at_wavelength(700.0) == 1.0);
+        assert!(Spectrum::read_from("400,0.5\n500\n".as_bytes()).is_err());
+        assert!(Spectrum::read_from("400,0.5\n500,half\n".as_bytes()).is_err());
+        assert!(Spectrum::read_from("wavelength,efficiency\n".as_bytes()).is_err());
+    }
 }
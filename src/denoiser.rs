@@ -0,0 +1,161 @@
+use crate::aovs::DenoisingAovs;
+use crate::colour::ColourXyz;
+use crate::math::Vec3;
+use crate::util::Array2D;
+
+/// Removes noise from a rendered image before it's tone mapped
+///
+/// Denoisers are given the [albedo and normal AOVs](DenoisingAovs) as well as the colour,
+/// so edges in the surfaces can be kept sharp while the noise in the lighting is smoothed
+/// away. Learned denoisers, such as Intel's Open Image Denoise, take the same three images,
+/// so they can be plugged in by implementing this trait; [CrossBilateralDenoiser] is a
+/// simple one that needs no external libraries.
+pub trait Denoiser: Send + Sync {
+    /// The denoised version of `colour`, which must be the same size as `aovs`
+    fn denoise(&self, colour: &Array2D<ColourXyz>, aovs: &DenoisingAovs) -> Array2D<ColourXyz>;
+}
+
+/// Averages each pixel with its neighbours, weighted by how close they are and how alike
+/// their albedos and normals are
+///
+/// This blurs the lighting within each surface but not across the edges between surfaces,
+/// or across changes of texture. Edges that are only in the lighting, such as shadows, are
+/// blurred too, so `radius` should stay small.
+#[derive(Clone, Copy, Debug)]
+pub struct CrossBilateralDenoiser {
+    /// Largest distance, in pixels, of the neighbours averaged with each pixel
+    pub radius: usize,
+
+    /// Standard deviation of the weights over distance, in pixels
+    pub spatial_sigma: f64,
+
+    /// Standard deviation of the weights over the difference in albedo
+    pub albedo_sigma: f64,
+
+    /// Standard deviation of the weights over the difference in normal
+    pub normal_sigma: f64,
+}
+
+impl Default for CrossBilateralDenoiser {
+    fn default() -> CrossBilateralDenoiser {
+        CrossBilateralDenoiser {
+            radius: 3,
+            spatial_sigma: 2.0,
+            albedo_sigma: 0.1,
+            normal_sigma: 0.2,
+        }
+    }
+}
+
+/// Weight of a difference of `distance_squared` with standard deviation `sigma`
+fn gaussian_weight(distance_squared: f64, sigma: f64) -> f64 {
+    (-0.5 * distance_squared / (sigma * sigma)).exp()
+}
+
+impl Denoiser for CrossBilateralDenoiser {
+    fn denoise(&self, colour: &Array2D<ColourXyz>, aovs: &DenoisingAovs) -> Array2D<ColourXyz> {
+        let height = colour.get_height();
+        let width = colour.get_width();
+        assert!(aovs.albedo.get_height() == height && aovs.albedo.get_width() == width);
+        assert!(aovs.normal.get_height() == height && aovs.normal.get_width() == width);
+        let mut result = Array2D::new(height, width);
+        for row in 0..height {
+            for column in 0..width {
+                let albedo = &aovs.albedo[row][column].values;
+                let normal = &aovs.normal[row][column];
+                let mut total = Vec3::zeros();
+                let mut total_weight = 0.0;
+                for other_row in
+                    row.saturating_sub(self.radius)..(row + self.radius + 1).min(height)
+                {
+                    for other_column in
+                        column.saturating_sub(self.radius)..(column + self.radius + 1).min(width)
+                    {
+                        let rows = other_row as f64 - row as f64;
+                        let columns = other_column as f64 - column as f64;
+                        let albedo_difference =
+                            aovs.albedo[other_row][other_column].values - *albedo;
+                        let normal_difference = aovs.normal[other_row][other_column] - *normal;
+                        let weight =
+                            gaussian_weight(rows * rows + columns * columns, self.spatial_sigma)
+                                * gaussian_weight(
+                                    albedo_difference.norm_squared(),
+                                    self.albedo_sigma,
+                                )
+                                * gaussian_weight(
+                                    normal_difference.norm_squared(),
+                                    self.normal_sigma,
+                                );
+                        total += colour[other_row][other_column].values * weight;
+                        total_weight += weight;
+                    }
+                }
+                // The pixel itself always has a weight of one, so the total can't be zero
+                result[row][column] = ColourXyz {
+                    values: total * (1.0 / total_weight),
+                };
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// AOVs for an image whose left and right halves are different surfaces
+    fn two_surfaces(width: usize, height: usize) -> DenoisingAovs {
+        let mut aovs = DenoisingAovs {
+            albedo: Array2D::new(height, width),
+            normal: Array2D::new(height, width),
+        };
+        for row in 0..height {
+            for column in 0..width {
+                let grey = if column < width / 2 { 0.2 } else { 0.8 };
+                aovs.albedo[row][column] = ColourXyz::new(grey, grey, grey);
+                aovs.normal[row][column] = Vec3::unit_z();
+            }
+        }
+        aovs
+    }
+
+    #[test]
+    fn noise_within_a_surface_is_reduced() {
+        let aovs = two_surfaces(16, 16);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut colour = Array2D::new(16, 16);
+        for row in 0..16 {
+            for column in 0..16 {
+                let y = 1.0 + rng.gen_range(-0.5, 0.5);
+                colour[row][column] = ColourXyz::new(y, y, y);
+            }
+        }
+        let target = CrossBilateralDenoiser::default().denoise(&colour, &aovs);
+        let error = |image: &Array2D<ColourXyz>| {
+            image
+                .as_slice()
+                .iter()
+                .map(|c| (c.y() - 1.0).powi(2))
+                .sum::<f64>()
+        };
+        assert!(error(&target) < 0.25 * error(&colour));
+    }
+
+    #[test]
+    fn edges_between_surfaces_are_kept() {
+        let aovs = two_surfaces(16, 4);
+        let mut colour = Array2D::new(4, 16);
+        for row in 0..4 {
+            for column in 0..16 {
+                colour[row][column] = aovs.albedo[row][column].clone();
+            }
+        }
+        let target = CrossBilateralDenoiser::default().denoise(&colour, &aovs);
+        assert!((target[2][7].y() - 0.2).abs() < 0.001);
+        assert!((target[2][8].y() - 0.8).abs() < 0.001);
+    }
+}
@@ -0,0 +1,420 @@
+//! Rendering an image on several machines at once
+//!
+//! A coordinator listens for workers with [coordinate()](coordinate) and hands each one
+//! tiles to render over TCP. Workers, started with [run_worker()](run_worker) on a copy of
+//! the same scene, render each tile with
+//! [partial_render_scene()](crate::camera::partial_render_scene) and send back the
+//! [AccumulationBuffer](AccumulationBuffer), which the coordinator merges into the image.
+//!
+//! Each tile is rendered with the coordinator's seed, so the image is the same as a single
+//! [pass](crate::progressive_renderer::ProgressiveRenderer::render_pass) rendered locally,
+//! however the tiles were shared out. Tiles held by workers that disconnect are given to
+//! the remaining workers.
+
+use rayon::prelude::*;
+
+use crate::accumulation_buffer::{read_u64, AccumulationBuffer};
+use crate::camera::{filter_footprint, partial_render_scene, RenderSettings};
+use crate::error::VanrijnError;
+use crate::scene::Scene;
+use crate::util::{Tile, TileOrder, TileScheduler};
+
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const JOB_MAGIC: &[u8; 8] = b"VRJOB001";
+const DONE_MAGIC: &[u8; 8] = b"VRDONE01";
+
+/// How long the coordinator waits before checking again for new workers or returned tiles
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long the coordinator waits for a worker to render a tile before giving the tile to
+/// another worker
+const TILE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How long the coordinator waits for a worker to accept a job before dropping it
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A tile for a worker to render
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TileJob {
+    tile: Tile,
+    width: usize,
+    height: usize,
+    seed: u64,
+    samples_per_pixel: usize,
+}
+
+impl TileJob {
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let mut message = JOB_MAGIC.to_vec();
+        for value in [
+            self.tile.start_column as u64,
+            self.tile.end_column as u64,
+            self.tile.start_row as u64,
+            self.tile.end_row as u64,
+            self.width as u64,
+            self.height as u64,
+            self.seed,
+            self.samples_per_pixel as u64,
+        ] {
+            message.extend_from_slice(&value.to_le_bytes());
+        }
+        writer.write_all(&message)?;
+        writer.flush()
+    }
+
+    /// Read the rest of a job whose magic number has already been read
+    fn read_from<R: Read>(reader: &mut R) -> Result<TileJob, Error> {
+        let mut next = || read_u64(reader).map(|value| value as usize);
+        let tile = Tile {
+            start_column: next()?,
+            end_column: next()?,
+            start_row: next()?,
+            end_row: next()?,
+        };
+        let width = next()?;
+        let height = next()?;
+        let seed = read_u64(reader)?;
+        let samples_per_pixel = read_u64(reader)? as usize;
+        if tile.start_column > tile.end_column
+            || tile.end_column > width
+            || tile.start_row > tile.end_row
+            || tile.end_row > height
+        {
+            return Err(Error::new(ErrorKind::InvalidData, "Tile is outside image."));
+        }
+        Ok(TileJob {
+            tile,
+            width,
+            height,
+            seed,
+            samples_per_pixel,
+        })
+    }
+}
+
+/// The tiles still to be rendered, shared between the threads serving each worker
+struct WorkQueue {
+    scheduler: TileScheduler,
+    returned: Mutex<Vec<Tile>>,
+    remaining: AtomicUsize,
+}
+
+impl WorkQueue {
+    fn take(&self) -> Option<Tile> {
+        let returned = self
+            .returned
+            .lock()
+            .expect("Returned tile lock poisoned.")
+            .pop();
+        returned.or_else(|| self.scheduler.next_tile())
+    }
+
+    /// Put back a tile that couldn't be rendered, for another worker to take
+    fn give_back(&self, tile: Tile) {
+        self.returned
+            .lock()
+            .expect("Returned tile lock poisoned.")
+            .push(tile);
+    }
+
+    fn complete(&self) {
+        self.remaining.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.remaining.load(Ordering::SeqCst) == 0
+    }
+}
+
+/// Render `job` on the worker at the other end of `stream`
+///
+/// The rendered tile must cover `footprint`, the pixels that the job's tile contributes
+/// to; a worker that sends back any other size is treated as having failed.
+fn render_remotely(
+    stream: &mut TcpStream,
+    job: &TileJob,
+    footprint: &Tile,
+) -> Result<AccumulationBuffer, Error> {
+    job.write_to(stream)?;
+    AccumulationBuffer::read_from_expecting(
+        &mut BufReader::new(stream),
+        footprint.width(),
+        footprint.height(),
+    )
+}
+
+/// Give tiles to the worker at the other end of `stream` until there are none left
+#[allow(clippy::too_many_arguments)]
+fn serve_worker(
+    mut stream: TcpStream,
+    work: &WorkQueue,
+    image: &Mutex<AccumulationBuffer>,
+    width: usize,
+    height: usize,
+    seed: u64,
+    settings: &RenderSettings,
+) -> Result<(), Error> {
+    stream.set_nonblocking(false)?;
+    // A worker that stops responding is dropped like one that disconnects
+    stream.set_read_timeout(Some(TILE_TIMEOUT))?;
+    stream.set_write_timeout(Some(SEND_TIMEOUT))?;
+    loop {
+        let tile = match work.take() {
+            Some(tile) => tile,
+            None if work.is_finished() => {
+                stream.write_all(DONE_MAGIC)?;
+                return stream.flush();
+            }
+            None => {
+                // Another worker may yet give a tile back
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+        };
+        let job = TileJob {
+            tile,
+            width,
+            height,
+            seed,
+            samples_per_pixel: settings.samples_per_pixel,
+        };
+        let footprint = filter_footprint(&tile, width, height, settings.filter.as_ref());
+        let rendered_tile = render_remotely(&mut stream, &job, &footprint);
+        match rendered_tile {
+            Ok(rendered_tile) => {
+                image
+                    .lock()
+                    .expect("Accumulation buffer lock poisoned.")
+                    .merge_tile(&footprint, &rendered_tile);
+                work.complete();
+            }
+            Err(error) => {
+                work.give_back(tile);
+                return Err(error);
+            }
+        }
+    }
+}
+
+/// Render one pass over `image` on the workers that connect to `listener`
+///
+/// Every pixel gets `settings.samples_per_pixel` samples, in tiles of `settings.tile_size`.
+/// Only the sample count, tile size, seed and filter in `settings` are used here; workers
+/// use their own settings for everything else, so they should be started with the same
+/// options as the coordinator. This returns once every tile has been merged into `image`,
+/// which waits for workers to connect if there are none. Workers that disconnect, send
+/// back invalid tiles or take longer than ten minutes over a tile are dropped, and their
+/// tiles rendered by the others.
+pub fn coordinate(
+    listener: &TcpListener,
+    image: &Mutex<AccumulationBuffer>,
+    settings: &RenderSettings,
+) -> Result<(), VanrijnError> {
+    let (width, height) = {
+        let image = image.lock().expect("Accumulation buffer lock poisoned.");
+        (image.width(), image.height())
+    };
+    let scheduler = TileScheduler::new(width, height, settings.tile_size, TileOrder::default());
+    let work = WorkQueue {
+        remaining: AtomicUsize::new(scheduler.len()),
+        scheduler,
+        returned: Mutex::new(vec![]),
+    };
+    listener.set_nonblocking(true)?;
+    std::thread::scope(|scope| {
+        while !work.is_finished() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let work = &work;
+                    scope.spawn(move || {
+                        // A worker going away isn't an error for the render as a whole
+                        serve_worker(stream, work, image, width, height, settings.seed, settings)
+                            .ok();
+                    });
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                    std::thread::sleep(POLL_INTERVAL)
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+        // Let workers that connected too late to help know that they aren't needed
+        while let Ok((mut stream, _)) = listener.accept() {
+            stream.write_all(DONE_MAGIC).ok();
+        }
+        Ok(())
+    })
+}
+
+/// Render the tiles that the coordinator at the other end of `stream` asks for, until it
+/// says that there are no more
+///
+/// Returns the number of tiles rendered. `settings` are used as they are, apart from the
+/// number of samples per pixel, which the coordinator chooses. A coordinator that hangs
+/// up between tiles is taken to have finished, as it may have done so before it could
+/// reply to a worker that connected late.
+pub fn serve_tiles<S: Read + Write>(
+    scene: &Scene,
+    settings: &RenderSettings,
+    mut stream: S,
+) -> Result<usize, Error> {
+    let mut tiles = 0;
+    loop {
+        let mut magic = [0u8; 8];
+        match stream.read_exact(&mut magic) {
+            Ok(()) => {}
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    ErrorKind::UnexpectedEof
+                        | ErrorKind::ConnectionReset
+                        | ErrorKind::ConnectionAborted
+                ) =>
+            {
+                return Ok(tiles)
+            }
+            Err(error) => return Err(error),
+        }
+        if &magic == DONE_MAGIC {
+            return Ok(tiles);
+        } else if &magic != JOB_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a tile job."));
+        }
+        let job = TileJob::read_from(&mut stream)?;
+        let settings = RenderSettings {
+            samples_per_pixel: job.samples_per_pixel,
+            ..settings.clone()
+        };
+        let rendered_tile =
+            partial_render_scene(scene, job.tile, job.height, job.width, job.seed, &settings);
+        let mut writer = BufWriter::new(&mut stream);
+        rendered_tile.write_to(&mut writer)?;
+        writer.flush()?;
+        tiles += 1;
+    }
+}
+
+/// Connect to the coordinator at `address` and render tiles for it until it's finished
+///
+/// `connections` connections are made, each rendering one tile at a time, so a worker
+/// should usually use one for each of its threads. Returns the total number of tiles
+/// rendered.
+pub fn run_worker<A: ToSocketAddrs + Sync>(
+    address: A,
+    scene: &Scene,
+    settings: &RenderSettings,
+    connections: usize,
+) -> Result<usize, VanrijnError> {
+    (0..connections)
+        .into_par_iter()
+        .map(|_| Ok(serve_tiles(scene, settings, TcpStream::connect(&address)?)?))
+        .try_reduce(|| 0, |a, b| Ok(a + b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+
+    use crate::math::Vec3;
+    use crate::progressive_renderer::ProgressiveRenderer;
+
+    use std::sync::Arc;
+
+    fn sky_scene() -> Scene {
+        Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Lens::Pinhole,
+        )))
+        .build()
+    }
+
+    fn coordinator_settings() -> RenderSettings {
+        RenderSettings {
+            tile_size: 4,
+            seed: 3,
+            ..RenderSettings::default()
+        }
+    }
+
+    fn local_render(width: usize, height: usize, seed: u64) -> AccumulationBuffer {
+        let scene = sky_scene();
+        let image = Arc::new(Mutex::new(AccumulationBuffer::new(width, height)));
+        let settings = RenderSettings {
+            tile_size: 4,
+            seed,
+            ..RenderSettings::default()
+        };
+        let mut renderer = ProgressiveRenderer::new(&scene, Arc::clone(&image), settings);
+        renderer.render_pass();
+        let image = image.lock().unwrap().clone();
+        image
+    }
+
+    #[test]
+    fn job_round_trips() {
+        let job = TileJob {
+            tile: Tile {
+                start_column: 1,
+                end_column: 5,
+                start_row: 2,
+                end_row: 3,
+            },
+            width: 10,
+            height: 7,
+            seed: u64::MAX,
+            samples_per_pixel: 16,
+        };
+        let mut message = vec![];
+        job.write_to(&mut message).unwrap();
+        let mut reader = &message[8..];
+        assert!(&message[..8] == JOB_MAGIC);
+        assert!(TileJob::read_from(&mut reader).unwrap() == job);
+    }
+
+    #[test]
+    fn workers_render_the_same_image_as_a_local_pass() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let worker = std::thread::spawn(move || {
+            run_worker(address, &sky_scene(), &RenderSettings::default(), 2).unwrap()
+        });
+        let image = Mutex::new(AccumulationBuffer::new(10, 7));
+        coordinate(&listener, &image, &coordinator_settings()).unwrap();
+        assert!(worker.join().unwrap() == 6);
+        let image = image.into_inner().unwrap();
+        let expected = local_render(10, 7, 3);
+        for row in 0..7 {
+            for column in 0..10 {
+                assert!(image.colour(row, column) == expected.colour(row, column));
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_from_lost_workers_are_rendered_by_others() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let worker = std::thread::spawn(move || {
+            // Take a tile and hang up without rendering it
+            let mut lost_worker = TcpStream::connect(address).unwrap();
+            let mut magic = [0u8; 8];
+            lost_worker.read_exact(&mut magic).unwrap();
+            drop(lost_worker);
+            run_worker(address, &sky_scene(), &RenderSettings::default(), 1).unwrap()
+        });
+        let image = Mutex::new(AccumulationBuffer::new(10, 7));
+        coordinate(&listener, &image, &coordinator_settings()).unwrap();
+        assert!(worker.join().unwrap() == 6);
+        let image = image.into_inner().unwrap();
+        let expected = local_render(10, 7, 3);
+        assert!(image.colour(0, 0) == expected.colour(0, 0));
+        assert!(image.colour(6, 9) == expected.colour(6, 9));
+    }
+}
@@ -0,0 +1,157 @@
+use rayon::ThreadPoolBuildError;
+
+use crate::validation::SceneIssue;
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything that can go wrong when loading a scene, rendering it or saving the result
+///
+/// Functions that read or write a single stream, such as
+/// [AccumulationBuffer::read_from()](crate::accumulation_buffer::AccumulationBuffer::read_from),
+/// return a plain [io::Error](std::io::Error) instead, in the same way as
+/// [Read](std::io::Read) and [Write](std::io::Write); it converts into
+/// [VanrijnError::Io](VanrijnError::Io).
+#[derive(Debug)]
+pub enum VanrijnError {
+    /// Reading or writing failed, other than for a mesh or image file
+    Io(io::Error),
+
+    /// A mesh file, or one of the material files it uses, couldn't be read
+    Mesh {
+        filename: PathBuf,
+        source: io::Error,
+    },
+
+    /// An image file couldn't be read or written
+    Image {
+        filename: PathBuf,
+        source: io::Error,
+    },
+
+    /// A file of measured material data couldn't be read
+    MaterialData { filename: PathBuf, message: String },
+
+    /// A LUT or spectrum used to process the rendered colours couldn't be read
+    ColourData {
+        filename: PathBuf,
+        source: io::Error,
+    },
+
+    /// The scene can't be rendered as it is, because of the
+    /// [errors](SceneIssue::is_error) among these issues
+    InvalidScene(Vec<SceneIssue>),
+
+    /// The renderer couldn't start
+    Renderer(ThreadPoolBuildError),
+}
+
+pub type Result<T> = std::result::Result<T, VanrijnError>;
+
+impl VanrijnError {
+    /// Turns an error from reading or writing the mesh `filename` into a
+    /// [Mesh](VanrijnError::Mesh) error, for use with `map_err()`
+    pub(crate) fn mesh(filename: &Path) -> impl FnOnce(io::Error) -> VanrijnError + '_ {
+        move |source| VanrijnError::Mesh {
+            filename: filename.to_path_buf(),
+            source,
+        }
+    }
+
+    /// Turns an error from reading or writing the image `filename` into an
+    /// [Image](VanrijnError::Image) error, for use with `map_err()`
+    pub(crate) fn image(filename: &Path) -> impl FnOnce(io::Error) -> VanrijnError + '_ {
+        move |source| VanrijnError::Image {
+            filename: filename.to_path_buf(),
+            source,
+        }
+    }
+
+    /// Turns an error from reading the LUT or spectrum `filename` into a
+    /// [ColourData](VanrijnError::ColourData) error, for use with `map_err()`
+    pub(crate) fn colour_data(filename: &Path) -> impl FnOnce(io::Error) -> VanrijnError + '_ {
+        move |source| VanrijnError::ColourData {
+            filename: filename.to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for VanrijnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VanrijnError::Io(error) => write!(f, "{}", error),
+            VanrijnError::Mesh { filename, source } => {
+                write!(f, "Couldn't load mesh {}: {}", filename.display(), source)
+            }
+            VanrijnError::Image { filename, source } => {
+                write!(
+                    f,
+                    "Couldn't access image {}: {}",
+                    filename.display(),
+                    source
+                )
+            }
+            VanrijnError::MaterialData { filename, message } => write!(
+                f,
+                "Couldn't load material data {}: {}",
+                filename.display(),
+                message
+            ),
+            VanrijnError::ColourData { filename, source } => write!(
+                f,
+                "Couldn't load colour data {}: {}",
+                filename.display(),
+                source
+            ),
+            VanrijnError::InvalidScene(issues) => {
+                let issues: Vec<String> = issues.iter().map(|issue| issue.to_string()).collect();
+                write!(f, "Invalid scene: {}", issues.join("; "))
+            }
+            VanrijnError::Renderer(error) => write!(f, "Couldn't start renderer: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for VanrijnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VanrijnError::Io(error) => Some(error),
+            VanrijnError::Mesh { source, .. }
+            | VanrijnError::Image { source, .. }
+            | VanrijnError::ColourData { source, .. } => Some(source),
+            VanrijnError::Renderer(error) => Some(error),
+            VanrijnError::MaterialData { .. } | VanrijnError::InvalidScene(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for VanrijnError {
+    fn from(error: io::Error) -> VanrijnError {
+        VanrijnError::Io(error)
+    }
+}
+
+impl From<ThreadPoolBuildError> for VanrijnError {
+    fn from(error: ThreadPoolBuildError) -> VanrijnError {
+        VanrijnError::Renderer(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::error::Error;
+
+    #[test]
+    fn file_errors_name_the_file_and_keep_the_cause() {
+        let target = VanrijnError::image(Path::new("missing.png"))(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No such file",
+        ));
+        assert!(target.to_string() == "Couldn't access image missing.png: No such file");
+        assert!(target.source().unwrap().to_string() == "No such file");
+    }
+}
@@ -0,0 +1,53 @@
+use super::Filter;
+
+/// Every sample within the filter radius has the same weight
+///
+/// With a radius of 0.5 each sample contributes only to the pixel it falls in, which is
+/// fast and sharp but prone to aliasing.
+#[derive(Clone, Copy, Debug)]
+pub struct BoxFilter {
+    pub radius: f64,
+}
+
+impl BoxFilter {
+    pub fn new(radius: f64) -> BoxFilter {
+        BoxFilter { radius }
+    }
+}
+
+impl Default for BoxFilter {
+    fn default() -> BoxFilter {
+        BoxFilter::new(0.5)
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        // The support is half-open so that, with a radius of 0.5, a sample on the border
+        // between two pixels only contributes to one of them.
+        let inside = |offset: f64| -self.radius <= offset && offset < self.radius;
+        if inside(x) && inside(y) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_filter_covers_exactly_one_pixel() {
+        let target = BoxFilter::default();
+        assert!(target.evaluate(0.0, 0.0) == 1.0);
+        assert!(target.evaluate(-0.5, 0.49) == 1.0);
+        assert!(target.evaluate(0.5, 0.0) == 0.0);
+        assert!(target.evaluate(0.0, -0.51) == 0.0);
+    }
+}
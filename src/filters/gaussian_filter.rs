@@ -0,0 +1,61 @@
+use super::Filter;
+
+/// A Gaussian bell curve, truncated at the radius
+///
+/// The curve is shifted down so that it reaches zero at the radius instead of stopping
+/// abruptly. This gives smooth, slightly soft images.
+#[derive(Clone, Copy, Debug)]
+pub struct GaussianFilter {
+    pub radius: f64,
+
+    /// Standard deviation of the curve, in pixels
+    pub standard_deviation: f64,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f64, standard_deviation: f64) -> GaussianFilter {
+        GaussianFilter {
+            radius,
+            standard_deviation,
+        }
+    }
+
+    fn gaussian(&self, offset: f64) -> f64 {
+        (-offset * offset / (2.0 * self.standard_deviation * self.standard_deviation)).exp()
+    }
+
+    fn evaluate_1d(&self, offset: f64) -> f64 {
+        (self.gaussian(offset) - self.gaussian(self.radius)).max(0.0)
+    }
+}
+
+impl Default for GaussianFilter {
+    fn default() -> GaussianFilter {
+        GaussianFilter::new(1.5, 0.5)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weight_is_largest_at_centre_and_zero_at_radius() {
+        let target = GaussianFilter::default();
+        assert!(target.evaluate(0.0, 0.0) > target.evaluate(0.5, 0.0));
+        assert!(target.evaluate(0.5, 0.0) > target.evaluate(0.5, 0.5));
+        assert!(target.evaluate(0.5, 0.5) > 0.0);
+        assert!(target.evaluate(1.5, 0.0) == 0.0);
+        assert!(target.evaluate(0.0, -2.0) == 0.0);
+    }
+}
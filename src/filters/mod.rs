@@ -0,0 +1,29 @@
+use std::fmt::Debug;
+
+pub mod box_filter;
+pub use box_filter::BoxFilter;
+
+pub mod tent_filter;
+pub use tent_filter::TentFilter;
+
+pub mod gaussian_filter;
+pub use gaussian_filter::GaussianFilter;
+
+pub mod mitchell_filter;
+pub use mitchell_filter::MitchellFilter;
+
+/// A reconstruction filter, which decides how much each sample contributes to the pixels
+/// near it
+///
+/// Each sample is added to every pixel whose centre is within `radius()` of it, in both x
+/// and y, weighted by the filter evaluated at the offset from the pixel centre. Offsets are
+/// measured in pixels.
+pub trait Filter: Debug + Send + Sync {
+    /// Half the width of the region in which the filter is non-zero
+    fn radius(&self) -> f64;
+
+    /// The weight of a sample at offset `(x, y)` from the centre of a pixel
+    ///
+    /// This may be negative, but must be zero outside `radius()`.
+    fn evaluate(&self, x: f64, y: f64) -> f64;
+}
@@ -0,0 +1,45 @@
+use super::Filter;
+
+/// Weights fall linearly from one at the centre of the pixel to zero at the radius
+#[derive(Clone, Copy, Debug)]
+pub struct TentFilter {
+    pub radius: f64,
+}
+
+impl TentFilter {
+    pub fn new(radius: f64) -> TentFilter {
+        TentFilter { radius }
+    }
+}
+
+impl Default for TentFilter {
+    fn default() -> TentFilter {
+        TentFilter::new(1.0)
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        let tent = |offset: f64| (1.0 - offset.abs() / self.radius).max(0.0);
+        tent(x) * tent(y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weight_falls_linearly_to_zero_at_radius() {
+        let target = TentFilter::new(2.0);
+        assert!(target.evaluate(0.0, 0.0) == 1.0);
+        assert!(target.evaluate(1.0, 0.0) == 0.5);
+        assert!(target.evaluate(-1.0, 1.0) == 0.25);
+        assert!(target.evaluate(0.0, 2.0) == 0.0);
+        assert!(target.evaluate(3.0, 0.0) == 0.0);
+    }
+}
@@ -1,8 +1,10 @@
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Write};
 use std::path::Path;
+use std::sync::Arc;
 
-use crate::colour::{ColourRgbF, ColourRgbU8, ColourXyz};
+use crate::colour::{ColourRgbF, ColourRgbU8, ColourSpace, ColourXyz, Lut3d, ResponseCurve};
+use crate::error::VanrijnError;
 use crate::util::Array2D;
 
 #[derive(Debug)]
@@ -49,23 +51,122 @@ impl ImageRgbU8 {
         self.data.update_block(start_row, start_column, &image.data);
     }
 
-    pub fn write_png(&self, filename: &Path) -> Result<(), std::io::Error> {
-        let file = File::create(filename)?;
-        let file_buffer = &mut BufWriter::new(file);
+    pub fn write_png(&self, filename: &Path) -> Result<(), VanrijnError> {
+        write_png_file(
+            filename,
+            (self.get_width(), self.get_height()),
+            png::ColorType::RGB,
+            png::BitDepth::Eight,
+            self.get_pixel_data(),
+        )
+        .map_err(VanrijnError::image(filename))
+    }
+}
 
-        let mut encoder = png::Encoder::new(
-            file_buffer,
-            self.get_width() as u32,
-            self.get_height() as u32,
-        );
-        encoder.set_color(png::ColorType::RGB);
-        encoder.set_depth(png::BitDepth::Eight);
-        let mut writer = encoder.write_header()?;
-        writer.write_image_data(self.get_pixel_data())?;
-        Ok(())
+/// A single-channel image with 16 bits per pixel, such as an ID buffer
+#[derive(Debug)]
+pub struct ImageGreyU16 {
+    data: Array2D<u16>,
+}
+
+impl ImageGreyU16 {
+    pub fn new(width: usize, height: usize) -> ImageGreyU16 {
+        ImageGreyU16 {
+            data: Array2D::new(height, width),
+        }
+    }
+
+    pub fn get_value(&self, row: usize, column: usize) -> u16 {
+        self.data[row][column]
+    }
+
+    pub fn set_value(&mut self, row: usize, column: usize, value: u16) {
+        self.data[row][column] = value;
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.data.get_width()
     }
+
+    pub fn get_height(&self) -> usize {
+        self.data.get_height()
+    }
+
+    /// Write the image as a 16-bit greyscale PNG
+    pub fn write_png(&self, filename: &Path) -> Result<(), VanrijnError> {
+        // PNG stores 16-bit samples big-endian
+        let pixel_data: Vec<u8> = self
+            .data
+            .as_slice()
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect();
+        write_png_file(
+            filename,
+            (self.get_width(), self.get_height()),
+            png::ColorType::Grayscale,
+            png::BitDepth::Sixteen,
+            &pixel_data,
+        )
+        .map_err(VanrijnError::image(filename))
+    }
+}
+
+/// Write `pixel_data`, an image of `(width, height)` pixels, to `filename` as a PNG
+fn write_png_file(
+    filename: &Path,
+    (width, height): (usize, usize),
+    color: png::ColorType,
+    depth: png::BitDepth,
+    pixel_data: &[u8],
+) -> Result<(), Error> {
+    let file = File::create(filename)?;
+    let file_buffer = &mut BufWriter::new(file);
+    let mut encoder = png::Encoder::new(file_buffer, width as u32, height as u32);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixel_data)?;
+    Ok(())
+}
+
+/// Read an 8- or 16-bit PNG image, converting each pixel with `convert`
+///
+/// `convert` is given the pixel's 8-bit samples, and the number of channels in the image.
+fn read_png_pixels<F: Fn(&[u8], usize) -> ColourRgbF>(
+    filename: &Path,
+    convert: F,
+) -> Result<ImageRgbF, Error> {
+    let mut decoder = png::Decoder::new(File::open(filename)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer)?;
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Unexpected indexed PNG.",
+            ))
+        }
+    };
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let mut image = ImageRgbF::new(width, height);
+    for row in 0..height {
+        for column in 0..width {
+            let pixel = &buffer[(row * info.line_size + column * channels)..];
+            image.set_colour(row, column, convert(pixel, channels));
+        }
+    }
+    Ok(image)
 }
 
+#[derive(Debug)]
 pub struct ImageRgbF {
     pub data: Array2D<ColourRgbF>,
 }
@@ -100,6 +201,201 @@ impl ImageRgbF {
     pub fn num_channels() -> usize {
         3
     }
+
+    /// Read an 8- or 16-bit sRGB PNG image, converting it to linear RGB
+    pub fn read_png(filename: &Path) -> Result<ImageRgbF, VanrijnError> {
+        read_png_pixels(filename, |pixel, channels| {
+            let (red, green, blue) = if channels < 3 {
+                (pixel[0], pixel[0], pixel[0])
+            } else {
+                (pixel[0], pixel[1], pixel[2])
+            };
+            ColourSpace::Srgb.decode_rgb(&ColourRgbF::new(
+                f64::byte_to_normalized(red),
+                f64::byte_to_normalized(green),
+                f64::byte_to_normalized(blue),
+            ))
+        })
+        .map_err(VanrijnError::image(filename))
+    }
+
+    /// Read the alpha channel of a PNG image as a grey image
+    ///
+    /// Alpha isn't gamma-encoded, so it's used as it is. Images without an alpha channel
+    /// are opaque everywhere.
+    pub fn read_png_alpha(filename: &Path) -> Result<ImageRgbF, VanrijnError> {
+        read_png_pixels(filename, |pixel, channels| {
+            let alpha = match channels {
+                2 => f64::byte_to_normalized(pixel[1]),
+                4 => f64::byte_to_normalized(pixel[3]),
+                _ => 1.0,
+            };
+            ColourRgbF::new(alpha, alpha, alpha)
+        })
+        .map_err(VanrijnError::image(filename))
+    }
+
+    /// Read a Radiance RGBE (.hdr) image
+    pub fn read_hdr(filename: &Path) -> Result<ImageRgbF, VanrijnError> {
+        File::open(filename)
+            .and_then(|file| ImageRgbF::read_hdr_from(BufReader::new(file)))
+            .map_err(VanrijnError::image(filename))
+    }
+
+    /// Read a Radiance RGBE image from `reader`
+    ///
+    /// Both uncompressed and run-length-encoded scanlines are supported, but only the
+    /// standard `-Y height +X width` orientation is.
+    pub fn read_hdr_from<R: BufRead>(mut reader: R) -> Result<ImageRgbF, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("#?") {
+            return Err(invalid("Not a Radiance HDR file."));
+        }
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid("Unexpected end of HDR header."));
+            }
+            let header_line = line.trim();
+            if header_line.is_empty() {
+                break;
+            }
+            if let Some(format) = header_line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(invalid("Unsupported HDR pixel format."));
+                }
+            }
+        }
+        line.clear();
+        reader.read_line(&mut line)?;
+        let (height, width) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (
+                height.parse().map_err(|_| invalid("Invalid HDR height."))?,
+                width.parse().map_err(|_| invalid("Invalid HDR width."))?,
+            ),
+            _ => return Err(invalid("Unsupported HDR resolution string.")),
+        };
+        let mut image = ImageRgbF::new(width, height);
+        let mut scanline = vec![[0u8; 4]; width];
+        for row in 0..height {
+            read_rgbe_scanline(&mut reader, &mut scanline)?;
+            for (column, rgbe) in scanline.iter().enumerate() {
+                image.set_colour(row, column, rgbe_to_colour(rgbe));
+            }
+        }
+        Ok(image)
+    }
+
+    /// Write the image as a Radiance RGBE (.hdr) file, keeping its full range
+    pub fn write_hdr(&self, filename: &Path) -> Result<(), VanrijnError> {
+        File::create(filename)
+            .and_then(|file| self.write_hdr_to(BufWriter::new(file)))
+            .map_err(VanrijnError::image(filename))
+    }
+
+    /// Write the image to `writer` in Radiance RGBE format
+    ///
+    /// Scanlines are written uncompressed, which every reader supports.
+    pub fn write_hdr_to<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        write!(
+            writer,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.get_height(),
+            self.get_width()
+        )?;
+        for row in 0..self.get_height() {
+            for column in 0..self.get_width() {
+                writer.write_all(&colour_to_rgbe(&self.get_colour(row, column)))?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+fn read_rgbe_scanline<R: Read>(reader: &mut R, scanline: &mut [[u8; 4]]) -> Result<(), Error> {
+    let width = scanline.len();
+    let mut first_pixel = [0u8; 4];
+    reader.read_exact(&mut first_pixel)?;
+    let is_run_length_encoded = (8..0x8000).contains(&width)
+        && first_pixel[0] == 2
+        && first_pixel[1] == 2
+        && first_pixel[2] & 0x80 == 0;
+    if !is_run_length_encoded {
+        scanline[0] = first_pixel;
+        for pixel in scanline.iter_mut().skip(1) {
+            reader.read_exact(pixel)?;
+        }
+        return Ok(());
+    }
+    if ((first_pixel[2] as usize) << 8 | first_pixel[3] as usize) != width {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "HDR scanline width mismatch.",
+        ));
+    }
+    // Each of the four channels is run-length encoded separately
+    for channel in 0..4 {
+        let mut column = 0;
+        while column < width {
+            let mut count = [0u8; 1];
+            reader.read_exact(&mut count)?;
+            let (count, is_run) = if count[0] > 128 {
+                (count[0] as usize - 128, true)
+            } else {
+                (count[0] as usize, false)
+            };
+            if count == 0 || column + count > width {
+                return Err(Error::new(ErrorKind::InvalidData, "Bad HDR scanline data."));
+            }
+            if is_run {
+                let mut value = [0u8; 1];
+                reader.read_exact(&mut value)?;
+                for pixel in &mut scanline[column..column + count] {
+                    pixel[channel] = value[0];
+                }
+            } else {
+                let mut values = vec![0u8; count];
+                reader.read_exact(&mut values)?;
+                for (pixel, value) in scanline[column..column + count].iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+            }
+            column += count;
+        }
+    }
+    Ok(())
+}
+
+fn rgbe_to_colour(rgbe: &[u8; 4]) -> ColourRgbF {
+    if rgbe[3] == 0 {
+        ColourRgbF::new(0.0, 0.0, 0.0)
+    } else {
+        let scale = 2.0f64.powi(rgbe[3] as i32 - (128 + 8));
+        ColourRgbF::new(
+            rgbe[0] as f64 * scale,
+            rgbe[1] as f64 * scale,
+            rgbe[2] as f64 * scale,
+        )
+    }
+}
+
+fn colour_to_rgbe(colour: &ColourRgbF) -> [u8; 4] {
+    let max = colour.red().max(colour.green()).max(colour.blue());
+    if !max.is_finite() || max <= 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // The exponent that puts the largest channel in [128, 256)
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 2.0f64.powi(8 - exponent);
+    let byte = |value: f64| (value.max(0.0) * scale).min(255.0) as u8;
+    [
+        byte(colour.red()),
+        byte(colour.green()),
+        byte(colour.blue()),
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
 }
 
 pub trait NormalizedAsByte {
@@ -131,33 +427,65 @@ pub trait ToneMapper<SourceType> {
     fn apply_tone_mapping(&self, image_in: &Array2D<SourceType>, image_out: &mut ImageRgbU8);
 }
 
+/// Clamps each channel to the range zero to one, after converting to the output colour
+/// space and applying the camera's response curve, which is usually the space's transfer
+/// function, and then the look LUT if there is one
 #[derive(Default)]
-pub struct ClampingToneMapper {}
+pub struct ClampingToneMapper {
+    /// The colour space of the image being written, usually sRGB
+    pub colour_space: ColourSpace,
+
+    /// How the light in each channel becomes the value stored for it
+    pub response: ResponseCurve,
+
+    /// A 3D LUT from a colour pipeline, which is applied to the stored values in the same
+    /// way as to any other image in the colour space
+    pub look: Option<Arc<Lut3d>>,
+}
 
 impl ClampingToneMapper {
+    /// Values are rounded to the nearest byte, since transfer functions don't quite map one
+    /// to one
     fn clamp(v: &f64) -> u8 {
-        v.clamp(0.0, 1.0).normalized_to_byte()
+        (v.clamp(0.0, 1.0) * (u8::MAX as f64)).round() as u8
+    }
+
+    /// Encode `linear`, which is in the output colour space, and write it to `image_out`
+    fn set_colour(
+        &self,
+        image_out: &mut ImageRgbU8,
+        row: usize,
+        column: usize,
+        linear: &ColourRgbF,
+    ) {
+        let mut colour = self.response.apply_rgb(self.colour_space, linear);
+        if let Some(look) = &self.look {
+            colour = look.apply(&colour);
+        }
+        image_out.set_colour(
+            row,
+            column,
+            ColourRgbU8 {
+                values: [
+                    Self::clamp(&colour.red()),
+                    Self::clamp(&colour.green()),
+                    Self::clamp(&colour.blue()),
+                ],
+            },
+        );
     }
 }
 
+/// The input is in linear sRGB
 impl ToneMapper<ColourRgbF> for ClampingToneMapper {
     fn apply_tone_mapping(&self, image_in: &Array2D<ColourRgbF>, image_out: &mut ImageRgbU8) {
         assert!(image_in.get_width() == image_out.get_width());
         assert!(image_in.get_height() == image_out.get_height());
+        let transform = self.colour_space.from_xyz_matrix() * ColourSpace::Srgb.to_xyz_matrix();
         for column in 0..image_in.get_width() {
             for row in 0..image_in.get_height() {
-                let colour = image_in[row][column];
-                image_out.set_colour(
-                    row,
-                    column,
-                    ColourRgbU8 {
-                        values: [
-                            Self::clamp(&colour.red()),
-                            Self::clamp(&colour.green()),
-                            Self::clamp(&colour.blue()),
-                        ],
-                    },
-                );
+                let colour = ColourRgbF::from_vec3(&(transform * image_in[row][column].values));
+                self.set_colour(image_out, row, column, &colour);
             }
         }
     }
@@ -167,29 +495,154 @@ impl ToneMapper<ColourXyz> for ClampingToneMapper {
     fn apply_tone_mapping(&self, image_in: &Array2D<ColourXyz>, image_out: &mut ImageRgbU8) {
         assert!(image_in.get_width() == image_out.get_width());
         assert!(image_in.get_height() == image_out.get_height());
+        let transform = self.colour_space.from_xyz_matrix();
         for column in 0..image_in.get_width() {
             for row in 0..image_in.get_height() {
-                let colour = image_in[row][column].to_srgb();
-                image_out.set_colour(
-                    row,
-                    column,
-                    ColourRgbU8 {
-                        values: [
-                            Self::clamp(&colour.red()),
-                            Self::clamp(&colour.green()),
-                            Self::clamp(&colour.blue()),
-                        ],
-                    },
-                );
+                let colour = ColourRgbF::from_vec3(&(transform * image_in[row][column].values));
+                self.set_colour(image_out, row, column, &colour);
             }
         }
     }
 }
 
+/// How much to brighten or darken an image before tone mapping it
+///
+/// Exposure is in stops, so each step of one doubles the brightness. With auto-exposure,
+/// the image is first scaled so that its log-average luminance, which is roughly the
+/// brightness that the eye adapts to, becomes middle grey, and `ev` then adjusts from
+/// there.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Exposure {
+    pub ev: f64,
+    pub auto_exposure: bool,
+}
+
+impl Exposure {
+    /// The luminance that auto-exposure brings the log-average luminance to
+    pub const MIDDLE_GREY: f64 = 0.18;
+
+    /// The geometric mean luminance of `image`
+    ///
+    /// Black pixels would make this zero, so a tiny luminance is added to every pixel.
+    pub fn log_average_luminance(image: &Array2D<ColourXyz>) -> f64 {
+        let pixels = image.as_slice();
+        if pixels.is_empty() {
+            return 0.0;
+        }
+        let total: f64 = pixels
+            .iter()
+            .map(|colour| (colour.y().max(0.0) + 0.000_001).ln())
+            .sum();
+        (total / pixels.len() as f64).exp()
+    }
+
+    /// The factor to multiply the colours of `image` by
+    pub fn scale(&self, image: &Array2D<ColourXyz>) -> f64 {
+        let auto_scale = if self.auto_exposure {
+            Exposure::MIDDLE_GREY / Exposure::log_average_luminance(image)
+        } else {
+            1.0
+        };
+        auto_scale * 2.0f64.powf(self.ev)
+    }
+}
+
+/// Applies an [Exposure](Exposure) to an image, then another tone mapper
+#[derive(Default)]
+pub struct ExposedToneMapper<T> {
+    pub exposure: Exposure,
+    pub tone_mapper: T,
+}
+
+impl<T: ToneMapper<ColourXyz>> ToneMapper<ColourXyz> for ExposedToneMapper<T> {
+    fn apply_tone_mapping(&self, image_in: &Array2D<ColourXyz>, image_out: &mut ImageRgbU8) {
+        let scale = self.exposure.scale(image_in);
+        let mut exposed = image_in.clone();
+        for row in 0..exposed.get_height() {
+            for colour in exposed[row].iter_mut() {
+                colour.values *= scale;
+            }
+        }
+        self.tone_mapper.apply_tone_mapping(&exposed, image_out);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    mod read_hdr {
+        use super::*;
+
+        use crate::math::Vec3;
+
+        fn header(width: usize, height: usize) -> Vec<u8> {
+            format!(
+                "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\nEXPOSURE=1.0\n\n-Y {} +X {}\n",
+                height, width
+            )
+            .into_bytes()
+        }
+
+        #[test]
+        fn reads_uncompressed_pixels() {
+            let mut data = header(2, 1);
+            data.extend_from_slice(&[128, 64, 0, 129, 0, 0, 0, 0]);
+            let image = ImageRgbF::read_hdr_from(&data[..]).unwrap();
+            assert!(image.get_width() == 2);
+            assert!(image.get_height() == 1);
+            assert!(image.get_colour(0, 0).values == Vec3::new(1.0, 0.5, 0.0));
+            assert!(image.get_colour(0, 1).values == Vec3::new(0.0, 0.0, 0.0));
+        }
+
+        #[test]
+        fn reads_run_length_encoded_scanlines() {
+            let mut data = header(8, 2);
+            for _ in 0..2 {
+                data.extend_from_slice(&[2, 2, 0, 8]);
+                // Red: a run of eight 128s
+                data.extend_from_slice(&[128 + 8, 128]);
+                // Green: eight literal values
+                data.extend_from_slice(&[8, 0, 16, 32, 48, 64, 80, 96, 112]);
+                // Blue: two runs of four
+                data.extend_from_slice(&[128 + 4, 0, 128 + 4, 64]);
+                // Exponent
+                data.extend_from_slice(&[128 + 8, 128]);
+            }
+            let image = ImageRgbF::read_hdr_from(&data[..]).unwrap();
+            assert!(image.get_colour(1, 0).values == Vec3::new(0.5, 0.0, 0.0));
+            assert!(image.get_colour(1, 7).values == Vec3::new(0.5, 0.4375, 0.25));
+        }
+
+        #[test]
+        fn rejects_non_hdr_data() {
+            assert!(ImageRgbF::read_hdr_from(&b"P6\n2 2\n255\n"[..]).is_err());
+        }
+
+        #[test]
+        fn reads_what_write_hdr_writes() {
+            let mut target = ImageRgbF::new(3, 2);
+            target.set_colour(0, 1, ColourRgbF::new(1.0, 0.5, 0.25));
+            target.set_colour(1, 2, ColourRgbF::new(1000.0, 3.0, 0.0));
+            let mut data = vec![];
+            target.write_hdr_to(&mut data).unwrap();
+            let image = ImageRgbF::read_hdr_from(&data[..]).unwrap();
+            assert!(image.get_width() == 3 && image.get_height() == 2);
+            assert!(image.get_colour(0, 1).values == Vec3::new(1.0, 0.5, 0.25));
+            assert!(image.get_colour(0, 0).values == Vec3::new(0.0, 0.0, 0.0));
+            let bright = image.get_colour(1, 2);
+            assert!((bright.red() - 1000.0).abs() < 1000.0 / 128.0);
+            assert!((bright.green() - 3.0).abs() < 1000.0 / 128.0);
+        }
+
+        #[test]
+        fn rejects_truncated_data() {
+            let mut data = header(2, 2);
+            data.extend_from_slice(&[128, 64, 0, 129]);
+            assert!(ImageRgbF::read_hdr_from(&data[..]).is_err());
+        }
+    }
+
     #[test]
     fn get_pixel_data_returns_correct_values() {
         let mut target = ImageRgbU8::new(4, 3);
@@ -214,6 +667,29 @@ mod tests {
         }
     }
 
+    mod image_grey_u16 {
+        use super::*;
+
+        #[test]
+        fn png_keeps_all_sixteen_bits() {
+            let mut target = ImageGreyU16::new(3, 2);
+            target.set_value(0, 1, 0x1234);
+            target.set_value(1, 2, 0xffff);
+            let filename = std::env::temp_dir()
+                .join(format!("vanrijn_grey_u16_test_{}.png", std::process::id()));
+            target.write_png(&filename).unwrap();
+            let mut decoder = png::Decoder::new(File::open(&filename).unwrap());
+            decoder.set_transformations(png::Transformations::IDENTITY);
+            let (info, mut reader) = decoder.read_info().unwrap();
+            let mut buffer = vec![0; info.buffer_size()];
+            reader.next_frame(&mut buffer).unwrap();
+            std::fs::remove_file(&filename).unwrap();
+            assert!(info.color_type == png::ColorType::Grayscale);
+            assert!(info.bit_depth == png::BitDepth::Sixteen);
+            assert!(buffer == vec![0, 0, 0x12, 0x34, 0, 0, 0, 0, 0, 0, 0xff, 0xff]);
+        }
+    }
+
     mod normalized_as_byte {
         use super::*;
 
@@ -278,12 +754,63 @@ mod tests {
         }
     }
 
+    mod exposure {
+        use super::*;
+
+        fn grey_image(luminances: &[f64]) -> Array2D<ColourXyz> {
+            let mut image = Array2D::new(1, luminances.len());
+            for (column, &luminance) in luminances.iter().enumerate() {
+                image[0][column] =
+                    ColourXyz::from_linear_rgb(&ColourRgbF::new(luminance, luminance, luminance));
+            }
+            image
+        }
+
+        #[test]
+        fn each_stop_doubles_brightness() {
+            let image = grey_image(&[0.5]);
+            for &ev in [-2.0, 0.0, 1.0, 3.0].iter() {
+                let exposure = Exposure {
+                    ev,
+                    auto_exposure: false,
+                };
+                assert!((exposure.scale(&image) - 2.0f64.powf(ev)).abs() < 0.000_000_001);
+            }
+        }
+
+        #[test]
+        fn auto_exposure_brings_image_to_middle_grey() {
+            let image = grey_image(&[0.02, 0.08, 2.0, 8.0]);
+            // The geometric mean of the luminances is 0.4
+            assert!((Exposure::log_average_luminance(&image) - 0.4).abs() < 0.001);
+            let exposure = Exposure {
+                ev: 1.0,
+                auto_exposure: true,
+            };
+            assert!((exposure.scale(&image) - 2.0 * 0.18 / 0.4).abs() < 0.001);
+        }
+
+        #[test]
+        fn exposed_tone_mapper_scales_before_mapping() {
+            let target = ExposedToneMapper {
+                exposure: Exposure {
+                    ev: 2.0,
+                    auto_exposure: false,
+                },
+                tone_mapper: ClampingToneMapper::default(),
+            };
+            let mut image_out = ImageRgbU8::new(1, 1);
+            target.apply_tone_mapping(&grey_image(&[0.25]), &mut image_out);
+            assert!(image_out.get_colour(0, 0).values == [0xff, 0xff, 0xff]);
+        }
+    }
+
     mod clamping_tone_mapper {
         use super::*;
 
         #[test]
         fn black_colourrgb_becomes_black_colourrgb24() {
-            let target = ClampingToneMapper {};
+            let target = ClampingToneMapper::default();
             let mut image_in = ImageRgbF::new(1, 1);
             let mut image_out = ImageRgbU8::new(1, 1);
             image_in.set_colour(0, 0, ColourRgbF::new(0.0, 0.0, 0.0));
@@ -293,7 +820,7 @@ mod tests {
 
         #[test]
         fn white_colourrgb_becomes_white_colourrgb24() {
-            let target = ClampingToneMapper {};
+            let target = ClampingToneMapper::default();
             let mut image_in = ImageRgbF::new(1, 1);
             let mut image_out = ImageRgbU8::new(1, 1);
             image_in.set_colour(0, 0, ColourRgbF::new(1.0, 1.0, 1.0));
@@ -303,7 +830,7 @@ mod tests {
 
         #[test]
         fn supersaturated_white_colourrgb_becomes_white_colourrgb24() {
-            let target = ClampingToneMapper {};
+            let target = ClampingToneMapper::default();
             let mut image_in = ImageRgbF::new(1, 1);
             let mut image_out = ImageRgbU8::new(1, 1);
             image_in.set_colour(0, 0, ColourRgbF::new(2.0, 2.0, 2.0));
@@ -313,7 +840,7 @@ mod tests {
 
         #[test]
         fn supersaturated_green_colourrgb_becomes_green_colourrgb24() {
-            let target = ClampingToneMapper {};
+            let target = ClampingToneMapper::default();
             let mut image_in = ImageRgbF::new(1, 1);
             let mut image_out = ImageRgbU8::new(1, 1);
             image_in.set_colour(0, 0, ColourRgbF::new(0.0, 2.0, 0.0));
@@ -323,12 +850,62 @@ mod tests {
 
         #[test]
         fn dark_red_colourrgb_becomes_dark_red_colourrgb24() {
-            let target = ClampingToneMapper {};
+            let target = ClampingToneMapper::default();
             let mut image_in = ImageRgbF::new(1, 1);
             let mut image_out = ImageRgbU8::new(1, 1);
             image_in.set_colour(0, 0, ColourRgbF::new(0.5, 0.0, 0.0));
             target.apply_tone_mapping(&image_in.data, &mut image_out);
-            assert!(image_out.get_colour(0, 0).values == [0x7f, 0x0, 0x0]);
+            // Linear 0.5 is gamma-encoded to about 0.735 in sRGB
+            assert!(image_out.get_colour(0, 0).values == [0xbc, 0x0, 0x0]);
+        }
+
+        #[test]
+        fn linear_colour_space_is_not_encoded() {
+            let target = ClampingToneMapper {
+                colour_space: ColourSpace::AcesCg,
+                ..Default::default()
+            };
+            let mut image_in = ImageRgbF::new(1, 1);
+            let mut image_out = ImageRgbU8::new(1, 1);
+            image_in.set_colour(0, 0, ColourRgbF::new(0.25, 0.25, 0.25));
+            target.apply_tone_mapping(&image_in.data, &mut image_out);
+            assert!(image_out.get_colour(0, 0).values == [0x40, 0x40, 0x40]);
+        }
+
+        #[test]
+        fn aces_response_keeps_highlights_apart() {
+            let target = ClampingToneMapper {
+                response: ResponseCurve::Aces,
+                ..Default::default()
+            };
+            let mut image_in = ImageRgbF::new(2, 1);
+            let mut image_out = ImageRgbU8::new(2, 1);
+            image_in.set_colour(0, 0, ColourRgbF::new(2.0, 2.0, 2.0));
+            image_in.set_colour(0, 1, ColourRgbF::new(4.0, 4.0, 4.0));
+            target.apply_tone_mapping(&image_in.data, &mut image_out);
+            let (bright, brighter) = (image_out.get_colour(0, 0), image_out.get_colour(0, 1));
+            assert!(bright.values[0] < brighter.values[0]);
+            assert!(brighter.values[0] < 0xff);
+        }
+
+        #[test]
+        fn look_is_applied_to_encoded_colour() {
+            // Inverts each channel
+            let lut = "LUT_3D_SIZE 2\n\
+                       1 1 1\n0 1 1\n1 0 1\n0 0 1\n\
+                       1 1 0\n0 1 0\n1 0 0\n0 0 0\n";
+            let target = ClampingToneMapper {
+                look: Some(Arc::new(Lut3d::read_cube_from(lut.as_bytes()).unwrap())),
+                ..Default::default()
+            };
+            let mut image_in = ImageRgbF::new(2, 1);
+            let mut image_out = ImageRgbU8::new(2, 1);
+            image_in.set_colour(0, 0, ColourRgbF::new(0.0, 1.0, 0.0));
+            image_in.set_colour(0, 1, ColourRgbF::new(0.5, 0.5, 0.5));
+            target.apply_tone_mapping(&image_in.data, &mut image_out);
+            assert!(image_out.get_colour(0, 0).values == [0xff, 0x0, 0xff]);
+            // Linear 0.5 is encoded as 0xbc, which becomes 0xff - 0xbc
+            assert!(image_out.get_colour(0, 1).values == [0x43, 0x43, 0x43]);
         }
     }
 }
@@ -0,0 +1,188 @@
+use crate::colour::{
+    ColourRgbF, ColourXyz, PhotonPacket, Spectrum, LONGEST_VISIBLE_WAVELENGTH,
+    SHORTEST_VISIBLE_WAVELENGTH,
+};
+use crate::raycasting::{IntersectionInfo, Ray};
+use crate::sampler::Sampler;
+use crate::statistics::thread_statistics;
+
+use super::Integrator;
+
+use rand::RngCore;
+
+/// What a [DebugIntegrator](DebugIntegrator) shows at each pixel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugView {
+    /// The shading normal, with x, y and z mapped from -1..1 to 0..1 as red, green and blue
+    Normals,
+
+    /// The distance to the nearest surface, from white at the camera to black at
+    /// `max_distance`
+    Depth { max_distance: f64 },
+
+    /// The surface coordinates, with u as red and v as green, repeating every unit
+    Uv,
+
+    /// The number of BVH nodes visited and triangles tested in finding the nearest surface,
+    /// as a heat map from blue at zero to red at `max_cost`
+    ///
+    /// The work is only counted when the crate is built with the `statistics` feature, so
+    /// without it every pixel is blue.
+    TraversalCost { max_cost: f64 },
+}
+
+/// Shows properties of the scene in false colour instead of rendering it, for debugging
+/// scenes and the renderer
+///
+/// The colours are emitted as light, so they go through the usual tile pipeline and
+/// reconstruction filter, and are averaged over each pixel's samples. Pixels that don't see
+/// any surface are black.
+pub struct DebugIntegrator {
+    pub view: DebugView,
+}
+
+/// Blue at zero, through green, to red at one
+fn heat_map(value: f64) -> ColourRgbF {
+    let t = value.clamp(0.0, 1.0);
+    if t < 0.5 {
+        ColourRgbF::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+    } else {
+        ColourRgbF::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+    }
+}
+
+/// Emit `colour` at the wavelengths of `packet`, scaled so that white has a luminance of
+/// one
+fn false_colour(colour: &ColourRgbF, packet: &PhotonPacket) -> PhotonPacket {
+    let spectrum = Spectrum::reflection_from_linear_rgb(colour);
+    let white_luminance = ColourXyz::from_spectrum(&Spectrum::grey(1.0)).y()
+        * (LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH);
+    packet.map(|photon| {
+        photon.set_intensity(spectrum.intensity_at_wavelength(photon.wavelength) / white_luminance)
+    })
+}
+
+impl DebugIntegrator {
+    fn surface_colour(&self, info: &IntersectionInfo) -> ColourRgbF {
+        match self.view {
+            DebugView::Normals => ColourRgbF::new(
+                0.5 * (info.normal.x() + 1.0),
+                0.5 * (info.normal.y() + 1.0),
+                0.5 * (info.normal.z() + 1.0),
+            ),
+            DebugView::Depth { max_distance } => {
+                let brightness = (1.0 - info.distance / max_distance).max(0.0);
+                ColourRgbF::new(brightness, brightness, brightness)
+            }
+            DebugView::Uv => ColourRgbF::new(
+                info.uv.x().rem_euclid(1.0),
+                info.uv.y().rem_euclid(1.0),
+                0.0,
+            ),
+            // The cost depends on the ray, which only integrate_hit() sees
+            DebugView::TraversalCost { .. } => heat_map(0.0),
+        }
+    }
+}
+
+impl Integrator for DebugIntegrator {
+    fn integrate_hit(
+        &self,
+        sampler: &dyn Sampler,
+        ray: &Ray,
+        hit: Option<IntersectionInfo>,
+        packet: &PhotonPacket,
+        _recursion_limit: u16,
+        _rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
+        match (self.view, hit) {
+            (DebugView::TraversalCost { max_cost }, _) => {
+                // The hit was found along with others in a packet, so the ray is traced
+                // again on its own to count just its share of the work
+                let before = thread_statistics();
+                sampler.sample(ray);
+                let after = thread_statistics();
+                let cost = (after.bvh_nodes_visited - before.bvh_nodes_visited)
+                    + (after.triangle_tests - before.triangle_tests);
+                false_colour(&heat_map(cost as f64 / max_cost), packet)
+            }
+            (_, Some(info)) => false_colour(&self.surface_colour(&info), packet),
+            (_, None) => packet.set_intensity(0.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+
+    use crate::materials::LambertianMaterial;
+    use crate::math::Vec3;
+    use crate::raycasting::{Plane, Primitive};
+    use crate::sampler::SceneSampler;
+    use crate::scene::Scene;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use std::sync::Arc;
+
+    fn floor_scene() -> Scene {
+        Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            Lens::Pinhole,
+        )))
+        .with_object(Box::new(vec![Box::new(Plane::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            0.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        )) as Box<dyn Primitive>]))
+        .build()
+    }
+
+    /// The colour seen looking down at the floor from one unit above it
+    fn colour_of_floor(view: DebugView) -> ColourXyz {
+        let scene = floor_scene();
+        let sampler = SceneSampler { scene: &scene };
+        let target = DebugIntegrator { view };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut colour = ColourXyz::default();
+        let samples = 1000;
+        for _ in 0..samples {
+            let packet = PhotonPacket::random_wavelengths(&mut rng);
+            let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+            let result = target.integrate_ray(&sampler, &ray, &packet, 1, &mut rng);
+            for photon in result.photons() {
+                let photon =
+                    photon.scale_intensity(1.0 / PhotonPacket::wavelength_pdf(photon.wavelength));
+                colour.values += ColourXyz::from_photon(&photon).values
+                    * (1.0 / (samples * result.photons().len()) as f64);
+            }
+        }
+        colour
+    }
+
+    #[test]
+    fn upward_normal_is_green() {
+        let rgb = colour_of_floor(DebugView::Normals).to_linear_rgb();
+        assert!(rgb.green() > 0.9);
+        assert!(rgb.red() < rgb.green() && rgb.blue() < rgb.green());
+    }
+
+    #[test]
+    fn depth_darkens_with_distance() {
+        let near = colour_of_floor(DebugView::Depth { max_distance: 2.0 });
+        let far = colour_of_floor(DebugView::Depth { max_distance: 1.0 });
+        assert!((near.y() - 0.5).abs() < 0.05);
+        assert!(far.y().abs() < 0.001);
+    }
+
+    #[test]
+    fn heat_map_runs_from_blue_to_red() {
+        assert!(heat_map(0.0).blue() == 1.0);
+        assert!(heat_map(0.5).green() == 1.0);
+        assert!(heat_map(2.0).red() == 1.0);
+    }
+}
@@ -0,0 +1,218 @@
+//! Furnace tests, which check that materials neither create nor destroy energy
+//!
+//! A sphere lit by the same radiance from every direction, and seen against it, reflects
+//! no more light than arrives if its material conserves energy, and the same amount if it
+//! doesn't absorb any; a white material on a convex object should disappear into the
+//! background. Materials that gain energy show up brighter than the environment, and ones
+//! that lose it where they shouldn't, darker.
+
+use crate::camera::{Lens, PerspectiveCamera};
+use crate::colour::{PhotonPacket, Spectrum};
+use crate::lights::EnvironmentLight;
+use crate::materials::{
+    ClearCoat, Conductor, HairMaterial, LambertianMaterial, Material, MixMaterial, PhongMaterial,
+    ReflectiveMaterial, RoughConductor, SmoothTransparentDialectric, TwoSided,
+};
+use crate::math::Vec3;
+use crate::random_distributions::{RandomDistribution, UniformSphere};
+use crate::raycasting::{Primitive, Ray, Sphere};
+use crate::sampler::SceneSampler;
+use crate::scene::Scene;
+
+use super::{Integrator, PolarizedIntegrator, SimpleRandomIntegrator, WavefrontIntegrator};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use std::sync::Arc;
+
+/// Radiance of one from every direction, at every wavelength
+struct UniformEnvironment {
+    distribution: UniformSphere,
+}
+
+impl EnvironmentLight for UniformEnvironment {
+    fn radiance(&self, _direction: &Vec3, _wavelength: f64) -> f64 {
+        1.0
+    }
+
+    fn direction_distribution(&self) -> &dyn RandomDistribution<Vec3> {
+        &self.distribution
+    }
+}
+
+fn furnace(material: Arc<dyn Material>) -> Scene {
+    Scene::builder(Box::new(PerspectiveCamera::new(
+        Vec3::new(0.0, 0.0, -3.0),
+        Lens::Pinhole,
+    )))
+    .with_object(Box::new(vec![
+        Box::new(Sphere::new(Vec3::zeros(), 1.0, material)) as Box<dyn Primitive>,
+    ]))
+    .with_environment(Box::new(UniformEnvironment {
+        distribution: UniformSphere::new(),
+    }))
+    .build()
+}
+
+/// The average radiance that `integrator` finds reflected from a sphere of `material` in
+/// the furnace, over rays spread across the sphere so every angle of incidence is tested
+fn reflected_radiance(integrator: &dyn Integrator, material: Arc<dyn Material>) -> f64 {
+    const RAYS: usize = 200;
+    const SAMPLES_PER_RAY: usize = 50;
+    let scene = furnace(material);
+    let sampler = SceneSampler { scene: &scene };
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut total = 0.0;
+    let mut count = 0;
+    for i in 0..RAYS {
+        // Up to just inside the sphere's silhouette
+        let offset = 0.99 * (i as f64 + 0.5) / RAYS as f64;
+        let ray = Ray::new(Vec3::new(offset, 0.0, -3.0), Vec3::unit_z());
+        for _ in 0..SAMPLES_PER_RAY {
+            let packet = PhotonPacket::random_wavelengths(&mut rng);
+            let result = integrator.integrate_ray(&sampler, &ray, &packet, 64, &mut rng);
+            for photon in result.photons() {
+                total += photon.intensity;
+                count += 1;
+            }
+        }
+    }
+    total / count as f64
+}
+
+fn white_lambertian() -> LambertianMaterial {
+    LambertianMaterial {
+        colour: Spectrum::grey(1.0),
+        diffuse_strength: 1.0,
+    }
+}
+
+/// Materials that absorb nothing, so should reflect exactly what arrives
+fn lossless_materials() -> Vec<(&'static str, Arc<dyn Material>)> {
+    vec![
+        ("lambertian", Arc::new(white_lambertian())),
+        ("two-sided", Arc::new(TwoSided::new(white_lambertian()))),
+        (
+            "dielectric",
+            Arc::new(SmoothTransparentDialectric::new(Spectrum::grey(1.5))),
+        ),
+        (
+            "mirror",
+            Arc::new(ReflectiveMaterial {
+                colour: Spectrum::grey(1.0),
+                diffuse_strength: 0.0,
+                reflection_strength: 1.0,
+            }),
+        ),
+        (
+            "mix",
+            Arc::new(MixMaterial::new(
+                white_lambertian(),
+                TwoSided::new(white_lambertian()),
+                Spectrum::grey(0.5),
+            )),
+        ),
+        (
+            "clear coat",
+            Arc::new(ClearCoat::new(white_lambertian(), 1.5)),
+        ),
+    ]
+}
+
+/// Materials that may absorb some light, but should never reflect more than arrives
+fn lossy_materials() -> Vec<(&'static str, Arc<dyn Material>)> {
+    vec![
+        ("gold", Arc::new(Conductor::gold())),
+        (
+            "brushed aluminium",
+            Arc::new(RoughConductor::new(Conductor::aluminium(), 0.05, 0.4)),
+        ),
+        (
+            "hair",
+            Arc::new(HairMaterial {
+                colour: Spectrum::grey(1.0),
+                diffuse_strength: 0.5,
+                specular_strength: 0.5,
+                smoothness: 20.0,
+            }),
+        ),
+    ]
+}
+
+/// Phong materials whose diffuse and specular strengths add up to one
+fn phong_materials() -> Vec<(&'static str, Arc<dyn Material>)> {
+    [(0.5, 20.0), (0.0, 20.0), (0.0, 1.0)]
+        .iter()
+        .map(|&(diffuse_strength, smoothness)| {
+            let material: Arc<dyn Material> = Arc::new(PhongMaterial {
+                colour: Spectrum::grey(1.0),
+                diffuse_strength,
+                specular_strength: 1.0 - diffuse_strength,
+                smoothness,
+            });
+            ("phong", material)
+        })
+        .collect()
+}
+
+fn integrators() -> [(&'static str, Box<dyn Integrator>); 3] {
+    [
+        ("simple random", Box::new(SimpleRandomIntegrator {})),
+        ("wavefront", Box::new(WavefrontIntegrator {})),
+        ("polarized", Box::new(PolarizedIntegrator { filter: None })),
+    ]
+}
+
+/// Allows for the noise in [reflected_radiance()](reflected_radiance)
+const TOLERANCE: f64 = 0.02;
+
+fn check_gains_no_energy(materials: Vec<(&'static str, Arc<dyn Material>)>) {
+    for (name, material) in materials {
+        for (integrator_name, integrator) in integrators().iter() {
+            let radiance = reflected_radiance(integrator.as_ref(), Arc::clone(&material));
+            assert!(
+                radiance <= 1.0 + TOLERANCE,
+                "{} reflects {} of the light arriving with the {} integrator",
+                name,
+                radiance,
+                integrator_name
+            );
+        }
+    }
+}
+
+#[test]
+fn materials_reflect_no_more_light_than_arrives() {
+    check_gains_no_energy(
+        lossless_materials()
+            .into_iter()
+            .chain(lossy_materials())
+            .collect(),
+    );
+}
+
+#[test]
+fn phong_materials_reflect_no_more_light_than_arrives() {
+    check_gains_no_energy(phong_materials());
+}
+
+fn check_loses_no_energy(materials: Vec<(&'static str, Arc<dyn Material>)>) {
+    for (name, material) in materials {
+        for (integrator_name, integrator) in integrators().iter() {
+            let radiance = reflected_radiance(integrator.as_ref(), Arc::clone(&material));
+            assert!(
+                (radiance - 1.0).abs() <= TOLERANCE,
+                "{} reflects {} of the light arriving with the {} integrator",
+                name,
+                radiance,
+                integrator_name
+            );
+        }
+    }
+}
+
+#[test]
+fn lossless_materials_reflect_all_light_that_arrives() {
+    check_loses_no_energy(lossless_materials());
+}
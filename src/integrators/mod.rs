@@ -1,19 +1,231 @@
-use super::colour::Photon;
-use super::raycasting::IntersectionInfo;
+use super::colour::PhotonPacket;
+use super::math::OrthonormalBasis;
+use super::raycasting::{IntersectionInfo, Ray, RAY_PACKET_WIDTH};
 use super::sampler::Sampler;
 
+use rand::rngs::StdRng;
+use rand::RngCore;
+
 mod whitted_integrator;
 pub use whitted_integrator::*;
 
+mod debug_integrator;
+pub use debug_integrator::*;
+
 mod simple_random_integrator;
 pub use simple_random_integrator::*;
 
-pub trait Integrator {
-    fn integrate(
+mod wavefront_integrator;
+pub use wavefront_integrator::*;
+
+mod polarized_integrator;
+pub use polarized_integrator::*;
+
+#[cfg(test)]
+mod furnace_tests;
+
+/// The frame that converts world-space directions to and from BSDF space at `info`
+///
+/// BSDF space has the tangent along x, the cotangent along y and the normal along z.
+/// Normally this is the geometric normal, so directions on the back of the surface have
+/// negative z and the material can tell which face was hit. For
+/// [two-sided](crate::materials::Material::is_two_sided) materials the frame is instead
+/// rotated half a turn about the tangent whenever `ray.direction.dot(normal) > 0`, so
+/// that the normal always faces the incoming ray and both faces look the same.
+fn bsdf_frame(info: &IntersectionInfo) -> OrthonormalBasis {
+    let frame = OrthonormalBasis::new(info.tangent, info.cotangent, info.normal);
+    if info.material.is_two_sided() && info.retro.dot(&info.normal) < 0.0 {
+        frame.flipped()
+    } else {
+        frame
+    }
+}
+
+/// Weight for combining a sample taken with density `pdf` with one taken from another
+/// distribution, which would have chosen the same direction with density `other_pdf`
+///
+/// This is Veach's power heuristic, with an exponent of two.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// Computes the light arriving along a ray
+///
+/// Light is computed for every wavelength in a [PhotonPacket](PhotonPacket) at once, and
+/// the result has the same wavelengths. Any random sampling uses `rng`, so the result
+/// depends only on the generator state.
+///
+/// Integrators decide for themselves what a ray that misses the scene sees, so camera rays
+/// and the rays followed after a bounce can be treated the same way.
+pub trait Integrator: Send + Sync {
+    /// The light arriving at the origin of `ray`, travelling back along it
+    ///
+    /// This finds the nearest intersection and passes it to
+    /// [integrate_hit()](Integrator::integrate_hit).
+    fn integrate_ray(
+        &self,
+        sampler: &dyn Sampler,
+        ray: &Ray,
+        packet: &PhotonPacket,
+        recursion_limit: u16,
+        rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
+        let hit = sampler.sample(ray);
+        self.integrate_hit(sampler, ray, hit, packet, recursion_limit, rng)
+    }
+
+    /// The light arriving at the origin of `ray`, travelling back along it, where `hit` is
+    /// its nearest intersection with the scene, or `None` if it misses
+    ///
+    /// This lets the intersections for several rays be found together, with
+    /// [sample_packet()](Sampler::sample_packet).
+    fn integrate_hit(
+        &self,
+        sampler: &dyn Sampler,
+        ray: &Ray,
+        hit: Option<IntersectionInfo>,
+        packet: &PhotonPacket,
+        recursion_limit: u16,
+        rng: &mut dyn RngCore,
+    ) -> PhotonPacket;
+
+    /// The light arriving at the origin of each of `rays`, for the wavelengths in the
+    /// corresponding packet in `packets`, using the corresponding generator in `rngs`
+    ///
+    /// This is how the renderer traces a batch of camera rays. By default the rays'
+    /// intersections are found a packet at a time and each is
+    /// [integrated](Integrator::integrate_hit) in turn, but integrators can instead trace
+    /// the whole batch together.
+    fn integrate_batch(
         &self,
-        sampler: &Sampler,
-        info: &IntersectionInfo,
-        photon: &Photon,
+        sampler: &dyn Sampler,
+        rays: &[Ray],
+        packets: &[PhotonPacket],
         recursion_limit: u16,
-    ) -> Photon;
+        rngs: &mut [StdRng],
+    ) -> Vec<PhotonPacket> {
+        assert!(rays.len() == packets.len() && rays.len() == rngs.len());
+        let mut result = Vec::with_capacity(rays.len());
+        for ((rays, packets), rngs) in rays
+            .chunks(RAY_PACKET_WIDTH)
+            .zip(packets.chunks(RAY_PACKET_WIDTH))
+            .zip(rngs.chunks_mut(RAY_PACKET_WIDTH))
+        {
+            let hits = sampler.sample_packet(rays);
+            for (lane, hit) in IntoIterator::into_iter(hits).take(rays.len()).enumerate() {
+                result.push(self.integrate_hit(
+                    sampler,
+                    &rays[lane],
+                    hit,
+                    &packets[lane],
+                    recursion_limit,
+                    &mut rngs[lane],
+                ));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+    use crate::colour::Spectrum;
+
+    use crate::materials::{LambertianMaterial, Material, TwoSided};
+    use crate::math::Vec3;
+    use crate::raycasting::{Intersect, Primitive, Rect};
+    use crate::sampler::SceneSampler;
+    use crate::scene::Scene;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use std::sync::Arc;
+
+    fn hit_from_behind(material: Arc<dyn Material>) -> IntersectionInfo {
+        let target = Rect::new(
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            material,
+        );
+        target
+            .intersect(&Ray::new(Vec3::new(0.2, 0.1, -3.0), Vec3::unit_z()))
+            .unwrap()
+    }
+
+    #[test]
+    fn back_face_of_one_sided_material_is_below_surface() {
+        let info = hit_from_behind(Arc::new(LambertianMaterial::new_dummy()));
+        assert!(bsdf_frame(&info).to_local(&info.retro).z() < 0.0);
+    }
+
+    #[test]
+    fn back_face_of_two_sided_material_is_above_surface() {
+        let info = hit_from_behind(Arc::new(TwoSided::new(LambertianMaterial::new_dummy())));
+        let frame = bsdf_frame(&info);
+        assert!(frame.to_local(&info.retro).z() > 0.0);
+        assert!((frame.tangent.cross(&frame.cotangent) - frame.normal).norm() < 0.000000001);
+    }
+
+    #[test]
+    fn power_heuristic_weights_sum_to_one() {
+        for &(a, b) in [(0.1, 0.9), (1.0, 1.0), (3.0, 0.5), (0.0, 2.0)].iter() {
+            assert!((power_heuristic(a, b) + power_heuristic(b, a) - 1.0).abs() < 0.000000001);
+        }
+        assert!(power_heuristic(0.0, 0.0) == 0.0);
+    }
+
+    #[test]
+    fn rays_that_miss_see_environment() {
+        let scene = Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::zeros(),
+            Lens::Pinhole,
+        )))
+        .with_object(Box::new(vec![Box::new(Rect::new(
+            Vec3::new(-1.0, -1.0, 2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Arc::new(LambertianMaterial::new_dummy()),
+        )) as Box<dyn Primitive>]))
+        .build();
+        let sampler = SceneSampler { scene: &scene };
+        let integrators: [Box<dyn Integrator>; 3] = [
+            Box::new(SimpleRandomIntegrator {}),
+            Box::new(WavefrontIntegrator {}),
+            Box::new(WhittedIntegrator {
+                ambient_light: Spectrum::black(),
+                area_lights: vec![],
+            }),
+        ];
+        let ray = Ray::new(Vec3::zeros(), Vec3::new(0.3, 0.4, -1.0).normalize());
+        let mut rng = StdRng::seed_from_u64(0);
+        let packet = PhotonPacket::random_wavelengths(&mut rng);
+        for integrator in integrators.iter() {
+            let single = integrator.integrate_ray(&sampler, &ray, &packet, 4, &mut rng);
+            let batch = integrator.integrate_batch(
+                &sampler,
+                std::slice::from_ref(&ray),
+                std::slice::from_ref(&packet),
+                4,
+                std::slice::from_mut(&mut rng),
+            );
+            for result in [single, batch[0].clone()].iter() {
+                for photon in result.photons() {
+                    let expected = scene
+                        .environment
+                        .radiance(&ray.direction, photon.wavelength);
+                    assert!((photon.intensity - expected).abs() < 0.000000001);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,251 @@
+use crate::colour::polarization::s_direction;
+use crate::colour::{MuellerMatrix, PhotonPacket, Stokes};
+use crate::math::{OrthonormalBasis, Vec3};
+use crate::raycasting::{IntersectionInfo, Ray};
+use crate::sampler::Sampler;
+
+use super::simple_random_integrator::bounce_weight;
+use super::{bsdf_frame, Integrator, SimpleRandomIntegrator};
+
+use rand::RngCore;
+
+/// A path tracer that follows the polarization of light through the surfaces that change
+/// it, such as glass and metals
+///
+/// From the camera, the path is followed through surfaces with a
+/// [Mueller matrix](crate::materials::Material::mueller_matrix), keeping the product of
+/// their matrices for each wavelength. At the first surface that doesn't polarize light,
+/// or where the path leaves the scene, the light arriving along the path is found by the
+/// [SimpleRandomIntegrator](SimpleRandomIntegrator) and treated as unpolarized. This is
+/// right for diffuse surfaces, the environment and the lights, none of which emit or
+/// reflect polarized light. Participating media aren't polarized, so paths are handed over
+/// at any surface with an interior medium, and straight away in scenes filled with one.
+///
+/// Polarization changes how much light passes through and reflects from stacks of glass,
+/// but to see it directly the camera needs a polarizing `filter`.
+pub struct PolarizedIntegrator {
+    /// The transmission axis of a linear polarizing filter in front of the camera, in
+    /// world space, or `None` for no filter
+    pub filter: Option<Vec3>,
+}
+
+impl PolarizedIntegrator {
+    /// The direction against which the Stokes vector of the light arriving along `ray` is
+    /// measured, and the filter's matrix in that reference
+    fn camera_reference(&self, ray: &Ray) -> (Vec3, MuellerMatrix) {
+        let travel = -ray.direction;
+        let axis = self
+            .filter
+            .map(|axis| axis - travel * axis.dot(&travel))
+            .filter(|axis| axis.norm_squared() > 0.0000000001);
+        match axis {
+            Some(axis) => (axis.normalize(), MuellerMatrix::linear_polarizer(0.0)),
+            None => (
+                OrthonormalBasis::from_normal(&travel).tangent,
+                if self.filter.is_some() {
+                    // Looking straight along the filter's axis, so nothing gets through
+                    MuellerMatrix::depolarizer(0.0)
+                } else {
+                    MuellerMatrix::identity()
+                },
+            ),
+        }
+    }
+}
+
+impl Integrator for PolarizedIntegrator {
+    fn integrate_hit(
+        &self,
+        sampler: &dyn Sampler,
+        ray: &Ray,
+        hit: Option<IntersectionInfo>,
+        packet: &PhotonPacket,
+        recursion_limit: u16,
+        rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
+        let (mut reference, filter) = self.camera_reference(ray);
+        // For each wavelength, the matrices that the light arriving along the current ray
+        // will pass through on its way to the camera, and the light found so far
+        let mut throughputs = vec![MuellerMatrix::identity(); packet.photons().len()];
+        let mut radiance = vec![Stokes::unpolarized(0.0); packet.photons().len()];
+        let mut wavelengths = packet.clone();
+        let mut ray = ray.clone();
+        let mut hit = hit;
+        let mut recursion_limit = recursion_limit;
+        let is_polarizing_scene = sampler.scene().medium.is_none();
+        while let Some(info) = hit.take() {
+            let bsdf_frame = bsdf_frame(&info);
+            let w_i = bsdf_frame.to_local(&info.retro);
+            // Materials that polarize light have a matrix for every direction, so any will
+            // do to find out, and the path is handed over before the material is sampled
+            let is_polarizing = info
+                .material
+                .mueller_matrix(&info.uv, &w_i, &w_i, wavelengths.hero())
+                .is_some();
+            if recursion_limit == 0
+                || !is_polarizing
+                || !is_polarizing_scene
+                || info.material.interior_medium().is_some()
+            {
+                hit = Some(info);
+                break;
+            }
+            let sample = info
+                .material
+                .sample(&info.uv, &w_i, wavelengths.hero(), rng);
+            let muellers: Vec<MuellerMatrix> = wavelengths
+                .photons()
+                .iter()
+                .map(|photon| {
+                    info.material
+                        .mueller_matrix(&info.uv, &w_i, &sample.direction, photon)
+                        .unwrap_or_else(|| MuellerMatrix::depolarizer(0.0))
+                })
+                .collect();
+            if info.material.is_dispersive() && !wavelengths.is_single_wavelength() {
+                // As PhotonPacket::expand_hero(), the hero carries the light for the packet
+                let weight = wavelengths.hero_weight();
+                throughputs[0] = throughputs[0] * weight;
+                for throughput in throughputs.iter_mut().skip(1) {
+                    *throughput = MuellerMatrix::depolarizer(0.0);
+                }
+                wavelengths = wavelengths.hero_only();
+            }
+            for ((photon, throughput), radiance) in wavelengths
+                .photons()
+                .iter()
+                .zip(throughputs.iter())
+                .zip(radiance.iter_mut())
+            {
+                let emitted = info.material.emission(&w_i, photon).intensity;
+                radiance.values += (*throughput * Stokes::unpolarized(emitted)).values;
+            }
+            let world_space_w_o = bsdf_frame.to_world(&sample.direction);
+            let s = bsdf_frame.to_world(&s_direction(&w_i));
+            let weight = bounce_weight(sample.pdf, sample.is_specular, &sample.direction);
+            let to_reference = MuellerMatrix::rotate_reference(&s, &reference, &info.retro);
+            for (throughput, mueller) in throughputs.iter_mut().zip(muellers) {
+                *throughput = *throughput * to_reference * mueller * weight;
+            }
+            reference = s;
+            ray = info.spawn_ray(&world_space_w_o);
+            hit = sampler.sample(&ray);
+            recursion_limit -= 1;
+        }
+        let incoming = SimpleRandomIntegrator {}.integrate_hit(
+            sampler,
+            &ray,
+            hit,
+            &wavelengths,
+            recursion_limit,
+            rng,
+        );
+        for ((photon, throughput), radiance) in incoming
+            .photons()
+            .iter()
+            .zip(throughputs.iter())
+            .zip(radiance.iter_mut())
+        {
+            radiance.values += (*throughput * Stokes::unpolarized(photon.intensity)).values;
+        }
+        let mut radiance = radiance.into_iter();
+        packet.map(|photon| photon.set_intensity((filter * radiance.next().unwrap()).intensity()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+    use crate::colour::polarization::fresnel_amplitudes;
+    use crate::colour::Photon;
+    use crate::colour::Spectrum;
+
+    use crate::materials::{Conductor, LambertianMaterial, Material};
+    use crate::math::Complex;
+    use crate::raycasting::{Plane, Primitive};
+    use crate::sampler::SceneSampler;
+    use crate::scene::Scene;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use std::sync::Arc;
+
+    fn floor(material: Arc<dyn Material>) -> Scene {
+        Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::zeros(),
+            Lens::Pinhole,
+        )))
+        .with_object(Box::new(vec![
+            Box::new(Plane::new(Vec3::unit_y(), -1.0, material)) as Box<dyn Primitive>,
+        ]))
+        .build()
+    }
+
+    #[test]
+    fn filter_separates_polarizations_reflected_from_metal() {
+        let scene = floor(Arc::new(Conductor::gold()));
+        let sampler = SceneSampler { scene: &scene };
+        let cos_theta = 0.3f64;
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let ray = Ray::new(Vec3::zeros(), Vec3::new(0.0, -cos_theta, sin_theta));
+        let packet = PhotonPacket::from_photon(&Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        });
+        let s = Vec3::unit_x();
+        let p = s.cross(&ray.direction);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut intensity = |filter| {
+            PolarizedIntegrator { filter }
+                .integrate_ray(&sampler, &ray, &packet, 8, &mut rng)
+                .hero()
+                .intensity
+        };
+        let (along_s, along_p, unfiltered) =
+            (intensity(Some(s)), intensity(Some(p)), intensity(None));
+        let (r_s, r_p) = fresnel_amplitudes(
+            cos_theta,
+            Complex::new(
+                Conductor::gold().eta.intensity_at_wavelength(550.0),
+                Conductor::gold().k.intensity_at_wavelength(550.0),
+            ),
+        );
+        assert!(along_s > along_p);
+        assert!((along_s + along_p - unfiltered).abs() < 0.0000001);
+        assert!((along_s / along_p - r_s.norm_squared() / r_p.norm_squared()).abs() < 0.0000001);
+    }
+
+    #[test]
+    fn unfiltered_diffuse_scene_matches_simple_random_integrator() {
+        let scene = floor(Arc::new(LambertianMaterial {
+            colour: Spectrum::grey(0.5),
+            diffuse_strength: 1.0,
+        }));
+        let sampler = SceneSampler { scene: &scene };
+        let ray = Ray::new(Vec3::zeros(), Vec3::new(0.2, -0.5, 1.0).normalize());
+        for seed in 0..10 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let packet = PhotonPacket::random_wavelengths(&mut rng);
+            let target = PolarizedIntegrator { filter: None }.integrate_ray(
+                &sampler,
+                &ray,
+                &packet,
+                8,
+                &mut StdRng::seed_from_u64(seed),
+            );
+            let expected = SimpleRandomIntegrator {}.integrate_ray(
+                &sampler,
+                &ray,
+                &packet,
+                8,
+                &mut StdRng::seed_from_u64(seed),
+            );
+            for (a, b) in target.photons().iter().zip(expected.photons()) {
+                assert!((a.intensity - b.intensity).abs() < 0.0000001);
+            }
+        }
+    }
+}
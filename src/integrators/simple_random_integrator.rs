@@ -1,65 +1,618 @@
-use crate::colour::{ColourRgbF, Photon, Spectrum};
+use crate::colour::PhotonPacket;
+use crate::lights::{portals_pdf, sample_portals};
 use crate::materials::MaterialSampleResult;
-use crate::math::Vec3;
+use crate::math::{OrthonormalBasis, Vec3};
+use crate::media::{Medium, MediumScattering};
+use crate::path_debug::{self, BounceRecord};
 use crate::raycasting::{IntersectionInfo, Ray};
 use crate::sampler::Sampler;
-use crate::util::algebra_utils::try_change_of_basis_matrix;
 
-use super::Integrator;
+use super::{bsdf_frame, power_heuristic, Integrator};
 
+use rand::RngCore;
+
+/// A path tracer that follows a single randomly-sampled direction at each bounce
+///
+/// Paths are scattered by participating media as well as by surfaces. The scene's medium
+/// fills the space between objects, and rays that pass into a surface with an
+/// [interior medium](crate::materials::Material::interior_medium) travel through that
+/// medium until they leave again.
 pub struct SimpleRandomIntegrator {}
 
-impl Integrator for SimpleRandomIntegrator {
-    fn integrate(
+impl SimpleRandomIntegrator {
+    /// The light arriving along `ray`, which is travelling through `medium`
+    ///
+    /// Returns `None` if the ray leaves the scene without hitting anything or being
+    /// scattered, so that the caller can decide what it sees.
+    fn trace(
+        &self,
+        sampler: &dyn Sampler,
+        ray: &Ray,
+        medium: Option<&dyn Medium>,
+        packet: &PhotonPacket,
+        recursion_limit: u16,
+        rng: &mut dyn RngCore,
+    ) -> Option<PhotonPacket> {
+        self.trace_from_hit(
+            sampler,
+            ray,
+            sampler.sample(ray),
+            medium,
+            packet,
+            recursion_limit,
+            rng,
+        )
+    }
+
+    /// As [trace()](SimpleRandomIntegrator::trace), where `hit` is the nearest intersection
+    /// of `ray` with the scene
+    #[allow(clippy::too_many_arguments)]
+    fn trace_from_hit(
+        &self,
+        sampler: &dyn Sampler,
+        ray: &Ray,
+        hit: Option<IntersectionInfo>,
+        medium: Option<&dyn Medium>,
+        packet: &PhotonPacket,
+        recursion_limit: u16,
+        rng: &mut dyn RngCore,
+    ) -> Option<PhotonPacket> {
+        if let Some(medium) = medium {
+            // How far light travels through the medium depends on its wavelength
+            if !packet.is_single_wavelength() {
+                let hero = packet.hero_only();
+                return self
+                    .trace_from_hit(sampler, ray, hit, Some(medium), &hero, recursion_limit, rng)
+                    .map(|result| packet.expand_hero(&result));
+            }
+            let max_distance = hit.as_ref().map_or(f64::INFINITY, |info| info.distance);
+            if let Some(MediumScattering { distance, weight }) =
+                medium.sample_scattering(ray, max_distance, packet.hero().wavelength, rng)
+            {
+                if recursion_limit == 0 {
+                    return Some(packet.set_intensity(0.0));
+                }
+                let direction = medium.phase_function().sample(&ray.direction, rng);
+                let scattered_ray = Ray::new(ray.point_at(distance), direction).with_time(ray.time);
+                return Some(
+                    self.trace_into_environment(
+                        sampler,
+                        &scattered_ray,
+                        Some(medium),
+                        packet,
+                        recursion_limit - 1,
+                        rng,
+                    )
+                    .scale_intensity(weight),
+                );
+            }
+        }
+        hit.map(|info| self.shade(sampler, &info, medium, packet, recursion_limit, rng))
+    }
+
+    /// As [trace()](SimpleRandomIntegrator::trace), but rays that leave the scene see the
+    /// environment
+    fn trace_into_environment(
         &self,
-        sampler: &Sampler,
+        sampler: &dyn Sampler,
+        ray: &Ray,
+        medium: Option<&dyn Medium>,
+        packet: &PhotonPacket,
+        recursion_limit: u16,
+        rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
+        self.trace(sampler, ray, medium, packet, recursion_limit, rng)
+            .unwrap_or_else(|| environment_radiance(sampler, &ray.direction, packet))
+    }
+
+    /// Light arriving at `info` directly from the environment, in a direction chosen by
+    /// importance-sampling the environment, or through one of the scene's
+    /// [portals](crate::scene::Scene::portals) if it has any
+    ///
+    /// The result is weighted with the power heuristic so that it can be added to the light
+    /// found by following the material sample.
+    fn sample_environment(
+        &self,
+        sampler: &dyn Sampler,
         info: &IntersectionInfo,
-        photon: &Photon,
+        bsdf_frame: &OrthonormalBasis,
+        w_i: &Vec3,
+        packet: &PhotonPacket,
+        rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
+        let direction = if sampler.scene().portals.is_empty() {
+            sampler
+                .scene()
+                .environment
+                .direction_distribution()
+                .value(rng)
+        } else {
+            match sample_portals(&sampler.scene().portals, &info.location, rng) {
+                Some((direction, _)) => direction,
+                None => return packet.set_intensity(0.0),
+            }
+        };
+        let w_l = bsdf_frame.to_local(&direction);
+        let light_pdf = environment_pdf(sampler, &info.location, &direction, &w_l);
+        let material_pdf = info.material.pdf(&info.uv, w_i, &w_l, packet.hero());
+        if light_pdf <= 0.0 || w_l.z() <= 0.0 {
+            return packet.set_intensity(0.0);
+        }
+        if sampler.is_occluded(&info.spawn_ray(&direction), f64::INFINITY) {
+            return packet.set_intensity(0.0);
+        }
+        // The light pdf is over the same polar angles as the material's, so the light is
+        // divided by it just as it is for a material sample
+        let weight =
+            power_heuristic(light_pdf, material_pdf) * bounce_weight(light_pdf, false, &w_l);
+        let bsdf = info.bsdf();
+        environment_radiance(sampler, &direction, packet)
+            .scale_intensity(weight)
+            .map(|photon| bsdf(&w_l, w_i, photon))
+    }
+
+    /// Light arriving at `info` directly from the scene's [lights](crate::scene::Scene::lights)
+    ///
+    /// Following the material sample never finds a light that can only be reached in one
+    /// direction, so these samples aren't weighted against it.
+    fn sample_lights(
+        &self,
+        sampler: &dyn Sampler,
+        info: &IntersectionInfo,
+        bsdf_frame: &OrthonormalBasis,
+        w_i: &Vec3,
+        packet: &PhotonPacket,
+        rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
+        let bsdf = info.bsdf();
+        sampler
+            .scene()
+            .lights
+            .iter()
+            .filter_map(|light| sampler.sample_light(light.as_ref(), &info.location, packet, rng))
+            .filter_map(|sample| {
+                let w_l = bsdf_frame.to_local(&sample.direction);
+                if w_l.z() <= 0.0
+                    || sampler.is_occluded(&info.spawn_ray(&sample.direction), sample.distance)
+                {
+                    return None;
+                }
+                Some(
+                    sample
+                        .radiance
+                        .scale_intensity(w_l.z() / sample.pdf)
+                        .map(|photon| bsdf(&w_l, w_i, photon)),
+                )
+            })
+            .fold(packet.set_intensity(0.0), |a, b| a.add(&b))
+    }
+
+    /// The light leaving the surface at `info`, which was reached through `medium`
+    fn shade(
+        &self,
+        sampler: &dyn Sampler,
+        info: &IntersectionInfo,
+        medium: Option<&dyn Medium>,
+        packet: &PhotonPacket,
         recursion_limit: u16,
-    ) -> Photon {
+        rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
         if recursion_limit == 0 {
-            return Photon {
-                wavelength: 0.0,
-                intensity: 0.0,
-            };
+            return packet.set_intensity(0.0);
+        }
+        if info.material.is_dispersive() && !packet.is_single_wavelength() {
+            let hero = packet.hero_only();
+            return packet.expand_hero(&self.shade(
+                sampler,
+                info,
+                medium,
+                &hero,
+                recursion_limit,
+                rng,
+            ));
         }
-        let world_to_bsdf_space =
-            try_change_of_basis_matrix(&info.tangent, &info.cotangent, &info.normal)
-                .expect("Normal, tangent and cotangent don't form a valid basis.");
-        let bsdf_to_world_space = world_to_bsdf_space
-            .try_inverse()
-            .expect("Expected matrix to be invertable.");
+        let bsdf_frame = bsdf_frame(info);
         let world_space_w_i = info.retro;
-        let w_i = world_to_bsdf_space * world_space_w_i;
+        let w_i = bsdf_frame.to_local(&world_space_w_i);
         let MaterialSampleResult {
             direction: w_o,
             pdf: w_o_pdf,
-        } = info.material.sample(&w_i, photon);
-        let world_space_w_o = bsdf_to_world_space * w_o;
-        info.material.bsdf()(
-            &w_o,
-            &w_i,
-            &match sampler.sample(&Ray::new(info.location, world_space_w_o).bias(0.000_000_1)) {
-                None => photon.set_intensity(test_lighting_environment(
-                    &world_space_w_o,
-                    photon.wavelength,
-                )),
-                Some(recursive_hit) => {
-                    self.integrate(sampler, &recursive_hit, photon, recursion_limit - 1)
-                }
-            }
-            .scale_intensity(w_o_pdf)
-            .scale_intensity(world_space_w_o.dot(&info.normal).abs()),
+            is_specular,
+        } = info.material.sample(&info.uv, &w_i, packet.hero(), rng);
+        let world_space_w_o = bsdf_frame.to_world(&w_o);
+        // Crossing the boundary of a medium either enters it or returns to the scene's
+        // medium; other surfaces don't change the medium
+        let w_o_medium = match info.material.interior_medium() {
+            Some(interior) if world_space_w_o.dot(&info.normal) < 0.0 => Some(interior),
+            Some(_) => sampler.scene().medium.as_deref(),
+            None => medium,
+        };
+        let emitted = packet.map(|photon| info.material.emission(&w_i, photon));
+        // The environment and the scene's lights are also sampled directly, and the
+        // environment is combined with the material sample using multiple importance
+        // sampling. Specular materials can't be lit this way, and the shadow ray doesn't
+        // account for media, so neither is sampled.
+        let sample_environment =
+            !is_specular && medium.is_none() && info.material.interior_medium().is_none();
+        let direct = if sample_environment {
+            self.sample_environment(sampler, info, &bsdf_frame, &w_i, packet, rng)
+                .add(&self.sample_lights(sampler, info, &bsdf_frame, &w_i, packet, rng))
+        } else {
+            packet.set_intensity(0.0)
+        };
+        let bounce = if path_debug::is_recording() {
+            let bsdf = info.bsdf();
+            path_debug::record_bounce(BounceRecord {
+                location: info.location,
+                normal: info.normal,
+                object_id: info.object_id,
+                material_id: sampler.scene().materials.id(&info.material),
+                w_i: world_space_w_i,
+                w_o: world_space_w_o,
+                pdf: w_o_pdf,
+                is_specular,
+                bsdf: bsdf(&w_o, &w_i, &packet.hero().set_intensity(1.0)),
+                emitted: emitted.hero().clone(),
+                direct: direct.hero().clone(),
+                radiance: packet.hero().set_intensity(0.0),
+            })
+        } else {
+            None
+        };
+        let mut ray = info.spawn_ray(&world_space_w_o);
+        // Only specular bounces keep the footprint coherent enough to be worth following
+        if let (true, Some(footprint)) = (is_specular, info.footprint) {
+            ray = ray.with_differential(footprint.specular_bounce(
+                &info.location,
+                &info.normal,
+                &-info.retro,
+                &world_space_w_o,
+            ));
+        }
+        let incoming = self
+            .trace(sampler, &ray, w_o_medium, packet, recursion_limit - 1, rng)
+            .unwrap_or_else(|| {
+                let weight = if sample_environment {
+                    power_heuristic(
+                        w_o_pdf,
+                        environment_pdf(sampler, &info.location, &world_space_w_o, &w_o),
+                    )
+                } else {
+                    1.0
+                };
+                environment_radiance(sampler, &ray.direction, packet).scale_intensity(weight)
+            })
+            .scale_intensity(bounce_weight(w_o_pdf, is_specular, &w_o));
+        let bsdf = info.bsdf();
+        let radiance = incoming
+            .map(|photon| bsdf(&w_o, &w_i, photon))
+            .add(&emitted)
+            .add(&direct);
+        if let Some(index) = bounce {
+            path_debug::set_radiance(index, radiance.hero().clone());
+        }
+        radiance
+    }
+}
+
+/// The factor that the light found by following a material sample in the BSDF-space
+/// direction `w_o` is scaled by, before the BSDF is applied
+///
+/// Non-specular samples are weighted by the cosine to the normal and divided by their
+/// density. Material pdfs are over the polar angles of `w_o` rather than solid angle, so
+/// the sin θ from the change of variables is included. Specular lobes are
+/// [delta distributions](MaterialSampleResult::is_specular), which are chosen with a
+/// probability rather than a density, so the light is only divided by it and the cosine
+/// is left to the BSDF.
+pub(super) fn bounce_weight(pdf: f64, is_specular: bool, w_o: &Vec3) -> f64 {
+    if pdf <= 0.0 {
+        0.0
+    } else if is_specular {
+        1.0 / pdf
+    } else {
+        let cos_theta = w_o.z().abs();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        cos_theta * sin_theta / pdf
+    }
+}
+
+/// The light arriving from the environment in `direction`
+pub(super) fn environment_radiance(
+    sampler: &dyn Sampler,
+    direction: &Vec3,
+    packet: &PhotonPacket,
+) -> PhotonPacket {
+    packet.map(|photon| {
+        photon.set_intensity(
+            sampler
+                .scene()
+                .environment
+                .radiance(direction, photon.wavelength),
+        )
+    })
+}
+
+/// The density with which the environment is sampled in `direction` from `location`
+///
+/// This is the environment's direction distribution, or the density of sampling through the
+/// scene's portals if it has any. Material pdfs are densities over the polar angles of `w`,
+/// the same direction in BSDF space, rather than over solid angle, so the result is
+/// converted to match.
+pub(super) fn environment_pdf(
+    sampler: &dyn Sampler,
+    location: &Vec3,
+    direction: &Vec3,
+    w: &Vec3,
+) -> f64 {
+    let sin_theta = (1.0 - w.z() * w.z()).max(0.0).sqrt();
+    let pdf = if sampler.scene().portals.is_empty() {
+        sampler
+            .scene()
+            .environment
+            .direction_distribution()
+            .pdf(*direction)
+    } else {
+        portals_pdf(&sampler.scene().portals, location, direction)
+    };
+    pdf * sin_theta
+}
+
+impl Integrator for SimpleRandomIntegrator {
+    fn integrate_hit(
+        &self,
+        sampler: &dyn Sampler,
+        ray: &Ray,
+        hit: Option<IntersectionInfo>,
+        packet: &PhotonPacket,
+        recursion_limit: u16,
+        rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
+        self.trace_from_hit(
+            sampler,
+            ray,
+            hit,
+            sampler.scene().medium.as_deref(),
+            packet,
+            recursion_limit,
+            rng,
         )
+        .unwrap_or_else(|| environment_radiance(sampler, &ray.direction, packet))
     }
 }
 
-pub fn test_lighting_environment(w_o: &Vec3, wavelength: f64) -> f64 {
-    //let sun_direction = Vec3::new(1.0, 1.0, -1.0).normalize();
-    //if w_o.dot(&sun_direction) >= 0.99 {
-    //    300.0
-    //} else {
-    let sky_colour = ColourRgbF::new(w_o.y(), w_o.y(), 1.0);
-    Spectrum::reflection_from_linear_rgb(&sky_colour).intensity_at_wavelength(wavelength)
-    //}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+    use crate::colour::{Photon, Spectrum};
+    use crate::lights::{PointLight, Portal, PreethamSky};
+    use crate::materials::{
+        EmissiveMaterial, LambertianMaterial, Material, MediumBoundary, TwoSided,
+    };
+    use crate::media::{HenyeyGreenstein, HomogeneousMedium};
+    use crate::raycasting::{Primitive, Rect};
+    use crate::sampler::SceneSampler;
+    use crate::scene::Scene;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    fn light_behind_medium(medium: Option<Box<dyn Medium>>) -> Scene {
+        let builder = Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::zeros(),
+            Lens::Pinhole,
+        )))
+        .with_object(Box::new(vec![Box::new(Rect::new(
+            Vec3::new(-1.0, -1.0, 2.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Arc::new(EmissiveMaterial {
+                emission: Spectrum::grey(1.0),
+            }),
+        )) as Box<dyn Primitive>]));
+        match medium {
+            Some(medium) => builder.with_medium(medium).build(),
+            None => builder.build(),
+        }
+    }
+
+    fn mean_radiance(scene: &Scene) -> f64 {
+        let sampler = SceneSampler { scene };
+        let mut rng = StdRng::seed_from_u64(0);
+        let packet = PhotonPacket::from_photon(&Photon {
+            wavelength: 550.0,
+            intensity: 0.0,
+        });
+        let ray = Ray::new(Vec3::zeros(), Vec3::unit_z());
+        (0..10000)
+            .map(|_| {
+                SimpleRandomIntegrator {}
+                    .integrate_ray(&sampler, &ray, &packet, 8, &mut rng)
+                    .hero()
+                    .intensity
+            })
+            .sum::<f64>()
+            / 10000.0
+    }
+
+    #[test]
+    fn light_is_unattenuated_in_vacuum() {
+        assert!((mean_radiance(&light_behind_medium(None)) - 1.0).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn absorbing_medium_attenuates_light_by_transmittance() {
+        let scene = light_behind_medium(Some(Box::new(HomogeneousMedium {
+            absorption: Spectrum::grey(0.5),
+            scattering: Spectrum::grey(0.0),
+            phase_function: HenyeyGreenstein::new(0.0),
+        })));
+        let expected = (-1.0f64).exp();
+        assert!((mean_radiance(&scene) - expected).abs() < 0.02);
+    }
+
+    #[test]
+    fn every_wavelength_in_packet_carries_light() {
+        let scene = light_behind_medium(None);
+        let sampler = SceneSampler { scene: &scene };
+        let mut rng = StdRng::seed_from_u64(0);
+        let packet = PhotonPacket::random_wavelengths(&mut rng);
+        let ray = Ray::new(Vec3::zeros(), Vec3::unit_z());
+        let result = SimpleRandomIntegrator {}.integrate_ray(&sampler, &ray, &packet, 8, &mut rng);
+        assert!(result.photons().len() == packet.photons().len());
+        assert!(result
+            .photons()
+            .iter()
+            .all(|photon| (photon.intensity - 1.0).abs() < 0.000000001));
+    }
+
+    #[test]
+    fn packet_collapses_to_hero_in_medium() {
+        let scene = light_behind_medium(Some(Box::new(HomogeneousMedium {
+            absorption: Spectrum::grey(0.5),
+            scattering: Spectrum::grey(0.0),
+            phase_function: HenyeyGreenstein::new(0.0),
+        })));
+        let sampler = SceneSampler { scene: &scene };
+        let mut rng = StdRng::seed_from_u64(0);
+        let packet = PhotonPacket::random_wavelengths(&mut rng);
+        let ray = Ray::new(Vec3::zeros(), Vec3::unit_z());
+        let result = SimpleRandomIntegrator {}.integrate_ray(&sampler, &ray, &packet, 8, &mut rng);
+        assert!(result.photons()[1..]
+            .iter()
+            .all(|photon| photon.intensity == 0.0));
+    }
+
+    /// A diffuse floor lit only by a clear sky, seen from above
+    fn floor_under_sky(material: Arc<dyn Material>) -> Scene {
+        Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::zeros(),
+            Lens::Pinhole,
+        )))
+        .with_object(Box::new(vec![Box::new(Rect::new(
+            Vec3::new(-100.0, 0.0, -100.0),
+            Vec3::new(0.0, 0.0, 200.0),
+            Vec3::new(200.0, 0.0, 0.0),
+            material,
+        )) as Box<dyn Primitive>]))
+        .with_environment(Box::new(PreethamSky::new(&Vec3::new(1.0, 1.0, 0.0), 3.0)))
+        .build()
+    }
+
+    /// Mean and variance of the light reflected from the floor
+    fn floor_statistics(scene: &Scene) -> (f64, f64) {
+        let sampler = SceneSampler { scene };
+        let mut rng = StdRng::seed_from_u64(0);
+        let packet = PhotonPacket::from_photon(&Photon {
+            wavelength: 550.0,
+            intensity: 0.0,
+        });
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let samples: Vec<f64> = (0..20000)
+            .map(|_| {
+                SimpleRandomIntegrator {}
+                    .integrate_ray(&sampler, &ray, &packet, 8, &mut rng)
+                    .hero()
+                    .intensity
+            })
+            .collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance =
+            samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / samples.len() as f64;
+        (mean, variance)
+    }
+
+    #[test]
+    fn sampling_environment_reduces_noise_without_bias() {
+        let lambertian = || {
+            Arc::new(LambertianMaterial {
+                colour: Spectrum::grey(0.5),
+                diffuse_strength: 1.0,
+            })
+        };
+        let (mean, variance) = floor_statistics(&floor_under_sky(lambertian()));
+        // The environment isn't sampled directly at the boundary of a medium, so this gives
+        // the estimate from material sampling alone.
+        let (reference_mean, reference_variance) =
+            floor_statistics(&floor_under_sky(Arc::new(MediumBoundary {
+                surface: lambertian(),
+                interior: Arc::new(HomogeneousMedium {
+                    absorption: Spectrum::grey(0.0),
+                    scattering: Spectrum::grey(0.0),
+                    phase_function: HenyeyGreenstein::new(0.0),
+                }),
+            })));
+        assert!(mean.is_finite() && mean > 0.0);
+        assert!((mean / reference_mean - 1.0).abs() < 0.02);
+        assert!(variance < reference_variance);
+    }
+
+    #[test]
+    fn point_light_adds_direct_illumination() {
+        let lambertian = || {
+            Arc::new(LambertianMaterial {
+                colour: Spectrum::grey(0.5),
+                diffuse_strength: 1.0,
+            })
+        };
+        let (without_light, _) = floor_statistics(&floor_under_sky(lambertian()));
+        let mut scene = floor_under_sky(lambertian());
+        // Gives an irradiance of one directly below it
+        scene.lights.push(Box::new(PointLight::new(
+            Vec3::new(0.0, 2.0, 0.0),
+            Spectrum::grey(4.0),
+        )));
+        let (with_light, _) = floor_statistics(&scene);
+        // Point lights don't use any randomness, so both renders follow the same paths
+        assert!((with_light - without_light - 0.5 / PI).abs() < 0.000001);
+    }
+
+    /// A closed room lit only by the sky, through a skylight above the middle of the floor
+    fn room_with_skylight(portals: Vec<Portal>) -> Scene {
+        let material: Arc<dyn Material> = Arc::new(TwoSided::new(LambertianMaterial {
+            colour: Spectrum::grey(0.5),
+            diffuse_strength: 1.0,
+        }));
+        let rect = |corner: Vec3, edge_u: Vec3, edge_v: Vec3| {
+            Box::new(Rect::new(corner, edge_u, edge_v, Arc::clone(&material))) as Box<dyn Primitive>
+        };
+        let (x, y, z) = (Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z());
+        let objects: Vec<Box<dyn Primitive>> = vec![
+            rect(Vec3::new(-2.0, 0.0, -2.0), x * 4.0, z * 4.0),
+            rect(Vec3::new(-2.0, 0.0, -2.0), x * 4.0, y * 2.0),
+            rect(Vec3::new(-2.0, 0.0, 2.0), x * 4.0, y * 2.0),
+            rect(Vec3::new(-2.0, 0.0, -2.0), z * 4.0, y * 2.0),
+            rect(Vec3::new(2.0, 0.0, -2.0), z * 4.0, y * 2.0),
+            // The ceiling, around a hole from -0.5 to 0.5 in x and z
+            rect(Vec3::new(-2.0, 2.0, -2.0), x * 1.5, z * 4.0),
+            rect(Vec3::new(0.5, 2.0, -2.0), x * 1.5, z * 4.0),
+            rect(Vec3::new(-0.5, 2.0, -2.0), x, z * 1.5),
+            rect(Vec3::new(-0.5, 2.0, 0.5), x, z * 1.5),
+        ];
+        portals
+            .into_iter()
+            .fold(
+                Scene::builder(Box::new(PerspectiveCamera::new(
+                    Vec3::zeros(),
+                    Lens::Pinhole,
+                )))
+                .with_object(Box::new(objects)),
+                |builder, portal| builder.with_portal(portal),
+            )
+            .build()
+    }
+
+    #[test]
+    fn sampling_through_portal_reduces_noise_without_bias() {
+        let skylight = Portal::new(Vec3::new(-0.5, 2.0, -0.5), Vec3::unit_x(), Vec3::unit_z());
+        let (mean, variance) = floor_statistics(&room_with_skylight(vec![skylight]));
+        let (reference_mean, reference_variance) = floor_statistics(&room_with_skylight(vec![]));
+        assert!(mean.is_finite() && mean > 0.0);
+        assert!((mean / reference_mean - 1.0).abs() < 0.05);
+        assert!(variance < reference_variance * 0.5);
+    }
 }
@@ -0,0 +1,420 @@
+use crate::colour::PhotonPacket;
+use crate::lights::sample_portals;
+use crate::materials::MaterialSampleResult;
+use crate::raycasting::{IntersectionInfo, Ray, RAY_PACKET_WIDTH};
+use crate::sampler::Sampler;
+
+use super::simple_random_integrator::{bounce_weight, environment_pdf, environment_radiance};
+use super::{bsdf_frame, power_heuristic, Integrator};
+
+use rand::rngs::StdRng;
+use rand::RngCore;
+
+/// The same path tracer as [SimpleRandomIntegrator](super::SimpleRandomIntegrator), but
+/// tracing a whole batch of paths one bounce at a time instead of following each path to
+/// its end by recursion
+///
+/// Each bounce finds the intersections of every live path together, in packets, then
+/// shades them all, queueing the shadow rays for direct lighting, which are then all
+/// traced together too. The stack doesn't grow with the length of the paths, and the work
+/// at each stage is the same for every path, which suits SIMD and GPU implementations.
+///
+/// Participating media aren't supported yet, so paths pass through them as if they were
+/// empty.
+pub struct WavefrontIntegrator {}
+
+/// A path being traced
+struct Path {
+    /// The ray leaving the last bounce, which is to be traced next
+    ray: Ray,
+
+    /// The wavelengths being traced, with zero intensity
+    wavelengths: PhotonPacket,
+
+    /// The fraction of the light arriving along `ray` that reaches the camera, at each
+    /// wavelength
+    throughput: PhotonPacket,
+
+    /// Light found so far that reaches the camera
+    radiance: PhotonPacket,
+
+    /// Whether only the hero wavelength is still carried, after meeting a dispersive
+    /// material
+    is_hero_only: bool,
+
+    /// How much of the environment seen along `ray` to count, which is all of it for camera
+    /// rays
+    environment_weight: f64,
+
+    /// Bounces left before the path is terminated
+    recursion_limit: u16,
+
+    is_finished: bool,
+}
+
+/// A ray that adds `contribution` to a path's radiance unless something blocks it
+struct ShadowRay {
+    path: usize,
+    ray: Ray,
+    max_distance: f64,
+    contribution: PhotonPacket,
+}
+
+/// Multiply the intensities of corresponding photons in packets of the same wavelengths
+fn multiply(a: &PhotonPacket, b: &PhotonPacket) -> PhotonPacket {
+    let mut others = b.photons().iter();
+    a.map(|photon| photon.scale_intensity(others.next().unwrap().intensity))
+}
+
+impl WavefrontIntegrator {
+    /// Shade the surface that `path` hit at `info`, adding any emitted light to the path
+    /// and queueing shadow rays towards the lights, then choose the next direction
+    fn shade<R: RngCore>(
+        &self,
+        sampler: &dyn Sampler,
+        index: usize,
+        path: &mut Path,
+        info: &IntersectionInfo,
+        rng: &mut R,
+        shadow_rays: &mut Vec<ShadowRay>,
+    ) {
+        if path.recursion_limit == 0 {
+            path.is_finished = true;
+            return;
+        }
+        if info.material.is_dispersive() && !path.is_hero_only {
+            // As PhotonPacket::expand_hero(), the hero carries the light for the packet
+            let scale = path.wavelengths.hero_weight();
+            let mut hero_only = path.wavelengths.set_intensity(0.0).photons().to_vec();
+            hero_only[0] = path.throughput.hero().scale_intensity(scale);
+            let mut photons = hero_only.into_iter();
+            path.throughput = path.throughput.map(|_| photons.next().unwrap());
+            path.is_hero_only = true;
+        }
+        let bsdf_frame = bsdf_frame(info);
+        let w_i = bsdf_frame.to_local(&info.retro);
+        let hero = path.wavelengths.hero();
+        let MaterialSampleResult {
+            direction: w_o,
+            pdf: w_o_pdf,
+            is_specular,
+        } = info.material.sample(&info.uv, &w_i, hero, rng);
+        let world_space_w_o = bsdf_frame.to_world(&w_o);
+        let emitted = path
+            .wavelengths
+            .map(|photon| info.material.emission(&w_i, photon));
+        path.radiance = path.radiance.add(&multiply(&path.throughput, &emitted));
+        let bsdf = info.bsdf();
+        let sample_environment = !is_specular;
+        if sample_environment {
+            let direction = if sampler.scene().portals.is_empty() {
+                Some(
+                    sampler
+                        .scene()
+                        .environment
+                        .direction_distribution()
+                        .value(rng),
+                )
+            } else {
+                sample_portals(&sampler.scene().portals, &info.location, rng)
+                    .map(|(direction, _)| direction)
+            };
+            if let Some(direction) = direction {
+                let w_l = bsdf_frame.to_local(&direction);
+                let light_pdf = environment_pdf(sampler, &info.location, &direction, &w_l);
+                let material_pdf = info.material.pdf(&info.uv, &w_i, &w_l, hero);
+                if light_pdf > 0.0 && w_l.z() > 0.0 {
+                    let weight = power_heuristic(light_pdf, material_pdf)
+                        * bounce_weight(light_pdf, false, &w_l);
+                    let contribution = environment_radiance(sampler, &direction, &path.wavelengths)
+                        .scale_intensity(weight)
+                        .map(|photon| bsdf(&w_l, &w_i, photon));
+                    shadow_rays.push(ShadowRay {
+                        path: index,
+                        ray: info.spawn_ray(&direction),
+                        max_distance: f64::INFINITY,
+                        contribution: multiply(&path.throughput, &contribution),
+                    });
+                }
+            }
+            for light in &sampler.scene().lights {
+                if let Some(sample) =
+                    sampler.sample_light(light.as_ref(), &info.location, &path.wavelengths, rng)
+                {
+                    let w_l = bsdf_frame.to_local(&sample.direction);
+                    if w_l.z() <= 0.0 {
+                        continue;
+                    }
+                    let contribution = sample
+                        .radiance
+                        .scale_intensity(w_l.z() / sample.pdf)
+                        .map(|photon| bsdf(&w_l, &w_i, photon));
+                    shadow_rays.push(ShadowRay {
+                        path: index,
+                        ray: info.spawn_ray(&sample.direction),
+                        max_distance: sample.distance,
+                        contribution: multiply(&path.throughput, &contribution),
+                    });
+                }
+            }
+        }
+        let mut ray = info.spawn_ray(&world_space_w_o);
+        if let (true, Some(footprint)) = (is_specular, info.footprint) {
+            ray = ray.with_differential(footprint.specular_bounce(
+                &info.location,
+                &info.normal,
+                &-info.retro,
+                &world_space_w_o,
+            ));
+        }
+        path.environment_weight = if sample_environment {
+            power_heuristic(
+                w_o_pdf,
+                environment_pdf(sampler, &info.location, &world_space_w_o, &w_o),
+            )
+        } else {
+            1.0
+        };
+        path.throughput = path
+            .throughput
+            .scale_intensity(bounce_weight(w_o_pdf, is_specular, &w_o))
+            .map(|photon| bsdf(&w_o, &w_i, photon));
+        path.ray = ray;
+        path.recursion_limit -= 1;
+        // Nothing more can reach the camera along this path
+        if path
+            .throughput
+            .photons()
+            .iter()
+            .all(|photon| photon.intensity == 0.0)
+        {
+            path.is_finished = true;
+        }
+    }
+
+    /// Follow `paths` to their ends, a bounce at a time
+    fn trace_paths<R: RngCore>(&self, sampler: &dyn Sampler, paths: &mut [Path], rngs: &mut [R]) {
+        let mut shadow_rays = vec![];
+        loop {
+            let live: Vec<usize> = (0..paths.len())
+                .filter(|&index| !paths[index].is_finished)
+                .collect();
+            if live.is_empty() {
+                return;
+            }
+            for chunk in live.chunks(RAY_PACKET_WIDTH) {
+                let rays: Vec<Ray> = chunk
+                    .iter()
+                    .map(|&index| paths[index].ray.clone())
+                    .collect();
+                let hits = sampler.sample_packet(&rays);
+                for (&index, hit) in chunk.iter().zip(IntoIterator::into_iter(hits)) {
+                    let path = &mut paths[index];
+                    match hit {
+                        Some(info) => self.shade(
+                            sampler,
+                            index,
+                            path,
+                            &info,
+                            &mut rngs[index],
+                            &mut shadow_rays,
+                        ),
+                        None => {
+                            let environment = environment_radiance(
+                                sampler,
+                                &path.ray.direction,
+                                &path.wavelengths,
+                            )
+                            .scale_intensity(path.environment_weight);
+                            path.radiance =
+                                path.radiance.add(&multiply(&path.throughput, &environment));
+                            path.is_finished = true;
+                        }
+                    }
+                }
+            }
+            for shadow_ray in shadow_rays.drain(..) {
+                if !sampler.is_occluded(&shadow_ray.ray, shadow_ray.max_distance) {
+                    let path = &mut paths[shadow_ray.path];
+                    path.radiance = path.radiance.add(&shadow_ray.contribution);
+                }
+            }
+        }
+    }
+
+    fn new_path(ray: Ray, packet: &PhotonPacket, recursion_limit: u16) -> Path {
+        Path {
+            ray,
+            wavelengths: packet.set_intensity(0.0),
+            throughput: packet.set_intensity(1.0),
+            radiance: packet.set_intensity(0.0),
+            is_hero_only: false,
+            environment_weight: 1.0,
+            recursion_limit,
+            is_finished: false,
+        }
+    }
+}
+
+impl Integrator for WavefrontIntegrator {
+    fn integrate_hit(
+        &self,
+        sampler: &dyn Sampler,
+        ray: &Ray,
+        hit: Option<IntersectionInfo>,
+        packet: &PhotonPacket,
+        recursion_limit: u16,
+        mut rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
+        let info = match hit {
+            Some(info) => info,
+            None => return environment_radiance(sampler, &ray.direction, packet),
+        };
+        // The first ray is only needed to find this hit, which is already known
+        let mut paths = [WavefrontIntegrator::new_path(
+            ray.clone(),
+            packet,
+            recursion_limit,
+        )];
+        let mut shadow_rays = vec![];
+        self.shade(sampler, 0, &mut paths[0], &info, &mut rng, &mut shadow_rays);
+        for shadow_ray in shadow_rays {
+            if !sampler.is_occluded(&shadow_ray.ray, shadow_ray.max_distance) {
+                paths[0].radiance = paths[0].radiance.add(&shadow_ray.contribution);
+            }
+        }
+        self.trace_paths(sampler, &mut paths, std::slice::from_mut(&mut rng));
+        let [path] = paths;
+        path.radiance
+    }
+
+    fn integrate_batch(
+        &self,
+        sampler: &dyn Sampler,
+        rays: &[Ray],
+        packets: &[PhotonPacket],
+        recursion_limit: u16,
+        rngs: &mut [StdRng],
+    ) -> Vec<PhotonPacket> {
+        assert!(rays.len() == packets.len() && rays.len() == rngs.len());
+        let mut paths: Vec<Path> = rays
+            .iter()
+            .zip(packets.iter())
+            .map(|(ray, packet)| {
+                WavefrontIntegrator::new_path(ray.clone(), packet, recursion_limit)
+            })
+            .collect();
+        self.trace_paths(sampler, &mut paths, rngs);
+        paths.into_iter().map(|path| path.radiance).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+    use crate::colour::Spectrum;
+    use crate::integrators::SimpleRandomIntegrator;
+    use crate::lights::PointLight;
+    use crate::materials::{LambertianMaterial, SmoothTransparentDialectric};
+    use crate::math::Vec3;
+    use crate::raycasting::{Plane, Primitive, Sphere};
+    use crate::sampler::SceneSampler;
+    use crate::scene::Scene;
+
+    use rand::SeedableRng;
+
+    use std::sync::Arc;
+
+    fn test_scene() -> Scene {
+        Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            Lens::Pinhole,
+        )))
+        .with_object(Box::new(vec![
+            Box::new(Plane::new(
+                Vec3::new(0.0, 1.0, 0.0),
+                0.0,
+                Arc::new(LambertianMaterial {
+                    colour: Spectrum::grey(0.5),
+                    diffuse_strength: 1.0,
+                }),
+            )) as Box<dyn Primitive>,
+            Box::new(Sphere::new(
+                Vec3::new(0.5, 0.5, 0.5),
+                0.3,
+                Arc::new(SmoothTransparentDialectric::new(
+                    Spectrum::diamond_index_of_refraction(),
+                )),
+            )),
+        ]))
+        .with_light(Box::new(PointLight::new(
+            Vec3::new(0.0, 2.0, 0.0),
+            Spectrum::grey(1.0),
+        )))
+        .build()
+    }
+
+    /// Mean and standard error of the hero radiance of `samples` rays from just above the
+    /// floor
+    fn statistics(integrator: &dyn Integrator, scene: &Scene, samples: usize) -> (f64, f64) {
+        let sampler = SceneSampler { scene };
+        let mut rng = StdRng::seed_from_u64(1);
+        let rays: Vec<Ray> = (0..samples)
+            .map(|i| {
+                let angle = i as f64 * 0.01;
+                Ray::new(
+                    Vec3::new(0.0, 1.0, 0.0),
+                    Vec3::new(angle.sin() * 0.5, -1.0, angle.cos() * 0.5),
+                )
+            })
+            .collect();
+        let packets: Vec<PhotonPacket> = (0..samples)
+            .map(|_| PhotonPacket::random_wavelengths(&mut rng))
+            .collect();
+        let mut rngs: Vec<StdRng> = (0..samples)
+            .map(|i| StdRng::seed_from_u64(i as u64))
+            .collect();
+        let values: Vec<f64> = integrator
+            .integrate_batch(&sampler, &rays, &packets, 8, &mut rngs)
+            .iter()
+            .map(|packet| packet.hero().intensity)
+            .collect();
+        let mean = values.iter().sum::<f64>() / samples as f64;
+        let variance =
+            values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (samples - 1) as f64;
+        (mean, (variance / samples as f64).sqrt())
+    }
+
+    #[test]
+    fn matches_recursive_path_tracer() {
+        let scene = test_scene();
+        let (expected, expected_error) = statistics(&SimpleRandomIntegrator {}, &scene, 20000);
+        let (actual, actual_error) = statistics(&WavefrontIntegrator {}, &scene, 20000);
+        assert!(expected > 0.0);
+        assert!((actual - expected).abs() < 4.0 * (expected_error + actual_error));
+    }
+
+    #[test]
+    fn camera_rays_that_miss_see_environment() {
+        let scene = test_scene();
+        let sampler = SceneSampler { scene: &scene };
+        let mut rng = StdRng::seed_from_u64(0);
+        let packet = PhotonPacket::random_wavelengths(&mut rng);
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let expected = environment_radiance(&sampler, &ray.direction, &packet);
+        let result = WavefrontIntegrator {}.integrate_batch(
+            &sampler,
+            &[ray],
+            &[packet],
+            8,
+            &mut [StdRng::seed_from_u64(0)],
+        );
+        assert!(result[0]
+            .photons()
+            .iter()
+            .zip(expected.photons())
+            .all(|(photon, expected)| photon.intensity == expected.intensity));
+    }
+}
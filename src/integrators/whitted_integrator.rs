@@ -1,87 +1,164 @@
-use crate::colour::{Photon, Spectrum};
+use crate::colour::{PhotonPacket, Spectrum};
+use crate::lights::LightSample;
 use crate::materials::MaterialSampleResult;
-use crate::math::Vec3;
-use crate::raycasting::{IntersectionInfo, Ray};
+use crate::raycasting::{IntersectionInfo, Ray, SampleSurface, SurfaceSample};
 use crate::sampler::Sampler;
-use crate::util::algebra_utils::try_change_of_basis_matrix;
 
-use super::Integrator;
+use super::simple_random_integrator::{bounce_weight, environment_radiance};
+use super::{bsdf_frame, Integrator};
 
-pub struct DirectionalLight {
-    pub direction: Vec3,
-    pub spectrum: Spectrum,
-}
+use rand::RngCore;
+
+use std::sync::Arc;
 
+/// Traces specular paths, lighting each surface directly from the scene's
+/// [lights](crate::scene::Scene::lights)
 pub struct WhittedIntegrator {
+    /// Light added in place of each light that a surface is shadowed from
     pub ambient_light: Spectrum,
-    pub lights: Vec<DirectionalLight>,
+
+    /// Primitives with an emissive material, which are sampled directly as light sources
+    pub area_lights: Vec<Arc<dyn SampleSurface>>,
 }
 
-impl Integrator for WhittedIntegrator {
-    fn integrate(
+impl WhittedIntegrator {
+    /// Sample the light arriving at `info` from a random point on `light`
+    fn sample_area_light(
+        &self,
+        sampler: &dyn Sampler,
+        info: &IntersectionInfo,
+        light: &dyn SampleSurface,
+        packet: &PhotonPacket,
+        rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
+        let SurfaceSample { location, pdf, .. } = light.sample_surface(rng);
+        let to_light = location - info.location;
+        let distance = to_light.norm();
+        let direction = to_light * (1.0 / distance);
+        match sampler.sample(&info.spawn_ray(&direction)) {
+            // Anything hit short of the sampled point is an occluder
+            Some(light_hit)
+                if (light_hit.location - location).norm() < 0.000_001 * distance.max(1.0) =>
+            {
+                let light_frame = bsdf_frame(&light_hit);
+                // Convert the area pdf into a solid-angle pdf as seen from info.location
+                let solid_angle_pdf =
+                    pdf * distance * distance / light_hit.retro.dot(&light_hit.normal).abs();
+                let bsdf_frame = bsdf_frame(info);
+                let bsdf = info.bsdf();
+                packet.map(|photon| {
+                    bsdf(
+                        &bsdf_frame.to_local(&direction),
+                        &bsdf_frame.to_local(&info.retro),
+                        &light_hit
+                            .material
+                            .emission(&light_frame.to_local(&light_hit.retro), photon)
+                            .scale_intensity(direction.dot(&info.normal).abs() / solid_angle_pdf),
+                    )
+                })
+            }
+            _ => packet.scale_intensity(0.0),
+        }
+    }
+
+    /// The light leaving `info` back along the ray that hit it
+    fn shade(
         &self,
-        sampler: &Sampler,
+        sampler: &dyn Sampler,
         info: &IntersectionInfo,
-        photon: &Photon,
+        packet: &PhotonPacket,
         recursion_limit: u16,
-    ) -> Photon {
-        let world_to_bsdf_space =
-            try_change_of_basis_matrix(&info.tangent, &info.cotangent, &info.normal)
-                .expect("Normal, tangent and cotangent don't for a valid basis.");
-        let bsdf_to_world_space = world_to_bsdf_space
-            .try_inverse()
-            .expect("Expected matrix to be invertable.");
-        self.lights
+        rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
+        if info.material.is_dispersive() && !packet.is_single_wavelength() {
+            let hero = packet.hero_only();
+            return packet.expand_hero(&self.shade(sampler, info, &hero, recursion_limit, rng));
+        }
+        let bsdf_frame = bsdf_frame(info);
+        let bsdf = info.bsdf();
+        let light_samples: Vec<PhotonPacket> = sampler
+            .scene()
+            .lights
             .iter()
-            .map(|light| {
-                match sampler.sample(&Ray::new(info.location, light.direction).bias(0.000_000_1)) {
-                    Some(_) => self.ambient_light.emit_photon(photon),
-                    None => info.material.bsdf()(
-                        &(world_to_bsdf_space * info.retro),
-                        &(world_to_bsdf_space * light.direction),
-                        &light
-                            .spectrum
-                            .emit_photon(photon)
-                            .scale_intensity(light.direction.dot(&info.normal).abs()),
-                    ),
-                }
-            })
-            .chain(
-                [info
-                    .material
-                    .sample(&(world_to_bsdf_space * info.retro), photon)]
-                .iter()
-                .map(|MaterialSampleResult { direction, pdf: _ }| {
-                    let world_space_direction = bsdf_to_world_space * direction;
-                    match sampler
-                        .sample(&Ray::new(info.location, world_space_direction).bias(0.000_000_1))
-                    {
-                        Some(recursive_hit) => {
-                            if recursion_limit > 0 {
-                                let photon = info.material.bsdf()(
-                                    &(world_to_bsdf_space * info.retro),
-                                    direction,
-                                    &self.integrate(
-                                        sampler,
-                                        &recursive_hit,
-                                        photon,
-                                        recursion_limit - 1,
-                                    ),
-                                );
-                                photon
-                                    .scale_intensity(world_space_direction.dot(&info.normal).abs())
-                            } else {
-                                photon.scale_intensity(0.0)
-                            }
-                        }
-                        None => photon.scale_intensity(0.0),
+            .map(
+                |light| match sampler.sample_light(light.as_ref(), &info.location, packet, rng) {
+                    Some(LightSample {
+                        direction,
+                        distance,
+                        radiance,
+                        pdf,
+                    }) if !sampler.is_occluded(&info.spawn_ray(&direction), distance) => {
+                        let cos_theta = direction.dot(&info.normal).abs();
+                        radiance.scale_intensity(cos_theta / pdf).map(|photon| {
+                            bsdf(
+                                &bsdf_frame.to_local(&direction),
+                                &bsdf_frame.to_local(&info.retro),
+                                photon,
+                            )
+                        })
                     }
-                }),
+                    _ => packet.map(|photon| self.ambient_light.emit_photon(photon)),
+                },
             )
-            .fold(photon.clone(), |a, b| {
-                let mut result = a;
-                result.intensity += b.intensity;
-                result
-            })
+            .collect();
+        let area_light_samples: Vec<PhotonPacket> = self
+            .area_lights
+            .iter()
+            .map(|light| self.sample_area_light(sampler, info, light.as_ref(), packet, rng))
+            .collect();
+        let material_sample = info.material.sample(
+            &info.uv,
+            &bsdf_frame.to_local(&info.retro),
+            packet.hero(),
+            rng,
+        );
+        light_samples
+            .into_iter()
+            .chain(area_light_samples)
+            .chain(std::iter::once(packet.map(|photon| {
+                info.material
+                    .emission(&bsdf_frame.to_local(&info.retro), photon)
+            })))
+            .chain(std::iter::once(material_sample).map(
+                |MaterialSampleResult {
+                     direction,
+                     pdf,
+                     is_specular,
+                 }| {
+                    if recursion_limit == 0 {
+                        return packet.scale_intensity(0.0);
+                    }
+                    let world_space_direction = bsdf_frame.to_world(&direction);
+                    self.integrate_ray(
+                        sampler,
+                        &info.spawn_ray(&world_space_direction),
+                        packet,
+                        recursion_limit - 1,
+                        rng,
+                    )
+                    .scale_intensity(bounce_weight(pdf, is_specular, &direction))
+                    .map(|photon| bsdf(&direction, &bsdf_frame.to_local(&info.retro), photon))
+                },
+            ))
+            .fold(packet.clone(), |a, b| a.add(&b))
+    }
+}
+
+impl Integrator for WhittedIntegrator {
+    /// Rays that miss the scene, whether from the camera or reflected, see the environment,
+    /// though surfaces aren't lit by it
+    fn integrate_hit(
+        &self,
+        sampler: &dyn Sampler,
+        ray: &Ray,
+        hit: Option<IntersectionInfo>,
+        packet: &PhotonPacket,
+        recursion_limit: u16,
+        rng: &mut dyn RngCore,
+    ) -> PhotonPacket {
+        match hit {
+            Some(info) => self.shade(sampler, &info, packet, recursion_limit, rng),
+            None => environment_radiance(sampler, &ray.direction, packet),
+        }
     }
 }
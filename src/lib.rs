@@ -1,18 +1,34 @@
 #![doc = include_str!("../README.md")]
 
 pub mod accumulation_buffer;
-mod camera;
+pub mod aovs;
+pub mod bucketed_image;
+pub mod camera;
 pub mod colour;
+pub mod denoiser;
+pub mod distributed;
+pub mod error;
+pub mod filters;
 pub mod image;
 pub mod integrators;
+pub mod lights;
 pub mod materials;
 pub mod math;
+pub mod media;
 pub mod mesh;
+pub mod path_debug;
+pub mod progressive_renderer;
 pub mod random_distributions;
 pub mod raycasting;
 pub mod realtype;
+pub mod renderer;
 pub mod sampler;
 pub mod scene;
+pub mod spectral_accumulation_buffer;
+pub mod statistics;
+pub mod textures;
 pub mod util;
+pub mod validation;
 
 pub use camera::partial_render_scene;
+pub use error::VanrijnError;
@@ -0,0 +1,41 @@
+use crate::colour::{PhotonPacket, Spectrum};
+use crate::math::Vec3;
+
+use super::{Light, LightSample};
+
+use rand::RngCore;
+
+/// A light infinitely far away, such as the sun, which lights the whole scene from the same
+/// direction
+#[derive(Debug)]
+pub struct DirectionalLight {
+    /// Direction towards the light
+    pub direction: Vec3,
+
+    /// The irradiance on a surface facing the light
+    pub spectrum: Spectrum,
+}
+
+impl Light for DirectionalLight {
+    fn sample_incident(
+        &self,
+        _location: &Vec3,
+        packet: &PhotonPacket,
+        _rng: &mut dyn RngCore,
+    ) -> Option<LightSample> {
+        Some(LightSample {
+            direction: self.direction,
+            distance: f64::INFINITY,
+            radiance: packet.map(|photon| self.spectrum.emit_photon(photon)),
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf(&self, _location: &Vec3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn power(&self, wavelength: f64) -> f64 {
+        self.spectrum.intensity_at_wavelength(wavelength)
+    }
+}
@@ -0,0 +1,130 @@
+use crate::colour::Spectrum;
+use crate::error::VanrijnError;
+use crate::image::ImageRgbF;
+use crate::math::Vec3;
+use crate::random_distributions::{EquirectangularDistribution, RandomDistribution};
+use crate::util::Array2D;
+
+use super::EnvironmentLight;
+
+use std::path::Path;
+
+/// An environment lit by an equirectangular (latitude-longitude) image, such as an HDR sky
+/// capture
+///
+/// The top row of the image is straight up (+Y). Directions are importance-sampled in
+/// proportion to the luminance of the image.
+pub struct ImageEnvironmentLight {
+    /// Emission spectra of the [reference colours](Spectrum::rgb_reference_colours)
+    reference_spectra: Vec<Spectrum>,
+
+    /// How much of each reference spectrum goes into each pixel
+    reference_weights: Array2D<[f64; 7]>,
+
+    distribution: EquirectangularDistribution,
+}
+
+impl ImageEnvironmentLight {
+    pub fn new(image: ImageRgbF) -> ImageEnvironmentLight {
+        let mut luminance = Array2D::new(image.get_height(), image.get_width());
+        let mut reference_weights = Array2D::new(image.get_height(), image.get_width());
+        for row in 0..image.get_height() {
+            for column in 0..image.get_width() {
+                let colour = image.get_colour(row, column);
+                luminance[row][column] =
+                    0.2126 * colour.red() + 0.7152 * colour.green() + 0.0722 * colour.blue();
+                reference_weights[row][column] = Spectrum::rgb_reference_weights(&colour);
+            }
+        }
+        let distribution = EquirectangularDistribution::new(&luminance);
+        ImageEnvironmentLight {
+            reference_spectra: Spectrum::rgb_reference_colours()
+                .iter()
+                .map(Spectrum::emission_from_linear_rgb)
+                .collect(),
+            reference_weights,
+            distribution,
+        }
+    }
+
+    /// Load a Radiance (.hdr) image to use as the environment
+    pub fn read_hdr(filename: &Path) -> Result<ImageEnvironmentLight, VanrijnError> {
+        Ok(ImageEnvironmentLight::new(ImageRgbF::read_hdr(filename)?))
+    }
+}
+
+impl EnvironmentLight for ImageEnvironmentLight {
+    fn radiance(&self, direction: &Vec3, wavelength: f64) -> f64 {
+        let (row, column) = self.distribution.pixel_for_direction(direction);
+        self.reference_weights[row][column]
+            .iter()
+            .zip(self.reference_spectra.iter())
+            .filter(|(weight, _)| **weight != 0.0)
+            .map(|(weight, spectrum)| weight * spectrum.intensity_at_wavelength(wavelength))
+            .sum()
+    }
+
+    fn direction_distribution(&self) -> &dyn RandomDistribution<Vec3> {
+        &self.distribution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::ColourRgbF;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn test_light() -> ImageEnvironmentLight {
+        let mut image = ImageRgbF::new(8, 4);
+        // Bright strip along the top row, dim everywhere else
+        for column in 0..8 {
+            image.set_colour(0, column, ColourRgbF::new(10.0, 10.0, 10.0));
+            for row in 1..4 {
+                image.set_colour(row, column, ColourRgbF::new(0.1, 0.1, 0.1));
+            }
+        }
+        ImageEnvironmentLight::new(image)
+    }
+
+    #[test]
+    fn radiance_comes_from_matching_pixel() {
+        let target = test_light();
+        assert!(target.radiance(&Vec3::unit_y(), 550.0) > 5.0);
+        assert!(target.radiance(&-Vec3::unit_y(), 550.0) < 0.5);
+    }
+
+    #[test]
+    fn radiance_matches_emission_spectrum_of_pixel() {
+        let mut image = ImageRgbF::new(8, 4);
+        for row in 0..4 {
+            for column in 0..8 {
+                image.set_colour(row, column, ColourRgbF::new(0.9, 0.4, 0.2));
+            }
+        }
+        let target = ImageEnvironmentLight::new(image);
+        let expected = Spectrum::emission_from_linear_rgb(&ColourRgbF::new(0.9, 0.4, 0.2));
+        for wavelength in [450.0, 550.0, 650.0] {
+            assert!(
+                (target.radiance(&Vec3::unit_x(), wavelength)
+                    - expected.intensity_at_wavelength(wavelength))
+                .abs()
+                    < 1e-12
+            );
+        }
+    }
+
+    #[test]
+    fn samples_favour_bright_pixels() {
+        let target = test_light();
+        let distribution = target.direction_distribution();
+        let mut rng = StdRng::seed_from_u64(0);
+        let upward = (0..1000)
+            .filter(|_| distribution.value(&mut rng).y() > (std::f64::consts::PI / 4.0).cos())
+            .count();
+        assert!(upward > 900);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::colour::PhotonPacket;
+use crate::math::Vec3;
+use crate::random_distributions::RandomDistribution;
+
+use rand::RngCore;
+
+pub mod sky_gradient;
+pub use sky_gradient::SkyGradient;
+
+pub mod image_environment_light;
+pub use image_environment_light::ImageEnvironmentLight;
+
+pub mod preetham_sky;
+pub use preetham_sky::PreethamSky;
+
+pub mod solar_position;
+pub use solar_position::SolarPosition;
+
+pub mod point_light;
+pub use point_light::PointLight;
+
+pub mod spot_light;
+pub use spot_light::SpotLight;
+
+pub mod directional_light;
+pub use directional_light::DirectionalLight;
+
+pub mod portal;
+pub use portal::{portals_pdf, sample_portals, Portal};
+
+/// Light arriving from infinitely far away, such as the sky
+///
+/// This is what a ray sees when it leaves the scene without hitting anything.
+pub trait EnvironmentLight: Send + Sync {
+    /// The radiance arriving from `direction`, at `wavelength`
+    ///
+    /// `direction` is in world space and points away from the scene.
+    fn radiance(&self, direction: &Vec3, wavelength: f64) -> f64;
+
+    /// A distribution of directions for importance-sampling the light
+    fn direction_distribution(&self) -> &dyn RandomDistribution<Vec3>;
+}
+
+/// Light arriving at a point from a [Light](Light), in a direction chosen by the light
+#[derive(Clone, Debug)]
+pub struct LightSample {
+    /// Direction from the lit point towards the light
+    pub direction: Vec3,
+
+    /// Distance from the lit point to the light, which is infinite for lights infinitely far
+    /// away
+    ///
+    /// Anything closer than this along `direction` casts a shadow.
+    pub distance: f64,
+
+    /// The light arriving, for each wavelength of the packet the sample was taken for
+    ///
+    /// For lights that can only be reached in one direction this is the irradiance on a
+    /// surface facing the light, rather than a radiance.
+    pub radiance: PhotonPacket,
+
+    /// The density with which `direction` was chosen, over solid angle
+    ///
+    /// This is one for lights that can only be reached in one direction.
+    pub pdf: f64,
+}
+
+/// A light source in the scene, which integrators sample directly
+///
+/// Unlike an [EnvironmentLight](EnvironmentLight), lights don't have to be infinitely far
+/// away, and rays that leave the scene don't see them.
+pub trait Light: Send + Sync {
+    /// Choose a direction from `location` towards the light, and find the light arriving
+    /// along it at each wavelength in `packet`
+    ///
+    /// Returns `None` if no light from it reaches `location`.
+    fn sample_incident(
+        &self,
+        location: &Vec3,
+        packet: &PhotonPacket,
+        rng: &mut dyn RngCore,
+    ) -> Option<LightSample>;
+
+    /// The density with which [sample_incident()](Light::sample_incident) chooses
+    /// `direction` from `location`, over solid angle
+    ///
+    /// This is zero for lights that can only be reached in one direction, since a
+    /// direction chosen any other way never finds them.
+    fn pdf(&self, location: &Vec3, direction: &Vec3) -> f64;
+
+    /// The total power the light emits at `wavelength`
+    ///
+    /// Lights infinitely far away have no finite power, so for them this is the power per
+    /// unit area facing the light.
+    fn power(&self, wavelength: f64) -> f64;
+}
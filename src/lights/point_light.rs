@@ -0,0 +1,84 @@
+use crate::colour::{PhotonPacket, Spectrum};
+use crate::math::Vec3;
+
+use super::{Light, LightSample};
+
+use rand::RngCore;
+
+use std::f64::consts::PI;
+
+/// A light that shines equally in every direction from a single point
+#[derive(Debug)]
+pub struct PointLight {
+    pub position: Vec3,
+
+    /// The power emitted per unit solid angle
+    pub intensity: Spectrum,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Spectrum) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_incident(
+        &self,
+        location: &Vec3,
+        packet: &PhotonPacket,
+        _rng: &mut dyn RngCore,
+    ) -> Option<LightSample> {
+        let to_light = self.position - *location;
+        let distance = to_light.norm();
+        if distance == 0.0 {
+            return None;
+        }
+        let falloff = 1.0 / (distance * distance);
+        Some(LightSample {
+            direction: to_light * (1.0 / distance),
+            distance,
+            radiance: packet
+                .map(|photon| self.intensity.emit_photon(photon))
+                .scale_intensity(falloff),
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf(&self, _location: &Vec3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn power(&self, wavelength: f64) -> f64 {
+        4.0 * PI * self.intensity.intensity_at_wavelength(wavelength)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::Photon;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn light_falls_off_with_square_of_distance() {
+        let target = PointLight::new(Vec3::new(0.0, 2.0, 0.0), Spectrum::grey(8.0));
+        let packet = PhotonPacket::from_photon(&Photon {
+            wavelength: 550.0,
+            intensity: 0.0,
+        });
+        let sample = target
+            .sample_incident(&Vec3::zeros(), &packet, &mut StdRng::seed_from_u64(0))
+            .unwrap();
+        assert!((sample.direction - Vec3::unit_y()).norm() < 0.000000001);
+        assert!((sample.distance - 2.0).abs() < 0.000000001);
+        assert!((sample.radiance.hero().intensity - 2.0).abs() < 0.000000001);
+        assert!((target.power(550.0) - 32.0 * PI).abs() < 0.000000001);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::math::Vec3;
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+/// An opening, such as a window, through which the environment lights an enclosed space
+///
+/// Seen from inside a room, most directions lead to a wall, so sampling the whole
+/// environment wastes nearly every sample. When a scene has portals,
+/// [environment](crate::scene::Scene::environment) light is sampled only through them
+/// instead. Directions that don't pass through a portal are still found by following
+/// material samples, so having too few portals makes no difference to the result, only to
+/// the noise.
+///
+/// Like a [Rect](crate::raycasting::Rect), a portal is a parallelogram spanned by two edges
+/// from a corner. It isn't part of the scene's geometry, so it doesn't block any light.
+#[derive(Clone, Debug)]
+pub struct Portal {
+    pub corner: Vec3,
+    pub edge_u: Vec3,
+    pub edge_v: Vec3,
+}
+
+impl Portal {
+    pub fn new(corner: Vec3, edge_u: Vec3, edge_v: Vec3) -> Portal {
+        Portal {
+            corner,
+            edge_u,
+            edge_v,
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        self.edge_u.cross(&self.edge_v).norm()
+    }
+
+    /// Convert the density of choosing `point` on the portal by area into a density over
+    /// solid angle as seen from `location`
+    fn solid_angle_pdf(&self, location: &Vec3, point: &Vec3) -> f64 {
+        let to_point = point - location;
+        let distance_squared = to_point.norm_squared();
+        let normal = self.edge_u.cross(&self.edge_v).normalize();
+        let cos_theta = normal.dot(&to_point).abs() / distance_squared.sqrt();
+        if cos_theta == 0.0 {
+            return 0.0;
+        }
+        distance_squared / (cos_theta * self.area())
+    }
+
+    /// A direction from `location` through a point chosen uniformly over the portal, and the
+    /// density over solid angle with which it was chosen
+    ///
+    /// Returns `None` if `location` lies in the plane of the portal.
+    pub fn sample_direction(&self, location: &Vec3, rng: &mut dyn RngCore) -> Option<(Vec3, f64)> {
+        let u: f64 = rng.sample(Open01);
+        let v: f64 = rng.sample(Open01);
+        let point = self.corner + self.edge_u * u + self.edge_v * v;
+        let pdf = self.solid_angle_pdf(location, &point);
+        if pdf == 0.0 || !pdf.is_finite() {
+            return None;
+        }
+        Some(((point - *location).normalize(), pdf))
+    }
+
+    /// The density with which [sample_direction()](Portal::sample_direction) chooses
+    /// `direction` from `location`, over solid angle
+    pub fn pdf(&self, location: &Vec3, direction: &Vec3) -> f64 {
+        let area_normal = self.edge_u.cross(&self.edge_v);
+        let direction_dot_normal = direction.dot(&area_normal);
+        if direction_dot_normal == 0.0 {
+            return 0.0;
+        }
+        let distance = (self.corner - *location).dot(&area_normal) / direction_dot_normal;
+        if distance <= 0.0 {
+            return 0.0;
+        }
+        let point = location + direction * distance;
+        let offset = point - self.corner;
+        let one_over_area_squared = 1.0 / area_normal.norm_squared();
+        let u = offset.cross(&self.edge_v).dot(&area_normal) * one_over_area_squared;
+        let v = self.edge_u.cross(&offset).dot(&area_normal) * one_over_area_squared;
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return 0.0;
+        }
+        self.solid_angle_pdf(location, &point)
+    }
+}
+
+/// A direction from `location` through one of `portals`, chosen at random, and the density
+/// over solid angle with which it was chosen
+///
+/// Each portal is equally likely to be chosen. The density accounts for every portal the
+/// direction passes through, so it matches [portals_pdf()](portals_pdf).
+pub fn sample_portals(
+    portals: &[Portal],
+    location: &Vec3,
+    rng: &mut dyn RngCore,
+) -> Option<(Vec3, f64)> {
+    if portals.is_empty() {
+        return None;
+    }
+    let index = rng.gen_range(0, portals.len());
+    let (direction, _) = portals[index].sample_direction(location, rng)?;
+    Some((direction, portals_pdf(portals, location, &direction)))
+}
+
+/// The density with which [sample_portals()](sample_portals) chooses `direction` from
+/// `location`, over solid angle
+pub fn portals_pdf(portals: &[Portal], location: &Vec3, direction: &Vec3) -> f64 {
+    portals
+        .iter()
+        .map(|portal| portal.pdf(location, direction))
+        .sum::<f64>()
+        / portals.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn window() -> Portal {
+        Portal::new(
+            Vec3::new(-1.0, 2.0, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+        )
+    }
+
+    #[test]
+    fn sampled_directions_pass_through_portal() {
+        let target = window();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let (direction, pdf) = target.sample_direction(&Vec3::zeros(), &mut rng).unwrap();
+            assert!((target.pdf(&Vec3::zeros(), &direction) - pdf).abs() < 0.000001 * pdf);
+        }
+        assert!(target.pdf(&Vec3::zeros(), &-Vec3::unit_y()) == 0.0);
+        assert!(target.pdf(&Vec3::zeros(), &Vec3::new(1.0, 0.1, 0.0).normalize()) == 0.0);
+    }
+
+    #[test]
+    fn pdf_integrates_to_one_over_sphere() {
+        let portals = [window(), window()];
+        let mut rng = StdRng::seed_from_u64(0);
+        let samples = 200000;
+        // Uniform sphere samples have density 1 / 4π
+        let integral = (0..samples)
+            .map(|_| {
+                let z: f64 = 2.0 * rng.sample::<f64, _>(Open01) - 1.0;
+                let phi: f64 = 2.0 * std::f64::consts::PI * rng.sample::<f64, _>(Open01);
+                let r = (1.0 - z * z).sqrt();
+                let direction = Vec3::new(r * phi.cos(), z, r * phi.sin());
+                portals_pdf(&portals, &Vec3::zeros(), &direction) * 4.0 * std::f64::consts::PI
+            })
+            .sum::<f64>()
+            / samples as f64;
+        assert!((integral - 1.0).abs() < 0.02);
+    }
+}
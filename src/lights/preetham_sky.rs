@@ -0,0 +1,252 @@
+use crate::colour::daylight::{daylight_basis, daylight_weights, DAYLIGHT_SHORTEST_WAVELENGTH};
+use crate::colour::ColourXyz;
+use crate::math::Vec3;
+use crate::random_distributions::{RandomDistribution, SkyLightPdf};
+
+use super::EnvironmentLight;
+
+use std::f64::consts::FRAC_PI_2;
+
+/// Luminous efficacy of monochromatic light at 555nm, in lm/W
+const LUMINOUS_EFFICACY: f64 = 683.0;
+
+/// Coefficients A to E of the Perez sky luminance distribution
+#[derive(Clone, Copy, Debug)]
+struct PerezCoefficients([f64; 5]);
+
+impl PerezCoefficients {
+    /// Coefficients that vary linearly with turbidity, given as (slope, intercept) pairs
+    fn for_turbidity(turbidity: f64, coefficients: [(f64, f64); 5]) -> PerezCoefficients {
+        let mut result = [0.0; 5];
+        for (value, (slope, intercept)) in result.iter_mut().zip(coefficients.iter()) {
+            *value = slope * turbidity + intercept;
+        }
+        PerezCoefficients(result)
+    }
+
+    /// Relative brightness at zenith angle `theta`, `gamma` radians from the sun
+    fn evaluate(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.0;
+        (1.0 + a * (b / cos_theta.max(0.0001)).exp())
+            * (1.0 + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+    }
+}
+
+/// The Preetham analytic model of a clear daytime sky
+///
+/// The sky's brightness and colour depend on the direction of the sun and on the
+/// atmospheric turbidity, which is around 2 for a very clear sky and around 10 for a hazy
+/// one. The model gives luminance and chromaticity, which are converted to a spectrum using
+/// the CIE daylight basis functions. The sky emits absolute radiance, in W/(m^2sr) per
+/// nanometre, so scenes lit by it are much brighter than with [SkyGradient](super::SkyGradient).
+///
+/// The sun disk itself isn't included, and nothing is emitted from below the horizon. The
+/// zenith is +Y.
+///
+/// See A. J. Preetham, P. Shirley and B. Smits, "A Practical Analytic Model for Daylight",
+/// SIGGRAPH 1999.
+pub struct PreethamSky {
+    sun_direction: Vec3,
+    perez: [PerezCoefficients; 3],
+
+    /// Zenith luminance (in cd/m^2) and chromaticity, divided by the Perez function at the
+    /// zenith so that scaling by the Perez function gives absolute values
+    zenith: [f64; 3],
+
+    /// Luminance of each daylight basis function
+    basis_luminance: [f64; 3],
+
+    distribution: SkyLightPdf,
+}
+
+impl PreethamSky {
+    pub fn new(sun_direction: &Vec3, turbidity: f64) -> PreethamSky {
+        let sun_direction = sun_direction.normalize();
+        let theta_sun = sun_direction.y().clamp(-1.0, 1.0).acos().min(FRAC_PI_2);
+        let t = turbidity;
+
+        let perez = [
+            PerezCoefficients::for_turbidity(
+                t,
+                [
+                    (0.1787, -1.4630),
+                    (-0.3554, 0.4275),
+                    (-0.0227, 5.3251),
+                    (0.1206, -2.5771),
+                    (-0.0670, 0.3703),
+                ],
+            ),
+            PerezCoefficients::for_turbidity(
+                t,
+                [
+                    (-0.0193, -0.2592),
+                    (-0.0665, 0.0008),
+                    (-0.0004, 0.2125),
+                    (-0.0641, -0.8989),
+                    (-0.0033, 0.0452),
+                ],
+            ),
+            PerezCoefficients::for_turbidity(
+                t,
+                [
+                    (-0.0167, -0.2608),
+                    (-0.0950, 0.0092),
+                    (-0.0079, 0.2102),
+                    (-0.0441, -1.6537),
+                    (-0.0109, 0.0529),
+                ],
+            ),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (std::f64::consts::PI - 2.0 * theta_sun);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192) * 1000.0;
+        let zenith_chromaticity = |coefficients: [[f64; 4]; 3]| {
+            let thetas = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+            let turbidities = [t * t, t, 1.0];
+            turbidities
+                .iter()
+                .zip(coefficients.iter())
+                .map(|(turbidity, row)| {
+                    turbidity
+                        * row
+                            .iter()
+                            .zip(thetas.iter())
+                            .map(|(c, s)| c * s)
+                            .sum::<f64>()
+                })
+                .sum::<f64>()
+        };
+        let zenith_x = zenith_chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = zenith_chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let mut zenith = [zenith_luminance, zenith_x, zenith_y];
+        for (value, coefficients) in zenith.iter_mut().zip(perez.iter()) {
+            *value /= coefficients.evaluate(1.0, theta_sun);
+        }
+
+        let mut basis_luminance = [0.0; 3];
+        let mut wavelength = DAYLIGHT_SHORTEST_WAVELENGTH;
+        while wavelength <= 780.0 {
+            let y_bar = ColourXyz::for_wavelength(wavelength).y();
+            for (luminance, basis) in basis_luminance.iter_mut().zip(daylight_basis(wavelength)) {
+                *luminance += basis * y_bar * LUMINOUS_EFFICACY;
+            }
+            wavelength += 1.0;
+        }
+
+        PreethamSky {
+            sun_direction,
+            perez,
+            zenith,
+            basis_luminance,
+            distribution: SkyLightPdf::new(),
+        }
+    }
+
+    /// Luminance (in cd/m^2) and CIE xy chromaticity of the sky in `direction`
+    fn luminance_and_chromaticity(&self, direction: &Vec3) -> [f64; 3] {
+        let cos_theta = direction.y();
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let mut result = self.zenith;
+        for (value, coefficients) in result.iter_mut().zip(self.perez.iter()) {
+            *value *= coefficients.evaluate(cos_theta, gamma);
+        }
+        result
+    }
+}
+
+impl EnvironmentLight for PreethamSky {
+    fn radiance(&self, direction: &Vec3, wavelength: f64) -> f64 {
+        let direction = direction.normalize();
+        if direction.y() <= 0.0 {
+            return 0.0;
+        }
+        let [luminance, x, y] = self.luminance_and_chromaticity(&direction);
+        let weights = daylight_weights(x, y);
+        let mut relative_radiance = 0.0;
+        let mut relative_luminance = 0.0;
+        for ((weight, basis), basis_luminance) in weights
+            .iter()
+            .zip(daylight_basis(wavelength).iter())
+            .zip(self.basis_luminance.iter())
+        {
+            relative_radiance += weight * basis;
+            relative_luminance += weight * basis_luminance;
+        }
+        (relative_radiance * luminance / relative_luminance).max(0.0)
+    }
+
+    fn direction_distribution(&self) -> &dyn RandomDistribution<Vec3> {
+        &self.distribution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_sky() -> PreethamSky {
+        PreethamSky::new(&Vec3::new(1.0, 1.0, 0.0), 3.0)
+    }
+
+    /// Luminance, in cd/m^2, of the radiance arriving from `direction`
+    fn luminance(target: &PreethamSky, direction: &Vec3) -> f64 {
+        (380..=780)
+            .map(|wavelength| {
+                let wavelength = wavelength as f64;
+                target.radiance(direction, wavelength)
+                    * ColourXyz::for_wavelength(wavelength).y()
+                    * LUMINOUS_EFFICACY
+            })
+            .sum()
+    }
+
+    #[test]
+    fn zenith_has_model_luminance() {
+        let target = test_sky();
+        let theta_sun = std::f64::consts::FRAC_PI_4;
+        let chi = (4.0 / 9.0 - 3.0 / 120.0) * (std::f64::consts::PI - 2.0 * theta_sun);
+        let expected = ((4.0453 * 3.0 - 4.9710) * chi.tan() - 0.2155 * 3.0 + 2.4192) * 1000.0;
+        let actual = luminance(&target, &Vec3::unit_y());
+        assert!((actual / expected - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn sky_is_brighter_near_sun() {
+        let target = test_sky();
+        let near_sun = Vec3::new(1.0, 1.2, 0.1);
+        let away_from_sun = Vec3::new(-1.0, 1.2, 0.1);
+        assert!(luminance(&target, &near_sun) > luminance(&target, &away_from_sun));
+    }
+
+    #[test]
+    fn clear_sky_is_blue() {
+        let target = test_sky();
+        let direction = Vec3::new(-1.0, 1.0, 0.5);
+        assert!(target.radiance(&direction, 450.0) > target.radiance(&direction, 650.0));
+    }
+
+    #[test]
+    fn nothing_is_emitted_below_horizon() {
+        let target = test_sky();
+        assert!(target.radiance(&Vec3::new(0.0, -1.0, 0.2), 550.0) == 0.0);
+    }
+
+    #[test]
+    fn hazy_sky_is_less_blue_than_clear_sky() {
+        let blueness = |target: &PreethamSky| {
+            let direction = Vec3::new(-1.0, 1.0, 0.5);
+            target.radiance(&direction, 450.0) / target.radiance(&direction, 650.0)
+        };
+        let clear = PreethamSky::new(&Vec3::new(1.0, 1.0, 0.0), 2.0);
+        let hazy = PreethamSky::new(&Vec3::new(1.0, 1.0, 0.0), 8.0);
+        assert!(blueness(&clear) > blueness(&hazy));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::colour::{ColourRgbF, Spectrum};
+use crate::math::Vec3;
+use crate::random_distributions::{RandomDistribution, UniformSphere};
+
+use super::EnvironmentLight;
+
+/// A simple sky that fades from blue at the horizon to white overhead
+pub struct SkyGradient {
+    /// Emission spectrum of the sky straight overhead
+    white: Spectrum,
+
+    /// Emission spectrum of the sky at the horizon
+    blue: Spectrum,
+
+    distribution: UniformSphere,
+}
+
+impl SkyGradient {
+    pub fn new() -> SkyGradient {
+        SkyGradient {
+            white: Spectrum::emission_from_linear_rgb(&ColourRgbF::new(1.0, 1.0, 1.0)),
+            blue: Spectrum::emission_from_linear_rgb(&ColourRgbF::new(0.0, 0.0, 1.0)),
+            distribution: UniformSphere::new(),
+        }
+    }
+}
+
+impl Default for SkyGradient {
+    fn default() -> SkyGradient {
+        SkyGradient::new()
+    }
+}
+
+impl EnvironmentLight for SkyGradient {
+    fn radiance(&self, direction: &Vec3, wavelength: f64) -> f64 {
+        // The sky colour is (y, y, 1), whose spectrum is y parts white to 1 - y parts blue
+        let y = direction.y();
+        y * self.white.intensity_at_wavelength(wavelength)
+            + (1.0 - y) * self.blue.intensity_at_wavelength(wavelength)
+    }
+
+    fn direction_distribution(&self) -> &dyn RandomDistribution<Vec3> {
+        &self.distribution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zenith_is_brighter_than_horizon_at_long_wavelengths() {
+        let target = SkyGradient::new();
+        assert!(target.radiance(&Vec3::unit_y(), 650.0) > target.radiance(&Vec3::unit_x(), 650.0));
+    }
+
+    #[test]
+    fn radiance_matches_emission_spectrum_of_sky_colour() {
+        let target = SkyGradient::new();
+        let direction = Vec3::new(0.6, 0.64, 0.48);
+        let expected = Spectrum::emission_from_linear_rgb(&ColourRgbF::new(0.64, 0.64, 1.0));
+        for wavelength in [450.0, 550.0, 650.0] {
+            assert!(
+                (target.radiance(&direction, wavelength)
+                    - expected.intensity_at_wavelength(wavelength))
+                .abs()
+                    < 1e-12
+            );
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use crate::colour::Spectrum;
+use crate::math::Vec3;
+
+use super::{DirectionalLight, PreethamSky};
+
+use std::f64::consts::PI;
+
+/// Temperature of the black body that the sun's spectrum approximates, in kelvin
+const SUN_TEMPERATURE: f64 = 5778.0;
+
+/// Solid angle of the sun seen from the Earth, at its mean distance, in steradians
+const SUN_SOLID_ANGLE: f64 = 6.8e-5;
+
+/// A place on the Earth and a moment in time, for finding where the sun is in the sky
+///
+/// The sun's position is found with the NOAA approximations to the equation of time and
+/// the solar declination, which are accurate to within a fraction of a degree. As for
+/// [PreethamSky](PreethamSky), +Y is up; north is along -Z and east along +X.
+#[derive(Clone, Copy, Debug)]
+pub struct SolarPosition {
+    /// Degrees north of the equator, negative in the southern hemisphere
+    pub latitude: f64,
+
+    /// Degrees east of Greenwich, negative in the western hemisphere
+    pub longitude: f64,
+
+    pub year: i32,
+
+    /// From 1 for January to 12 for December
+    pub month: u32,
+
+    /// Day of the month, from 1
+    pub day: u32,
+
+    /// Hours since midnight in Coordinated Universal Time, so local time with its offset
+    /// from UTC subtracted
+    pub hour: f64,
+}
+
+impl SolarPosition {
+    fn is_leap_year(&self) -> bool {
+        (self.year % 4 == 0 && self.year % 100 != 0) || self.year % 400 == 0
+    }
+
+    /// Days since the start of the year, from 1 for the first of January
+    fn day_of_year(&self) -> u32 {
+        const DAYS_BEFORE_MONTH: [u32; 12] =
+            [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+        let month = self.month.clamp(1, 12);
+        let leap_day = if month > 2 && self.is_leap_year() {
+            1
+        } else {
+            0
+        };
+        DAYS_BEFORE_MONTH[month as usize - 1] + leap_day + self.day
+    }
+
+    /// The position in the Earth's orbit, as an angle from the start of the year
+    fn fractional_year(&self) -> f64 {
+        let days = if self.is_leap_year() { 366.0 } else { 365.0 };
+        2.0 * PI / days * (self.day_of_year() as f64 - 1.0 + (self.hour - 12.0) / 24.0)
+    }
+
+    /// Unit vector towards the centre of the sun
+    pub fn sun_direction(&self) -> Vec3 {
+        let gamma = self.fractional_year();
+        let harmonic = |coefficients: &[f64]| {
+            coefficients[0]
+                + (1..=coefficients.len() / 2)
+                    .map(|k| {
+                        let angle = k as f64 * gamma;
+                        coefficients[2 * k - 1] * angle.cos() + coefficients[2 * k] * angle.sin()
+                    })
+                    .sum::<f64>()
+        };
+        // In minutes
+        let equation_of_time =
+            229.18 * harmonic(&[0.000075, 0.001868, -0.032077, -0.014615, -0.040849]);
+        let declination = harmonic(&[
+            0.006918, -0.399912, 0.070257, -0.006758, 0.000907, -0.002697, 0.00148,
+        ]);
+        let solar_minutes = self.hour * 60.0 + equation_of_time + 4.0 * self.longitude;
+        let hour_angle = (solar_minutes / 4.0 - 180.0).to_radians();
+        let latitude = self.latitude.to_radians();
+        let east = -declination.cos() * hour_angle.sin();
+        let north = latitude.cos() * declination.sin()
+            - latitude.sin() * declination.cos() * hour_angle.cos();
+        let up = latitude.sin() * declination.sin()
+            + latitude.cos() * declination.cos() * hour_angle.cos();
+        Vec3::new(east, up, -north).normalize()
+    }
+
+    /// The clear sky at this place and time, for `turbidity` as in
+    /// [PreethamSky::new()](PreethamSky::new)
+    pub fn sky(&self, turbidity: f64) -> PreethamSky {
+        PreethamSky::new(&self.sun_direction(), turbidity)
+    }
+
+    /// Direct sunlight at this place and time, or `None` if the sun is below the horizon
+    ///
+    /// The irradiance is absolute, in W/m^2 per nanometre, to match [sky()](Self::sky). The
+    /// sun is treated as a black body whose light is attenuated by Rayleigh scattering and
+    /// by aerosols, using the transmittances given by Preetham, Shirley and Smits; absorption
+    /// by ozone and water vapour is left out. This makes the sun dimmer and redder when it's
+    /// low in the sky, or when the turbidity is high.
+    pub fn sun(&self, turbidity: f64) -> Option<DirectionalLight> {
+        let direction = self.sun_direction();
+        if direction.y() <= 0.0 {
+            return None;
+        }
+        let zenith_angle = direction.y().acos().to_degrees();
+        let air_mass = 1.0 / (direction.y() + 0.15 * (93.885 - zenith_angle).powf(-1.253));
+        // The Earth is nearest the sun in early January
+        let distance_factor = 1.0 + 0.033 * (2.0 * PI * self.day_of_year() as f64 / 365.0).cos();
+        let beta = 0.04608365 * turbidity - 0.04586025;
+        let spectrum = Spectrum::from_function(73, |wavelength| {
+            let metres = wavelength * 1e-9;
+            let microns = wavelength * 1e-3;
+            // Planck's law, in W/(m^2 sr) per metre, with hc/k in metre-kelvins
+            let radiance = 1.191_042_97e-16
+                / (metres.powi(5) * ((0.014_387_77 / (metres * SUN_TEMPERATURE)).exp() - 1.0));
+            let rayleigh = (-0.008735 * microns.powf(-4.08) * air_mass).exp();
+            let aerosol = (-beta * microns.powf(-1.3) * air_mass).exp();
+            radiance * 1e-9 * SUN_SOLID_ANGLE * distance_factor * rayleigh * aerosol
+        });
+        Some(DirectionalLight {
+            direction,
+            spectrum,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(latitude: f64, month: u32, day: u32, hour: f64) -> SolarPosition {
+        SolarPosition {
+            latitude,
+            longitude: 0.0,
+            year: 2023,
+            month,
+            day,
+            hour,
+        }
+    }
+
+    fn elevation(target: &SolarPosition) -> f64 {
+        target.sun_direction().y().asin().to_degrees()
+    }
+
+    #[test]
+    fn sun_is_overhead_at_equator_at_equinox() {
+        // Solar noon is a few minutes after midday, by the equation of time
+        assert!(elevation(&position(0.0, 3, 20, 12.0)) > 87.5);
+        assert!(elevation(&position(0.0, 3, 20, 12.12)) > 89.0);
+    }
+
+    #[test]
+    fn midsummer_noon_elevation_matches_latitude_and_tilt() {
+        let target = position(51.5, 6, 21, 12.0);
+        assert!((elevation(&target) - (90.0 - 51.5 + 23.44)).abs() < 0.5);
+        // The sun is to the south
+        assert!(target.sun_direction().z() > 0.0);
+        // In the southern hemisphere it's to the north
+        assert!(position(-33.9, 6, 21, 12.0).sun_direction().z() < 0.0);
+    }
+
+    #[test]
+    fn sun_rises_in_east_and_sets_in_west() {
+        assert!(position(0.0, 3, 20, 8.0).sun_direction().x() > 0.5);
+        assert!(position(0.0, 3, 20, 16.0).sun_direction().x() < -0.5);
+    }
+
+    #[test]
+    fn longitude_shifts_solar_noon() {
+        let target = SolarPosition {
+            longitude: -90.0,
+            ..position(0.0, 3, 20, 18.12)
+        };
+        assert!(elevation(&target) > 89.0);
+    }
+
+    #[test]
+    fn no_sunlight_at_midnight() {
+        assert!(position(51.5, 12, 21, 0.0).sun(3.0).is_none());
+    }
+
+    #[test]
+    fn low_sun_is_dimmer_and_redder() {
+        let high = position(0.0, 3, 20, 12.0).sun(3.0).unwrap().spectrum;
+        let low = position(0.0, 3, 20, 17.5).sun(3.0).unwrap().spectrum;
+        let blue_to_red = |spectrum: &Spectrum| {
+            spectrum.intensity_at_wavelength(450.0) / spectrum.intensity_at_wavelength(650.0)
+        };
+        assert!(low.intensity_at_wavelength(550.0) < high.intensity_at_wavelength(550.0));
+        assert!(blue_to_red(&low) < blue_to_red(&high));
+        // Around the measured irradiance of overhead sunlight
+        let irradiance = high.intensity_at_wavelength(550.0);
+        assert!(irradiance > 1.0 && irradiance < 2.0);
+    }
+}
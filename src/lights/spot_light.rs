@@ -0,0 +1,139 @@
+use crate::colour::{PhotonPacket, Spectrum};
+use crate::math::Vec3;
+
+use super::{Light, LightSample};
+
+use rand::RngCore;
+
+use std::f64::consts::PI;
+
+/// A point light that only shines within a cone
+///
+/// The light is at full intensity within `falloff_start` of the axis of the cone, and
+/// fades smoothly to nothing at `total_width`.
+#[derive(Debug)]
+pub struct SpotLight {
+    pub position: Vec3,
+
+    /// Direction of the axis of the cone, away from the light
+    pub direction: Vec3,
+
+    /// The power emitted per unit solid angle, along the axis of the cone
+    pub intensity: Spectrum,
+
+    cos_total_width: f64,
+    cos_falloff_start: f64,
+}
+
+impl SpotLight {
+    /// A spot light at `position`, pointing in `direction`
+    ///
+    /// Angles are in radians, from the axis of the cone to its edge.
+    pub fn new(
+        position: Vec3,
+        direction: Vec3,
+        intensity: Spectrum,
+        total_width: f64,
+        falloff_start: f64,
+    ) -> SpotLight {
+        SpotLight {
+            position,
+            direction: direction.normalize(),
+            intensity,
+            cos_total_width: total_width.cos(),
+            cos_falloff_start: falloff_start.min(total_width).cos(),
+        }
+    }
+
+    /// The fraction of the full intensity shone in `direction`, away from the light
+    fn falloff(&self, direction: &Vec3) -> f64 {
+        let cos_theta = direction.dot(&self.direction);
+        if cos_theta <= self.cos_total_width {
+            0.0
+        } else if cos_theta >= self.cos_falloff_start {
+            1.0
+        } else {
+            let t = (cos_theta - self.cos_total_width)
+                / (self.cos_falloff_start - self.cos_total_width);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_incident(
+        &self,
+        location: &Vec3,
+        packet: &PhotonPacket,
+        _rng: &mut dyn RngCore,
+    ) -> Option<LightSample> {
+        let to_light = self.position - *location;
+        let distance = to_light.norm();
+        if distance == 0.0 {
+            return None;
+        }
+        let direction = to_light * (1.0 / distance);
+        let falloff = self.falloff(&-direction);
+        if falloff == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: packet
+                .map(|photon| self.intensity.emit_photon(photon))
+                .scale_intensity(falloff / (distance * distance)),
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf(&self, _location: &Vec3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn power(&self, wavelength: f64) -> f64 {
+        // The smooth falloff is approximated as linear in the cosine, which is close
+        2.0 * PI
+            * self.intensity.intensity_at_wavelength(wavelength)
+            * (1.0 - 0.5 * (self.cos_falloff_start + self.cos_total_width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::Photon;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn light_only_reaches_points_inside_cone() {
+        let target = SpotLight::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            -Vec3::unit_y(),
+            Spectrum::grey(1.0),
+            0.5,
+            0.25,
+        );
+        let packet = PhotonPacket::from_photon(&Photon {
+            wavelength: 550.0,
+            intensity: 0.0,
+        });
+        let mut rng = StdRng::seed_from_u64(0);
+        let centre = target
+            .sample_incident(&Vec3::zeros(), &packet, &mut rng)
+            .unwrap();
+        assert!((centre.radiance.hero().intensity - 1.0).abs() < 0.000000001);
+        // tan(0.4) is between the falloff start and the edge of the cone
+        let edge = target
+            .sample_incident(&Vec3::new(0.4f64.tan(), 0.0, 0.0), &packet, &mut rng)
+            .unwrap();
+        assert!(edge.radiance.hero().intensity > 0.0);
+        assert!(edge.radiance.hero().intensity < centre.radiance.hero().intensity);
+        assert!(target
+            .sample_incident(&Vec3::new(1.0, 0.0, 0.0), &packet, &mut rng)
+            .is_none());
+    }
+}
@@ -1,5 +1,3 @@
-use rayon::prelude::*;
-
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormatEnum;
@@ -9,30 +7,105 @@ use sdl2::Sdl;
 
 use clap::Arg;
 
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::TcpListener;
 use std::path::{Path, PathBuf};
-use std::sync::{mpsc, Arc};
+use std::sync::{mpsc, Arc, Mutex};
 use std::time::Duration;
 
 use vanrijn::accumulation_buffer::AccumulationBuffer;
-use vanrijn::colour::{ColourRgbF, NamedColour, Spectrum};
-use vanrijn::image::{ClampingToneMapper, ImageRgbU8};
-use vanrijn::materials::LambertianMaterial;
+use vanrijn::aovs::{render_denoising_aovs, render_id_buffers, IdBuffers};
+use vanrijn::bucketed_image::{render_bucketed, BucketedImage};
+use vanrijn::camera::{
+    Camera, Lens, OmniDirectionalStereoCamera, PerspectiveCamera, RenderSettings, Shutter,
+    StereoCamera, StereoLayout,
+};
+use vanrijn::colour::{ColourRgbF, Lut1d, Lut3d, NamedColour, ResponseCurve, Spectrum};
+use vanrijn::denoiser::{CrossBilateralDenoiser, Denoiser};
+use vanrijn::distributed;
+use vanrijn::filters::{BoxFilter, Filter, GaussianFilter, MitchellFilter, TentFilter};
+use vanrijn::image::{ClampingToneMapper, ExposedToneMapper, Exposure, ImageRgbU8, ToneMapper};
+use vanrijn::integrators::{
+    DebugIntegrator, DebugView, Integrator, PolarizedIntegrator, SimpleRandomIntegrator,
+    WavefrontIntegrator, WhittedIntegrator,
+};
+use vanrijn::lights::{
+    DirectionalLight, EnvironmentLight, ImageEnvironmentLight, Light, SkyGradient, SolarPosition,
+};
+use vanrijn::materials::{LambertianMaterial, MaterialLibrary};
 use vanrijn::math::Vec3;
-use vanrijn::mesh::load_obj;
-use vanrijn::partial_render_scene;
-use vanrijn::raycasting::{Aggregate, BoundingVolumeHierarchy, Plane, Primitive, Sphere};
+use vanrijn::mesh::{load_mesh_buffers, subdivide};
+use vanrijn::path_debug::{trace_light_paths, LightPathPoints};
+use vanrijn::progressive_renderer::{ProgressiveRenderer, SnapshotInterval, SnapshotSchedule};
+use vanrijn::raycasting::{
+    Aggregate, ClippingPlane, HasBoundingBox, Plane, Primitive, Sphere, TriangleMesh, WithObjectId,
+};
 use vanrijn::scene::Scene;
-use vanrijn::util::TileIterator;
+use vanrijn::statistics::{self, RayStatistics};
+use vanrijn::util::CancellationToken;
+use vanrijn::VanrijnError;
+
+/// Size of the tiles rendered by each thread in a progressive pass
+const PASS_TILE_SIZE: usize = 2048;
+
+/// Size of the tiles handed to workers when rendering with `--coordinator`
+///
+/// Small enough that there are plenty to share out, but large enough that sending them
+/// back doesn't take longer than rendering them.
+const DISTRIBUTED_TILE_SIZE: usize = 64;
+
+/// Turbidity of the clear sky used with `--sun`
+const SKY_TURBIDITY: f64 = 3.0;
+
+/// Size of the tiles rendered at once with `--bucket-file`
+const BUCKET_SIZE: usize = 128;
 
 #[derive(Debug)]
 struct CommandLineParameters {
     width: usize,
     height: usize,
     output_file: Option<PathBuf>,
-    time: f64,
+    environment_file: Option<PathBuf>,
+    shutter: Shutter,
+    integrator: String,
+    filter: String,
+    stereo: Option<String>,
+    interpupillary_distance: f64,
+    convergence_distance: Option<f64>,
+    samples_per_pixel: Option<usize>,
+    max_depth: u16,
+    max_radiance: Option<f64>,
+    checkpoint_file: Option<PathBuf>,
+    resume: bool,
+    write_ids: bool,
+    subdivision_levels: u32,
+    frame_model: bool,
+    exposure: Exposure,
+    response: Option<String>,
+    response_lut: Option<PathBuf>,
+    quantum_efficiency_file: Option<PathBuf>,
+    look_file: Option<PathBuf>,
+    denoise: bool,
+    show_invalid: bool,
+    worker: Option<String>,
+    coordinator: Option<String>,
+    bucket_file: Option<PathBuf>,
+    light_paths_file: Option<PathBuf>,
+    sun: Option<SolarPosition>,
+    section: Option<(Vec3, Vec3)>,
+    polarizing_filter: Option<Vec3>,
+    deterministic: bool,
+    snapshot_interval: Option<SnapshotInterval>,
+    snapshot_hdr: bool,
 }
 
-fn parse_args() -> CommandLineParameters {
+fn parse_args<I, T>(args: I) -> CommandLineParameters
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
     let matches = clap::App::new("vanrijn")
         .version("alpha")
         .author("Matthew Gordon <matthew@gordon.earth")
@@ -53,24 +126,425 @@ fn parse_args() -> CommandLineParameters {
                 .takes_value(true)
                 .required(false),
         )
+        .arg(
+            Arg::with_name("ids")
+                .long("ids")
+                .help("Also write object and material ID buffers, as 16-bit PNGs.")
+                .requires("output_png"),
+        )
+        .arg(
+            Arg::with_name("environment_hdr")
+                .long("environment")
+                .value_name("FILENAME")
+                .help("Equirectangular Radiance HDR image to light the scene with.")
+                .takes_value(true)
+                .required(false),
+        )
         .arg(
             Arg::with_name("time")
                 .long("time")
                 .value_name("SECONDS")
+                .help("Time at which the shutter opens.")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("shutter")
+                .long("shutter")
+                .value_name("SECONDS")
+                .help("How long each row of the image is exposed for.")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("rolling_shutter")
+                .long("rolling-shutter")
+                .value_name("SECONDS")
+                .help(
+                    "Expose the rows one after another, with the bottom row starting this \
+                     long after the top.",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("integrator")
+                .long("integrator")
+                .value_name("NAME")
+                .help("Algorithm used to compute the light arriving at the camera.")
+                .takes_value(true)
+                .possible_values(&[
+                    "simple-random",
+                    "wavefront",
+                    "polarized",
+                    "whitted",
+                    "normals",
+                    "depth",
+                    "uv",
+                    "traversal-cost",
+                ])
+                .default_value("simple-random"),
+        )
+        .arg(
+            Arg::with_name("stereo")
+                .long("stereo")
+                .value_name("LAYOUT")
+                .help(
+                    "Render a view for each eye, side by side or one above the other, or as \
+                     an omni-directional stereo panorama for VR.",
+                )
+                .takes_value(true)
+                .possible_values(&["side-by-side", "top-bottom", "ods"]),
+        )
+        .arg(
+            Arg::with_name("ipd")
+                .long("ipd")
+                .value_name("DISTANCE")
+                .help("Distance between the eyes with --stereo.")
+                .takes_value(true)
+                .default_value("0.064"),
+        )
+        .arg(
+            Arg::with_name("convergence")
+                .long("convergence")
+                .value_name("DISTANCE")
+                .help(
+                    "Distance at which the eyes' views line up with --stereo, which appears \
+                     at the depth of the screen. Defaults to the distance to the model.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .value_name("NAME")
+                .help("Reconstruction filter used to spread samples over nearby pixels.")
                 .takes_value(true)
+                .possible_values(&["box", "tent", "gaussian", "mitchell"])
+                .default_value("box"),
+        )
+        .arg(
+            Arg::with_name("spp")
+                .long("spp")
+                .value_name("SAMPLES")
+                .help("Stop rendering after this many samples per pixel.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("max_depth")
+                .long("max-depth")
+                .value_name("BOUNCES")
+                .help("Maximum number of times a path can bounce.")
+                .takes_value(true)
+                .default_value("128"),
+        )
+        .arg(
+            Arg::with_name("exposure")
+                .long("exposure")
+                .value_name("EV")
+                .help("Brighten the image by this many stops, or darken it if negative.")
+                .takes_value(true)
+                .allow_hyphen_values(true)
                 .default_value("0"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("auto_exposure")
+                .long("auto-exposure")
+                .help("Adjust the exposure to the average brightness of the image first."),
+        )
+        .arg(
+            Arg::with_name("response")
+                .long("response")
+                .value_name("CURVE")
+                .help(
+                    "Camera response curve that turns the light in each channel into the \
+                     stored value, instead of the colour space's transfer function.",
+                )
+                .takes_value(true)
+                .possible_values(&["srgb", "aces"]),
+        )
+        .arg(
+            Arg::with_name("response_lut")
+                .long("response-lut")
+                .value_name("FILE")
+                .help("Use the 1D LUT in this .cube file as the camera response curve.")
+                .takes_value(true)
+                .conflicts_with("response"),
+        )
+        .arg(
+            Arg::with_name("look")
+                .long("look")
+                .value_name("FILE")
+                .help(
+                    "Apply the 3D LUT in this .cube file to the image after tone mapping, to \
+                     give it the look of a colour pipeline.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("quantum_efficiency")
+                .long("quantum-efficiency")
+                .value_name("FILE")
+                .help(
+                    "Record each wavelength with the sensor efficiency read from this file, \
+                     with a wavelength in nanometres and an efficiency on each line.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("denoise")
+                .long("denoise")
+                .help("Remove noise from the final image before saving it."),
+        )
+        .arg(
+            Arg::with_name("show_invalid")
+                .long("show-invalid")
+                .help("Paint pixels that had NaN or infinite samples dropped magenta."),
+        )
+        .arg(
+            Arg::with_name("clamp")
+                .long("clamp")
+                .value_name("RADIANCE")
+                .help("Clamp the radiance of each sample to remove fireflies.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .value_name("FILENAME")
+                .help("File to save the progress of the render to after each pass.")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("subdivide")
+                .long("subdivide")
+                .value_name("LEVELS")
+                .help("Number of times to subdivide the model to smooth it.")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("frame_model")
+                .long("frame-model")
+                .help("Move the camera so the whole model is in view."),
+        )
+        .arg(
+            Arg::with_name("coordinator")
+                .long("coordinator")
+                .value_name("ADDRESS")
+                .help("Listen at this address for workers, and have them render the image.")
+                .takes_value(true)
+                .requires("spp"),
+        )
+        .arg(
+            Arg::with_name("worker")
+                .long("worker")
+                .value_name("ADDRESS")
+                .help("Render tiles for the coordinator at this address instead of an image.")
+                .takes_value(true)
+                .conflicts_with("coordinator"),
+        )
+        .arg(
+            Arg::with_name("bucket_file")
+                .long("bucket-file")
+                .value_name("FILENAME")
+                .help("Render straight to this scratch file, without a preview, to save memory.")
+                .takes_value(true)
+                .requires_all(&["spp", "output_png"])
+                .conflicts_with_all(&["coordinator", "worker"]),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .help("Continue the render saved in the checkpoint file.")
+                .requires("checkpoint"),
+        )
+        .arg(
+            Arg::with_name("light_paths")
+                .long("light-paths")
+                .value_name("FILENAME")
+                .help(
+                    "Also write where the paths met surfaces, as a PLY point cloud if the \
+                     filename ends in .ply, and otherwise as a top-down density PNG.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sun")
+                .long("sun")
+                .value_names(&["LATITUDE", "LONGITUDE", "DATE", "TIME"])
+                .help(
+                    "Light the scene with the sun and a clear sky, as seen from LATITUDE and \
+                     LONGITUDE (in degrees north and east) on DATE (YYYY-MM-DD) at TIME \
+                     (HH:MM, in UTC).",
+                )
+                .takes_value(true)
+                .number_of_values(4)
+                .conflicts_with("environment_hdr"),
+        )
+        .arg(
+            Arg::with_name("section")
+                .long("section")
+                .value_names(&["X", "Y", "Z", "NX", "NY", "NZ"])
+                .help(
+                    "Cut away everything in front of the plane through (X, Y, Z) with normal \
+                     (NX, NY, NZ), filling in the cut surfaces.",
+                )
+                .takes_value(true)
+                .number_of_values(6),
+        )
+        .arg(
+            Arg::with_name("polarizing_filter")
+                .long("polarizing-filter")
+                .value_names(&["X", "Y", "Z"])
+                .help(
+                    "Put a linear polarizing filter that passes light polarized along \
+                     (X, Y, Z) in front of the camera. Only the polarized integrator \
+                     models it.",
+                )
+                .takes_value(true)
+                .number_of_values(3),
+        )
+        .arg(Arg::with_name("deterministic").long("deterministic").help(
+            "Merge tiles in a fixed order, so renders with the same settings are \
+                     identical.",
+        ))
+        .arg(
+            Arg::with_name("snapshot_every")
+                .long("snapshot-every")
+                .value_name("N|Ns")
+                .help(
+                    "Save the image rendered so far next to the output PNG after every N \
+                     passes, or every N seconds with an \"s\" suffix.",
+                )
+                .takes_value(true)
+                .validator(|interval| interval.parse::<SnapshotInterval>().map(|_| ()))
+                .requires("output_png"),
+        )
+        .arg(
+            Arg::with_name("snapshot_hdr")
+                .long("snapshot-hdr")
+                .help("Also save each snapshot as a Radiance HDR image.")
+                .requires("snapshot_every"),
+        )
+        .get_matches_from(args);
     let mut size_iter = matches.values_of("size").unwrap();
     let width = size_iter.next().unwrap().parse().unwrap();
     let height = size_iter.next().unwrap().parse().unwrap();
     let output_file = matches.value_of_os("output_png").map(PathBuf::from);
-    let time = matches.value_of("time").unwrap().parse().unwrap();
+    let environment_file = matches.value_of_os("environment_hdr").map(PathBuf::from);
+    let shutter = Shutter {
+        open: matches.value_of("time").unwrap().parse().unwrap(),
+        exposure: matches.value_of("shutter").unwrap().parse().unwrap(),
+        rolling_time: matches
+            .value_of("rolling_shutter")
+            .unwrap()
+            .parse()
+            .unwrap(),
+    };
+    let integrator = matches.value_of("integrator").unwrap().to_string();
+    let filter = matches.value_of("filter").unwrap().to_string();
+    let stereo = matches.value_of("stereo").map(String::from);
+    let interpupillary_distance = matches.value_of("ipd").unwrap().parse().unwrap();
+    let convergence_distance = matches
+        .value_of("convergence")
+        .map(|distance| distance.parse().unwrap());
+    let samples_per_pixel = matches.value_of("spp").map(|spp| spp.parse().unwrap());
+    let max_depth = matches.value_of("max_depth").unwrap().parse().unwrap();
+    let max_radiance = matches
+        .value_of("clamp")
+        .map(|clamp| clamp.parse().unwrap());
+    let checkpoint_file = matches.value_of_os("checkpoint").map(PathBuf::from);
+    let resume = matches.is_present("resume");
+    let write_ids = matches.is_present("ids");
+    let subdivision_levels = matches.value_of("subdivide").unwrap().parse().unwrap();
+    let frame_model = matches.is_present("frame_model");
+    let exposure = Exposure {
+        ev: matches.value_of("exposure").unwrap().parse().unwrap(),
+        auto_exposure: matches.is_present("auto_exposure"),
+    };
+    let response = matches.value_of("response").map(String::from);
+    let response_lut = matches.value_of_os("response_lut").map(PathBuf::from);
+    let quantum_efficiency_file = matches.value_of_os("quantum_efficiency").map(PathBuf::from);
+    let look_file = matches.value_of_os("look").map(PathBuf::from);
+    let denoise = matches.is_present("denoise");
+    let show_invalid = matches.is_present("show_invalid");
+    let worker = matches.value_of("worker").map(String::from);
+    let coordinator = matches.value_of("coordinator").map(String::from);
+    let bucket_file = matches.value_of_os("bucket_file").map(PathBuf::from);
+    let light_paths_file = matches.value_of_os("light_paths").map(PathBuf::from);
+    let sun = matches
+        .values_of("sun")
+        .map(|values| parse_solar_position(&values.collect::<Vec<_>>()));
+    let section = matches.values_of("section").map(|values| {
+        let values: Vec<f64> = values.map(|x| x.parse().unwrap()).collect();
+        (
+            Vec3::new(values[0], values[1], values[2]),
+            Vec3::new(values[3], values[4], values[5]),
+        )
+    });
+    let polarizing_filter = matches.values_of("polarizing_filter").map(|values| {
+        let values: Vec<f64> = values.map(|x| x.parse().unwrap()).collect();
+        Vec3::new(values[0], values[1], values[2])
+    });
+    let deterministic = matches.is_present("deterministic");
+    let snapshot_interval = matches
+        .value_of("snapshot_every")
+        .map(|interval| interval.parse().unwrap());
+    let snapshot_hdr = matches.is_present("snapshot_hdr");
     CommandLineParameters {
         width,
         height,
         output_file,
-        time,
+        environment_file,
+        shutter,
+        integrator,
+        filter,
+        stereo,
+        interpupillary_distance,
+        convergence_distance,
+        samples_per_pixel,
+        max_depth,
+        max_radiance,
+        checkpoint_file,
+        resume,
+        write_ids,
+        subdivision_levels,
+        frame_model,
+        exposure,
+        response,
+        response_lut,
+        quantum_efficiency_file,
+        look_file,
+        denoise,
+        show_invalid,
+        worker,
+        coordinator,
+        bucket_file,
+        light_paths_file,
+        sun,
+        section,
+        polarizing_filter,
+        deterministic,
+        snapshot_interval,
+        snapshot_hdr,
+    }
+}
+
+/// The place and time given to `--sun`
+fn parse_solar_position(values: &[&str]) -> SolarPosition {
+    let date: Vec<&str> = values[2].split('-').collect();
+    let time: Vec<f64> = values[3].split(':').map(|x| x.parse().unwrap()).collect();
+    SolarPosition {
+        latitude: values[0].parse().unwrap(),
+        longitude: values[1].parse().unwrap(),
+        year: date[0].parse().unwrap(),
+        month: date[1].parse().unwrap(),
+        day: date[2].parse().unwrap(),
+        hour: time[0] + time.get(1).unwrap_or(&0.0) / 60.0,
     }
 }
 
@@ -84,6 +558,78 @@ fn update_texture(image: &ImageRgbU8, texture: &mut Texture) {
         .expect("Couldn't update texture.");
 }
 
+/// Write the ID buffers next to the image `image_filename`, with "_objects" and
+/// "_materials" added to its name
+fn write_id_buffers(id_buffers: &IdBuffers, image_filename: &Path) -> Result<(), VanrijnError> {
+    let stem = image_filename
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    id_buffers
+        .object_ids
+        .write_png(&image_filename.with_file_name(format!("{}_objects.png", stem)))?;
+    id_buffers
+        .material_ids
+        .write_png(&image_filename.with_file_name(format!("{}_materials.png", stem)))
+}
+
+/// Write `points` to `filename`, as a PLY point cloud or a top-down density image of
+/// `width` by `height` pixels, depending on its extension
+fn write_light_paths(
+    points: &LightPathPoints,
+    filename: &Path,
+    width: usize,
+    height: usize,
+) -> Result<(), VanrijnError> {
+    if filename
+        .extension()
+        .is_some_and(|extension| extension == "ply")
+    {
+        Ok(points.write_ply(BufWriter::new(File::create(filename)?))?)
+    } else {
+        points.top_down_density(width, height).write_png(filename)
+    }
+}
+
+/// Write the image rendered so far next to the image `image_filename`, with "_snapshot"
+/// added to its name, replacing any earlier snapshot
+fn write_snapshot(
+    image: &AccumulationBuffer,
+    image_filename: &Path,
+    tone_mapper: &ExposedToneMapper<ClampingToneMapper>,
+    write_hdr: bool,
+) -> Result<(), VanrijnError> {
+    let stem = image_filename
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    image
+        .to_image_rgb_u8(tone_mapper)
+        .write_png(&image_filename.with_file_name(format!("{}_snapshot.png", stem)))?;
+    if write_hdr {
+        image
+            .to_image_rgb_f()
+            .write_hdr(&image_filename.with_file_name(format!("{}_snapshot.hdr", stem)))?;
+    }
+    Ok(())
+}
+
+/// The tone mapper for every image that's shown or written
+fn tone_mapper(
+    exposure: Exposure,
+    response: &ResponseCurve,
+    look: &Option<Arc<Lut3d>>,
+) -> ExposedToneMapper<ClampingToneMapper> {
+    ExposedToneMapper {
+        exposure,
+        tone_mapper: ClampingToneMapper {
+            response: response.clone(),
+            look: look.clone(),
+            ..Default::default()
+        },
+    }
+}
+
 fn init_canvas(
     image_width: usize,
     image_height: usize,
@@ -102,26 +648,30 @@ fn init_canvas(
 }
 
 pub fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let parameters = parse_args();
+    let parameters = parse_args(std::env::args_os());
+    let response = match (parameters.response.as_deref(), &parameters.response_lut) {
+        (_, Some(lut_file)) => ResponseCurve::Lut(Lut1d::read_cube(lut_file)?),
+        (Some("srgb"), None) => ResponseCurve::Srgb,
+        (Some("aces"), None) => ResponseCurve::Aces,
+        _ => ResponseCurve::TransferFunction,
+    };
+    let look = match parameters.look_file {
+        Some(ref file) => Some(Arc::new(Lut3d::read_cube(file)?)),
+        None => None,
+    };
+    let quantum_efficiency = match parameters.quantum_efficiency_file {
+        Some(ref file) => Some(Arc::new(Spectrum::read(file)?)),
+        None => None,
+    };
     let image_width = parameters.width;
     let image_height = parameters.height;
 
-    let mut rendered_image = AccumulationBuffer::new(image_width, image_height);
-
-    let (sdl_context, mut canvas) = init_canvas(image_width, image_height)?;
-
-    let texture_creator = canvas.texture_creator();
-    let mut rendered_image_texture = texture_creator.create_texture_streaming(
-        PixelFormatEnum::RGB24,
-        image_width as u32,
-        image_height as u32,
-    )?;
-
     let model_file_path =
         Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/stanford_bunny.obj");
     println!("Loading object...");
-    let mut model_object = load_obj(
-        &model_file_path,
+    let mut materials = MaterialLibrary::new();
+    materials.insert(
+        "bunny",
         Arc::new(LambertianMaterial {
             colour: Spectrum::reflection_from_linear_rgb(&ColourRgbF::from_named(
                 NamedColour::Yellow,
@@ -129,100 +679,388 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
             diffuse_strength: 0.05,
             //reflection_strength: 0.9,
         }),
-    )?;
-    println!("Building BVH...");
-    let model_bvh: Box<dyn Aggregate> =
-        Box::new(BoundingVolumeHierarchy::build(model_object.as_mut_slice()));
+    );
+    let model_buffers = load_mesh_buffers(&model_file_path, materials.get("bunny").unwrap())?;
+    let model_object = TriangleMesh::new(subdivide(&model_buffers, parameters.subdivision_levels));
+    let environment: Box<dyn EnvironmentLight> =
+        match (&parameters.environment_file, parameters.sun) {
+            (Some(environment_file), _) => {
+                println!("Loading environment...");
+                Box::new(ImageEnvironmentLight::read_hdr(environment_file)?)
+            }
+            (None, Some(sun)) => Box::new(sun.sky(SKY_TURBIDITY)),
+            (None, None) => Box::new(SkyGradient::new()),
+        };
     println!("Constructing Scene...");
+    materials.insert(
+        "section_cap",
+        Arc::new(LambertianMaterial {
+            colour: Spectrum::reflection_from_linear_rgb(&ColourRgbF::from_named(NamedColour::Red)),
+            diffuse_strength: 0.1,
+        }),
+    );
+    materials.insert(
+        "floor",
+        Arc::new(LambertianMaterial {
+            colour: Spectrum::reflection_from_linear_rgb(&ColourRgbF::new(0.55, 0.27, 0.04)),
+            diffuse_strength: 0.1,
+        }),
+    );
+    materials.insert(
+        "green",
+        Arc::new(LambertianMaterial {
+            colour: Spectrum::reflection_from_linear_rgb(&ColourRgbF::from_named(
+                NamedColour::Green,
+            )),
+            diffuse_strength: 0.1,
+        }),
+    );
+    materials.insert(
+        "blue",
+        Arc::new(LambertianMaterial {
+            colour: Spectrum::reflection_from_linear_rgb(&ColourRgbF::from_named(
+                NamedColour::Blue,
+            )),
+            diffuse_strength: 0.1,
+            //                        diffuse_strength: 0.01,
+            //                        reflection_strength: 0.99,
+        }),
+    );
+    materials.insert(
+        "red",
+        Arc::new(LambertianMaterial {
+            colour: Spectrum::reflection_from_linear_rgb(&ColourRgbF::from_named(NamedColour::Red)),
+            diffuse_strength: 0.05,
+            //smoothness: 100.0,
+            //specular_strength: 1.0,
+        }),
+    );
 
+    // Each eye of a stereo pair has half of the image
+    let eye_aspect_ratio = image_width as f64 / image_height as f64
+        * match parameters.stereo.as_deref() {
+            Some("side-by-side") => 0.5,
+            Some("top-bottom") => 2.0,
+            _ => 1.0,
+        };
+    let camera = if parameters.frame_model {
+        PerspectiveCamera::framing(
+            &model_object.bounding_box(),
+            &Vec3::unit_z(),
+            &Vec3::unit_y(),
+            PerspectiveCamera::DEFAULT_VERTICAL_FOV,
+            eye_aspect_ratio,
+            Lens::Pinhole,
+        )
+    } else {
+        PerspectiveCamera::new(Vec3::new(-2.0, 1.0, -5.0), Lens::Pinhole)
+    };
+    let stereo_layout = match parameters.stereo.as_deref() {
+        Some("side-by-side") => StereoLayout::SideBySide,
+        _ => StereoLayout::TopBottom,
+    };
+    let camera: Box<dyn Camera> = match parameters.stereo.as_deref() {
+        None => Box::new(camera),
+        Some("ods") => Box::new(OmniDirectionalStereoCamera {
+            location: camera.location,
+            orientation: camera.orientation,
+            interpupillary_distance: parameters.interpupillary_distance,
+            layout: stereo_layout,
+        }),
+        Some(_) => Box::new(StereoCamera {
+            location: camera.location,
+            orientation: camera.orientation,
+            vertical_fov: camera.vertical_fov,
+            interpupillary_distance: parameters.interpupillary_distance,
+            convergence_distance: parameters
+                .convergence_distance
+                .unwrap_or_else(|| (model_object.bounding_box().centre() - camera.location).norm()),
+            layout: stereo_layout,
+        }),
+    };
     let scene = Scene {
-        camera_location: Vec3::new(-2.0, 1.0, -5.0),
+        camera,
         objects: vec![
-            Box::new(vec![
-                Box::new(Plane::new(
-                    Vec3::new(0.0, 1.0, 0.0),
-                    -2.0,
-                    Arc::new(LambertianMaterial {
-                        colour: Spectrum::reflection_from_linear_rgb(&ColourRgbF::new(
-                            0.55, 0.27, 0.04,
-                        )),
-                        diffuse_strength: 0.1,
-                    }),
-                )) as Box<dyn Primitive>,
-                Box::new(Sphere::new(
-                    Vec3::new(-6.25, -0.5, 1.0),
-                    1.0,
-                    Arc::new(LambertianMaterial {
-                        colour: Spectrum::reflection_from_linear_rgb(&ColourRgbF::from_named(
-                            NamedColour::Green,
-                        )),
-                        diffuse_strength: 0.1,
-                    }),
-                )),
-                Box::new(Sphere::new(
-                    Vec3::new(-4.25, -0.5, 2.0),
-                    1.0,
-                    Arc::new(LambertianMaterial {
-                        colour: Spectrum::reflection_from_linear_rgb(&ColourRgbF::from_named(
-                            NamedColour::Blue,
-                        )),
-                        diffuse_strength: 0.1,
-                        //                        diffuse_strength: 0.01,
-                        //                        reflection_strength: 0.99,
-                    }),
-                )),
-                Box::new(Sphere::new(
-                    Vec3::new(-5.0, 1.5, 1.0),
-                    1.0,
-                    Arc::new(LambertianMaterial {
-                        colour: Spectrum::reflection_from_linear_rgb(&ColourRgbF::from_named(
-                            NamedColour::Red,
-                        )),
-                        diffuse_strength: 0.05,
-                        //smoothness: 100.0,
-                        //specular_strength: 1.0,
-                    }),
-                )),
-            ]) as Box<dyn Aggregate>,
-            model_bvh,
+            Box::new(WithObjectId::new(
+                vec![
+                    Box::new(Plane::new(
+                        Vec3::new(0.0, 1.0, 0.0),
+                        -2.0,
+                        materials.get("floor").unwrap(),
+                    )) as Box<dyn Primitive>,
+                    Box::new(Sphere::new(
+                        Vec3::new(-6.25, -0.5, 1.0),
+                        1.0,
+                        materials.get("green").unwrap(),
+                    )),
+                    Box::new(Sphere::new(
+                        Vec3::new(-4.25, -0.5, 2.0),
+                        1.0,
+                        materials.get("blue").unwrap(),
+                    )),
+                    Box::new(Sphere::new(
+                        Vec3::new(-5.0, 1.5, 1.0),
+                        1.0,
+                        materials.get("red").unwrap(),
+                    )),
+                ],
+                1,
+            )) as Box<dyn Aggregate>,
+            Box::new(WithObjectId::new(model_object, 2)),
         ],
+        environment,
+        // The sky models don't include the sun's disk, so it's added as a light
+        lights: if let Some(sun) = parameters.sun {
+            sun.sun(SKY_TURBIDITY)
+                .into_iter()
+                .map(|sun| Box::new(sun) as Box<dyn Light>)
+                .collect()
+        } else if parameters.integrator == "whitted" {
+            // The Whitted integrator isn't lit by the environment, so it needs a light of its own
+            vec![Box::new(DirectionalLight {
+                direction: Vec3::new(1.0, 1.0, -1.0).normalize(),
+                spectrum: Spectrum::grey(1.0),
+            }) as Box<dyn Light>]
+        } else {
+            vec![]
+        },
+        portals: vec![],
+        medium: None,
+        clipping_planes: parameters
+            .section
+            .map(|(point, normal)| ClippingPlane::new(point, normal, materials.get("section_cap")))
+            .into_iter()
+            .collect(),
+        materials,
     };
+    for warning in scene.validate()? {
+        println!("Warning: {}", warning);
+    }
     println!("Done.");
 
+    let integrator: Arc<dyn Integrator> = match parameters.integrator.as_str() {
+        "wavefront" => Arc::new(WavefrontIntegrator {}),
+        "polarized" => Arc::new(PolarizedIntegrator {
+            filter: parameters.polarizing_filter,
+        }),
+        "whitted" => Arc::new(WhittedIntegrator {
+            ambient_light: Spectrum::black(),
+            area_lights: vec![],
+        }),
+        "normals" => Arc::new(DebugIntegrator {
+            view: DebugView::Normals,
+        }),
+        "depth" => Arc::new(DebugIntegrator {
+            view: DebugView::Depth {
+                max_distance: scene.bounding_box().diagonal().norm().min(100.0),
+            },
+        }),
+        "uv" => Arc::new(DebugIntegrator {
+            view: DebugView::Uv,
+        }),
+        "traversal-cost" => Arc::new(DebugIntegrator {
+            view: DebugView::TraversalCost { max_cost: 200.0 },
+        }),
+        _ => Arc::new(SimpleRandomIntegrator {}),
+    };
+    let filter: Arc<dyn Filter> = match parameters.filter.as_str() {
+        "tent" => Arc::new(TentFilter::default()),
+        "gaussian" => Arc::new(GaussianFilter::default()),
+        "mitchell" => Arc::new(MitchellFilter::default()),
+        _ => Arc::new(BoxFilter::default()),
+    };
+    let settings = RenderSettings {
+        integrator,
+        samples_per_pixel: 1,
+        max_depth: parameters.max_depth,
+        tile_size: PASS_TILE_SIZE,
+        seed: 0,
+        filter,
+        max_radiance: parameters.max_radiance,
+        quantum_efficiency,
+        cancellation: CancellationToken::new(),
+        shutter: parameters.shutter,
+    };
+    let cancellation = settings.cancellation.clone();
+    if let Some(ref address) = parameters.worker {
+        println!("Rendering tiles for {}...", address);
+        let connections = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let tiles = distributed::run_worker(address.as_str(), &scene, &settings, connections)?;
+        println!("Rendered {} tiles.", tiles);
+        return Ok(());
+    }
+
+    if let (Some(bucket_file), Some(image_filename)) =
+        (&parameters.bucket_file, &parameters.output_file)
+    {
+        println!("Rendering to {}...", bucket_file.display());
+        let image = BucketedImage::create(bucket_file, image_width, image_height)?;
+        let settings = RenderSettings {
+            samples_per_pixel: parameters.samples_per_pixel.unwrap(),
+            tile_size: BUCKET_SIZE,
+            ..settings
+        };
+        render_bucketed(&scene, &image, &settings)?;
+        image.write_png(
+            image_filename,
+            &tone_mapper(parameters.exposure, &response, &look),
+        )?;
+        std::fs::remove_file(bucket_file)?;
+        return Ok(());
+    }
+
+    if let Some(ref light_paths_file) = parameters.light_paths_file {
+        println!("Tracing light paths...");
+        let settings = RenderSettings {
+            samples_per_pixel: parameters.samples_per_pixel.unwrap_or(1),
+            ..settings.clone()
+        };
+        let points = trace_light_paths(&scene, &settings, image_width, image_height);
+        write_light_paths(&points, light_paths_file, image_width, image_height)?;
+    }
+
+    let id_buffers = if parameters.write_ids {
+        println!("Rendering ID buffers...");
+        Some(render_id_buffers(&scene, image_width, image_height))
+    } else {
+        None
+    };
+
+    let denoising_aovs = if parameters.denoise {
+        println!("Rendering denoising AOVs...");
+        Some(render_denoising_aovs(&scene, image_width, image_height))
+    } else {
+        None
+    };
+
+    let rendered_image = Arc::new(Mutex::new(AccumulationBuffer::new(
+        image_width,
+        image_height,
+    )));
+
+    let (sdl_context, mut canvas) = init_canvas(image_width, image_height)?;
+
+    let texture_creator = canvas.texture_creator();
+    let mut rendered_image_texture = texture_creator.create_texture_streaming(
+        PixelFormatEnum::RGB24,
+        image_width as u32,
+        image_height as u32,
+    )?;
+
     let mut event_pump = sdl_context.event_pump()?;
 
-    let (tile_tx, tile_rx) = mpsc::channel();
-    let mut tile_rx = Some(tile_rx);
+    let total_samples_per_pixel = parameters.samples_per_pixel;
+    let checkpoint_file = parameters.checkpoint_file.clone();
+    let resume = parameters.resume;
+    let deterministic = parameters.deterministic;
+    let snapshot_file = parameters.output_file.clone();
+    let mut snapshot_schedule = parameters.snapshot_interval.map(SnapshotSchedule::new);
+    let snapshot_tone_mapper = tone_mapper(parameters.exposure, &response, &look);
+    let snapshot_hdr = parameters.snapshot_hdr;
+    let coordinator = parameters.coordinator.clone();
 
+    let (pass_tx, pass_rx) = mpsc::channel();
+    let mut pass_rx = Some(pass_rx);
+
+    let worker_image = Arc::clone(&rendered_image);
     let worker_boss = std::thread::spawn(move || {
-        let end_tx = tile_tx.clone();
-        TileIterator::new(image_width as usize, image_height as usize, 2048)
-            .cycle()
-            .map(move |tile| (tile, tile_tx.clone()))
-            .par_bridge()
-            .try_for_each(|(tile, tx)| {
-                let rendered_tile = partial_render_scene(&scene, tile, image_height, image_width);
-
-                // There's nothing we can do if this fails, and we're already
-                // at the end of the function anyway, so just ignore result.
-                tx.send(Some((tile, rendered_tile))).ok()
-            });
-        end_tx.send(None).ok();
+        if let Some(address) = coordinator {
+            let listener = TcpListener::bind(address)?;
+            println!("Waiting for workers at {}...", listener.local_addr()?);
+            // Every sample is taken in one pass, since workers leave once it's finished
+            let settings = RenderSettings {
+                samples_per_pixel: total_samples_per_pixel.unwrap(),
+                tile_size: DISTRIBUTED_TILE_SIZE,
+                ..settings
+            };
+            distributed::coordinate(&listener, &worker_image, &settings)?;
+            println!("Done.");
+            pass_tx.send(None).ok();
+            return Ok(());
+        }
+        let mut renderer = ProgressiveRenderer::new(&scene, worker_image, settings);
+        renderer.set_deterministic(deterministic);
+        if let (true, Some(checkpoint_file)) = (resume, &checkpoint_file) {
+            println!("Resuming from checkpoint...");
+            renderer.resume_from_checkpoint(checkpoint_file)?;
+        }
+        let mut rays = RayStatistics::default();
+        loop {
+            let statistics = renderer.render_pass();
+            rays = rays.add(&statistics.rays);
+            println!(
+                "Pass {} done in {:.2}s ({:.0} samples/s)",
+                statistics.pass,
+                statistics.duration.as_secs_f64(),
+                statistics.samples_per_second
+            );
+            if let Some(ref checkpoint_file) = checkpoint_file {
+                renderer.write_checkpoint(checkpoint_file)?;
+            }
+            if let (Some(schedule), Some(snapshot_file)) = (&mut snapshot_schedule, &snapshot_file)
+            {
+                if schedule.pass_completed() {
+                    let image = renderer.image();
+                    let image = image.lock().expect("Accumulation buffer lock poisoned.");
+                    write_snapshot(&image, snapshot_file, &snapshot_tone_mapper, snapshot_hdr)?;
+                }
+            }
+            // Stop rendering once enough samples have been taken, or once the display loop
+            // has hung up
+            if pass_tx.send(Some(statistics)).is_err()
+                || total_samples_per_pixel
+                    .is_some_and(|total| statistics.samples_per_pixel >= total)
+            {
+                break;
+            }
+        }
+        if statistics::ENABLED {
+            println!(
+                "{} rays, {} shadow rays, {} BVH nodes visited, {} triangle tests",
+                rays.rays_cast, rays.shadow_rays, rays.bvh_nodes_visited, rays.triangle_tests
+            );
+        }
+        pass_tx.send(None).ok();
+        Ok::<(), VanrijnError>(())
     });
 
     'running: loop {
-        if let Some(ref tile_rx) = tile_rx {
-            for message in tile_rx.try_iter() {
-                if let Some((tile, tile_accumulation_buffer)) = message {
-                    rendered_image.merge_tile(&tile, &tile_accumulation_buffer);
-                    let rgb_image = rendered_image.to_image_rgb_u8(&ClampingToneMapper {});
+        if let Some(ref pass_rx) = pass_rx {
+            for message in pass_rx.try_iter() {
+                let tone_mapper = tone_mapper(parameters.exposure, &response, &look);
+                let buffer = rendered_image
+                    .lock()
+                    .expect("Accumulation buffer lock poisoned.");
+                // Only the final image is denoised, since the display is updated each pass
+                let mut rgb_image = match (&message, &denoising_aovs) {
+                    (None, Some(aovs)) => {
+                        let denoised =
+                            CrossBilateralDenoiser::default().denoise(buffer.colours(), aovs);
+                        let mut rgb_image = ImageRgbU8::new(buffer.width(), buffer.height());
+                        tone_mapper.apply_tone_mapping(&denoised, &mut rgb_image);
+                        rgb_image
+                    }
+                    _ => buffer.to_image_rgb_u8(&tone_mapper),
+                };
+                if parameters.show_invalid {
+                    buffer.mark_invalid_pixels(&mut rgb_image);
+                }
+                if message.is_none() && buffer.total_invalid_samples() > 0 {
+                    println!(
+                        "Dropped {} NaN or infinite samples.",
+                        buffer.total_invalid_samples()
+                    );
+                }
+                drop(buffer);
+                if message.is_some() {
                     update_texture(&rgb_image, &mut rendered_image_texture);
                     canvas.copy(&rendered_image_texture, None, None).unwrap();
                     canvas.present();
                 } else if let Some(image_filename) = parameters.output_file {
-                    rendered_image
-                        .to_image_rgb_u8(&ClampingToneMapper {})
-                        .write_png(&image_filename)?;
+                    rgb_image.write_png(&image_filename)?;
+                    if let Some(ref id_buffers) = id_buffers {
+                        write_id_buffers(id_buffers, &image_filename)?;
+                    }
                     break 'running;
                 }
             }
@@ -234,14 +1072,58 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => break 'running,
+                } => {
+                    cancellation.cancel();
+                    break 'running;
+                }
                 _ => {}
             }
         }
 
         ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
     }
-    drop(tile_rx.take());
-    worker_boss.join().expect("Couldn't join worker threads.");
+    drop(pass_rx.take());
+    worker_boss.join().expect("Couldn't join worker threads.")?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_arguments_are_parsed() {
+        let target = parse_args([
+            "vanrijn",
+            "--size",
+            "64",
+            "48",
+            "--out",
+            "image.png",
+            "--snapshot-every",
+            "30s",
+            "--snapshot-hdr",
+        ]);
+        assert!(target.snapshot_interval == Some(SnapshotInterval::Time(Duration::from_secs(30))));
+        assert!(target.snapshot_hdr);
+        let target = parse_args([
+            "vanrijn",
+            "--size",
+            "64",
+            "48",
+            "--out",
+            "image.png",
+            "--snapshot-every",
+            "10",
+        ]);
+        assert!(target.snapshot_interval == Some(SnapshotInterval::Passes(10)));
+        assert!(!target.snapshot_hdr);
+    }
+
+    #[test]
+    fn snapshots_are_off_by_default() {
+        let target = parse_args(["vanrijn", "--size", "64", "48"]);
+        assert!(target.snapshot_interval.is_none());
+        assert!(!target.snapshot_hdr);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::colour::Photon;
+use crate::math::{Vec2, Vec3};
+use crate::media::Medium;
+use crate::raycasting::Footprint;
+use crate::validation::SceneValidator;
+
+use super::smooth_transparent_dialectric::fresnel;
+use super::{Bsdf, Material, MaterialSampleResult};
+
+use rand::{Rng, RngCore};
+
+use std::f64::consts::PI;
+
+/// A smooth, clear dielectric coating over another material, such as the lacquer on car
+/// paint or varnished wood
+///
+/// At each point light is either reflected by the coating, as a mirror, or passes through
+/// it to the base material, in proportion to the coating's Fresnel reflectance. Light
+/// reflected by the base has to pass back out through the coating, and the part that the
+/// coating reflects back down is reflected by the base again, and so on. The coating is
+/// treated as infinitely thin, so it doesn't bend light on the way to the base.
+///
+/// The light reflected back down is added up assuming the base scatters it evenly, with
+/// the reflectance it has along the normal, which is exact for Lambertian bases.
+#[derive(Debug)]
+pub struct ClearCoat<M: Material> {
+    pub base: M,
+
+    /// The index of refraction of the coating
+    eta: f64,
+
+    /// The fraction of light scattered evenly by the base that the coating reflects back
+    /// down to it
+    internal_reflectance: f64,
+}
+
+impl<M: Material> ClearCoat<M> {
+    pub fn new(base: M, eta: f64) -> ClearCoat<M> {
+        // The cosine-weighted average of the reflectance over the hemisphere, by the
+        // midpoint rule in the cosine
+        const STEPS: usize = 1000;
+        let internal_reflectance = (0..STEPS)
+            .map(|i| {
+                let cos_theta = (i as f64 + 0.5) / STEPS as f64;
+                let w = Vec3::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta);
+                2.0 * cos_theta * fresnel(&w, 1.0, eta).reflection_strength
+            })
+            .sum::<f64>()
+            / STEPS as f64;
+        ClearCoat {
+            base,
+            eta,
+            internal_reflectance,
+        }
+    }
+
+    /// The index of refraction of the coating
+    pub fn eta(&self) -> f64 {
+        self.eta
+    }
+
+    /// The fraction of light arriving from `w` that the coating reflects
+    fn reflectance(&self, w: &Vec3) -> f64 {
+        if w.z() <= 0.0 {
+            0.0
+        } else {
+            fresnel(w, 1.0, self.eta).reflection_strength
+        }
+    }
+
+    fn is_coating_reflection(w_i: &Vec3, w_o: &Vec3) -> bool {
+        let reflection_direction = w_i.reflect(&Vec3::unit_z());
+        (*w_o - reflection_direction).norm_squared() < 0.0000000001
+    }
+
+    /// The BSDF of the coated material, where `base` is the BSDF of the base
+    fn coat<'a>(&'a self, base: Bsdf<'a>) -> Bsdf<'a> {
+        Box::new(move |w_o: &Vec3, w_i: &Vec3, photon_in: &Photon| {
+            let reflectance = self.reflectance(w_i);
+            if reflectance > 0.0 && ClearCoat::<M>::is_coating_reflection(w_i, w_o) {
+                photon_in.scale_intensity(reflectance)
+            } else {
+                let albedo = PI
+                    * base(
+                        &Vec3::unit_z(),
+                        &Vec3::unit_z(),
+                        &photon_in.set_intensity(1.0),
+                    )
+                    .intensity;
+                let bounces = 1.0 / (1.0 - albedo * self.internal_reflectance);
+                base(w_o, w_i, photon_in)
+                    .scale_intensity((1.0 - reflectance) * (1.0 - self.reflectance(w_o)) * bounces)
+            }
+        })
+    }
+}
+
+impl<M: Material> Material for ClearCoat<M> {
+    fn bsdf<'a>(&'a self, uv: &Vec2) -> Bsdf<'a> {
+        self.coat(self.base.bsdf(uv))
+    }
+
+    fn filtered_bsdf<'a>(&'a self, uv: &Vec2, footprint: &Footprint) -> Bsdf<'a> {
+        self.coat(self.base.filtered_bsdf(uv, footprint))
+    }
+
+    fn sample(
+        &self,
+        uv: &Vec2,
+        w_i: &Vec3,
+        photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        // The coating is chosen as often as it reflects, so that the two layers are sampled
+        // in proportion to the light they contribute
+        let reflectance = self.reflectance(w_i);
+        if rng.gen::<f64>() < reflectance {
+            MaterialSampleResult {
+                direction: w_i.reflect(&Vec3::unit_z()),
+                pdf: reflectance,
+                is_specular: true,
+            }
+        } else {
+            let base = self.base.sample(uv, w_i, photon, rng);
+            MaterialSampleResult {
+                pdf: base.pdf * (1.0 - reflectance),
+                ..base
+            }
+        }
+    }
+
+    fn pdf(&self, uv: &Vec2, w_i: &Vec3, w_o: &Vec3, photon: &Photon) -> f64 {
+        let reflectance = self.reflectance(w_i);
+        let base = self.base.pdf(uv, w_i, w_o, photon) * (1.0 - reflectance);
+        if reflectance > 0.0 && ClearCoat::<M>::is_coating_reflection(w_i, w_o) {
+            base + reflectance
+        } else {
+            base
+        }
+    }
+
+    fn emission(&self, w_o: &Vec3, photon: &Photon) -> Photon {
+        self.base
+            .emission(w_o, photon)
+            .scale_intensity(1.0 - self.reflectance(w_o))
+    }
+
+    fn interior_medium(&self) -> Option<&dyn Medium> {
+        self.base.interior_medium()
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+
+    fn is_two_sided(&self) -> bool {
+        self.base.is_two_sided()
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        self.base.validate(validator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::Spectrum;
+    use crate::materials::LambertianMaterial;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn target() -> ClearCoat<LambertianMaterial> {
+        ClearCoat::new(
+            LambertianMaterial {
+                colour: Spectrum::grey(0.5),
+                diffuse_strength: 1.0,
+            },
+            1.5,
+        )
+    }
+
+    fn photon() -> Photon {
+        Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        }
+    }
+
+    #[test]
+    fn coating_reflects_like_glass() {
+        let target = target();
+        let w_i = Vec3::unit_z();
+        let bsdf = target.bsdf(&Vec2::new(0.0, 0.0));
+        // Glass with an index of 1.5 reflects 4% at normal incidence
+        assert!((bsdf(&w_i, &w_i, &photon()).intensity - 0.04).abs() < 0.000001);
+    }
+
+    #[test]
+    fn base_is_dimmed_by_light_reflected_by_coating() {
+        let target = target();
+        let w_i = Vec3::new(0.0, 0.6, 0.8);
+        let w_o = Vec3::new(0.3, 0.0, 0.9).normalize();
+        let coated = target.bsdf(&Vec2::new(0.0, 0.0))(&w_o, &w_i, &photon()).intensity;
+        let base = target.base.bsdf(&Vec2::new(0.0, 0.0))(&w_o, &w_i, &photon()).intensity;
+        assert!(coated < base);
+        assert!(coated > base * 0.9);
+    }
+
+    #[test]
+    fn coating_over_white_base_reflects_all_light() {
+        let target = ClearCoat::new(
+            LambertianMaterial {
+                colour: Spectrum::grey(1.0),
+                diffuse_strength: 1.0,
+            },
+            1.5,
+        );
+        let bsdf = target.bsdf(&Vec2::new(0.0, 0.0));
+        for w_i in [Vec3::unit_z(), Vec3::new(0.0, 0.8, 0.6)] {
+            // The base scatters evenly, so only the angle to the normal matters
+            let steps = 1000;
+            let base = (0..steps)
+                .map(|i| {
+                    let cos_theta = (i as f64 + 0.5) / steps as f64;
+                    let w_o = Vec3::new(0.0, -(1.0 - cos_theta * cos_theta).sqrt(), cos_theta);
+                    2.0 * PI * cos_theta * bsdf(&w_o, &w_i, &photon()).intensity
+                })
+                .sum::<f64>()
+                / steps as f64;
+            assert!((base + target.reflectance(&w_i) - 1.0).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn grazing_light_samples_coating_more_often() {
+        let target = target();
+        let mut rng = StdRng::seed_from_u64(0);
+        let specular_fraction = |w_i: Vec3, rng: &mut StdRng| {
+            (0..10000)
+                .filter(|_| {
+                    target
+                        .sample(&Vec2::new(0.0, 0.0), &w_i, &photon(), rng)
+                        .is_specular
+                })
+                .count() as f64
+                / 10000.0
+        };
+        let normal = specular_fraction(Vec3::unit_z(), &mut rng);
+        let grazing = specular_fraction(Vec3::new(0.0, 0.995, 0.1).normalize(), &mut rng);
+        assert!((normal - 0.04).abs() < 0.01);
+        assert!(grazing > 0.5);
+    }
+
+    #[test]
+    fn pdf_matches_sample() {
+        let target = target();
+        let mut rng = StdRng::seed_from_u64(0);
+        let w_i = Vec3::new(0.0, 0.6, 0.8);
+        for _ in 0..100 {
+            let sample = target.sample(&Vec2::new(0.0, 0.0), &w_i, &photon(), &mut rng);
+            let pdf = target.pdf(&Vec2::new(0.0, 0.0), &w_i, &sample.direction, &photon());
+            if sample.is_specular {
+                assert!((sample.pdf - target.reflectance(&w_i)).abs() < 0.000001);
+            } else {
+                assert!((pdf - sample.pdf).abs() < 0.000001);
+            }
+        }
+    }
+}
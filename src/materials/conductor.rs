@@ -0,0 +1,220 @@
+use crate::colour::polarization::fresnel_amplitudes;
+use crate::colour::{MuellerMatrix, Photon, Spectrum};
+use crate::math::{Complex, Vec2, Vec3};
+
+use super::{Bsdf, Material, MaterialSampleResult};
+
+use rand::RngCore;
+
+/// The fraction of unpolarized light reflected by a conductor at an angle with cosine
+/// `cos_theta` to the normal
+///
+/// `eta` and `k` are the real and imaginary parts of the conductor's index of refraction,
+/// relative to the medium the light arrives through.
+fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta * cos_theta;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta * a;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+    0.5 * (parallel + perpendicular)
+}
+
+/// A smooth metal, which reflects light like a mirror with a colour that depends on the
+/// angle
+///
+/// The reflectance is found from the metal's complex index of refraction,
+/// `eta + i k`, using the Fresnel equations for conductors. Presets are provided for some
+/// common metals, using measured data.
+#[derive(Debug)]
+pub struct Conductor {
+    pub eta: Spectrum,
+    pub k: Spectrum,
+}
+
+impl Conductor {
+    pub fn new(eta: Spectrum, k: Spectrum) -> Conductor {
+        Conductor { eta, k }
+    }
+
+    pub fn gold() -> Conductor {
+        Conductor::new(
+            Spectrum::gold_index_of_refraction(),
+            Spectrum::gold_extinction_coefficient(),
+        )
+    }
+
+    pub fn silver() -> Conductor {
+        Conductor::new(
+            Spectrum::silver_index_of_refraction(),
+            Spectrum::silver_extinction_coefficient(),
+        )
+    }
+
+    pub fn copper() -> Conductor {
+        Conductor::new(
+            Spectrum::copper_index_of_refraction(),
+            Spectrum::copper_extinction_coefficient(),
+        )
+    }
+
+    pub fn aluminium() -> Conductor {
+        Conductor::new(
+            Spectrum::aluminium_index_of_refraction(),
+            Spectrum::aluminium_extinction_coefficient(),
+        )
+    }
+
+    /// The fraction of light of `wavelength` reflected at an angle with cosine `cos_theta`
+    /// to the normal
+    pub fn reflectance(&self, cos_theta: f64, wavelength: f64) -> f64 {
+        fresnel_conductor(
+            cos_theta.clamp(0.0, 1.0),
+            self.eta.intensity_at_wavelength(wavelength),
+            self.k.intensity_at_wavelength(wavelength),
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn bsdf<'a>(&'a self, _uv: &Vec2) -> Bsdf<'a> {
+        Box::new(move |w_o: &Vec3, w_i: &Vec3, photon_in: &Photon| {
+            let reflection_direction = w_i.reflect(&Vec3::unit_z());
+            if w_i.z() <= 0.0 || (*w_o - reflection_direction).norm_squared() >= 0.0000000001 {
+                photon_in.set_intensity(0.0)
+            } else {
+                photon_in.scale_intensity(self.reflectance(w_i.z(), photon_in.wavelength))
+            }
+        })
+    }
+
+    fn sample(
+        &self,
+        _uv: &Vec2,
+        w_i: &Vec3,
+        _photon: &Photon,
+        _rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        MaterialSampleResult {
+            direction: w_i.reflect(&Vec3::unit_z()),
+            pdf: 1.0,
+            is_specular: true,
+        }
+    }
+
+    fn pdf(&self, _uv: &Vec2, w_i: &Vec3, w_o: &Vec3, _photon: &Photon) -> f64 {
+        let reflection_direction = w_i.reflect(&Vec3::unit_z());
+        if (*w_o - reflection_direction).norm_squared() < 0.0000000001 {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    fn mueller_matrix(
+        &self,
+        _uv: &Vec2,
+        w_i: &Vec3,
+        w_o: &Vec3,
+        photon: &Photon,
+    ) -> Option<MuellerMatrix> {
+        let reflection_direction = w_i.reflect(&Vec3::unit_z());
+        if w_i.z() <= 0.0 || (*w_o - reflection_direction).norm_squared() >= 0.0000000001 {
+            return Some(MuellerMatrix::depolarizer(0.0));
+        }
+        let eta = Complex::new(
+            self.eta.intensity_at_wavelength(photon.wavelength),
+            self.k.intensity_at_wavelength(photon.wavelength),
+        );
+        let (r_s, r_p) = fresnel_amplitudes(w_i.z(), eta);
+        Some(MuellerMatrix::fresnel_reflection(r_s, r_p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::Stokes;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn normal_incidence_matches_closed_form() {
+        let (eta, k): (f64, f64) = (0.5, 2.5);
+        let expected = ((eta - 1.0).powi(2) + k * k) / ((eta + 1.0).powi(2) + k * k);
+        assert!((fresnel_conductor(1.0, eta, k) - expected).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn grazing_light_is_fully_reflected() {
+        for metal in [Conductor::gold(), Conductor::aluminium()].iter() {
+            assert!(metal.reflectance(0.0, 550.0) > 0.999);
+            assert!(metal.reflectance(1.0, 550.0) < metal.reflectance(0.01, 550.0));
+        }
+    }
+
+    #[test]
+    fn metals_have_their_colours() {
+        let gold = Conductor::gold();
+        let copper = Conductor::copper();
+        let silver = Conductor::silver();
+        // Gold and copper reflect red light much better than blue
+        assert!(gold.reflectance(1.0, 650.0) > gold.reflectance(1.0, 450.0) + 0.3);
+        assert!(copper.reflectance(1.0, 650.0) > copper.reflectance(1.0, 450.0) + 0.3);
+        // Silver reflects almost everything
+        assert!(silver.reflectance(1.0, 450.0) > 0.9);
+        assert!(silver.reflectance(1.0, 650.0) > 0.9);
+    }
+
+    #[test]
+    fn only_mirror_direction_is_reflected() {
+        let target = Conductor::silver();
+        let w_i = Vec3::new(0.6, 0.0, 0.8);
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let bsdf = target.bsdf(&Vec2::new(0.0, 0.0));
+        let sample = target.sample(
+            &Vec2::new(0.0, 0.0),
+            &w_i,
+            &photon,
+            &mut StdRng::seed_from_u64(0),
+        );
+        assert!(bsdf(&sample.direction, &w_i, &photon).intensity > 0.9);
+        assert!(bsdf(&w_i, &w_i, &photon).intensity == 0.0);
+    }
+
+    #[test]
+    fn mueller_matrix_reflects_unpolarized_light_as_bsdf_does() {
+        let target = Conductor::gold();
+        let photon = Photon {
+            wavelength: 600.0,
+            intensity: 1.0,
+        };
+        let bsdf = target.bsdf(&Vec2::new(0.0, 0.0));
+        for w_i in [
+            Vec3::new(0.6, 0.0, 0.8),
+            Vec3::new(0.1, -0.9, 0.2).normalize(),
+        ]
+        .iter()
+        {
+            let w_o = w_i.reflect(&Vec3::unit_z());
+            let mueller = target
+                .mueller_matrix(&Vec2::new(0.0, 0.0), w_i, &w_o, &photon)
+                .unwrap();
+            let reflected = (mueller * Stokes::unpolarized(1.0)).intensity();
+            assert!((reflected - bsdf(&w_o, w_i, &photon).intensity).abs() < 0.000000001);
+        }
+    }
+}
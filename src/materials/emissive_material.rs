@@ -0,0 +1,71 @@
+use crate::colour::{Photon, Spectrum};
+use crate::math::{Vec2, Vec3};
+
+use super::{Bsdf, Material};
+
+use std::fmt::Debug;
+
+/// A material that emits light, turning the surface it's applied to into an area light
+///
+/// Light is only emitted from the front side of the surface (the side the normal points
+/// towards). All light arriving at the surface is absorbed.
+#[derive(Debug)]
+pub struct EmissiveMaterial {
+    /// The emitted radiance, in W/(m^2sr) per nanometre
+    pub emission: Spectrum,
+}
+
+impl Material for EmissiveMaterial {
+    fn bsdf<'a>(&'a self, _uv: &Vec2) -> Bsdf<'a> {
+        Box::new(move |_w_o: &Vec3, _w_i: &Vec3, photon_in: &Photon| photon_in.set_intensity(0.0))
+    }
+
+    fn emission(&self, w_o: &Vec3, photon: &Photon) -> Photon {
+        if w_o.z() > 0.0 {
+            self.emission.emit_photon(photon)
+        } else {
+            photon.set_intensity(0.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_photon() -> Photon {
+        Photon {
+            wavelength: 500.0,
+            intensity: 0.25,
+        }
+    }
+
+    #[test]
+    fn emits_expected_intensity_from_front_face() {
+        let target = EmissiveMaterial {
+            emission: Spectrum::grey(3.0),
+        };
+        let emitted = target.emission(&Vec3::new(0.0, 0.6, 0.8), &test_photon());
+        assert!(emitted.intensity == 3.0);
+        assert!(emitted.wavelength == 500.0);
+    }
+
+    #[test]
+    fn does_not_emit_from_back_face() {
+        let target = EmissiveMaterial {
+            emission: Spectrum::grey(3.0),
+        };
+        let emitted = target.emission(&Vec3::new(0.0, 0.6, -0.8), &test_photon());
+        assert!(emitted.intensity == 0.0);
+    }
+
+    #[test]
+    fn absorbs_incoming_light() {
+        let target = EmissiveMaterial {
+            emission: Spectrum::grey(3.0),
+        };
+        let reflected =
+            target.bsdf(&Vec2::new(0.0, 0.0))(&Vec3::unit_z(), &Vec3::unit_z(), &test_photon());
+        assert!(reflected.intensity == 0.0);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::colour::{Photon, Spectrum};
+use crate::math::{Vec2, Vec3};
+use crate::random_distributions::{RandomDistribution, UniformSphere};
+use crate::raycasting::Footprint;
+use crate::textures::Texture;
+use crate::validation::SceneValidator;
+
+use super::{Bsdf, Material, MaterialSampleResult};
+
+use rand::RngCore;
+
+use std::f64::consts::PI;
+
+/// A simple model of hair fibres, for use with [Curve](crate::raycasting::Curve)
+///
+/// This is the Kajiya-Kay model. Each fibre is a thin cylinder along the tangent, so it
+/// reflects light arriving from any direction around it, not only from above the surface.
+/// The diffuse part is brightest for light arriving perpendicular to the fibre, and the
+/// specular part reflects light into a cone around the fibre, at the same angle to it as
+/// the light arrives, which gives hair its characteristic highlights.
+///
+/// The model doesn't account for light passing through the fibres, so it suits dark hair
+/// better than light hair.
+#[derive(Debug)]
+pub struct HairMaterial<T: Texture = Spectrum> {
+    pub colour: T,
+    pub diffuse_strength: f64,
+    pub specular_strength: f64,
+
+    /// How tightly the highlight is concentrated on the cone of reflection
+    pub smoothness: f64,
+}
+
+impl<T: Texture> HairMaterial<T> {
+    /// The BSDF, with the colour at each wavelength given by `colour`
+    fn bsdf_with_colour<'a, F: Fn(f64) -> f64 + 'a>(&'a self, colour: F) -> Bsdf<'a> {
+        Box::new(move |w_o: &Vec3, w_i: &Vec3, photon_in: &Photon| {
+            // Integrators weight the BSDF by the cosine to the normal, which doesn't apply to
+            // a fibre, so it's divided out. Grazing the normal exactly, it's left out.
+            let cos_to_normal = w_o.z().abs();
+            if cos_to_normal < 0.000_001 {
+                return photon_in.set_intensity(0.0);
+            }
+            // The tangent is along x, so the x coordinates are the cosines to the fibre
+            let sin_o = (1.0 - w_o.x() * w_o.x()).max(0.0).sqrt();
+            let sin_i = (1.0 - w_i.x() * w_i.x()).max(0.0).sqrt();
+            // The diffuse part integrates to one over the sphere. The highlight integrates
+            // to at most one, and to nearly that for light arriving perpendicular to the
+            // fibre, since the integral of cosⁿ over a half circle is less than √(2π/(n+½)).
+            let diffuse = colour(photon_in.wavelength) * self.diffuse_strength * sin_o / (PI * PI);
+            let cos_to_cone = (sin_o * sin_i - w_o.x() * w_i.x()).max(0.0);
+            let specular = self.specular_strength
+                * cos_to_cone.powf(self.smoothness)
+                * ((self.smoothness + 0.5) / (2.0 * PI)).sqrt()
+                / (2.0 * PI);
+            photon_in.scale_intensity((diffuse + specular) / cos_to_normal)
+        })
+    }
+}
+
+/// The density, over the polar angles of `w`, of directions chosen uniformly over the sphere
+fn sphere_pdf(w: &Vec3) -> f64 {
+    let sin_theta = (1.0 - w.z() * w.z()).max(0.0).sqrt();
+    UniformSphere::new().pdf(*w) * sin_theta
+}
+
+impl<T: Texture> Material for HairMaterial<T> {
+    fn bsdf<'a>(&'a self, uv: &Vec2) -> Bsdf<'a> {
+        let uv = *uv;
+        self.bsdf_with_colour(move |wavelength| self.colour.value(&uv, wavelength))
+    }
+
+    fn filtered_bsdf<'a>(&'a self, uv: &Vec2, footprint: &Footprint) -> Bsdf<'a> {
+        let (uv, footprint) = (*uv, *footprint);
+        self.bsdf_with_colour(move |wavelength| {
+            self.colour.filtered_value(&uv, &footprint, wavelength)
+        })
+    }
+
+    /// Directions are chosen over the whole sphere, since fibres are lit from behind as
+    /// well as in front
+    fn sample(
+        &self,
+        _uv: &Vec2,
+        _w_i: &Vec3,
+        _photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        let direction = UniformSphere::new().value(rng);
+        MaterialSampleResult {
+            direction,
+            pdf: sphere_pdf(&direction),
+            is_specular: false,
+        }
+    }
+
+    fn pdf(&self, _uv: &Vec2, _w_i: &Vec3, w_o: &Vec3, _photon: &Photon) -> f64 {
+        sphere_pdf(&w_o.normalize())
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_strength("diffuse_strength", self.diffuse_strength);
+        validator.check_strength("specular_strength", self.specular_strength);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn test_hair(diffuse_strength: f64, specular_strength: f64) -> HairMaterial {
+        HairMaterial {
+            colour: Spectrum::grey(1.0),
+            diffuse_strength,
+            specular_strength,
+            smoothness: 50.0,
+        }
+    }
+
+    #[test]
+    fn diffuse_part_reflects_all_light_over_sphere() {
+        let target = test_hair(1.0, 0.0);
+        let bsdf = target.bsdf(&Vec2::new(0.0, 0.0));
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let w_i = Vec3::new(0.3, 0.2, 0.8).normalize();
+        let mut rng = StdRng::seed_from_u64(0);
+        let samples = 100000;
+        let total = (0..samples)
+            .map(|_| {
+                let sample = target.sample(&Vec2::new(0.0, 0.0), &w_i, &photon, &mut rng);
+                let cos_theta = sample.direction.z().abs();
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                bsdf(&sample.direction, &w_i, &photon).intensity * cos_theta * sin_theta
+                    / sample.pdf
+            })
+            .sum::<f64>()
+            / samples as f64;
+        assert!((total - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn highlight_lies_on_cone_around_fibre() {
+        let target = test_hair(0.0, 1.0);
+        let bsdf = target.bsdf(&Vec2::new(0.0, 0.0));
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let w_i = Vec3::new(0.5, 0.0, 0.75f64.sqrt());
+        // Any direction at the mirrored angle to the fibre, all the way around it
+        let on_cone = Vec3::new(-0.5, 0.75f64.sqrt() * 0.6, 0.75f64.sqrt() * 0.8);
+        let off_cone = Vec3::new(0.5, 0.0, 0.75f64.sqrt());
+        assert!(bsdf(&on_cone, &w_i, &photon).intensity > 0.1);
+        assert!(bsdf(&off_cone, &w_i, &photon).intensity < 0.000_001);
+    }
+}
@@ -1,17 +1,26 @@
 use crate::colour::{Photon, Spectrum};
-use crate::math::Vec3;
+use crate::math::{Vec2, Vec3};
+use crate::raycasting::Footprint;
+use crate::textures::Texture;
+use crate::validation::SceneValidator;
 
-use super::{Material, MaterialSampleResult};
+use super::{Bsdf, Material, MaterialSampleResult};
 
 use rand::distributions::Open01;
-use rand::{thread_rng, Rng};
+use rand::{Rng, RngCore};
 
 use std::f64::consts::PI;
 use std::fmt::Debug;
 
+fn sample_pdf(w_o: &Vec3) -> f64 {
+    let cos_theta = w_o.dot(&Vec3::unit_z());
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    (cos_theta * sin_theta) / PI
+}
+
 #[derive(Debug)]
-pub struct LambertianMaterial {
-    pub colour: Spectrum,
+pub struct LambertianMaterial<T: Texture = Spectrum> {
+    pub colour: T,
     pub diffuse_strength: f64,
 }
 
@@ -24,17 +33,40 @@ impl LambertianMaterial {
     }
 }
 
-impl Material for LambertianMaterial {
-    fn bsdf<'a>(&'a self) -> Box<dyn Fn(&Vec3, &Vec3, &Photon) -> Photon + 'a> {
+impl<T: Texture> LambertianMaterial<T> {
+    /// The BSDF, with the colour at each wavelength given by `colour`
+    ///
+    /// Light is scattered equally in every direction, so the BSDF is the reflectance
+    /// divided by π.
+    fn bsdf_with_colour<'a, F: Fn(f64) -> f64 + 'a>(&'a self, colour: F) -> Bsdf<'a> {
         Box::new(move |_w_o: &Vec3, _w_i: &Vec3, photon_in: &Photon| {
-            let mut result = self.colour.scale_photon(photon_in);
-            result.intensity *= self.diffuse_strength;
+            let mut result = photon_in.scale_intensity(colour(photon_in.wavelength));
+            result.intensity *= self.diffuse_strength / PI;
             result
         })
     }
+}
+
+impl<T: Texture> Material for LambertianMaterial<T> {
+    fn bsdf<'a>(&'a self, uv: &Vec2) -> Bsdf<'a> {
+        let uv = *uv;
+        self.bsdf_with_colour(move |wavelength| self.colour.value(&uv, wavelength))
+    }
+
+    fn filtered_bsdf<'a>(&'a self, uv: &Vec2, footprint: &Footprint) -> Bsdf<'a> {
+        let (uv, footprint) = (*uv, *footprint);
+        self.bsdf_with_colour(move |wavelength| {
+            self.colour.filtered_value(&uv, &footprint, wavelength)
+        })
+    }
 
-    fn sample(&self, _w_i: &Vec3, _photon: &Photon) -> MaterialSampleResult {
-        let mut rng = thread_rng();
+    fn sample(
+        &self,
+        _uv: &Vec2,
+        _w_i: &Vec3,
+        _photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
         let mut w_o = Vec3::new(
             2.0 * rng.sample::<f64, _>(Open01) - 1.0,
             2.0 * rng.sample::<f64, _>(Open01) - 1.0,
@@ -50,11 +82,74 @@ impl Material for LambertianMaterial {
         w_o.coords[2] = (1.0 - w_o.x() * w_o.x() - w_o.y() * w_o.y())
             .sqrt()
             .max(0.0);
-        let cos_theta = w_o.dot(&Vec3::unit_z());
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let direction = w_o.normalize();
         MaterialSampleResult {
-            direction: w_o.normalize(),
-            pdf: (cos_theta * sin_theta) / PI,
+            direction,
+            pdf: sample_pdf(&direction),
+            is_specular: false,
+        }
+    }
+
+    fn pdf(&self, _uv: &Vec2, _w_i: &Vec3, w_o: &Vec3, _photon: &Photon) -> f64 {
+        if w_o.z() < 0.0 {
+            0.0
+        } else {
+            sample_pdf(&w_o.normalize())
+        }
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_strength("diffuse_strength", self.diffuse_strength);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::ColourRgbF;
+    use crate::image::ImageRgbF;
+    use crate::textures::ImageTexture;
+
+    #[test]
+    fn textured_albedo_varies_with_surface_coordinates() {
+        let mut image = ImageRgbF::new(2, 1);
+        image.set_colour(0, 1, ColourRgbF::new(1.0, 1.0, 1.0));
+        let target = LambertianMaterial {
+            colour: ImageTexture::new(image),
+            diffuse_strength: 1.0,
+        };
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let w = Vec3::unit_z();
+        let dark = target.bsdf(&Vec2::new(0.25, 0.5))(&w, &w, &photon);
+        let bright = target.bsdf(&Vec2::new(0.75, 0.5))(&w, &w, &photon);
+        assert!(dark.intensity < 0.01);
+        assert!(bright.intensity > 0.9 / PI);
+    }
+
+    #[test]
+    fn pdf_matches_pdf_of_sampled_direction() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+        let target = LambertianMaterial::new_dummy();
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let w_i = Vec3::new(0.3, -0.2, 0.8).normalize();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let sample = target.sample(&Vec2::new(0.0, 0.0), &w_i, &photon, &mut rng);
+            assert!(!sample.is_specular);
+            assert!(
+                (target.pdf(&Vec2::new(0.0, 0.0), &w_i, &sample.direction, &photon) - sample.pdf)
+                    .abs()
+                    < 0.000001
+            );
         }
+        assert!(target.pdf(&Vec2::new(0.0, 0.0), &w_i, &-Vec3::unit_z(), &photon) == 0.0);
     }
 }
@@ -0,0 +1,122 @@
+use super::Material;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A set of materials, each identified by a name
+///
+/// Objects that should look the same can share a material by looking it up by name, rather
+/// than by passing the same `Arc` around.
+///
+/// Each material also has a numeric ID, for [ID buffers](crate::aovs::render_id_buffers).
+/// IDs are given out from 1 in the order materials are added, and replacing a material
+/// keeps its ID.
+#[derive(Clone, Debug, Default)]
+pub struct MaterialLibrary {
+    indices: HashMap<String, usize>,
+    materials: Vec<(String, Arc<dyn Material>)>,
+}
+
+impl MaterialLibrary {
+    pub fn new() -> MaterialLibrary {
+        MaterialLibrary {
+            indices: HashMap::new(),
+            materials: Vec::new(),
+        }
+    }
+
+    /// Add `material` as `name`, returning the material it replaces, if any
+    pub fn insert(&mut self, name: &str, material: Arc<dyn Material>) -> Option<Arc<dyn Material>> {
+        match self.indices.get(name) {
+            Some(&index) => Some(std::mem::replace(&mut self.materials[index].1, material)),
+            None => {
+                self.indices.insert(name.to_string(), self.materials.len());
+                self.materials.push((name.to_string(), material));
+                None
+            }
+        }
+    }
+
+    /// The material called `name`, if there is one
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Material>> {
+        self.indices
+            .get(name)
+            .map(|&index| Arc::clone(&self.materials[index].1))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.indices.contains_key(name)
+    }
+
+    /// The ID of `material`, or `None` if it isn't in the library
+    ///
+    /// Materials are compared by identity rather than by value, so this finds `material`
+    /// only if it's one of the `Arc`s that was added. It searches every material, so it's
+    /// meant for occasional lookups rather than for use while shading.
+    pub fn id(&self, material: &Arc<dyn Material>) -> Option<u32> {
+        let target = Arc::as_ptr(material) as *const ();
+        self.materials
+            .iter()
+            .position(|(_, candidate)| Arc::as_ptr(candidate) as *const () == target)
+            .map(|index| index as u32 + 1)
+    }
+
+    /// The names of every material in the library, in the order they were added
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.materials.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+
+    #[test]
+    fn materials_are_shared_by_name() {
+        let mut target = MaterialLibrary::new();
+        let material: Arc<dyn Material> = Arc::new(LambertianMaterial::new_dummy());
+        assert!(target.insert("floor", Arc::clone(&material)).is_none());
+        assert!(target.contains("floor"));
+        assert!(Arc::ptr_eq(&target.get("floor").unwrap(), &material));
+        assert!(target.get("ceiling").is_none());
+    }
+
+    #[test]
+    fn insert_replaces_existing_material() {
+        let mut target = MaterialLibrary::new();
+        let first: Arc<dyn Material> = Arc::new(LambertianMaterial::new_dummy());
+        let second: Arc<dyn Material> = Arc::new(LambertianMaterial::new_dummy());
+        target.insert("floor", Arc::clone(&first));
+        let replaced = target.insert("floor", Arc::clone(&second)).unwrap();
+        assert!(Arc::ptr_eq(&replaced, &first));
+        assert!(Arc::ptr_eq(&target.get("floor").unwrap(), &second));
+        assert!(target.len() == 1);
+        assert!(target.names().eq(["floor"].iter().copied()));
+    }
+
+    #[test]
+    fn ids_follow_insertion_order() {
+        let mut target = MaterialLibrary::new();
+        let first: Arc<dyn Material> = Arc::new(LambertianMaterial::new_dummy());
+        let second: Arc<dyn Material> = Arc::new(LambertianMaterial::new_dummy());
+        let replacement: Arc<dyn Material> = Arc::new(LambertianMaterial::new_dummy());
+        target.insert("first", Arc::clone(&first));
+        target.insert("second", Arc::clone(&second));
+        assert!(target.id(&first) == Some(1));
+        assert!(target.id(&second) == Some(2));
+        target.insert("first", Arc::clone(&replacement));
+        assert!(target.id(&replacement) == Some(1));
+        assert!(target.id(&first).is_none());
+        assert!(target.names().eq(["first", "second"].iter().copied()));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::colour::Photon;
+use crate::math::{Vec2, Vec3};
+use crate::media::Medium;
+use crate::raycasting::Footprint;
+use crate::validation::SceneValidator;
+
+use super::{Bsdf, Material, MaterialSampleResult};
+
+use rand::RngCore;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// A material that encloses a medium, such as the fog inside a box or the tint of glass
+///
+/// Light scatters off the surface according to `surface`, which is usually transparent.
+/// Rays that pass through the surface to the side opposite the normal travel through
+/// `interior` until they leave again.
+#[derive(Debug)]
+pub struct MediumBoundary {
+    pub surface: Arc<dyn Material>,
+    pub interior: Arc<dyn Medium>,
+}
+
+impl Material for MediumBoundary {
+    fn bsdf<'a>(&'a self, uv: &Vec2) -> Bsdf<'a> {
+        self.surface.bsdf(uv)
+    }
+
+    fn filtered_bsdf<'a>(&'a self, uv: &Vec2, footprint: &Footprint) -> Bsdf<'a> {
+        self.surface.filtered_bsdf(uv, footprint)
+    }
+
+    fn sample(
+        &self,
+        uv: &Vec2,
+        w_i: &Vec3,
+        photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        self.surface.sample(uv, w_i, photon, rng)
+    }
+
+    fn pdf(&self, uv: &Vec2, w_i: &Vec3, w_o: &Vec3, photon: &Photon) -> f64 {
+        self.surface.pdf(uv, w_i, w_o, photon)
+    }
+
+    fn emission(&self, w_o: &Vec3, photon: &Photon) -> Photon {
+        self.surface.emission(w_o, photon)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.surface.is_dispersive()
+    }
+
+    fn interior_medium(&self) -> Option<&dyn Medium> {
+        Some(self.interior.as_ref())
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_material(&self.surface)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::Spectrum;
+    use crate::materials::EmissiveMaterial;
+    use crate::media::{HenyeyGreenstein, HomogeneousMedium};
+    use crate::raycasting::Ray;
+
+    #[test]
+    fn surface_properties_come_from_wrapped_material() {
+        let target = MediumBoundary {
+            surface: Arc::new(EmissiveMaterial {
+                emission: Spectrum::grey(2.0),
+            }),
+            interior: Arc::new(HomogeneousMedium {
+                absorption: Spectrum::grey(1.0),
+                scattering: Spectrum::grey(0.0),
+                phase_function: HenyeyGreenstein::new(0.0),
+            }),
+        };
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        assert!(target.emission(&Vec3::unit_z(), &photon).intensity == 2.0);
+        let interior = target.interior_medium().unwrap();
+        assert!(
+            (interior.transmittance(&Ray::new(Vec3::zeros(), Vec3::unit_z()), 1.0, 550.0)
+                - (-1.0f64).exp())
+            .abs()
+                < 0.000000001
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::colour::{Photon, Spectrum};
+use crate::math::{Vec2, Vec3};
+use crate::raycasting::Footprint;
+use crate::textures::Texture;
+use crate::validation::SceneValidator;
+
+use super::{Bsdf, Material, MaterialSampleResult};
+
+use rand::{Rng, RngCore};
+
+/// A blend of two materials, such as patches of rust on a metal or paint worn away from
+/// wood
+///
+/// The surface behaves as `first` where `factor` is zero and as `second` where it's one,
+/// with a weighted average between. `factor` is clamped to between zero and one, and it
+/// can be a constant [Spectrum](Spectrum) or any [Texture](Texture), so a mask image or a
+/// procedural pattern decides where each material appears.
+///
+/// Only the surface is blended: the mix has no interior medium and is one-sided, whatever
+/// its children are.
+#[derive(Debug)]
+pub struct MixMaterial<A: Material, B: Material, F: Texture = Spectrum> {
+    pub first: A,
+    pub second: B,
+    pub factor: F,
+}
+
+impl<A: Material, B: Material, F: Texture> MixMaterial<A, B, F> {
+    pub fn new(first: A, second: B, factor: F) -> MixMaterial<A, B, F> {
+        MixMaterial {
+            first,
+            second,
+            factor,
+        }
+    }
+
+    fn factor(&self, uv: &Vec2, wavelength: f64) -> f64 {
+        self.factor.value(uv, wavelength).clamp(0.0, 1.0)
+    }
+
+    /// The blend of the BSDFs `first` and `second`, with the weight of `second` given by
+    /// `factor`
+    fn mix<'a, G: Fn(f64) -> f64 + 'a>(first: Bsdf<'a>, second: Bsdf<'a>, factor: G) -> Bsdf<'a> {
+        Box::new(move |w_o: &Vec3, w_i: &Vec3, photon_in: &Photon| {
+            let factor = factor(photon_in.wavelength);
+            photon_in.set_intensity(
+                first(w_o, w_i, photon_in).intensity * (1.0 - factor)
+                    + second(w_o, w_i, photon_in).intensity * factor,
+            )
+        })
+    }
+}
+
+impl<A: Material, B: Material, F: Texture> Material for MixMaterial<A, B, F> {
+    fn bsdf<'a>(&'a self, uv: &Vec2) -> Bsdf<'a> {
+        let uv = *uv;
+        MixMaterial::<A, B, F>::mix(self.first.bsdf(&uv), self.second.bsdf(&uv), move |w| {
+            self.factor(&uv, w)
+        })
+    }
+
+    fn filtered_bsdf<'a>(&'a self, uv: &Vec2, footprint: &Footprint) -> Bsdf<'a> {
+        let (uv, footprint) = (*uv, *footprint);
+        MixMaterial::<A, B, F>::mix(
+            self.first.filtered_bsdf(&uv, &footprint),
+            self.second.filtered_bsdf(&uv, &footprint),
+            move |wavelength| {
+                self.factor
+                    .filtered_value(&uv, &footprint, wavelength)
+                    .clamp(0.0, 1.0)
+            },
+        )
+    }
+
+    fn sample(
+        &self,
+        uv: &Vec2,
+        w_i: &Vec3,
+        photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        // Each material is chosen in proportion to its weight, and the pdf of the choice
+        // accounts for the chance that the other material could have chosen the same
+        // direction. Specular directions can only come from the material that chose them.
+        let factor = self.factor(uv, photon.wavelength);
+        let choose_second = rng.gen::<f64>() < factor;
+        let sample = if choose_second {
+            self.second.sample(uv, w_i, photon, rng)
+        } else {
+            self.first.sample(uv, w_i, photon, rng)
+        };
+        let pdf = if sample.is_specular {
+            sample.pdf * if choose_second { factor } else { 1.0 - factor }
+        } else {
+            self.pdf(uv, w_i, &sample.direction, photon)
+        };
+        MaterialSampleResult { pdf, ..sample }
+    }
+
+    fn pdf(&self, uv: &Vec2, w_i: &Vec3, w_o: &Vec3, photon: &Photon) -> f64 {
+        let factor = self.factor(uv, photon.wavelength);
+        self.first.pdf(uv, w_i, w_o, photon) * (1.0 - factor)
+            + self.second.pdf(uv, w_i, w_o, photon) * factor
+    }
+
+    fn emission(&self, w_o: &Vec3, photon: &Photon) -> Photon {
+        // Emission isn't given surface coordinates, so only a constant factor is used
+        let factor = self.factor(&Vec2::new(0.0, 0.0), photon.wavelength);
+        photon.set_intensity(
+            self.first.emission(w_o, photon).intensity * (1.0 - factor)
+                + self.second.emission(w_o, photon).intensity * factor,
+        )
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.first.is_dispersive() || self.second.is_dispersive()
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        self.first.validate(validator);
+        self.second.validate(validator);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::{Conductor, LambertianMaterial};
+    use crate::textures::Gradient;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use std::f64::consts::PI;
+
+    fn lambertian(albedo: f64) -> LambertianMaterial {
+        LambertianMaterial {
+            colour: Spectrum::grey(albedo),
+            diffuse_strength: 1.0,
+        }
+    }
+
+    fn photon() -> Photon {
+        Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        }
+    }
+
+    #[test]
+    fn bsdf_is_weighted_by_mask() {
+        let target = MixMaterial::new(
+            lambertian(0.2),
+            lambertian(0.6),
+            Gradient::new(Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0)),
+        );
+        let w = Vec3::unit_z();
+        let at = |u: f64| target.bsdf(&Vec2::new(u, 0.0))(&w, &w, &photon()).intensity;
+        assert!((at(0.0) - 0.2 / PI).abs() < 0.000000001);
+        assert!((at(0.5) - 0.4 / PI).abs() < 0.000000001);
+        assert!((at(1.0) - 0.6 / PI).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn lobes_are_chosen_by_weight() {
+        let target = MixMaterial::new(lambertian(0.5), Conductor::silver(), Spectrum::grey(0.25));
+        let uv = Vec2::new(0.0, 0.0);
+        let w_i = Vec3::new(0.0, 0.6, 0.8);
+        let mut rng = StdRng::seed_from_u64(0);
+        let samples: Vec<_> = (0..10000)
+            .map(|_| target.sample(&uv, &w_i, &photon(), &mut rng))
+            .collect();
+        let specular = samples.iter().filter(|sample| sample.is_specular).count();
+        assert!((specular as f64 / 10000.0 - 0.25).abs() < 0.02);
+        for sample in samples.iter() {
+            if sample.is_specular {
+                assert!((sample.pdf - 0.25).abs() < 0.000000001);
+            } else {
+                let expected = target.first.pdf(&uv, &w_i, &sample.direction, &photon()) * 0.75;
+                assert!((sample.pdf - expected).abs() < 0.000000001);
+            }
+        }
+    }
+}
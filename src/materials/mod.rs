@@ -1,34 +1,180 @@
-use crate::math::Vec3;
+use crate::math::{Vec2, Vec3};
 
-use super::colour::Photon;
+use super::colour::{MuellerMatrix, Photon};
+use super::media::Medium;
 use super::random_distributions::{CosineWeightedHemisphere, RandomDistribution};
+use super::raycasting::Footprint;
+use super::validation::SceneValidator;
+
+use rand::RngCore;
 
 use std::fmt::Debug;
 
+pub mod clear_coat;
+pub use clear_coat::ClearCoat;
+
+pub mod conductor;
+pub use conductor::Conductor;
+
+pub mod emissive_material;
+pub use emissive_material::EmissiveMaterial;
+
+pub mod hair_material;
+pub use hair_material::HairMaterial;
+
 pub mod lambertian_material;
 pub use lambertian_material::LambertianMaterial;
 
+pub mod material_library;
+pub use material_library::MaterialLibrary;
+
+pub mod medium_boundary;
+pub use medium_boundary::MediumBoundary;
+
+pub mod mix_material;
+pub use mix_material::MixMaterial;
+
 pub mod phong_material;
 pub use phong_material::PhongMaterial;
 
 pub mod reflective_material;
 pub use reflective_material::ReflectiveMaterial;
 
+pub mod rough_conductor;
+pub use rough_conductor::RoughConductor;
+
 pub mod smooth_transparent_dialectric;
 pub use smooth_transparent_dialectric::SmoothTransparentDialectric;
 
+pub mod subsurface_material;
+pub use subsurface_material::SubsurfaceMaterial;
+
+pub mod two_sided;
+pub use two_sided::TwoSided;
+
+pub mod validate;
+
+/// A BSDF, as returned by [Material::bsdf()](Material::bsdf)
+pub type Bsdf<'a> = Box<dyn Fn(&Vec3, &Vec3, &Photon) -> Photon + 'a>;
+
 pub struct MaterialSampleResult {
     pub direction: Vec3,
     pub pdf: f64,
+
+    /// Whether the direction was chosen from a specular lobe
+    ///
+    /// Specular lobes scatter light in only a few discrete directions, so they can't be
+    /// found by sampling lights and must be followed by sampling the material.
+    ///
+    /// They're delta distributions: `pdf` is then the chance of the lobe being chosen,
+    /// rather than a density, and the [BSDF](Material::bsdf) in that exact direction
+    /// gives the fraction of the light the lobe carries, with the cosine already
+    /// accounted for. The light found along the direction is divided by `pdf` and
+    /// weighted by the BSDF, and nothing else.
+    pub is_specular: bool,
 }
 
 pub trait Material: Debug + Sync + Send {
-    fn bsdf<'a>(&'a self) -> Box<dyn Fn(&Vec3, &Vec3, &Photon) -> Photon + 'a>;
+    /// The BSDF at surface coordinates `uv`
+    ///
+    /// `uv` is only used by materials with spatially varying properties, such as textures.
+    /// The BSDF is the radiance scattered towards `w_i` per unit of irradiance arriving
+    /// from `w_o`, so a white Lambertian surface has a BSDF of 1/π; integrators apply the
+    /// cosine to the normal.
+    fn bsdf<'a>(&'a self, uv: &Vec2) -> Bsdf<'a>;
 
-    fn sample(&self, _w_i: &Vec3, _photon: &Photon) -> MaterialSampleResult {
-        let distribution = CosineWeightedHemisphere::new();
-        let direction = distribution.value();
-        let pdf = distribution.pdf(direction);
-        MaterialSampleResult { direction, pdf }
+    /// The BSDF at surface coordinates `uv`, with textures averaged over `footprint`
+    ///
+    /// Only materials with textures need to provide this; the default is the same as
+    /// [bsdf()](Material::bsdf).
+    fn filtered_bsdf<'a>(&'a self, uv: &Vec2, _footprint: &Footprint) -> Bsdf<'a> {
+        self.bsdf(uv)
     }
+
+    /// Choose a direction to sample the BSDF at surface coordinates `uv` in, using `rng` as
+    /// the source of randomness
+    fn sample(
+        &self,
+        uv: &Vec2,
+        w_i: &Vec3,
+        photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        let direction = CosineWeightedHemisphere::new().value(rng);
+        MaterialSampleResult {
+            direction,
+            pdf: self.pdf(uv, w_i, &direction, photon),
+            is_specular: false,
+        }
+    }
+
+    /// The probability density of [sample()](Material::sample) choosing `w_o` for `w_i`
+    ///
+    /// This is the same value that `sample()` returns as the pdf when it chooses `w_o`, so
+    /// it's zero for directions that don't lie on a specular lobe. It's a density over the
+    /// polar angles of `w_o`, θ from the normal and φ around it, rather than over solid
+    /// angle, so it includes the sin θ from the change of variables.
+    fn pdf(&self, _uv: &Vec2, _w_i: &Vec3, w_o: &Vec3, _photon: &Photon) -> f64 {
+        if w_o.z() < 0.0 {
+            0.0
+        } else {
+            let sin_theta = (1.0 - w_o.z() * w_o.z()).max(0.0).sqrt();
+            CosineWeightedHemisphere::new().pdf(*w_o) * sin_theta
+        }
+    }
+
+    /// Light emitted by the surface in the direction `w_o`, at the photon's wavelength
+    ///
+    /// Most materials don't emit any light, which is the default.
+    fn emission(&self, _w_o: &Vec3, photon: &Photon) -> Photon {
+        photon.set_intensity(0.0)
+    }
+
+    /// The medium enclosed by surfaces with this material, if any
+    ///
+    /// Rays that pass to the side of the surface opposite the normal enter this medium.
+    fn interior_medium(&self) -> Option<&dyn Medium> {
+        None
+    }
+
+    /// Whether the directions chosen by [sample()](Material::sample) depend on wavelength
+    ///
+    /// Light of different wavelengths can't follow the same path through a dispersive
+    /// material, so integrators trace only a single wavelength from it onwards.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+
+    /// How the surface changes the polarization of light arriving from `w_o` and leaving
+    /// towards `w_i`, or `None` if it doesn't polarize light
+    ///
+    /// The Stokes vectors on both sides are measured against the
+    /// [s direction](crate::colour::polarization::s_direction) of `w_i`. The matrix's first
+    /// element is the [BSDF](Material::bsdf) in that direction, so it scales the intensity
+    /// the same way. Materials that return a matrix for one pair of directions must return
+    /// one for every pair, which can be a [depolarizer](MuellerMatrix::depolarizer) that
+    /// blocks all light away from their specular lobes. Everything else is treated as
+    /// unpolarizing, which is the default.
+    fn mueller_matrix(
+        &self,
+        _uv: &Vec2,
+        _w_i: &Vec3,
+        _w_o: &Vec3,
+        _photon: &Photon,
+    ) -> Option<MuellerMatrix> {
+        None
+    }
+
+    /// Whether the back of the surface should look the same as the front
+    ///
+    /// Integrators flip the BSDF space of two-sided materials to face the incoming ray,
+    /// so the material only ever sees directions above the surface.
+    fn is_two_sided(&self) -> bool {
+        false
+    }
+
+    /// Report anything about the material that will make it render incorrectly
+    ///
+    /// Nothing is checked by default.
+    fn validate(&self, _validator: &mut SceneValidator) {}
 }
@@ -1,37 +1,220 @@
 use crate::colour::{Photon, Spectrum};
-use crate::math::Vec3;
+use crate::math::{OrthonormalBasis, Vec2, Vec3};
+use crate::raycasting::Footprint;
+use crate::textures::Texture;
+use crate::validation::SceneValidator;
 
-use std::fmt::Debug;
+use super::{Bsdf, Material, MaterialSampleResult};
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
 
-use super::Material;
+use std::f64::consts::PI;
+use std::fmt::Debug;
 
 #[derive(Debug)]
-pub struct PhongMaterial {
-    pub colour: Spectrum,
+pub struct PhongMaterial<T: Texture = Spectrum> {
+    pub colour: T,
     pub diffuse_strength: f64,
     pub specular_strength: f64,
     pub smoothness: f64,
 }
 
-impl Material for PhongMaterial {
-    fn bsdf<'a>(&'a self) -> Box<dyn Fn(&Vec3, &Vec3, &Photon) -> Photon + 'a> {
+impl<T: Texture> PhongMaterial<T> {
+    /// The BSDF, with the colour at each wavelength given by `colour`
+    ///
+    /// This is the normalized modified Phong model: the specular lobe reflects all of
+    /// `specular_strength` for light arriving along the normal, and less towards grazing
+    /// angles, where some of the lobe falls below the surface.
+    fn bsdf_with_colour<'a, F: Fn(f64) -> f64 + 'a>(&'a self, colour: F) -> Bsdf<'a> {
         Box::new(move |w_o: &Vec3, w_i: &Vec3, photon_in: &Photon| {
             if w_i.z() < 0.0 || w_o.z() < 0.0 {
-                Photon {
-                    wavelength: photon_in.wavelength,
-                    intensity: 0.0,
-                }
+                photon_in.set_intensity(0.0)
             } else {
-                let reflection_vector = Vec3::new(-w_i.x(), -w_i.y(), w_i.z());
-                let intensity = self.colour.scale_photon(photon_in).intensity
-                    * self.diffuse_strength
-                    + w_o.dot(&reflection_vector).abs().powf(self.smoothness)
-                        * (self.specular_strength / w_i.dot(&Vec3::unit_z()));
-                Photon {
-                    wavelength: photon_in.wavelength,
-                    intensity,
-                }
+                let reflection_vector = w_i.reflect(&Vec3::unit_z());
+                let diffuse = colour(photon_in.wavelength) * self.diffuse_strength / PI;
+                let specular = w_o.dot(&reflection_vector).max(0.0).powf(self.smoothness)
+                    * self.specular_strength
+                    * (self.smoothness + 2.0)
+                    / (2.0 * PI);
+                photon_in.scale_intensity(diffuse + specular)
             }
         })
     }
 }
+
+impl<T: Texture> PhongMaterial<T> {
+    /// The chance of [sample()](Material::sample) choosing a direction from the specular
+    /// lobe rather than the diffuse one
+    fn specular_probability(&self) -> f64 {
+        let total = self.diffuse_strength + self.specular_strength;
+        if total > 0.0 {
+            self.specular_strength / total
+        } else {
+            0.0
+        }
+    }
+
+    /// The density, over the polar angles of `w_o`, of choosing it from the specular lobe
+    ///
+    /// Around the mirror direction the lobe is proportional to the cosine raised to the
+    /// power of the smoothness, over solid angle, so the sin θ from the change of variables
+    /// is measured from the normal. Some of the lobe can fall below the surface.
+    fn specular_pdf(&self, w_i: &Vec3, w_o: &Vec3) -> f64 {
+        let cos_alpha = w_o.dot(&w_i.reflect(&Vec3::unit_z())).max(0.0);
+        let sin_theta = (1.0 - w_o.z() * w_o.z()).max(0.0).sqrt();
+        (self.smoothness + 1.0) / (2.0 * PI) * cos_alpha.powf(self.smoothness) * sin_theta
+    }
+}
+
+/// The density, over the polar angles of `w_o`, of choosing it from the diffuse lobe
+fn diffuse_pdf(w_o: &Vec3) -> f64 {
+    if w_o.z() < 0.0 {
+        0.0
+    } else {
+        let sin_theta = (1.0 - w_o.z() * w_o.z()).max(0.0).sqrt();
+        w_o.z() * sin_theta / PI
+    }
+}
+
+impl<T: Texture> Material for PhongMaterial<T> {
+    fn bsdf<'a>(&'a self, uv: &Vec2) -> Bsdf<'a> {
+        let uv = *uv;
+        self.bsdf_with_colour(move |wavelength| self.colour.value(&uv, wavelength))
+    }
+
+    fn filtered_bsdf<'a>(&'a self, uv: &Vec2, footprint: &Footprint) -> Bsdf<'a> {
+        let (uv, footprint) = (*uv, *footprint);
+        self.bsdf_with_colour(move |wavelength| {
+            self.colour.filtered_value(&uv, &footprint, wavelength)
+        })
+    }
+
+    /// Chooses between the diffuse and specular lobes in proportion to their strengths,
+    /// then samples the diffuse lobe by the cosine and the specular one by the power of the
+    /// cosine around the mirror direction
+    fn sample(
+        &self,
+        uv: &Vec2,
+        w_i: &Vec3,
+        photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        let w_i = w_i.normalize();
+        let (lobe, cos_alpha) = if rng.sample::<f64, _>(Open01) < self.specular_probability() {
+            let lobe = OrthonormalBasis::from_normal(&w_i.reflect(&Vec3::unit_z()));
+            let cos_alpha = rng
+                .sample::<f64, _>(Open01)
+                .powf(1.0 / (self.smoothness + 1.0));
+            (lobe, cos_alpha)
+        } else {
+            let lobe = OrthonormalBasis::from_normal(&Vec3::unit_z());
+            (lobe, rng.sample::<f64, _>(Open01).sqrt())
+        };
+        let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.sample::<f64, _>(Open01);
+        let direction = lobe
+            .to_world(&Vec3::new(
+                sin_alpha * phi.cos(),
+                sin_alpha * phi.sin(),
+                cos_alpha,
+            ))
+            .normalize();
+        MaterialSampleResult {
+            direction,
+            pdf: self.pdf(uv, &w_i, &direction, photon),
+            is_specular: false,
+        }
+    }
+
+    fn pdf(&self, _uv: &Vec2, w_i: &Vec3, w_o: &Vec3, _photon: &Photon) -> f64 {
+        let (w_i, w_o) = (w_i.normalize(), w_o.normalize());
+        let specular_probability = self.specular_probability();
+        (1.0 - specular_probability) * diffuse_pdf(&w_o)
+            + specular_probability * self.specular_pdf(&w_i, &w_o)
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_strength("diffuse_strength", self.diffuse_strength);
+        validator.check_strength("specular_strength", self.specular_strength);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::validate::{chi_square_test, pdf_integral};
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn test_phong(diffuse_strength: f64, smoothness: f64) -> PhongMaterial {
+        PhongMaterial {
+            colour: Spectrum::grey(1.0),
+            diffuse_strength,
+            specular_strength: 1.0 - diffuse_strength,
+            smoothness,
+        }
+    }
+
+    #[test]
+    fn samples_match_pdf() {
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let uv = Vec2::new(0.5, 0.5);
+        let mut rng = StdRng::seed_from_u64(0);
+        for &(diffuse_strength, smoothness) in &[(0.5, 20.0), (0.0, 5.0), (0.0, 50.0)] {
+            let target = test_phong(diffuse_strength, smoothness);
+            for &w_i in &[Vec3::new(0.3, 0.2, 0.9), Vec3::new(0.9, 0.0, 0.2)] {
+                let w_i = w_i.normalize();
+                assert!((pdf_integral(&target, &uv, &w_i, &photon, 200) - 1.0).abs() < 0.01);
+                let test = chi_square_test(&target, &uv, &w_i, &photon, 10000, &mut rng);
+                assert!(test.p_value() > 0.001);
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_specular_samples_are_near_mirror_direction() {
+        let target = test_phong(0.0, 1000.0);
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let w_i = Vec3::new(0.6, 0.0, 0.8);
+        let mirror = w_i.reflect(&Vec3::unit_z());
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let sample = target.sample(&Vec2::new(0.5, 0.5), &w_i, &photon, &mut rng);
+            assert!(sample.direction.dot(&mirror) > 0.99);
+            assert!(!sample.is_specular);
+        }
+    }
+
+    #[test]
+    fn specular_lobe_reflects_all_light_at_normal_incidence() {
+        let target = test_phong(0.0, 20.0);
+        let bsdf = target.bsdf(&Vec2::new(0.5, 0.5));
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let w_i = Vec3::unit_z();
+        let mut rng = StdRng::seed_from_u64(0);
+        let samples = 100000;
+        let total = (0..samples)
+            .map(|_| {
+                let sample = target.sample(&Vec2::new(0.5, 0.5), &w_i, &photon, &mut rng);
+                let cos_theta = sample.direction.z().abs();
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                bsdf(&sample.direction, &w_i, &photon).intensity * cos_theta * sin_theta
+                    / sample.pdf
+            })
+            .sum::<f64>()
+            / samples as f64;
+        assert!((total - 1.0).abs() < 0.01);
+    }
+}
@@ -1,48 +1,124 @@
 use crate::colour::{Photon, Spectrum};
-use crate::math::Vec3;
+use crate::math::{Vec2, Vec3};
+use crate::random_distributions::{CosineWeightedHemisphere, RandomDistribution};
+use crate::raycasting::Footprint;
+use crate::textures::Texture;
+use crate::validation::SceneValidator;
 
+use rand::{Rng, RngCore};
+
+use std::f64::consts::PI;
 use std::fmt::Debug;
 
-use super::{Material, MaterialSampleResult};
+use super::{Bsdf, Material, MaterialSampleResult};
 
 #[derive(Debug)]
-pub struct ReflectiveMaterial {
-    pub colour: Spectrum,
+pub struct ReflectiveMaterial<T: Texture = Spectrum> {
+    pub colour: T,
     pub diffuse_strength: f64,
     pub reflection_strength: f64,
 }
 
-impl Material for ReflectiveMaterial {
-    fn bsdf<'a>(&'a self) -> Box<dyn Fn(&Vec3, &Vec3, &Photon) -> Photon + 'a> {
+impl<T: Texture> ReflectiveMaterial<T> {
+    /// The BSDF, with the colour at each wavelength given by `colour`
+    ///
+    /// The mirror reflects `reflection_strength` of the light into exactly the mirror
+    /// direction, so at that direction the BSDF gives the weight of the mirror lobe; the
+    /// rest of the light is scattered diffusely.
+    fn bsdf_with_colour<'a, F: Fn(f64) -> f64 + 'a>(&'a self, colour: F) -> Bsdf<'a> {
         Box::new(move |w_o: &Vec3, w_i: &Vec3, photon_in: &Photon| {
             if w_i.z() <= 0.0 || w_o.z() <= 0.0 {
-                Photon {
-                    wavelength: photon_in.wavelength,
-                    intensity: 0.0,
-                }
+                photon_in.set_intensity(0.0)
+            } else if is_mirror_reflection(w_i, w_o) {
+                photon_in.scale_intensity(self.reflection_strength)
             } else {
-                let reflection_vector = Vec3::new(-w_o.x(), -w_o.y(), w_o.z());
-                let mut photon_out = self.colour.scale_photon(photon_in);
-                photon_out.intensity *= self.diffuse_strength;
-                let sigma = 0.05;
-                let two = 2.0;
-                // These are normalized vectors, but sometimes rounding errors cause the
-                // dot product to be slightly above 1 or below 0. The call to clamp
-                // ensures the values stay within the domain of acos,
-                let theta = w_i.dot(&reflection_vector).clamp(0.0, 1.0).abs().acos();
-                let reflection_factor =
-                    self.reflection_strength * (-(theta.powf(two)) / (two * sigma * sigma)).exp();
-                photon_out.intensity =
-                    photon_out.intensity * (1.0 - reflection_factor) + reflection_factor;
-                photon_out
+                photon_in.scale_intensity(
+                    colour(photon_in.wavelength)
+                        * self.diffuse_strength
+                        * (1.0 - self.reflection_strength)
+                        / PI,
+                )
             }
         })
     }
 
-    fn sample(&self, w_o: &Vec3, _photon: &Photon) -> MaterialSampleResult {
-        MaterialSampleResult {
-            direction: Vec3::new(-w_o.x(), -w_o.y(), w_o.z()),
-            pdf: 1.0,
+    /// The chance of [sample()](Material::sample) choosing the mirror direction rather
+    /// than a diffuse one
+    fn mirror_probability(&self) -> f64 {
+        let diffuse = self.diffuse_strength * (1.0 - self.reflection_strength);
+        if self.reflection_strength + diffuse > 0.0 {
+            self.reflection_strength / (self.reflection_strength + diffuse)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// The density, over the polar angles of `w_o`, of cosine-weighted diffuse directions
+fn diffuse_pdf(w_o: &Vec3) -> f64 {
+    if w_o.z() < 0.0 {
+        0.0
+    } else {
+        let sin_theta = (1.0 - w_o.z() * w_o.z()).max(0.0).sqrt();
+        w_o.z() * sin_theta / PI
+    }
+}
+
+fn is_mirror_reflection(w_i: &Vec3, w_o: &Vec3) -> bool {
+    (*w_o - w_i.reflect(&Vec3::unit_z())).norm_squared() < 0.0000000001
+}
+
+impl<T: Texture> Material for ReflectiveMaterial<T> {
+    fn bsdf<'a>(&'a self, uv: &Vec2) -> Bsdf<'a> {
+        let uv = *uv;
+        self.bsdf_with_colour(move |wavelength| self.colour.value(&uv, wavelength))
+    }
+
+    fn filtered_bsdf<'a>(&'a self, uv: &Vec2, footprint: &Footprint) -> Bsdf<'a> {
+        let (uv, footprint) = (*uv, *footprint);
+        self.bsdf_with_colour(move |wavelength| {
+            self.colour.filtered_value(&uv, &footprint, wavelength)
+        })
+    }
+
+    /// Chooses between the mirror direction and a cosine-weighted diffuse direction, in
+    /// proportion to the light each reflects
+    fn sample(
+        &self,
+        _uv: &Vec2,
+        w_i: &Vec3,
+        _photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        let mirror_probability = self.mirror_probability();
+        if rng.gen::<f64>() < mirror_probability {
+            MaterialSampleResult {
+                direction: w_i.reflect(&Vec3::unit_z()),
+                pdf: mirror_probability,
+                is_specular: true,
+            }
+        } else {
+            let distribution = CosineWeightedHemisphere::new();
+            let direction = distribution.value(rng);
+            MaterialSampleResult {
+                direction,
+                pdf: (1.0 - mirror_probability) * diffuse_pdf(&direction),
+                is_specular: false,
+            }
         }
     }
+
+    fn pdf(&self, _uv: &Vec2, w_i: &Vec3, w_o: &Vec3, _photon: &Photon) -> f64 {
+        let mirror_probability = self.mirror_probability();
+        if is_mirror_reflection(w_i, w_o) {
+            mirror_probability
+        } else {
+            (1.0 - mirror_probability) * diffuse_pdf(w_o)
+        }
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_strength("diffuse_strength", self.diffuse_strength);
+        validator.check_strength("reflection_strength", self.reflection_strength);
+    }
 }
@@ -1,5 +1,6 @@
 use super::{Bsdf, Material};
 use crate::colour::ColourRgbF;
+use crate::error::VanrijnError;
 use crate::math::Vec3;
 use crate::realtype::NormalizedToU32;
 
@@ -12,7 +13,7 @@ use std::f64::consts::{FRAC_PI_2, PI};
 
 #[derive(Debug)]
 pub struct RgbSampledBsdfMaterial {
-    lut: Arc<Vec<Vec<Vec<Vec<Vec3>>>>>,
+    lut: Arc<Lut>,
 }
 
 fn expand_and_index<T: Clone>(v: &mut Vec<T>, i: usize, default: T) -> &mut T {
@@ -22,36 +23,47 @@ fn expand_and_index<T: Clone>(v: &mut Vec<T>, i: usize, default: T) -> &mut T {
     &mut v[i]
 }
 
-impl RgbSampledBsdfMaterial {
-    pub fn from_csv_file(filename: &str) -> Result<RgbSampledBsdfMaterial, Box<dyn Error>> {
-        let csv_file = File::open(filename)?;
-        let mut reader = csv::Reader::from_reader(BufReader::new(&csv_file));
-        let mut lut = Vec::new();
-        for row_result in reader.records() {
-            let row = row_result?;
-            let theta_in_index = row[0].trim().parse::<usize>()?;
-            let phi_in_index = row[1].trim().parse::<usize>()?;
-            let theta_out_index = row[2].trim().parse::<usize>()?;
-            let phi_out_index = row[3].trim().parse::<usize>()?;
-            let red = row[4].trim().parse::<f64>()?;
-            let green = row[5].trim().parse::<f64>()?;
-            let blue = row[6].trim().parse::<f64>()?;
-            *expand_and_index(
+/// Red, green and blue reflectance, indexed by incoming polar and azimuthal angle, then
+/// outgoing polar and azimuthal angle
+type Lut = Vec<Vec<Vec<Vec<Vec3>>>>;
+
+fn read_lut(filename: &str) -> Result<Lut, Box<dyn Error>> {
+    let csv_file = File::open(filename)?;
+    let mut reader = csv::Reader::from_reader(BufReader::new(&csv_file));
+    let mut lut = Vec::new();
+    for row_result in reader.records() {
+        let row = row_result?;
+        let theta_in_index = row[0].trim().parse::<usize>()?;
+        let phi_in_index = row[1].trim().parse::<usize>()?;
+        let theta_out_index = row[2].trim().parse::<usize>()?;
+        let phi_out_index = row[3].trim().parse::<usize>()?;
+        let red = row[4].trim().parse::<f64>()?;
+        let green = row[5].trim().parse::<f64>()?;
+        let blue = row[6].trim().parse::<f64>()?;
+        *expand_and_index(
+            expand_and_index(
                 expand_and_index(
-                    expand_and_index(
-                        expand_and_index(&mut lut, theta_in_index, Vec::new()),
-                        phi_in_index,
-                        Vec::new(),
-                    ),
-                    theta_out_index,
+                    expand_and_index(&mut lut, theta_in_index, Vec::new()),
+                    phi_in_index,
                     Vec::new(),
                 ),
-                phi_out_index,
-                Vec3::zeros(),
-            ) = Vec3::new(red, green, blue);
-        }
-        let lut = Arc::new(lut);
-        Ok(RgbSampledBsdfMaterial { lut })
+                theta_out_index,
+                Vec::new(),
+            ),
+            phi_out_index,
+            Vec3::zeros(),
+        ) = Vec3::new(red, green, blue);
+    }
+    Ok(lut)
+}
+
+impl RgbSampledBsdfMaterial {
+    pub fn from_csv_file(filename: &str) -> Result<RgbSampledBsdfMaterial, VanrijnError> {
+        let lut = read_lut(filename).map_err(|error| VanrijnError::MaterialData {
+            filename: filename.into(),
+            message: error.to_string(),
+        })?;
+        Ok(RgbSampledBsdfMaterial { lut: Arc::new(lut) })
     }
 }
 
@@ -0,0 +1,212 @@
+use crate::colour::Photon;
+use crate::math::{Vec2, Vec3};
+use crate::validation::SceneValidator;
+
+use super::{Bsdf, Conductor, Material, MaterialSampleResult};
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+use std::f64::consts::PI;
+
+/// Roughness below this is treated as this, since a perfectly smooth microfacet
+/// distribution is a delta that can't be evaluated
+const MIN_ALPHA: f64 = 0.001;
+
+/// A rough metal, such as brushed aluminium or a weathered copper roof
+///
+/// The surface is made of tiny mirror facets, each reflecting like a smooth
+/// [Conductor](Conductor), with normals following the GGX distribution. The roughness
+/// can be different along the surface tangent, `alpha_x`, and across it, `alpha_y`, so
+/// the highlight is stretched in the rougher direction as on brushed metal; which way the
+/// tangent runs depends on the [Primitive](crate::raycasting::Primitive). Facets
+/// shadowing and masking each other are accounted for with the height-correlated Smith
+/// term, but light reflected between facets more than once is lost, so very rough metals
+/// are slightly darker than they should be.
+#[derive(Debug)]
+pub struct RoughConductor {
+    pub conductor: Conductor,
+
+    /// The GGX roughness along the tangent
+    pub alpha_x: f64,
+
+    /// The GGX roughness along the cotangent
+    pub alpha_y: f64,
+}
+
+impl RoughConductor {
+    pub fn new(conductor: Conductor, alpha_x: f64, alpha_y: f64) -> RoughConductor {
+        RoughConductor {
+            conductor,
+            alpha_x,
+            alpha_y,
+        }
+    }
+
+    fn alphas(&self) -> (f64, f64) {
+        (self.alpha_x.max(MIN_ALPHA), self.alpha_y.max(MIN_ALPHA))
+    }
+
+    /// The density of facets with normal `h`, over solid angle and projected onto the
+    /// surface
+    fn distribution(&self, h: &Vec3) -> f64 {
+        let (alpha_x, alpha_y) = self.alphas();
+        let x = h.x() / alpha_x;
+        let y = h.y() / alpha_y;
+        let denominator = x * x + y * y + h.z() * h.z();
+        1.0 / (PI * alpha_x * alpha_y * denominator * denominator)
+    }
+
+    /// Smith's Λ for the direction `w`, which gives the fraction of facets hidden from it
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let (alpha_x, alpha_y) = self.alphas();
+        let x = alpha_x * w.x();
+        let y = alpha_y * w.y();
+        let tan2 = (x * x + y * y) / (w.z() * w.z());
+        0.5 * ((1.0 + tan2).sqrt() - 1.0)
+    }
+
+    /// The fraction of the facets facing `w` that aren't hidden from it by others
+    fn masking(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// A facet normal chosen in proportion to how much of it is visible from `w_i`
+    ///
+    /// This is Heitz's method of sampling the distribution of visible normals, which
+    /// stretches the surface so that it's smooth, samples the projected hemisphere there,
+    /// and stretches it back.
+    fn sample_visible_normal(&self, w_i: &Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        let (alpha_x, alpha_y) = self.alphas();
+        let v = Vec3::new(alpha_x * w_i.x(), alpha_y * w_i.y(), w_i.z()).normalize();
+        let length_squared = v.x() * v.x() + v.y() * v.y();
+        let t1 = if length_squared > 0.0 {
+            Vec3::new(-v.y(), v.x(), 0.0) / length_squared.sqrt()
+        } else {
+            Vec3::unit_x()
+        };
+        let t2 = v.cross(&t1);
+        let r = rng.sample::<f64, _>(Open01).sqrt();
+        let phi = 2.0 * PI * rng.sample::<f64, _>(Open01);
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let n = t1 * p1 + t2 * p2 + v * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        Vec3::new(alpha_x * n.x(), alpha_y * n.y(), n.z().max(0.0)).normalize()
+    }
+}
+
+impl Material for RoughConductor {
+    fn bsdf<'a>(&'a self, _uv: &Vec2) -> Bsdf<'a> {
+        Box::new(move |w_o: &Vec3, w_i: &Vec3, photon_in: &Photon| {
+            if w_i.z() <= 0.0 || w_o.z() <= 0.0 {
+                return photon_in.set_intensity(0.0);
+            }
+            let h = (*w_o + *w_i).normalize();
+            let shadowing = 1.0 / (1.0 + self.lambda(w_o) + self.lambda(w_i));
+            let fresnel = self
+                .conductor
+                .reflectance(w_i.dot(&h), photon_in.wavelength);
+            photon_in.scale_intensity(
+                fresnel * self.distribution(&h) * shadowing / (4.0 * w_o.z() * w_i.z()),
+            )
+        })
+    }
+
+    /// Reflects `w_i` about a facet normal chosen from those visible from it
+    ///
+    /// Some of the reflections point below the surface, where the BSDF is zero.
+    fn sample(
+        &self,
+        uv: &Vec2,
+        w_i: &Vec3,
+        photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        let direction = if w_i.z() > 0.0 {
+            w_i.reflect(&self.sample_visible_normal(w_i, rng))
+        } else {
+            w_i.reflect(&Vec3::unit_z())
+        };
+        MaterialSampleResult {
+            direction,
+            pdf: self.pdf(uv, w_i, &direction, photon),
+            is_specular: false,
+        }
+    }
+
+    fn pdf(&self, _uv: &Vec2, w_i: &Vec3, w_o: &Vec3, _photon: &Photon) -> f64 {
+        let (w_i, w_o) = (w_i.normalize(), w_o.normalize());
+        let h = w_o + w_i;
+        if w_i.z() <= 0.0 || h.z() <= 0.0 {
+            return 0.0;
+        }
+        let h = h.normalize();
+        let sin_theta = (1.0 - w_o.z() * w_o.z()).max(0.0).sqrt();
+        self.masking(&w_i) * self.distribution(&h) / (4.0 * w_i.z()) * sin_theta
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_strength("alpha_x", self.alpha_x);
+        validator.check_strength("alpha_y", self.alpha_y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn photon() -> Photon {
+        Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        }
+    }
+
+    #[test]
+    fn highlight_is_stretched_along_rougher_direction() {
+        let target = RoughConductor::new(Conductor::aluminium(), 0.05, 0.4);
+        let bsdf = target.bsdf(&Vec2::new(0.0, 0.0));
+        let w_i = Vec3::unit_z();
+        let along_tangent = Vec3::new(0.3, 0.0, 0.91f64.sqrt());
+        let along_cotangent = Vec3::new(0.0, 0.3, 0.91f64.sqrt());
+        assert!(
+            bsdf(&along_cotangent, &w_i, &photon()).intensity
+                > 10.0 * bsdf(&along_tangent, &w_i, &photon()).intensity
+        );
+    }
+
+    #[test]
+    fn isotropic_lobe_is_symmetric_about_normal() {
+        let target = RoughConductor::new(Conductor::gold(), 0.3, 0.3);
+        let bsdf = target.bsdf(&Vec2::new(0.0, 0.0));
+        let w_i = Vec3::unit_z();
+        let a = bsdf(&Vec3::new(0.6, 0.0, 0.8), &w_i, &photon()).intensity;
+        let b = bsdf(&Vec3::new(0.0, -0.6, 0.8), &w_i, &photon()).intensity;
+        assert!((a - b).abs() < 0.000000001 * a);
+    }
+
+    #[test]
+    fn smooth_metal_reflects_all_its_reflectance() {
+        let target = RoughConductor::new(Conductor::silver(), 0.01, 0.01);
+        let bsdf = target.bsdf(&Vec2::new(0.0, 0.0));
+        let w_i = Vec3::new(0.3, 0.2, 0.8).normalize();
+        let mut rng = StdRng::seed_from_u64(0);
+        let samples = 10000;
+        let total = (0..samples)
+            .map(|_| {
+                let sample = target.sample(&Vec2::new(0.0, 0.0), &w_i, &photon(), &mut rng);
+                let cos_theta = sample.direction.z().abs();
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                bsdf(&sample.direction, &w_i, &photon()).intensity * cos_theta * sin_theta
+                    / sample.pdf
+            })
+            .sum::<f64>()
+            / samples as f64;
+        let expected = target.conductor.reflectance(w_i.z(), 550.0);
+        assert!((total - expected).abs() < 0.01);
+    }
+}
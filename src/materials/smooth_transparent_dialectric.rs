@@ -1,55 +1,51 @@
-use crate::colour::{Photon, Spectrum};
-use crate::materials::{Material, MaterialSampleResult};
-use crate::math::Vec3;
+use crate::colour::polarization::fresnel_amplitudes;
+use crate::colour::{MuellerMatrix, Photon, Spectrum};
+use crate::materials::{Bsdf, Material, MaterialSampleResult};
+use crate::math::{Complex, Vec2, Vec3};
 
-use rand::random;
+use rand::{Rng, RngCore};
 
 #[derive(Debug)]
-struct FresnelResult {
-    reflection_direction: Vec3,
-    reflection_strength: f64,
-    transmission_direction: Vec3,
-    transmission_strength: f64,
+pub(super) struct FresnelResult {
+    pub(super) reflection_direction: Vec3,
+    pub(super) reflection_strength: f64,
+    pub(super) transmission_direction: Vec3,
+    pub(super) transmission_strength: f64,
 }
 
-fn fresnel(w_i: &Vec3, eta1: f64, eta2: f64) -> FresnelResult {
+pub(super) fn fresnel(w_i: &Vec3, eta1: f64, eta2: f64) -> FresnelResult {
     let normal = if w_i.z() > 0.0 {
         Vec3::unit_z()
     } else {
         -Vec3::unit_z()
     };
-    let reflection_direction = Vec3::new(-w_i.x(), -w_i.y(), w_i.z());
-    let r = eta1 / eta2;
+    let reflection_direction = w_i.reflect(&normal);
     let cos_theta1 = normal.dot(w_i);
-    let cos_theta2_squared = 1.0 - r * r * (1.0 - cos_theta1 * cos_theta1);
-    let mut result = if cos_theta2_squared >= 0.0 {
-        let cos_theta2 = cos_theta2_squared.sqrt();
-        let reflection_strength_parallel_sqrt =
-            (eta1 * cos_theta2 - eta2 * cos_theta1) / (eta1 * cos_theta2 + eta2 * cos_theta1);
-        let reflection_strength_perpendicular_sqrt =
-            (eta1 * cos_theta1 - eta2 * cos_theta2) / (eta1 * cos_theta1 + eta2 * cos_theta2);
-        let reflection_strength = 0.5
-            * (reflection_strength_parallel_sqrt * reflection_strength_parallel_sqrt
-                + reflection_strength_perpendicular_sqrt * reflection_strength_perpendicular_sqrt);
-        let transmission_direction =
-            (-r * w_i + (r * cos_theta1 - cos_theta2) * normal).normalize();
-        let transmission_strength = 1.0 - reflection_strength;
-        FresnelResult {
-            reflection_direction,
-            reflection_strength,
-            transmission_direction,
-            transmission_strength,
+    let mut result = match w_i.refract(&normal, eta1 / eta2) {
+        Some(transmission_direction) => {
+            let cos_theta2 = -normal.dot(&transmission_direction);
+            let reflection_strength_parallel_sqrt =
+                (eta1 * cos_theta2 - eta2 * cos_theta1) / (eta1 * cos_theta2 + eta2 * cos_theta1);
+            let reflection_strength_perpendicular_sqrt =
+                (eta1 * cos_theta1 - eta2 * cos_theta2) / (eta1 * cos_theta1 + eta2 * cos_theta2);
+            let reflection_strength = 0.5
+                * (reflection_strength_parallel_sqrt * reflection_strength_parallel_sqrt
+                    + reflection_strength_perpendicular_sqrt
+                        * reflection_strength_perpendicular_sqrt);
+            let transmission_strength = 1.0 - reflection_strength;
+            FresnelResult {
+                reflection_direction,
+                reflection_strength,
+                transmission_direction,
+                transmission_strength,
+            }
         }
-    } else {
-        let reflection_strength = 1.0;
-        let transmission_strength = 0.0;
-        let transmission_direction = Default::default();
-        FresnelResult {
+        None => FresnelResult {
             reflection_direction,
-            reflection_strength,
-            transmission_direction,
-            transmission_strength,
-        }
+            reflection_strength: 1.0,
+            transmission_direction: Default::default(),
+            transmission_strength: 0.0,
+        },
     };
     if w_i.z() < 0.0 {
         result.reflection_direction.coords[2] *= -1.0;
@@ -67,17 +63,21 @@ impl SmoothTransparentDialectric {
     pub fn new(eta: Spectrum) -> SmoothTransparentDialectric {
         SmoothTransparentDialectric { eta }
     }
+
+    fn fresnel(&self, w_i: &Vec3, photon: &Photon) -> FresnelResult {
+        let (eta1, eta2) = if w_i.z() >= 0.0 {
+            (1.0, self.eta.intensity_at_wavelength(photon.wavelength))
+        } else {
+            (self.eta.intensity_at_wavelength(photon.wavelength), 1.0)
+        };
+        fresnel(w_i, eta1, eta2)
+    }
 }
 
 impl Material for SmoothTransparentDialectric {
-    fn bsdf<'a>(&'a self) -> Box<dyn Fn(&Vec3, &Vec3, &Photon) -> Photon + 'a> {
+    fn bsdf<'a>(&'a self, _uv: &Vec2) -> Bsdf<'a> {
         Box::new(move |w_o: &Vec3, w_i: &Vec3, photon_in: &Photon| {
-            let (eta1, eta2) = if w_i.z() >= 0.0 {
-                (1.0, self.eta.intensity_at_wavelength(photon_in.wavelength))
-            } else {
-                (self.eta.intensity_at_wavelength(photon_in.wavelength), 1.0)
-            };
-            let fresnel = fresnel(w_i, eta1, eta2);
+            let fresnel = self.fresnel(w_i, photon_in);
             if (*w_o - fresnel.reflection_direction).norm_squared() < 0.0000000001 {
                 photon_in.scale_intensity(fresnel.reflection_strength)
             } else if (*w_o - fresnel.transmission_direction).norm_squared() < 0.0000000001 {
@@ -88,27 +88,145 @@ impl Material for SmoothTransparentDialectric {
         })
     }
 
-    fn sample(&self, w_i: &Vec3, photon: &Photon) -> MaterialSampleResult {
-        let (eta1, eta2) = if w_i.z() >= 0.0 {
-            (1.0, self.eta.intensity_at_wavelength(photon.wavelength))
-        } else {
-            (self.eta.intensity_at_wavelength(photon.wavelength), 1.0)
-        };
-        let fresnel = fresnel(w_i, eta1, eta2);
-        if fresnel.transmission_strength <= 0.0000000001 {
+    fn sample(
+        &self,
+        _uv: &Vec2,
+        w_i: &Vec3,
+        photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        // Reflection and transmission are chosen as often as they carry light, so every
+        // sample carries the same weight
+        let fresnel = self.fresnel(w_i, photon);
+        if fresnel.transmission_strength <= 0.0000000001
+            || rng.gen::<f64>() < fresnel.reflection_strength
+        {
             MaterialSampleResult {
                 direction: fresnel.reflection_direction,
-                pdf: 0.5,
+                pdf: fresnel.reflection_strength,
+                is_specular: true,
             }
-        } else if fresnel.reflection_strength <= 0.0000000001 || random() {
+        } else {
             MaterialSampleResult {
                 direction: fresnel.transmission_direction,
-                pdf: 0.5,
+                pdf: fresnel.transmission_strength,
+                is_specular: true,
             }
+        }
+    }
+
+    fn is_dispersive(&self) -> bool {
+        true
+    }
+
+    fn mueller_matrix(
+        &self,
+        _uv: &Vec2,
+        w_i: &Vec3,
+        w_o: &Vec3,
+        photon: &Photon,
+    ) -> Option<MuellerMatrix> {
+        let eta = self.eta.intensity_at_wavelength(photon.wavelength);
+        let eta_ratio = if w_i.z() >= 0.0 { eta } else { 1.0 / eta };
+        let (r_s, r_p) = fresnel_amplitudes(w_i.z().abs(), Complex::real(eta_ratio));
+        let fresnel = self.fresnel(w_i, photon);
+        Some(
+            if (*w_o - fresnel.reflection_direction).norm_squared() < 0.0000000001 {
+                MuellerMatrix::fresnel_reflection(r_s, r_p)
+            } else if fresnel.transmission_strength > 0.0000000001
+                && (*w_o - fresnel.transmission_direction).norm_squared() < 0.0000000001
+            {
+                MuellerMatrix::fresnel_transmission(
+                    1.0 - r_s.norm_squared(),
+                    1.0 - r_p.norm_squared(),
+                )
+            } else {
+                MuellerMatrix::depolarizer(0.0)
+            },
+        )
+    }
+
+    fn pdf(&self, _uv: &Vec2, w_i: &Vec3, w_o: &Vec3, photon: &Photon) -> f64 {
+        let fresnel = self.fresnel(w_i, photon);
+        if fresnel.transmission_strength > 0.0000000001
+            && (*w_o - fresnel.transmission_direction).norm_squared() < 0.0000000001
+        {
+            fresnel.transmission_strength
+        } else if (*w_o - fresnel.reflection_direction).norm_squared() < 0.0000000001 {
+            fresnel.reflection_strength
         } else {
-            MaterialSampleResult {
-                direction: fresnel.reflection_direction,
-                pdf: 0.5,
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::Stokes;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn pdf_matches_pdf_of_sampled_directions() {
+        let target = SmoothTransparentDialectric::new(Spectrum::grey(1.5));
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        for w_i in [
+            Vec3::new(0.3, -0.2, 0.8).normalize(),
+            Vec3::new(-0.1, 0.4, -0.6).normalize(),
+            Vec3::new(0.9, 0.0, -0.1).normalize(),
+        ]
+        .iter()
+        {
+            for _ in 0..10 {
+                let sample = target.sample(&Vec2::new(0.0, 0.0), w_i, &photon, &mut rng);
+                assert!(sample.is_specular);
+                assert!(
+                    target.pdf(&Vec2::new(0.0, 0.0), w_i, &sample.direction, &photon) == sample.pdf
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pdf_is_zero_away_from_specular_directions() {
+        let target = SmoothTransparentDialectric::new(Spectrum::grey(1.5));
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let w_i = Vec3::new(0.3, -0.2, 0.8).normalize();
+        assert!(target.pdf(&Vec2::new(0.0, 0.0), &w_i, &Vec3::unit_z(), &photon) == 0.0);
+    }
+
+    #[test]
+    fn mueller_matrix_scatters_unpolarized_light_as_bsdf_does() {
+        let target = SmoothTransparentDialectric::new(Spectrum::grey(1.5));
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let bsdf = target.bsdf(&Vec2::new(0.0, 0.0));
+        for w_i in [
+            Vec3::new(0.3, -0.2, 0.8).normalize(),
+            Vec3::new(-0.1, 0.4, -0.6).normalize(),
+            Vec3::new(0.9, 0.0, -0.1).normalize(),
+        ]
+        .iter()
+        {
+            let fresnel = target.fresnel(w_i, &photon);
+            for w_o in [fresnel.reflection_direction, fresnel.transmission_direction].iter() {
+                let mueller = target
+                    .mueller_matrix(&Vec2::new(0.0, 0.0), w_i, w_o, &photon)
+                    .unwrap();
+                let scattered = (mueller * Stokes::unpolarized(1.0)).intensity();
+                assert!((scattered - bsdf(w_o, w_i, &photon).intensity).abs() < 0.000000001);
             }
         }
     }
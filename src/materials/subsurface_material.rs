@@ -0,0 +1,141 @@
+use crate::colour::{Photon, Spectrum};
+use crate::math::{Vec2, Vec3};
+use crate::media::{HenyeyGreenstein, HomogeneousMedium, Medium};
+
+use super::{Bsdf, Material, MaterialSampleResult, SmoothTransparentDialectric};
+
+use rand::RngCore;
+
+/// The single-scattering albedo of a medium that, filling a thick slab, reflects `albedo`
+/// of the light that enters it
+///
+/// This is the fit from C. Chiang et al., "Practical and Controllable Subsurface
+/// Scattering for Production Path Tracing", SIGGRAPH 2016.
+fn single_scattering_albedo(albedo: f64) -> f64 {
+    let albedo = albedo.clamp(0.0, 1.0);
+    let root = 4.09712 + 4.20863 * albedo
+        - (9.59217 + 41.6808 * albedo + 17.7126 * albedo * albedo).sqrt();
+    (1.0 - root * root).clamp(0.0, 1.0)
+}
+
+/// A translucent material, such as skin, wax or marble, that light scatters through
+/// beneath the surface
+///
+/// Light is refracted into the object by a smooth dielectric surface, and then follows a
+/// random walk through a scattering medium inside it until it finds its way back out. The
+/// integrator traces the walk like any other [interior medium](Material::interior_medium),
+/// so it exits wherever the [Sampler](crate::sampler::Sampler) finds the surface again,
+/// and the object must be closed.
+#[derive(Debug)]
+pub struct SubsurfaceMaterial {
+    surface: SmoothTransparentDialectric,
+    interior: HomogeneousMedium,
+}
+
+impl SubsurfaceMaterial {
+    /// A material that looks like a diffuse surface of colour `albedo` where it's thick
+    ///
+    /// `mean_free_path` is the average distance light travels between scattering events
+    /// inside the material, which sets how far it bleeds under the surface; thin parts of
+    /// objects let more light through. `eta` is the index of refraction of the surface.
+    pub fn new(albedo: &Spectrum, mean_free_path: f64, eta: f64) -> SubsurfaceMaterial {
+        let extinction = 1.0 / mean_free_path;
+        SubsurfaceMaterial {
+            surface: SmoothTransparentDialectric::new(Spectrum::grey(eta)),
+            interior: HomogeneousMedium {
+                absorption: albedo
+                    .map(|albedo| (1.0 - single_scattering_albedo(albedo)) * extinction),
+                scattering: albedo.map(|albedo| single_scattering_albedo(albedo) * extinction),
+                phase_function: HenyeyGreenstein::new(0.0),
+            },
+        }
+    }
+}
+
+impl Material for SubsurfaceMaterial {
+    fn bsdf<'a>(&'a self, uv: &Vec2) -> Bsdf<'a> {
+        self.surface.bsdf(uv)
+    }
+
+    fn sample(
+        &self,
+        uv: &Vec2,
+        w_i: &Vec3,
+        photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        self.surface.sample(uv, w_i, photon, rng)
+    }
+
+    fn pdf(&self, uv: &Vec2, w_i: &Vec3, w_o: &Vec3, photon: &Photon) -> f64 {
+        self.surface.pdf(uv, w_i, w_o, photon)
+    }
+
+    fn interior_medium(&self) -> Option<&dyn Medium> {
+        Some(&self.interior)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+    use crate::colour::PhotonPacket;
+    use crate::integrators::{Integrator, SimpleRandomIntegrator};
+
+    use crate::raycasting::{Primitive, Ray, Sphere};
+    use crate::sampler::SceneSampler;
+    use crate::scene::Scene;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn single_scattering_albedo_covers_range() {
+        assert!(single_scattering_albedo(0.0) < 0.0001);
+        assert!(single_scattering_albedo(1.0) > 0.999);
+        // Light that scatters many times is absorbed more, so a medium must scatter more
+        // of the light than it appears to reflect
+        assert!(single_scattering_albedo(0.5) > 0.5);
+        assert!(single_scattering_albedo(0.3) < single_scattering_albedo(0.6));
+    }
+
+    /// Mean light leaving the top of a sphere of `material`, seen from above
+    fn mean_radiance(material: SubsurfaceMaterial) -> f64 {
+        let sphere: Box<dyn Primitive> =
+            Box::new(Sphere::new(Vec3::zeros(), 1.0, Arc::new(material)));
+        let scene = Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::zeros(),
+            Lens::Pinhole,
+        )))
+        .with_object(Box::new(vec![sphere]))
+        .build();
+        let sampler = SceneSampler { scene: &scene };
+        let mut rng = StdRng::seed_from_u64(0);
+        let packet = PhotonPacket::from_photon(&Photon {
+            wavelength: 550.0,
+            intensity: 0.0,
+        });
+        let ray = Ray::new(Vec3::new(0.0, 3.0, 0.0), -Vec3::unit_y());
+        (0..2000)
+            .map(|_| {
+                SimpleRandomIntegrator {}
+                    .integrate_ray(&sampler, &ray, &packet, 64, &mut rng)
+                    .hero()
+                    .intensity
+            })
+            .sum::<f64>()
+            / 2000.0
+    }
+
+    #[test]
+    fn light_scattered_out_increases_with_albedo() {
+        let dark = mean_radiance(SubsurfaceMaterial::new(&Spectrum::grey(0.05), 0.1, 1.3));
+        let bright = mean_radiance(SubsurfaceMaterial::new(&Spectrum::grey(0.95), 0.1, 1.3));
+        assert!(dark > 0.0);
+        assert!(bright > dark * 2.0);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::colour::{MuellerMatrix, Photon};
+use crate::math::{Vec2, Vec3};
+use crate::media::Medium;
+use crate::raycasting::Footprint;
+use crate::validation::SceneValidator;
+
+use super::{Bsdf, Material, MaterialSampleResult};
+
+use rand::RngCore;
+
+use std::fmt::Debug;
+
+/// Makes a material look the same from both sides of the surface
+///
+/// Most materials only reflect light arriving from the side the normal points towards,
+/// which leaves the backs of planes and open meshes black. Wrapping the material in
+/// `TwoSided` makes the integrators turn the shading frame around when the back is hit.
+#[derive(Debug)]
+pub struct TwoSided<M: Material> {
+    pub material: M,
+}
+
+impl<M: Material> TwoSided<M> {
+    pub fn new(material: M) -> TwoSided<M> {
+        TwoSided { material }
+    }
+}
+
+impl<M: Material> Material for TwoSided<M> {
+    fn bsdf<'a>(&'a self, uv: &Vec2) -> Bsdf<'a> {
+        self.material.bsdf(uv)
+    }
+
+    fn filtered_bsdf<'a>(&'a self, uv: &Vec2, footprint: &Footprint) -> Bsdf<'a> {
+        self.material.filtered_bsdf(uv, footprint)
+    }
+
+    fn sample(
+        &self,
+        uv: &Vec2,
+        w_i: &Vec3,
+        photon: &Photon,
+        rng: &mut dyn RngCore,
+    ) -> MaterialSampleResult {
+        self.material.sample(uv, w_i, photon, rng)
+    }
+
+    fn pdf(&self, uv: &Vec2, w_i: &Vec3, w_o: &Vec3, photon: &Photon) -> f64 {
+        self.material.pdf(uv, w_i, w_o, photon)
+    }
+
+    fn emission(&self, w_o: &Vec3, photon: &Photon) -> Photon {
+        self.material.emission(w_o, photon)
+    }
+
+    fn interior_medium(&self) -> Option<&dyn Medium> {
+        self.material.interior_medium()
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.material.is_dispersive()
+    }
+
+    fn mueller_matrix(
+        &self,
+        uv: &Vec2,
+        w_i: &Vec3,
+        w_o: &Vec3,
+        photon: &Photon,
+    ) -> Option<MuellerMatrix> {
+        self.material.mueller_matrix(uv, w_i, w_o, photon)
+    }
+
+    fn is_two_sided(&self) -> bool {
+        true
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        self.material.validate(validator)
+    }
+}
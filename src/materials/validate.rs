@@ -0,0 +1,531 @@
+//! Numerical checks that a [Material](super::Material) is self-consistent
+//!
+//! These are for testing materials rather than rendering with them. They check that the
+//! pdf integrates to one, that [sample()](super::Material::sample) chooses directions with
+//! the density that [pdf()](super::Material::pdf) says it does, and that the BSDF obeys
+//! Helmholtz reciprocity.
+//!
+//! Material pdfs are densities over the polar angles of the direction, θ from the normal
+//! and φ around it, so they're integrated over those rather than over solid angle.
+//! Specular lobes are deltas, which can't be integrated, so they're treated separately.
+
+use crate::colour::Photon;
+use crate::math::{Vec2, Vec3};
+
+use super::Material;
+
+use rand::RngCore;
+
+use std::f64::consts::PI;
+
+/// Number of bins across θ, from zero to π, used by [chi_square_test()](chi_square_test)
+const THETA_BINS: usize = 10;
+
+/// Number of bins around φ used by [chi_square_test()](chi_square_test)
+const PHI_BINS: usize = 20;
+
+/// Steps across each bin, in each direction, when integrating the pdf over it
+const STEPS_PER_BIN: usize = 16;
+
+/// Bins expected to hold fewer samples than this are merged, so that the chi-square
+/// statistic follows its distribution closely enough
+const MIN_EXPECTED_COUNT: f64 = 5.0;
+
+/// The unit vector at polar angle `theta` from +Z and azimuth `phi` around it from +X
+fn direction(theta: f64, phi: f64) -> Vec3 {
+    Vec3::new(
+        theta.sin() * phi.cos(),
+        theta.sin() * phi.sin(),
+        theta.cos(),
+    )
+}
+
+/// The polar angles of `w`, with φ between zero and 2π
+fn polar_angles(w: &Vec3) -> (f64, f64) {
+    let w = w.normalize();
+    let phi = w.y().atan2(w.x());
+    (
+        w.z().clamp(-1.0, 1.0).acos(),
+        if phi < 0.0 { phi + 2.0 * PI } else { phi },
+    )
+}
+
+/// The integral of `material`'s pdf over the region of θ from `theta_min` to `theta_max`
+/// and φ from `phi_min` to `phi_max`, by the midpoint rule with `steps` steps each way
+fn integrate_pdf_over(
+    material: &dyn Material,
+    uv: &Vec2,
+    w_i: &Vec3,
+    photon: &Photon,
+    (theta_min, theta_max): (f64, f64),
+    (phi_min, phi_max): (f64, f64),
+    steps: usize,
+) -> f64 {
+    let theta_step = (theta_max - theta_min) / steps as f64;
+    let phi_step = (phi_max - phi_min) / steps as f64;
+    (0..steps)
+        .flat_map(|i| (0..steps).map(move |j| (i, j)))
+        .map(|(i, j)| {
+            let theta = theta_min + (i as f64 + 0.5) * theta_step;
+            let phi = phi_min + (j as f64 + 0.5) * phi_step;
+            material.pdf(uv, w_i, &direction(theta, phi), photon)
+        })
+        .sum::<f64>()
+        * theta_step
+        * phi_step
+}
+
+/// The integral of `material`'s pdf for light arriving from `w_i`, over every direction
+///
+/// This is one for materials that only scatter into non-specular directions. For materials
+/// with specular lobes it's the chance of choosing a direction that isn't specular. The
+/// pdf is evaluated on a grid of `resolution` by `resolution` points in θ and φ.
+pub fn pdf_integral(
+    material: &dyn Material,
+    uv: &Vec2,
+    w_i: &Vec3,
+    photon: &Photon,
+    resolution: usize,
+) -> f64 {
+    integrate_pdf_over(
+        material,
+        uv,
+        w_i,
+        photon,
+        (0.0, PI),
+        (0.0, 2.0 * PI),
+        resolution,
+    )
+}
+
+/// The result of [chi_square_test()](chi_square_test)
+#[derive(Clone, Copy, Debug)]
+pub struct ChiSquareTest {
+    pub statistic: f64,
+    pub degrees_of_freedom: usize,
+}
+
+impl ChiSquareTest {
+    /// The chance of a statistic at least this large if the samples really were drawn from
+    /// the pdf
+    ///
+    /// This uses the Wilson-Hilferty approximation to the chi-square distribution, which
+    /// is plenty accurate enough to decide whether to reject the pdf.
+    pub fn p_value(&self) -> f64 {
+        if self.degrees_of_freedom == 0 {
+            return 1.0;
+        }
+        let k = self.degrees_of_freedom as f64;
+        let variance = 2.0 / (9.0 * k);
+        let z = ((self.statistic / k).cbrt() - (1.0 - variance)) / variance.sqrt();
+        0.5 * erfc(z / 2.0f64.sqrt())
+    }
+}
+
+/// The complementary error function, to within about 1e-7, from Abramowitz and Stegun
+/// 7.1.26
+fn erfc(x: f64) -> f64 {
+    if x < 0.0 {
+        return 2.0 - erfc(-x);
+    }
+    let t = 1.0 / (1.0 + 0.5 * x);
+    t * (-x * x - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+        .exp()
+}
+
+/// Compare the directions chosen by `material`'s [sample()](super::Material::sample)
+/// against its [pdf()](super::Material::pdf), for light arriving from `w_i`
+///
+/// `sample_count` directions are sorted into bins across θ and φ, and the counts compared
+/// with those expected from integrating the pdf over each bin. Specular samples go into a
+/// bin of their own, which is expected to hold whatever the pdf leaves over, so materials
+/// with specular lobes can be tested too. A small [p-value](ChiSquareTest::p_value) means
+/// that the two disagree.
+pub fn chi_square_test(
+    material: &dyn Material,
+    uv: &Vec2,
+    w_i: &Vec3,
+    photon: &Photon,
+    sample_count: usize,
+    rng: &mut dyn RngCore,
+) -> ChiSquareTest {
+    let mut observed = vec![0.0; THETA_BINS * PHI_BINS + 1];
+    let specular_bin = THETA_BINS * PHI_BINS;
+    for _ in 0..sample_count {
+        let sample = material.sample(uv, w_i, photon, rng);
+        let bin = if sample.is_specular {
+            specular_bin
+        } else {
+            let (theta, phi) = polar_angles(&sample.direction);
+            let theta_bin = ((theta / PI * THETA_BINS as f64) as usize).min(THETA_BINS - 1);
+            let phi_bin = ((phi / (2.0 * PI) * PHI_BINS as f64) as usize).min(PHI_BINS - 1);
+            theta_bin * PHI_BINS + phi_bin
+        };
+        observed[bin] += 1.0;
+    }
+    let theta_size = PI / THETA_BINS as f64;
+    let phi_size = 2.0 * PI / PHI_BINS as f64;
+    let mut expected: Vec<f64> = (0..THETA_BINS * PHI_BINS)
+        .map(|bin| {
+            let theta_min = (bin / PHI_BINS) as f64 * theta_size;
+            let phi_min = (bin % PHI_BINS) as f64 * phi_size;
+            sample_count as f64
+                * integrate_pdf_over(
+                    material,
+                    uv,
+                    w_i,
+                    photon,
+                    (theta_min, theta_min + theta_size),
+                    (phi_min, phi_min + phi_size),
+                    STEPS_PER_BIN,
+                )
+        })
+        .collect();
+    let non_specular: f64 = expected.iter().sum();
+    expected.push((sample_count as f64 - non_specular).max(0.0));
+    // Sparse bins are pooled, from the least likely up, until the pool is big enough
+    let mut bins: Vec<(f64, f64)> = expected.into_iter().zip(observed).collect();
+    bins.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    let mut pooled = vec![];
+    let mut pool = (0.0, 0.0);
+    for (expected, observed) in bins {
+        pool = (pool.0 + expected, pool.1 + observed);
+        if pool.0 >= MIN_EXPECTED_COUNT {
+            pooled.push(pool);
+            pool = (0.0, 0.0);
+        }
+    }
+    if pool.0 > 0.0 || pool.1 > 0.0 {
+        match pooled.last_mut() {
+            Some(last) => *last = (last.0 + pool.0, last.1 + pool.1),
+            None => pooled.push(pool),
+        }
+    }
+    let statistic = pooled
+        .iter()
+        .map(|&(expected, observed)| {
+            if expected > 0.0 {
+                (observed - expected) * (observed - expected) / expected
+            } else if observed > 0.0 {
+                // Samples where the pdf says there can't be any
+                f64::INFINITY
+            } else {
+                0.0
+            }
+        })
+        .sum();
+    ChiSquareTest {
+        statistic,
+        degrees_of_freedom: pooled.len().saturating_sub(1),
+    }
+}
+
+/// How far `material`'s BSDF is from being the same with `w_a` and `w_b` swapped, relative
+/// to its value
+///
+/// Physically-based BSDFs obey Helmholtz reciprocity, so this should be close to zero. It
+/// is zero where the BSDF is zero in both directions.
+pub fn reciprocity_error(
+    material: &dyn Material,
+    uv: &Vec2,
+    w_a: &Vec3,
+    w_b: &Vec3,
+    photon: &Photon,
+) -> f64 {
+    let bsdf = material.bsdf(uv);
+    let forward = bsdf(w_a, w_b, photon).intensity;
+    let backward = bsdf(w_b, w_a, photon).intensity;
+    let scale = forward.abs().max(backward.abs());
+    if scale == 0.0 {
+        0.0
+    } else {
+        (forward - backward).abs() / scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::Spectrum;
+    use crate::materials::{
+        Bsdf, ClearCoat, Conductor, EmissiveMaterial, HairMaterial, LambertianMaterial,
+        MediumBoundary, MixMaterial, PhongMaterial, ReflectiveMaterial, RoughConductor,
+        SmoothTransparentDialectric, SubsurfaceMaterial, TwoSided,
+    };
+    use crate::media::{HenyeyGreenstein, HomogeneousMedium};
+
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use std::sync::Arc;
+
+    /// Small enough that a correct material is very unlikely to fail in any of the runs
+    const SIGNIFICANCE: f64 = 0.000001;
+
+    fn white_lambertian() -> LambertianMaterial {
+        LambertianMaterial {
+            colour: Spectrum::grey(1.0),
+            diffuse_strength: 1.0,
+        }
+    }
+
+    fn mirror() -> ReflectiveMaterial {
+        ReflectiveMaterial {
+            colour: Spectrum::grey(1.0),
+            diffuse_strength: 0.0,
+            reflection_strength: 1.0,
+        }
+    }
+
+    /// `w` moved into the upper hemisphere and normalized, or `None` if it's too close to
+    /// the surface to test with
+    fn upper(w: Vec3) -> Option<Vec3> {
+        let w = Vec3::new(w.x(), w.y(), w.z().abs());
+        if w.norm() > 0.0 && w.norm().is_finite() && w.z() > 0.01 * w.norm() {
+            Some(w.normalize())
+        } else {
+            None
+        }
+    }
+
+    fn test_photon() -> Photon {
+        Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        }
+    }
+
+    /// Whether `material`'s pdf integrates as it should and matches its samples, for light
+    /// arriving from `w_i`
+    ///
+    /// Materials without specular lobes must have pdfs that integrate to one; the others
+    /// can only be checked against their samples.
+    fn samples_match_pdf(material: &dyn Material, w_i: &Vec3, is_specular_free: bool) -> bool {
+        let uv = Vec2::new(0.5, 0.5);
+        let photon = test_photon();
+        let integral = pdf_integral(material, &uv, w_i, &photon, 200);
+        let mut rng = StdRng::seed_from_u64(0);
+        let chi_square = chi_square_test(material, &uv, w_i, &photon, 10000, &mut rng);
+        let integral_is_right = if is_specular_free {
+            (integral - 1.0).abs() < 0.01
+        } else {
+            integral < 1.01
+        };
+        integral_is_right && chi_square.p_value() > SIGNIFICANCE
+    }
+
+    /// Run all of the checks on `material`, for light arriving from `w_i` and, for
+    /// reciprocity, leaving towards `w_o`, both moved into the upper hemisphere
+    fn check(material: &dyn Material, w_i: Vec3, w_o: Vec3, is_specular_free: bool) -> TestResult {
+        let (w_i, w_o) = match (upper(w_i), upper(w_o)) {
+            (Some(w_i), Some(w_o)) => (w_i, w_o),
+            _ => return TestResult::discard(),
+        };
+        let uv = Vec2::new(0.5, 0.5);
+        TestResult::from_bool(
+            samples_match_pdf(material, &w_i, is_specular_free)
+                && reciprocity_error(material, &uv, &w_i, &w_o, &test_photon()) < 0.000001,
+        )
+    }
+
+    #[test]
+    fn p_value_is_half_at_median() {
+        // The median of the chi-square distribution is close to k (1 - 2 / 9k)^3
+        let k: f64 = 50.0;
+        let target = ChiSquareTest {
+            statistic: k * (1.0 - 2.0 / (9.0 * k)).powi(3),
+            degrees_of_freedom: 50,
+        };
+        assert!((target.p_value() - 0.5).abs() < 0.001);
+        let large = ChiSquareTest {
+            statistic: 200.0,
+            ..target
+        };
+        assert!(large.p_value() < 0.000001);
+    }
+
+    #[test]
+    fn chi_square_test_rejects_wrong_pdf() {
+        /// Claims to sample uniformly over the hemisphere, but samples in proportion to
+        /// the cosine like a Lambertian material
+        #[derive(Debug)]
+        struct WrongPdf;
+
+        impl Material for WrongPdf {
+            fn bsdf<'a>(&'a self, _uv: &Vec2) -> Bsdf<'a> {
+                Box::new(|_, _, photon| photon.clone())
+            }
+
+            fn sample(
+                &self,
+                uv: &Vec2,
+                w_i: &Vec3,
+                photon: &Photon,
+                rng: &mut dyn RngCore,
+            ) -> crate::materials::MaterialSampleResult {
+                white_lambertian().sample(uv, w_i, photon, rng)
+            }
+
+            fn pdf(&self, _uv: &Vec2, _w_i: &Vec3, w_o: &Vec3, _photon: &Photon) -> f64 {
+                if w_o.z() < 0.0 {
+                    0.0
+                } else {
+                    (1.0 - w_o.z() * w_o.z()).max(0.0).sqrt() / (2.0 * PI)
+                }
+            }
+        }
+
+        let uv = Vec2::new(0.5, 0.5);
+        let photon = Photon {
+            wavelength: 550.0,
+            intensity: 1.0,
+        };
+        let w_i = Vec3::unit_z();
+        assert!((pdf_integral(&WrongPdf, &uv, &w_i, &photon, 100) - 1.0).abs() < 0.01);
+        let mut rng = StdRng::seed_from_u64(0);
+        let result = chi_square_test(&WrongPdf, &uv, &w_i, &photon, 10000, &mut rng);
+        assert!(result.p_value() < SIGNIFICANCE);
+    }
+
+    #[quickcheck]
+    fn lambertian_material_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        check(&white_lambertian(), w_i, w_o, true)
+    }
+
+    #[quickcheck]
+    fn two_sided_material_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        check(&TwoSided::new(white_lambertian()), w_i, w_o, true)
+    }
+
+    #[quickcheck]
+    fn mix_material_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        let target = MixMaterial::new(white_lambertian(), mirror(), Spectrum::grey(0.3));
+        check(&target, w_i, w_o, false)
+    }
+
+    #[quickcheck]
+    fn clear_coat_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        check(&ClearCoat::new(white_lambertian(), 1.5), w_i, w_o, false)
+    }
+
+    #[quickcheck]
+    fn conductor_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        check(&Conductor::gold(), w_i, w_o, false)
+    }
+
+    #[quickcheck]
+    fn rough_conductor_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        check(
+            &RoughConductor::new(Conductor::aluminium(), 0.2, 0.5),
+            w_i,
+            w_o,
+            true,
+        )
+    }
+
+    #[quickcheck]
+    fn reflective_material_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        check(&mirror(), w_i, w_o, false)
+    }
+
+    #[quickcheck]
+    fn partly_diffuse_reflective_material_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        let target = ReflectiveMaterial {
+            colour: Spectrum::grey(0.8),
+            diffuse_strength: 0.5,
+            reflection_strength: 0.3,
+        };
+        check(&target, w_i, w_o, false)
+    }
+
+    #[quickcheck]
+    fn smooth_transparent_dialectric_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        let target = SmoothTransparentDialectric::new(Spectrum::grey(1.5));
+        check(&target, w_i, w_o, false)
+    }
+
+    #[quickcheck]
+    fn emissive_material_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        let target = EmissiveMaterial {
+            emission: Spectrum::grey(1.0),
+        };
+        check(&target, w_i, w_o, true)
+    }
+
+    #[quickcheck]
+    fn medium_boundary_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        let target = MediumBoundary {
+            surface: Arc::new(white_lambertian()),
+            interior: Arc::new(HomogeneousMedium {
+                absorption: Spectrum::grey(0.1),
+                scattering: Spectrum::grey(0.5),
+                phase_function: HenyeyGreenstein::new(0.3),
+            }),
+        };
+        check(&target, w_i, w_o, true)
+    }
+
+    #[quickcheck]
+    fn subsurface_material_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        let target = SubsurfaceMaterial::new(&Spectrum::grey(0.8), 0.1, 1.3);
+        check(&target, w_i, w_o, false)
+    }
+
+    #[quickcheck]
+    fn phong_material_is_consistent(w_i: Vec3, w_o: Vec3) -> TestResult {
+        let target = PhongMaterial {
+            colour: Spectrum::grey(1.0),
+            diffuse_strength: 0.5,
+            specular_strength: 0.5,
+            smoothness: 20.0,
+        };
+        check(&target, w_i, w_o, true)
+    }
+
+    fn test_hair() -> HairMaterial {
+        HairMaterial {
+            colour: Spectrum::grey(1.0),
+            diffuse_strength: 0.5,
+            specular_strength: 0.5,
+            smoothness: 20.0,
+        }
+    }
+
+    /// Hair is only checked for sampling, since its BSDF isn't reciprocal
+    #[quickcheck]
+    fn hair_material_samples_match_pdf(w_i: Vec3) -> TestResult {
+        match upper(w_i) {
+            Some(w_i) => TestResult::from_bool(samples_match_pdf(&test_hair(), &w_i, true)),
+            None => TestResult::discard(),
+        }
+    }
+
+    /// Known to fail reciprocity: the cosine to the normal is divided out for the light
+    /// direction only, and the diffuse part depends only on the light's angle to the fibre.
+    /// If this starts failing, the model has become reciprocal and should be checked in
+    /// full.
+    #[test]
+    fn hair_material_is_not_reciprocal() {
+        let w_a = Vec3::new(0.3, 0.1, 0.95).normalize();
+        let w_b = Vec3::new(0.3, 0.9, 0.3).normalize();
+        let error = reciprocity_error(
+            &test_hair(),
+            &Vec2::new(0.5, 0.5),
+            &w_a,
+            &w_b,
+            &test_photon(),
+        );
+        assert!(error > 0.1);
+    }
+}
@@ -0,0 +1,237 @@
+use super::{Mat3, Mat4, Vec3};
+
+use std::ops::Mul;
+
+/// An affine transformation of 3D space
+///
+/// Stored as a linear part (rotation, scale and shear) followed by a translation.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Affine3 {
+    linear: Mat3,
+    translation: Vec3,
+}
+
+impl Affine3 {
+    pub fn new(linear: Mat3, translation: Vec3) -> Affine3 {
+        Affine3 {
+            linear,
+            translation,
+        }
+    }
+
+    pub fn identity() -> Affine3 {
+        Affine3 {
+            linear: Mat3::identity(),
+            translation: Vec3::zeros(),
+        }
+    }
+
+    pub fn translation(translation: &Vec3) -> Affine3 {
+        Affine3 {
+            linear: Mat3::identity(),
+            translation: *translation,
+        }
+    }
+
+    pub fn scale(x: f64, y: f64, z: f64) -> Affine3 {
+        Affine3 {
+            linear: Mat3::new(x, 0.0, 0.0, 0.0, y, 0.0, 0.0, 0.0, z),
+            translation: Vec3::zeros(),
+        }
+    }
+
+    pub fn uniform_scale(scale: f64) -> Affine3 {
+        Affine3::scale(scale, scale, scale)
+    }
+
+    /// Rotation by `angle` radians, counter-clockwise about `axis`
+    pub fn rotation(axis: &Vec3, angle: f64) -> Affine3 {
+        let axis = axis.normalize();
+        let (sin, cos) = angle.sin_cos();
+        let one_minus_cos = 1.0 - cos;
+        let (x, y, z) = (axis.x(), axis.y(), axis.z());
+        Affine3 {
+            linear: Mat3::new(
+                cos + x * x * one_minus_cos,
+                x * y * one_minus_cos - z * sin,
+                x * z * one_minus_cos + y * sin,
+                y * x * one_minus_cos + z * sin,
+                cos + y * y * one_minus_cos,
+                y * z * one_minus_cos - x * sin,
+                z * x * one_minus_cos - y * sin,
+                z * y * one_minus_cos + x * sin,
+                cos + z * z * one_minus_cos,
+            ),
+            translation: Vec3::zeros(),
+        }
+    }
+
+    pub fn get_linear(&self) -> Mat3 {
+        self.linear
+    }
+
+    pub fn get_translation(&self) -> Vec3 {
+        self.translation
+    }
+
+    pub fn transform_point(&self, point: &Vec3) -> Vec3 {
+        self.linear * point + self.translation
+    }
+
+    pub fn transform_vector(&self, vector: &Vec3) -> Vec3 {
+        self.linear * vector
+    }
+
+    /// Transform a surface normal
+    ///
+    /// Normals transform by the inverse-transpose of the linear part, so that they stay
+    /// perpendicular to the surface even under non-uniform scaling. The result is not
+    /// normalized.
+    pub fn transform_normal(&self, normal: &Vec3) -> Vec3 {
+        // cofactor_matrix() is the inverse-transpose scaled by the determinant
+        self.linear.cofactor_matrix() * normal * (1.0 / self.linear.determinant())
+    }
+
+    pub fn try_inverse(&self) -> Option<Affine3> {
+        self.linear.try_inverse().map(|linear| Affine3 {
+            linear,
+            translation: -(linear * self.translation),
+        })
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        let l = &self.linear;
+        let t = &self.translation;
+        Mat4::new(
+            l.get_element(0, 0),
+            l.get_element(0, 1),
+            l.get_element(0, 2),
+            t.x(),
+            l.get_element(1, 0),
+            l.get_element(1, 1),
+            l.get_element(1, 2),
+            t.y(),
+            l.get_element(2, 0),
+            l.get_element(2, 1),
+            l.get_element(2, 2),
+            t.z(),
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+        )
+    }
+}
+
+impl Mul<Affine3> for Affine3 {
+    type Output = Affine3;
+
+    /// Compose two transformations; `rhs` is applied first
+    fn mul(self, rhs: Affine3) -> Affine3 {
+        Affine3 {
+            linear: self.linear * rhs.linear,
+            translation: self.linear * rhs.translation + self.translation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::math::Vec4;
+
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+
+    use std::f64::consts::FRAC_PI_2;
+
+    fn nearly_equal(a: &Vec3, b: &Vec3) -> bool {
+        let scale = a.norm().max(b.norm()).max(1.0);
+        (a - b).norm() < 0.000000001 * scale
+    }
+
+    #[quickcheck]
+    fn identity_does_not_change_point(p: Vec3) -> bool {
+        Affine3::identity().transform_point(&p) == p
+    }
+
+    #[quickcheck]
+    fn translation_moves_point(p: Vec3, t: Vec3) -> bool {
+        Affine3::translation(&t).transform_point(&p) == p + t
+    }
+
+    #[quickcheck]
+    fn translation_does_not_change_vector(v: Vec3, t: Vec3) -> bool {
+        Affine3::translation(&t).transform_vector(&v) == v
+    }
+
+    #[test]
+    fn scale_scales_each_axis() {
+        let target = Affine3::scale(2.0, 3.0, 4.0);
+        assert!(target.transform_point(&Vec3::new(1.0, 1.0, 1.0)) == Vec3::new(2.0, 3.0, 4.0));
+    }
+
+    #[test]
+    fn rotation_about_z_maps_x_to_y() {
+        let target = Affine3::rotation(&Vec3::unit_z(), FRAC_PI_2);
+        assert!(nearly_equal(
+            &target.transform_vector(&Vec3::unit_x()),
+            &Vec3::unit_y()
+        ));
+    }
+
+    #[quickcheck]
+    fn rotation_preserves_length(axis: Vec3, angle: f64, v: Vec3) -> TestResult {
+        if axis.norm() == 0.0 || !angle.is_finite() {
+            return TestResult::discard();
+        }
+        let target = Affine3::rotation(&axis, angle);
+        let rotated = target.transform_vector(&v);
+        TestResult::from_bool((rotated.norm() - v.norm()).abs() < 0.000000001 * v.norm().max(1.0))
+    }
+
+    #[quickcheck]
+    fn composition_applies_rhs_first(p: Vec3, t: Vec3) -> bool {
+        let scale = Affine3::uniform_scale(2.0);
+        let translation = Affine3::translation(&t);
+        nearly_equal(&(scale * translation).transform_point(&p), &((p + t) * 2.0))
+    }
+
+    #[quickcheck]
+    fn inverse_undoes_transformation(p: Vec3, t: Vec3, axis: Vec3, angle: f64) -> TestResult {
+        if axis.norm() == 0.0 || !angle.is_finite() {
+            return TestResult::discard();
+        }
+        let target = Affine3::translation(&t)
+            * Affine3::rotation(&axis, angle)
+            * Affine3::scale(2.0, 0.5, 3.0);
+        let inverse = target.try_inverse().unwrap();
+        TestResult::from_bool(nearly_equal(
+            &inverse.transform_point(&target.transform_point(&p)),
+            &p,
+        ))
+    }
+
+    #[test]
+    fn singular_transformation_has_no_inverse() {
+        assert!(Affine3::scale(1.0, 0.0, 1.0).try_inverse().is_none());
+    }
+
+    #[test]
+    fn normal_stays_perpendicular_under_non_uniform_scale() {
+        let target = Affine3::scale(1.0, 4.0, 1.0);
+        let tangent = Vec3::new(1.0, 1.0, 0.0);
+        let normal = Vec3::new(1.0, -1.0, 0.0);
+        let transformed_tangent = target.transform_vector(&tangent);
+        let transformed_normal = target.transform_normal(&normal);
+        assert!(transformed_tangent.dot(&transformed_normal).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn to_mat4_transforms_homogeneous_points() {
+        let target = Affine3::translation(&Vec3::new(1.0, 2.0, 3.0)) * Affine3::uniform_scale(2.0);
+        let result = target.to_mat4() * Vec4::new(1.0, 1.0, 1.0, 1.0);
+        assert!(result == Vec4::new(3.0, 4.0, 5.0, 1.0));
+    }
+}
@@ -0,0 +1,126 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// A complex number, for the amplitudes in the Fresnel equations
+#[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    pub fn real(re: f64) -> Complex {
+        Complex { re, im: 0.0 }
+    }
+
+    pub fn conjugate(&self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    /// The square of the modulus
+    pub fn norm_squared(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// The principal square root, with a non-negative real part
+    pub fn sqrt(&self) -> Complex {
+        let norm = self.norm();
+        let re = (0.5 * (norm + self.re)).max(0.0).sqrt();
+        let im = (0.5 * (norm - self.re)).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, rhs: Complex) -> Complex {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Mul<f64> for Complex {
+    type Output = Complex;
+
+    fn mul(self, rhs: f64) -> Complex {
+        Complex::new(self.re * rhs, self.im * rhs)
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, rhs: Complex) -> Complex {
+        let denominator = rhs.norm_squared();
+        let numerator = self * rhs.conjugate();
+        Complex::new(numerator.re / denominator, numerator.im / denominator)
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+
+    fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn product_of_i_with_itself_is_minus_one() {
+        let i = Complex::new(0.0, 1.0);
+        assert!(i * i == Complex::real(-1.0));
+    }
+
+    #[test]
+    fn division_undoes_multiplication() {
+        let a = Complex::new(1.5, -2.0);
+        let b = Complex::new(0.25, 3.0);
+        let target = (a * b) / b;
+        assert!((target - a).norm() < 0.000000001);
+    }
+
+    #[test]
+    fn square_root_squares_to_original() {
+        for &value in &[
+            Complex::new(3.0, 4.0),
+            Complex::new(-4.0, 0.0),
+            Complex::new(-1.0, -2.0),
+            Complex::new(0.0, 0.0),
+        ] {
+            let root = value.sqrt();
+            assert!(root.re >= 0.0);
+            assert!((root * root - value).norm() < 0.000000001);
+        }
+        assert!(Complex::real(-4.0).sqrt() == Complex::new(0.0, 2.0));
+    }
+}
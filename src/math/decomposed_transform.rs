@@ -0,0 +1,209 @@
+use super::{Affine3, Mat3, Quaternion, Vec3};
+
+/// A scale, followed by a rotation, followed by a translation
+///
+/// Keeping the parts separate, rather than multiplied together as in an
+/// [Affine3](Affine3), means that transforms can be [interpolated](DecomposedTransform::lerp)
+/// between animation keyframes without shearing or shrinking whatever they're applied to.
+/// The combined matrix and its inverse are computed once, when the transform is created.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct DecomposedTransform {
+    translation: Vec3,
+    rotation: Quaternion,
+    scale: Vec3,
+    matrix: Affine3,
+    inverse: Option<Affine3>,
+}
+
+impl DecomposedTransform {
+    pub fn new(translation: Vec3, rotation: Quaternion, scale: Vec3) -> DecomposedTransform {
+        let rotation = rotation.normalize();
+        let matrix = Affine3::translation(&translation)
+            * Affine3::new(rotation.to_mat3(), Vec3::zeros())
+            * Affine3::scale(scale.x(), scale.y(), scale.z());
+        DecomposedTransform {
+            translation,
+            rotation,
+            scale,
+            matrix,
+            inverse: matrix.try_inverse(),
+        }
+    }
+
+    pub fn identity() -> DecomposedTransform {
+        DecomposedTransform::new(
+            Vec3::zeros(),
+            Quaternion::identity(),
+            Vec3::new(1.0, 1.0, 1.0),
+        )
+    }
+
+    /// Split `matrix` into a scale, rotation and translation
+    ///
+    /// Any shear in `matrix` is lost. A mirror image is represented by negating the scale
+    /// along the x axis. Returns `None` if `matrix` flattens space onto a plane, line or
+    /// point, since it then has no rotation.
+    pub fn decompose(matrix: &Affine3) -> Option<DecomposedTransform> {
+        let linear = matrix.get_linear();
+        let determinant = linear.determinant();
+        if determinant == 0.0 || !determinant.is_finite() {
+            return None;
+        }
+        let columns = [
+            linear.get_column(0),
+            linear.get_column(1),
+            linear.get_column(2),
+        ];
+        let mut scale = Vec3::new(columns[0].norm(), columns[1].norm(), columns[2].norm());
+        if determinant < 0.0 {
+            scale[0] = -scale[0];
+        }
+        let [x, y, z] = [
+            columns[0] * (1.0 / scale.x()),
+            columns[1] * (1.0 / scale.y()),
+            columns[2] * (1.0 / scale.z()),
+        ];
+        let rotation = Quaternion::from_mat3(&Mat3::from_rows(&x, &y, &z).transpose());
+        Some(DecomposedTransform::new(
+            matrix.get_translation(),
+            rotation,
+            scale,
+        ))
+    }
+
+    pub fn get_translation(&self) -> Vec3 {
+        self.translation
+    }
+
+    pub fn get_rotation(&self) -> Quaternion {
+        self.rotation
+    }
+
+    pub fn get_scale(&self) -> Vec3 {
+        self.scale
+    }
+
+    /// The transform as a single matrix
+    pub fn to_affine3(&self) -> Affine3 {
+        self.matrix
+    }
+
+    /// The matrix that undoes the transform, or `None` if any of the scale factors is zero
+    pub fn inverse(&self) -> Option<Affine3> {
+        self.inverse
+    }
+
+    /// Interpolate between this transform, at `t == 0`, and `other`, at `t == 1`
+    ///
+    /// The translations and scales are interpolated linearly and the rotations by
+    /// [slerp()](Quaternion::slerp).
+    pub fn lerp(&self, other: &DecomposedTransform, t: f64) -> DecomposedTransform {
+        DecomposedTransform::new(
+            self.translation * (1.0 - t) + other.translation * t,
+            self.rotation.slerp(&other.rotation, t),
+            self.scale * (1.0 - t) + other.scale * t,
+        )
+    }
+
+    pub fn transform_point(&self, point: &Vec3) -> Vec3 {
+        self.matrix.transform_point(point)
+    }
+
+    pub fn transform_vector(&self, vector: &Vec3) -> Vec3 {
+        self.matrix.transform_vector(vector)
+    }
+
+    /// See [Affine3::transform_normal()](Affine3::transform_normal)
+    pub fn transform_normal(&self, normal: &Vec3) -> Vec3 {
+        self.matrix.transform_normal(normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+
+    use std::f64::consts::FRAC_PI_2;
+
+    fn nearly_equal(a: &Vec3, b: &Vec3) -> bool {
+        let scale = a.norm().max(b.norm()).max(1.0);
+        (a - b).norm() < 0.000000001 * scale
+    }
+
+    #[test]
+    fn scale_is_applied_before_rotation() {
+        let target = DecomposedTransform::new(
+            Vec3::new(0.0, 0.0, 1.0),
+            Quaternion::from_axis_angle(&Vec3::unit_z(), FRAC_PI_2),
+            Vec3::new(2.0, 1.0, 1.0),
+        );
+        assert!(nearly_equal(
+            &target.transform_point(&Vec3::unit_x()),
+            &Vec3::new(0.0, 2.0, 1.0)
+        ));
+    }
+
+    #[quickcheck]
+    fn cached_inverse_undoes_transform(p: Vec3, t: Vec3, axis: Vec3, angle: f64) -> TestResult {
+        if axis.norm() == 0.0 || !angle.is_finite() {
+            return TestResult::discard();
+        }
+        let target = DecomposedTransform::new(
+            t,
+            Quaternion::from_axis_angle(&axis, angle),
+            Vec3::new(2.0, 0.5, 3.0),
+        );
+        let inverse = target.inverse().unwrap();
+        TestResult::from_bool(nearly_equal(
+            &inverse.transform_point(&target.transform_point(&p)),
+            &p,
+        ))
+    }
+
+    #[test]
+    fn zero_scale_has_no_inverse() {
+        let target = DecomposedTransform::new(
+            Vec3::zeros(),
+            Quaternion::identity(),
+            Vec3::new(1.0, 0.0, 1.0),
+        );
+        assert!(target.inverse().is_none());
+    }
+
+    #[quickcheck]
+    fn decompose_recovers_parts(p: Vec3, t: Vec3, axis: Vec3, angle: f64) -> TestResult {
+        if axis.norm() == 0.0 || !angle.is_finite() {
+            return TestResult::discard();
+        }
+        let original = DecomposedTransform::new(
+            t,
+            Quaternion::from_axis_angle(&axis, angle),
+            Vec3::new(-2.0, 0.5, 3.0),
+        );
+        let target = DecomposedTransform::decompose(&original.to_affine3()).unwrap();
+        TestResult::from_bool(
+            nearly_equal(&target.transform_point(&p), &original.transform_point(&p))
+                && nearly_equal(&target.get_translation(), &t),
+        )
+    }
+
+    #[test]
+    fn lerp_blends_each_part() {
+        let start = DecomposedTransform::identity();
+        let end = DecomposedTransform::new(
+            Vec3::new(2.0, 0.0, 0.0),
+            Quaternion::from_axis_angle(&Vec3::unit_z(), FRAC_PI_2),
+            Vec3::new(3.0, 3.0, 3.0),
+        );
+        let target = start.lerp(&end, 0.5);
+        // Scaled by 2, rotated by 45°, then moved 1 along x
+        let expected = Vec3::new(1.0 + 2.0_f64.sqrt(), 2.0_f64.sqrt(), 0.0);
+        assert!(nearly_equal(
+            &target.transform_point(&Vec3::unit_x()),
+            &expected
+        ));
+    }
+}
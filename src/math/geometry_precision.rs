@@ -0,0 +1,125 @@
+//! The precision that large amounts of geometry are stored in
+//!
+//! Meshes with millions of triangles spend much of their time waiting for vertices and
+//! bounding boxes to come from memory. Building with the `f32-geometry` feature stores
+//! them in single precision, halving the memory they take up, while all of the arithmetic
+//! on rays, hits and light is still done in double precision; values are widened as
+//! they're read. Without the feature everything is stored in double precision, as before.
+
+use super::Vec3;
+
+/// The type each coordinate of stored geometry is held in
+#[cfg(feature = "f32-geometry")]
+pub type GeometryReal = f32;
+
+/// The type each coordinate of stored geometry is held in
+#[cfg(not(feature = "f32-geometry"))]
+pub type GeometryReal = f64;
+
+/// A stored coordinate, as a double
+#[cfg(feature = "f32-geometry")]
+#[inline]
+pub fn widen(value: GeometryReal) -> f64 {
+    f64::from(value)
+}
+
+/// A stored coordinate, as a double
+#[cfg(not(feature = "f32-geometry"))]
+#[inline]
+pub fn widen(value: GeometryReal) -> f64 {
+    value
+}
+
+/// The nearest value that can be stored, for points that don't need to be exact
+#[cfg(feature = "f32-geometry")]
+pub fn narrow(value: f64) -> GeometryReal {
+    value as f32
+}
+
+/// The nearest value that can be stored, for points that don't need to be exact
+#[cfg(not(feature = "f32-geometry"))]
+pub fn narrow(value: f64) -> GeometryReal {
+    value
+}
+
+/// The largest value that can be stored that isn't greater than `value`, for the lower
+/// corners of bounding boxes
+#[cfg(feature = "f32-geometry")]
+pub fn narrow_down(value: f64) -> GeometryReal {
+    let result = value as f32;
+    if f64::from(result) > value {
+        result.next_down()
+    } else {
+        result
+    }
+}
+
+/// The largest value that can be stored that isn't greater than `value`, for the lower
+/// corners of bounding boxes
+#[cfg(not(feature = "f32-geometry"))]
+pub fn narrow_down(value: f64) -> GeometryReal {
+    value
+}
+
+/// The smallest value that can be stored that isn't less than `value`, for the upper
+/// corners of bounding boxes
+#[cfg(feature = "f32-geometry")]
+pub fn narrow_up(value: f64) -> GeometryReal {
+    let result = value as f32;
+    if f64::from(result) < value {
+        result.next_up()
+    } else {
+        result
+    }
+}
+
+/// The smallest value that can be stored that isn't less than `value`, for the upper
+/// corners of bounding boxes
+#[cfg(not(feature = "f32-geometry"))]
+pub fn narrow_up(value: f64) -> GeometryReal {
+    value
+}
+
+/// A point or direction held in [GeometryReal](GeometryReal) precision
+///
+/// Convert to and from [Vec3](Vec3) with `From`; arithmetic is done on the `Vec3`.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct StoredVec3 {
+    coords: [GeometryReal; 3],
+}
+
+impl From<Vec3> for StoredVec3 {
+    fn from(v: Vec3) -> StoredVec3 {
+        StoredVec3 {
+            coords: v.coords.map(narrow),
+        }
+    }
+}
+
+impl From<StoredVec3> for Vec3 {
+    #[inline]
+    fn from(v: StoredVec3) -> Vec3 {
+        Vec3 {
+            coords: v.coords.map(widen),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn narrowed_bounds_contain_value(value: f64) -> bool {
+        widen(narrow_down(value)) <= value && widen(narrow_up(value)) >= value
+    }
+
+    #[test]
+    fn round_trip_is_close() {
+        let v = Vec3::new(0.1, -2.5, 1.0e6 / 3.0);
+        let result = Vec3::from(StoredVec3::from(v));
+        assert!((result - v).norm() <= v.norm() * widen(GeometryReal::EPSILON));
+    }
+}
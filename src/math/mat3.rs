@@ -113,7 +113,7 @@ impl Mat3 {
         if determinant == 0.0 {
             None
         } else {
-            Some(self.cofactor_matrix().transpose() * determinant)
+            Some(self.cofactor_matrix().transpose() * (1.0 / determinant))
         }
     }
 }
@@ -301,6 +301,21 @@ mod tests {
         assert!(target.try_inverse() == expected);
     }
 
+    #[test]
+    fn inverse_returns_expected_result_when_determinant_is_not_one() {
+        let target = Mat3::from_rows(
+            &Vec3::new(2.0, 0.0, 0.0),
+            &Vec3::new(0.0, 4.0, 0.0),
+            &Vec3::new(0.0, 0.0, 0.5),
+        );
+        let expected = Some(Mat3::from_rows(
+            &Vec3::new(0.5, 0.0, 0.0),
+            &Vec3::new(0.0, 0.25, 0.0),
+            &Vec3::new(0.0, 0.0, 2.0),
+        ));
+        assert!(target.try_inverse() == expected);
+    }
+
     #[test]
     fn mul_with_mat3_returns_expected_result() {
         let a = Mat3::from_rows(
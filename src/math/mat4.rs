@@ -1,8 +1,9 @@
-use super::Vec4;
+use super::{Affine3, Mat3, Vec3, Vec4};
 
+use std::array;
 use std::ops::{Mul, MulAssign};
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Copy, Clone)]
 pub struct Mat4 {
     elements: [[f64; 4]; 4],
 }
@@ -36,6 +37,64 @@ impl Mat4 {
         }
     }
 
+    pub fn identity() -> Mat4 {
+        Affine3::identity().to_mat4()
+    }
+
+    pub fn translation(translation: &Vec3) -> Mat4 {
+        Affine3::translation(translation).to_mat4()
+    }
+
+    pub fn scale(x: f64, y: f64, z: f64) -> Mat4 {
+        Affine3::scale(x, y, z).to_mat4()
+    }
+
+    /// Rotation by `angle` radians, counter-clockwise about `axis`
+    pub fn rotation(axis: &Vec3, angle: f64) -> Mat4 {
+        Affine3::rotation(axis, angle).to_mat4()
+    }
+
+    /// The transformation from world space to the space of a camera at `location` looking
+    /// at `target`
+    ///
+    /// This is the inverse of [camera::look_at()](crate::camera::look_at), so camera space
+    /// is the same: the view direction is +Z, +Y is up and +X is to the right.
+    pub fn look_at(location: &Vec3, target: &Vec3, up: &Vec3) -> Mat4 {
+        let forward = (target - location).normalize();
+        let right = up.cross(&forward).normalize();
+        let up = forward.cross(&right);
+        let rotation = Mat3::from_rows(&right, &up, &forward);
+        Affine3::new(rotation, -(rotation * location)).to_mat4()
+    }
+
+    /// A perspective projection from camera space, looking along +Z, to clip space
+    ///
+    /// After dividing by w, points in the field of view have x and y between -1 and 1, and
+    /// depths between `near` and `far` are mapped to z between 0 and 1. `vertical_fov` is
+    /// in radians and `aspect_ratio` is width divided by height.
+    pub fn perspective(vertical_fov: f64, aspect_ratio: f64, near: f64, far: f64) -> Mat4 {
+        let focal_length = 1.0 / (0.5 * vertical_fov).tan();
+        let depth_scale = far / (far - near);
+        Mat4::new(
+            focal_length / aspect_ratio,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            focal_length,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            depth_scale,
+            -near * depth_scale,
+            0.0,
+            0.0,
+            1.0,
+            0.0,
+        )
+    }
+
     pub fn from_rows(r0: &Vec4, r1: &Vec4, r2: &Vec4, r3: &Vec4) -> Mat4 {
         let mut elements = [[0.0; 4]; 4];
         for (row, v) in elements.iter_mut().zip([r0, r1, r2, r3].iter()) {
@@ -63,6 +122,50 @@ impl Mat4 {
         }
         Vec4 { coords }
     }
+
+    pub fn transpose(&self) -> Mat4 {
+        Mat4 {
+            elements: array::from_fn(|i| array::from_fn(|j| self.elements[j][i])),
+        }
+    }
+
+    pub fn first_minor(&self, row: usize, column: usize) -> f64 {
+        let mut elements = [0.0; 9];
+        let mut k = 0;
+        for i in (0..4).filter(|i| *i != row) {
+            for j in (0..4).filter(|j| *j != column) {
+                elements[k] = self.elements[i][j];
+                k += 1;
+            }
+        }
+        let [m00, m01, m02, m10, m11, m12, m20, m21, m22] = elements;
+        Mat3::new(m00, m01, m02, m10, m11, m12, m20, m21, m22).determinant()
+    }
+
+    pub fn cofactor(&self, row: usize, column: usize) -> f64 {
+        ((-1i64).pow((row + column) as u32) as f64) * self.first_minor(row, column)
+    }
+
+    pub fn cofactor_matrix(&self) -> Mat4 {
+        Mat4 {
+            elements: array::from_fn(|i| array::from_fn(|j| self.cofactor(i, j))),
+        }
+    }
+
+    pub fn determinant(&self) -> f64 {
+        (0..4)
+            .map(|column| self.elements[0][column] * self.cofactor(0, column))
+            .sum()
+    }
+
+    pub fn try_inverse(&self) -> Option<Mat4> {
+        let determinant = self.determinant();
+        if determinant == 0.0 {
+            None
+        } else {
+            Some(self.cofactor_matrix().transpose() * (1.0 / determinant))
+        }
+    }
 }
 
 impl Mul<Mat4> for Mat4 {
@@ -91,6 +194,16 @@ impl MulAssign<Mat4> for Mat4 {
     }
 }
 
+impl Mul<f64> for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: f64) -> Mat4 {
+        Mat4 {
+            elements: array::from_fn(|i| array::from_fn(|j| self.elements[i][j] * rhs)),
+        }
+    }
+}
+
 impl Mul<Vec4> for Mat4 {
     type Output = Vec4;
 
@@ -230,4 +343,86 @@ mod tests {
         let c = Vec4::new(190.0, 486.0, 782.0, 1078.0);
         assert!(a * b == c);
     }
+
+    fn nearly_equal(a: &Vec4, b: &Vec4) -> bool {
+        (0..4).all(|i| (a.coords[i] - b.coords[i]).abs() < 0.000000001)
+    }
+
+    fn test_matrix() -> Mat4 {
+        Mat4::new(
+            2.0, 0.0, 1.0, 3.0, 1.0, 3.0, 0.0, -1.0, 0.0, 1.0, 4.0, 2.0, 1.0, 0.0, 0.0, 1.0,
+        )
+    }
+
+    #[test]
+    fn transpose_swaps_rows_and_columns() {
+        let target = test_matrix();
+        assert!(target.transpose().get_row(1) == target.get_column(1));
+        assert!(target.transpose().transpose() == target);
+    }
+
+    #[test]
+    fn determinant_returns_expected_value() {
+        assert!((test_matrix().determinant() + 4.0).abs() < 0.000000001);
+        assert!(Mat4::scale(2.0, 3.0, 4.0).determinant() == 24.0);
+    }
+
+    #[test]
+    fn inverse_undoes_transformation() {
+        let target = test_matrix();
+        let inverse = target.try_inverse().unwrap();
+        let v = || Vec4::new(1.0, -2.0, 3.0, 1.0);
+        assert!(nearly_equal(&(inverse * (target * v())), &v()));
+    }
+
+    #[test]
+    fn singular_matrix_has_no_inverse() {
+        assert!(Mat4::scale(1.0, 1.0, 0.0).try_inverse().is_none());
+    }
+
+    #[test]
+    fn translation_moves_points_but_not_directions() {
+        let target = Mat4::translation(&Vec3::new(1.0, 2.0, 3.0));
+        assert!(target * Vec4::new(1.0, 1.0, 1.0, 1.0) == Vec4::new(2.0, 3.0, 4.0, 1.0));
+        assert!(target * Vec4::new(1.0, 1.0, 1.0, 0.0) == Vec4::new(1.0, 1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn look_at_puts_target_in_front_of_camera() {
+        let target = Mat4::look_at(
+            &Vec3::new(1.0, 2.0, 3.0),
+            &Vec3::new(1.0, 2.0, -2.0),
+            &Vec3::unit_y(),
+        );
+        assert!(nearly_equal(
+            &(target * Vec4::new(1.0, 2.0, 3.0, 1.0)),
+            &Vec4::new(0.0, 0.0, 0.0, 1.0)
+        ));
+        assert!(nearly_equal(
+            &(target * Vec4::new(1.0, 2.0, -2.0, 1.0)),
+            &Vec4::new(0.0, 0.0, 5.0, 1.0)
+        ));
+    }
+
+    #[test]
+    fn perspective_maps_near_and_far_to_depth_range() {
+        let target = Mat4::perspective(std::f64::consts::FRAC_PI_2, 2.0, 1.0, 10.0);
+        let project = |v: Vec4| {
+            let clip = target * v;
+            Vec4::new(
+                clip.coords[0] / clip.coords[3],
+                clip.coords[1] / clip.coords[3],
+                clip.coords[2] / clip.coords[3],
+                1.0,
+            )
+        };
+        assert!(nearly_equal(
+            &project(Vec4::new(2.0, 1.0, 1.0, 1.0)),
+            &Vec4::new(1.0, 1.0, 0.0, 1.0)
+        ));
+        assert!(nearly_equal(
+            &project(Vec4::new(0.0, -10.0, 10.0, 1.0)),
+            &Vec4::new(0.0, -1.0, 1.0, 1.0)
+        ));
+    }
 }
@@ -15,3 +15,21 @@ pub use mat3::*;
 
 mod mat4;
 pub use mat4::*;
+
+mod affine3;
+pub use affine3::*;
+
+mod geometry_precision;
+pub use geometry_precision::*;
+
+mod orthonormal_basis;
+pub use orthonormal_basis::*;
+
+mod quaternion;
+pub use quaternion::*;
+
+mod decomposed_transform;
+pub use decomposed_transform::*;
+
+mod complex;
+pub use complex::*;
@@ -0,0 +1,114 @@
+use super::Vec3;
+
+/// Three perpendicular unit vectors, for converting directions between world space and a
+/// local frame around a surface normal
+///
+/// In the local frame the tangent is along x, the cotangent along y and the normal along
+/// z. Because the basis is orthonormal, converting in either direction is just three dot
+/// products or a weighted sum, with no matrix to invert.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct OrthonormalBasis {
+    pub tangent: Vec3,
+    pub cotangent: Vec3,
+    pub normal: Vec3,
+}
+
+impl OrthonormalBasis {
+    /// A basis from vectors that are already perpendicular and of unit length, with
+    /// `tangent.cross(cotangent) == normal`
+    pub fn new(tangent: Vec3, cotangent: Vec3, normal: Vec3) -> OrthonormalBasis {
+        OrthonormalBasis {
+            tangent,
+            cotangent,
+            normal,
+        }
+    }
+
+    /// Some basis around the unit vector `normal`, for when any tangent will do
+    ///
+    /// This is the method of Duff et al., "Building an Orthonormal Basis, Revisited", which
+    /// is accurate for every direction and doesn't branch.
+    pub fn from_normal(normal: &Vec3) -> OrthonormalBasis {
+        let (x, y, z) = (normal.x(), normal.y(), normal.z());
+        let sign = 1.0_f64.copysign(z);
+        let a = -1.0 / (sign + z);
+        let b = x * y * a;
+        OrthonormalBasis {
+            tangent: Vec3::new(1.0 + sign * x * x * a, sign * b, -sign * x),
+            cotangent: Vec3::new(b, sign + y * y * a, -y),
+            normal: *normal,
+        }
+    }
+
+    /// The same basis turned half a turn about the tangent, so the normal points the other
+    /// way
+    pub fn flipped(&self) -> OrthonormalBasis {
+        OrthonormalBasis {
+            tangent: self.tangent,
+            cotangent: -self.cotangent,
+            normal: -self.normal,
+        }
+    }
+
+    /// Convert `vector` from world space to the local frame
+    pub fn to_local(&self, vector: &Vec3) -> Vec3 {
+        Vec3::new(
+            vector.dot(&self.tangent),
+            vector.dot(&self.cotangent),
+            vector.dot(&self.normal),
+        )
+    }
+
+    /// Convert `vector` from the local frame to world space
+    pub fn to_world(&self, vector: &Vec3) -> Vec3 {
+        self.tangent * vector.x() + self.cotangent * vector.y() + self.normal * vector.z()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn from_normal_is_right_handed_and_orthonormal(normal: Vec3) -> TestResult {
+        if normal.norm() == 0.0 {
+            return TestResult::discard();
+        }
+        let target = OrthonormalBasis::from_normal(&normal.normalize());
+        TestResult::from_bool(
+            (target.tangent.norm() - 1.0).abs() < 0.000000001
+                && (target.cotangent.norm() - 1.0).abs() < 0.000000001
+                && target.tangent.dot(&target.normal).abs() < 0.000000001
+                && target.cotangent.dot(&target.normal).abs() < 0.000000001
+                && (target.tangent.cross(&target.cotangent) - target.normal).norm() < 0.000000001,
+        )
+    }
+
+    #[test]
+    fn from_normal_handles_poles() {
+        for normal in [Vec3::unit_z(), -Vec3::unit_z()].iter() {
+            let target = OrthonormalBasis::from_normal(normal);
+            assert!((target.tangent.cross(&target.cotangent) - *normal).norm() < 0.000000001);
+        }
+    }
+
+    #[quickcheck]
+    fn to_world_undoes_to_local(normal: Vec3, v: Vec3) -> TestResult {
+        if normal.norm() == 0.0 {
+            return TestResult::discard();
+        }
+        let target = OrthonormalBasis::from_normal(&normal.normalize());
+        let result = target.to_world(&target.to_local(&v));
+        TestResult::from_bool((result - v).norm() < 0.000000001 * v.norm().max(1.0))
+    }
+
+    #[test]
+    fn flipped_basis_is_still_right_handed() {
+        let target = OrthonormalBasis::from_normal(&Vec3::new(0.6, 0.0, 0.8)).flipped();
+        assert!((target.tangent.cross(&target.cotangent) - target.normal).norm() < 0.000000001);
+        assert!(target.to_local(&Vec3::new(0.6, 0.0, 0.8)).z() < 0.0);
+    }
+}
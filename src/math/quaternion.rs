@@ -0,0 +1,302 @@
+use super::{Mat3, Mat4, Vec3};
+
+use std::ops::Mul;
+
+/// A rotation of 3D space, stored as a unit quaternion
+///
+/// Unlike a rotation matrix, a quaternion can be [interpolated](Quaternion::slerp) smoothly,
+/// which is what's needed to blend between the keyframes of an animation.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Quaternion {
+    w: f64,
+    v: Vec3,
+}
+
+impl Quaternion {
+    /// The quaternion `w + xi + yj + zk`
+    ///
+    /// Only unit quaternions represent rotations; see [normalize()](Quaternion::normalize).
+    pub fn new(w: f64, x: f64, y: f64, z: f64) -> Quaternion {
+        Quaternion {
+            w,
+            v: Vec3::new(x, y, z),
+        }
+    }
+
+    pub fn identity() -> Quaternion {
+        Quaternion::new(1.0, 0.0, 0.0, 0.0)
+    }
+
+    /// Rotation by `angle` radians, counter-clockwise about `axis`
+    pub fn from_axis_angle(axis: &Vec3, angle: f64) -> Quaternion {
+        let (sin, cos) = (0.5 * angle).sin_cos();
+        Quaternion {
+            w: cos,
+            v: axis.normalize() * sin,
+        }
+    }
+
+    /// The rotation represented by `matrix`, which must be orthonormal with a determinant
+    /// of one
+    pub fn from_mat3(matrix: &Mat3) -> Quaternion {
+        let m = |row, column| matrix.get_element(row, column);
+        let trace = m(0, 0) + m(1, 1) + m(2, 2);
+        // Divide by the largest of the four possible denominators, to keep the result
+        // accurate for rotations of close to half a turn
+        let result = if trace > 0.0 {
+            let s = 2.0 * (trace + 1.0).sqrt();
+            Quaternion::new(
+                0.25 * s,
+                (m(2, 1) - m(1, 2)) / s,
+                (m(0, 2) - m(2, 0)) / s,
+                (m(1, 0) - m(0, 1)) / s,
+            )
+        } else if m(0, 0) > m(1, 1) && m(0, 0) > m(2, 2) {
+            let s = 2.0 * (1.0 + m(0, 0) - m(1, 1) - m(2, 2)).sqrt();
+            Quaternion::new(
+                (m(2, 1) - m(1, 2)) / s,
+                0.25 * s,
+                (m(0, 1) + m(1, 0)) / s,
+                (m(0, 2) + m(2, 0)) / s,
+            )
+        } else if m(1, 1) > m(2, 2) {
+            let s = 2.0 * (1.0 + m(1, 1) - m(0, 0) - m(2, 2)).sqrt();
+            Quaternion::new(
+                (m(0, 2) - m(2, 0)) / s,
+                (m(0, 1) + m(1, 0)) / s,
+                0.25 * s,
+                (m(1, 2) + m(2, 1)) / s,
+            )
+        } else {
+            let s = 2.0 * (1.0 + m(2, 2) - m(0, 0) - m(1, 1)).sqrt();
+            Quaternion::new(
+                (m(1, 0) - m(0, 1)) / s,
+                (m(0, 2) + m(2, 0)) / s,
+                (m(1, 2) + m(2, 1)) / s,
+                0.25 * s,
+            )
+        };
+        result.normalize()
+    }
+
+    /// The rotation in the upper-left 3×3 corner of `matrix`
+    pub fn from_mat4(matrix: &Mat4) -> Quaternion {
+        let m = |row, column| matrix.get_element(row, column);
+        Quaternion::from_mat3(&Mat3::new(
+            m(0, 0),
+            m(0, 1),
+            m(0, 2),
+            m(1, 0),
+            m(1, 1),
+            m(1, 2),
+            m(2, 0),
+            m(2, 1),
+            m(2, 2),
+        ))
+    }
+
+    pub fn w(&self) -> f64 {
+        self.w
+    }
+
+    /// The `i`, `j` and `k` parts of the quaternion
+    pub fn vector(&self) -> Vec3 {
+        self.v
+    }
+
+    pub fn dot(&self, rhs: &Quaternion) -> f64 {
+        self.w * rhs.w + self.v.dot(&rhs.v)
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(&self) -> Quaternion {
+        let scale = 1.0 / self.norm();
+        Quaternion {
+            w: self.w * scale,
+            v: self.v * scale,
+        }
+    }
+
+    /// The opposite rotation, for a unit quaternion
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion {
+            w: self.w,
+            v: -self.v,
+        }
+    }
+
+    pub fn rotate(&self, vector: &Vec3) -> Vec3 {
+        // Expansion of q * (0, vector) * conjugate(q) for a unit quaternion
+        let t = self.v.cross(vector) * 2.0;
+        vector + t * self.w + self.v.cross(&t)
+    }
+
+    /// Interpolate along the shortest arc from this rotation, at `t == 0`, to `other`, at
+    /// `t == 1`, at a constant angular speed
+    pub fn slerp(&self, other: &Quaternion, t: f64) -> Quaternion {
+        // q and -q are the same rotation; pick whichever is closer so as not to go the long
+        // way round
+        let (other, cos_angle) = match self.dot(other) {
+            dot if dot < 0.0 => (
+                Quaternion {
+                    w: -other.w,
+                    v: -other.v,
+                },
+                -dot,
+            ),
+            dot => (*other, dot),
+        };
+        let (a, b) = if cos_angle > 0.9995 {
+            // Close enough that linear interpolation is accurate, and sin(angle) ≈ 0
+            (1.0 - t, t)
+        } else {
+            let angle = cos_angle.acos();
+            let sin_angle = angle.sin();
+            (
+                ((1.0 - t) * angle).sin() / sin_angle,
+                (t * angle).sin() / sin_angle,
+            )
+        };
+        Quaternion {
+            w: self.w * a + other.w * b,
+            v: self.v * a + other.v * b,
+        }
+        .normalize()
+    }
+
+    pub fn to_mat3(&self) -> Mat3 {
+        let (w, x, y, z) = (self.w, self.v.x(), self.v.y(), self.v.z());
+        Mat3::new(
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y - w * z),
+            2.0 * (x * z + w * y),
+            2.0 * (x * y + w * z),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z - w * x),
+            2.0 * (x * z - w * y),
+            2.0 * (y * z + w * x),
+            1.0 - 2.0 * (x * x + y * y),
+        )
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        let m = self.to_mat3();
+        let e = |row, column| m.get_element(row, column);
+        Mat4::new(
+            e(0, 0),
+            e(0, 1),
+            e(0, 2),
+            0.0,
+            e(1, 0),
+            e(1, 1),
+            e(1, 2),
+            0.0,
+            e(2, 0),
+            e(2, 1),
+            e(2, 2),
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+        )
+    }
+}
+
+impl Mul<Quaternion> for Quaternion {
+    type Output = Quaternion;
+
+    /// Compose two rotations; `rhs` is applied first
+    fn mul(self, rhs: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * rhs.w - self.v.dot(&rhs.v),
+            v: rhs.v * self.w + self.v * rhs.w + self.v.cross(&rhs.v),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::math::Affine3;
+
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    fn nearly_equal(a: &Vec3, b: &Vec3) -> bool {
+        let scale = a.norm().max(b.norm()).max(1.0);
+        (a - b).norm() < 0.000000001 * scale
+    }
+
+    #[quickcheck]
+    fn rotation_matches_affine_rotation(axis: Vec3, angle: f64, v: Vec3) -> TestResult {
+        if axis.norm() == 0.0 || !angle.is_finite() {
+            return TestResult::discard();
+        }
+        let target = Quaternion::from_axis_angle(&axis, angle);
+        let expected = Affine3::rotation(&axis, angle).transform_vector(&v);
+        TestResult::from_bool(
+            nearly_equal(&target.rotate(&v), &expected)
+                && nearly_equal(&(target.to_mat3() * v), &expected),
+        )
+    }
+
+    #[quickcheck]
+    fn mat3_round_trip_gives_same_rotation(axis: Vec3, angle: f64, v: Vec3) -> TestResult {
+        if axis.norm() == 0.0 || !angle.is_finite() {
+            return TestResult::discard();
+        }
+        let original = Quaternion::from_axis_angle(&axis, angle);
+        let target = Quaternion::from_mat3(&original.to_mat3());
+        TestResult::from_bool(nearly_equal(&target.rotate(&v), &original.rotate(&v)))
+    }
+
+    #[test]
+    fn half_turn_survives_mat4_round_trip() {
+        let original = Quaternion::from_axis_angle(&Vec3::new(1.0, 1.0, 0.0), PI);
+        let target = Quaternion::from_mat4(&original.to_mat4());
+        let v = Vec3::new(0.3, -2.0, 1.5);
+        assert!(nearly_equal(&target.rotate(&v), &original.rotate(&v)));
+    }
+
+    #[test]
+    fn composition_applies_rhs_first() {
+        let x = Quaternion::from_axis_angle(&Vec3::unit_x(), FRAC_PI_2);
+        let z = Quaternion::from_axis_angle(&Vec3::unit_z(), FRAC_PI_2);
+        let v = Vec3::new(1.0, 2.0, 3.0);
+        assert!(nearly_equal(&(z * x).rotate(&v), &z.rotate(&x.rotate(&v))));
+    }
+
+    #[test]
+    fn slerp_halfway_is_half_the_angle() {
+        let start = Quaternion::identity();
+        let end = Quaternion::from_axis_angle(&Vec3::unit_z(), FRAC_PI_2);
+        let target = start.slerp(&end, 0.5);
+        let expected = Quaternion::from_axis_angle(&Vec3::unit_z(), FRAC_PI_2 * 0.5);
+        assert!(nearly_equal(
+            &target.rotate(&Vec3::unit_x()),
+            &expected.rotate(&Vec3::unit_x())
+        ));
+        assert!(start.slerp(&end, 0.0) == start);
+    }
+
+    #[test]
+    fn slerp_takes_shortest_arc() {
+        let start = Quaternion::identity();
+        let end = Quaternion::from_axis_angle(&Vec3::unit_z(), FRAC_PI_2);
+        let v = end.vector();
+        let negated_end = Quaternion::new(-end.w(), -v.x(), -v.y(), -v.z());
+        let target = start.slerp(&negated_end, 0.5);
+        let expected = Quaternion::from_axis_angle(&Vec3::unit_z(), FRAC_PI_2 * 0.5);
+        assert!(nearly_equal(
+            &target.rotate(&Vec3::unit_x()),
+            &expected.rotate(&Vec3::unit_x())
+        ));
+    }
+}
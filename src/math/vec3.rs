@@ -2,7 +2,9 @@ use super::Mat3;
 
 use itertools::izip;
 
-use std::ops::{Add, AddAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign};
+use std::ops::{
+    Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign,
+};
 
 #[derive(Copy, Clone, PartialEq, Debug, Default)]
 pub struct Vec3 {
@@ -135,6 +137,55 @@ impl Vec3 {
         }
         Vec3 { coords }
     }
+
+    /// The smaller of each pair of coordinates
+    pub fn component_min(&self, rhs: &Self) -> Self {
+        Vec3::new(
+            self.x().min(rhs.x()),
+            self.y().min(rhs.y()),
+            self.z().min(rhs.z()),
+        )
+    }
+
+    /// The larger of each pair of coordinates
+    pub fn component_max(&self, rhs: &Self) -> Self {
+        Vec3::new(
+            self.x().max(rhs.x()),
+            self.y().max(rhs.y()),
+            self.z().max(rhs.z()),
+        )
+    }
+
+    /// The point `t` of the way from `self` to `rhs`
+    pub fn lerp(&self, rhs: &Self, t: f64) -> Self {
+        self * (1.0 - t) + rhs * t
+    }
+
+    /// The mirror image of this direction about `normal`, which must be of unit length
+    ///
+    /// Both directions point away from the surface, as they do in BSDF space, so reflecting
+    /// about +Z just negates x and y.
+    pub fn reflect(&self, normal: &Vec3) -> Self {
+        normal * (2.0 * self.dot(normal)) - *self
+    }
+
+    /// The direction light arriving along this one is bent into on passing through a
+    /// surface with unit normal `normal`
+    ///
+    /// This direction points away from the surface on the same side as `normal`; the
+    /// result points away from it on the other side. `eta_ratio` is the refractive index on
+    /// this side divided by the index on the other. Returns `None` if the light is totally
+    /// internally reflected instead.
+    pub fn refract(&self, normal: &Vec3, eta_ratio: f64) -> Option<Self> {
+        let cos_theta1 = self.dot(normal);
+        let cos_theta2_squared = 1.0 - eta_ratio * eta_ratio * (1.0 - cos_theta1 * cos_theta1);
+        if cos_theta2_squared < 0.0 {
+            None
+        } else {
+            let cos_theta2 = cos_theta2_squared.sqrt();
+            Some((normal * (eta_ratio * cos_theta1 - cos_theta2) - self * eta_ratio).normalize())
+        }
+    }
 }
 
 impl Index<usize> for Vec3 {
@@ -300,6 +351,28 @@ impl MulAssign<Mat3> for Vec3 {
     }
 }
 
+impl Div<f64> for &Vec3 {
+    type Output = Vec3;
+
+    fn div(self, rhs: f64) -> Vec3 {
+        self * (1.0 / rhs)
+    }
+}
+
+impl Div<f64> for Vec3 {
+    type Output = Vec3;
+
+    fn div(self, rhs: f64) -> Vec3 {
+        self * (1.0 / rhs)
+    }
+}
+
+impl DivAssign<f64> for Vec3 {
+    fn div_assign(&mut self, rhs: f64) {
+        *self *= 1.0 / rhs;
+    }
+}
+
 impl Mul<Vec3> for f64 {
     type Output = Vec3;
 
@@ -319,7 +392,8 @@ impl Mul<&Vec3> for f64 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use quickcheck::{Arbitrary, Gen};
+    use quickcheck::{Arbitrary, Gen, TestResult};
+    use quickcheck_macros::quickcheck;
 
     impl Arbitrary for Vec3 {
         fn arbitrary<G: Gen>(g: &mut G) -> Vec3 {
@@ -492,4 +566,70 @@ mod tests {
         b *= a;
         assert!(b == c);
     }
+
+    #[quickcheck]
+    fn division_undoes_multiplication(v: Vec3, s: f64) -> TestResult {
+        if s == 0.0 || !s.is_finite() || !(v * s).norm().is_finite() {
+            return TestResult::discard();
+        }
+        let mut divided = v * s;
+        divided /= s;
+        TestResult::from_bool(
+            ((v * s) / s - v).norm() <= 0.000000001 * v.norm()
+                && (divided - v).norm() <= 0.000000001 * v.norm(),
+        )
+    }
+
+    #[quickcheck]
+    fn component_min_and_max_bound_both(a: Vec3, b: Vec3) -> bool {
+        let min = a.component_min(&b);
+        let max = a.component_max(&b);
+        (0..3).all(|i| min[i] <= a[i] && min[i] <= b[i] && max[i] >= a[i] && max[i] >= b[i])
+    }
+
+    #[quickcheck]
+    fn lerp_passes_through_both_ends(a: Vec3, b: Vec3) -> bool {
+        a.lerp(&b, 0.0) == a && (a.lerp(&b, 1.0) - b).norm() <= 0.000000001 * a.norm().max(b.norm())
+    }
+
+    #[quickcheck]
+    fn reflect_preserves_angle_to_normal(v: Vec3, normal: Vec3) -> TestResult {
+        if v.norm() == 0.0 || normal.norm() == 0.0 {
+            return TestResult::discard();
+        }
+        let (v, normal) = (v.normalize(), normal.normalize());
+        let reflected = v.reflect(&normal);
+        TestResult::from_bool(
+            (reflected.dot(&normal) - v.dot(&normal)).abs() < 0.000000001
+                && (reflected.norm() - 1.0).abs() < 0.000000001
+                && normal.cross(&v).dot(&reflected).abs() < 0.000000001,
+        )
+    }
+
+    #[test]
+    fn reflect_about_z_negates_x_and_y() {
+        let target = Vec3::new(0.6, 0.0, 0.8);
+        assert!((target.reflect(&Vec3::unit_z()) - Vec3::new(-0.6, 0.0, 0.8)).norm() < 0.000000001);
+    }
+
+    #[quickcheck]
+    fn refract_obeys_snells_law(v: Vec3, eta_ratio: f64) -> TestResult {
+        if v.z() <= 0.0 || !(0.2..5.0).contains(&eta_ratio) {
+            return TestResult::discard();
+        }
+        let v = v.normalize();
+        let normal = Vec3::unit_z();
+        match v.refract(&normal, eta_ratio) {
+            Some(refracted) => {
+                let sin_theta1 = v.cross(&normal).norm();
+                let sin_theta2 = refracted.cross(&normal).norm();
+                TestResult::from_bool(
+                    refracted.z() < 0.0
+                        && (eta_ratio * sin_theta1 - sin_theta2).abs() < 0.000001
+                        && normal.cross(&v).dot(&refracted).abs() < 0.000001,
+                )
+            }
+            None => TestResult::from_bool(eta_ratio * v.cross(&normal).norm() > 1.0),
+        }
+    }
 }
@@ -0,0 +1,160 @@
+use crate::colour::Spectrum;
+use crate::raycasting::Ray;
+use crate::util::VoxelGrid;
+
+use super::{HenyeyGreenstein, Medium, MediumScattering, PhaseFunction};
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+use std::sync::Arc;
+
+/// A medium whose density is given by a [VoxelGrid](VoxelGrid), for clouds and smoke
+///
+/// The coefficients are per unit distance at a density of one, and scale with the density
+/// at each point. The medium is empty outside the grid's bounding box, so a grid can fill
+/// the whole scene, or the interior of a primitive enclosing it, through a
+/// [MediumBoundary](crate::materials::MediumBoundary).
+#[derive(Debug)]
+pub struct GridMedium {
+    pub grid: Arc<VoxelGrid>,
+
+    /// Fraction of light absorbed per unit distance, at unit density
+    pub absorption: Spectrum,
+
+    /// Fraction of light scattered per unit distance, at unit density
+    pub scattering: Spectrum,
+
+    pub phase_function: HenyeyGreenstein,
+}
+
+impl GridMedium {
+    fn attenuation(&self, wavelength: f64) -> f64 {
+        self.absorption.intensity_at_wavelength(wavelength)
+            + self.scattering.intensity_at_wavelength(wavelength)
+    }
+}
+
+impl Medium for GridMedium {
+    /// The optical depth is found by ray marching in steps of half a voxel
+    fn transmittance(&self, ray: &Ray, distance: f64, wavelength: f64) -> f64 {
+        let range = self.grid.bounding_box().distances_inside(ray);
+        let (start, end) = (range.get_min(), range.get_max().min(distance));
+        if end <= start {
+            return 1.0;
+        }
+        let steps = ((end - start) / (0.5 * self.grid.voxel_size()))
+            .ceil()
+            .max(1.0);
+        let step = (end - start) / steps;
+        let total_density: f64 = (0..steps as usize)
+            .map(|i| {
+                self.grid
+                    .value_at(&ray.point_at(start + (i as f64 + 0.5) * step))
+            })
+            .sum();
+        (-self.attenuation(wavelength) * total_density * step).exp()
+    }
+
+    /// Scattering events are chosen by delta tracking: candidate events are chosen as if
+    /// the whole grid had its maximum density, and each is accepted with probability of
+    /// the actual density over the maximum
+    fn sample_scattering(
+        &self,
+        ray: &Ray,
+        max_distance: f64,
+        wavelength: f64,
+        rng: &mut dyn RngCore,
+    ) -> Option<MediumScattering> {
+        let attenuation = self.attenuation(wavelength);
+        let majorant = attenuation * self.grid.max_value();
+        if majorant <= 0.0 {
+            return None;
+        }
+        let range = self.grid.bounding_box().distances_inside(ray);
+        let end = range.get_max().min(max_distance);
+        let mut distance = range.get_min();
+        loop {
+            distance -= (1.0 - rng.sample::<f64, _>(Open01)).ln() / majorant;
+            if distance >= end {
+                return None;
+            }
+            let density = self.grid.value_at(&ray.point_at(distance));
+            if rng.gen::<f64>() * self.grid.max_value() < density {
+                return Some(MediumScattering {
+                    distance,
+                    weight: self.scattering.intensity_at_wavelength(wavelength) / attenuation,
+                });
+            }
+        }
+    }
+
+    fn phase_function(&self) -> &dyn PhaseFunction {
+        &self.phase_function
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::math::Vec3;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// A ball of density falling from two at the centre to zero at the edge
+    fn test_medium() -> GridMedium {
+        let grid = VoxelGrid::from_fn(Vec3::new(-1.0, -1.0, -1.0), 0.1, [20, 20, 20], |point| {
+            2.0 * (1.0 - point.norm()).max(0.0)
+        });
+        GridMedium {
+            grid: Arc::new(grid),
+            absorption: Spectrum::grey(0.5),
+            scattering: Spectrum::grey(1.5),
+            phase_function: HenyeyGreenstein::new(0.0),
+        }
+    }
+
+    #[test]
+    fn medium_is_empty_outside_grid() {
+        let target = test_medium();
+        let ray = Ray::new(Vec3::new(0.0, 2.0, -5.0), Vec3::unit_z());
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(target.transmittance(&ray, 10.0, 550.0) == 1.0);
+        assert!(target
+            .sample_scattering(&ray, 10.0, 550.0, &mut rng)
+            .is_none());
+    }
+
+    #[test]
+    fn transmittance_matches_integrated_density() {
+        let target = test_medium();
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::unit_z());
+        // The density along the diameter integrates to two
+        let expected = (-2.0f64 * 2.0).exp();
+        assert!((target.transmittance(&ray, 10.0, 550.0) - expected).abs() < 0.01);
+        // Stopping at the centre travels through half of it
+        let expected_half = (-2.0f64).exp();
+        assert!((target.transmittance(&ray, 5.0, 550.0) - expected_half).abs() < 0.01);
+    }
+
+    #[test]
+    fn fraction_of_rays_scattered_matches_transmittance() {
+        let target = test_medium();
+        let ray = Ray::new(Vec3::new(0.1, 0.2, -5.0), Vec3::unit_z());
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut unscattered = 0;
+        for _ in 0..10000 {
+            match target.sample_scattering(&ray, 10.0, 550.0, &mut rng) {
+                None => unscattered += 1,
+                Some(scattering) => {
+                    assert!(scattering.distance > 4.0 && scattering.distance < 6.0);
+                    assert!((scattering.weight - 0.75).abs() < 0.000000001);
+                }
+            }
+        }
+        let expected = target.transmittance(&ray, 10.0, 550.0) * 10000.0;
+        assert!((unscattered as f64 - expected).abs() < 200.0);
+    }
+}
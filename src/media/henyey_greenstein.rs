@@ -0,0 +1,112 @@
+use crate::math::{OrthonormalBasis, Vec3};
+
+use super::PhaseFunction;
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+use std::f64::consts::PI;
+
+/// The Henyey-Greenstein phase function
+///
+/// A single parameter, `asymmetry`, controls the shape of the distribution. Zero scatters
+/// light equally in all directions, positive values favour forward scattering (as in fog
+/// and haze) and negative values favour back scattering. The value must be strictly
+/// between -1 and 1.
+#[derive(Clone, Debug)]
+pub struct HenyeyGreenstein {
+    pub asymmetry: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(asymmetry: f64) -> HenyeyGreenstein {
+        HenyeyGreenstein { asymmetry }
+    }
+
+    fn value_for_cos_theta(&self, cos_theta: f64) -> f64 {
+        let g = self.asymmetry;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+    }
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn value(&self, direction: &Vec3, scattered_direction: &Vec3) -> f64 {
+        self.value_for_cos_theta(direction.normalize().dot(&scattered_direction.normalize()))
+    }
+
+    fn sample(&self, direction: &Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        let g = self.asymmetry;
+        let xi: f64 = rng.sample(Open01);
+        let cos_theta = if g.abs() < 0.001 {
+            1.0 - 2.0 * xi
+        } else {
+            let term = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+            ((1.0 + g * g - term * term) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.sample::<f64, _>(Open01);
+
+        let direction = direction.normalize();
+        let OrthonormalBasis {
+            tangent, cotangent, ..
+        } = OrthonormalBasis::from_normal(&direction);
+        direction * cos_theta + (tangent * phi.cos() + cotangent * phi.sin()) * sin_theta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::random_distributions::{RandomDistribution, UniformSphere};
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn isotropic_value_is_uniform() {
+        let target = HenyeyGreenstein::new(0.0);
+        let value = target.value(&Vec3::unit_x(), &Vec3::new(0.3, -0.2, 0.9));
+        assert!((value - 1.0 / (4.0 * PI)).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn value_integrates_to_one() {
+        let target = HenyeyGreenstein::new(0.6);
+        let distribution = UniformSphere::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        let integral = (0..100000)
+            .map(|_| {
+                let w = distribution.value(&mut rng);
+                target.value(&Vec3::unit_z(), &w) / distribution.pdf(w)
+            })
+            .sum::<f64>()
+            / 100000.0;
+        assert!((integral - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn mean_cosine_of_samples_is_asymmetry() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let direction = Vec3::new(1.0, 2.0, -0.5).normalize();
+        for &g in &[-0.5, 0.0, 0.3, 0.8] {
+            let target = HenyeyGreenstein::new(g);
+            let mean_cosine = (0..10000)
+                .map(|_| target.sample(&direction, &mut rng).dot(&direction))
+                .sum::<f64>()
+                / 10000.0;
+            assert!((mean_cosine - g).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn samples_are_unit_vectors() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let target = HenyeyGreenstein::new(0.7);
+        for _ in 0..1000 {
+            let sample = target.sample(&Vec3::new(0.0, -3.0, 0.0), &mut rng);
+            assert!((sample.norm() - 1.0).abs() < 0.000000001);
+        }
+    }
+}
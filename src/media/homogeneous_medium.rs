@@ -0,0 +1,133 @@
+use crate::colour::Spectrum;
+use crate::raycasting::Ray;
+
+use super::{HenyeyGreenstein, Medium, MediumScattering, PhaseFunction};
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+/// A medium with the same density everywhere
+///
+/// Coefficients are per unit distance, and may vary with wavelength so, for example,
+/// absorbing glass can be tinted.
+#[derive(Debug)]
+pub struct HomogeneousMedium {
+    /// Fraction of light absorbed per unit distance
+    pub absorption: Spectrum,
+
+    /// Fraction of light scattered per unit distance
+    pub scattering: Spectrum,
+
+    pub phase_function: HenyeyGreenstein,
+}
+
+impl HomogeneousMedium {
+    fn attenuation(&self, wavelength: f64) -> f64 {
+        self.absorption.intensity_at_wavelength(wavelength)
+            + self.scattering.intensity_at_wavelength(wavelength)
+    }
+}
+
+impl Medium for HomogeneousMedium {
+    fn transmittance(&self, _ray: &Ray, distance: f64, wavelength: f64) -> f64 {
+        (-self.attenuation(wavelength) * distance).exp()
+    }
+
+    fn sample_scattering(
+        &self,
+        _ray: &Ray,
+        max_distance: f64,
+        wavelength: f64,
+        rng: &mut dyn RngCore,
+    ) -> Option<MediumScattering> {
+        let attenuation = self.attenuation(wavelength);
+        if attenuation <= 0.0 {
+            return None;
+        }
+        // Distances are exponentially distributed, in proportion to the transmittance
+        let distance = -(1.0 - rng.sample::<f64, _>(Open01)).ln() / attenuation;
+        if distance >= max_distance {
+            None
+        } else {
+            Some(MediumScattering {
+                distance,
+                weight: self.scattering.intensity_at_wavelength(wavelength) / attenuation,
+            })
+        }
+    }
+
+    fn phase_function(&self) -> &dyn PhaseFunction {
+        &self.phase_function
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::math::Vec3;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn test_ray() -> Ray {
+        Ray::new(Vec3::zeros(), Vec3::unit_z())
+    }
+
+    fn test_medium() -> HomogeneousMedium {
+        HomogeneousMedium {
+            absorption: Spectrum::grey(0.25),
+            scattering: Spectrum::grey(0.75),
+            phase_function: HenyeyGreenstein::new(0.0),
+        }
+    }
+
+    #[test]
+    fn transmittance_follows_beer_lambert_law() {
+        let target = test_medium();
+        assert!(target.transmittance(&test_ray(), 0.0, 550.0) == 1.0);
+        assert!(
+            (target.transmittance(&test_ray(), 2.0, 550.0) - (-2.0f64).exp()).abs() < 0.000000001
+        );
+    }
+
+    #[test]
+    fn fraction_of_rays_scattered_matches_transmittance() {
+        let target = test_medium();
+        let mut rng = StdRng::seed_from_u64(0);
+        let unscattered = (0..10000)
+            .filter(|_| {
+                target
+                    .sample_scattering(&test_ray(), 1.5, 550.0, &mut rng)
+                    .is_none()
+            })
+            .count();
+        let expected = target.transmittance(&test_ray(), 1.5, 550.0) * 10000.0;
+        assert!((unscattered as f64 - expected).abs() < 200.0);
+    }
+
+    #[test]
+    fn scattering_weight_is_albedo() {
+        let target = test_medium();
+        let mut rng = StdRng::seed_from_u64(0);
+        let scattering = target
+            .sample_scattering(&test_ray(), 1000.0, 550.0, &mut rng)
+            .unwrap();
+        assert!(scattering.distance < 1000.0);
+        assert!((scattering.weight - 0.75).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn empty_medium_never_scatters() {
+        let target = HomogeneousMedium {
+            absorption: Spectrum::grey(0.0),
+            scattering: Spectrum::grey(0.0),
+            phase_function: HenyeyGreenstein::new(0.0),
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(target
+            .sample_scattering(&test_ray(), 1000.0, 550.0, &mut rng)
+            .is_none());
+        assert!(target.transmittance(&test_ray(), 1000.0, 550.0) == 1.0);
+    }
+}
@@ -0,0 +1,69 @@
+//! Participating media, such as fog, smoke or the interior of tinted glass
+//!
+//! A medium absorbs and scatters light as it travels through the volume it fills, rather
+//! than only at surfaces. The whole scene can be filled with a medium (see
+//! [Scene::medium](crate::scene::Scene::medium)), and primitives can be given an interior
+//! medium with [MediumBoundary](crate::materials::MediumBoundary).
+
+use crate::math::Vec3;
+use crate::raycasting::Ray;
+
+use rand::RngCore;
+
+use std::fmt::Debug;
+
+pub mod grid_medium;
+pub use grid_medium::GridMedium;
+
+pub mod henyey_greenstein;
+pub use henyey_greenstein::HenyeyGreenstein;
+
+pub mod homogeneous_medium;
+pub use homogeneous_medium::HomogeneousMedium;
+
+/// The angular distribution of light scattered within a medium
+pub trait PhaseFunction: Debug + Send + Sync {
+    /// Probability density of light travelling in `direction` being scattered into
+    /// `scattered_direction`, per steradian
+    fn value(&self, direction: &Vec3, scattered_direction: &Vec3) -> f64;
+
+    /// Choose a scattered direction for light travelling in `direction`
+    ///
+    /// Directions are chosen with probability density equal to
+    /// [value()](PhaseFunction::value), so no further weighting is needed.
+    fn sample(&self, direction: &Vec3, rng: &mut dyn RngCore) -> Vec3;
+}
+
+/// A point where a ray travelling through a medium is scattered
+#[derive(Clone, Debug)]
+pub struct MediumScattering {
+    /// Distance along the ray to the scattering event
+    pub distance: f64,
+
+    /// Weight to apply to the light arriving at the scattering event
+    ///
+    /// This is the ratio of the transmittance to the probability density of choosing the
+    /// event, multiplied by the scattering coefficient.
+    pub weight: f64,
+}
+
+pub trait Medium: Debug + Send + Sync {
+    /// Fraction of light at `wavelength` that travels `distance` along `ray` through the
+    /// medium without being absorbed or scattered
+    fn transmittance(&self, ray: &Ray, distance: f64, wavelength: f64) -> f64;
+
+    /// Choose where `ray` is scattered before travelling `max_distance` through the medium
+    ///
+    /// Returns `None` if the ray reaches `max_distance` without being scattered. In that
+    /// case the probability of getting this far equals the transmittance, so the light
+    /// arriving from beyond needs no further weighting.
+    fn sample_scattering(
+        &self,
+        ray: &Ray,
+        max_distance: f64,
+        wavelength: f64,
+        rng: &mut dyn RngCore,
+    ) -> Option<MediumScattering>;
+
+    fn phase_function(&self) -> &dyn PhaseFunction;
+}
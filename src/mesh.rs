@@ -1,91 +1,1036 @@
 /// Load a model from a Wavefront .obj file
 mod wavefront_obj {
-    use crate::materials::Material;
-    use crate::math::Vec3;
-    use crate::raycasting::{Primitive, Triangle};
+    use crate::colour::{ColourRgbF, Spectrum};
+    use crate::error::VanrijnError;
+    use crate::materials::{
+        EmissiveMaterial, LambertianMaterial, Material, PhongMaterial, ReflectiveMaterial,
+        SmoothTransparentDialectric,
+    };
+    use crate::math::{Vec2, Vec3};
+    use crate::raycasting::{
+        BilinearPatch, MeshBuffers, MeshFace, MeshTriangle, Primitive, TriangleMesh,
+    };
+    use crate::textures::{ImageTexture, OpacityMask};
 
     use obj::{IndexTuple, Obj, SimplePolygon};
 
-    use std::io::Result;
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader, Error, ErrorKind};
     use std::path::Path;
     use std::sync::Arc;
 
-    fn get_vertex_and_normal(
-        index_tuple: &IndexTuple,
-        vertex_positions: &[[f32; 3]],
-        normal_positions: &[[f32; 3]],
-    ) -> (Vec3, Vec3) {
-        let &IndexTuple(vertex_index, _, maybe_normal_index) = index_tuple;
-        (
-            {
-                let vertex_coords = &vertex_positions[vertex_index];
-                Vec3::new(
-                    vertex_coords[0] as f64,
-                    vertex_coords[1] as f64,
-                    vertex_coords[2] as f64,
-                )
-            },
-            match maybe_normal_index {
-                Some(normal_index) => {
-                    let normal_coords = &normal_positions[normal_index];
-                    Vec3::new(
-                        normal_coords[0] as f64,
-                        normal_coords[1] as f64,
-                        normal_coords[2] as f64,
-                    )
+    /// Texture coordinates for the corners of faces that don't have any
+    fn default_uvs() -> [Vec2; 3] {
+        [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(0.0, 1.0),
+        ]
+    }
+
+    fn to_vec3(coords: &[f32; 3]) -> Vec3 {
+        Vec3::new(coords[0] as f64, coords[1] as f64, coords[2] as f64)
+    }
+
+    fn to_colour(colour: &[f32; 3]) -> ColourRgbF {
+        ColourRgbF::new(colour[0] as f64, colour[1] as f64, colour[2] as f64)
+    }
+
+    fn to_spectrum(colour: &[f32; 3]) -> Spectrum {
+        Spectrum::reflection_from_linear_rgb(&to_colour(colour))
+    }
+
+    /// Convert a material from a .mtl file into the closest equivalent
+    ///
+    /// Texture filenames are relative to `directory`, and only the diffuse map is used.
+    fn convert_material(
+        mtl: &obj::Material,
+        directory: &Path,
+    ) -> Result<Arc<dyn Material>, VanrijnError> {
+        if let Some(ke) = mtl.ke.filter(|ke| ke.iter().any(|&e| e > 0.0)) {
+            return Ok(Arc::new(EmissiveMaterial {
+                emission: Spectrum::emission_from_linear_rgb(&to_colour(&ke)),
+            }));
+        }
+        let kd = mtl.kd.unwrap_or([1.0, 1.0, 1.0]);
+        let ks = mtl.ks.unwrap_or([0.0, 0.0, 0.0]);
+        let specular_strength = ks.iter().fold(0.0f32, |a, &b| a.max(b)) as f64;
+        Ok(match mtl.illum {
+            // Refraction and glass illumination models
+            Some(4) | Some(6) | Some(7) | Some(9) => Arc::new(SmoothTransparentDialectric::new(
+                Spectrum::grey(mtl.ni.unwrap_or(1.5) as f64),
+            )),
+            // Mirror-like reflection
+            Some(3) | Some(5) | Some(8) => Arc::new(ReflectiveMaterial {
+                colour: to_spectrum(&kd),
+                diffuse_strength: 1.0 - specular_strength,
+                reflection_strength: specular_strength,
+            }),
+            _ => match &mtl.map_kd {
+                Some(map_kd) => Arc::new(LambertianMaterial {
+                    colour: ImageTexture::read_png(&directory.join(map_kd))?,
+                    diffuse_strength: 1.0,
+                }),
+                None if specular_strength > 0.0 && mtl.illum != Some(1) => {
+                    Arc::new(PhongMaterial {
+                        colour: to_spectrum(&kd),
+                        diffuse_strength: 1.0,
+                        specular_strength,
+                        smoothness: mtl.ns.unwrap_or(1.0) as f64,
+                    })
                 }
-                None => Vec3::zeros(),
+                None => Arc::new(LambertianMaterial {
+                    colour: to_spectrum(&kd),
+                    diffuse_strength: 1.0,
+                }),
             },
-        )
+        })
+    }
+
+    /// A triangle from a .obj file
+    struct Face {
+        vertices: [IndexTuple; 3],
+        material_index: usize,
+
+        /// Texture coordinates for the corners if the file doesn't give any
+        default_uvs: [Vec2; 3],
+
+        /// Whether this is half of a quad that is kept whole, in which case the other half
+        /// is the next face
+        is_quad_half: bool,
+
+        /// Faces only share smoothed vertex normals with faces in the same group, and faces
+        /// with no group get flat normals
+        smoothing_group: Option<u32>,
+    }
+
+    /// The smoothing group of each polygon in the file, in order
+    ///
+    /// The obj crate ignores smoothing groups, so they're read separately. Returns `None`
+    /// if the file doesn't contain any smoothing group statements.
+    fn read_smoothing_groups(filename: &Path) -> Result<Option<Vec<Option<u32>>>, Error> {
+        let mut found_smoothing_group = false;
+        let mut current_group = None;
+        let mut groups = Vec::new();
+        for line in BufReader::new(File::open(filename)?).lines() {
+            let line = line?;
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("f") => groups.push(current_group),
+                Some("s") => {
+                    found_smoothing_group = true;
+                    current_group = match words.next() {
+                        Some("off") | Some("0") | None => None,
+                        Some(group) => Some(group.parse().map_err(|_| {
+                            Error::new(
+                                ErrorKind::InvalidData,
+                                format!("Invalid smoothing group: {}", line),
+                            )
+                        })?),
+                    };
+                }
+                _ => (),
+            }
+        }
+        Ok(if found_smoothing_group {
+            Some(groups)
+        } else {
+            None
+        })
+    }
+
+    /// Vertex normals for each corner of each face
+    ///
+    /// Each vertex normal is the average of the normals of the faces around the vertex
+    /// position that share its smoothing group, weighted by the angle each face makes at
+    /// the vertex. This is less sensitive to how the surface happens to be triangulated than
+    /// weighting by area.
+    fn smooth_normals(positions: &[Vec3], faces: &[Face]) -> Vec<[Vec3; 3]> {
+        let face_normal = |face: &Face| {
+            let [a, b, c] = [0, 1, 2].map(|i| positions[face.vertices[i].0]);
+            let normal = (b - a).cross(&(c - a));
+            if normal.norm_squared() > 0.0 {
+                normal.normalize()
+            } else {
+                normal
+            }
+        };
+        let mut normal_sums = HashMap::new();
+        for face in faces.iter() {
+            if let Some(group) = face.smoothing_group {
+                let normal = face_normal(face);
+                for i in 0..3 {
+                    let corner = positions[face.vertices[i].0];
+                    let to_next = positions[face.vertices[(i + 1) % 3].0] - corner;
+                    let to_previous = positions[face.vertices[(i + 2) % 3].0] - corner;
+                    if to_next.norm_squared() == 0.0 || to_previous.norm_squared() == 0.0 {
+                        continue;
+                    }
+                    let angle = to_next
+                        .normalize()
+                        .dot(&to_previous.normalize())
+                        .clamp(-1.0, 1.0)
+                        .acos();
+                    *normal_sums
+                        .entry((face.vertices[i].0, group))
+                        .or_insert_with(Vec3::zeros) += normal * angle;
+                }
+            }
+        }
+        faces
+            .iter()
+            .map(|face| match face.smoothing_group {
+                Some(group) => [0, 1, 2].map(|i| {
+                    let normal = normal_sums
+                        .get(&(face.vertices[i].0, group))
+                        .copied()
+                        .unwrap_or_else(Vec3::zeros);
+                    if normal.norm_squared() > 0.0 {
+                        normal.normalize()
+                    } else {
+                        normal
+                    }
+                }),
+                None => [face_normal(face); 3],
+            })
+            .collect()
+    }
+
+    /// Triangulate a polygon, assuming it is convex
+    fn fan_triangulate(polygon: &SimplePolygon) -> impl Iterator<Item = [IndexTuple; 3]> + '_ {
+        polygon
+            .iter()
+            .skip(1)
+            .zip(polygon.iter().skip(2))
+            .map(move |(&v1, &v2)| [polygon[0], v1, v2])
+    }
+
+    /// A quad from a .obj file that is kept whole, with its corners in order around the edge
+    struct Quad {
+        vertices: [u32; 4],
+        material: u32,
     }
 
-    fn get_triangles(
-        polygon: &SimplePolygon,
-        vertex_positions: &[[f32; 3]],
-        normal_positions: &[[f32; 3]],
+    /// Read a .obj file, along with any .mtl files it references, into indexed buffers
+    ///
+    /// Corners of different faces that have the same position, normal and texture
+    /// coordinates share a single vertex. If `preserve_quads` is set, quads are returned
+    /// separately, and only the other faces are triangulated.
+    fn read_obj(
+        filename: &Path,
         material: Arc<dyn Material>,
-    ) -> Vec<Triangle> {
-        if let Some(v0_index) = polygon.iter().next() {
-            let (v0_vertex, v0_normal) =
-                get_vertex_and_normal(v0_index, &vertex_positions, normal_positions);
-            polygon
-                .iter()
-                .skip(1)
-                .zip(polygon.iter().skip(2))
-                .map(|(v1_index, v2_index)| {
-                    let (v1_vertex, v1_normal) =
-                        get_vertex_and_normal(v1_index, vertex_positions, normal_positions);
-                    let (v2_vertex, v2_normal) =
-                        get_vertex_and_normal(v2_index, vertex_positions, normal_positions);
-                    let vertices = [v0_vertex, v1_vertex, v2_vertex];
-                    let normals = [v0_normal, v1_normal, v2_normal];
-                    Triangle {
+        preserve_quads: bool,
+    ) -> Result<(MeshBuffers, Vec<Quad>), VanrijnError> {
+        let mut obj = Obj::<SimplePolygon>::load(filename).map_err(VanrijnError::mesh(filename))?;
+        obj.load_mtls()
+            .map_err(|errors| {
+                let (mtl_filename, error) = &errors[0];
+                Error::new(
+                    error.kind(),
+                    format!("Couldn't load {}: {}", mtl_filename, error),
+                )
+            })
+            .map_err(VanrijnError::mesh(filename))?;
+
+        let smoothing_groups =
+            read_smoothing_groups(filename).map_err(VanrijnError::mesh(filename))?;
+        let mut polygon_index = 0;
+        let mut materials = vec![material];
+        let mut opacity = vec![None];
+        let mut material_indices = HashMap::new();
+        let mut faces = Vec::new();
+        for group in obj.objects.iter().flat_map(|object| object.groups.iter()) {
+            let material_index = match &group.material {
+                Some(mtl) => match material_indices.get(&mtl.name) {
+                    Some(&index) => index,
+                    None => {
+                        materials.push(convert_material(mtl, &obj.path)?);
+                        opacity.push(match &mtl.map_d {
+                            Some(map_d) => {
+                                Some(Arc::new(OpacityMask::read_png(&obj.path.join(map_d), 0.5)?))
+                            }
+                            None => None,
+                        });
+                        material_indices.insert(mtl.name.clone(), materials.len() - 1);
+                        materials.len() - 1
+                    }
+                },
+                None => 0,
+            };
+            for polygon in &group.polys {
+                // Without any smoothing group statements everything is in one group
+                let smoothing_group = match &smoothing_groups {
+                    Some(groups) => groups.get(polygon_index).copied().flatten(),
+                    None => Some(0),
+                };
+                polygon_index += 1;
+                if preserve_quads && polygon.len() == 4 {
+                    // Split the same way as fan_triangulate(), so that smoothing treats quads
+                    // like any other face
+                    let uvs = BilinearPatch::default_uvs();
+                    let halves = [
+                        ([0, 1, 2], [uvs[0], uvs[1], uvs[2]]),
+                        ([0, 2, 3], [uvs[0], uvs[2], uvs[3]]),
+                    ];
+                    faces.extend(
+                        IntoIterator::into_iter(halves).map(|(corners, default_uvs)| Face {
+                            vertices: corners.map(|corner| polygon[corner]),
+                            material_index,
+                            default_uvs,
+                            is_quad_half: true,
+                            smoothing_group,
+                        }),
+                    );
+                } else {
+                    faces.extend(fan_triangulate(polygon).map(|vertices| Face {
                         vertices,
-                        normals,
-                        material: material.clone(),
+                        material_index,
+                        default_uvs: default_uvs(),
+                        is_quad_half: false,
+                        smoothing_group,
+                    }));
+                }
+            }
+        }
+
+        let positions: Vec<Vec3> = obj.position.iter().map(to_vec3).collect();
+        let smoothed_normals = smooth_normals(&positions, &faces);
+        let mut buffers = MeshBuffers {
+            materials,
+            opacity,
+            ..MeshBuffers::default()
+        };
+        let mut vertex_indices = HashMap::new();
+        let mut quads = Vec::new();
+        let mut first_quad_half: Option<[u32; 3]> = None;
+        for (face, smoothed_normals) in faces.iter().zip(smoothed_normals.iter()) {
+            let has_uvs = face.vertices.iter().all(|vertex| vertex.1.is_some());
+            let vertices = [0, 1, 2].map(|i| {
+                let IndexTuple(position_index, uv_index, normal_index) = face.vertices[i];
+                let normal =
+                    normal_index.map_or(smoothed_normals[i], |index| to_vec3(&obj.normal[index]));
+                let uv = match uv_index.filter(|_| has_uvs) {
+                    Some(index) => {
+                        let uv = obj.texture[index];
+                        Vec2::new(uv[0] as f64, uv[1] as f64)
                     }
-                })
-                .collect()
-        } else {
-            vec![]
+                    None => face.default_uvs[i],
+                };
+                let key = (
+                    position_index,
+                    [
+                        normal.x().to_bits(),
+                        normal.y().to_bits(),
+                        normal.z().to_bits(),
+                        uv.x().to_bits(),
+                        uv.y().to_bits(),
+                    ],
+                );
+                *vertex_indices
+                    .entry(key)
+                    .or_insert_with(|| buffers.push_vertex(positions[position_index], normal, uv))
+            });
+            if !face.is_quad_half {
+                buffers.faces.push(MeshFace {
+                    vertices,
+                    material: face.material_index as u32,
+                });
+            } else if let Some([a, b, c]) = first_quad_half.take() {
+                quads.push(Quad {
+                    vertices: [a, b, c, vertices[2]],
+                    material: face.material_index as u32,
+                });
+            } else {
+                first_quad_half = Some(vertices);
+            }
         }
+        Ok((buffers, quads))
     }
 
+    /// Load a .obj file, along with any .mtl files it references
+    ///
+    /// Faces that have a material in a .mtl file use that material, and all other faces
+    /// use `material`. Materials with an alpha map (`map_d`) cut away the faces that use
+    /// them wherever its alpha is below one half. Vertex normals and texture coordinates are read from the file
+    /// when present. Missing normals are replaced with smoothed vertex normals, respecting
+    /// any smoothing groups in the file; files without smoothing groups are smoothed
+    /// everywhere.
+    ///
+    /// Each face is a [MeshTriangle](MeshTriangle) that shares the vertices of the whole
+    /// mesh.
     pub fn load_obj(
         filename: &Path,
         material: Arc<dyn Material>,
-    ) -> Result<Vec<Arc<dyn Primitive>>> {
-        let obj = Obj::<SimplePolygon>::load(filename)?;
+    ) -> Result<Vec<Arc<dyn Primitive>>, VanrijnError> {
+        let (buffers, _) = read_obj(filename, material, false)?;
+        Ok(MeshTriangle::all_faces(&Arc::new(buffers)))
+    }
+
+    /// Load a .obj file in the same way as [load_obj()](load_obj), except that quads are
+    /// kept whole
+    ///
+    /// Each quad becomes a [BilinearPatch](BilinearPatch), which interpolates normals and
+    /// texture coordinates smoothly across the whole face, rather than separately over two
+    /// triangles. Faces with more than four sides are still triangulated.
+    pub fn load_obj_preserving_quads(
+        filename: &Path,
+        material: Arc<dyn Material>,
+    ) -> Result<Vec<Arc<dyn Primitive>>, VanrijnError> {
+        let (buffers, quads) = read_obj(filename, material, true)?;
+        let patches: Vec<Arc<dyn Primitive>> = quads
+            .iter()
+            .map(|quad| {
+                let corners = quad.vertices.map(|i| i as usize);
+                Arc::new(BilinearPatch {
+                    vertices: corners.map(|i| buffers.position(i)),
+                    normals: corners.map(|i| buffers.normal(i)),
+                    uvs: corners.map(|i| buffers.uvs[i]),
+                    material: Arc::clone(&buffers.materials[quad.material as usize]),
+                    opacity: buffers
+                        .opacity
+                        .get(quad.material as usize)
+                        .cloned()
+                        .flatten(),
+                }) as Arc<dyn Primitive>
+            })
+            .collect();
+        let mut primitives = MeshTriangle::all_faces(&Arc::new(buffers));
+        primitives.extend(patches);
+        Ok(primitives)
+    }
+
+    /// Load a .obj file into indexed buffers, in the same way as [load_obj()](load_obj)
+    ///
+    /// This is for meshes that need more work, such as [subdivision](super::subdivide),
+    /// before they're turned into a [TriangleMesh](TriangleMesh).
+    pub fn load_mesh_buffers(
+        filename: &Path,
+        material: Arc<dyn Material>,
+    ) -> Result<MeshBuffers, VanrijnError> {
+        let (buffers, _) = read_obj(filename, material, false)?;
+        Ok(buffers)
+    }
+
+    /// Load a .obj file as a [TriangleMesh](TriangleMesh), in the same way as
+    /// [load_obj()](load_obj)
+    pub fn load_triangle_mesh(
+        filename: &Path,
+        material: Arc<dyn Material>,
+    ) -> Result<TriangleMesh, VanrijnError> {
+        Ok(TriangleMesh::new(load_mesh_buffers(filename, material)?))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use crate::raycasting::{IntersectionInfo, Ray};
+
+        use std::fs;
+        use std::path::PathBuf;
+
+        /// Write the files into a fresh temporary directory and return its path
+        fn write_test_files(test_name: &str, files: &[(&str, &str)]) -> PathBuf {
+            let directory = std::env::temp_dir().join(format!(
+                "vanrijn-mesh-{}-{}",
+                test_name,
+                std::process::id()
+            ));
+            fs::create_dir_all(&directory).unwrap();
+            for (name, contents) in files {
+                fs::write(directory.join(name), contents).unwrap();
+            }
+            directory
+        }
+
+        fn hit(primitives: &[Arc<dyn Primitive>], ray: &Ray) -> IntersectionInfo {
+            primitives
+                .iter()
+                .find_map(|primitive| primitive.intersect(ray))
+                .unwrap()
+        }
+
+        const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+        #[test]
+        fn quads_are_triangulated() {
+            let directory =
+                write_test_files("quads", &[("quad.obj", &format!("{}f 1 2 3 4\n", SQUARE))]);
+            let primitives = load_obj(
+                &directory.join("quad.obj"),
+                Arc::new(LambertianMaterial::new_dummy()),
+            )
+            .unwrap();
+            assert!(primitives.len() == 2);
+        }
+
+        #[test]
+        fn quads_can_be_kept_whole() {
+            // One corner of the quad is raised, so it isn't flat and isn't split along a
+            // diagonal. The pentagon is still triangulated.
+            let directory = write_test_files(
+                "preserved-quads",
+                &[(
+                    "quad.obj",
+                    "v 0 0 0\nv 1 0 0\nv 1 1 1\nv 0 1 0\n\
+                     v 3 0 0\nv 4 0 0\nv 4 1 0\nv 3.5 2 0\nv 3 1 0\n\
+                     f 1 2 3 4\nf 5 6 7 8 9\n",
+                )],
+            );
+            let primitives = load_obj_preserving_quads(
+                &directory.join("quad.obj"),
+                Arc::new(LambertianMaterial::new_dummy()),
+            )
+            .unwrap();
+            assert!(primitives.len() == 4);
+            let info = hit(
+                &primitives,
+                &Ray::new(Vec3::new(0.5, 0.5, 2.0), -Vec3::unit_z()),
+            );
+            assert!((info.location.z() - 0.25).abs() < 0.000_001);
+            assert!((info.uv.x() - 0.5).abs() < 0.000_001);
+            assert!((info.uv.y() - 0.5).abs() < 0.000_001);
+        }
+
+        #[test]
+        fn shared_corners_are_stored_once() {
+            let directory = write_test_files(
+                "shared-vertices",
+                &[(
+                    "quad.obj",
+                    &format!("{}vn 0 0 1\nf 1//1 2//1 3//1 4//1\n", SQUARE),
+                )],
+            );
+            let mesh = load_triangle_mesh(
+                &directory.join("quad.obj"),
+                Arc::new(LambertianMaterial::new_dummy()),
+            )
+            .unwrap();
+            let buffers = mesh.buffers();
+            assert!(buffers.faces.len() == 2);
+            // The corners on the diagonal get the default texture coordinates of different
+            // corners of each triangle, so only the first is shared
+            assert!(buffers.positions.len() == 5);
+            assert!(buffers.faces[0].vertices[0] == buffers.faces[1].vertices[0]);
+        }
+
+        #[test]
+        fn missing_normals_are_computed() {
+            let directory = write_test_files(
+                "normals",
+                &[("quad.obj", &format!("{}f 1 2 3 4\n", SQUARE))],
+            );
+            let primitives = load_obj(
+                &directory.join("quad.obj"),
+                Arc::new(LambertianMaterial::new_dummy()),
+            )
+            .unwrap();
+            let info = hit(
+                &primitives,
+                &Ray::new(Vec3::new(0.25, 0.75, -1.0), Vec3::unit_z()),
+            );
+            assert!((info.normal - Vec3::unit_z()).norm() < 0.000000001);
+        }
+
+        #[test]
+        fn smoothed_normals_are_weighted_by_angle() {
+            let positions = [
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(10.0, 0.0, 0.0),
+                Vec3::new(10.0, 0.5, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+            ];
+            let face = |a, b, c| Face {
+                vertices: [a, b, c].map(|index| IndexTuple(index, None, None)),
+                material_index: 0,
+                default_uvs: default_uvs(),
+                is_quad_half: false,
+                smoothing_group: Some(0),
+            };
+            // A large face with a narrow angle at the origin, facing +Z, and a small face with
+            // a right angle at the origin, facing +X
+            let normals = smooth_normals(&positions, &[face(0, 1, 2), face(0, 3, 4)]);
+            let at_origin = normals[1][0];
+            assert!(at_origin.x() > 0.9);
+            assert!(at_origin.z() > 0.0 && at_origin.z() < 0.1);
+        }
+
+        const FOLD: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 0 1 1\n";
+
+        fn normal_near_fold(test_name: &str, smoothing: (&str, &str)) -> Vec3 {
+            let data = format!(
+                "{}{}\nf 1 2 3 4\n{}\nf 1 4 6 5\n",
+                FOLD, smoothing.0, smoothing.1
+            );
+            let directory = write_test_files(test_name, &[("fold.obj", &data)]);
+            let primitives = load_obj(
+                &directory.join("fold.obj"),
+                Arc::new(LambertianMaterial::new_dummy()),
+            )
+            .unwrap();
+            hit(
+                &primitives,
+                &Ray::new(Vec3::new(0.1, 0.5, -1.0), Vec3::unit_z()),
+            )
+            .normal
+        }
+
+        #[test]
+        fn faces_in_same_smoothing_group_share_normals() {
+            let normal = normal_near_fold("same-group", ("s 1", "s 1"));
+            assert!(normal.x() > 0.1);
+        }
+
+        #[test]
+        fn faces_in_different_smoothing_groups_do_not_share_normals() {
+            let normal = normal_near_fold("different-groups", ("s 1", "s 2"));
+            assert!((normal - Vec3::unit_z()).norm() < 0.000000001);
+        }
+
+        #[test]
+        fn faces_with_smoothing_off_are_flat() {
+            let normal = normal_near_fold("smoothing-off", ("s off", "s 1"));
+            assert!((normal - Vec3::unit_z()).norm() < 0.000000001);
+        }
+
+        #[test]
+        fn files_without_smoothing_groups_are_smoothed() {
+            let normal = normal_near_fold("no-groups", ("", ""));
+            assert!(normal.x() > 0.1);
+        }
+
+        #[test]
+        fn normals_and_uvs_are_read_from_file() {
+            let data = format!(
+                "{}vn 0 0 -1\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1/1 2/2/1 3/3/1 4/4/1\n",
+                SQUARE
+            );
+            let directory = write_test_files("uvs", &[("quad.obj", &data)]);
+            let primitives = load_obj(
+                &directory.join("quad.obj"),
+                Arc::new(LambertianMaterial::new_dummy()),
+            )
+            .unwrap();
+            let info = hit(
+                &primitives,
+                &Ray::new(Vec3::new(0.25, 0.75, -1.0), Vec3::unit_z()),
+            );
+            assert!((info.normal + Vec3::unit_z()).norm() < 0.000000001);
+            assert!((info.uv.x() - 0.25).abs() < 0.000000001);
+            assert!((info.uv.y() - 0.75).abs() < 0.000000001);
+        }
+
+        #[test]
+        fn faces_use_materials_from_mtl_file() {
+            let data = format!(
+                "mtllib test.mtl\n{}usemtl light\nf 1 2 3\nusemtl\nf 1 3 4\n",
+                SQUARE
+            );
+            let mtl = "newmtl light\nKe 2 2 2\n";
+            let directory = write_test_files("mtl", &[("quad.obj", &data), ("test.mtl", mtl)]);
+            let primitives = load_obj(
+                &directory.join("quad.obj"),
+                Arc::new(LambertianMaterial::new_dummy()),
+            )
+            .unwrap();
+            let photon = crate::colour::Photon {
+                wavelength: 550.0,
+                intensity: 0.0,
+            };
+            let emissive = hit(
+                &primitives,
+                &Ray::new(Vec3::new(0.75, 0.25, -1.0), Vec3::unit_z()),
+            );
+            assert!(
+                emissive
+                    .material
+                    .emission(&Vec3::unit_z(), &photon)
+                    .intensity
+                    > 1.0
+            );
+            let expected = Spectrum::emission_from_linear_rgb(&ColourRgbF::new(2.0, 2.0, 2.0));
+            for wavelength in [450.0, 550.0, 650.0] {
+                let photon = crate::colour::Photon {
+                    wavelength,
+                    intensity: 0.0,
+                };
+                assert!(
+                    (emissive
+                        .material
+                        .emission(&Vec3::unit_z(), &photon)
+                        .intensity
+                        - expected.intensity_at_wavelength(wavelength))
+                    .abs()
+                        < 1e-12
+                );
+            }
+            let default = hit(
+                &primitives,
+                &Ray::new(Vec3::new(0.25, 0.75, -1.0), Vec3::unit_z()),
+            );
+            assert!(
+                default
+                    .material
+                    .emission(&Vec3::unit_z(), &photon)
+                    .intensity
+                    == 0.0
+            );
+        }
+
+        #[test]
+        fn missing_mtl_file_is_an_error() {
+            let data = format!("mtllib missing.mtl\n{}f 1 2 3\n", SQUARE);
+            let directory = write_test_files("missing-mtl", &[("quad.obj", &data)]);
+            assert!(matches!(
+                load_obj(
+                    &directory.join("quad.obj"),
+                    Arc::new(LambertianMaterial::new_dummy()),
+                ),
+                Err(VanrijnError::Mesh { .. })
+            ));
+        }
+    }
+}
+
+pub use wavefront_obj::{
+    load_mesh_buffers, load_obj, load_obj_preserving_quads, load_triangle_mesh,
+};
+
+/// Smoothing and displacing triangle meshes before they're rendered
+mod subdivision {
+    use crate::math::{StoredVec3, Vec3};
+    use crate::raycasting::{MeshBuffers, MeshFace};
+    use crate::textures::Texture;
+
+    use std::collections::{BTreeMap, HashMap};
+
+    /// The same index for every vertex at the same position
+    ///
+    /// Vertices are split wherever the normals or texture coordinates change, such as
+    /// along texture seams, but the surface has to be treated as connected there, or it
+    /// would come apart.
+    fn weld_positions(positions: &[StoredVec3]) -> (Vec<usize>, Vec<Vec3>) {
+        let mut indices = HashMap::new();
+        let mut points = Vec::new();
+        let welded = positions
+            .iter()
+            .map(|&position| {
+                let position = Vec3::from(position);
+                let key = [position.x(), position.y(), position.z()].map(f64::to_bits);
+                *indices.entry(key).or_insert_with(|| {
+                    points.push(position);
+                    points.len() - 1
+                })
+            })
+            .collect();
+        (welded, points)
+    }
+
+    fn edge_key<T: Ord>(a: T, b: T) -> (T, T) {
+        if a < b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    /// Replace every vertex normal with the area-weighted average of the normals of the
+    /// faces around its position
+    fn recompute_normals(buffers: &mut MeshBuffers) {
+        let (welded, points) = weld_positions(&buffers.positions);
+        let mut normal_sums = vec![Vec3::zeros(); points.len()];
+        for face in &buffers.faces {
+            let [a, b, c] = face.vertices.map(|i| buffers.position(i as usize));
+            // The cross product's length is twice the area, which gives the weighting
+            let normal = (b - a).cross(&(c - a));
+            for &i in &face.vertices {
+                normal_sums[welded[i as usize]] += normal;
+            }
+        }
+        for (normal, &point) in buffers.normals.iter_mut().zip(welded.iter()) {
+            if normal_sums[point].norm_squared() > 0.0 {
+                *normal = normal_sums[point].normalize().into();
+            }
+        }
+    }
+
+    /// One level of Loop subdivision, which splits each triangle into four
+    fn subdivide_once(buffers: &MeshBuffers) -> MeshBuffers {
+        let (welded, points) = weld_positions(&buffers.positions);
+        // The corners opposite each edge, which is shared by two faces unless it's on the
+        // boundary
+        let mut opposite_corners: BTreeMap<(usize, usize), Vec<usize>> = BTreeMap::new();
+        for face in &buffers.faces {
+            let corners = face.vertices.map(|i| welded[i as usize]);
+            for i in 0..3 {
+                opposite_corners
+                    .entry(edge_key(corners[i], corners[(i + 1) % 3]))
+                    .or_default()
+                    .push(corners[(i + 2) % 3]);
+            }
+        }
+        let mut neighbours = vec![Vec::new(); points.len()];
+        let mut boundary_neighbours = vec![Vec::new(); points.len()];
+        for (&(a, b), opposite) in &opposite_corners {
+            neighbours[a].push(b);
+            neighbours[b].push(a);
+            if opposite.len() == 1 {
+                boundary_neighbours[a].push(b);
+                boundary_neighbours[b].push(a);
+            }
+        }
+        let sum = |indices: &[usize]| {
+            indices
+                .iter()
+                .fold(Vec3::zeros(), |acc, &index| acc + points[index])
+        };
+        // Each existing point is moved towards its neighbours, using Warren's weights
+        let moved_points: Vec<Vec3> = (0..points.len())
+            .map(|index| {
+                let point = points[index];
+                match boundary_neighbours[index].len() {
+                    0 if !neighbours[index].is_empty() => {
+                        let n = neighbours[index].len();
+                        let beta = if n == 3 {
+                            3.0 / 16.0
+                        } else {
+                            3.0 / (8.0 * n as f64)
+                        };
+                        point * (1.0 - n as f64 * beta) + sum(&neighbours[index]) * beta
+                    }
+                    2 => point * 0.75 + sum(&boundary_neighbours[index]) * 0.125,
+                    // Isolated points and corners where several boundaries meet stay put
+                    _ => point,
+                }
+            })
+            .collect();
+        let edge_point = |a: usize, b: usize| match opposite_corners[&edge_key(a, b)].as_slice() {
+            &[c, d] => (points[a] + points[b]) * 0.375 + (points[c] + points[d]) * 0.125,
+            _ => (points[a] + points[b]) * 0.5,
+        };
+
+        let mut result = MeshBuffers {
+            positions: welded
+                .iter()
+                .map(|&point| moved_points[point].into())
+                .collect(),
+            normals: buffers.normals.clone(),
+            uvs: buffers.uvs.clone(),
+            faces: Vec::with_capacity(buffers.faces.len() * 4),
+            materials: buffers.materials.clone(),
+            opacity: buffers.opacity.clone(),
+        };
+        // Edges get a new vertex for each pair of vertices, rather than each pair of
+        // points, so that seams stay sharp in the normals and texture coordinates
+        let mut edge_vertices = HashMap::new();
+        let mut edge_vertex = |a: u32, b: u32| {
+            *edge_vertices.entry(edge_key(a, b)).or_insert_with(|| {
+                let (a, b) = (a as usize, b as usize);
+                result.push_vertex(
+                    edge_point(welded[a], welded[b]),
+                    (buffers.normal(a) + buffers.normal(b)) * 0.5,
+                    (buffers.uvs[a] + buffers.uvs[b]) * 0.5,
+                )
+            })
+        };
+        let mut faces = Vec::with_capacity(buffers.faces.len() * 4);
+        for face in &buffers.faces {
+            let [a, b, c] = face.vertices;
+            let (ab, bc, ca) = (edge_vertex(a, b), edge_vertex(b, c), edge_vertex(c, a));
+            faces.extend(
+                [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]].map(|vertices| MeshFace {
+                    vertices,
+                    material: face.material,
+                }),
+            );
+        }
+        result.faces = faces;
+        result
+    }
+
+    /// Smooth a mesh with `levels` rounds of Loop subdivision
+    ///
+    /// Each round splits every triangle into four and moves the vertices so that the mesh
+    /// approaches a smooth surface, so a coarse cage can be rendered without visible
+    /// facets. Creases in the cage are smoothed out as well, but boundaries are kept.
+    /// Texture coordinates are interpolated linearly, and vertex normals are recomputed
+    /// from the smoothed surface.
+    pub fn subdivide(buffers: &MeshBuffers, levels: u32) -> MeshBuffers {
+        if levels == 0 {
+            return buffers.clone();
+        }
+        let mut result = subdivide_once(buffers);
+        for _ in 1..levels {
+            result = subdivide_once(&result);
+        }
+        recompute_normals(&mut result);
+        result
+    }
 
-        Ok(obj
-            .objects
+    /// Move each vertex along its normal by `scale` times the value of `height` at its
+    /// surface coordinates
+    ///
+    /// The mesh should already be finely divided, with [subdivide()](subdivide) if
+    /// necessary, as detail smaller than its triangles is lost. Vertices at the same
+    /// position are all moved by their average displacement, so that seams don't open up.
+    /// Vertex normals are recomputed afterwards. The height is looked up in the middle of
+    /// the visible spectrum, so grey textures work as expected.
+    pub fn displace(buffers: &MeshBuffers, height: &dyn Texture, scale: f64) -> MeshBuffers {
+        let (welded, points) = weld_positions(&buffers.positions);
+        let mut displacement_sums = vec![(Vec3::zeros(), 0); points.len()];
+        for ((&point, &normal), uv) in welded
             .iter()
-            .flat_map(|object| object.groups.iter())
-            .flat_map(|group| group.polys.iter())
-            .flat_map(|poly| get_triangles(poly, &obj.position, &obj.normal, material.clone()))
-            .map(|triangle| Arc::new(triangle) as Arc<dyn Primitive>)
-            .collect())
+            .zip(buffers.normals.iter())
+            .zip(buffers.uvs.iter())
+        {
+            let (sum, count) = &mut displacement_sums[point];
+            *sum += Vec3::from(normal) * (scale * height.value(uv, 550.0));
+            *count += 1;
+        }
+        let mut result = MeshBuffers {
+            positions: welded
+                .iter()
+                .map(|&point| {
+                    let (sum, count) = displacement_sums[point];
+                    (points[point] + sum * (1.0 / count as f64)).into()
+                })
+                .collect(),
+            ..buffers.clone()
+        };
+        recompute_normals(&mut result);
+        result
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use crate::colour::Spectrum;
+        use crate::materials::LambertianMaterial;
+        use crate::math::Vec2;
+
+        use std::sync::Arc;
+
+        fn buffers(positions: Vec<Vec3>, faces: &[[u32; 3]]) -> MeshBuffers {
+            MeshBuffers {
+                normals: vec![Vec3::unit_z().into(); positions.len()],
+                uvs: positions
+                    .iter()
+                    .map(|position| Vec2::new(position.x(), position.y()))
+                    .collect(),
+                positions: positions.into_iter().map(StoredVec3::from).collect(),
+                faces: faces
+                    .iter()
+                    .map(|&vertices| MeshFace {
+                        vertices,
+                        material: 0,
+                    })
+                    .collect(),
+                materials: vec![Arc::new(LambertianMaterial::new_dummy())],
+                opacity: vec![None],
+            }
+        }
+
+        fn tetrahedron_corners() -> [Vec3; 4] {
+            [
+                Vec3::new(1.0, 1.0, 1.0),
+                Vec3::new(1.0, -1.0, -1.0),
+                Vec3::new(-1.0, 1.0, -1.0),
+                Vec3::new(-1.0, -1.0, 1.0),
+            ]
+        }
+
+        const TETRAHEDRON_FACES: [[u32; 3]; 4] = [[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]];
+
+        fn unit_square() -> MeshBuffers {
+            buffers(
+                vec![
+                    Vec3::new(0.0, 0.0, 0.0),
+                    Vec3::new(1.0, 0.0, 0.0),
+                    Vec3::new(1.0, 1.0, 0.0),
+                    Vec3::new(0.0, 1.0, 0.0),
+                ],
+                &[[0, 1, 2], [0, 2, 3]],
+            )
+        }
+
+        fn positions(buffers: &MeshBuffers) -> Vec<Vec3> {
+            buffers.positions.iter().map(|&p| p.into()).collect()
+        }
+
+        fn normals(buffers: &MeshBuffers) -> Vec<Vec3> {
+            buffers.normals.iter().map(|&n| n.into()).collect()
+        }
+
+        #[test]
+        fn each_level_splits_faces_into_four_and_rounds_off_corners() {
+            let target = subdivide(
+                &buffers(tetrahedron_corners().to_vec(), &TETRAHEDRON_FACES),
+                2,
+            );
+            assert!(target.faces.len() == 64);
+            let corner_distance = 3.0f64.sqrt();
+            assert!(positions(&target)
+                .iter()
+                .all(|position| position.norm() < corner_distance));
+            // Normals point outwards from the smoothed surface
+            assert!(positions(&target)
+                .iter()
+                .zip(normals(&target).iter())
+                .all(|(position, normal)| position.normalize().dot(normal) > 0.5));
+        }
+
+        #[test]
+        fn split_vertices_are_subdivided_like_shared_ones() {
+            let shared = subdivide(
+                &buffers(tetrahedron_corners().to_vec(), &TETRAHEDRON_FACES),
+                1,
+            );
+            // The same tetrahedron with separate corners for every face
+            let split = subdivide(
+                &buffers(
+                    TETRAHEDRON_FACES
+                        .iter()
+                        .flat_map(|face| face.map(|i| tetrahedron_corners()[i as usize]))
+                        .collect(),
+                    &[[0, 1, 2], [3, 4, 5], [6, 7, 8], [9, 10, 11]],
+                ),
+                1,
+            );
+            assert!(positions(&split).iter().all(|position| positions(&shared)
+                .iter()
+                .any(|other| (position - other).norm() < 0.000_000_001)));
+        }
+
+        #[test]
+        fn flat_mesh_stays_flat() {
+            let target = subdivide(&unit_square(), 3);
+            assert!(target.faces.len() == 128);
+            assert!(positions(&target)
+                .iter()
+                .all(|position| position.z() == 0.0));
+            assert!(normals(&target)
+                .iter()
+                .all(|normal| (*normal - Vec3::unit_z()).norm() < 0.000_001));
+            assert!(target
+                .uvs
+                .iter()
+                .all(|uv| (0.0..=1.0).contains(&uv.x()) && (0.0..=1.0).contains(&uv.y())));
+        }
+
+        #[test]
+        fn displacement_moves_vertices_along_normals() {
+            let target = displace(&subdivide(&unit_square(), 1), &Spectrum::grey(0.5), 2.0);
+            assert!(positions(&target)
+                .iter()
+                .all(|position| (position.z() - 1.0).abs() < 0.000_001));
+            assert!(normals(&target)
+                .iter()
+                .all(|normal| (*normal - Vec3::unit_z()).norm() < 0.000_001));
+        }
     }
 }
 
-pub use wavefront_obj::load_obj;
+pub use subdivision::{displace, subdivide};
@@ -0,0 +1,418 @@
+//! Recording what happens along a single path, for diagnosing pixels that look wrong
+//!
+//! [trace_pixel()](trace_pixel) retraces one sample of one pixel exactly as
+//! the renderer took it, and returns a [PathLog](PathLog) with a
+//! [BounceRecord](BounceRecord) for every surface the path met. Recording is only switched
+//! on for the thread doing the retracing, so it costs next to nothing in normal renders.
+//!
+//! [trace_light_paths()](trace_light_paths) does the same for every sample
+//! of every pixel, but keeps only where each path met a surface, as
+//! [LightPathPoints](LightPathPoints). These can be written out as a point cloud, or as a
+//! density image looking down on the scene, to show where light travels through it.
+//!
+//! Only the [SimpleRandomIntegrator](crate::integrators::SimpleRandomIntegrator) records
+//! bounces; with other integrators the log only has the camera ray and the result.
+
+use crate::camera::{pixel_rng, ImageSampler, RenderSettings};
+use crate::colour::{Photon, PhotonPacket};
+use crate::image::ImageGreyU16;
+use crate::math::Vec3;
+use crate::raycasting::Ray;
+use crate::sampler::{Sampler, SceneSampler};
+use crate::scene::Scene;
+
+use rand::rngs::StdRng;
+
+use rayon::prelude::*;
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+
+/// What happened where a path met a surface
+///
+/// Directions are in world space, and all the light values are for the wavelength of the
+/// packet's hero photon.
+#[derive(Clone, Debug)]
+pub struct BounceRecord {
+    /// Where the path hit the surface
+    pub location: Vec3,
+
+    /// The surface normal at `location`
+    pub normal: Vec3,
+
+    /// The [object ID](crate::raycasting::WithObjectId) of the surface, or zero if it has
+    /// none
+    pub object_id: u32,
+
+    /// The [ID](crate::materials::MaterialLibrary::id) of the surface's material, if it's
+    /// in the scene's material library
+    pub material_id: Option<u32>,
+
+    /// The direction back along the path towards the camera
+    pub w_i: Vec3,
+
+    /// The direction the material chose to continue the path in
+    pub w_o: Vec3,
+
+    /// The probability density with which the material chose `w_o`
+    pub pdf: f64,
+
+    /// Whether `w_o` was chosen from a specular lobe
+    pub is_specular: bool,
+
+    /// The BSDF for `w_i` and `w_o`, applied to a photon of unit intensity
+    pub bsdf: Photon,
+
+    /// Light emitted by the surface towards `w_i`
+    pub emitted: Photon,
+
+    /// Light from sampling the lights and environment directly
+    pub direct: Photon,
+
+    /// All of the light leaving the surface towards `w_i`, including that found by
+    /// following the rest of the path
+    pub radiance: Photon,
+}
+
+/// Everything recorded while tracing one sample of one pixel
+#[derive(Clone, Debug)]
+pub struct PathLog {
+    /// The ray leaving the camera
+    pub camera_ray: Ray,
+
+    /// The surfaces the path met, in the order it met them
+    pub bounces: Vec<BounceRecord>,
+
+    /// The light arriving at the camera, for the hero photon's wavelength
+    pub radiance: Photon,
+}
+
+/// The locations where a set of paths met surfaces
+#[derive(Clone, Debug, Default)]
+pub struct LightPathPoints {
+    pub points: Vec<Vec3>,
+}
+
+impl LightPathPoints {
+    /// Write the points as an ASCII PLY point cloud, which most 3D viewers can open
+    pub fn write_ply<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "ply")?;
+        writeln!(writer, "format ascii 1.0")?;
+        writeln!(writer, "element vertex {}", self.points.len())?;
+        for axis in ["x", "y", "z"].iter() {
+            writeln!(writer, "property float {}", axis)?;
+        }
+        writeln!(writer, "end_header")?;
+        for point in &self.points {
+            writeln!(writer, "{} {} {}", point.x(), point.y(), point.z())?;
+        }
+        writer.flush()
+    }
+
+    /// Count the points in each pixel of an image looking down the y axis, with x to the
+    /// right and z increasing down the image
+    ///
+    /// The image is scaled to fit the points' extent, keeping their proportions. Counts are
+    /// on a logarithmic scale, with the densest pixel white, so that sparse paths are still
+    /// visible next to the surfaces most paths hit.
+    pub fn top_down_density(&self, width: usize, height: usize) -> ImageGreyU16 {
+        let mut counts = vec![0u32; width * height];
+        let finite: Vec<&Vec3> = self
+            .points
+            .iter()
+            .filter(|point| point.as_slice().iter().all(|x| x.is_finite()))
+            .collect();
+        if let Some(&&first) = finite.first() {
+            let (min, max) = finite.iter().fold((first, first), |(min, max), point| {
+                (min.component_min(point), max.component_max(point))
+            });
+            let scale = ((max.x() - min.x()) / width as f64)
+                .max((max.z() - min.z()) / height as f64)
+                .max(f64::MIN_POSITIVE);
+            let pixel = |offset: f64, size: usize| ((offset / scale) as usize).min(size - 1);
+            for point in finite {
+                let row = pixel(point.z() - min.z(), height);
+                let column = pixel(point.x() - min.x(), width);
+                counts[row * width + column] += 1;
+            }
+        }
+        let mut result = ImageGreyU16::new(width, height);
+        let max_count = counts.iter().copied().max().unwrap_or(0);
+        if max_count > 0 {
+            let scale = f64::from(u16::MAX) / (1.0 + f64::from(max_count)).ln();
+            for (index, &count) in counts.iter().enumerate() {
+                let value = (1.0 + f64::from(count)).ln() * scale;
+                result.set_value(index / width, index % width, value.round() as u16);
+            }
+        }
+        result
+    }
+}
+
+/// Trace sample number `sample` of the pixel at `row` and `column` again, exactly as
+/// [partial_render_scene()](crate::camera::partial_render_scene) traced it with `seed`,
+/// and record what happened along its path
+///
+/// This is for finding out why a pixel is the colour it is, such as one that stays black
+/// however many samples are taken. Samples are counted from zero within the call that
+/// rendered them, so for a [progressive render](crate::progressive_renderer) `seed` is
+/// the seed of the pass. The recorded light is before clamping to `max_radiance`.
+#[allow(clippy::too_many_arguments)]
+pub fn trace_pixel(
+    scene: &Scene,
+    settings: &RenderSettings,
+    width: usize,
+    height: usize,
+    row: usize,
+    column: usize,
+    seed: u64,
+    sample: usize,
+) -> PathLog {
+    let image_sampler =
+        ImageSampler::new(width, height, scene.camera.as_ref()).with_shutter(settings.shutter);
+    let integrator = settings.integrator.as_ref();
+    let sampler = SceneSampler { scene };
+    let mut rng = pixel_rng(seed, row, column);
+    let trace = |rng: &mut StdRng| {
+        let (ray, _) = image_sampler.sample_pixel(row, column, rng);
+        let hit = sampler.sample(&ray);
+        let packet = integrator.integrate_hit(
+            &sampler,
+            &ray,
+            hit,
+            &PhotonPacket::random_wavelengths(rng),
+            settings.max_depth,
+            rng,
+        );
+        (ray, packet)
+    };
+    // The earlier samples are traced again to leave the generator as they left it
+    for _ in 0..sample {
+        trace(&mut rng);
+    }
+    let ((camera_ray, packet), bounces) = record(|| trace(&mut rng));
+    PathLog {
+        camera_ray,
+        bounces,
+        radiance: packet.hero().clone(),
+    }
+}
+
+/// Trace every sample of every pixel, as
+/// [partial_render_scene()](crate::camera::partial_render_scene) would with `settings`,
+/// and collect the locations where the paths met surfaces
+///
+/// As with [trace_pixel()](trace_pixel), only integrators that record bounces contribute
+/// any points.
+pub fn trace_light_paths(
+    scene: &Scene,
+    settings: &RenderSettings,
+    width: usize,
+    height: usize,
+) -> LightPathPoints {
+    let image_sampler =
+        ImageSampler::new(width, height, scene.camera.as_ref()).with_shutter(settings.shutter);
+    let integrator = settings.integrator.as_ref();
+    let sampler = SceneSampler { scene };
+    let points = (0..height)
+        .into_par_iter()
+        .flat_map_iter(|row| {
+            let mut points = vec![];
+            for column in 0..width {
+                let mut rng = pixel_rng(settings.seed, row, column);
+                for _ in 0..settings.samples_per_pixel {
+                    let (ray, _) = image_sampler.sample_pixel(row, column, &mut rng);
+                    let packet = PhotonPacket::random_wavelengths(&mut rng);
+                    let (_, bounces) = record(|| {
+                        integrator.integrate_ray(
+                            &sampler,
+                            &ray,
+                            &packet,
+                            settings.max_depth,
+                            &mut rng,
+                        )
+                    });
+                    points.extend(bounces.into_iter().map(|bounce| bounce.location));
+                }
+            }
+            points
+        })
+        .collect();
+    LightPathPoints { points }
+}
+
+thread_local! {
+    static RECORDED_BOUNCES: RefCell<Option<Vec<BounceRecord>>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with recording switched on for this thread, and return the bounces recorded
+pub(crate) fn record<T, F: FnOnce() -> T>(f: F) -> (T, Vec<BounceRecord>) {
+    RECORDED_BOUNCES.with(|bounces| *bounces.borrow_mut() = Some(vec![]));
+    let result = f();
+    let bounces = RECORDED_BOUNCES.with(|bounces| bounces.borrow_mut().take());
+    (result, bounces.unwrap_or_default())
+}
+
+/// Whether bounces are being recorded on this thread
+#[inline]
+pub(crate) fn is_recording() -> bool {
+    RECORDED_BOUNCES.with(|bounces| bounces.borrow().is_some())
+}
+
+/// Add `bounce` to the path being recorded, returning its index
+///
+/// The radiance isn't known until the rest of the path has been traced; it can be filled
+/// in with [set_radiance()].
+pub(crate) fn record_bounce(bounce: BounceRecord) -> Option<usize> {
+    RECORDED_BOUNCES.with(|bounces| {
+        bounces.borrow_mut().as_mut().map(|bounces| {
+            bounces.push(bounce);
+            bounces.len() - 1
+        })
+    })
+}
+
+pub(crate) fn set_radiance(index: usize, radiance: Photon) {
+    RECORDED_BOUNCES.with(|bounces| {
+        if let Some(bounce) = bounces
+            .borrow_mut()
+            .as_mut()
+            .and_then(|bounces| bounces.get_mut(index))
+        {
+            bounce.radiance = radiance;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+    use crate::colour::Spectrum;
+
+    use crate::materials::{LambertianMaterial, Material};
+    use crate::raycasting::{Plane, Primitive};
+
+    use std::sync::Arc;
+
+    fn bounce() -> BounceRecord {
+        let photon = Photon {
+            wavelength: 500.0,
+            intensity: 0.0,
+        };
+        BounceRecord {
+            location: Vec3::zeros(),
+            normal: Vec3::unit_z(),
+            object_id: 0,
+            material_id: None,
+            w_i: Vec3::unit_z(),
+            w_o: Vec3::unit_z(),
+            pdf: 1.0,
+            is_specular: false,
+            bsdf: photon.clone(),
+            emitted: photon.clone(),
+            direct: photon.clone(),
+            radiance: photon,
+        }
+    }
+
+    #[test]
+    fn bounces_are_only_recorded_inside_record() {
+        assert!(!is_recording());
+        assert!(record_bounce(bounce()).is_none());
+        let (_, bounces) = record(|| {
+            assert!(is_recording());
+            let index = record_bounce(bounce()).unwrap();
+            set_radiance(
+                index,
+                Photon {
+                    wavelength: 500.0,
+                    intensity: 2.0,
+                },
+            );
+        });
+        assert!(!is_recording());
+        assert!(bounces.len() == 1);
+        assert!(bounces[0].radiance.intensity == 2.0);
+    }
+
+    #[test]
+    fn ply_header_counts_points() {
+        let target = LightPathPoints {
+            points: vec![Vec3::zeros(), Vec3::new(1.0, 2.0, 3.0)],
+        };
+        let mut buffer = vec![];
+        target.write_ply(&mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.starts_with("ply\n"));
+        assert!(text.contains("element vertex 2\n"));
+        assert!(text.ends_with("end_header\n0 0 0\n1 2 3\n"));
+    }
+
+    #[test]
+    fn densest_pixel_of_top_down_image_is_white() {
+        let mut points = vec![Vec3::new(0.0, 5.0, 0.0), Vec3::new(4.0, -1.0, 2.0)];
+        points.extend(std::iter::repeat_n(Vec3::new(4.0, 0.0, 0.0), 10));
+        let target = LightPathPoints { points }.top_down_density(4, 4);
+        assert!(target.get_value(0, 3) == u16::MAX);
+        assert!(target.get_value(0, 0) > 0 && target.get_value(0, 0) < u16::MAX);
+        assert!(target.get_value(2, 3) == target.get_value(0, 0));
+        assert!(target.get_value(3, 3) == 0);
+    }
+
+    #[test]
+    fn records_bounces_of_rendered_sample() {
+        let material: Arc<dyn Material> = Arc::new(LambertianMaterial {
+            colour: Spectrum::grey(0.5),
+            diffuse_strength: 1.0,
+        });
+        let scene = Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::new(0.0, 1.0, -1.0),
+            Lens::Pinhole,
+        )))
+        .with_material("floor", Arc::clone(&material))
+        .with_object(Box::new(vec![
+            Box::new(Plane::new(Vec3::new(0.0, 1.0, 0.0), 0.0, material)) as Box<dyn Primitive>,
+        ]))
+        .build();
+        let settings = RenderSettings {
+            samples_per_pixel: 3,
+            ..RenderSettings::default()
+        };
+        // The bottom row of the image sees the floor
+        let (row, column) = (7, 4);
+        let target = trace_pixel(&scene, &settings, 8, 8, row, column, 11, 2);
+        assert!(!target.bounces.is_empty());
+        let first = &target.bounces[0];
+        assert!(first.location.y().abs() < 0.000_001);
+        assert!(first.material_id == Some(1));
+        assert!(first.bsdf.intensity > 0.0);
+        assert!(first.radiance.intensity == target.radiance.intensity);
+    }
+
+    #[test]
+    fn points_are_on_surfaces_seen_by_camera() {
+        let scene = Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::new(0.0, 1.0, -1.0),
+            Lens::Pinhole,
+        )))
+        .with_object(Box::new(vec![Box::new(Plane::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            0.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        )) as Box<dyn Primitive>]))
+        .build();
+        let settings = RenderSettings {
+            samples_per_pixel: 2,
+            ..RenderSettings::default()
+        };
+        let target = trace_light_paths(&scene, &settings, 8, 8);
+        // Only the bottom half of the image sees the floor, and paths leave it upwards
+        assert!(!target.points.is_empty() && target.points.len() < 8 * 8 * 2);
+        assert!(target
+            .points
+            .iter()
+            .all(|point| point.y().abs() < 0.000_001));
+    }
+}
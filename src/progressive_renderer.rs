@@ -0,0 +1,511 @@
+use rayon::prelude::*;
+
+use crate::accumulation_buffer::{read_u64, AccumulationBuffer};
+use crate::camera::{filter_footprint, partial_render_scene, RenderSettings};
+use crate::error::VanrijnError;
+use crate::scene::Scene;
+use crate::statistics::{take_thread_statistics, RayStatistics};
+use crate::util::{TileOrder, TileScheduler, TileSequencer};
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"VRPROG01";
+
+/// Statistics about a single completed pass over the image
+#[derive(Clone, Copy, Debug)]
+pub struct PassStatistics {
+    /// Index of the pass, starting at zero
+    pub pass: usize,
+
+    /// Samples accumulated in each pixel so far, including this pass
+    pub samples_per_pixel: usize,
+
+    /// Number of tiles rendered during the pass
+    pub tiles: usize,
+
+    /// Wall-clock time taken by the pass
+    pub duration: Duration,
+
+    /// Camera samples traced per second during the pass
+    pub samples_per_second: f64,
+
+    /// Work done during the pass, if the crate was built with the `statistics` feature
+    pub rays: RayStatistics,
+}
+
+/// How often to save a snapshot of the image during a long progressive render
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnapshotInterval {
+    /// After every so many passes
+    Passes(usize),
+
+    /// After the first pass to finish at least this long after the last snapshot
+    Time(Duration),
+}
+
+impl FromStr for SnapshotInterval {
+    type Err = String;
+
+    /// Parses a number of passes, such as `"10"`, or of seconds, such as `"30s"`
+    fn from_str(text: &str) -> Result<SnapshotInterval, String> {
+        let invalid = || format!("Invalid snapshot interval \"{}\".", text);
+        match text.strip_suffix('s') {
+            Some(seconds) => {
+                let seconds: f64 = seconds.parse().map_err(|_| invalid())?;
+                Duration::try_from_secs_f64(seconds)
+                    .map(SnapshotInterval::Time)
+                    .map_err(|_| invalid())
+            }
+            None => match text.parse() {
+                Ok(passes) if passes > 0 => Ok(SnapshotInterval::Passes(passes)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+/// Decides after each pass of a progressive render whether a snapshot is due
+#[derive(Clone, Debug)]
+pub struct SnapshotSchedule {
+    interval: SnapshotInterval,
+    passes_since_snapshot: usize,
+    last_snapshot: Instant,
+}
+
+impl SnapshotSchedule {
+    /// Start timing from now, as if a snapshot had just been taken
+    pub fn new(interval: SnapshotInterval) -> SnapshotSchedule {
+        SnapshotSchedule {
+            interval,
+            passes_since_snapshot: 0,
+            last_snapshot: Instant::now(),
+        }
+    }
+
+    /// Count a completed pass, and return whether a snapshot should be taken now
+    pub fn pass_completed(&mut self) -> bool {
+        self.passes_since_snapshot += 1;
+        let is_due = match self.interval {
+            SnapshotInterval::Passes(passes) => self.passes_since_snapshot >= passes,
+            SnapshotInterval::Time(interval) => self.last_snapshot.elapsed() >= interval,
+        };
+        if is_due {
+            self.passes_since_snapshot = 0;
+            self.last_snapshot = Instant::now();
+        }
+        is_due
+    }
+}
+
+/// Renders a scene progressively, adding `settings.samples_per_pixel` samples to every
+/// pixel in each pass
+///
+/// Each pass renders every tile of the image in parallel and merges the results into a
+/// shared [AccumulationBuffer](AccumulationBuffer), so another thread can display the image
+/// as it refines. Tiles are handed to the worker threads in [spiral](TileOrder::Spiral)
+/// order unless another order is chosen with
+/// [set_tile_order()](ProgressiveRenderer::set_tile_order).
+///
+/// Each pass is rendered with a seed derived from the [settings' seed](RenderSettings::seed)
+/// and the pass index, so rendering the same scene with the same seed always produces the
+/// same image, up to rounding where the tiles' filter footprints overlap. Tiles are merged
+/// as they finish, unless [set_deterministic()](ProgressiveRenderer::set_deterministic)
+/// asks for them to be merged in the order they were scheduled, which makes every
+/// render with the same seed identical, and the previews between passes too.
+pub struct ProgressiveRenderer<'a> {
+    scene: &'a Scene,
+    image: Arc<Mutex<AccumulationBuffer>>,
+    settings: RenderSettings,
+    tile_order: TileOrder,
+    is_deterministic: bool,
+    passes_completed: usize,
+}
+
+impl<'a> ProgressiveRenderer<'a> {
+    /// Create a renderer that accumulates `scene` into `image`
+    ///
+    /// The size of the rendered image is the size of `image`.
+    pub fn new(
+        scene: &'a Scene,
+        image: Arc<Mutex<AccumulationBuffer>>,
+        settings: RenderSettings,
+    ) -> ProgressiveRenderer<'a> {
+        ProgressiveRenderer {
+            scene,
+            image,
+            settings,
+            tile_order: TileOrder::default(),
+            is_deterministic: false,
+            passes_completed: 0,
+        }
+    }
+
+    pub fn set_tile_order(&mut self, tile_order: TileOrder) {
+        self.tile_order = tile_order;
+    }
+
+    /// Merge tiles into the image in the order they were scheduled, rather than as they
+    /// finish
+    ///
+    /// A tile that finishes early waits for the ones before it, so the image can lag a
+    /// little behind the render, and if the pass is cancelled, finished tiles still waiting
+    /// are dropped.
+    pub fn set_deterministic(&mut self, is_deterministic: bool) {
+        self.is_deterministic = is_deterministic;
+    }
+
+    /// The buffer that passes are accumulated into
+    pub fn image(&self) -> Arc<Mutex<AccumulationBuffer>> {
+        Arc::clone(&self.image)
+    }
+
+    pub fn passes_completed(&self) -> usize {
+        self.passes_completed
+    }
+
+    /// Save the progress of the render to `filename`
+    ///
+    /// The checkpoint holds the accumulated image along with the seed and number of passes
+    /// completed, so a render resumed from it continues exactly as if it hadn't stopped.
+    /// The file is replaced atomically, so a crash while saving leaves the previous
+    /// checkpoint intact.
+    pub fn write_checkpoint(&self, filename: &Path) -> Result<(), VanrijnError> {
+        let temporary_filename = filename.with_extension("partial");
+        {
+            let mut writer = BufWriter::new(File::create(&temporary_filename)?);
+            writer.write_all(CHECKPOINT_MAGIC)?;
+            writer.write_all(&self.settings.seed.to_le_bytes())?;
+            writer.write_all(&(self.passes_completed as u64).to_le_bytes())?;
+            self.image
+                .lock()
+                .expect("Accumulation buffer lock poisoned.")
+                .write_to(&mut writer)?;
+            writer.flush()?;
+        }
+        Ok(std::fs::rename(&temporary_filename, filename)?)
+    }
+
+    /// Continue from a checkpoint written by
+    /// [write_checkpoint()](ProgressiveRenderer::write_checkpoint)
+    ///
+    /// The accumulated image replaces the contents of the renderer's image, which must be
+    /// the same size.
+    pub fn resume_from_checkpoint(&mut self, filename: &Path) -> Result<(), VanrijnError> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+        let mut reader = BufReader::new(File::open(filename)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(invalid("Not a render checkpoint.").into());
+        }
+        let seed = read_u64(&mut reader)?;
+        let passes_completed = read_u64(&mut reader)? as usize;
+        let checkpoint_image = AccumulationBuffer::read_from(&mut reader)?;
+        let mut image = self
+            .image
+            .lock()
+            .expect("Accumulation buffer lock poisoned.");
+        if checkpoint_image.width() != image.width() || checkpoint_image.height() != image.height()
+        {
+            return Err(invalid("Checkpoint image size doesn't match.").into());
+        }
+        *image = checkpoint_image;
+        self.settings.seed = seed;
+        self.passes_completed = passes_completed;
+        Ok(())
+    }
+
+    /// Render every tile once
+    ///
+    /// If the settings' [cancellation token](RenderSettings::cancellation) is cancelled
+    /// during the pass, this returns as soon as the tiles being rendered stop. Tiles that
+    /// were finished before then are kept, but the pass isn't counted as completed.
+    pub fn render_pass(&mut self) -> PassStatistics {
+        let (width, height) = {
+            let image = self
+                .image
+                .lock()
+                .expect("Accumulation buffer lock poisoned.");
+            (image.width(), image.height())
+        };
+        let start_time = Instant::now();
+        let scene = self.scene;
+        let image = &self.image;
+        let settings = &self.settings;
+        let seed = settings.seed.wrapping_add(self.passes_completed as u64);
+        let scheduler = TileScheduler::new(width, height, settings.tile_size, self.tile_order);
+        let is_deterministic = self.is_deterministic;
+        let sequencer = Mutex::new(TileSequencer::new());
+        let rays = (0..rayon::current_num_threads())
+            .into_par_iter()
+            .map(|_| {
+                // Discard anything counted on this thread outside of the render
+                take_thread_statistics();
+                let mut rays = RayStatistics::default();
+                while let Some((index, tile)) = scheduler.next_indexed_tile() {
+                    let rendered_tile =
+                        partial_render_scene(scene, tile, height, width, seed, settings);
+                    rays = rays.add(&take_thread_statistics());
+                    if settings.cancellation.is_cancelled() {
+                        // The tile may be incomplete, and so may the rest of the pass
+                        break;
+                    }
+                    let footprint =
+                        filter_footprint(&tile, width, height, settings.filter.as_ref());
+                    if is_deterministic {
+                        // The sequencer stays locked while merging, so that tiles released
+                        // by different threads can't overtake each other
+                        let mut sequencer =
+                            sequencer.lock().expect("Tile sequencer lock poisoned.");
+                        let ready = sequencer.push(index, (footprint, rendered_tile));
+                        let mut image = image.lock().expect("Accumulation buffer lock poisoned.");
+                        for (footprint, rendered_tile) in ready {
+                            image.merge_tile(&footprint, &rendered_tile);
+                        }
+                    } else {
+                        image
+                            .lock()
+                            .expect("Accumulation buffer lock poisoned.")
+                            .merge_tile(&footprint, &rendered_tile);
+                    }
+                }
+                rays
+            })
+            .reduce(RayStatistics::default, |a, b| a.add(&b));
+        let tiles = scheduler.len();
+        let duration = start_time.elapsed();
+        let statistics = PassStatistics {
+            pass: self.passes_completed,
+            samples_per_pixel: (self.passes_completed + 1) * settings.samples_per_pixel,
+            tiles,
+            duration,
+            samples_per_second: (width * height * settings.samples_per_pixel) as f64
+                / duration.as_secs_f64(),
+            rays,
+        };
+        if !settings.cancellation.is_cancelled() {
+            self.passes_completed += 1;
+        }
+        statistics
+    }
+
+    /// Render passes until `should_continue` returns false, or the render is cancelled
+    ///
+    /// `should_continue` is called with the statistics for each pass as it completes.
+    pub fn render<F: FnMut(&PassStatistics) -> bool>(&mut self, mut should_continue: F) {
+        while should_continue(&self.render_pass()) && !self.settings.cancellation.is_cancelled() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+
+    use crate::math::Vec3;
+
+    fn empty_scene() -> Scene {
+        Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Lens::Pinhole,
+        )))
+        .build()
+    }
+
+    #[test]
+    fn pass_renders_every_tile() {
+        let scene = empty_scene();
+        let image = Arc::new(Mutex::new(AccumulationBuffer::new(10, 7)));
+        let mut target = ProgressiveRenderer::new(
+            &scene,
+            image,
+            RenderSettings {
+                tile_size: 4,
+                ..RenderSettings::default()
+            },
+        );
+        let statistics = target.render_pass();
+        assert!(statistics.tiles == 6);
+        // Camera rays don't hit anything in an empty scene, so there's one per pixel
+        let expected_rays = if crate::statistics::ENABLED { 70 } else { 0 };
+        assert!(statistics.rays.rays_cast == expected_rays);
+    }
+
+    #[test]
+    fn samples_per_pixel_increases_with_each_pass() {
+        let scene = empty_scene();
+        let image = Arc::new(Mutex::new(AccumulationBuffer::new(4, 4)));
+        let mut target = ProgressiveRenderer::new(
+            &scene,
+            image,
+            RenderSettings {
+                tile_size: 2,
+                ..RenderSettings::default()
+            },
+        );
+        assert!(target.render_pass().samples_per_pixel == 1);
+        let statistics = target.render_pass();
+        assert!(statistics.pass == 1);
+        assert!(statistics.samples_per_pixel == 2);
+        assert!(target.passes_completed() == 2);
+    }
+
+    #[test]
+    fn deterministic_renders_are_identical() {
+        let scene = empty_scene();
+        let render = || {
+            let image = Arc::new(Mutex::new(AccumulationBuffer::new(12, 9)));
+            let mut target = ProgressiveRenderer::new(
+                &scene,
+                Arc::clone(&image),
+                RenderSettings {
+                    tile_size: 2,
+                    filter: Arc::new(crate::filters::TentFilter::default()),
+                    ..RenderSettings::default()
+                },
+            );
+            target.set_deterministic(true);
+            target.render_pass();
+            target.render_pass();
+            let mut bytes = Vec::new();
+            image.lock().unwrap().write_to(&mut bytes).unwrap();
+            bytes
+        };
+        assert!(render() == render());
+    }
+
+    #[test]
+    fn render_stops_when_callback_returns_false() {
+        let scene = empty_scene();
+        let image = Arc::new(Mutex::new(AccumulationBuffer::new(4, 4)));
+        let mut target = ProgressiveRenderer::new(
+            &scene,
+            image,
+            RenderSettings {
+                tile_size: 2,
+                ..RenderSettings::default()
+            },
+        );
+        target.render(|statistics| statistics.samples_per_pixel < 3);
+        assert!(target.passes_completed() == 3);
+    }
+
+    #[test]
+    fn cancelled_passes_are_not_counted() {
+        let scene = empty_scene();
+        let image = Arc::new(Mutex::new(AccumulationBuffer::new(4, 4)));
+        let settings = RenderSettings {
+            tile_size: 2,
+            ..RenderSettings::default()
+        };
+        let cancellation = settings.cancellation.clone();
+        let mut target = ProgressiveRenderer::new(&scene, image, settings);
+        target.render_pass();
+        cancellation.cancel();
+        target.render(|_| true);
+        assert!(target.passes_completed() == 1);
+    }
+
+    #[test]
+    fn samples_per_pixel_counts_samples_in_each_pass() {
+        let scene = empty_scene();
+        let image = Arc::new(Mutex::new(AccumulationBuffer::new(4, 4)));
+        let settings = RenderSettings {
+            samples_per_pixel: 3,
+            tile_size: 2,
+            ..RenderSettings::default()
+        };
+        let mut target = ProgressiveRenderer::new(&scene, image, settings);
+        target.render_pass();
+        assert!(target.render_pass().samples_per_pixel == 6);
+    }
+
+    #[test]
+    fn resumed_render_matches_uninterrupted_render() {
+        let scene = empty_scene();
+        let checkpoint = std::env::temp_dir().join(format!(
+            "vanrijn_checkpoint_test_{}.checkpoint",
+            std::process::id()
+        ));
+
+        let uninterrupted_image = Arc::new(Mutex::new(AccumulationBuffer::new(6, 4)));
+        let mut uninterrupted = ProgressiveRenderer::new(
+            &scene,
+            uninterrupted_image,
+            RenderSettings {
+                tile_size: 3,
+                seed: 5,
+                ..RenderSettings::default()
+            },
+        );
+        uninterrupted.render(|statistics| statistics.samples_per_pixel < 3);
+
+        let first_image = Arc::new(Mutex::new(AccumulationBuffer::new(6, 4)));
+        let mut first = ProgressiveRenderer::new(
+            &scene,
+            first_image,
+            RenderSettings {
+                tile_size: 3,
+                seed: 5,
+                ..RenderSettings::default()
+            },
+        );
+        first.render(|statistics| statistics.samples_per_pixel < 2);
+        first.write_checkpoint(&checkpoint).unwrap();
+
+        let resumed_image = Arc::new(Mutex::new(AccumulationBuffer::new(6, 4)));
+        let mut resumed = ProgressiveRenderer::new(
+            &scene,
+            resumed_image,
+            RenderSettings {
+                tile_size: 3,
+                seed: 99,
+                ..RenderSettings::default()
+            },
+        );
+        resumed.resume_from_checkpoint(&checkpoint).unwrap();
+        std::fs::remove_file(&checkpoint).unwrap();
+        assert!(resumed.passes_completed() == 2);
+        assert!(resumed.render_pass().samples_per_pixel == 3);
+
+        let mut expected = Vec::new();
+        let mut actual = Vec::new();
+        uninterrupted
+            .image()
+            .lock()
+            .unwrap()
+            .write_to(&mut expected)
+            .unwrap();
+        resumed
+            .image()
+            .lock()
+            .unwrap()
+            .write_to(&mut actual)
+            .unwrap();
+        assert!(expected == actual);
+    }
+
+    #[test]
+    fn snapshot_intervals_parse_as_passes_or_seconds() {
+        assert!("10".parse() == Ok(SnapshotInterval::Passes(10)));
+        assert!("1.5s".parse() == Ok(SnapshotInterval::Time(Duration::from_millis(1500))));
+        assert!("0".parse::<SnapshotInterval>().is_err());
+        assert!("-2s".parse::<SnapshotInterval>().is_err());
+        assert!("often".parse::<SnapshotInterval>().is_err());
+    }
+
+    #[test]
+    fn snapshots_are_due_every_n_passes() {
+        let mut target = SnapshotSchedule::new(SnapshotInterval::Passes(3));
+        let due: Vec<bool> = (0..7).map(|_| target.pass_completed()).collect();
+        assert!(due == vec![false, false, true, false, false, true, false]);
+        let mut target = SnapshotSchedule::new(SnapshotInterval::Time(Duration::ZERO));
+        assert!(target.pass_completed());
+    }
+}
@@ -1,5 +1,7 @@
 use std::f64::consts::PI;
 
+use rand::RngCore;
+
 use crate::math::Vec3;
 
 use super::{RandomDistribution, UnitDisc};
@@ -17,8 +19,8 @@ impl CosineWeightedHemisphere {
 }
 
 impl RandomDistribution<Vec3> for CosineWeightedHemisphere {
-    fn value(&self) -> Vec3 {
-        let point_on_disc = self.unit_disc.value();
+    fn value(&self, rng: &mut dyn RngCore) -> Vec3 {
+        let point_on_disc = self.unit_disc.value(rng);
         let z = 0.0f64
             .max(
                 1.0 - point_on_disc.x() * point_on_disc.x() - point_on_disc.y() * point_on_disc.y(),
@@ -28,7 +30,7 @@ impl RandomDistribution<Vec3> for CosineWeightedHemisphere {
     }
 
     fn pdf(&self, v: Vec3) -> f64 {
-        (v.x() * v.x() + v.y() * v.y()).sqrt() / PI
+        v.z().max(0.0) / PI
     }
 }
 
@@ -36,12 +38,16 @@ impl RandomDistribution<Vec3> for CosineWeightedHemisphere {
 mod tests {
     use super::*;
 
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
     #[test]
     #[ignore]
     fn print_values() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = CosineWeightedHemisphere::new();
         for _ in 0..1000 {
-            let value = target.value();
+            let value = target.value(&mut rng);
             println!("{}, {}, {}", value.x(), value.y(), value.z());
         }
     }
@@ -49,9 +55,10 @@ mod tests {
     #[test]
     #[ignore]
     fn integral_is_near_area() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = CosineWeightedHemisphere::new();
         let integral = (0..100000)
-            .map(|_| target.value())
+            .map(|_| target.value(&mut rng))
             .map(|value| 1.0 / target.pdf(value))
             .sum::<f64>()
             / 100000.0;
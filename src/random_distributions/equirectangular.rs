@@ -0,0 +1,226 @@
+use std::f64::consts::PI;
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+use crate::math::Vec3;
+use crate::util::Array2D;
+
+use super::RandomDistribution;
+
+/// A distribution of directions proportional to the weights in an equirectangular map
+///
+/// Row 0 of the weight map is the +Y pole and the last row is the -Y pole. Columns wrap
+/// around the Y axis, starting and ending at -X. This is the usual layout of a
+/// latitude-longitude environment map, so an image's luminance can be used as weights to
+/// importance-sample it.
+#[derive(Debug)]
+pub struct EquirectangularDistribution {
+    width: usize,
+    height: usize,
+    /// Probability of choosing each pixel
+    pixel_probabilities: Array2D<f64>,
+    /// Cumulative distribution over rows
+    row_cdf: Vec<f64>,
+    /// Cumulative distribution over columns within each row
+    column_cdfs: Array2D<f64>,
+}
+
+impl EquirectangularDistribution {
+    /// Create a distribution from a map of non-negative weights
+    ///
+    /// If every weight is zero, the result samples the sphere uniformly.
+    pub fn new(weights: &Array2D<f64>) -> EquirectangularDistribution {
+        let width = weights.get_width();
+        let height = weights.get_height();
+        assert!(width > 0 && height > 0);
+        let all_zero = weights.as_slice().iter().all(|&weight| weight <= 0.0);
+        let mut pixel_probabilities = Array2D::new(height, width);
+        for row in 0..height {
+            for column in 0..width {
+                let weight = if all_zero {
+                    1.0
+                } else {
+                    weights[row][column].max(0.0)
+                };
+                // Rows near the poles cover less solid angle
+                pixel_probabilities[row][column] = weight * row_sin_theta(row, height);
+            }
+        }
+        let total: f64 = pixel_probabilities.as_slice().iter().sum();
+        for row in 0..height {
+            for column in 0..width {
+                pixel_probabilities[row][column] /= total;
+            }
+        }
+        let mut row_cdf = Vec::with_capacity(height);
+        let mut column_cdfs = Array2D::new(height, width);
+        let mut row_total = 0.0;
+        for row in 0..height {
+            let row_probability: f64 = pixel_probabilities[row].iter().sum();
+            let mut column_total = 0.0;
+            for column in 0..width {
+                column_total += if row_probability > 0.0 {
+                    pixel_probabilities[row][column] / row_probability
+                } else {
+                    1.0 / width as f64
+                };
+                column_cdfs[row][column] = column_total;
+            }
+            row_total += row_probability;
+            row_cdf.push(row_total);
+        }
+        EquirectangularDistribution {
+            width,
+            height,
+            pixel_probabilities,
+            row_cdf,
+            column_cdfs,
+        }
+    }
+
+    /// The direction at equirectangular coordinates `u` and `v`, both in [0, 1]
+    pub fn uv_to_direction(u: f64, v: f64) -> Vec3 {
+        let theta = v * PI;
+        let phi = u * 2.0 * PI - PI;
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin())
+    }
+
+    /// The equirectangular coordinates of `direction`, which must be normalized
+    pub fn direction_to_uv(direction: &Vec3) -> (f64, f64) {
+        let theta = direction.y().clamp(-1.0, 1.0).acos();
+        let phi = direction.z().atan2(direction.x());
+        ((phi + PI) / (2.0 * PI), theta / PI)
+    }
+
+    /// The row and column of the map containing `direction`
+    pub fn pixel_for_direction(&self, direction: &Vec3) -> (usize, usize) {
+        let (u, v) = EquirectangularDistribution::direction_to_uv(direction);
+        (
+            ((v * self.height as f64) as usize).min(self.height - 1),
+            ((u * self.width as f64) as usize).min(self.width - 1),
+        )
+    }
+}
+
+fn row_sin_theta(row: usize, height: usize) -> f64 {
+    ((row as f64 + 0.5) * PI / height as f64).sin()
+}
+
+fn search_cdf(cdf: &[f64], value: f64) -> usize {
+    cdf.iter()
+        .position(|&cumulative| value < cumulative)
+        .unwrap_or(cdf.len() - 1)
+}
+
+impl RandomDistribution<Vec3> for EquirectangularDistribution {
+    fn value(&self, rng: &mut dyn RngCore) -> Vec3 {
+        let row = search_cdf(
+            &self.row_cdf,
+            rng.sample::<f64, _>(Open01) * self.row_cdf[self.height - 1],
+        );
+        let column = search_cdf(
+            &self.column_cdfs[row],
+            rng.sample::<f64, _>(Open01) * self.column_cdfs[row][self.width - 1],
+        );
+        EquirectangularDistribution::uv_to_direction(
+            (column as f64 + rng.sample::<f64, _>(Open01)) / self.width as f64,
+            (row as f64 + rng.sample::<f64, _>(Open01)) / self.height as f64,
+        )
+    }
+
+    fn pdf(&self, value: Vec3) -> f64 {
+        let (row, column) = self.pixel_for_direction(&value);
+        let sin_theta = (1.0 - value.y() * value.y()).max(0.0).sqrt();
+        if sin_theta == 0.0 {
+            0.0
+        } else {
+            // Each pixel covers 1/(width*height) of the (u,v) square, and the (u,v) square
+            // maps onto the sphere with a Jacobian of 2*pi^2*sin(theta)
+            self.pixel_probabilities[row][column] * (self.width * self.height) as f64
+                / (2.0 * PI * PI * sin_theta)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use quickcheck_macros::quickcheck;
+
+    fn single_bright_pixel(row: usize, column: usize) -> EquirectangularDistribution {
+        let mut weights = Array2D::new(8, 16);
+        weights[row][column] = 1.0;
+        EquirectangularDistribution::new(&weights)
+    }
+
+    #[quickcheck]
+    fn uv_round_trips_through_direction(u: f64, v: f64) -> bool {
+        let u = u.fract().abs();
+        let v = v.fract().abs() * 0.98 + 0.01;
+        let (u2, v2) = EquirectangularDistribution::direction_to_uv(
+            &EquirectangularDistribution::uv_to_direction(u, v),
+        );
+        (u - u2).abs() < 0.000001 && (v - v2).abs() < 0.000001
+    }
+
+    #[test]
+    fn top_row_is_positive_y() {
+        assert!(EquirectangularDistribution::uv_to_direction(0.3, 0.0).y() > 0.999999);
+    }
+
+    #[test]
+    fn samples_only_come_from_bright_pixel() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let target = single_bright_pixel(2, 5);
+        for _ in 0..1000 {
+            assert!(target.pixel_for_direction(&target.value(&mut rng)) == (2, 5));
+        }
+    }
+
+    #[test]
+    fn pdf_is_zero_outside_bright_pixel() {
+        let target = single_bright_pixel(2, 5);
+        let direction = EquirectangularDistribution::uv_to_direction(0.9, 0.9);
+        assert!(target.pdf(direction) == 0.0);
+    }
+
+    #[test]
+    fn uniform_weights_give_near_uniform_pdf() {
+        let mut weights = Array2D::new(64, 128);
+        for row in 0..64 {
+            for column in 0..128 {
+                weights[row][column] = 1.0;
+            }
+        }
+        let target = EquirectangularDistribution::new(&weights);
+        let direction = Vec3::new(1.0, 0.5, -0.25).normalize();
+        assert!((target.pdf(direction) * 4.0 * PI - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn zero_weights_give_near_uniform_pdf() {
+        let target = EquirectangularDistribution::new(&Array2D::new(64, 128));
+        let direction = Vec3::new(-0.5, -0.5, 0.25).normalize();
+        assert!((target.pdf(direction) * 4.0 * PI - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn integral_is_near_area() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let target = single_bright_pixel(3, 9);
+        let integral = (0..10000)
+            .map(|_| target.value(&mut rng))
+            .map(|value| 1.0 / target.pdf(value))
+            .sum::<f64>()
+            / 10000.0;
+        // The integral of 1/pdf over the samples approximates the solid angle of the pixel
+        let pixel_solid_angle = 2.0 * PI * PI * row_sin_theta(3, 8) / (8.0 * 16.0);
+        assert!((integral - pixel_solid_angle).abs() < pixel_solid_angle * 0.05);
+    }
+}
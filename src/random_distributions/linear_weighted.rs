@@ -1,5 +1,5 @@
 use rand::distributions::Open01;
-use rand::{thread_rng, Rng};
+use rand::{Rng, RngCore};
 
 use super::RandomDistribution;
 
@@ -14,8 +14,7 @@ impl LinearWeighted {
 }
 
 impl RandomDistribution<f64> for LinearWeighted {
-    fn value(&self) -> f64 {
-        let mut rng = thread_rng();
+    fn value(&self, rng: &mut dyn RngCore) -> f64 {
         rng.sample::<f64, _>(Open01).sqrt() * self.max_value
     }
 
@@ -28,12 +27,16 @@ impl RandomDistribution<f64> for LinearWeighted {
 mod tests {
     use super::*;
 
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
     #[test]
     #[ignore]
     fn print_values() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = LinearWeighted::new(2.0);
         for _ in 0..1000 {
-            let value = target.value();
+            let value = target.value(&mut rng);
             println!("{}", value);
         }
     }
@@ -41,10 +44,11 @@ mod tests {
     #[test]
     #[ignore]
     fn print_buckets() {
+        let mut rng = StdRng::seed_from_u64(0);
         let mut buckets = [0; 20];
         let target = LinearWeighted::new(20.0);
         for _ in 0..10000 {
-            let value = target.value();
+            let value = target.value(&mut rng);
             let i = value as usize;
             buckets[i] += 1;
         }
@@ -56,9 +60,10 @@ mod tests {
     #[test]
     #[ignore]
     fn integral_is_near_area() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = LinearWeighted::new(2.0);
         let integral = (0..100000)
-            .map(|_| target.value())
+            .map(|_| target.value(&mut rng))
             .map(|value| 1.0 / target.pdf(value))
             .sum::<f64>()
             / 100000.0;
@@ -4,6 +4,9 @@ pub use uniform_square::UniformSquare;
 mod unit_disc;
 pub use unit_disc::UnitDisc;
 
+mod uniform_sphere;
+pub use uniform_sphere::UniformSphere;
+
 mod uniform_hemisphere;
 pub use uniform_hemisphere::UniformHemisphere;
 
@@ -16,7 +19,16 @@ pub use linear_weighted::LinearWeighted;
 mod sky_light_pdf;
 pub use sky_light_pdf::SkyLightPdf;
 
+mod equirectangular;
+pub use equirectangular::EquirectangularDistribution;
+
+use rand::RngCore;
+
+/// A probability distribution that values can be drawn from
+///
+/// All randomness comes from the generator passed to [value()](RandomDistribution::value),
+/// so the same generator state always produces the same value.
 pub trait RandomDistribution<T> {
-    fn value(&self) -> T;
+    fn value(&self, rng: &mut dyn RngCore) -> T;
     fn pdf(&self, value: T) -> f64;
 }
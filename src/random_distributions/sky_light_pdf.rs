@@ -1,20 +1,23 @@
 use std::f64::consts::PI;
 
 use rand::distributions::Open01;
-use rand::{thread_rng, Rng};
+use rand::{Rng, RngCore};
 
 use crate::math::Vec3;
 
 use super::{LinearWeighted, RandomDistribution};
 
+/// Directions in the upper (+Y) hemisphere, weighted by their cosine with the zenith
+///
+/// This suits skies that are brightest overhead and emit nothing below the horizon.
 pub struct SkyLightPdf {
-    z_distribution: LinearWeighted,
+    y_distribution: LinearWeighted,
 }
 
 impl SkyLightPdf {
     pub fn new() -> SkyLightPdf {
-        let z_distribution = LinearWeighted::new(1.0);
-        SkyLightPdf { z_distribution }
+        let y_distribution = LinearWeighted::new(1.0);
+        SkyLightPdf { y_distribution }
     }
 }
 
@@ -25,20 +28,19 @@ impl Default for SkyLightPdf {
 }
 
 impl RandomDistribution<Vec3> for SkyLightPdf {
-    fn value(&self) -> Vec3 {
-        let mut rng = thread_rng();
+    fn value(&self, rng: &mut dyn RngCore) -> Vec3 {
         let phi = rng.sample::<f64, _>(Open01) * 2.0 * PI;
-        let z = self.z_distribution.value();
-        let r = (1.0 - z * z).sqrt();
-        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+        let y = self.y_distribution.value(rng);
+        let r = (1.0 - y * y).sqrt();
+        Vec3::new(r * phi.cos(), y, r * phi.sin())
     }
 
     fn pdf(&self, value: Vec3) -> f64 {
-        let z = value.z();
-        if z < 0.0 {
+        let y = value.y();
+        if y < 0.0 {
             0.0
         } else {
-            z / PI
+            y / PI
         }
     }
 }
@@ -47,12 +49,27 @@ impl RandomDistribution<Vec3> for SkyLightPdf {
 mod tests {
     use super::*;
 
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn values_are_in_upper_hemisphere() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let target = SkyLightPdf::new();
+        for _ in 0..1000 {
+            let value = target.value(&mut rng);
+            assert!(value.y() >= 0.0);
+            assert!((value.norm() - 1.0).abs() < 0.000000001);
+        }
+    }
+
     #[test]
     #[ignore]
     fn print_values() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = SkyLightPdf::new();
         for _ in 0..1000 {
-            let value = target.value();
+            let value = target.value(&mut rng);
             println!("{}, {}, {}", value.x(), value.y(), value.z());
         }
     }
@@ -60,9 +77,10 @@ mod tests {
     #[test]
     #[ignore]
     fn integral_is_near_area() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = SkyLightPdf::new();
         let integral = (0..100000)
-            .map(|_| target.value())
+            .map(|_| target.value(&mut rng))
             .map(|value| 1.0 / target.pdf(value))
             .sum::<f64>()
             / 100000.0;
@@ -1,7 +1,7 @@
 use std::f64::consts::PI;
 
 use rand::distributions::{Open01, OpenClosed01};
-use rand::{thread_rng, Rng};
+use rand::{Rng, RngCore};
 
 use crate::math::Vec3;
 
@@ -17,8 +17,7 @@ impl UniformHemisphere {
 }
 
 impl RandomDistribution<Vec3> for UniformHemisphere {
-    fn value(&self) -> Vec3 {
-        let mut rng = thread_rng();
+    fn value(&self, rng: &mut dyn RngCore) -> Vec3 {
         let mut result = Vec3::new(
             2.0 * rng.sample::<f64, _>(Open01) - 1.0,
             2.0 * rng.sample::<f64, _>(Open01) - 1.0,
@@ -43,12 +42,16 @@ impl RandomDistribution<Vec3> for UniformHemisphere {
 mod tests {
     use super::*;
 
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
     #[test]
     #[ignore]
     fn print_values() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = UniformHemisphere::new();
         for _ in 0..1000 {
-            let value = target.value();
+            let value = target.value(&mut rng);
             println!("{}, {}, {}", value.x(), value.y(), value.z());
         }
     }
@@ -56,9 +59,10 @@ mod tests {
     #[test]
     #[ignore]
     fn integral_is_near_area() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = UniformHemisphere::new();
         let integral = (0..1000)
-            .map(|_| target.value())
+            .map(|_| target.value(&mut rng))
             .map(|value| 1.0 / target.pdf(value))
             .sum::<f64>()
             / 1000.0;
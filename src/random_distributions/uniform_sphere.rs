@@ -0,0 +1,57 @@
+use std::f64::consts::PI;
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+use crate::math::Vec3;
+
+use super::RandomDistribution;
+
+#[derive(Default)]
+pub struct UniformSphere {}
+
+impl UniformSphere {
+    pub fn new() -> UniformSphere {
+        UniformSphere {}
+    }
+}
+
+impl RandomDistribution<Vec3> for UniformSphere {
+    fn value(&self, rng: &mut dyn RngCore) -> Vec3 {
+        let z = 1.0 - 2.0 * rng.sample::<f64, _>(Open01);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.sample::<f64, _>(Open01);
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    fn pdf(&self, _: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn values_are_unit_vectors() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let target = UniformSphere::new();
+        for _ in 0..1000 {
+            assert!((target.value(&mut rng).norm() - 1.0).abs() < 0.000000001);
+        }
+    }
+
+    #[test]
+    fn values_cover_both_hemispheres() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let target = UniformSphere::new();
+        let below = (0..1000)
+            .filter(|_| target.value(&mut rng).z() < 0.0)
+            .count();
+        assert!(below > 400 && below < 600);
+    }
+}
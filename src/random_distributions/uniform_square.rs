@@ -1,5 +1,5 @@
 use rand::distributions::Open01;
-use rand::{thread_rng, Rng};
+use rand::{Rng, RngCore};
 
 use crate::math::Vec2;
 
@@ -18,8 +18,7 @@ impl UniformSquare {
 }
 
 impl RandomDistribution<Vec2> for UniformSquare {
-    fn value(&self) -> Vec2 {
-        let mut rng = thread_rng();
+    fn value(&self, rng: &mut dyn RngCore) -> Vec2 {
         self.corner
             + Vec2::new(rng.sample::<f64, _>(Open01), rng.sample::<f64, _>(Open01)) * self.size
     }
@@ -33,15 +32,19 @@ impl RandomDistribution<Vec2> for UniformSquare {
 mod tests {
     use super::*;
 
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
     #[test]
     #[ignore]
     fn print_values() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = UniformSquare {
             corner: Vec2::new(1.5, -2.5),
             size: 3.0,
         };
         for _ in 0..1000 {
-            let value = target.value();
+            let value = target.value(&mut rng);
             println!("{}, {}", value.x(), value.y());
         }
     }
@@ -49,12 +52,13 @@ mod tests {
     #[test]
     #[ignore]
     fn integral_is_near_area() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = UniformSquare {
             corner: Vec2::new(1.5, -2.5),
             size: 3.0,
         };
         let integral = (0..1000)
-            .map(|_| target.value())
+            .map(|_| target.value(&mut rng))
             .map(|value| 1.0 / target.pdf(value))
             .sum::<f64>()
             / 1000.0;
@@ -1,5 +1,7 @@
 use std::f64::consts::PI;
 
+use rand::RngCore;
+
 use crate::math::Vec2;
 
 use super::{RandomDistribution, UniformSquare};
@@ -25,8 +27,8 @@ impl UnitDisc {
 }
 
 impl RandomDistribution<Vec2> for UnitDisc {
-    fn value(&self) -> Vec2 {
-        let offset = self.square_distribution.value();
+    fn value(&self, rng: &mut dyn RngCore) -> Vec2 {
+        let offset = self.square_distribution.value(rng);
         if offset.x() == 0.0 && offset.y() == 0.0 {
             offset
         } else {
@@ -48,12 +50,16 @@ impl RandomDistribution<Vec2> for UnitDisc {
 mod tests {
     use super::*;
 
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
     #[test]
     #[ignore]
     fn print_values() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = UnitDisc::new();
         for _ in 0..1000 {
-            let value = target.value();
+            let value = target.value(&mut rng);
             println!("{}, {}", value.x(), value.y());
         }
     }
@@ -61,9 +67,10 @@ mod tests {
     #[test]
     #[ignore]
     fn integral_is_near_area() {
+        let mut rng = StdRng::seed_from_u64(0);
         let target = UnitDisc::new();
         let integral = (0..1000)
-            .map(|_| target.value())
+            .map(|_| target.value(&mut rng))
             .map(|value| 1.0 / target.pdf(value))
             .sum::<f64>()
             / 1000.0;
@@ -6,6 +6,22 @@ use itertools::izip;
 
 pub use crate::util::axis_aligned_bounding_box::BoundingBox;
 
+impl BoundingBox {
+    /// The part of `ray` inside the box, as distances along it, ignoring anything behind the
+    /// ray's origin
+    pub fn distances_inside(&self, ray: &Ray) -> Interval {
+        (0..3)
+            .map(|axis| {
+                let interval = self.bounds[axis];
+                Interval::new(
+                    (interval.get_min() - ray.origin[axis]) / ray.direction[axis],
+                    (interval.get_max() - ray.origin[axis]) / ray.direction[axis],
+                )
+            })
+            .fold(Interval::new(0.0, f64::INFINITY), Interval::intersection)
+    }
+}
+
 impl IntersectP for BoundingBox {
     fn intersect(&self, ray: &Ray) -> bool {
         let mut t_interval_in_bounds = Interval::infinite();
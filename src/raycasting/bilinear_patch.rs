@@ -0,0 +1,357 @@
+use crate::materials::Material;
+use crate::math::{Affine3, Vec2, Vec3};
+use crate::statistics;
+use crate::textures::OpacityMask;
+use crate::util::float_error::gamma;
+use crate::validation::SceneValidator;
+
+use super::{
+    BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray, SampleSurface,
+    SurfaceSample, Transform,
+};
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+use std::sync::Arc;
+
+/// A quadrilateral whose corners don't have to lie in a plane
+///
+/// The surface is the bilinear interpolation of the four corners, which are listed in order
+/// around the edge, as they are in a .obj face. Surface parameters (u, v) are (0, 0) at the
+/// first corner, (1, 0) at the second, (1, 1) at the third and (0, 1) at the fourth, and
+/// normals and texture coordinates are interpolated in the same way. Unlike splitting the
+/// quad into two triangles, this doesn't put a crease along either diagonal.
+#[derive(Debug, Clone)]
+pub struct BilinearPatch {
+    pub vertices: [Vec3; 4],
+    pub normals: [Vec3; 4],
+    /// Surface coordinates at each vertex
+    pub uvs: [Vec2; 4],
+    pub material: Arc<dyn Material>,
+    /// Where the patch is cut away, if anywhere
+    pub opacity: Option<Arc<OpacityMask>>,
+}
+
+/// The weight of each corner at surface parameters (`u`, `v`)
+fn bilinear_weights(u: f64, v: f64) -> [f64; 4] {
+    [(1.0 - u) * (1.0 - v), u * (1.0 - v), u * v, (1.0 - u) * v]
+}
+
+impl BilinearPatch {
+    /// Texture coordinates for the corners of patches that don't have any
+    pub fn default_uvs() -> [Vec2; 4] {
+        [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(0.0, 1.0),
+        ]
+    }
+
+    /// The point at surface parameters (`u`, `v`)
+    pub fn point_at(&self, u: f64, v: f64) -> Vec3 {
+        bilinear_weights(u, v)
+            .iter()
+            .zip(self.vertices.iter())
+            .fold(Vec3::zeros(), |acc, (&weight, vertex)| {
+                acc + vertex * weight
+            })
+    }
+
+    /// The derivatives of [point_at()](BilinearPatch::point_at) with respect to u and v
+    fn partial_derivatives(&self, u: f64, v: f64) -> (Vec3, Vec3) {
+        let [p00, p10, p11, p01] = self.vertices;
+        (
+            (p10 - p00) * (1.0 - v) + (p11 - p01) * v,
+            (p01 - p00) * (1.0 - u) + (p11 - p10) * u,
+        )
+    }
+
+    /// The surface normal at (`u`, `v`), interpolated from the vertex normals
+    fn shading_normal(&self, u: f64, v: f64) -> Vec3 {
+        bilinear_weights(u, v)
+            .iter()
+            .zip(self.normals.iter())
+            .fold(Vec3::zeros(), |acc, (&weight, normal)| {
+                acc + normal * weight
+            })
+            .normalize()
+    }
+
+    /// The surface parameters and distance of the nearest intersection with `ray`
+    ///
+    /// This is the method from Reshetov, "Cool Patches: A Geometric Approach to Ray/Bilinear
+    /// Patch Intersections" (Ray Tracing Gems, chapter 8). Each line of constant u across
+    /// the patch is a straight segment, and the values of u where the ray meets one of these
+    /// segments are the roots of a quadratic. The position along the segment then gives v.
+    fn intersect_parameters(&self, ray: &Ray) -> Option<(f64, f64, f64)> {
+        let [p00, p10, p11, p01] = self.vertices;
+        let q00 = p00 - ray.origin;
+        let q10 = p10 - ray.origin;
+        let e00 = p01 - p00;
+        let e11 = p11 - p10;
+        let normal = (p10 - p00).cross(&(p01 - p11));
+        let a = q00.cross(&ray.direction).dot(&e00);
+        let c = normal.dot(&ray.direction);
+        let b = q10.cross(&ray.direction).dot(&e11) - (a + c);
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let roots = if c == 0.0 {
+            // The patch is a parallelogram seen edge-on along u, so the quadratic is linear
+            [-a / b, f64::NAN]
+        } else {
+            // Computed so as not to subtract nearly equal values
+            let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+            [q / c, a / q]
+        };
+        let mut nearest: Option<(f64, f64, f64)> = None;
+        for u in IntoIterator::into_iter(roots).filter(|u| (0.0..=1.0).contains(u)) {
+            let start = q00 * (1.0 - u) + q10 * u;
+            let along = e00 * (1.0 - u) + e11 * u;
+            let n = ray.direction.cross(&along);
+            let n_squared = n.norm_squared();
+            if n_squared == 0.0 {
+                continue;
+            }
+            let m = n.cross(&start);
+            let distance = m.dot(&along) / n_squared;
+            let v = m.dot(&ray.direction) / n_squared;
+            let is_nearer = match nearest {
+                Some((_, _, nearest)) => distance < nearest,
+                None => true,
+            };
+            if distance > 0.0 && (0.0..=1.0).contains(&v) && is_nearer {
+                nearest = Some((u, v, distance));
+            }
+        }
+        nearest
+    }
+}
+
+impl Transform for BilinearPatch {
+    fn transform(&self, transformation: &Affine3) -> Self {
+        BilinearPatch {
+            vertices: self
+                .vertices
+                .map(|vertex| transformation.transform_point(&vertex)),
+            normals: self
+                .normals
+                .map(|normal| transformation.transform_normal(&normal)),
+            uvs: self.uvs,
+            material: Arc::clone(&self.material),
+            opacity: self.opacity.clone(),
+        }
+    }
+}
+
+impl Intersect for BilinearPatch {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        statistics::count_triangle_test();
+        let (u, v, distance) = self.intersect_parameters(ray)?;
+        let weights = bilinear_weights(u, v);
+        let uv = weights
+            .iter()
+            .zip(self.uvs.iter())
+            .fold(Vec2::new(0.0, 0.0), |acc, (&weight, &uv)| acc + uv * weight);
+        if self
+            .opacity
+            .as_ref()
+            .is_some_and(|mask| !mask.is_opaque(&uv))
+        {
+            return None;
+        }
+        let location = self.point_at(u, v);
+        let location_error = weights
+            .iter()
+            .zip(self.vertices.iter())
+            .map(|(&weight, vertex)| (vertex * weight).abs())
+            .fold(Vec3::zeros(), |a, e| a + e)
+            * gamma(10);
+        let normal = self.shading_normal(u, v);
+        let (dp_du, dp_dv) = self.partial_derivatives(u, v);
+        let along_u = dp_du - normal * normal.dot(&dp_du);
+        let tangent = if along_u.norm_squared() > 0.0 {
+            along_u.normalize()
+        } else {
+            dp_dv.cross(&normal).normalize()
+        };
+        Some(IntersectionInfo {
+            distance,
+            location,
+            location_error,
+            normal,
+            tangent,
+            cotangent: normal.cross(&tangent),
+            retro: -ray.direction,
+            uv,
+            material: Arc::clone(&self.material),
+            object_id: 0,
+            footprint: None,
+            time: 0.0,
+        })
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        self.vertices
+            .iter()
+            .for_each(|vertex| validator.check_vertex(vertex));
+        self.normals
+            .iter()
+            .for_each(|normal| validator.check_normal(normal));
+        validator.check_material(&self.material);
+    }
+}
+
+impl HasBoundingBox for BilinearPatch {
+    fn bounding_box(&self) -> BoundingBox {
+        // The patch is a weighted average of its corners, so it lies within their bounds
+        BoundingBox::from_points(&self.vertices)
+    }
+}
+
+impl Primitive for BilinearPatch {
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(self.transform(transformation))
+    }
+}
+
+impl SampleSurface for BilinearPatch {
+    /// The area, found by integrating over an 8×8 grid of cells
+    ///
+    /// This is exact for flat parallelograms, and very close for any patch that isn't
+    /// badly twisted.
+    fn surface_area(&self) -> f64 {
+        const CELLS: usize = 8;
+        let cell_size = 1.0 / CELLS as f64;
+        (0..CELLS * CELLS)
+            .map(|cell| {
+                let u = ((cell % CELLS) as f64 + 0.5) * cell_size;
+                let v = ((cell / CELLS) as f64 + 0.5) * cell_size;
+                let (dp_du, dp_dv) = self.partial_derivatives(u, v);
+                dp_du.cross(&dp_dv).norm()
+            })
+            .sum::<f64>()
+            * cell_size
+            * cell_size
+    }
+
+    /// A point chosen uniformly over the surface parameters
+    ///
+    /// Where the patch is stretched, points are spread more thinly, and the density is
+    /// lower in proportion.
+    fn sample_surface(&self, rng: &mut dyn RngCore) -> SurfaceSample {
+        let u: f64 = rng.sample(Open01);
+        let v: f64 = rng.sample(Open01);
+        let (dp_du, dp_dv) = self.partial_derivatives(u, v);
+        SurfaceSample {
+            location: self.point_at(u, v),
+            normal: self.shading_normal(u, v),
+            pdf: 1.0 / dp_du.cross(&dp_dv).norm(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+
+    use quickcheck_macros::quickcheck;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// A patch over the unit square whose (1, 1) corner is raised, so that it isn't flat
+    fn twisted_patch() -> BilinearPatch {
+        let vertices = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let mut target = BilinearPatch {
+            vertices,
+            normals: [Vec3::unit_z(); 4],
+            uvs: BilinearPatch::default_uvs(),
+            material: Arc::new(LambertianMaterial::new_dummy()),
+            opacity: None,
+        };
+        // The true surface normal at each corner, so that shading matches the geometry
+        target.normals = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(|(u, v)| {
+            let (dp_du, dp_dv) = target.partial_derivatives(u, v);
+            dp_du.cross(&dp_dv).normalize()
+        });
+        target
+    }
+
+    #[quickcheck]
+    fn ray_hits_patch_at_aimed_point(u: f64, v: f64) -> bool {
+        let u = u.abs().fract();
+        let v = v.abs().fract();
+        let target = twisted_patch();
+        let point = target.point_at(u, v);
+        let origin = Vec3::new(0.3, -0.2, 3.0);
+        let ray = Ray::new(origin, point - origin);
+        match target.intersect(&ray) {
+            Some(info) => {
+                (info.location - point).norm() < 0.000_001
+                    && (info.distance - (point - origin).norm()).abs() < 0.000_001
+                    && (info.uv.x() - u).abs() < 0.000_001
+                    && (info.uv.y() - v).abs() < 0.000_001
+            }
+            None => false,
+        }
+    }
+
+    #[test]
+    fn ray_outside_patch_does_not_intersect() {
+        let target = twisted_patch();
+        for &(x, y) in &[(-0.1, 0.5), (1.1, 0.5), (0.5, -0.1), (0.5, 1.1)] {
+            let ray = Ray::new(Vec3::new(x, y, 5.0), -Vec3::unit_z());
+            assert!(target.intersect(&ray).is_none());
+        }
+    }
+
+    #[test]
+    fn ray_crossing_twisted_patch_twice_hits_nearest_point() {
+        // This ray is in the plane x = y, over which the patch's height is x². It crosses
+        // the surface at x = 0.2 and again at x = 0.8.
+        let target = twisted_patch();
+        let origin = Vec3::new(0.0, 0.0, -0.16);
+        let ray = Ray::new(origin, Vec3::new(1.0, 1.0, 1.0));
+        let info = target.intersect(&ray).unwrap();
+        assert!((info.location - Vec3::new(0.2, 0.2, 0.04)).norm() < 0.000_001);
+    }
+
+    #[test]
+    fn normals_and_frame_follow_curved_surface() {
+        let target = twisted_patch();
+        let ray = Ray::new(Vec3::new(0.5, 0.5, 5.0), -Vec3::unit_z());
+        let info = target.intersect(&ray).unwrap();
+        // The surface is z = xy, whose normal is (-y, -x, 1). It's interpolated from the
+        // corners, so it's only close to that in between them.
+        let expected = Vec3::new(-0.5, -0.5, 1.0).normalize();
+        assert!(info.normal.dot(&expected) > 0.995);
+        assert!(info.tangent.dot(&info.normal).abs() < 0.000_000_001);
+        assert!((info.tangent.cross(&info.cotangent) - info.normal).norm() < 0.000_000_001);
+    }
+
+    #[test]
+    fn flat_patch_area_and_density_match_parallelogram() {
+        let target = BilinearPatch {
+            vertices: [
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(3.0, 3.0, 0.0),
+                Vec3::new(1.0, 3.0, 0.0),
+            ],
+            ..twisted_patch()
+        };
+        assert!((target.surface_area() - 6.0).abs() < 0.000_000_001);
+        let sample = target.sample_surface(&mut StdRng::seed_from_u64(0));
+        assert!((sample.pdf - 1.0 / 6.0).abs() < 0.000_000_001);
+    }
+}
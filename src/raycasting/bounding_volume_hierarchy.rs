@@ -1,9 +1,17 @@
 use crate::math::Vec3;
+use crate::statistics;
+use crate::util::morton::morton_order_value_3d;
+use crate::util::normalizer::Point3Normalizer;
+use crate::validation::{SceneIssue, SceneValidator};
 
+use super::ray_packet::closest_in_each_lane;
 use super::{
-    Aggregate, BoundingBox, HasBoundingBox, Intersect, IntersectP, IntersectionInfo, Primitive, Ray,
+    Aggregate, BoundingBox, HasBoundingBox, Intersect, IntersectP, IntersectionInfo, Lanes,
+    Primitive, Ray, RayPacket,
 };
 
+use rayon::prelude::*;
+
 use std::cmp::Ordering;
 use std::sync::Arc;
 
@@ -15,16 +23,71 @@ use std::sync::Arc;
 /// Each node knows the overall bounds of all it's children, which means that a ray that
 /// doesn't intersect the [BoundingBox](BoundingBox) of the node doesn't intersect any of
 /// the primitives stored in it's children.
-pub enum BoundingVolumeHierarchy {
-    Node {
-        bounds: BoundingBox,
-        left: Box<BoundingVolumeHierarchy>,
-        right: Box<BoundingVolumeHierarchy>,
-    },
-    Leaf {
-        bounds: BoundingBox,
-        primitives: Vec<Arc<dyn Primitive>>,
-    },
+///
+/// The nodes are stored in a single array, in depth-first order, and the primitives in
+/// another, with each leaf owning a contiguous range of them. Building the tree makes only
+/// a few large allocations, and a ray's path through it stays close together in memory.
+///
+/// Primitives with [unbounded](BoundingBox::is_unbounded) bounding boxes, such as
+/// [planes](super::Plane), would make the bounds of every node above them infinite, so
+/// they're kept out of the tree in a list of their own and tested against every ray.
+///
+/// For animated scenes, primitives can be moved with
+/// [update_primitives()](BoundingVolumeHierarchy::update_primitives) and the bounds then
+/// brought up to date with [refit()](BoundingVolumeHierarchy::refit), which is much faster
+/// than building a new tree.
+pub struct BoundingVolumeHierarchy {
+    nodes: Vec<Node>,
+    primitives: Vec<Arc<dyn Primitive>>,
+    unbounded: Vec<Arc<dyn Primitive>>,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct Node {
+    pub(super) bounds: BoundingBox,
+    pub(super) contents: NodeContents,
+
+    /// Some primitive below this node has moved since `bounds` was calculated
+    dirty: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(super) enum NodeContents {
+    /// The left child immediately follows this node, and the right child is `right_offset`
+    /// places after it
+    ///
+    /// Offsets are relative so that subtrees built separately can be joined without
+    /// renumbering them.
+    Interior { right_offset: usize },
+
+    /// Primitives `start..start + count`
+    Leaf { start: usize, count: usize },
+}
+
+impl Node {
+    fn leaf(bounds: BoundingBox, start: usize, count: usize) -> Node {
+        Node {
+            bounds,
+            contents: NodeContents::Leaf { start, count },
+            dirty: false,
+        }
+    }
+
+    fn interior(bounds: BoundingBox, right_offset: usize) -> Node {
+        Node {
+            bounds,
+            contents: NodeContents::Interior { right_offset },
+            dirty: false,
+        }
+    }
+}
+
+/// Append the nodes built separately for a left and right subtree to `nodes`, under a new
+/// node with `bounds`
+fn join_subtrees(nodes: &mut Vec<Node>, bounds: BoundingBox, left: Vec<Node>, right: Vec<Node>) {
+    nodes.push(Node::interior(bounds, 1 + left.len()));
+    nodes.extend(left);
+    nodes.extend(right);
 }
 
 fn centre(bounds: &BoundingBox) -> Vec3 {
@@ -35,42 +98,327 @@ fn centre(bounds: &BoundingBox) -> Vec3 {
     )
 }
 
+/// Below this many primitives a subtree is built on the current thread, because the cost
+/// of handing work to another thread outweighs the gain
+const PARALLEL_BUILD_THRESHOLD: usize = 4096;
+
 fn heuristic_split(primitives: &mut [Arc<dyn Primitive>], bounds: &BoundingBox) -> usize {
     let largest_dimension = bounds.largest_dimension();
-    primitives.sort_unstable_by(|a, b| {
+    let compare = |a: &Arc<dyn Primitive>, b: &Arc<dyn Primitive>| {
         centre(&a.bounding_box())[largest_dimension]
             .partial_cmp(&centre(&b.bounding_box())[largest_dimension])
             .unwrap_or(Ordering::Equal)
-    });
+    };
+    if primitives.len() >= PARALLEL_BUILD_THRESHOLD {
+        primitives.par_sort_unstable_by(compare);
+    } else {
+        primitives.sort_unstable_by(compare);
+    }
     primitives.len() / 2
 }
 
+/// Whether `primitive` can go in the tree, rather than being
+/// [unbounded](BoundingBox::is_unbounded)
+fn is_bounded(primitive: &Arc<dyn Primitive>) -> bool {
+    !primitive.bounding_box().is_unbounded()
+}
+
+fn bounds_of(primitives: &[Arc<dyn Primitive>]) -> BoundingBox {
+    if primitives.len() >= PARALLEL_BUILD_THRESHOLD {
+        primitives
+            .par_iter()
+            .map(|p| p.bounding_box())
+            .reduce(BoundingBox::empty, |a, b| a.union(&b))
+    } else {
+        primitives
+            .iter()
+            .fold(BoundingBox::empty(), |acc, p| acc.union(&p.bounding_box()))
+    }
+}
+
+/// Index of the first primitive in the second half of a run of sorted Morton codes
+///
+/// The run is split where the highest bit that differs between the first and last codes
+/// changes from zero to one, so that each half occupies its own region of space.
+fn morton_split(codes: &[u32]) -> usize {
+    let first = codes[0];
+    let last = codes[codes.len() - 1];
+    if first == last {
+        return codes.len() / 2;
+    }
+    let highest_differing_bit = 31 - (first ^ last).leading_zeros();
+    let prefix_mask = !0u32 << highest_differing_bit;
+    let split_prefix = last & prefix_mask;
+    codes.partition_point(|&code| (code & prefix_mask) < split_prefix)
+}
+
 impl BoundingVolumeHierarchy {
     pub fn build(primitives: &mut [Arc<dyn Primitive>]) -> Self {
         BoundingVolumeHierarchy::build_from_slice(primitives)
     }
 
     pub fn build_from_slice(primitives: &mut [Arc<dyn Primitive>]) -> Self {
-        let bounds = primitives
-            .iter()
-            .fold(BoundingBox::empty(), |acc, p| acc.union(&p.bounding_box()));
+        let (mut primitives, unbounded): (Vec<_>, Vec<_>) =
+            primitives.iter().cloned().partition(is_bounded);
+        let mut nodes = Vec::with_capacity(2 * primitives.len().max(1) - 1);
+        // Building sorts the primitives into the order of the leaves
+        BoundingVolumeHierarchy::build_nodes(&mut nodes, &mut primitives, 0);
+        BoundingVolumeHierarchy {
+            nodes,
+            primitives,
+            unbounded,
+        }
+    }
+
+    /// Append the nodes of a tree for `primitives`, which start at index `first` of the
+    /// whole tree's primitives, to `nodes`
+    fn build_nodes(nodes: &mut Vec<Node>, primitives: &mut [Arc<dyn Primitive>], first: usize) {
+        let bounds = bounds_of(primitives);
+        if primitives.len() <= 1 {
+            nodes.push(Node::leaf(bounds, first, primitives.len()));
+            return;
+        }
+        let pivot = heuristic_split(primitives, &bounds);
+        let parallel = primitives.len() >= PARALLEL_BUILD_THRESHOLD;
+        let (left, right) = primitives.split_at_mut(pivot);
+        if parallel {
+            let build = |primitives: &mut [Arc<dyn Primitive>], first| {
+                let mut nodes = vec![];
+                BoundingVolumeHierarchy::build_nodes(&mut nodes, primitives, first);
+                nodes
+            };
+            let (left, right) = rayon::join(|| build(left, first), || build(right, first + pivot));
+            join_subtrees(nodes, bounds, left, right);
+        } else {
+            let index = nodes.len();
+            nodes.push(Node::interior(bounds, 0));
+            BoundingVolumeHierarchy::build_nodes(nodes, left, first);
+            nodes[index].contents = NodeContents::Interior {
+                right_offset: nodes.len() - index,
+            };
+            BoundingVolumeHierarchy::build_nodes(nodes, right, first + pivot);
+        }
+    }
+
+    /// Build a linear BVH, which orders the primitives along a Morton curve
+    ///
+    /// This is much faster to build than [build()](BoundingVolumeHierarchy::build) for
+    /// large meshes, at the cost of a tree that is somewhat slower to traverse.
+    pub fn build_lbvh(primitives: &[Arc<dyn Primitive>]) -> Self {
+        let (primitives, unbounded): (Vec<_>, Vec<_>) =
+            primitives.iter().cloned().partition(is_bounded);
+        let centres: Vec<Vec3> = primitives
+            .par_iter()
+            .map(|p| centre(&p.bounding_box()))
+            .collect();
+        let normalizer = Point3Normalizer::new(BoundingBox::from_points(&centres));
+        let mut ordered: Vec<(u32, Arc<dyn Primitive>)> = centres
+            .par_iter()
+            .zip(primitives.par_iter())
+            .map(|(&c, p)| {
+                (
+                    morton_order_value_3d(normalizer.normalize_and_clamp(c)),
+                    p.clone(),
+                )
+            })
+            .collect();
+        ordered.par_sort_unstable_by_key(|(code, _)| *code);
+        let (codes, primitives): (Vec<u32>, Vec<Arc<dyn Primitive>>) = ordered.into_iter().unzip();
+        let mut nodes = Vec::with_capacity(2 * primitives.len().max(1) - 1);
+        BoundingVolumeHierarchy::build_lbvh_nodes(&mut nodes, &codes, &primitives, 0);
+        BoundingVolumeHierarchy {
+            nodes,
+            primitives,
+            unbounded,
+        }
+    }
+
+    fn build_lbvh_nodes(
+        nodes: &mut Vec<Node>,
+        codes: &[u32],
+        primitives: &[Arc<dyn Primitive>],
+        first: usize,
+    ) {
         if primitives.len() <= 1 {
-            let primitives = primitives.to_vec();
-            BoundingVolumeHierarchy::Leaf { bounds, primitives }
+            nodes.push(Node::leaf(bounds_of(primitives), first, primitives.len()));
+            return;
+        }
+        let pivot = morton_split(codes);
+        let (left_codes, right_codes) = codes.split_at(pivot);
+        let (left, right) = primitives.split_at(pivot);
+        if primitives.len() >= PARALLEL_BUILD_THRESHOLD {
+            let build = |codes, primitives, first| {
+                let mut nodes = vec![];
+                BoundingVolumeHierarchy::build_lbvh_nodes(&mut nodes, codes, primitives, first);
+                nodes
+            };
+            let (left, right) = rayon::join(
+                || build(left_codes, left, first),
+                || build(right_codes, right, first + pivot),
+            );
+            let bounds = left[0].bounds.union(&right[0].bounds);
+            join_subtrees(nodes, bounds, left, right);
         } else {
-            let pivot = heuristic_split(primitives, &bounds);
-            let left = Box::new(BoundingVolumeHierarchy::build_from_slice(
-                &mut primitives[0..pivot],
-            ));
-            let right = Box::new(BoundingVolumeHierarchy::build_from_slice(
-                &mut primitives[pivot..],
-            ));
-            BoundingVolumeHierarchy::Node {
-                bounds,
-                left,
-                right,
+            let index = nodes.len();
+            nodes.push(Node::interior(BoundingBox::empty(), 0));
+            BoundingVolumeHierarchy::build_lbvh_nodes(nodes, left_codes, left, first);
+            let right_offset = nodes.len() - index;
+            BoundingVolumeHierarchy::build_lbvh_nodes(nodes, right_codes, right, first + pivot);
+            nodes[index] = Node::interior(
+                nodes[index + 1]
+                    .bounds
+                    .union(&nodes[index + right_offset].bounds),
+                right_offset,
+            );
+        }
+    }
+
+    pub(super) fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub(super) fn primitives(&self) -> &[Arc<dyn Primitive>] {
+        &self.primitives
+    }
+
+    /// The primitives that are kept out of the tree because their bounds are infinite
+    pub(super) fn unbounded(&self) -> &[Arc<dyn Primitive>] {
+        &self.unbounded
+    }
+
+    /// Indices of the children of the interior node at `index`
+    pub(super) fn children(&self, index: usize) -> Option<(usize, usize)> {
+        match self.nodes[index].contents {
+            NodeContents::Interior { right_offset } => Some((index + 1, index + right_offset)),
+            NodeContents::Leaf { .. } => None,
+        }
+    }
+
+    /// Call `update` with each primitive in the tree, allowing it to be replaced
+    ///
+    /// `update` should return `true` if it moved the primitive (for example by replacing it
+    /// with a transformed copy), which marks it as dirty. The bounds of dirty parts of the
+    /// tree are out of date, so rays may miss primitives that have moved until
+    /// [refit()](BoundingVolumeHierarchy::refit) is called.
+    ///
+    /// Returns whether any primitive was marked as dirty.
+    pub fn update_primitives<F: FnMut(&mut Arc<dyn Primitive>) -> bool>(
+        &mut self,
+        update: &mut F,
+    ) -> bool {
+        // Unbounded primitives aren't in any node, so moving them doesn't make the tree dirty
+        for primitive in self.unbounded.iter_mut() {
+            update(primitive);
+        }
+        self.update_node(0, update)
+    }
+
+    fn update_node<F: FnMut(&mut Arc<dyn Primitive>) -> bool>(
+        &mut self,
+        index: usize,
+        update: &mut F,
+    ) -> bool {
+        match self.nodes[index].contents {
+            NodeContents::Interior { right_offset } => {
+                let left_moved = self.update_node(index + 1, update);
+                let right_moved = self.update_node(index + right_offset, update);
+                self.nodes[index].dirty |= left_moved || right_moved;
+            }
+            NodeContents::Leaf { start, count } => {
+                for primitive in self.primitives[start..start + count].iter_mut() {
+                    self.nodes[index].dirty |= update(primitive);
+                }
             }
         }
+        self.nodes[index].dirty
+    }
+
+    /// Whether any primitive has moved since the bounds were last updated
+    pub fn is_dirty(&self) -> bool {
+        self.nodes[0].dirty
+    }
+
+    /// Recalculate the bounds of every dirty node, working up from the leaves
+    ///
+    /// The structure of the tree doesn't change, so it becomes less efficient to traverse
+    /// as primitives move further from where they were when it was built.
+    pub fn refit(&mut self) {
+        self.refit_node(0);
+    }
+
+    fn refit_node(&mut self, index: usize) {
+        if !self.nodes[index].dirty {
+            return;
+        }
+        self.nodes[index].bounds = match self.nodes[index].contents {
+            NodeContents::Interior { right_offset } => {
+                self.refit_node(index + 1);
+                self.refit_node(index + right_offset);
+                self.nodes[index + 1]
+                    .bounds
+                    .union(&self.nodes[index + right_offset].bounds)
+            }
+            NodeContents::Leaf { start, count } => {
+                bounds_of(&self.primitives[start..start + count])
+            }
+        };
+        self.nodes[index].dirty = false;
+    }
+
+    fn intersect_node(&self, index: usize, ray: &Ray) -> Option<IntersectionInfo> {
+        statistics::count_bvh_node_visited();
+        let node = &self.nodes[index];
+        if !node.bounds.intersect(ray) {
+            return None;
+        }
+        match node.contents {
+            NodeContents::Interior { right_offset } => closest_intersection(
+                self.intersect_node(index + 1, ray),
+                self.intersect_node(index + right_offset, ray),
+            ),
+            NodeContents::Leaf { start, count } => self.primitives[start..start + count]
+                .iter()
+                .map(|elem| elem.intersect(ray))
+                .fold(None, closest_intersection),
+        }
+    }
+
+    fn intersect_node_packet(
+        &self,
+        index: usize,
+        packet: &RayPacket,
+    ) -> Lanes<Option<IntersectionInfo>> {
+        statistics::count_bvh_node_visited();
+        let node = &self.nodes[index];
+        let packet = packet.masked(&packet.intersect_bounds(&node.bounds));
+        if !packet.any_active() {
+            return Default::default();
+        }
+        match node.contents {
+            NodeContents::Interior { right_offset } => closest_in_each_lane(
+                self.intersect_node_packet(index + 1, &packet),
+                self.intersect_node_packet(index + right_offset, &packet),
+            ),
+            NodeContents::Leaf { start, count } => self.primitives[start..start + count]
+                .iter()
+                .map(|elem| elem.intersect_packet(&packet))
+                .fold(Default::default(), closest_in_each_lane),
+        }
+    }
+
+    fn intersect_node_any(&self, index: usize, ray: &Ray, max_distance: f64) -> bool {
+        statistics::count_bvh_node_visited();
+        let node = &self.nodes[index];
+        node.bounds.intersect(ray)
+            && match node.contents {
+                NodeContents::Interior { right_offset } => {
+                    self.intersect_node_any(index + 1, ray, max_distance)
+                        || self.intersect_node_any(index + right_offset, ray, max_distance)
+                }
+                NodeContents::Leaf { start, count } => self.primitives[start..start + count]
+                    .iter()
+                    .any(|elem| elem.intersect_any(ray, max_distance)),
+            }
     }
 }
 
@@ -93,39 +441,252 @@ fn closest_intersection(
 
 impl Intersect for BoundingVolumeHierarchy {
     fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
-        match self {
-            BoundingVolumeHierarchy::Node {
-                bounds,
-                left,
-                right,
-            } => {
-                if bounds.intersect(ray) {
-                    closest_intersection(left.intersect(ray), right.intersect(ray))
-                } else {
-                    None
-                }
-            }
-            BoundingVolumeHierarchy::Leaf { bounds, primitives } => {
-                if bounds.intersect(ray) {
-                    primitives
-                        .iter()
-                        .map(|elem| elem.intersect(ray))
-                        .fold(None, closest_intersection)
-                } else {
-                    None
-                }
-            }
+        self.unbounded
+            .iter()
+            .map(|elem| elem.intersect(ray))
+            .fold(self.intersect_node(0, ray), closest_intersection)
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes<Option<IntersectionInfo>> {
+        self.unbounded
+            .iter()
+            .map(|elem| elem.intersect_packet(packet))
+            .fold(self.intersect_node_packet(0, packet), closest_in_each_lane)
+    }
+
+    fn intersect_any(&self, ray: &Ray, max_distance: f64) -> bool {
+        self.unbounded
+            .iter()
+            .any(|elem| elem.intersect_any(ray, max_distance))
+            || self.intersect_node_any(0, ray, max_distance)
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        if self.primitives.is_empty() && self.unbounded.is_empty() {
+            validator.report(SceneIssue::EmptyAggregate);
+        }
+        for primitive in self.primitives.iter().chain(self.unbounded.iter()) {
+            primitive.validate(validator);
         }
     }
 }
 
 impl HasBoundingBox for BoundingVolumeHierarchy {
     fn bounding_box(&self) -> BoundingBox {
-        BoundingBox::empty()
+        self.unbounded
+            .iter()
+            .fold(self.nodes[0].bounds, |acc, p| acc.union(&p.bounding_box()))
     }
 }
 
 impl Aggregate for BoundingVolumeHierarchy {}
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+    use crate::math::Affine3;
+    use crate::raycasting::{Plane, Sphere};
+
+    fn row_of_spheres() -> BoundingVolumeHierarchy {
+        let material = Arc::new(LambertianMaterial::new_dummy());
+        let mut spheres: Vec<Arc<dyn Primitive>> = (0..8)
+            .map(|i| {
+                Arc::new(Sphere::new(
+                    Vec3::new(i as f64 * 3.0, 0.0, 0.0),
+                    1.0,
+                    material.clone(),
+                )) as Arc<dyn Primitive>
+            })
+            .collect();
+        BoundingVolumeHierarchy::build(&mut spheres)
+    }
+
+    #[test]
+    fn intersect_any_finds_sphere_inside_max_distance() {
+        let target = row_of_spheres();
+        let ray = Ray::new(Vec3::new(9.0, 0.0, -5.0), Vec3::unit_z());
+        assert!(target.intersect_any(&ray, 10.0));
+    }
+
+    #[test]
+    fn intersect_any_ignores_sphere_beyond_max_distance() {
+        let target = row_of_spheres();
+        let ray = Ray::new(Vec3::new(9.0, 0.0, -5.0), Vec3::unit_z());
+        assert!(!target.intersect_any(&ray, 3.0));
+    }
+
+    #[test]
+    fn intersect_any_is_false_when_ray_misses() {
+        let target = row_of_spheres();
+        let ray = Ray::new(Vec3::new(10.5, 0.0, -5.0), Vec3::unit_z());
+        assert!(!target.intersect_any(&ray, f64::INFINITY));
+    }
+
+    fn grid_of_spheres(size: usize) -> Vec<Arc<dyn Primitive>> {
+        let material = Arc::new(LambertianMaterial::new_dummy());
+        (0..size * size)
+            .map(|i| {
+                Arc::new(Sphere::new(
+                    Vec3::new((i % size) as f64 * 3.0, (i / size) as f64 * 3.0, 0.0),
+                    1.0,
+                    material.clone(),
+                )) as Arc<dyn Primitive>
+            })
+            .collect()
+    }
+
+    fn hit_points(target: &BoundingVolumeHierarchy, size: usize) -> Vec<Option<Vec3>> {
+        (0..size * size * 4)
+            .map(|i| {
+                let x = (i % (size * 2)) as f64 * 1.5 + 0.25;
+                let y = (i / (size * 2)) as f64 * 1.5 - 0.25;
+                let ray = Ray::new(Vec3::new(x, y, -5.0), Vec3::unit_z());
+                target.intersect(&ray).map(|info| info.location)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parallel_build_finds_same_intersections_as_brute_force() {
+        let size = 70;
+        let mut primitives = grid_of_spheres(size);
+        assert!(primitives.len() > PARALLEL_BUILD_THRESHOLD);
+        let target = BoundingVolumeHierarchy::build(&mut primitives);
+        let brute_force = BoundingVolumeHierarchy {
+            nodes: vec![Node::leaf(target.bounding_box(), 0, size * size)],
+            primitives: grid_of_spheres(size),
+            unbounded: vec![],
+        };
+        assert!(hit_points(&target, size) == hit_points(&brute_force, size));
+    }
+
+    #[test]
+    fn leaves_own_consecutive_primitives_in_depth_first_order() {
+        let size = 70;
+        for target in [
+            BoundingVolumeHierarchy::build(&mut grid_of_spheres(size)),
+            BoundingVolumeHierarchy::build_lbvh(&grid_of_spheres(size)),
+        ]
+        .iter()
+        {
+            assert!(target.nodes().len() == 2 * size * size - 1);
+            let mut next = 0;
+            for node in target.nodes() {
+                if let NodeContents::Leaf { start, count } = node.contents {
+                    assert!(start == next && count == 1);
+                    next += count;
+                }
+            }
+            assert!(next == target.primitives().len());
+        }
+    }
+
+    #[test]
+    fn lbvh_finds_same_intersections_as_bvh() {
+        let size = 70;
+        let mut primitives = grid_of_spheres(size);
+        let lbvh = BoundingVolumeHierarchy::build_lbvh(&primitives);
+        let bvh = BoundingVolumeHierarchy::build(&mut primitives);
+        assert!(hit_points(&lbvh, size) == hit_points(&bvh, size));
+    }
+
+    #[test]
+    fn unbounded_primitives_are_kept_out_of_tree() {
+        let mut primitives = grid_of_spheres(4);
+        primitives.push(Arc::new(Plane::new(
+            Vec3::unit_z(),
+            2.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        )));
+        for target in [
+            BoundingVolumeHierarchy::build(&mut primitives.clone()),
+            BoundingVolumeHierarchy::build_lbvh(&primitives),
+        ]
+        .iter()
+        {
+            assert!(!target.nodes()[0].bounds.is_unbounded());
+            assert!(target.unbounded().len() == 1);
+            assert!(target.bounding_box().is_unbounded());
+            // Between the spheres, rays go on to hit the plane
+            let between = Ray::new(Vec3::new(1.5, 1.5, -5.0), Vec3::unit_z());
+            assert!((target.intersect(&between).unwrap().distance - 7.0).abs() < 0.000000001);
+            assert!(target.intersect_any(&between, 8.0));
+            let on_sphere = Ray::new(Vec3::new(3.0, 3.0, -5.0), Vec3::unit_z());
+            assert!((target.intersect(&on_sphere).unwrap().distance - 4.0).abs() < 0.000000001);
+        }
+    }
+
+    #[test]
+    fn lbvh_bounds_contain_all_primitives() {
+        let primitives = grid_of_spheres(5);
+        let target = BoundingVolumeHierarchy::build_lbvh(&primitives);
+        let bounds = target.bounding_box();
+        for p in primitives.iter() {
+            let b = p.bounding_box();
+            assert!(bounds.contains_point(centre(&b)));
+        }
+    }
+
+    #[test]
+    fn lbvh_of_no_primitives_never_intersects() {
+        let target = BoundingVolumeHierarchy::build_lbvh(&[]);
+        let ray = Ray::new(Vec3::zeros(), Vec3::unit_z());
+        assert!(target.intersect(&ray).is_none());
+    }
+
+    #[test]
+    fn morton_split_separates_on_highest_differing_bit() {
+        assert!(morton_split(&[0b0001, 0b0011, 0b0100, 0b0111]) == 2);
+        assert!(morton_split(&[0b0001, 0b1000, 0b1001, 0b1111]) == 1);
+        assert!(morton_split(&[5, 5, 5, 5, 5]) == 2);
+    }
+
+    #[test]
+    fn refit_finds_moved_primitives() {
+        let size = 10;
+        let offset = Vec3::new(1.5, 0.0, 0.0);
+        let mut target = BoundingVolumeHierarchy::build(&mut grid_of_spheres(size));
+        let translation = Affine3::translation(&offset);
+        let mut index = 0;
+        let moved = target.update_primitives(&mut |primitive| {
+            // Move every other row of spheres
+            let moving = centre(&primitive.bounding_box()).y() % 6.0 == 0.0;
+            if moving {
+                *primitive = primitive.transform_primitive(&translation);
+            }
+            index += 1;
+            moving
+        });
+        assert!(moved && target.is_dirty());
+        assert!(index == size * size);
+        target.refit();
+        assert!(!target.is_dirty());
+
+        let mut expected: Vec<Arc<dyn Primitive>> = grid_of_spheres(size)
+            .iter()
+            .map(|primitive| {
+                if centre(&primitive.bounding_box()).y() % 6.0 == 0.0 {
+                    primitive.transform_primitive(&translation)
+                } else {
+                    primitive.clone()
+                }
+            })
+            .collect();
+        let rebuilt = BoundingVolumeHierarchy::build(&mut expected);
+        assert!(hit_points(&target, size) == hit_points(&rebuilt, size));
+        let (bounds, rebuilt_bounds) = (target.bounding_box(), rebuilt.bounding_box());
+        for axis in 0..3 {
+            assert!(bounds.bounds[axis].get_min() == rebuilt_bounds.bounds[axis].get_min());
+            assert!(bounds.bounds[axis].get_max() == rebuilt_bounds.bounds[axis].get_max());
+        }
+    }
+
+    #[test]
+    fn unchanged_primitives_are_not_dirty() {
+        let mut target = row_of_spheres();
+        assert!(!target.update_primitives(&mut |_| false));
+        assert!(!target.is_dirty());
+    }
+}
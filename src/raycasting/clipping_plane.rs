@@ -0,0 +1,144 @@
+use crate::materials::Material;
+use crate::math::{OrthonormalBasis, Vec2, Vec3};
+use crate::util::float_error::ray_plane_point_error;
+use crate::util::Interval;
+
+use super::{IntersectionInfo, Ray};
+
+use std::sync::Arc;
+
+/// A plane that cuts away everything in the [Scene](crate::scene::Scene) on one side of
+/// it, for section views
+///
+/// Geometry on the side the normal points towards is removed, so the normal should point
+/// at the camera. Where the cut goes through a closed object, the inside of the object
+/// is seen through the hole, unless the plane has a `cap` material, in which case the cut
+/// surface is filled in with it. Whether a point on the plane is inside an object is
+/// decided by whether the next surface along the ray faces away from it, so capping needs
+/// objects with outward-facing normals.
+///
+/// Only what rays hit is clipped; lights on the removed side still shine.
+#[derive(Clone, Debug)]
+pub struct ClippingPlane {
+    point: Vec3,
+    frame: OrthonormalBasis,
+    cap: Option<Arc<dyn Material>>,
+}
+
+impl ClippingPlane {
+    /// A plane through `point` that removes everything in front of `normal`
+    pub fn new(point: Vec3, normal: Vec3, cap: Option<Arc<dyn Material>>) -> ClippingPlane {
+        ClippingPlane {
+            point,
+            frame: OrthonormalBasis::from_normal(&normal.normalize()),
+            cap,
+        }
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.frame.normal
+    }
+
+    pub fn cap(&self) -> Option<&Arc<dyn Material>> {
+        self.cap.as_ref()
+    }
+
+    /// The distances along `ray`, from its origin onwards, that aren't cut away
+    pub fn kept_interval(&self, ray: &Ray) -> Interval {
+        let height = (ray.origin - self.point).dot(&self.frame.normal);
+        let rate = ray.direction.dot(&self.frame.normal);
+        if rate == 0.0 {
+            return if height > 0.0 {
+                Interval::empty()
+            } else {
+                Interval::new(0.0, f64::INFINITY)
+            };
+        }
+        let crossing = Interval::degenerate(-height / rate);
+        let kept = if rate > 0.0 {
+            crossing.expand_to_value(f64::NEG_INFINITY)
+        } else {
+            crossing.expand_to_value(f64::INFINITY)
+        };
+        kept.intersection(Interval::new(0.0, f64::INFINITY))
+    }
+
+    /// The cap surface where `ray` crosses the plane `distance` from its origin, or `None`
+    /// if the plane has no cap material
+    pub fn cap_intersection(&self, ray: &Ray, distance: f64) -> Option<IntersectionInfo> {
+        let material = self.cap.as_ref()?;
+        let location = ray.point_at(distance);
+        let offset = location - self.point;
+        Some(IntersectionInfo {
+            distance,
+            location,
+            location_error: ray_plane_point_error(
+                &ray.origin,
+                &(ray.direction * distance),
+                &self.point,
+            ),
+            normal: self.frame.normal,
+            tangent: self.frame.tangent,
+            cotangent: self.frame.cotangent,
+            retro: -ray.direction,
+            uv: Vec2::new(
+                offset.dot(&self.frame.tangent),
+                offset.dot(&self.frame.cotangent),
+            ),
+            material: Arc::clone(material),
+            object_id: 0,
+            footprint: None,
+            time: ray.time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+
+    fn plane() -> ClippingPlane {
+        ClippingPlane::new(
+            Vec3::new(0.0, 0.0, 1.0),
+            -Vec3::unit_z(),
+            Some(Arc::new(LambertianMaterial::new_dummy())),
+        )
+    }
+
+    #[test]
+    fn kept_interval_starts_where_ray_crosses_into_kept_side() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -2.0), Vec3::unit_z());
+        let interval = plane().kept_interval(&ray);
+        assert!((interval.get_min() - 3.0).abs() < 0.000000001);
+        assert!(interval.get_max() == f64::INFINITY);
+    }
+
+    #[test]
+    fn kept_interval_ends_where_ray_crosses_into_removed_side() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 3.0), -Vec3::unit_z());
+        let interval = plane().kept_interval(&ray);
+        assert!(interval.get_min() == 0.0);
+        assert!((interval.get_max() - 2.0).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn rays_that_never_cross_plane_are_kept_or_removed_entirely() {
+        let kept = Ray::new(Vec3::new(0.0, 0.0, 2.0), Vec3::unit_x());
+        let removed = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::unit_x());
+        assert!(plane().kept_interval(&kept).get_max() == f64::INFINITY);
+        assert!(plane().kept_interval(&removed).is_empty());
+        // Heading further into the removed side
+        let away = Ray::new(Vec3::new(0.0, 0.0, 0.0), -Vec3::unit_z());
+        assert!(plane().kept_interval(&away).is_empty());
+    }
+
+    #[test]
+    fn cap_faces_removed_side() {
+        let ray = Ray::new(Vec3::new(0.5, 0.0, -2.0), Vec3::unit_z());
+        let info = plane().cap_intersection(&ray, 3.0).unwrap();
+        assert!((info.location - Vec3::new(0.5, 0.0, 1.0)).norm() < 0.000000001);
+        assert!(info.normal.dot(&ray.direction) < 0.0);
+    }
+}
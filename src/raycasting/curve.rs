@@ -0,0 +1,482 @@
+use crate::materials::Material;
+use crate::math::{Affine3, Vec2, Vec3};
+
+use super::{BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray, Transform};
+
+use std::sync::Arc;
+
+/// How the surface of a [Curve](Curve) is shaded across its width
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CurveType {
+    /// A flat ribbon that always faces the ray
+    Flat,
+
+    /// A tube, which is still intersected as a ribbon facing the ray, but has normals that
+    /// curve around it
+    ///
+    /// This looks the same as a real tube as long as the curve is thin, as hair is.
+    Cylinder,
+}
+
+/// The parts of a curve that are shared by all of its segments
+#[derive(Debug)]
+struct CurveCommon {
+    control_points: [Vec3; 4],
+    widths: [f64; 2],
+    curve_type: CurveType,
+    material: Arc<dyn Material>,
+}
+
+/// A thin strand along a cubic Bézier curve, for hair and fur
+///
+/// The width changes linearly from one end to the other. Surface coordinates have u
+/// running along the curve from 0 to 1 and v running across it, and the tangent always
+/// points along the curve, which is what [HairMaterial](crate::materials::HairMaterial)
+/// expects.
+///
+/// A long, curly strand has a very loose bounding box, so it should be
+/// [split](Curve::segments) into several segments before it is put into a
+/// [BoundingVolumeHierarchy](super::BoundingVolumeHierarchy).
+#[derive(Clone, Debug)]
+pub struct Curve {
+    common: Arc<CurveCommon>,
+    u_min: f64,
+    u_max: f64,
+}
+
+/// Evaluate a cubic Bézier curve and its derivative at `u`
+fn evaluate_bezier(control_points: &[Vec3; 4], u: f64) -> (Vec3, Vec3) {
+    let lerp = |a: Vec3, b: Vec3| a * (1.0 - u) + b * u;
+    let [p0, p1, p2, p3] = *control_points;
+    let (q0, q1, q2) = (lerp(p0, p1), lerp(p1, p2), lerp(p2, p3));
+    let (r0, r1) = (lerp(q0, q1), lerp(q1, q2));
+    // The derivative of a cubic is three times the difference of the last two points
+    if (r1 - r0).norm_squared() > 0.0 {
+        (lerp(r0, r1), (r1 - r0) * 3.0)
+    } else {
+        (lerp(r0, r1), p3 - p0)
+    }
+}
+
+/// The control points of the part of a cubic Bézier curve from `u_min` to `u_max`
+///
+/// Each control point is the curve's blossom at that many copies of `u_max` and the rest
+/// `u_min`.
+fn bezier_segment(control_points: &[Vec3; 4], u_min: f64, u_max: f64) -> [Vec3; 4] {
+    let blossom = |u: [f64; 3]| {
+        let lerp = |t: f64, a: Vec3, b: Vec3| a * (1.0 - t) + b * t;
+        let [p0, p1, p2, p3] = *control_points;
+        let a = [lerp(u[0], p0, p1), lerp(u[0], p1, p2), lerp(u[0], p2, p3)];
+        let b = [lerp(u[1], a[0], a[1]), lerp(u[1], a[1], a[2])];
+        lerp(u[2], b[0], b[1])
+    };
+    [
+        blossom([u_min, u_min, u_min]),
+        blossom([u_min, u_min, u_max]),
+        blossom([u_min, u_max, u_max]),
+        blossom([u_max, u_max, u_max]),
+    ]
+}
+
+/// Split a cubic Bézier curve in half, giving the seven control points of the two halves,
+/// with the middle one shared
+fn split_bezier(cp: &[Vec3; 4]) -> [Vec3; 7] {
+    let mid = |a: Vec3, b: Vec3| (a + b) * 0.5;
+    let (q0, q1, q2) = (mid(cp[0], cp[1]), mid(cp[1], cp[2]), mid(cp[2], cp[3]));
+    let (r0, r1) = (mid(q0, q1), mid(q1, q2));
+    [cp[0], q0, r0, mid(r0, r1), r1, q2, cp[3]]
+}
+
+/// Where a ray hits a curve: the distance along the ray, and the surface coordinates
+struct CurveHit {
+    distance: f64,
+    u: f64,
+    v: f64,
+}
+
+impl Curve {
+    /// A single curve, with `widths` at its start and end
+    pub fn new(
+        control_points: [Vec3; 4],
+        widths: [f64; 2],
+        curve_type: CurveType,
+        material: Arc<dyn Material>,
+    ) -> Curve {
+        Curve {
+            common: Arc::new(CurveCommon {
+                control_points,
+                widths,
+                curve_type,
+                material,
+            }),
+            u_min: 0.0,
+            u_max: 1.0,
+        }
+    }
+
+    /// The same curve as [new()](Curve::new), split into `segment_count` equal parts of u
+    ///
+    /// The parts share one copy of the curve, and together they're intersected exactly like
+    /// the whole curve, but each has a tighter bounding box.
+    pub fn segments(
+        control_points: [Vec3; 4],
+        widths: [f64; 2],
+        curve_type: CurveType,
+        material: Arc<dyn Material>,
+        segment_count: usize,
+    ) -> Vec<Arc<dyn Primitive>> {
+        let curve = Curve::new(control_points, widths, curve_type, material);
+        (0..segment_count)
+            .map(|segment| {
+                Arc::new(Curve {
+                    common: Arc::clone(&curve.common),
+                    u_min: segment as f64 / segment_count as f64,
+                    u_max: (segment + 1) as f64 / segment_count as f64,
+                }) as Arc<dyn Primitive>
+            })
+            .collect()
+    }
+
+    fn width_at(&self, u: f64) -> f64 {
+        let [start, end] = self.common.widths;
+        start * (1.0 - u) + end * u
+    }
+
+    fn max_width(&self, u_min: f64, u_max: f64) -> f64 {
+        self.width_at(u_min).max(self.width_at(u_max))
+    }
+
+    /// The nearest hit closer than `max_distance` on the part of the curve from `u_min` to
+    /// `u_max`, whose control points in ray space are `cp`
+    ///
+    /// In ray space the ray starts at the origin and runs along z, so it hits the curve
+    /// wherever the curve passes within half its width of the z axis. The curve is split
+    /// in half `depth` times, skipping halves whose bounds don't reach the axis, until each
+    /// part is close enough to a straight line to be tested as one.
+    fn intersect_segment(
+        &self,
+        cp: &[Vec3; 4],
+        u_min: f64,
+        u_max: f64,
+        depth: u32,
+        max_distance: f64,
+    ) -> Option<CurveHit> {
+        if depth > 0 {
+            let split = split_bezier(cp);
+            let u = [u_min, (u_min + u_max) * 0.5, u_max];
+            let mut nearest: Option<CurveHit> = None;
+            for half in 0..2 {
+                let cp = [
+                    split[3 * half],
+                    split[3 * half + 1],
+                    split[3 * half + 2],
+                    split[3 * half + 3],
+                ];
+                let half_width = 0.5 * self.max_width(u[half], u[half + 1]);
+                let bounds = BoundingBox::from_points(&cp);
+                let limit = nearest.as_ref().map_or(max_distance, |hit| hit.distance);
+                if bounds.bounds[0].get_max() + half_width < 0.0
+                    || bounds.bounds[0].get_min() - half_width > 0.0
+                    || bounds.bounds[1].get_max() + half_width < 0.0
+                    || bounds.bounds[1].get_min() - half_width > 0.0
+                    || bounds.bounds[2].get_max() + half_width < 0.0
+                    || bounds.bounds[2].get_min() - half_width > limit
+                {
+                    continue;
+                }
+                if let Some(hit) =
+                    self.intersect_segment(&cp, u[half], u[half + 1], depth - 1, limit)
+                {
+                    nearest = Some(hit);
+                }
+            }
+            return nearest;
+        }
+
+        // The ray must pass between the lines perpendicular to the curve at each end
+        let start_edge = (cp[1].y() - cp[0].y()) * -cp[0].y() + cp[0].x() * (cp[0].x() - cp[1].x());
+        let end_edge = (cp[2].y() - cp[3].y()) * -cp[3].y() + cp[3].x() * (cp[3].x() - cp[2].x());
+        if start_edge < 0.0 || end_edge < 0.0 {
+            return None;
+        }
+        // Find the nearest point to the ray, treating the segment as a straight line
+        let along = Vec2::new(cp[3].x() - cp[0].x(), cp[3].y() - cp[0].y());
+        let length_squared = along.dot(&along);
+        if length_squared == 0.0 {
+            return None;
+        }
+        let w = (Vec2::new(-cp[0].x(), -cp[0].y()).dot(&along) / length_squared).clamp(0.0, 1.0);
+        let u = u_min * (1.0 - w) + u_max * w;
+        let width = self.width_at(u);
+        let (point, derivative) = evaluate_bezier(cp, w);
+        let distance_squared = point.x() * point.x() + point.y() * point.y();
+        if distance_squared > width * width * 0.25 || point.z() <= 0.0 || point.z() > max_distance {
+            return None;
+        }
+        // v runs from 0 on one side of the curve, as seen along the ray, to 1 on the other
+        let offset = distance_squared.sqrt() / width;
+        let side = derivative.x() * -point.y() + point.x() * derivative.y();
+        let v = if side > 0.0 {
+            0.5 + offset
+        } else {
+            0.5 - offset
+        };
+        Some(CurveHit {
+            distance: point.z(),
+            u,
+            v,
+        })
+    }
+}
+
+impl Transform for Curve {
+    fn transform(&self, transformation: &Affine3) -> Self {
+        // As with spheres, the width is only correct if the scaling is uniform
+        let scale = transformation
+            .transform_vector(&Vec3::new(1.0, 0.0, 0.0))
+            .norm();
+        Curve {
+            common: Arc::new(CurveCommon {
+                control_points: self
+                    .common
+                    .control_points
+                    .map(|point| transformation.transform_point(&point)),
+                widths: self.common.widths.map(|width| width * scale),
+                curve_type: self.common.curve_type,
+                material: Arc::clone(&self.common.material),
+            }),
+            u_min: self.u_min,
+            u_max: self.u_max,
+        }
+    }
+}
+
+impl Intersect for Curve {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        // An orthonormal frame with the ray along z
+        let mut axis_closest_to_perpendicular = Vec3::zeros();
+        axis_closest_to_perpendicular[ray.direction.smallest_coord()] = 1.0;
+        let ray_x = ray
+            .direction
+            .cross(&axis_closest_to_perpendicular)
+            .normalize();
+        let ray_y = ray.direction.cross(&ray_x);
+        let to_ray_space = |point: Vec3| {
+            let offset = point - ray.origin;
+            Vec3::new(
+                offset.dot(&ray_x),
+                offset.dot(&ray_y),
+                offset.dot(&ray.direction),
+            )
+        };
+        let cp =
+            bezier_segment(&self.common.control_points, self.u_min, self.u_max).map(to_ray_space);
+
+        let half_width = 0.5 * self.max_width(self.u_min, self.u_max);
+        let bounds = BoundingBox::from_points(&cp);
+        if bounds.bounds[0].get_max() + half_width < 0.0
+            || bounds.bounds[0].get_min() - half_width > 0.0
+            || bounds.bounds[1].get_max() + half_width < 0.0
+            || bounds.bounds[1].get_min() - half_width > 0.0
+            || bounds.bounds[2].get_max() + half_width < 0.0
+        {
+            return None;
+        }
+
+        // Split until the curve's deviation from a straight line is a small fraction of its
+        // width. Each split divides the deviation by four.
+        let deviation = (0..2)
+            .map(|i| {
+                let second_difference = cp[i] - cp[i + 1] * 2.0 + cp[i + 2];
+                second_difference
+                    .abs()
+                    .coords
+                    .iter()
+                    .fold(0.0f64, |a, &b| a.max(b))
+            })
+            .fold(0.0, f64::max);
+        let tolerance = 0.05 * self.max_width(self.u_min, self.u_max);
+        let depth = if deviation > 0.0 && tolerance > 0.0 {
+            ((2.0f64.sqrt() * 6.0 * deviation / (8.0 * tolerance)).log2() * 0.5)
+                .round()
+                .clamp(0.0, 10.0) as u32
+        } else {
+            0
+        };
+
+        let hit = self.intersect_segment(&cp, self.u_min, self.u_max, depth, f64::INFINITY)?;
+        let width = self.width_at(hit.u);
+        let location = ray.point_at(hit.distance);
+        let (_, dp_du) = evaluate_bezier(&self.common.control_points, hit.u);
+        // Across the ribbon, perpendicular to both the curve and the ray
+        let across = ray.direction.cross(&dp_du).normalize();
+        let facing_ray = dp_du.cross(&across).normalize();
+        let normal = match self.common.curve_type {
+            CurveType::Flat => facing_ray,
+            CurveType::Cylinder => {
+                // Rotate the normal around the curve, from one side to the other
+                let angle = (hit.v - 0.5) * std::f64::consts::PI;
+                facing_ray * angle.cos() + across * angle.sin()
+            }
+        };
+        let normal = if normal.dot(&ray.direction) > 0.0 {
+            -normal
+        } else {
+            normal
+        };
+        let along_curve = dp_du - normal * normal.dot(&dp_du);
+        let tangent = along_curve.normalize();
+        Some(IntersectionInfo {
+            distance: hit.distance,
+            location,
+            // The hit is only somewhere within the width of the ribbon
+            location_error: Vec3::new(width, width, width) * 2.0,
+            normal,
+            tangent,
+            cotangent: normal.cross(&tangent),
+            retro: -ray.direction,
+            uv: Vec2::new(hit.u, hit.v),
+            material: Arc::clone(&self.common.material),
+            object_id: 0,
+            footprint: None,
+            time: 0.0,
+        })
+    }
+}
+
+impl HasBoundingBox for Curve {
+    fn bounding_box(&self) -> BoundingBox {
+        // The curve lies inside the hull of its control points
+        let half_width = 0.5 * self.max_width(self.u_min, self.u_max);
+        let margin = Vec3::new(half_width, half_width, half_width);
+        let corners: Vec<Vec3> =
+            bezier_segment(&self.common.control_points, self.u_min, self.u_max)
+                .iter()
+                .flat_map(|&point| [point - margin, point + margin])
+                .collect();
+        BoundingBox::from_points(&corners)
+    }
+}
+
+impl Primitive for Curve {
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(self.transform(transformation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+    use crate::raycasting::BoundingVolumeHierarchy;
+
+    use quickcheck_macros::quickcheck;
+
+    /// An S-shaped strand in the plane z = 0
+    fn s_curve_points() -> [Vec3; 4] {
+        [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 2.0, 0.0),
+            Vec3::new(2.0, -2.0, 0.0),
+            Vec3::new(3.0, 0.0, 0.0),
+        ]
+    }
+
+    fn straight_curve(curve_type: CurveType) -> Curve {
+        Curve::new(
+            [
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(3.0, 0.0, 0.0),
+            ],
+            [0.2, 0.2],
+            curve_type,
+            Arc::new(LambertianMaterial::new_dummy()),
+        )
+    }
+
+    #[test]
+    fn straight_curve_is_hit_within_its_width() {
+        let target = straight_curve(CurveType::Flat);
+        let info = target
+            .intersect(&Ray::new(Vec3::new(1.5, 0.05, 5.0), -Vec3::unit_z()))
+            .unwrap();
+        assert!((info.distance - 5.0).abs() < 0.000_001);
+        assert!((info.uv.x() - 0.5).abs() < 0.000_001);
+        assert!(((info.uv.y() - 0.5).abs() - 0.25).abs() < 0.000_001);
+        assert!((info.normal - Vec3::unit_z()).norm() < 0.000_001);
+        assert!((info.tangent - Vec3::unit_x()).norm() < 0.000_001);
+        for &(x, y) in &[(1.5, 0.11), (1.5, -0.11), (-0.2, 0.0), (3.2, 0.0)] {
+            let ray = Ray::new(Vec3::new(x, y, 5.0), -Vec3::unit_z());
+            assert!(target.intersect(&ray).is_none());
+        }
+    }
+
+    #[test]
+    fn cylinder_normals_curve_around_the_strand() {
+        let target = straight_curve(CurveType::Cylinder);
+        let centre = target
+            .intersect(&Ray::new(Vec3::new(1.5, 0.0, 5.0), -Vec3::unit_z()))
+            .unwrap();
+        assert!((centre.normal - Vec3::unit_z()).norm() < 0.000_001);
+        let edge = target
+            .intersect(&Ray::new(Vec3::new(1.5, 0.099, 5.0), -Vec3::unit_z()))
+            .unwrap();
+        assert!(edge.normal.y().abs() > 0.9);
+        assert!(edge.normal.x().abs() < 0.000_001);
+    }
+
+    #[quickcheck]
+    fn ray_aimed_at_curve_hits_it(u: f64) -> bool {
+        let u = u.abs().fract();
+        let target = Curve::new(
+            s_curve_points(),
+            [0.05, 0.05],
+            CurveType::Flat,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let (point, _) = evaluate_bezier(&s_curve_points(), u);
+        let origin = Vec3::new(1.0, 0.5, 4.0);
+        let ray = Ray::new(origin, point - origin);
+        target.intersect(&ray).is_some_and(|info| {
+            (info.location - point).norm() < 0.05 && (info.uv.x() - u).abs() < 0.05
+        })
+    }
+
+    #[test]
+    fn segments_are_hit_like_whole_curve() {
+        let material: Arc<dyn Material> = Arc::new(LambertianMaterial::new_dummy());
+        let whole = Curve::new(
+            s_curve_points(),
+            [0.1, 0.02],
+            CurveType::Flat,
+            Arc::clone(&material),
+        );
+        let mut segments =
+            Curve::segments(s_curve_points(), [0.1, 0.02], CurveType::Flat, material, 8);
+        let whole_bounds = whole.bounding_box();
+        let segment_bounds = segments
+            .iter()
+            .map(|segment| segment.bounding_box())
+            .fold(BoundingBox::empty(), |a, b| a.union(&b));
+        assert!(segments.iter().all(|segment| {
+            let bounds = segment.bounding_box();
+            (0..3).all(|axis| bounds.bounds[axis].get_min() >= whole_bounds.bounds[axis].get_min())
+        }));
+        assert!(segment_bounds.bounds[1].get_max() <= whole_bounds.bounds[1].get_max());
+        let bvh = BoundingVolumeHierarchy::build(&mut segments);
+        for i in 0..100 {
+            let (point, _) = evaluate_bezier(&s_curve_points(), i as f64 / 100.0);
+            let ray = Ray::new(point + Vec3::new(0.0, 0.0, 3.0), -Vec3::unit_z());
+            let expected = whole.intersect(&ray).map(|info| info.distance);
+            let actual = bvh.intersect(&ray).map(|info| info.distance);
+            assert!(match (expected, actual) {
+                (Some(expected), Some(actual)) => (expected - actual).abs() < 0.000_001,
+                (None, None) => true,
+                _ => false,
+            });
+        }
+    }
+}
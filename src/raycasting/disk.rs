@@ -0,0 +1,264 @@
+use crate::materials::Material;
+use crate::math::{Affine3, OrthonormalBasis, Vec2, Vec3};
+use crate::random_distributions::{RandomDistribution, UnitDisc};
+use crate::util::float_error::ray_plane_point_error;
+use crate::validation::SceneValidator;
+
+use super::{
+    BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray, SampleSurface,
+    SurfaceSample, Transform,
+};
+
+use rand::RngCore;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// A flat, circular disk
+///
+/// The disk can be hit from either side, but its normal faces in the direction given when
+/// it was created, which matters for one-sided materials such as
+/// [EmissiveMaterial](crate::materials::EmissiveMaterial).
+#[derive(Clone, Debug)]
+pub struct Disk {
+    centre: Vec3,
+    normal: Vec3,
+    tangent: Vec3,
+    cotangent: Vec3,
+    radius: f64,
+    material: Arc<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(centre: Vec3, normal: Vec3, radius: f64, material: Arc<dyn Material>) -> Disk {
+        let normal = normal.normalize();
+        let OrthonormalBasis {
+            tangent, cotangent, ..
+        } = OrthonormalBasis::from_normal(&normal);
+        Disk {
+            centre,
+            normal,
+            tangent,
+            cotangent,
+            radius,
+            material,
+        }
+    }
+}
+
+impl Transform for Disk {
+    fn transform(&self, transformation: &Affine3) -> Self {
+        let normal = transformation.transform_normal(&self.normal).normalize();
+        let scaled_tangent = transformation.transform_vector(&self.tangent);
+        let tangent = (scaled_tangent - normal * scaled_tangent.dot(&normal)).normalize();
+        Disk {
+            centre: transformation.transform_point(&self.centre),
+            normal,
+            tangent,
+            cotangent: normal.cross(&tangent),
+            // As with spheres, this is only correct if the result is still a circular disk
+            radius: scaled_tangent.norm() * self.radius,
+            material: Arc::clone(&self.material),
+        }
+    }
+}
+
+impl Intersect for Disk {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        let ray_direction_dot_normal = ray.direction.dot(&self.normal);
+        if ray_direction_dot_normal == 0.0 {
+            return None;
+        }
+        let distance = (self.centre - ray.origin).dot(&self.normal) / ray_direction_dot_normal;
+        if distance <= 0.0 {
+            return None;
+        }
+        let location = ray.point_at(distance);
+        let offset = location - self.centre;
+        if offset.norm_squared() > self.radius * self.radius {
+            return None;
+        }
+        // Map the disk onto the unit square, with the centre at (0.5, 0.5)
+        let uv = Vec2::new(
+            0.5 + 0.5 * offset.dot(&self.tangent) / self.radius,
+            0.5 + 0.5 * offset.dot(&self.cotangent) / self.radius,
+        );
+        Some(IntersectionInfo {
+            distance,
+            location,
+            location_error: ray_plane_point_error(
+                &ray.origin,
+                &(ray.direction * distance),
+                &self.centre,
+            ),
+            normal: self.normal,
+            tangent: self.tangent,
+            cotangent: self.cotangent,
+            retro: -ray.direction,
+            uv,
+            material: Arc::clone(&self.material),
+            object_id: 0,
+            footprint: None,
+            time: 0.0,
+        })
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_vertex(&self.centre);
+        validator.check_material(&self.material);
+    }
+}
+
+impl HasBoundingBox for Disk {
+    fn bounding_box(&self) -> BoundingBox {
+        // Along each axis the disk extends by its radius, foreshortened by the normal
+        let extent_along = |n: f64| self.radius * (1.0 - n * n).max(0.0).sqrt();
+        let extent = Vec3::new(
+            extent_along(self.normal.x()),
+            extent_along(self.normal.y()),
+            extent_along(self.normal.z()),
+        );
+        BoundingBox::from_corners(self.centre + extent, self.centre - extent)
+    }
+}
+
+impl Primitive for Disk {
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(self.transform(transformation))
+    }
+}
+
+impl SampleSurface for Disk {
+    fn surface_area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self, rng: &mut dyn RngCore) -> SurfaceSample {
+        let point_on_disc = UnitDisc::new().value(rng) * self.radius;
+        SurfaceSample {
+            location: self.centre
+                + self.tangent * point_on_disc.x()
+                + self.cotangent * point_on_disc.y(),
+            normal: self.normal,
+            pdf: 1.0 / self.surface_area(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn test_disk() -> Disk {
+        Disk::new(
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            2.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        )
+    }
+
+    #[test]
+    fn ray_through_disk_intersects() {
+        let ray = Ray::new(Vec3::new(2.0, 3.0, 0.0), Vec3::unit_z());
+        let info = test_disk().intersect(&ray).unwrap();
+        assert!((info.location - Vec3::new(2.0, 3.0, 3.0)).norm() < 0.000000001);
+        assert!((info.distance - 3.0).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn ray_outside_radius_does_not_intersect() {
+        let ray = Ray::new(Vec3::new(2.5, 3.5, 0.0), Vec3::unit_z());
+        assert!(test_disk().intersect(&ray).is_none());
+    }
+
+    #[test]
+    fn disk_can_be_hit_from_behind() {
+        let ray = Ray::new(Vec3::new(1.0, 2.0, 10.0), -Vec3::unit_z());
+        let info = test_disk().intersect(&ray).unwrap();
+        assert!(info.normal == Vec3::unit_z());
+    }
+
+    #[test]
+    fn centre_has_uv_at_middle_of_square() {
+        let ray = Ray::new(Vec3::new(1.0, 2.0, 0.0), Vec3::unit_z());
+        let info = test_disk().intersect(&ray).unwrap();
+        assert!((info.uv.x() - 0.5).abs() < 0.000000001);
+        assert!((info.uv.y() - 0.5).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn frame_is_orthonormal() {
+        let target = Disk::new(
+            Vec3::zeros(),
+            Vec3::new(1.0, -2.0, 0.5),
+            1.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        assert!(target.tangent.dot(&target.normal).abs() < 0.000000001);
+        assert!(target.cotangent.dot(&target.normal).abs() < 0.000000001);
+        assert!(target.tangent.dot(&target.cotangent).abs() < 0.000000001);
+        assert!((target.tangent.cross(&target.cotangent) - target.normal).norm() < 0.000000001);
+    }
+
+    #[quickcheck]
+    fn samples_lie_on_disk(seed: u64) -> bool {
+        let target = test_disk();
+        let sample = target.sample_surface(&mut StdRng::seed_from_u64(seed));
+        let offset = sample.location - target.centre;
+        offset.z().abs() < 0.000000001 && offset.norm() <= target.radius + 0.000000001
+    }
+
+    #[test]
+    fn sample_pdf_is_inverse_of_area() {
+        let target = test_disk();
+        let sample = target.sample_surface(&mut StdRng::seed_from_u64(0));
+        assert!((sample.pdf * 4.0 * PI - 1.0).abs() < 0.000000001);
+    }
+
+    #[quickcheck]
+    fn bounding_box_contains_samples(normal: Vec3, seed: u64) -> TestResult {
+        if normal.norm() == 0.0 {
+            return TestResult::discard();
+        }
+        let target = Disk::new(
+            Vec3::new(1.0, 2.0, 3.0),
+            normal,
+            2.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let sample = target.sample_surface(&mut StdRng::seed_from_u64(seed));
+        let bounds = target.bounding_box();
+        // Allow for rounding in the sampled location
+        let nudge = (sample.location - target.centre) * -0.000001;
+        TestResult::from_bool(bounds.contains_point(sample.location + nudge))
+    }
+
+    #[test]
+    fn bounding_box_is_flat_for_axis_aligned_disk() {
+        let bounds = test_disk().bounding_box();
+        assert!(bounds.contains_point(Vec3::new(2.9, 2.0, 3.0)));
+        assert!(!bounds.contains_point(Vec3::new(3.1, 2.0, 3.0)));
+        assert!(!bounds.contains_point(Vec3::new(1.0, 2.0, 3.1)));
+    }
+
+    #[test]
+    fn transformed_disk_is_intersected_at_expected_location() {
+        let target = test_disk().transform(
+            &(Affine3::translation(&Vec3::new(0.0, 0.0, 2.0))
+                * Affine3::rotation(&Vec3::unit_x(), std::f64::consts::FRAC_PI_2)
+                * Affine3::uniform_scale(0.5)),
+        );
+        assert!((target.radius - 1.0).abs() < 0.000000001);
+        let ray = Ray::new(Vec3::new(0.5, 10.0, 3.0), -Vec3::unit_y());
+        let info = target.intersect(&ray).unwrap();
+        assert!((info.location - Vec3::new(0.5, -1.5, 3.0)).norm() < 0.000000001);
+    }
+}
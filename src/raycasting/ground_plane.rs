@@ -0,0 +1,223 @@
+use crate::colour::Spectrum;
+use crate::materials::{LambertianMaterial, Material};
+use crate::math::{Affine3, OrthonormalBasis, Vec2, Vec3};
+use crate::textures::Checkerboard;
+use crate::util::float_error::ray_plane_point_error;
+use crate::validation::SceneValidator;
+
+use super::{
+    BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray, SampleSurface,
+    SurfaceSample, Transform,
+};
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+use std::sync::Arc;
+
+/// A large, square floor, for use instead of an infinite [Plane](super::Plane)
+///
+/// A plane's bounding box is infinite, which makes the bounds of anything it's grouped with
+/// infinite too. A ground plane only reaches `half_size` from its centre, so it can go in a
+/// [BoundingVolumeHierarchy](super::BoundingVolumeHierarchy) like any other primitive.
+///
+/// As with planes, surface coordinates are distances along the tangents, here measured from
+/// the centre, so textures keep the same scale whatever size the ground plane is.
+#[derive(Clone, Debug)]
+pub struct GroundPlane {
+    centre: Vec3,
+    frame: OrthonormalBasis,
+    half_size: f64,
+    material: Arc<dyn Material>,
+}
+
+impl GroundPlane {
+    pub fn new(
+        centre: Vec3,
+        normal: Vec3,
+        half_size: f64,
+        material: Arc<dyn Material>,
+    ) -> GroundPlane {
+        GroundPlane {
+            centre,
+            frame: OrthonormalBasis::from_normal(&normal.normalize()),
+            half_size,
+            material,
+        }
+    }
+
+    /// A diffuse ground plane in a checkerboard of `even` and `odd` squares, each
+    /// `square_size` across
+    pub fn checkered(
+        centre: Vec3,
+        normal: Vec3,
+        half_size: f64,
+        square_size: f64,
+        even: Spectrum,
+        odd: Spectrum,
+    ) -> GroundPlane {
+        GroundPlane::new(
+            centre,
+            normal,
+            half_size,
+            Arc::new(LambertianMaterial {
+                colour: Checkerboard::new(even, odd, square_size),
+                diffuse_strength: 1.0,
+            }),
+        )
+    }
+}
+
+impl Transform for GroundPlane {
+    fn transform(&self, transformation: &Affine3) -> Self {
+        let normal = transformation
+            .transform_normal(&self.frame.normal)
+            .normalize();
+        let scaled_tangent = transformation.transform_vector(&self.frame.tangent);
+        let tangent = (scaled_tangent - normal * scaled_tangent.dot(&normal)).normalize();
+        GroundPlane {
+            centre: transformation.transform_point(&self.centre),
+            frame: OrthonormalBasis::new(tangent, normal.cross(&tangent), normal),
+            // As with disks, this is only correct if the result is still square
+            half_size: scaled_tangent.norm() * self.half_size,
+            material: Arc::clone(&self.material),
+        }
+    }
+}
+
+impl Intersect for GroundPlane {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        let normal = self.frame.normal;
+        let ray_direction_dot_normal = ray.direction.dot(&normal);
+        if ray_direction_dot_normal == 0.0 {
+            return None;
+        }
+        let distance = (self.centre - ray.origin).dot(&normal) / ray_direction_dot_normal;
+        if distance <= 0.0 {
+            return None;
+        }
+        let location = ray.point_at(distance);
+        let offset = location - self.centre;
+        let uv = Vec2::new(
+            offset.dot(&self.frame.tangent),
+            offset.dot(&self.frame.cotangent),
+        );
+        if uv.x().abs() > self.half_size || uv.y().abs() > self.half_size {
+            return None;
+        }
+        Some(IntersectionInfo {
+            distance,
+            location,
+            location_error: ray_plane_point_error(
+                &ray.origin,
+                &(ray.direction * distance),
+                &self.centre,
+            ),
+            normal,
+            tangent: self.frame.tangent,
+            cotangent: self.frame.cotangent,
+            retro: -ray.direction,
+            uv,
+            material: Arc::clone(&self.material),
+            object_id: 0,
+            footprint: None,
+            time: 0.0,
+        })
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_vertex(&self.centre);
+        validator.check_material(&self.material);
+    }
+}
+
+impl HasBoundingBox for GroundPlane {
+    fn bounding_box(&self) -> BoundingBox {
+        let tangent = self.frame.tangent * self.half_size;
+        let cotangent = self.frame.cotangent * self.half_size;
+        BoundingBox::from_points(&[
+            self.centre + tangent + cotangent,
+            self.centre + tangent - cotangent,
+            self.centre - tangent + cotangent,
+            self.centre - tangent - cotangent,
+        ])
+    }
+}
+
+impl Primitive for GroundPlane {
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(self.transform(transformation))
+    }
+}
+
+impl SampleSurface for GroundPlane {
+    fn surface_area(&self) -> f64 {
+        4.0 * self.half_size * self.half_size
+    }
+
+    fn sample_surface(&self, rng: &mut dyn RngCore) -> SurfaceSample {
+        let mut coordinate = || self.half_size * (2.0 * rng.sample::<f64, _>(Open01) - 1.0);
+        let (u, v) = (coordinate(), coordinate());
+        SurfaceSample {
+            location: self.centre + self.frame.tangent * u + self.frame.cotangent * v,
+            normal: self.frame.normal,
+            pdf: 1.0 / self.surface_area(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::raycasting::Plane;
+
+    fn floor() -> GroundPlane {
+        GroundPlane::checkered(
+            Vec3::new(0.0, -2.0, 0.0),
+            Vec3::unit_y(),
+            100.0,
+            1.0,
+            Spectrum::grey(0.8),
+            Spectrum::grey(0.2),
+        )
+    }
+
+    #[test]
+    fn matches_plane_within_half_size() {
+        let plane = Plane::new(
+            Vec3::unit_y(),
+            -2.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let ray = Ray::new(Vec3::new(3.0, 1.0, 4.0), Vec3::new(0.1, -1.0, 0.3));
+        let expected = plane.intersect(&ray).unwrap();
+        let info = floor().intersect(&ray).unwrap();
+        assert!((info.distance - expected.distance).abs() < 0.000000001);
+        assert!((info.normal - Vec3::unit_y()).norm() < 0.000000001);
+    }
+
+    #[test]
+    fn misses_beyond_half_size() {
+        let ray = Ray::new(Vec3::new(150.0, 1.0, 0.0), -Vec3::unit_y());
+        assert!(floor().intersect(&ray).is_none());
+    }
+
+    #[test]
+    fn bounding_box_is_finite() {
+        let bounds = floor().bounding_box();
+        assert!(!bounds.is_unbounded());
+        assert!(bounds.contains_point(Vec3::new(99.0, -2.0, -99.0)));
+    }
+
+    #[test]
+    fn surface_coordinates_are_distances_from_centre() {
+        let target = floor();
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), -Vec3::unit_y());
+        let centre = target.intersect(&ray).unwrap();
+        assert!(centre.uv.x().abs() < 0.000000001 && centre.uv.y().abs() < 0.000000001);
+        let ray = Ray::new(Vec3::new(3.0, 1.0, 4.0), -Vec3::unit_y());
+        let info = target.intersect(&ray).unwrap();
+        assert!((info.uv.x().hypot(info.uv.y()) - 5.0).abs() < 0.000000001);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::math::Affine3;
+use crate::validation::SceneValidator;
+
+use super::{
+    Aggregate, BoundingBox, HasBoundingBox, Instance, Intersect, IntersectionInfo, Lanes,
+    Primitive, Ray, RayPacket,
+};
+
+use std::sync::Arc;
+
+/// An aggregate, such as a [BoundingVolumeHierarchy](super::BoundingVolumeHierarchy) or
+/// [TriangleMesh](super::TriangleMesh), treated as a single primitive
+///
+/// This lets aggregates be nested inside each other, so a scene can be built as a tree of
+/// objects, each with its own acceleration structure, under one top-level BVH. Unlike an
+/// [Instance](Instance), rays aren't transformed on the way in, so there's no cost beyond
+/// that of the wrapped aggregate.
+///
+/// The bounding box is calculated once, when the group is created, since aggregates such as
+/// `Vec`s of primitives recalculate theirs every time they're asked.
+#[derive(Clone)]
+pub struct Group {
+    object: Arc<dyn Aggregate>,
+    bounds: BoundingBox,
+}
+
+impl Group {
+    pub fn new(object: Arc<dyn Aggregate>) -> Group {
+        Group {
+            bounds: object.bounding_box(),
+            object,
+        }
+    }
+}
+
+impl Intersect for Group {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        self.object.intersect(ray)
+    }
+
+    fn intersect_any(&self, ray: &Ray, max_distance: f64) -> bool {
+        self.object.intersect_any(ray, max_distance)
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes<Option<IntersectionInfo>> {
+        self.object.intersect_packet(packet)
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        self.object.validate(validator)
+    }
+}
+
+impl HasBoundingBox for Group {
+    fn bounding_box(&self) -> BoundingBox {
+        self.bounds
+    }
+}
+
+impl Primitive for Group {
+    /// The wrapped aggregate can't be transformed in place, so the result is an
+    /// [Instance](Instance) of it
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(Instance::new(Arc::clone(&self.object), *transformation))
+    }
+}
+
+impl Aggregate for Group {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+    use crate::math::Vec3;
+    use crate::raycasting::{BoundingVolumeHierarchy, Sphere, WithObjectId};
+
+    fn row_of_spheres(y: f64) -> Vec<Arc<dyn Primitive>> {
+        let material = Arc::new(LambertianMaterial::new_dummy());
+        (0..8)
+            .map(|i| {
+                Arc::new(Sphere::new(
+                    Vec3::new(i as f64 * 3.0, y, 0.0),
+                    1.0,
+                    material.clone(),
+                )) as Arc<dyn Primitive>
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nested_trees_find_same_intersections_as_flat_tree() {
+        let mut flat: Vec<Arc<dyn Primitive>> = vec![];
+        let mut groups: Vec<Arc<dyn Primitive>> = vec![];
+        for row in 0..8 {
+            let mut spheres = row_of_spheres(row as f64 * 3.0);
+            flat.extend(spheres.iter().cloned());
+            let tree = BoundingVolumeHierarchy::build(&mut spheres);
+            groups.push(Arc::new(Group::new(Arc::new(WithObjectId::new(
+                tree,
+                row + 1,
+            )))));
+        }
+        let flat = BoundingVolumeHierarchy::build(&mut flat);
+        let target = BoundingVolumeHierarchy::build(&mut groups);
+        for i in 0..256 {
+            let x = (i % 16) as f64 * 1.5 + 0.25;
+            let y = (i / 16) as f64 * 1.5 - 0.25;
+            let ray = Ray::new(Vec3::new(x, y, -5.0), Vec3::unit_z());
+            let expected = flat.intersect(&ray).map(|info| info.location);
+            let info = target.intersect(&ray);
+            assert!(info.as_ref().map(|info| info.location) == expected);
+            if let Some(info) = info {
+                // Each nested tree keeps its own object ID
+                assert!(info.object_id == ((info.location.y() + 1.5) / 3.0) as u32 + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn transformed_group_is_an_instance() {
+        let mut spheres = row_of_spheres(0.0);
+        let target = Group::new(Arc::new(BoundingVolumeHierarchy::build(&mut spheres)))
+            .transform_primitive(&Affine3::translation(&Vec3::new(0.0, 10.0, 0.0)));
+        let ray = Ray::new(Vec3::new(3.0, 10.0, -5.0), Vec3::unit_z());
+        assert!((target.intersect(&ray).unwrap().distance - 4.0).abs() < 0.000001);
+        assert!(target
+            .bounding_box()
+            .contains_point(Vec3::new(3.0, 10.0, 0.0)));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::math::{Affine3, Vec3};
+use crate::util::float_error::transformed_point_error;
+use crate::util::Interval;
+use crate::validation::SceneValidator;
+
+use super::{
+    Aggregate, BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray, Transform,
+};
+
+use std::sync::Arc;
+
+/// A transformed copy of some shared geometry
+///
+/// Rays are transformed into the object space of the wrapped aggregate, so any number of
+/// instances can share one copy of the geometry (and its acceleration structure).
+#[derive(Clone)]
+pub struct Instance {
+    object: Arc<dyn Aggregate>,
+    transformation: Affine3,
+    inverse_transformation: Affine3,
+}
+
+impl Instance {
+    /// Create an instance of `object` placed in the world by `transformation`
+    ///
+    /// Panics if `transformation` isn't invertible.
+    pub fn new(object: Arc<dyn Aggregate>, transformation: Affine3) -> Instance {
+        let inverse_transformation = transformation
+            .try_inverse()
+            .expect("Instance transformation must be invertible.");
+        Instance {
+            object,
+            transformation,
+            inverse_transformation,
+        }
+    }
+
+    fn to_object_space(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.inverse_transformation.transform_point(&ray.origin),
+            self.inverse_transformation.transform_vector(&ray.direction),
+        )
+    }
+}
+
+impl Transform for Instance {
+    fn transform(&self, transformation: &Affine3) -> Self {
+        Instance::new(
+            Arc::clone(&self.object),
+            *transformation * self.transformation,
+        )
+    }
+}
+
+impl Intersect for Instance {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        let info = self.object.intersect(&self.to_object_space(ray))?;
+        let location = self.transformation.transform_point(&info.location);
+        let location_error =
+            transformed_point_error(&self.transformation, &info.location, &info.location_error);
+        let normal = self
+            .transformation
+            .transform_normal(&info.normal)
+            .normalize();
+        let tangent = self.transformation.transform_vector(&info.tangent);
+        let tangent = (tangent - normal * tangent.dot(&normal)).normalize();
+        // Keep the same handedness as the frame the wrapped primitive returned
+        let handedness = info
+            .normal
+            .cross(&info.tangent)
+            .dot(&info.cotangent)
+            .signum();
+        let cotangent = normal.cross(&tangent) * handedness;
+        Some(IntersectionInfo {
+            distance: (location - ray.origin).norm(),
+            location,
+            location_error,
+            normal,
+            tangent,
+            cotangent,
+            retro: -ray.direction,
+            uv: info.uv,
+            material: info.material,
+            object_id: info.object_id,
+            footprint: None,
+            time: 0.0,
+        })
+    }
+
+    fn intersect_any(&self, ray: &Ray, max_distance: f64) -> bool {
+        // Distances shrink or grow with the transformation
+        let scale = self
+            .inverse_transformation
+            .transform_vector(&ray.direction)
+            .norm();
+        self.object
+            .intersect_any(&self.to_object_space(ray), max_distance * scale)
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        self.object.validate(validator)
+    }
+}
+
+impl HasBoundingBox for Instance {
+    fn bounding_box(&self) -> BoundingBox {
+        let bounds = self.object.bounding_box();
+        if bounds.bounds.iter().any(|interval| interval.is_empty()) {
+            return BoundingBox::empty();
+        }
+        let is_finite = bounds
+            .bounds
+            .iter()
+            .all(|interval| interval.get_min().is_finite() && interval.get_max().is_finite());
+        if !is_finite {
+            return BoundingBox {
+                bounds: [Interval::infinite(); 3],
+            };
+        }
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| {
+                let corner = |axis: usize| {
+                    if i & (1 << axis) == 0 {
+                        bounds.bounds[axis].get_min()
+                    } else {
+                        bounds.bounds[axis].get_max()
+                    }
+                };
+                self.transformation
+                    .transform_point(&Vec3::new(corner(0), corner(1), corner(2)))
+            })
+            .collect();
+        BoundingBox::from_points(&corners)
+    }
+}
+
+impl Primitive for Instance {
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(self.transform(transformation))
+    }
+}
+
+impl Aggregate for Instance {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+    use crate::raycasting::{BoundingVolumeHierarchy, Sphere};
+
+    fn unit_sphere_bvh() -> Arc<dyn Aggregate> {
+        let mut primitives: Vec<Arc<dyn Primitive>> = vec![Arc::new(Sphere::new(
+            Vec3::zeros(),
+            1.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        ))];
+        Arc::new(BoundingVolumeHierarchy::build(&mut primitives))
+    }
+
+    #[test]
+    fn translated_instance_is_hit_at_new_location() {
+        let target = Instance::new(
+            unit_sphere_bvh(),
+            Affine3::translation(&Vec3::new(5.0, 0.0, 0.0)),
+        );
+        let ray = Ray::new(Vec3::new(5.0, 0.0, -10.0), Vec3::unit_z());
+        let info = target.intersect(&ray).unwrap();
+        assert!((info.location - Vec3::new(5.0, 0.0, -1.0)).norm() < 0.000001);
+        assert!((info.distance - 9.0).abs() < 0.000001);
+        assert!((info.normal + Vec3::unit_z()).norm() < 0.000001);
+        assert!(target
+            .intersect(&Ray::new(Vec3::new(0.0, 0.0, -10.0), Vec3::unit_z()))
+            .is_none());
+    }
+
+    #[test]
+    fn scaled_instance_reports_world_space_distance() {
+        let target = Instance::new(unit_sphere_bvh(), Affine3::uniform_scale(2.0));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -10.0), Vec3::unit_z());
+        let info = target.intersect(&ray).unwrap();
+        assert!((info.distance - 8.0).abs() < 0.000001);
+        assert!(target.intersect_any(&ray, 8.5));
+        assert!(!target.intersect_any(&ray, 7.5));
+    }
+
+    #[test]
+    fn returned_frame_is_orthonormal() {
+        let target = Instance::new(
+            unit_sphere_bvh(),
+            Affine3::rotation(&Vec3::new(1.0, 1.0, 0.0), 0.7) * Affine3::scale(1.0, 3.0, 1.0),
+        );
+        let ray = Ray::new(Vec3::new(0.3, 0.2, -10.0), Vec3::unit_z());
+        let info = target.intersect(&ray).unwrap();
+        assert!(info.normal.dot(&info.tangent).abs() < 0.000001);
+        assert!(info.normal.dot(&info.cotangent).abs() < 0.000001);
+        assert!((info.cotangent.norm() - 1.0).abs() < 0.000001);
+    }
+
+    #[test]
+    fn bounding_box_contains_transformed_geometry() {
+        let target = Instance::new(
+            unit_sphere_bvh(),
+            Affine3::translation(&Vec3::new(0.0, 10.0, 0.0)) * Affine3::uniform_scale(2.0),
+        );
+        let bounds = target.bounding_box();
+        assert!(bounds.contains_point(Vec3::new(0.0, 11.9, 0.0)));
+        assert!(!bounds.contains_point(Vec3::new(0.0, 0.0, 0.0)));
+    }
+}
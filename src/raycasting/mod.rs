@@ -1,26 +1,78 @@
-use crate::math::Vec3;
+use crate::math::{Affine3, Vec2, Vec3};
 
-use super::materials::Material;
+use super::materials::{Bsdf, Material};
+use super::util::float_error::offset_ray_origin;
+use super::validation::SceneValidator;
+
+use rand::RngCore;
 
 use std::sync::Arc;
 
 pub mod sphere;
 pub use sphere::Sphere;
 
+pub mod partial_sphere;
+pub use partial_sphere::PartialSphere;
+
 pub mod plane;
 pub use plane::Plane;
 
+pub mod ground_plane;
+pub use ground_plane::GroundPlane;
+
 pub mod triangle;
 pub use triangle::Triangle;
 
+pub mod triangle_mesh;
+pub use triangle_mesh::{MeshBuffers, MeshFace, MeshTriangle, TriangleMesh};
+
+pub mod disk;
+pub use disk::Disk;
+
+pub mod rect;
+pub use rect::Rect;
+
+pub mod bilinear_patch;
+pub use bilinear_patch::BilinearPatch;
+
+pub mod curve;
+pub use curve::{Curve, CurveType};
+
+pub mod sdf_primitive;
+pub use sdf_primitive::{DistanceGrid, SdfPrimitive, SignedDistanceField};
+
+pub mod voxel_surface;
+pub use voxel_surface::VoxelSurface;
+
 pub mod axis_aligned_bounding_box;
 pub use axis_aligned_bounding_box::BoundingBox;
 
 pub mod bounding_volume_hierarchy;
 pub use bounding_volume_hierarchy::BoundingVolumeHierarchy;
 
+pub mod quad_bounding_volume_hierarchy;
+pub use quad_bounding_volume_hierarchy::QuadBoundingVolumeHierarchy;
+
+pub mod ray_differential;
+pub use ray_differential::{Footprint, RayDifferential};
+
+pub mod ray_packet;
+pub use ray_packet::{Lanes, RayPacket, RAY_PACKET_WIDTH};
+
+pub mod instance;
+pub use instance::Instance;
+
+pub mod group;
+pub use group::Group;
+
+pub mod clipping_plane;
+pub use clipping_plane::ClippingPlane;
+
 pub mod vec_aggregate;
 
+pub mod with_object_id;
+pub use with_object_id::WithObjectId;
+
 /// A ray, consisting or a start point and direction
 ///
 /// This is the basic ray struct used to define things like a line-of-sight
@@ -34,6 +86,15 @@ pub struct Ray {
     ///
     /// This vector should always be kept normalized
     pub direction: Vec3,
+
+    /// The rays through the neighbouring pixels, if they're being followed
+    pub differential: Option<RayDifferential>,
+
+    /// The time, in seconds, at which the ray is travelling
+    ///
+    /// Camera rays get this from the [shutter](crate::camera::Shutter), and every ray
+    /// leaving a surface along their paths keeps it.
+    pub time: f64,
 }
 
 impl Ray {
@@ -42,22 +103,26 @@ impl Ray {
         Ray {
             origin,
             direction: direction.normalize(),
+            differential: None,
+            time: 0.0,
+        }
+    }
+
+    pub fn with_differential(self, differential: RayDifferential) -> Ray {
+        Ray {
+            differential: Some(differential),
+            ..self
         }
     }
 
+    pub fn with_time(self, time: f64) -> Ray {
+        Ray { time, ..self }
+    }
+
     /// Return the point on the ray that is `t` units from the start
     pub fn point_at(&self, t: f64) -> Vec3 {
         self.origin + self.direction * t
     }
-
-    /// Create a new ray by moving the original ray along it's direction by `amount`
-    ///
-    /// `amount` is normally a very small number. This function is useful for ensuring
-    /// that rounding-errors don;t cause a reflection ray doesn't intersect with the point
-    /// it's reflected from.
-    pub fn bias(&self, amount: f64) -> Ray {
-        Ray::new(self.origin + self.direction * amount, self.direction)
-    }
 }
 
 /// Information about a ray-primitive intersection.
@@ -72,6 +137,12 @@ pub struct IntersectionInfo {
     /// The intersection point
     pub location: Vec3,
 
+    /// A bound on the rounding error in each coordinate of `location`
+    ///
+    /// Rays leaving the surface should be made with [spawn_ray()](IntersectionInfo::spawn_ray),
+    /// which uses this to start them clear of the surface.
+    pub location_error: Vec3,
+
     /// The surface normal at the intersection point
     pub normal: Vec3,
 
@@ -91,9 +162,61 @@ pub struct IntersectionInfo {
     /// Equal to `-ray.direction`
     pub retro: Vec3,
 
+    /// The surface (texture) coordinates of the intersection point
+    ///
+    /// How these are parameterized depends on the [Primitive](Primitive).
+    pub uv: Vec2,
+
     /// The [Material](crate::materials::Material) which describes the optical
     /// properties of the intersected surface
     pub material: Arc<dyn Material>,
+
+    /// The ID of the object that was hit, for [ID buffers](crate::aovs::render_id_buffers)
+    ///
+    /// This is zero unless the object is wrapped in [WithObjectId](WithObjectId).
+    pub object_id: u32,
+
+    /// The area of the surface covered by the ray's pixel, for filtering textures
+    ///
+    /// Primitives leave this as `None`; it's filled in by
+    /// [Sampler::sample()](crate::sampler::Sampler::sample) for rays that carry a
+    /// [RayDifferential](RayDifferential).
+    pub footprint: Option<Footprint>,
+
+    /// The [time](Ray::time) of the ray that hit the surface
+    ///
+    /// Primitives leave this as zero; it's filled in by
+    /// [Sampler::sample()](crate::sampler::Sampler::sample), so that rays
+    /// [spawned](IntersectionInfo::spawn_ray) from the surface carry it on.
+    pub time: f64,
+}
+
+impl IntersectionInfo {
+    /// A ray leaving the intersection point in `direction`
+    ///
+    /// The ray starts just far enough from the surface, on the side that `direction` leaves
+    /// from, that it can't hit the same surface again due to rounding error.
+    pub fn spawn_ray(&self, direction: &Vec3) -> Ray {
+        Ray::new(
+            offset_ray_origin(
+                &self.location,
+                &self.location_error,
+                &self.normal,
+                direction,
+            ),
+            *direction,
+        )
+        .with_time(self.time)
+    }
+
+    /// The BSDF of the surface at the intersection point, with textures filtered over the
+    /// footprint if there is one
+    pub fn bsdf(&self) -> Bsdf<'_> {
+        match &self.footprint {
+            Some(footprint) => self.material.filtered_bsdf(&self.uv, footprint),
+            None => self.material.bsdf(&self.uv),
+        }
+    }
 }
 
 /// A geometric object that has a [Material](crate::materials::Material) and can be
@@ -101,6 +224,31 @@ pub struct IntersectionInfo {
 pub trait Intersect: Send + Sync {
     /// Test if the ray intersects the object, and return information about the object and intersection.
     fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo>;
+
+    /// Test if the ray intersects the object closer than `max_distance` from its origin
+    ///
+    /// This is meant for shadow rays, which only need to know if anything is in the way.
+    /// Aggregates override it to stop searching at the first intersection found, rather
+    /// than looking for the closest one.
+    fn intersect_any(&self, ray: &Ray, max_distance: f64) -> bool {
+        self.intersect(ray)
+            .is_some_and(|info| info.distance < max_distance)
+    }
+
+    /// Find the nearest intersection of each active ray in `packet`
+    ///
+    /// The result for each ray must be the same as from [intersect()](Intersect::intersect).
+    /// By default each ray is intersected on its own; aggregates override this to traverse
+    /// their contents with the whole packet at once.
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes<Option<IntersectionInfo>> {
+        packet.map_active(|ray| self.intersect(ray))
+    }
+
+    /// Report anything about the object, or the objects and materials it contains, that
+    /// will make it render incorrectly
+    ///
+    /// Nothing is checked by default.
+    fn validate(&self, _validator: &mut SceneValidator) {}
 }
 
 /// A geometric object that can be intersected with a ray
@@ -124,15 +272,51 @@ pub trait HasBoundingBox: Send + Sync {
 /// Any geometric object which can have an affine transformation applied to it
 ///
 /// Used for moving, rotating or scaling primitives
-/*pub trait Transform {
+pub trait Transform {
     /// Create a new object by applying the transformation to this object.
-    fn transform(&self, transformation: &Affine3<f64>) -> Self;
-}*/
+    fn transform(&self, transformation: &Affine3) -> Self;
+}
 
 /// A basic geometric primitive such as a sphere or a triangle
 pub trait Primitive: Intersect + HasBoundingBox {
-    // / Create a new object by applying the transformation to this object.
-    //fn transform(&self, transformation: &Affine3) -> dyn Primitive;
+    /// Create a new primitive by applying the transformation to this primitive.
+    ///
+    /// This is the object-safe equivalent of [Transform](Transform), which allows
+    /// collections of `dyn Primitive` (such as meshes) to be transformed.
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive>;
+}
+
+impl Transform for Vec<Arc<dyn Primitive>> {
+    fn transform(&self, transformation: &Affine3) -> Self {
+        self.iter()
+            .map(|primitive| primitive.transform_primitive(transformation))
+            .collect()
+    }
+}
+
+/// A point chosen at random on the surface of a geometric object
+#[derive(Clone, Debug)]
+pub struct SurfaceSample {
+    /// The chosen point
+    pub location: Vec3,
+
+    /// The surface normal at the chosen point
+    pub normal: Vec3,
+
+    /// The probability density of choosing `location`, with respect to surface area
+    pub pdf: f64,
+}
+
+/// A geometric object whose surface can be sampled at random
+///
+/// This allows integrators to sample light arriving from emissive primitives (area lights)
+/// directly, rather than waiting for a ray to hit them by chance.
+pub trait SampleSurface: Send + Sync {
+    /// The total surface area of the object
+    fn surface_area(&self) -> f64;
+
+    /// Choose a random point on the surface of the object
+    fn sample_surface(&self, rng: &mut dyn RngCore) -> SurfaceSample;
 }
 
 /// Either a primitive or a collection of primitives
@@ -179,4 +363,81 @@ mod tests {
     fn t_is_distance(ray: Ray, t: f64) -> bool {
         (ray.point_at(t) - ray.origin).norm() - t.abs() < 0.0000000001
     }
+
+    #[test]
+    fn transforming_primitive_list_transforms_every_primitive() {
+        use crate::materials::LambertianMaterial;
+        let material = Arc::new(LambertianMaterial::new_dummy());
+        let target: Vec<Arc<dyn Primitive>> = vec![
+            Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material.clone())),
+            Arc::new(Sphere::new(Vec3::new(4.0, 0.0, 0.0), 1.0, material)),
+        ];
+        let translation = Vec3::new(0.0, 10.0, 0.0);
+        let result = target.transform(&Affine3::translation(&translation));
+        assert!(result.len() == 2);
+        assert!(result[0]
+            .bounding_box()
+            .contains_point(Vec3::new(0.0, 10.0, 0.0)));
+        assert!(result[1]
+            .bounding_box()
+            .contains_point(Vec3::new(4.0, 10.0, 0.0)));
+        assert!(!result[1]
+            .bounding_box()
+            .contains_point(Vec3::new(4.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn spawned_rays_do_not_hit_surface_they_leave() {
+        use crate::materials::LambertianMaterial;
+        use crate::math::Vec2;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+        let material: Arc<dyn Material> = Arc::new(LambertianMaterial::new_dummy());
+        // Far from the origin, where coordinates are coarse
+        let centre = Vec3::new(3.0e6, -2.0e6, 1.0e6);
+        let sphere = Sphere::new(centre, 10.0, Arc::clone(&material));
+        let (edge1, edge2) = (Vec3::new(20.0, 1.0, 0.0), Vec3::new(10.0, -1.0, 20.0));
+        let triangle = Triangle {
+            vertices: [
+                centre + Vec3::new(-10.0, 0.0, -10.0),
+                centre + Vec3::new(-10.0, 0.0, -10.0) + edge1,
+                centre + Vec3::new(-10.0, 0.0, -10.0) + edge2,
+            ],
+            normals: [edge2.cross(&edge1).normalize(); 3],
+            uvs: [Vec2::new(0.0, 0.0); 3],
+            material,
+            opacity: None,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut random_direction = || {
+            Vec3::new(
+                rng.gen_range(-1.0, 1.0),
+                rng.gen_range(-1.0, 1.0),
+                rng.gen_range(-1.0, 1.0),
+            )
+            .normalize()
+        };
+        let targets: [&dyn Intersect; 2] = [&sphere, &triangle];
+        for target in targets.iter() {
+            for _ in 0..1000 {
+                let ray = Ray::new(centre + random_direction() * 100.0, random_direction());
+                let ray = Ray::new(ray.origin, centre - ray.origin + ray.direction);
+                let info = match target.intersect(&ray) {
+                    Some(info) => info,
+                    None => continue,
+                };
+                // Leave on the side the ray arrived from, somewhere in that hemisphere
+                let side = info.retro.dot(&info.normal).signum();
+                let direction = random_direction();
+                let direction = if direction.dot(&info.normal) * side < 0.0 {
+                    -direction
+                } else {
+                    direction
+                };
+                let spawned = info.spawn_ray(&direction);
+                assert!(target.intersect(&spawned).is_none());
+                assert!(!target.intersect_any(&spawned, f64::INFINITY));
+            }
+        }
+    }
 }
@@ -0,0 +1,331 @@
+use crate::materials::Material;
+use crate::math::{Affine3, OrthonormalBasis, Vec2, Vec3};
+use crate::util::float_error::gamma;
+use crate::validation::{SceneIssue, SceneValidator};
+
+use super::{
+    BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray, SampleSurface,
+    SurfaceSample, Transform,
+};
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// A sphere clipped to a band of heights along an axis, and to a range of angles around it
+///
+/// This follows PBRT: only the part of the sphere with a height (measured from the centre,
+/// along `axis`) between `z_min` and `z_max`, and an angle around the axis between zero and
+/// `phi_max`, is kept. A hemisphere has `z_min == 0.0`, and a dome or bowl a `z_min` or
+/// `z_max` part of the way to the pole. The surface is open, so it can be seen from inside
+/// through the gaps, but the normal always faces away from the centre.
+#[derive(Clone, Debug)]
+pub struct PartialSphere {
+    centre: Vec3,
+    radius: f64,
+    frame: OrthonormalBasis,
+    z_min: f64,
+    z_max: f64,
+    phi_max: f64,
+    material: Arc<dyn Material>,
+}
+
+impl PartialSphere {
+    /// Angles are measured counter-clockwise about `axis`, starting from an arbitrary
+    /// direction perpendicular to it; `z_min` and `z_max` are clamped to the sphere and
+    /// `phi_max` to a full turn.
+    pub fn new(
+        centre: Vec3,
+        radius: f64,
+        axis: Vec3,
+        z_min: f64,
+        z_max: f64,
+        phi_max: f64,
+        material: Arc<dyn Material>,
+    ) -> PartialSphere {
+        // Not clamp(), which would panic on the bad radii that validate() reports
+        let to_sphere = |z: f64| z.max(-radius.abs()).min(radius.abs());
+        PartialSphere {
+            centre,
+            radius,
+            frame: OrthonormalBasis::from_normal(&axis.normalize()),
+            z_min: to_sphere(z_min.min(z_max)),
+            z_max: to_sphere(z_min.max(z_max)),
+            phi_max: phi_max.clamp(0.0, 2.0 * PI),
+            material,
+        }
+    }
+
+    /// A half sphere, open on the side away from `axis`
+    pub fn hemisphere(
+        centre: Vec3,
+        radius: f64,
+        axis: Vec3,
+        material: Arc<dyn Material>,
+    ) -> PartialSphere {
+        PartialSphere::new(centre, radius, axis, 0.0, radius, 2.0 * PI, material)
+    }
+
+    /// The angle of `local` about the axis, between zero and a full turn
+    fn phi(local: &Vec3) -> f64 {
+        let phi = local.y().atan2(local.x());
+        if phi < 0.0 {
+            phi + 2.0 * PI
+        } else {
+            phi
+        }
+    }
+
+    fn uv(&self, local: &Vec3) -> Vec2 {
+        let theta = |z: f64| (z / self.radius).clamp(-1.0, 1.0).acos();
+        let theta_z_min = theta(self.z_min);
+        let theta_z_max = theta(self.z_max);
+        Vec2::new(
+            PartialSphere::phi(local) / self.phi_max,
+            (theta(local.z()) - theta_z_min) / (theta_z_max - theta_z_min),
+        )
+    }
+
+    /// A frame with the tangent along the direction of increasing phi
+    fn tangent_frame(&self, local: &Vec3, normal: &Vec3) -> OrthonormalBasis {
+        let along_phi = self.frame.to_world(&Vec3::new(-local.y(), local.x(), 0.0));
+        let length = along_phi.norm();
+        if length < 0.000_001 * self.radius {
+            OrthonormalBasis::from_normal(normal)
+        } else {
+            let tangent = along_phi / length;
+            OrthonormalBasis::new(tangent, normal.cross(&tangent), *normal)
+        }
+    }
+}
+
+impl Transform for PartialSphere {
+    fn transform(&self, transformation: &Affine3) -> Self {
+        let normal = transformation
+            .transform_normal(&self.frame.normal)
+            .normalize();
+        let scaled_tangent = transformation.transform_vector(&self.frame.tangent);
+        let tangent = (scaled_tangent - normal * scaled_tangent.dot(&normal)).normalize();
+        // As with spheres, this is only correct if the result is still a sphere
+        let scale = scaled_tangent.norm();
+        PartialSphere {
+            centre: transformation.transform_point(&self.centre),
+            radius: self.radius * scale,
+            frame: OrthonormalBasis::new(tangent, normal.cross(&tangent), normal),
+            z_min: self.z_min * scale,
+            z_max: self.z_max * scale,
+            phi_max: self.phi_max,
+            material: Arc::clone(&self.material),
+        }
+    }
+}
+
+impl Intersect for PartialSphere {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        let offset = ray.origin - self.centre;
+        let a = ray.direction.norm_squared();
+        let b = 2.0 * offset.dot(&ray.direction);
+        let c = offset.norm_squared() - self.radius * self.radius;
+        let delta_squared = b * b - 4.0 * a * c;
+        if delta_squared < 0.0 {
+            return None;
+        }
+        let delta = delta_squared.sqrt();
+        let one_over_2_a = 1.0 / (2.0 * a);
+        // If the nearer hit has been clipped away the ray may still hit the far side,
+        // through the gap
+        [(-b - delta) * one_over_2_a, (-b + delta) * one_over_2_a]
+            .iter()
+            .filter(|&&distance| distance > 0.0)
+            .find_map(|&distance| {
+                let from_centre = ray.point_at(distance) - self.centre;
+                let from_centre = from_centre * (self.radius / from_centre.norm());
+                let local = self.frame.to_local(&from_centre);
+                if local.z() < self.z_min
+                    || local.z() > self.z_max
+                    || PartialSphere::phi(&local) > self.phi_max
+                {
+                    return None;
+                }
+                let location = self.centre + from_centre;
+                let normal = from_centre.normalize();
+                let OrthonormalBasis {
+                    tangent, cotangent, ..
+                } = self.tangent_frame(&local, &normal);
+                Some(IntersectionInfo {
+                    distance,
+                    location,
+                    location_error: from_centre.abs() * gamma(5) + location.abs() * gamma(1),
+                    normal,
+                    tangent,
+                    cotangent,
+                    retro: -ray.direction,
+                    uv: self.uv(&local),
+                    material: Arc::clone(&self.material),
+                    object_id: 0,
+                    footprint: None,
+                    time: 0.0,
+                })
+            })
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_vertex(&self.centre);
+        if self.radius <= 0.0 || self.radius.is_nan() {
+            validator.report(SceneIssue::ZeroRadiusSphere {
+                centre: self.centre,
+                radius: self.radius,
+            });
+        }
+        validator.check_material(&self.material);
+    }
+}
+
+impl HasBoundingBox for PartialSphere {
+    fn bounding_box(&self) -> BoundingBox {
+        // The box around the band of heights, in the sphere's own frame
+        let r = self.radius;
+        let corners: Vec<Vec3> = [(-r, -r), (-r, r), (r, -r), (r, r)]
+            .iter()
+            .flat_map(|&(x, y)| {
+                [self.z_min, self.z_max]
+                    .iter()
+                    .map(move |&z| self.centre + self.frame.to_world(&Vec3::new(x, y, z)))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        BoundingBox::from_points(&corners)
+    }
+}
+
+impl Primitive for PartialSphere {
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(self.transform(transformation))
+    }
+}
+
+impl SampleSurface for PartialSphere {
+    fn surface_area(&self) -> f64 {
+        // By Archimedes' hat-box theorem, equal heights of sphere have equal areas
+        self.phi_max * self.radius * (self.z_max - self.z_min)
+    }
+
+    fn sample_surface(&self, rng: &mut dyn RngCore) -> SurfaceSample {
+        let z = self.z_min + (self.z_max - self.z_min) * rng.sample::<f64, _>(Open01);
+        let phi = self.phi_max * rng.sample::<f64, _>(Open01);
+        let r = (self.radius * self.radius - z * z).max(0.0).sqrt();
+        let normal = self
+            .frame
+            .to_world(&Vec3::new(r * phi.cos(), r * phi.sin(), z))
+            / self.radius;
+        SurfaceSample {
+            location: self.centre + normal * self.radius,
+            normal,
+            pdf: 1.0 / self.surface_area(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn dome() -> PartialSphere {
+        PartialSphere::hemisphere(
+            Vec3::new(1.0, 2.0, 3.0),
+            2.0,
+            Vec3::unit_z(),
+            Arc::new(LambertianMaterial::new_dummy()),
+        )
+    }
+
+    #[test]
+    fn ray_through_open_side_hits_inside_of_dome() {
+        let ray = Ray::new(Vec3::new(1.0, 2.0, -5.0), Vec3::unit_z());
+        let info = dome().intersect(&ray).unwrap();
+        assert!((info.distance - 10.0).abs() < 0.000001);
+        assert!((info.normal - Vec3::unit_z()).norm() < 0.000001);
+    }
+
+    #[test]
+    fn ray_below_dome_misses() {
+        let ray = Ray::new(Vec3::new(-5.0, 2.0, 2.0), Vec3::unit_x());
+        assert!(dome().intersect(&ray).is_none());
+    }
+
+    #[test]
+    fn phi_max_clips_around_axis() {
+        let half = PartialSphere::new(
+            Vec3::zeros(),
+            1.0,
+            Vec3::unit_z(),
+            -1.0,
+            1.0,
+            PI,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let inside = half.frame.to_world(&Vec3::new(0.0, 0.5, 0.0));
+        let outside = half.frame.to_world(&Vec3::new(0.0, -0.5, 0.0));
+        let hit =
+            |point: Vec3| half.intersect(&Ray::new(point + Vec3::unit_z() * 5.0, -Vec3::unit_z()));
+        assert!(hit(inside).is_some());
+        // Both the near and the far hit are on the half that's been clipped away
+        assert!(hit(outside).is_none());
+    }
+
+    #[test]
+    fn hemisphere_has_half_the_area_of_sphere() {
+        assert!((dome().surface_area() - 8.0 * PI).abs() < 0.000000001);
+    }
+
+    #[quickcheck]
+    fn surface_samples_are_within_bounds(seed: u64, axis: Vec3) -> TestResult {
+        if axis.norm() == 0.0 {
+            return TestResult::discard();
+        }
+        let target = PartialSphere::new(
+            Vec3::new(1.0, 2.0, 3.0),
+            2.0,
+            axis,
+            -0.5,
+            1.5,
+            4.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let sample = target.sample_surface(&mut StdRng::seed_from_u64(seed));
+        let local = target.frame.to_local(&(sample.location - target.centre));
+        TestResult::from_bool(
+            target.bounding_box().contains_point(sample.location)
+                && local.z() >= -0.500001
+                && local.z() <= 1.500001
+                && PartialSphere::phi(&local) <= 4.000001,
+        )
+    }
+
+    #[test]
+    fn uv_spans_clipped_range() {
+        let target = dome();
+        let top = target
+            .intersect(&Ray::new(Vec3::new(1.0, 2.0, 10.0), -Vec3::unit_z()))
+            .unwrap();
+        assert!((top.uv.y() - 1.0).abs() < 0.000001);
+        assert!((top.tangent.cross(&top.cotangent) - top.normal).norm() < 0.000001);
+    }
+
+    #[test]
+    fn translation_moves_clipped_region() {
+        let target = dome().transform(&Affine3::translation(&Vec3::new(0.0, 0.0, 10.0)));
+        let ray = Ray::new(Vec3::new(1.0, 2.0, 5.0), Vec3::unit_z());
+        let info = target.intersect(&ray).unwrap();
+        assert!((info.distance - 10.0).abs() < 0.000001);
+    }
+}
@@ -1,7 +1,9 @@
 use crate::materials::Material;
-use crate::math::Vec3;
+use crate::math::{Affine3, OrthonormalBasis, Vec2, Vec3};
+use crate::util::float_error::ray_plane_point_error;
+use crate::validation::SceneValidator;
 
-use super::{BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray};
+use super::{BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray, Transform};
 
 use std::sync::Arc;
 
@@ -17,10 +19,9 @@ pub struct Plane {
 impl Plane {
     pub fn new(normal: Vec3, distance_from_origin: f64, material: Arc<dyn Material>) -> Plane {
         let normal = normal.normalize();
-        let mut axis_closest_to_tangent = Vec3::zeros();
-        axis_closest_to_tangent[normal.smallest_coord()] = 1.0;
-        let cotangent = normal.cross(&axis_closest_to_tangent).normalize();
-        let tangent = normal.cross(&cotangent);
+        let OrthonormalBasis {
+            tangent, cotangent, ..
+        } = OrthonormalBasis::from_normal(&normal);
         Plane {
             normal,
             tangent,
@@ -31,19 +32,21 @@ impl Plane {
     }
 }
 
-/*impl Transform for Plane {
-    fn transform(&self, transformation: &Affine3<f64>) -> Self {
+impl Transform for Plane {
+    fn transform(&self, transformation: &Affine3) -> Self {
+        let point_on_plane =
+            transformation.transform_point(&(self.normal * self.distance_from_origin));
+        let normal = transformation.transform_normal(&self.normal).normalize();
+        let cotangent = transformation.transform_vector(&self.cotangent).normalize();
         Plane {
-            normal: transformation.transform_vector(&self.normal).normalize(),
-            cotangent: transformation.transform_vector(&self.cotangent).normalize(),
-            tangent: self.normal.cross(&self.cotangent),
-            distance_from_origin: transformation
-                .transform_vector(&(self.normal * self.distance_from_origin))
-                .norm(),
+            normal,
+            tangent: cotangent.cross(&normal),
+            cotangent,
+            distance_from_origin: point_on_plane.dot(&normal),
             material: Arc::clone(&self.material),
         }
     }
-}*/
+}
 
 impl Intersect for Plane {
     fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
@@ -62,16 +65,31 @@ impl Intersect for Plane {
         if t < 0.0 {
             return None;
         }
+        let location = ray.point_at(t);
         Some(IntersectionInfo {
             distance: t,
-            location: ray.point_at(t),
+            location,
+            location_error: ray_plane_point_error(
+                &ray.origin,
+                &(ray.direction * t),
+                &point_on_plane,
+            ),
             normal: self.normal,
             tangent: self.tangent,
             cotangent: self.cotangent,
             retro: -ray.direction,
+            uv: Vec2::new(location.dot(&self.tangent), location.dot(&self.cotangent)),
             material: Arc::clone(&self.material),
+            object_id: 0,
+            footprint: None,
+            time: 0.0,
         })
     }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_normal(&self.normal);
+        validator.check_material(&self.material);
+    }
 }
 
 impl HasBoundingBox for Plane {
@@ -106,7 +124,11 @@ impl HasBoundingBox for Plane {
     }
 }
 
-impl Primitive for Plane {}
+impl Primitive for Plane {
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(self.transform(transformation))
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -153,16 +175,53 @@ mod tests {
             Some(IntersectionInfo {
                 distance: _,
                 location,
+                location_error: _,
                 normal: _,
                 tangent: _,
                 cotangent: _,
                 retro: _,
+                uv: _,
                 material: _,
+                object_id: _,
+                footprint: _,
+                time: _,
             }) => assert!((location.x() - (-5.0f64)).abs() < 0.0000000001),
             None => panic!(),
         }
     }
 
+    #[test]
+    fn translation_along_normal_changes_distance_from_origin() {
+        let target = Plane::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            2.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let target = target.transform(&Affine3::translation(&Vec3::new(5.0, 3.0, -1.0)));
+        assert!((target.distance_from_origin - 5.0).abs() < 0.0000000001);
+        assert!((target.normal - Vec3::new(0.0, 1.0, 0.0)).norm() < 0.0000000001);
+    }
+
+    #[test]
+    fn rotated_plane_is_intersected_at_expected_location() {
+        let target = Plane::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            -2.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let target = target.transform(&Affine3::rotation(
+            &Vec3::unit_z(),
+            std::f64::consts::FRAC_PI_2,
+        ));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let location = target.intersect(&ray).unwrap().location;
+        assert!((location - Vec3::new(2.0, 0.0, 0.0)).norm() < 0.0000000001);
+        let basis_is_orthonormal = target.tangent.dot(&target.normal).abs() < 0.0000000001
+            && target.cotangent.dot(&target.normal).abs() < 0.0000000001
+            && (target.tangent.norm() - 1.0).abs() < 0.0000000001;
+        assert!(basis_is_orthonormal);
+    }
+
     #[test]
     fn bounding_box_is_correct_for_yz_plane() {
         let target = Plane::new(
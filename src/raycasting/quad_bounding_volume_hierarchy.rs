@@ -0,0 +1,433 @@
+use crate::math::{narrow_down, narrow_up, widen, GeometryReal, Vec3};
+use crate::statistics;
+use crate::validation::{SceneIssue, SceneValidator};
+
+use super::bounding_volume_hierarchy::NodeContents;
+use super::ray_packet::keep_nearest_in_each_lane;
+use super::{
+    Aggregate, BoundingBox, BoundingVolumeHierarchy, HasBoundingBox, Intersect, IntersectionInfo,
+    Lanes, Primitive, Ray, RayPacket,
+};
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// Number of children of each node of a
+/// [QuadBoundingVolumeHierarchy](QuadBoundingVolumeHierarchy)
+pub const BRANCHING_FACTOR: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+enum QuadChild {
+    Empty,
+    Node(usize),
+    Leaf { start: usize, count: usize },
+}
+
+/// A node of a [QuadBoundingVolumeHierarchy](QuadBoundingVolumeHierarchy)
+///
+/// The bounds of the children are stored one coordinate at a time, so that a ray can be
+/// tested against all four boxes together.
+struct QuadNode {
+    min: [[GeometryReal; BRANCHING_FACTOR]; 3],
+    max: [[GeometryReal; BRANCHING_FACTOR]; 3],
+    children: [QuadChild; BRANCHING_FACTOR],
+}
+
+impl QuadNode {
+    fn empty() -> QuadNode {
+        QuadNode {
+            min: [[GeometryReal::INFINITY; BRANCHING_FACTOR]; 3],
+            max: [[GeometryReal::NEG_INFINITY; BRANCHING_FACTOR]; 3],
+            children: [QuadChild::Empty; BRANCHING_FACTOR],
+        }
+    }
+
+    fn set_child(&mut self, slot: usize, bounds: &BoundingBox, child: QuadChild) {
+        for (axis, interval) in bounds.bounds.iter().enumerate() {
+            self.min[axis][slot] = narrow_down(interval.get_min());
+            self.max[axis][slot] = narrow_up(interval.get_max());
+        }
+        self.children[slot] = child;
+    }
+
+    fn child_bounds(&self, slot: usize) -> BoundingBox {
+        BoundingBox::from_corners(
+            Vec3::new(
+                widen(self.min[0][slot]),
+                widen(self.min[1][slot]),
+                widen(self.min[2][slot]),
+            ),
+            Vec3::new(
+                widen(self.max[0][slot]),
+                widen(self.max[1][slot]),
+                widen(self.max[2][slot]),
+            ),
+        )
+    }
+
+    /// The distance along `ray` to where it enters the bounds of each child, or `None` for
+    /// children it misses
+    ///
+    /// Bounds are tested in the same way as [IntersectP](super::IntersectP) does, so the
+    /// entry distance can be negative if the ray starts inside or beyond the box.
+    fn intersect_children(&self, ray: &Ray) -> [Option<f64>; BRANCHING_FACTOR] {
+        let mut t_min = [f64::NEG_INFINITY; BRANCHING_FACTOR];
+        let mut t_max = [f64::INFINITY; BRANCHING_FACTOR];
+        for axis in 0..3 {
+            let (origin, direction) = (ray.origin[axis], ray.direction[axis]);
+            for slot in 0..BRANCHING_FACTOR {
+                let t0 = (widen(self.min[axis][slot]) - origin) / direction;
+                let t1 = (widen(self.max[axis][slot]) - origin) / direction;
+                let (near, far) = if t0 > t1 { (t1, t0) } else { (t0, t1) };
+                t_min[slot] = t_min[slot].max(near);
+                t_max[slot] = t_max[slot].min(far);
+            }
+        }
+        let mut result = [None; BRANCHING_FACTOR];
+        for (slot, child) in self.children.iter().enumerate() {
+            if !matches!(child, QuadChild::Empty) && t_min[slot] <= t_max[slot] {
+                result[slot] = Some(t_min[slot]);
+            }
+        }
+        result
+    }
+}
+
+/// Indices of the nodes of `bvh` that become the children of node `index` in a four-way
+/// tree
+///
+/// These are the grandchildren of the node, or its children where they are leaves.
+fn quad_children(bvh: &BoundingVolumeHierarchy, index: usize) -> Vec<usize> {
+    let expand = |child| match bvh.children(child) {
+        Some((left, right)) => vec![left, right],
+        None => vec![child],
+    };
+    match bvh.children(index) {
+        Some((left, right)) => {
+            let mut children = expand(left);
+            children.extend(expand(right));
+            children
+        }
+        None => vec![index],
+    }
+}
+
+/// A bounding volume hierarchy with four children at each node
+///
+/// This is built by collapsing every other level of a binary
+/// [BoundingVolumeHierarchy](BoundingVolumeHierarchy), so it has half as many levels. Each
+/// node stores the bounds of all four children together, and a ray is tested against them
+/// at once, which the compiler turns into SIMD instructions. Children are visited nearest
+/// first, and any that start beyond the nearest intersection found so far are skipped.
+///
+/// A ray finds the same intersections as with the binary tree it was built from, but with
+/// fewer, cheaper steps, which speeds up large meshes.
+///
+/// As with the binary tree, [unbounded](BoundingBox::is_unbounded) primitives are kept out of
+/// the nodes and tested against every ray first, so that the nearest of them can cut the
+/// traversal short.
+///
+/// Nodes and primitives are stored in flat arrays, so the tree can't be changed once it's
+/// built; animated scenes should use a [BoundingVolumeHierarchy](BoundingVolumeHierarchy)
+/// and [refit()](BoundingVolumeHierarchy::refit) it instead.
+pub struct QuadBoundingVolumeHierarchy {
+    bounds: BoundingBox,
+    nodes: Vec<QuadNode>,
+    primitives: Vec<Arc<dyn Primitive>>,
+    unbounded: Vec<Arc<dyn Primitive>>,
+}
+
+impl QuadBoundingVolumeHierarchy {
+    pub fn build(primitives: &mut [Arc<dyn Primitive>]) -> Self {
+        QuadBoundingVolumeHierarchy::from_binary(&BoundingVolumeHierarchy::build(primitives))
+    }
+
+    /// A four-way tree with the same leaves as `bvh`
+    pub fn from_binary(bvh: &BoundingVolumeHierarchy) -> Self {
+        let mut result = QuadBoundingVolumeHierarchy {
+            bounds: bvh.bounding_box(),
+            nodes: vec![],
+            primitives: bvh.primitives().to_vec(),
+            unbounded: bvh.unbounded().to_vec(),
+        };
+        result.add_node(bvh, 0);
+        result
+    }
+
+    /// Add the node whose children are the [quad children](quad_children) of node `index`
+    /// of `bvh`, along with everything below it, and return its index
+    fn add_node(&mut self, bvh: &BoundingVolumeHierarchy, index: usize) -> usize {
+        let quad_index = self.nodes.len();
+        self.nodes.push(QuadNode::empty());
+        for (slot, child) in quad_children(bvh, index).into_iter().enumerate() {
+            let node = &bvh.nodes()[child];
+            let entry = match node.contents {
+                NodeContents::Interior { .. } => QuadChild::Node(self.add_node(bvh, child)),
+                NodeContents::Leaf { start, count } => QuadChild::Leaf { start, count },
+            };
+            self.nodes[quad_index].set_child(slot, &node.bounds, entry);
+        }
+        quad_index
+    }
+
+    fn leaf_primitives(&self, start: usize, count: usize) -> &[Arc<dyn Primitive>] {
+        &self.primitives[start..start + count]
+    }
+
+    /// Push the children of `node` that `ray` passes through onto `stack`, with the
+    /// distances at which it enters them, so that the nearest is on top
+    fn push_children_hit(&self, node: usize, ray: &Ray, stack: &mut Vec<(f64, QuadChild)>) {
+        let node = &self.nodes[node];
+        let first = stack.len();
+        for (t_near, &child) in node
+            .intersect_children(ray)
+            .iter()
+            .zip(node.children.iter())
+        {
+            if let Some(t_near) = *t_near {
+                // Insertion sort, farthest first, as there are at most four
+                let mut position = stack.len();
+                while position > first && stack[position - 1].0 < t_near {
+                    position -= 1;
+                }
+                stack.insert(position, (t_near, child));
+            }
+        }
+    }
+
+    fn intersect_node_packet(
+        &self,
+        node: usize,
+        packet: &RayPacket,
+    ) -> Lanes<Option<IntersectionInfo>> {
+        statistics::count_bvh_node_visited();
+        let node = &self.nodes[node];
+        let mut nearest = Default::default();
+        for (slot, &child) in node.children.iter().enumerate() {
+            if let QuadChild::Empty = child {
+                continue;
+            }
+            let packet = packet.masked(&packet.intersect_bounds(&node.child_bounds(slot)));
+            if !packet.any_active() {
+                continue;
+            }
+            match child {
+                QuadChild::Empty => {}
+                QuadChild::Node(index) => {
+                    keep_nearest_in_each_lane(
+                        &mut nearest,
+                        self.intersect_node_packet(index, &packet),
+                    );
+                }
+                QuadChild::Leaf { start, count } => {
+                    for primitive in self.leaf_primitives(start, count) {
+                        keep_nearest_in_each_lane(
+                            &mut nearest,
+                            primitive.intersect_packet(&packet),
+                        );
+                    }
+                }
+            }
+        }
+        nearest
+    }
+}
+
+impl Intersect for QuadBoundingVolumeHierarchy {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        let mut nearest = self
+            .unbounded
+            .iter()
+            .filter_map(|primitive| primitive.intersect(ray))
+            .min_by(|a, b| {
+                a.distance
+                    .partial_cmp(&b.distance)
+                    .unwrap_or(Ordering::Equal)
+            });
+        let mut stack = Vec::with_capacity(64);
+        stack.push((f64::NEG_INFINITY, QuadChild::Node(0)));
+        while let Some((t_near, child)) = stack.pop() {
+            if nearest.as_ref().is_some_and(|info| info.distance < t_near) {
+                continue;
+            }
+            match child {
+                QuadChild::Empty => {}
+                QuadChild::Node(index) => {
+                    statistics::count_bvh_node_visited();
+                    self.push_children_hit(index, ray, &mut stack);
+                }
+                QuadChild::Leaf { start, count } => {
+                    for primitive in self.leaf_primitives(start, count) {
+                        if let Some(info) = primitive.intersect(ray) {
+                            if nearest
+                                .as_ref()
+                                .is_none_or(|current| current.distance > info.distance)
+                            {
+                                nearest = Some(info);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        nearest
+    }
+
+    fn intersect_any(&self, ray: &Ray, max_distance: f64) -> bool {
+        if self
+            .unbounded
+            .iter()
+            .any(|primitive| primitive.intersect_any(ray, max_distance))
+        {
+            return true;
+        }
+        let mut stack = Vec::with_capacity(64);
+        stack.push((f64::NEG_INFINITY, QuadChild::Node(0)));
+        while let Some((t_near, child)) = stack.pop() {
+            if t_near > max_distance {
+                continue;
+            }
+            match child {
+                QuadChild::Empty => {}
+                QuadChild::Node(index) => {
+                    statistics::count_bvh_node_visited();
+                    self.push_children_hit(index, ray, &mut stack);
+                }
+                QuadChild::Leaf { start, count } => {
+                    if self
+                        .leaf_primitives(start, count)
+                        .iter()
+                        .any(|primitive| primitive.intersect_any(ray, max_distance))
+                    {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes<Option<IntersectionInfo>> {
+        let mut nearest = self.intersect_node_packet(0, packet);
+        for primitive in &self.unbounded {
+            keep_nearest_in_each_lane(&mut nearest, primitive.intersect_packet(packet));
+        }
+        nearest
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        if self.primitives.is_empty() && self.unbounded.is_empty() {
+            validator.report(SceneIssue::EmptyAggregate);
+        }
+        for primitive in self.primitives.iter().chain(self.unbounded.iter()) {
+            primitive.validate(validator);
+        }
+    }
+}
+
+impl HasBoundingBox for QuadBoundingVolumeHierarchy {
+    fn bounding_box(&self) -> BoundingBox {
+        self.bounds
+    }
+}
+
+impl Aggregate for QuadBoundingVolumeHierarchy {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+    use crate::raycasting::{Plane, Sphere, RAY_PACKET_WIDTH};
+
+    fn grid_of_spheres(size: usize) -> Vec<Arc<dyn Primitive>> {
+        let material = Arc::new(LambertianMaterial::new_dummy());
+        (0..size * size)
+            .map(|i| {
+                Arc::new(Sphere::new(
+                    Vec3::new((i % size) as f64 * 3.0, (i / size) as f64 * 3.0, 0.0),
+                    1.0,
+                    material.clone(),
+                )) as Arc<dyn Primitive>
+            })
+            .collect()
+    }
+
+    /// Rays from a point in front of the grid, spread across it and a little beyond
+    fn rays(size: usize) -> Vec<Ray> {
+        (0..size * size * 4)
+            .map(|i| {
+                let x = (i % (size * 2)) as f64 * 1.5 - 1.75;
+                let y = (i / (size * 2)) as f64 * 1.5 - 1.75;
+                Ray::new(
+                    Vec3::new(10.0, 10.0, -20.0),
+                    Vec3::new(x - 10.0, y - 10.0, 20.0),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_same_intersections_as_binary_tree() {
+        let size = 20;
+        let bvh = BoundingVolumeHierarchy::build(&mut grid_of_spheres(size));
+        let target = QuadBoundingVolumeHierarchy::from_binary(&bvh);
+        for ray in rays(size) {
+            let expected = bvh.intersect(&ray).map(|info| info.location);
+            assert!(target.intersect(&ray).map(|info| info.location) == expected);
+            for &max_distance in [5.0, 21.0, f64::INFINITY].iter() {
+                assert!(
+                    target.intersect_any(&ray, max_distance)
+                        == bvh.intersect_any(&ray, max_distance)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn packets_find_same_intersections_as_single_rays() {
+        let size = 12;
+        let target = QuadBoundingVolumeHierarchy::build(&mut grid_of_spheres(size));
+        for rays in rays(size).chunks(RAY_PACKET_WIDTH) {
+            let hits = target.intersect_packet(&RayPacket::new(rays));
+            for (ray, hit) in rays.iter().zip(hits.iter()) {
+                let expected = target.intersect(ray).map(|info| info.location);
+                assert!(hit.as_ref().map(|info| info.location) == expected);
+            }
+        }
+    }
+
+    #[test]
+    fn unbounded_primitives_are_hit_as_in_binary_tree() {
+        let size = 8;
+        let mut primitives = grid_of_spheres(size);
+        primitives.push(Arc::new(Plane::new(
+            Vec3::unit_z(),
+            2.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        )));
+        let bvh = BoundingVolumeHierarchy::build(&mut primitives);
+        let target = QuadBoundingVolumeHierarchy::from_binary(&bvh);
+        for rays in rays(size).chunks(RAY_PACKET_WIDTH) {
+            let hits = target.intersect_packet(&RayPacket::new(rays));
+            for (ray, hit) in rays.iter().zip(hits.iter()) {
+                let expected = bvh.intersect(ray).map(|info| info.location);
+                assert!(expected.is_some());
+                assert!(target.intersect(ray).map(|info| info.location) == expected);
+                assert!(hit.as_ref().map(|info| info.location) == expected);
+                assert!(target.intersect_any(ray, 5.0) == bvh.intersect_any(ray, 5.0));
+            }
+        }
+    }
+
+    #[test]
+    fn small_trees_have_a_single_node() {
+        let target = QuadBoundingVolumeHierarchy::build(&mut grid_of_spheres(2));
+        assert!(target.nodes.len() == 1);
+        assert!(target.primitives.len() == 4);
+        let empty = QuadBoundingVolumeHierarchy::build(&mut []);
+        assert!(empty
+            .intersect(&Ray::new(Vec3::zeros(), Vec3::unit_z()))
+            .is_none());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::math::{Vec2, Vec3};
+
+/// The rays through the neighbouring pixels of the image, one pixel to the right and one
+/// pixel up
+///
+/// Following these alongside a camera ray shows how large an area of each surface the ray's
+/// pixel covers, so that textures can be filtered over that area instead of being sampled
+/// at a single point. See H. Igehy, "Tracing Ray Differentials", SIGGRAPH 1999.
+#[derive(Clone, Copy, Debug)]
+pub struct RayDifferential {
+    pub x_origin: Vec3,
+    pub x_direction: Vec3,
+    pub y_origin: Vec3,
+    pub y_direction: Vec3,
+}
+
+/// The area of a surface covered by one pixel, as seen from the point a ray hit
+///
+/// This is the parallelogram spanned by `dp_dx` and `dp_dy`, in the plane tangent to the
+/// surface.
+#[derive(Clone, Copy, Debug)]
+pub struct Footprint {
+    /// Offset from the hit point to where the ray through the next pixel to the right
+    /// meets the tangent plane
+    pub dp_dx: Vec3,
+
+    /// Offset from the hit point to where the ray through the next pixel up meets the
+    /// tangent plane
+    pub dp_dy: Vec3,
+
+    /// Change in surface coordinates from the hit point to where the ray through the next
+    /// pixel to the right meets the surface
+    ///
+    /// Zero when it isn't known, which textures treat as a point sample.
+    pub duv_dx: Vec2,
+
+    /// Change in surface coordinates from the hit point to where the ray through the next
+    /// pixel up meets the surface
+    pub duv_dy: Vec2,
+
+    /// Direction of the ray through the next pixel to the right
+    pub x_direction: Vec3,
+
+    /// Direction of the ray through the next pixel up
+    pub y_direction: Vec3,
+}
+
+/// Where the ray from `origin` in `direction` meets the plane through `location` with
+/// normal `normal`, or `None` if it's parallel to the plane
+fn intersect_tangent_plane(
+    origin: &Vec3,
+    direction: &Vec3,
+    location: &Vec3,
+    normal: &Vec3,
+) -> Option<Vec3> {
+    let denominator = normal.dot(direction);
+    if denominator.abs() < 0.000_000_001 {
+        return None;
+    }
+    let t = normal.dot(&(location - origin)) / denominator;
+    Some(origin + direction * t)
+}
+
+impl RayDifferential {
+    /// The footprint of the pixel on a surface hit at `location`, where its normal is
+    /// `normal`
+    ///
+    /// Returns `None` if a neighbouring ray doesn't reach the surface's tangent plane.
+    pub fn footprint(&self, location: &Vec3, normal: &Vec3) -> Option<Footprint> {
+        let x = intersect_tangent_plane(&self.x_origin, &self.x_direction, location, normal)?;
+        let y = intersect_tangent_plane(&self.y_origin, &self.y_direction, location, normal)?;
+        Some(Footprint {
+            dp_dx: x - *location,
+            dp_dy: y - *location,
+            duv_dx: Vec2::new(0.0, 0.0),
+            duv_dy: Vec2::new(0.0, 0.0),
+            x_direction: self.x_direction,
+            y_direction: self.y_direction,
+        })
+    }
+}
+
+/// Turn `direction` the same way a specular surface with normal `normal` turned `incoming`
+/// into `outgoing`
+///
+/// If `outgoing` is on the same side of the surface as `incoming` came from then this is a
+/// mirror reflection, and otherwise it's a refraction with the relative index of refraction
+/// implied by the two directions.
+fn turn_like(direction: &Vec3, normal: &Vec3, incoming: &Vec3, outgoing: &Vec3) -> Vec3 {
+    // Face the normal towards where the light came from
+    let normal = if incoming.dot(normal) > 0.0 {
+        -*normal
+    } else {
+        *normal
+    };
+    let reflection = *direction - normal * (2.0 * direction.dot(&normal));
+    if outgoing.dot(&normal) > 0.0 {
+        return reflection;
+    }
+    // Snell's law scales the tangential part of the direction by the relative index of
+    // refraction; at normal incidence it can't be recovered, so assume the two are equal
+    let tangential = |v: &Vec3| *v - normal * v.dot(&normal);
+    let incoming_tangential = tangential(incoming).norm();
+    let eta = if incoming_tangential > 0.000_001 {
+        tangential(outgoing).norm() / incoming_tangential
+    } else {
+        1.0
+    };
+    let cos_i = -direction.dot(&normal);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return reflection;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    (direction * eta + normal * (eta * cos_i - cos_t)).normalize()
+}
+
+impl Footprint {
+    /// The largest distance across the footprint
+    ///
+    /// This is the usual measure for choosing which level of a mipmap to sample.
+    pub fn width(&self) -> f64 {
+        self.dp_dx.norm().max(self.dp_dy.norm())
+    }
+
+    /// The differential of the ray leaving `location` in direction `outgoing`, after a ray
+    /// arriving in direction `incoming` bounced off a specular surface there
+    ///
+    /// The surface is treated as flat across the footprint, which is a good approximation
+    /// unless it's very strongly curved.
+    pub fn specular_bounce(
+        &self,
+        location: &Vec3,
+        normal: &Vec3,
+        incoming: &Vec3,
+        outgoing: &Vec3,
+    ) -> RayDifferential {
+        RayDifferential {
+            x_origin: location + self.dp_dx,
+            x_direction: turn_like(&self.x_direction, normal, incoming, outgoing),
+            y_origin: location + self.dp_dy,
+            y_direction: turn_like(&self.y_direction, normal, incoming, outgoing),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spreading_differential() -> RayDifferential {
+        RayDifferential {
+            x_origin: Vec3::zeros(),
+            x_direction: Vec3::new(0.01, 0.0, 1.0).normalize(),
+            y_origin: Vec3::zeros(),
+            y_direction: Vec3::new(0.0, 0.01, 1.0).normalize(),
+        }
+    }
+
+    #[test]
+    fn footprint_grows_with_distance() {
+        let target = spreading_differential();
+        let near = target
+            .footprint(&Vec3::new(0.0, 0.0, 1.0), &-Vec3::unit_z())
+            .unwrap();
+        let far = target
+            .footprint(&Vec3::new(0.0, 0.0, 10.0), &-Vec3::unit_z())
+            .unwrap();
+        assert!((near.width() - 0.01).abs() < 0.000001);
+        assert!((far.width() - 0.1).abs() < 0.000001);
+        assert!((far.dp_dx - Vec3::new(0.1, 0.0, 0.0)).norm() < 0.000001);
+    }
+
+    #[test]
+    fn footprint_is_stretched_on_slanted_surface() {
+        let target = spreading_differential();
+        let normal = Vec3::new(1.0, 0.0, -1.0).normalize();
+        let footprint = target
+            .footprint(&Vec3::new(0.0, 0.0, 10.0), &normal)
+            .unwrap();
+        assert!(footprint.dp_dx.norm() > 0.1 * 2.0f64.sqrt() * 0.99);
+        assert!((footprint.dp_dy.norm() - 0.1).abs() < 0.001);
+    }
+
+    #[test]
+    fn mirror_reflection_keeps_spread() {
+        let footprint = spreading_differential()
+            .footprint(&Vec3::new(0.0, 0.0, 10.0), &-Vec3::unit_z())
+            .unwrap();
+        let reflected = footprint.specular_bounce(
+            &Vec3::new(0.0, 0.0, 10.0),
+            &-Vec3::unit_z(),
+            &Vec3::unit_z(),
+            &-Vec3::unit_z(),
+        );
+        assert!(reflected.x_direction.z() < 0.0);
+        // Reflected rays keep diverging as if they came from a mirror image of the camera
+        let next = reflected
+            .footprint(&Vec3::zeros(), &Vec3::unit_z())
+            .unwrap();
+        assert!((next.width() - 0.2).abs() < 0.000001);
+    }
+
+    #[test]
+    fn refraction_bends_neighbouring_rays_by_same_index() {
+        let normal = Vec3::unit_y();
+        let incoming = Vec3::new(1.0, -1.0, 0.0).normalize();
+        let eta: f64 = 1.0 / 1.5;
+        let sin_t = eta * std::f64::consts::FRAC_1_SQRT_2;
+        let outgoing = Vec3::new(sin_t, -(1.0 - sin_t * sin_t).sqrt(), 0.0);
+        let neighbour = Vec3::new(1.1, -1.0, 0.0).normalize();
+        let result = turn_like(&neighbour, &normal, &incoming, &outgoing);
+        let sin_i = neighbour.x();
+        assert!((result.x() - eta * sin_i).abs() < 0.000001);
+        assert!(result.y() < 0.0);
+        assert!((result.norm() - 1.0).abs() < 0.000001);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::math::Vec3;
+
+use super::{BoundingBox, IntersectionInfo, Ray};
+
+/// Number of rays traced together in a [RayPacket](RayPacket)
+pub const RAY_PACKET_WIDTH: usize = 4;
+
+/// One value for each ray in a [RayPacket](RayPacket)
+pub type Lanes<T> = [T; RAY_PACKET_WIDTH];
+
+/// Several rays that are traced through the scene together
+///
+/// Rays that start close together and point in similar directions, such as the camera rays
+/// for neighbouring pixels, mostly visit the same nodes of a
+/// [BoundingVolumeHierarchy](super::BoundingVolumeHierarchy). Tracing them as a packet
+/// loads each node once for all of them, and tests its bounds against every ray at once.
+///
+/// Ray coordinates are stored one axis at a time, so that the same operation is applied to
+/// every ray in turn, which the compiler turns into SIMD instructions. Rays that are no
+/// longer of interest are masked off, and primitives skip them.
+#[derive(Clone, Debug)]
+pub struct RayPacket<'a> {
+    rays: &'a [Ray],
+    active: Lanes<bool>,
+    origin: [Lanes<f64>; 3],
+    direction: [Lanes<f64>; 3],
+}
+
+impl<'a> RayPacket<'a> {
+    /// A packet of `rays`, all of which are active
+    ///
+    /// # Panics
+    ///
+    /// If there are more than [RAY_PACKET_WIDTH](RAY_PACKET_WIDTH) rays.
+    pub fn new(rays: &'a [Ray]) -> RayPacket<'a> {
+        assert!(rays.len() <= RAY_PACKET_WIDTH);
+        // Unused lanes get a copy of the first ray so that they don't produce NaNs
+        let lane_ray = |lane: usize| rays.get(lane).or_else(|| rays.first());
+        let coordinate = |f: fn(&Ray) -> Vec3, axis: usize| {
+            std::array::from_fn(|lane| lane_ray(lane).map_or(0.0, |ray| f(ray)[axis]))
+        };
+        RayPacket {
+            rays,
+            active: std::array::from_fn(|lane| lane < rays.len()),
+            origin: std::array::from_fn(|axis| coordinate(|ray| ray.origin, axis)),
+            direction: std::array::from_fn(|axis| coordinate(|ray| ray.direction, axis)),
+        }
+    }
+
+    /// The same packet, with only the rays for which `mask` is true still active
+    pub fn masked(&self, mask: &Lanes<bool>) -> RayPacket<'a> {
+        RayPacket {
+            active: std::array::from_fn(|lane| self.active[lane] && mask[lane]),
+            ..self.clone()
+        }
+    }
+
+    pub fn is_active(&self, lane: usize) -> bool {
+        self.active[lane]
+    }
+
+    pub fn any_active(&self) -> bool {
+        self.active.iter().any(|&active| active)
+    }
+
+    pub fn active(&self) -> &Lanes<bool> {
+        &self.active
+    }
+
+    /// The ray in `lane`, or `None` if the lane is unused
+    pub fn ray(&self, lane: usize) -> Option<&'a Ray> {
+        self.rays.get(lane)
+    }
+
+    /// The rays in the packet, in lane order
+    pub fn rays(&self) -> &'a [Ray] {
+        self.rays
+    }
+
+    /// Coordinate `axis` of the origin of each ray
+    pub fn origin(&self, axis: usize) -> &Lanes<f64> {
+        &self.origin[axis]
+    }
+
+    /// Coordinate `axis` of the direction of each ray
+    pub fn direction(&self, axis: usize) -> &Lanes<f64> {
+        &self.direction[axis]
+    }
+
+    /// Call `f` with each active ray, giving `None` for inactive lanes
+    pub fn map_active<T, F: FnMut(&Ray) -> Option<T>>(&self, mut f: F) -> Lanes<Option<T>> {
+        std::array::from_fn(|lane| match self.ray(lane) {
+            Some(ray) if self.active[lane] => f(ray),
+            _ => None,
+        })
+    }
+
+    /// Which active rays pass through `bounds`
+    ///
+    /// This gives the same result for each ray as testing it alone with
+    /// [IntersectP](super::IntersectP).
+    pub fn intersect_bounds(&self, bounds: &BoundingBox) -> Lanes<bool> {
+        let mut t_min = [f64::NEG_INFINITY; RAY_PACKET_WIDTH];
+        let mut t_max = [f64::INFINITY; RAY_PACKET_WIDTH];
+        for (axis, interval) in bounds.bounds.iter().enumerate() {
+            let (min, max) = (interval.get_min(), interval.get_max());
+            for lane in 0..RAY_PACKET_WIDTH {
+                let t0 = (min - self.origin[axis][lane]) / self.direction[axis][lane];
+                let t1 = (max - self.origin[axis][lane]) / self.direction[axis][lane];
+                let (near, far) = if t0 > t1 { (t1, t0) } else { (t0, t1) };
+                t_min[lane] = t_min[lane].max(near);
+                t_max[lane] = t_max[lane].min(far);
+            }
+        }
+        std::array::from_fn(|lane| self.active[lane] && t_min[lane] <= t_max[lane])
+    }
+}
+
+/// The nearer of two intersections for each lane
+///
+/// Where both are the same distance away, `b` is kept, as with the single-ray traversal of
+/// a [BoundingVolumeHierarchy](super::BoundingVolumeHierarchy).
+pub(super) fn closest_in_each_lane(
+    a: Lanes<Option<IntersectionInfo>>,
+    b: Lanes<Option<IntersectionInfo>>,
+) -> Lanes<Option<IntersectionInfo>> {
+    let mut result = b;
+    for (lane, a_info) in IntoIterator::into_iter(a).enumerate() {
+        if let Some(a_info) = a_info {
+            let keep_a = match &result[lane] {
+                None => true,
+                Some(b_info) => a_info.distance < b_info.distance,
+            };
+            if keep_a {
+                result[lane] = Some(a_info);
+            }
+        }
+    }
+    result
+}
+
+/// Replace the intersection in each lane of `nearest` with the one in `candidates`, if
+/// it's strictly nearer
+///
+/// This keeps the first of several intersections at the same distance, as
+/// [Iterator::min_by()] does when finding the nearest intersection of a single ray.
+pub(super) fn keep_nearest_in_each_lane(
+    nearest: &mut Lanes<Option<IntersectionInfo>>,
+    candidates: Lanes<Option<IntersectionInfo>>,
+) {
+    for (lane, candidate) in IntoIterator::into_iter(candidates).enumerate() {
+        if let Some(candidate) = candidate {
+            let is_nearer = match &nearest[lane] {
+                None => true,
+                Some(current) => current.distance > candidate.distance,
+            };
+            if is_nearer {
+                nearest[lane] = Some(candidate);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::raycasting::IntersectP;
+
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn bounds_test_matches_single_ray(rays: Vec<Ray>, corner1: Vec3, corner2: Vec3) -> bool {
+        let bounds = BoundingBox::from_corners(corner1, corner2);
+        rays.chunks(RAY_PACKET_WIDTH).all(|rays| {
+            let hits = RayPacket::new(rays).intersect_bounds(&bounds);
+            rays.iter()
+                .enumerate()
+                .all(|(lane, ray)| hits[lane] == bounds.intersect(ray))
+        })
+    }
+
+    #[test]
+    fn unused_lanes_are_inactive() {
+        let rays = [Ray::new(Vec3::zeros(), Vec3::unit_z())];
+        let target = RayPacket::new(&rays);
+        assert!(target.is_active(0));
+        assert!(!target.is_active(1));
+        assert!(target.ray(1).is_none());
+        let masked = target.masked(&[false; RAY_PACKET_WIDTH]);
+        assert!(!masked.any_active());
+        let bounds =
+            BoundingBox::from_corners(Vec3::new(-1.0, -1.0, 1.0), Vec3::new(1.0, 1.0, 2.0));
+        let hits = target.intersect_bounds(&bounds);
+        assert!(hits[0] && !hits[1..].iter().any(|&hit| hit));
+    }
+}
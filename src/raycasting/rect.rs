@@ -0,0 +1,242 @@
+use crate::materials::Material;
+use crate::math::{Affine3, Vec2, Vec3};
+use crate::util::float_error::ray_plane_point_error;
+use crate::validation::SceneValidator;
+
+use super::{
+    BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray, SampleSurface,
+    SurfaceSample, Transform,
+};
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+use std::sync::Arc;
+
+/// A flat quadrilateral, spanned by two edges from a corner
+///
+/// The edges don't have to be perpendicular, so any parallelogram can be represented. The
+/// normal is `edge_u × edge_v`, and surface coordinates run from (0, 0) at `corner` to
+/// (1, 1) at the opposite corner.
+#[derive(Clone, Debug)]
+pub struct Rect {
+    corner: Vec3,
+    edge_u: Vec3,
+    edge_v: Vec3,
+    normal: Vec3,
+    material: Arc<dyn Material>,
+}
+
+impl Rect {
+    pub fn new(corner: Vec3, edge_u: Vec3, edge_v: Vec3, material: Arc<dyn Material>) -> Rect {
+        Rect {
+            corner,
+            edge_u,
+            edge_v,
+            normal: edge_u.cross(&edge_v).normalize(),
+            material,
+        }
+    }
+
+    fn corners(&self) -> [Vec3; 4] {
+        [
+            self.corner,
+            self.corner + self.edge_u,
+            self.corner + self.edge_u + self.edge_v,
+            self.corner + self.edge_v,
+        ]
+    }
+}
+
+impl Transform for Rect {
+    fn transform(&self, transformation: &Affine3) -> Self {
+        Rect {
+            corner: transformation.transform_point(&self.corner),
+            edge_u: transformation.transform_vector(&self.edge_u),
+            edge_v: transformation.transform_vector(&self.edge_v),
+            // Unlike the edge cross product, this stays on the same side of the surface under
+            // reflections.
+            normal: transformation.transform_normal(&self.normal).normalize(),
+            material: Arc::clone(&self.material),
+        }
+    }
+}
+
+impl Intersect for Rect {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        let ray_direction_dot_normal = ray.direction.dot(&self.normal);
+        if ray_direction_dot_normal == 0.0 {
+            return None;
+        }
+        let distance = (self.corner - ray.origin).dot(&self.normal) / ray_direction_dot_normal;
+        if distance <= 0.0 {
+            return None;
+        }
+        let location = ray.point_at(distance);
+        // Solve offset = u * edge_u + v * edge_v within the plane of the rect
+        let offset = location - self.corner;
+        let area_normal = self.edge_u.cross(&self.edge_v);
+        let one_over_area_squared = 1.0 / area_normal.norm_squared();
+        let u = offset.cross(&self.edge_v).dot(&area_normal) * one_over_area_squared;
+        let v = self.edge_u.cross(&offset).dot(&area_normal) * one_over_area_squared;
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        let tangent = self.edge_u.normalize();
+        Some(IntersectionInfo {
+            distance,
+            location,
+            location_error: ray_plane_point_error(
+                &ray.origin,
+                &(ray.direction * distance),
+                &self.corner,
+            ),
+            normal: self.normal,
+            tangent,
+            cotangent: self.normal.cross(&tangent),
+            retro: -ray.direction,
+            uv: Vec2::new(u, v),
+            material: Arc::clone(&self.material),
+            object_id: 0,
+            footprint: None,
+            time: 0.0,
+        })
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_vertex(&self.corner);
+        validator.check_material(&self.material);
+    }
+}
+
+impl HasBoundingBox for Rect {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::from_points(&self.corners())
+    }
+}
+
+impl Primitive for Rect {
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(self.transform(transformation))
+    }
+}
+
+impl SampleSurface for Rect {
+    fn surface_area(&self) -> f64 {
+        self.edge_u.cross(&self.edge_v).norm()
+    }
+
+    fn sample_surface(&self, rng: &mut dyn RngCore) -> SurfaceSample {
+        let u: f64 = rng.sample(Open01);
+        let v: f64 = rng.sample(Open01);
+        SurfaceSample {
+            location: self.corner + self.edge_u * u + self.edge_v * v,
+            normal: self.normal,
+            pdf: 1.0 / self.surface_area(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+
+    use quickcheck_macros::quickcheck;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn test_rect() -> Rect {
+        Rect::new(
+            Vec3::new(1.0, 1.0, 2.0),
+            Vec3::new(3.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Arc::new(LambertianMaterial::new_dummy()),
+        )
+    }
+
+    #[test]
+    fn normal_follows_edge_order() {
+        assert!(test_rect().normal == Vec3::unit_z());
+    }
+
+    #[test]
+    fn ray_through_rect_intersects_with_expected_uv() {
+        let ray = Ray::new(Vec3::new(2.5, 2.5, 0.0), Vec3::unit_z());
+        let info = test_rect().intersect(&ray).unwrap();
+        assert!((info.location - Vec3::new(2.5, 2.5, 2.0)).norm() < 0.000000001);
+        assert!((info.uv.x() - 0.5).abs() < 0.000000001);
+        assert!((info.uv.y() - 0.75).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn ray_outside_rect_does_not_intersect() {
+        let target = test_rect();
+        for &(x, y) in &[(0.5, 2.0), (4.5, 2.0), (2.0, 0.5), (2.0, 3.5)] {
+            let ray = Ray::new(Vec3::new(x, y, 0.0), Vec3::unit_z());
+            assert!(target.intersect(&ray).is_none());
+        }
+    }
+
+    #[test]
+    fn parallelogram_is_intersected_inside_skewed_edges() {
+        let target = Rect::new(
+            Vec3::zeros(),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let hit = Ray::new(Vec3::new(2.5, 0.75, -1.0), Vec3::unit_z());
+        let miss = Ray::new(Vec3::new(0.25, 0.75, -1.0), Vec3::unit_z());
+        assert!(target.intersect(&hit).is_some());
+        assert!(target.intersect(&miss).is_none());
+    }
+
+    #[test]
+    fn frame_is_orthonormal() {
+        let ray = Ray::new(Vec3::new(2.5, 2.5, 0.0), Vec3::unit_z());
+        let info = test_rect().intersect(&ray).unwrap();
+        assert!(info.tangent.dot(&info.normal).abs() < 0.000000001);
+        assert!(info.cotangent.dot(&info.normal).abs() < 0.000000001);
+        assert!((info.tangent.cross(&info.cotangent) - info.normal).norm() < 0.000000001);
+    }
+
+    #[quickcheck]
+    fn samples_lie_inside_rect(seed: u64) -> bool {
+        let target = test_rect();
+        let sample = target.sample_surface(&mut StdRng::seed_from_u64(seed));
+        let p = sample.location - target.corner;
+        p.z().abs() < 0.000000001
+            && (0.0..=3.0).contains(&p.x())
+            && (0.0..=2.0).contains(&p.y())
+            && sample.normal == target.normal
+    }
+
+    #[test]
+    fn sample_pdf_is_inverse_of_area() {
+        let target = test_rect();
+        assert!((target.surface_area() - 6.0).abs() < 0.000000001);
+        let sample = target.sample_surface(&mut StdRng::seed_from_u64(0));
+        assert!((sample.pdf - 1.0 / 6.0).abs() < 0.000000001);
+    }
+
+    #[test]
+    fn bounding_box_contains_corners_only() {
+        let bounds = test_rect().bounding_box();
+        assert!(bounds.contains_point(Vec3::new(1.0, 1.0, 2.0)));
+        assert!(bounds.contains_point(Vec3::new(4.0, 3.0, 2.0)));
+        assert!(!bounds.contains_point(Vec3::new(4.5, 2.0, 2.0)));
+        assert!(!bounds.contains_point(Vec3::new(2.0, 2.0, 2.5)));
+    }
+
+    #[test]
+    fn reflection_keeps_normal_on_same_side() {
+        let target = test_rect().transform(&Affine3::scale(1.0, 1.0, -1.0));
+        assert!((target.normal + Vec3::unit_z()).norm() < 0.000000001);
+        let ray = Ray::new(Vec3::new(2.5, 2.5, 0.0), -Vec3::unit_z());
+        let info = target.intersect(&ray).unwrap();
+        assert!((info.location - Vec3::new(2.5, 2.5, -2.0)).norm() < 0.000000001);
+        assert!((info.uv.y() - 0.75).abs() < 0.000000001);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::materials::Material;
+use crate::math::{Affine3, OrthonormalBasis, Vec2, Vec3};
+
+use super::{
+    Aggregate, BoundingBox, HasBoundingBox, Instance, Intersect, IntersectionInfo, Primitive, Ray,
+};
+
+use std::sync::Arc;
+
+/// A shape given by the signed distance from any point to its surface
+///
+/// The distance is negative inside the shape and positive outside. It doesn't have to be
+/// exact, but it mustn't overestimate the distance by more than the
+/// [Lipschitz bound](SdfPrimitive::with_lipschitz_bound) allows, or rays can skip through
+/// the surface.
+pub trait SignedDistanceField: Send + Sync {
+    fn distance(&self, point: &Vec3) -> f64;
+}
+
+impl<F: Fn(&Vec3) -> f64 + Send + Sync> SignedDistanceField for F {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self(point)
+    }
+}
+
+/// Signed distances sampled on a regular grid, and interpolated trilinearly between
+///
+/// The samples are at the corners of `resolution` cells along each axis spanning
+/// `bounds`, stored with x varying fastest and z slowest. Points outside the grid take
+/// the value at the nearest point on its surface, plus the distance to it.
+#[derive(Clone, Debug)]
+pub struct DistanceGrid {
+    bounds: BoundingBox,
+    resolution: [usize; 3],
+    distances: Vec<f64>,
+}
+
+impl DistanceGrid {
+    /// Panics if `distances` doesn't hold one value for each corner of every cell.
+    pub fn new(bounds: BoundingBox, resolution: [usize; 3], distances: Vec<f64>) -> DistanceGrid {
+        assert!(resolution.iter().all(|&cells| cells > 0));
+        assert!(distances.len() == resolution.iter().map(|cells| cells + 1).product::<usize>());
+        DistanceGrid {
+            bounds,
+            resolution,
+            distances,
+        }
+    }
+
+    /// A grid with samples of `field` at each corner
+    pub fn sample<F: SignedDistanceField>(
+        field: &F,
+        bounds: BoundingBox,
+        resolution: [usize; 3],
+    ) -> DistanceGrid {
+        let [nx, ny, nz] = resolution.map(|cells| cells + 1);
+        let mut distances = Vec::with_capacity(nx * ny * nz);
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx {
+                    distances.push(field.distance(&Self::corner(&bounds, resolution, [i, j, k])));
+                }
+            }
+        }
+        DistanceGrid::new(bounds, resolution, distances)
+    }
+
+    fn corner(bounds: &BoundingBox, resolution: [usize; 3], index: [usize; 3]) -> Vec3 {
+        let coordinate = |axis: usize| {
+            let (min, max) = (bounds.bounds[axis].get_min(), bounds.bounds[axis].get_max());
+            min + (max - min) * index[axis] as f64 / resolution[axis] as f64
+        };
+        Vec3::new(coordinate(0), coordinate(1), coordinate(2))
+    }
+
+    fn value_at(&self, index: [usize; 3]) -> f64 {
+        let [nx, ny, _] = self.resolution.map(|cells| cells + 1);
+        self.distances[index[0] + nx * (index[1] + ny * index[2])]
+    }
+}
+
+impl SignedDistanceField for DistanceGrid {
+    fn distance(&self, point: &Vec3) -> f64 {
+        let mut cell = [0; 3];
+        let mut fraction = [0.0; 3];
+        let mut outside = Vec3::zeros();
+        for axis in 0..3 {
+            let (min, max) = (
+                self.bounds.bounds[axis].get_min(),
+                self.bounds.bounds[axis].get_max(),
+            );
+            let clamped = point[axis].clamp(min, max);
+            outside[axis] = point[axis] - clamped;
+            let position = (clamped - min) / (max - min) * self.resolution[axis] as f64;
+            // Points on the far face are in the last cell, at its far side
+            let index = (position.floor() as usize).min(self.resolution[axis] - 1);
+            cell[axis] = index;
+            fraction[axis] = position - index as f64;
+        }
+        let mut interpolated = 0.0;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let weight = (0..3)
+                .map(|axis| {
+                    if offset[axis] == 1 {
+                        fraction[axis]
+                    } else {
+                        1.0 - fraction[axis]
+                    }
+                })
+                .product::<f64>();
+            if weight > 0.0 {
+                interpolated += weight
+                    * self.value_at([
+                        cell[0] + offset[0],
+                        cell[1] + offset[1],
+                        cell[2] + offset[2],
+                    ]);
+            }
+        }
+        interpolated + outside.norm()
+    }
+}
+
+/// The most steps taken along a ray before giving up on finding the surface
+const MAX_STEPS: usize = 512;
+
+/// A surface defined by a [SignedDistanceField](SignedDistanceField), for procedural shapes
+/// that would be impractical to mesh
+///
+/// Rays are intersected by sphere tracing: nothing is closer to a point than the distance
+/// to the surface, so the ray can safely step that far, over and over, until it's close
+/// enough to call a hit. Normals are the gradient of the field, found numerically. There
+/// are no natural surface coordinates, so `uv` is always zero.
+///
+/// The surface must lie inside `bounds`, and rays are only traced inside them.
+pub struct SdfPrimitive<F: SignedDistanceField> {
+    field: Arc<F>,
+    bounds: BoundingBox,
+    material: Arc<dyn Material>,
+    lipschitz_bound: f64,
+    tolerance: f64,
+}
+
+impl<F: SignedDistanceField> SdfPrimitive<F> {
+    /// The surface where `field` is zero, inside `bounds`
+    ///
+    /// Rays stop when they reach a millionth of the size of `bounds` from the surface.
+    pub fn new(field: F, bounds: BoundingBox, material: Arc<dyn Material>) -> SdfPrimitive<F> {
+        SdfPrimitive {
+            field: Arc::new(field),
+            bounds,
+            material,
+            lipschitz_bound: 1.0,
+            tolerance: 0.000_001 * bounds.diagonal().norm(),
+        }
+    }
+
+    /// The same surface, for a field that can change by up to `lipschitz_bound` times the
+    /// distance between two points
+    ///
+    /// An exact distance has a bound of one. Fields that overestimate the distance, such as
+    /// distorted shapes, need a larger bound, which makes each step shorter.
+    pub fn with_lipschitz_bound(self, lipschitz_bound: f64) -> SdfPrimitive<F> {
+        SdfPrimitive {
+            lipschitz_bound,
+            ..self
+        }
+    }
+
+    /// The gradient of the field at `point`, by central differences
+    fn gradient(&self, point: &Vec3) -> Vec3 {
+        let h = self.tolerance;
+        let difference = |axis: Vec3| {
+            self.field.distance(&(point + axis * h)) - self.field.distance(&(*point - axis * h))
+        };
+        Vec3::new(
+            difference(Vec3::unit_x()),
+            difference(Vec3::unit_y()),
+            difference(Vec3::unit_z()),
+        )
+    }
+}
+
+impl<F: SignedDistanceField> Clone for SdfPrimitive<F> {
+    fn clone(&self) -> Self {
+        SdfPrimitive {
+            field: Arc::clone(&self.field),
+            bounds: self.bounds,
+            material: Arc::clone(&self.material),
+            lipschitz_bound: self.lipschitz_bound,
+            tolerance: self.tolerance,
+        }
+    }
+}
+
+impl<F: SignedDistanceField> Intersect for SdfPrimitive<F> {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        let range = self.bounds.distances_inside(ray);
+        if range.is_empty() {
+            return None;
+        }
+        let mut distance = range.get_min();
+        // Rays leaving the surface start right next to it, so a hit only counts once the
+        // ray has got clear of wherever it started
+        let mut has_left_surface = false;
+        for _ in 0..MAX_STEPS {
+            if distance > range.get_max() {
+                return None;
+            }
+            let field_distance = self.field.distance(&ray.point_at(distance)).abs();
+            if field_distance < self.tolerance {
+                if has_left_surface {
+                    break;
+                }
+            } else {
+                has_left_surface = true;
+            }
+            distance += (field_distance / self.lipschitz_bound).max(self.tolerance);
+        }
+        if !has_left_surface || distance > range.get_max() {
+            return None;
+        }
+        let location = ray.point_at(distance);
+        let gradient = self.gradient(&location);
+        if gradient.norm_squared() == 0.0 {
+            return None;
+        }
+        let normal = gradient.normalize();
+        let OrthonormalBasis {
+            tangent, cotangent, ..
+        } = OrthonormalBasis::from_normal(&normal);
+        // The surface is only known to be within the tolerance of the hit
+        let error = 2.0 * self.tolerance * self.lipschitz_bound;
+        Some(IntersectionInfo {
+            distance,
+            location,
+            location_error: Vec3::new(error, error, error),
+            normal,
+            tangent,
+            cotangent,
+            retro: -ray.direction,
+            uv: Vec2::new(0.0, 0.0),
+            material: Arc::clone(&self.material),
+            object_id: 0,
+            footprint: None,
+            time: 0.0,
+        })
+    }
+}
+
+impl<F: SignedDistanceField> HasBoundingBox for SdfPrimitive<F> {
+    fn bounding_box(&self) -> BoundingBox {
+        self.bounds
+    }
+}
+
+impl<F: SignedDistanceField + 'static> Primitive for SdfPrimitive<F> {
+    /// The field can't be transformed directly, so the result is an
+    /// [Instance](Instance) of it
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(Instance::new(Arc::new(self.clone()), *transformation))
+    }
+}
+
+impl<F: SignedDistanceField> Aggregate for SdfPrimitive<F> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+
+    fn unit_sphere(point: &Vec3) -> f64 {
+        point.norm() - 1.0
+    }
+
+    fn sphere_bounds() -> BoundingBox {
+        BoundingBox::from_corners(Vec3::new(-1.5, -1.5, -1.5), Vec3::new(1.5, 1.5, 1.5))
+    }
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(LambertianMaterial::new_dummy())
+    }
+
+    #[test]
+    fn sphere_traced_sphere_matches_analytic_sphere() {
+        let target = SdfPrimitive::new(unit_sphere, sphere_bounds(), material());
+        let ray = Ray::new(Vec3::new(0.3, 0.2, -5.0), Vec3::unit_z());
+        let info = target.intersect(&ray).unwrap();
+        let expected = Vec3::new(0.3, 0.2, -(1.0f64 - 0.09 - 0.04).sqrt());
+        assert!((info.location - expected).norm() < 0.000_01);
+        assert!((info.normal - expected).norm() < 0.000_01);
+        assert!(target
+            .intersect(&Ray::new(Vec3::new(1.1, 0.0, -5.0), Vec3::unit_z()))
+            .is_none());
+    }
+
+    #[test]
+    fn closures_can_be_used_as_fields() {
+        let radius = 0.5;
+        let target = SdfPrimitive::new(
+            move |point: &Vec3| point.norm() - radius,
+            sphere_bounds(),
+            material(),
+        );
+        let info = target
+            .intersect(&Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::unit_z()))
+            .unwrap();
+        assert!((info.distance - 4.5).abs() < 0.000_01);
+    }
+
+    #[test]
+    fn spawned_rays_leave_the_surface() {
+        let target = SdfPrimitive::new(unit_sphere, sphere_bounds(), material());
+        let info = target
+            .intersect(&Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::unit_z()))
+            .unwrap();
+        // Out the way it came, and through the sphere to the far side
+        assert!(target
+            .intersect(&info.spawn_ray(&-Vec3::unit_z()))
+            .is_none());
+        let through = target.intersect(&info.spawn_ray(&Vec3::unit_z())).unwrap();
+        assert!((through.location.z() - 1.0).abs() < 0.000_01);
+    }
+
+    #[test]
+    fn grid_interpolates_sampled_field() {
+        let grid = DistanceGrid::sample(&unit_sphere, sphere_bounds(), [30, 30, 30]);
+        for point in &[
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.7, -0.3, 0.2),
+            Vec3::new(1.5, 1.5, 1.5),
+        ] {
+            assert!((grid.distance(point) - unit_sphere(point)).abs() < 0.01);
+        }
+        // Outside the grid, the distance to it is added on
+        let outside = Vec3::new(2.5, 0.0, 0.0);
+        assert!((grid.distance(&outside) - 1.5).abs() < 0.000_001);
+        let target = SdfPrimitive::new(grid, sphere_bounds(), material());
+        let info = target
+            .intersect(&Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::unit_z()))
+            .unwrap();
+        assert!((info.location.z() + 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn transformed_field_is_moved() {
+        let target = SdfPrimitive::new(unit_sphere, sphere_bounds(), material())
+            .transform_primitive(&Affine3::translation(&Vec3::new(10.0, 0.0, 0.0)));
+        let info = target
+            .intersect(&Ray::new(Vec3::new(10.0, 0.0, -5.0), Vec3::unit_z()))
+            .unwrap();
+        assert!((info.location - Vec3::new(10.0, 0.0, -1.0)).norm() < 0.000_01);
+    }
+}
@@ -1,8 +1,17 @@
 use crate::materials::Material;
-use crate::math::Vec3;
+use crate::math::{Affine3, OrthonormalBasis, Vec2, Vec3};
+use crate::util::float_error::gamma;
+use crate::validation::{SceneIssue, SceneValidator};
 
-use super::{BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray};
+use super::{
+    BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray, SampleSurface,
+    SurfaceSample, Transform,
+};
 
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
+
+use std::f64::consts::PI;
 use std::sync::Arc;
 
 #[derive(Clone, Debug)]
@@ -22,41 +31,42 @@ impl Sphere {
     }
 }
 
-/*impl Transform for Sphere {
-    fn transform(&self, transformation: &Affine3<f64>) -> Self {
+impl Transform for Sphere {
+    fn transform(&self, transformation: &Affine3) -> Self {
         Sphere {
             centre: transformation.transform_point(&self.centre),
             // This is not the most efficient way of calculating the radius,
             //but will work as long as the resulting shape is still a sphere.
             radius: transformation
-                .transform_vector(&Vector3::new(self.radius, 0.0, 0.0))
+                .transform_vector(&Vec3::new(self.radius, 0.0, 0.0))
                 .norm(),
             material: Arc::clone(&self.material),
         }
     }
-}*/
+}
+
+/// A frame with the tangent along the direction of increasing u
+///
+/// At the poles every direction is a line of longitude, so any tangent will do.
+fn uv_frame(normal: &Vec3) -> OrthonormalBasis {
+    let along_u = Vec3::new(-normal.z(), 0.0, normal.x());
+    let length = along_u.norm();
+    if length < 0.000_001 {
+        OrthonormalBasis::from_normal(normal)
+    } else {
+        let tangent = along_u / length;
+        OrthonormalBasis::new(tangent, normal.cross(&tangent), *normal)
+    }
+}
 
 impl Intersect for Sphere {
     fn intersect<'a>(&'_ self, ray: &Ray) -> Option<IntersectionInfo> {
-        let r_o = ray.origin;
-        let centre_coords = self.centre;
-        let a = ray
-            .direction
-            .component_mul(&ray.direction)
-            .coords
-            .iter()
-            .fold(0.0, |a, b| a + *b);
-        let b = ((r_o.component_mul(&ray.direction) - centre_coords.component_mul(&ray.direction))
-            * 2.0)
-            .coords
-            .iter()
-            .fold(0.0, |a, b| a + *b);
-        let c = (r_o.component_mul(&r_o) + centre_coords.component_mul(&centre_coords)
-            - centre_coords.component_mul(&r_o) * 2.0)
-            .coords
-            .iter()
-            .fold(0.0, |a, b| a + *b)
-            - self.radius * self.radius;
+        // Working relative to the centre keeps the terms small, so that they don't cancel
+        // out when the sphere is far from the origin
+        let offset = ray.origin - self.centre;
+        let a = ray.direction.norm_squared();
+        let b = 2.0 * offset.dot(&ray.direction);
+        let c = offset.norm_squared() - self.radius * self.radius;
         let delta_squared = b * b - 4.0 * a * c;
         if delta_squared < 0.0 {
             None
@@ -73,23 +83,50 @@ impl Intersect for Sphere {
             if distance <= 0.0 {
                 None
             } else {
-                let location = ray.point_at(distance);
-                let normal = (location - self.centre).normalize();
-                let tangent = normal.cross(&Vec3::unit_z()).normalize();
-                let cotangent = normal.cross(&tangent);
+                // Moving the point back onto the sphere leaves much less error than the
+                // distance had
+                let from_centre = ray.point_at(distance) - self.centre;
+                let from_centre = from_centre * (self.radius / from_centre.norm());
+                let location = self.centre + from_centre;
+                let location_error = from_centre.abs() * gamma(5) + location.abs() * gamma(1);
+                let normal = from_centre.normalize();
+                let OrthonormalBasis {
+                    tangent, cotangent, ..
+                } = uv_frame(&normal);
                 let retro = -ray.direction;
+                // Latitude-longitude coordinates, with v = 1 at the +Y pole
+                let uv = Vec2::new(
+                    (normal.z().atan2(normal.x()) + PI) / (2.0 * PI),
+                    1.0 - normal.y().clamp(-1.0, 1.0).acos() / PI,
+                );
                 Some(IntersectionInfo {
                     distance,
                     location,
+                    location_error,
                     normal,
                     tangent,
                     cotangent,
                     retro,
+                    uv,
                     material: Arc::clone(&self.material),
+                    object_id: 0,
+                    footprint: None,
+                    time: 0.0,
                 })
             }
         }
     }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_vertex(&self.centre);
+        if self.radius <= 0.0 || self.radius.is_nan() {
+            validator.report(SceneIssue::ZeroRadiusSphere {
+                centre: self.centre,
+                radius: self.radius,
+            });
+        }
+        validator.check_material(&self.material);
+    }
 }
 
 impl HasBoundingBox for Sphere {
@@ -99,7 +136,29 @@ impl HasBoundingBox for Sphere {
     }
 }
 
-impl Primitive for Sphere {}
+impl Primitive for Sphere {
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(self.transform(transformation))
+    }
+}
+
+impl SampleSurface for Sphere {
+    fn surface_area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self, rng: &mut dyn RngCore) -> SurfaceSample {
+        let z = 1.0 - 2.0 * rng.sample::<f64, _>(Open01);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.sample::<f64, _>(Open01);
+        let normal = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+        SurfaceSample {
+            location: self.centre + normal * self.radius,
+            normal,
+            pdf: 1.0 / self.surface_area(),
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -109,6 +168,9 @@ mod tests {
     use super::*;
     use crate::materials::LambertianMaterial;
 
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
     #[test]
     fn ray_intersects_sphere() {
         let r = Ray::new(Vec3::new(1.0, 2.0, 3.0), Vec3::new(0.0, 0.0, 1.0));
@@ -194,7 +256,7 @@ mod tests {
         bounding_box.contains_point(sphere_centre + radius_vector)
     }
 
-    /*#[quickcheck]
+    #[quickcheck]
     fn translation_moves_centre(
         sphere_centre: Vec3,
         radius: f64,
@@ -209,8 +271,7 @@ mod tests {
             Arc::new(LambertianMaterial::new_dummy()),
         );
         let expected_centre = sphere.centre + translation_vector;
-        let mut transformation = Affine3::identity();
-        transformation *= Translation3::from(translation_vector);
+        let transformation = Affine3::translation(&translation_vector);
         let sphere = sphere.transform(&transformation);
         TestResult::from_bool(expected_centre == sphere.centre)
     }
@@ -230,8 +291,7 @@ mod tests {
             Arc::new(LambertianMaterial::new_dummy()),
         );
         let expected_radius = sphere.radius;
-        let mut transformation = Affine3::identity();
-        transformation *= Translation3::from(translation_vector);
+        let transformation = Affine3::translation(&translation_vector);
         let sphere = sphere.transform(&transformation);
         TestResult::from_bool(expected_radius == sphere.radius)
     }
@@ -251,11 +311,96 @@ mod tests {
             Arc::new(LambertianMaterial::new_dummy()),
         );
         let expected_centre = sphere.centre;
-        let mut transformation = Affine3::identity();
-        transformation *= Translation3::from(sphere.centre.coords)
-            * Rotation3::new(rotation_vector)
-            * Translation3::from(-sphere.centre.coords);
+        if rotation_vector.norm() == 0.0 {
+            return TestResult::discard();
+        }
+        let transformation = Affine3::translation(&sphere.centre)
+            * Affine3::rotation(&rotation_vector, rotation_vector.norm())
+            * Affine3::translation(&-sphere.centre);
         let sphere = sphere.transform(&transformation);
         TestResult::from_bool((expected_centre - sphere.centre).norm() < 0.000000001)
-    }*/
+    }
+
+    #[quickcheck]
+    fn surface_samples_lie_on_sphere(sphere_centre: Vec3, radius: f64) -> TestResult {
+        if radius <= 0.0 {
+            return TestResult::discard();
+        }
+        let sphere = Sphere::new(
+            sphere_centre,
+            radius,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let sample = sphere.sample_surface(&mut StdRng::seed_from_u64(0));
+        let tolerance = 0.000001 * sphere_centre.norm().max(radius).max(1.0);
+        TestResult::from_bool(
+            ((sample.location - sphere_centre).norm() - radius).abs() < tolerance
+                && (sample.location - (sphere_centre + sample.normal * radius)).norm() < tolerance,
+        )
+    }
+
+    #[test]
+    fn surface_sample_pdf_integrates_to_one() {
+        let sphere = Sphere::new(
+            Vec3::new(1.0, 2.0, 3.0),
+            2.5,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let sample = sphere.sample_surface(&mut StdRng::seed_from_u64(0));
+        assert!((sample.pdf * sphere.surface_area() - 1.0).abs() < 0.000000001);
+    }
+
+    #[quickcheck]
+    fn tangent_points_along_increasing_u(direction: Vec3) -> TestResult {
+        if direction.norm() == 0.0 {
+            return TestResult::discard();
+        }
+        let sphere = Sphere::new(
+            Vec3::zeros(),
+            1.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let origin = direction.normalize() * 5.0;
+        let info = sphere.intersect(&Ray::new(origin, -origin)).unwrap();
+        let frame = OrthonormalBasis::new(info.tangent, info.cotangent, info.normal);
+        let orthonormal = (frame.tangent.cross(&frame.cotangent) - frame.normal).norm() < 0.000001;
+        let step = info.location + info.tangent * 0.0001;
+        let stepped = sphere.intersect(&Ray::new(step * 5.0, -step)).unwrap();
+        let du = stepped.uv.x() - info.uv.x();
+        // Skip the seam, where u wraps around from one back to zero
+        TestResult::from_bool(
+            orthonormal && (!(-0.5..=0.0).contains(&du) || info.normal.y().abs() > 0.999),
+        )
+    }
+
+    #[test]
+    fn tangent_frame_is_finite_at_poles() {
+        let sphere = Sphere::new(
+            Vec3::zeros(),
+            1.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        for &pole in [Vec3::unit_y(), -Vec3::unit_y()].iter() {
+            let info = sphere.intersect(&Ray::new(pole * 5.0, -pole)).unwrap();
+            assert!((info.tangent.cross(&info.cotangent) - info.normal).norm() < 0.000001);
+        }
+    }
+
+    #[test]
+    fn uv_is_latitude_longitude() {
+        let sphere = Sphere::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            1.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        );
+        let top = sphere
+            .intersect(&Ray::new(Vec3::new(0.0, 5.0, 0.0), -Vec3::unit_y()))
+            .unwrap();
+        assert!((top.uv.y() - 1.0).abs() < 0.000001);
+        let side = sphere
+            .intersect(&Ray::new(Vec3::new(5.0, 0.0, 0.0), -Vec3::unit_x()))
+            .unwrap();
+        assert!((side.uv.x() - 0.5).abs() < 0.000001);
+        assert!((side.uv.y() - 0.5).abs() < 0.000001);
+    }
 }
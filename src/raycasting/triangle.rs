@@ -1,7 +1,17 @@
 use crate::materials::Material;
-use crate::math::{Vec2, Vec3};
+use crate::math::{Affine3, Vec2, Vec3};
+use crate::statistics;
+use crate::textures::OpacityMask;
+use crate::util::float_error::gamma;
+use crate::validation::SceneValidator;
 
-use super::{BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray};
+use super::{
+    BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Lanes, Primitive, Ray, RayPacket,
+    SampleSurface, SurfaceSample, Transform,
+};
+
+use rand::distributions::Open01;
+use rand::{Rng, RngCore};
 
 use std::sync::Arc;
 
@@ -9,13 +19,15 @@ use std::sync::Arc;
 pub struct Triangle {
     pub vertices: [Vec3; 3],
     pub normals: [Vec3; 3],
+    /// Surface coordinates at each vertex
+    pub uvs: [Vec2; 3],
     pub material: Arc<dyn Material>,
+    /// Where the triangle is cut away, if anywhere
+    pub opacity: Option<Arc<OpacityMask>>,
 }
 
-/*impl Transform for Triangle {
-    fn transform(&self, transformation: &Affine3<f64>) -> Self {
-        let normal_transformation =
-            Affine3::from_matrix_unchecked(transformation.inverse().matrix().transpose());
+impl Transform for Triangle {
+    fn transform(&self, transformation: &Affine3) -> Self {
         Triangle {
             vertices: [
                 transformation.transform_point(&self.vertices[0]),
@@ -23,77 +35,179 @@ pub struct Triangle {
                 transformation.transform_point(&self.vertices[2]),
             ],
             normals: [
-                normal_transformation.transform_vector(&self.normals[0]),
-                normal_transformation.transform_vector(&self.normals[1]),
-                normal_transformation.transform_vector(&self.normals[2]),
+                transformation.transform_normal(&self.normals[0]),
+                transformation.transform_normal(&self.normals[1]),
+                transformation.transform_normal(&self.normals[2]),
             ],
+            uvs: self.uvs,
             material: Arc::clone(&self.material),
+            opacity: self.opacity.clone(),
         }
     }
-}*/
+}
 
 impl Intersect for Triangle {
     fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
-        let translation = -ray.origin;
-        let indices = indices_with_index_of_largest_element_last(&ray.direction);
-        let permuted_ray_direction = permute_vector_elements(&ray.direction, &indices);
-        let shear_slopes = calculate_shear_to_z_axis(&permuted_ray_direction);
-        let transformed_vertices: Vec<Vec3> = self
-            .vertices
+        intersect_triangle(
+            &self.vertices,
+            &self.normals,
+            &self.uvs,
+            &self.material,
+            self.opacity.as_deref(),
+            ray,
+        )
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes<Option<IntersectionInfo>> {
+        packet
+            .masked(&packet_candidates(&self.vertices, packet))
+            .map_active(|ray| self.intersect(ray))
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        validator.check_triangle(&self.vertices);
+        self.normals
             .iter()
-            .map(|elem| {
-                apply_shear_to_z_axis(
-                    &permute_vector_elements(&(elem + translation), &indices),
-                    &shear_slopes,
-                )
-            })
-            .collect();
-        let edge_functions = signed_edge_functions(&transformed_vertices);
-        if edge_functions.coords.iter().all(|e| e.is_sign_positive())
-            || edge_functions.coords.iter().all(|e| e.is_sign_negative())
-        {
-            let barycentric_coordinates =
-                barycentric_coordinates_from_signed_edge_functions(edge_functions.abs());
-            let transformed_z = barycentric_coordinates
-                .coords
-                .iter()
-                .zip(transformed_vertices.iter())
-                .map(|(&coord, vertex)| vertex.z() * coord)
-                .fold(0.0, |acc, z| acc + z);
-            if transformed_z.is_sign_positive() != permuted_ray_direction.z().is_sign_positive() {
-                return None;
-            }
-            let location = barycentric_coordinates
-                .coords
-                .iter()
-                .zip(self.vertices.iter())
-                .map(|(&barycentric_coord, vertex)| vertex * barycentric_coord)
-                .fold(Vec3::zeros(), |a, e| a + e);
-            let distance = (ray.origin - location).norm();
-            let normal: Vec3 = barycentric_coordinates
-                .coords
-                .iter()
-                .zip(self.normals.iter())
-                .fold(Vec3::zeros(), |acc, (&coord, vertex)| acc + vertex * coord)
-                .normalize();
-            let cotangent = (self.vertices[0] - self.vertices[1])
-                .cross(&normal)
-                .normalize();
-            let tangent = cotangent.cross(&normal).normalize();
-            let retro = (ray.origin - location).normalize();
-            let material = Arc::clone(&self.material);
-            Some(IntersectionInfo {
-                distance,
-                location,
-                normal,
-                tangent,
-                cotangent,
-                retro,
-                material,
-            })
-        } else {
-            None
+            .for_each(|normal| validator.check_normal(normal));
+        validator.check_material(&self.material);
+    }
+}
+
+/// Which active rays of `packet` might hit the triangle with corners `vertices`
+///
+/// This is a Möller-Trumbore test of every ray at once, which is much cheaper than
+/// [intersect_triangle()] for rays that miss. It accepts rays that pass slightly outside
+/// the triangle, so that it never rejects a ray the exact test would accept, and so the
+/// exact test still decides which rays hit.
+pub(super) fn packet_candidates(vertices: &[Vec3; 3], packet: &RayPacket) -> Lanes<bool> {
+    const TOLERANCE: f64 = 0.000_000_1;
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+    let parallel_threshold = 0.000_000_000_001 * edge1.norm() * edge2.norm();
+    let mut candidates = *packet.active();
+    for (lane, candidate) in candidates.iter_mut().enumerate() {
+        let direction = Vec3::new(
+            packet.direction(0)[lane],
+            packet.direction(1)[lane],
+            packet.direction(2)[lane],
+        );
+        let offset = Vec3::new(
+            packet.origin(0)[lane],
+            packet.origin(1)[lane],
+            packet.origin(2)[lane],
+        ) - vertices[0];
+        let p = direction.cross(&edge2);
+        let determinant = edge1.dot(&p);
+        let inverse_determinant = 1.0 / determinant;
+        let u = offset.dot(&p) * inverse_determinant;
+        let q = offset.cross(&edge1);
+        let v = direction.dot(&q) * inverse_determinant;
+        // Rays nearly parallel to the triangle are left to the exact test. This is written
+        // without branches so that the lanes can be computed together.
+        let is_parallel = determinant.abs() < parallel_threshold;
+        *candidate &=
+            is_parallel | ((u >= -TOLERANCE) & (v >= -TOLERANCE) & (u + v <= 1.0 + TOLERANCE));
+    }
+    candidates
+}
+
+/// Intersect a ray with the triangle with the given vertex attributes
+///
+/// This is shared by [Triangle] and [MeshTriangle](super::MeshTriangle), which store their
+/// vertices differently. Rays pass through the parts of the triangle that `opacity` cuts
+/// away, so shadow rays do as well.
+pub(super) fn intersect_triangle(
+    vertices: &[Vec3; 3],
+    normals: &[Vec3; 3],
+    uvs: &[Vec2; 3],
+    material: &Arc<dyn Material>,
+    opacity: Option<&OpacityMask>,
+    ray: &Ray,
+) -> Option<IntersectionInfo> {
+    statistics::count_triangle_test();
+    let translation = -ray.origin;
+    let indices = indices_with_index_of_largest_element_last(&ray.direction);
+    let permuted_ray_direction = permute_vector_elements(&ray.direction, &indices);
+    let shear_slopes = calculate_shear_to_z_axis(&permuted_ray_direction);
+    let transformed_vertices: Vec<Vec3> = vertices
+        .iter()
+        .map(|elem| {
+            apply_shear_to_z_axis(
+                &permute_vector_elements(&(elem + translation), &indices),
+                &shear_slopes,
+            )
+        })
+        .collect();
+    let mut edge_functions = signed_edge_functions(&transformed_vertices);
+    // A ray exactly on an edge gives zero, which must be decided the same way for both
+    // triangles that share the edge. It's recomputed more accurately in case it's really
+    // just a little to one side.
+    if edge_functions.coords.contains(&0.0) {
+        edge_functions = precise_signed_edge_functions(&transformed_vertices);
+    }
+    let is_inside = !(edge_functions.coords.iter().any(|&e| e < 0.0)
+        && edge_functions.coords.iter().any(|&e| e > 0.0));
+    let determinant: f64 = edge_functions.coords.iter().sum();
+    if is_inside && determinant != 0.0 {
+        let barycentric_coordinates =
+            barycentric_coordinates_from_signed_edge_functions(edge_functions.abs());
+        let transformed_z = barycentric_coordinates
+            .coords
+            .iter()
+            .zip(transformed_vertices.iter())
+            .map(|(&coord, vertex)| vertex.z() * coord)
+            .fold(0.0, |acc, z| acc + z);
+        if transformed_z.is_sign_positive() != permuted_ray_direction.z().is_sign_positive() {
+            return None;
+        }
+        let location = barycentric_coordinates
+            .coords
+            .iter()
+            .zip(vertices.iter())
+            .map(|(&barycentric_coord, vertex)| vertex * barycentric_coord)
+            .fold(Vec3::zeros(), |a, e| a + e);
+        let location_error = barycentric_coordinates
+            .coords
+            .iter()
+            .zip(vertices.iter())
+            .map(|(&barycentric_coord, vertex)| (vertex * barycentric_coord).abs())
+            .fold(Vec3::zeros(), |a, e| a + e)
+            * gamma(7);
+        let distance = (ray.origin - location).norm();
+        let normal: Vec3 = barycentric_coordinates
+            .coords
+            .iter()
+            .zip(normals.iter())
+            .fold(Vec3::zeros(), |acc, (&coord, vertex)| acc + vertex * coord)
+            .normalize();
+        let cotangent = (vertices[0] - vertices[1]).cross(&normal).normalize();
+        let tangent = cotangent.cross(&normal).normalize();
+        let retro = (ray.origin - location).normalize();
+        let uv = barycentric_coordinates
+            .coords
+            .iter()
+            .zip(uvs.iter())
+            .fold(Vec2::new(0.0, 0.0), |acc, (&coord, &uv)| acc + uv * coord);
+        if opacity.is_some_and(|mask| !mask.is_opaque(&uv)) {
+            return None;
         }
+        let material = Arc::clone(material);
+        Some(IntersectionInfo {
+            distance,
+            location,
+            location_error,
+            normal,
+            tangent,
+            cotangent,
+            retro,
+            uv,
+            material,
+            object_id: 0,
+            footprint: None,
+            time: 0.0,
+        })
+    } else {
+        None
     }
 }
 
@@ -103,9 +217,45 @@ impl HasBoundingBox for Triangle {
     }
 }
 
-impl Primitive for Triangle {}
+impl Primitive for Triangle {
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(self.transform(transformation))
+    }
+}
+
+impl SampleSurface for Triangle {
+    fn surface_area(&self) -> f64 {
+        (self.vertices[1] - self.vertices[0])
+            .cross(&(self.vertices[2] - self.vertices[0]))
+            .norm()
+            * 0.5
+    }
+
+    fn sample_surface(&self, rng: &mut dyn RngCore) -> SurfaceSample {
+        let sqrt_u = rng.sample::<f64, _>(Open01).sqrt();
+        let v: f64 = rng.sample(Open01);
+        // Square-root warping gives barycentric coordinates that are uniform over the area
+        let barycentric_coordinates = [1.0 - sqrt_u, sqrt_u * (1.0 - v), sqrt_u * v];
+        let location = barycentric_coordinates
+            .iter()
+            .zip(self.vertices.iter())
+            .fold(Vec3::zeros(), |acc, (&coord, vertex)| acc + vertex * coord);
+        let normal = barycentric_coordinates
+            .iter()
+            .zip(self.normals.iter())
+            .fold(Vec3::zeros(), |acc, (&coord, normal)| acc + normal * coord)
+            .normalize();
+        SurfaceSample {
+            location,
+            normal,
+            pdf: 1.0 / self.surface_area(),
+        }
+    }
+}
 
+/// A permutation that puts the coordinate of `v` with the largest magnitude last
 fn indices_with_index_of_largest_element_last(v: &Vec3) -> [usize; 3] {
+    let v = v.abs();
     if v.x() > v.y() {
         if v.z() > v.x() {
             [0, 1, 2]
@@ -157,6 +307,27 @@ fn signed_edge_functions(vertices: &[Vec3]) -> Vec3 {
     Vec3::new(coords[0], coords[1], coords[2])
 }
 
+/// `a * b - c * d`, correct to within a couple of units in the last place
+///
+/// The rounding error of `c * d` is found exactly with a fused multiply-add and added
+/// back, which is Kahan's algorithm.
+fn difference_of_products(a: f64, b: f64, c: f64, d: f64) -> f64 {
+    let cd = c * d;
+    let error = (-c).mul_add(d, cd);
+    a.mul_add(b, -cd) + error
+}
+
+/// As [signed_edge_functions()], but without the cancellation that can round the result
+/// to zero for rays very close to an edge
+fn precise_signed_edge_functions(vertices: &[Vec3]) -> Vec3 {
+    let edge_function = |a: &Vec3, b: &Vec3| difference_of_products(a.x(), b.y(), b.x(), a.y());
+    Vec3::new(
+        edge_function(&vertices[1], &vertices[2]),
+        edge_function(&vertices[2], &vertices[0]),
+        edge_function(&vertices[0], &vertices[1]),
+    )
+}
+
 fn barycentric_coordinates_from_signed_edge_functions(e: Vec3) -> Vec3 {
     e * (1.0 / e.coords.iter().fold(0.0, |a, &b| a + b))
 }
@@ -165,7 +336,7 @@ fn barycentric_coordinates_from_signed_edge_functions(e: Vec3) -> Vec3 {
 mod tests {
     use super::*;
 
-    /*mod triangle_transform {
+    mod triangle_transform {
         use super::*;
         use quickcheck_macros::quickcheck;
 
@@ -186,7 +357,9 @@ mod tests {
             let target = Triangle {
                 vertices: [v0, v1, v2],
                 normals: [n0, n1, n2],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             let target = target.transform(&Affine3::identity());
             target.vertices[0] == v0
@@ -213,9 +386,11 @@ mod tests {
             let target = Triangle {
                 vertices: [v0, v1, v2],
                 normals: [n0, n1, n2],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
-            let transformation = Affine3::identity() * Translation3::from(translation);
+            let transformation = Affine3::translation(&translation);
             let target = target.transform(&transformation);
             target.normals[0] == n0 && target.normals[1] == n1 && target.normals[2] == n2
         }
@@ -236,15 +411,17 @@ mod tests {
             let target = Triangle {
                 vertices: [v0, v1, v2],
                 normals: [n0, n1, n2],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
-            let transformation = Affine3::identity() * Translation3::from(translation);
+            let transformation = Affine3::translation(&translation);
             let target = target.transform(&transformation);
             target.vertices[0] == v0 + translation
                 && target.vertices[1] == v1 + translation
                 && target.vertices[2] == v2 + translation
         }
-    }*/
+    }
 
     mod index_of_largest_element {
         use super::*;
@@ -277,19 +454,19 @@ mod tests {
         #[quickcheck]
         fn last_index_is_greater_than_or_equal_to_x(v: Vec3) -> bool {
             let indices = indices_with_index_of_largest_element_last(&v);
-            v[indices[2]] >= v.x()
+            v[indices[2]].abs() >= v.x().abs()
         }
 
         #[quickcheck]
         fn last_index_is_greater_than_or_equal_to_y(v: Vec3) -> bool {
             let indices = indices_with_index_of_largest_element_last(&v);
-            v[indices[2]] >= v.y()
+            v[indices[2]].abs() >= v.y().abs()
         }
 
         #[quickcheck]
         fn last_index_is_greater_than_or_equal_to_z(v: Vec3) -> bool {
             let indices = indices_with_index_of_largest_element_last(&v);
-            v[indices[2]] >= v.z()
+            v[indices[2]].abs() >= v.z().abs()
         }
     }
 
@@ -300,19 +477,19 @@ mod tests {
         #[quickcheck]
         fn last_index_is_greater_than_or_equal_to_x(v: Vec3) -> bool {
             let p = permute_vector_elements(&v, &indices_with_index_of_largest_element_last(&v));
-            p.z() >= v.x()
+            p.z().abs() >= v.x().abs()
         }
 
         #[quickcheck]
         fn last_index_is_greater_than_or_equal_to_y(v: Vec3) -> bool {
             let p = permute_vector_elements(&v, &indices_with_index_of_largest_element_last(&v));
-            p.z() >= v.y()
+            p.z().abs() >= v.y().abs()
         }
 
         #[quickcheck]
         fn last_index_is_greater_than_or_equal_to_z(v: Vec3) -> bool {
             let p = permute_vector_elements(&v, &indices_with_index_of_largest_element_last(&v));
-            p.z() >= v.z()
+            p.z().abs() >= v.z().abs()
         }
     }
 
@@ -393,6 +570,95 @@ mod tests {
         use quickcheck::{Arbitrary, TestResult};
         use quickcheck_macros::quickcheck;
 
+        fn flat_triangle(vertices: [Vec3; 3]) -> Triangle {
+            let normal = (vertices[1] - vertices[0])
+                .cross(&(vertices[2] - vertices[0]))
+                .normalize();
+            Triangle {
+                vertices,
+                normals: [normal; 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
+                material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
+            }
+        }
+
+        #[test]
+        fn rays_along_shared_edge_hit_one_of_the_triangles() {
+            use rand::rngs::StdRng;
+            use rand::{Rng, SeedableRng};
+
+            let squares = [
+                [
+                    Vec3::new(0.0, 0.0, 0.0),
+                    Vec3::new(1.0, 0.0, 0.0),
+                    Vec3::new(1.0, 1.0, 0.0),
+                    Vec3::new(0.0, 1.0, 0.0),
+                ],
+                [
+                    Vec3::new(0.3, -1.7, 2.1),
+                    Vec3::new(1.9, -0.2, 2.6),
+                    Vec3::new(1.1, 1.3, 0.4),
+                    Vec3::new(-0.4, 0.1, 0.3),
+                ],
+            ];
+            let mut rng = StdRng::seed_from_u64(0);
+            for corners in squares.iter() {
+                // Both triangles share the edge from corners[0] to corners[2]
+                let first = flat_triangle([corners[0], corners[1], corners[2]]);
+                let second = flat_triangle([corners[0], corners[2], corners[3]]);
+                let normal = first.normals[0];
+                for i in 0..2000 {
+                    let point = if i < 16 {
+                        // Points exactly on the edge, seen straight on
+                        corners[0] + (corners[2] - corners[0]) * (i as f64 / 16.0)
+                    } else {
+                        let t: f64 = rng.gen();
+                        corners[0] * (1.0 - t) + corners[2] * t
+                    };
+                    let offset = if i < 16 {
+                        normal
+                    } else {
+                        Vec3::new(rng.gen(), rng.gen(), rng.gen()) + normal
+                    };
+                    for &side in [1.0, -1.0].iter() {
+                        let origin = point + offset * side;
+                        let ray = Ray::new(origin, point - origin);
+                        assert!(
+                            first.intersect(&ray).is_some() || second.intersect(&ray).is_some()
+                        );
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn ray_through_shared_vertex_hits_one_of_the_fan() {
+            let centre = Vec3::new(0.25, 0.5, 1.0);
+            let rim: Vec<Vec3> = (0..6)
+                .map(|i| {
+                    let angle = i as f64 * std::f64::consts::PI / 3.0;
+                    centre + Vec3::new(angle.cos(), angle.sin(), 0.0)
+                })
+                .collect();
+            let fan: Vec<Triangle> = (0..6)
+                .map(|i| flat_triangle([centre, rim[i], rim[(i + 1) % 6]]))
+                .collect();
+            for direction in [
+                Vec3::unit_z(),
+                -Vec3::unit_z(),
+                Vec3::new(0.3, -0.2, 1.0),
+                Vec3::new(-0.7, 0.1, -1.0),
+            ]
+            .iter()
+            {
+                let ray = Ray::new(centre - *direction * 3.0, *direction);
+                assert!(fan
+                    .iter()
+                    .any(|triangle| triangle.intersect(&ray).is_some()));
+            }
+        }
+
         #[test]
         fn intersection_passes_with_ray_along_z_axis_ccw_winding() {
             let target_triangle = Triangle {
@@ -402,7 +668,9 @@ mod tests {
                     Vec3::new(-1.0, -1.0, 1.0),
                 ],
                 normals: [Vec3::zeros(); 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             let target_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
             if let None = target_triangle.intersect(&target_ray) {
@@ -419,7 +687,9 @@ mod tests {
                     Vec3::new(1.0, -1.0, 1.0),
                 ],
                 normals: [Vec3::zeros(); 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             let target_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
             if let None = target_triangle.intersect(&target_ray) {
@@ -436,7 +706,9 @@ mod tests {
                     Vec3::new(-1.0, -1.0, -1.0),
                 ],
                 normals: [Vec3::zeros(); 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             let target_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
             if let None = target_triangle.intersect(&target_ray) {
@@ -453,7 +725,9 @@ mod tests {
                     Vec3::new(1.0, -1.0, -1.0),
                 ],
                 normals: [Vec3::zeros(); 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             let target_ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
             if let None = target_triangle.intersect(&target_ray) {
@@ -470,7 +744,9 @@ mod tests {
                     Vec3::new(4.0, 4.0, 6.0),
                 ],
                 normals: [Vec3::zeros(); 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             let target_ray = Ray::new(Vec3::new(5.0, 5.0, 5.0), Vec3::new(0.0, 0.0, 1.0));
             if let None = target_triangle.intersect(&target_ray) {
@@ -487,7 +763,9 @@ mod tests {
                     Vec3::new(5.0, 4.5, 6.0),
                 ],
                 normals: [Vec3::zeros(); 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             let target_ray = Ray::new(Vec3::new(5.0, 5.0, 5.0), Vec3::new(1.0, 0.5, 1.0));
             if let None = target_triangle.intersect(&target_ray) {
@@ -521,7 +799,9 @@ mod tests {
                     Vec3::from(vertex2),
                 ],
                 normals: [normal; 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             let ray = Ray::new(ray_origin, ray_direction);
 
@@ -552,7 +832,9 @@ mod tests {
                     Vec3::from(vertex2),
                 ],
                 normals: [normal; 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             let ray = Ray::new(ray_origin, ray_direction);
 
@@ -697,7 +979,9 @@ mod tests {
                     Vec3::from(vertex2),
                 ],
                 normals: [normal; 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             let ray = Ray::new(ray_origin, ray_direction);
 
@@ -820,11 +1104,15 @@ mod tests {
             let ray = Ray {
                 origin: ray_origin,
                 direction: (target_point - ray_origin).normalize(),
+                differential: None,
+                time: 0.0,
             };
             let triangle = Triangle {
                 vertices: [vertex0, vertex1, vertex2],
                 normals: [Vec3::zeros(); 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             match triangle.intersect(&ray) {
                 Some(_) => false,
@@ -848,11 +1136,15 @@ mod tests {
             let ray = Ray {
                 origin: ray_origin,
                 direction: (target_point - ray_origin).normalize(),
+                differential: None,
+                time: 0.0,
             };
             let triangle = Triangle {
                 vertices: [vertex0, vertex1, vertex2],
                 normals: [Vec3::zeros(); 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             match triangle.intersect(&ray) {
                 Some(_) => false,
@@ -876,11 +1168,15 @@ mod tests {
             let ray = Ray {
                 origin: ray_origin,
                 direction: (target_point - ray_origin).normalize(),
+                differential: None,
+                time: 0.0,
             };
             let triangle = Triangle {
                 vertices: [vertex0, vertex1, vertex2],
                 normals: [Vec3::zeros(); 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             match triangle.intersect(&ray) {
                 Some(_) => false,
@@ -902,11 +1198,15 @@ mod tests {
             let ray = Ray {
                 origin: ray_origin,
                 direction: (ray_origin - point_behind_ray).normalize(),
+                differential: None,
+                time: 0.0,
             };
             let triangle = Triangle {
                 vertices: [vertex0, vertex1, vertex2],
                 normals: [Vec3::zeros(); 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
                 material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
             };
             match triangle.intersect(&ray) {
                 Some(_) => false,
@@ -914,4 +1214,87 @@ mod tests {
             }
         }
     }
+
+    mod sample_surface {
+        use super::*;
+
+        use crate::materials::LambertianMaterial;
+
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        fn test_triangle() -> Triangle {
+            Triangle {
+                vertices: [
+                    Vec3::new(1.0, 1.0, 2.0),
+                    Vec3::new(4.0, 1.0, 2.0),
+                    Vec3::new(1.0, 3.0, 2.0),
+                ],
+                normals: [Vec3::unit_z(); 3],
+                uvs: [Vec2::new(0.0, 0.0); 3],
+                material: Arc::new(LambertianMaterial::new_dummy()),
+                opacity: None,
+            }
+        }
+
+        #[test]
+        fn surface_area_is_correct() {
+            assert!((test_triangle().surface_area() - 3.0).abs() < 0.000000001);
+        }
+
+        #[test]
+        fn samples_lie_inside_triangle() {
+            let mut rng = StdRng::seed_from_u64(0);
+            let target = test_triangle();
+            for _ in 0..1000 {
+                let sample = target.sample_surface(&mut rng);
+                let p = sample.location - target.vertices[0];
+                assert!(p.z().abs() < 0.000000001);
+                assert!(p.x() >= 0.0 && p.y() >= 0.0);
+                assert!(p.x() / 3.0 + p.y() / 2.0 <= 1.0 + 0.000000001);
+            }
+        }
+
+        #[test]
+        fn intersection_interpolates_uvs() {
+            let mut target = test_triangle();
+            target.uvs = [
+                Vec2::new(0.0, 0.0),
+                Vec2::new(1.0, 0.0),
+                Vec2::new(0.0, 1.0),
+            ];
+            let ray = Ray::new(Vec3::new(2.5, 1.5, -5.0), Vec3::unit_z());
+            let info = target.intersect(&ray).unwrap();
+            assert!((info.uv.x() - 0.5).abs() < 0.000000001);
+            assert!((info.uv.y() - 0.25).abs() < 0.000000001);
+        }
+
+        #[test]
+        fn sample_has_interpolated_normal_and_uniform_pdf() {
+            let sample = test_triangle().sample_surface(&mut StdRng::seed_from_u64(0));
+            assert!(sample.normal == Vec3::unit_z());
+            assert!((sample.pdf - 1.0 / 3.0).abs() < 0.000000001);
+        }
+
+        #[test]
+        fn rays_pass_through_cut_out_parts() {
+            use crate::textures::Gradient;
+
+            let mut target = test_triangle();
+            target.uvs = [
+                Vec2::new(0.0, 0.0),
+                Vec2::new(1.0, 0.0),
+                Vec2::new(0.0, 1.0),
+            ];
+            // Cut away everywhere with u below a half
+            let mask = Gradient::new(Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0));
+            target.opacity = Some(Arc::new(OpacityMask::new(Arc::new(mask), 0.5)));
+            let through = Ray::new(Vec3::new(1.5, 1.5, -5.0), Vec3::unit_z());
+            assert!(target.intersect(&through).is_none());
+            assert!(!target.intersect_any(&through, 100.0));
+            let solid = Ray::new(Vec3::new(3.0, 1.2, -5.0), Vec3::unit_z());
+            assert!(target.intersect(&solid).is_some());
+            assert!(target.intersect_any(&solid, 100.0));
+        }
+    }
 }
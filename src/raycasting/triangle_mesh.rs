@@ -0,0 +1,390 @@
+use crate::materials::Material;
+use crate::math::{Affine3, StoredVec3, Vec2, Vec3};
+use crate::textures::OpacityMask;
+use crate::validation::{SceneIssue, SceneValidator};
+
+use super::triangle::{intersect_triangle, packet_candidates};
+use super::{
+    Aggregate, BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Lanes, Primitive,
+    QuadBoundingVolumeHierarchy, Ray, RayPacket, SampleSurface, SurfaceSample, Transform, Triangle,
+};
+
+use rand::RngCore;
+
+use std::sync::Arc;
+
+/// One triangle of a [MeshBuffers](MeshBuffers)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshFace {
+    /// Index of each corner in the vertex buffers
+    pub vertices: [u32; 3],
+
+    /// Index of the face's material in the list of materials
+    pub material: u32,
+}
+
+/// The vertex and index buffers of a triangle mesh
+///
+/// Each vertex has a position, normal and surface coordinates, stored at the same index in
+/// `positions`, `normals` and `uvs`. Faces refer to their corners by index, so vertices
+/// shared by several faces are only stored once.
+#[derive(Clone, Debug, Default)]
+pub struct MeshBuffers {
+    pub positions: Vec<StoredVec3>,
+    pub normals: Vec<StoredVec3>,
+    pub uvs: Vec<Vec2>,
+    pub faces: Vec<MeshFace>,
+    pub materials: Vec<Arc<dyn Material>>,
+
+    /// The opacity mask to go with each material, if any
+    ///
+    /// Materials beyond the end of the list have no mask.
+    pub opacity: Vec<Option<Arc<OpacityMask>>>,
+}
+
+impl MeshBuffers {
+    pub fn position(&self, index: usize) -> Vec3 {
+        self.positions[index].into()
+    }
+
+    pub fn normal(&self, index: usize) -> Vec3 {
+        self.normals[index].into()
+    }
+
+    /// Add a vertex, returning its index
+    pub fn push_vertex(&mut self, position: Vec3, normal: Vec3, uv: Vec2) -> u32 {
+        self.positions.push(position.into());
+        self.normals.push(normal.into());
+        self.uvs.push(uv);
+        (self.positions.len() - 1) as u32
+    }
+
+    fn corners(&self, face: &MeshFace) -> ([Vec3; 3], [Vec3; 3], [Vec2; 3]) {
+        let [a, b, c] = face.vertices.map(|i| i as usize);
+        (
+            [self.position(a), self.position(b), self.position(c)],
+            [self.normal(a), self.normal(b), self.normal(c)],
+            [self.uvs[a], self.uvs[b], self.uvs[c]],
+        )
+    }
+
+    fn validate_face(&self, face: &MeshFace, validator: &mut SceneValidator) {
+        let (vertices, normals, _) = self.corners(face);
+        validator.check_triangle(&vertices);
+        normals
+            .iter()
+            .for_each(|normal| validator.check_normal(normal));
+        validator.check_material(&self.materials[face.material as usize]);
+    }
+
+    fn opacity(&self, face: &MeshFace) -> Option<&Arc<OpacityMask>> {
+        self.opacity.get(face.material as usize)?.as_ref()
+    }
+
+    /// A standalone copy of the face at `index`
+    pub fn triangle(&self, index: usize) -> Triangle {
+        let face = &self.faces[index];
+        let (vertices, normals, uvs) = self.corners(face);
+        Triangle {
+            vertices,
+            normals,
+            uvs,
+            material: Arc::clone(&self.materials[face.material as usize]),
+            opacity: self.opacity(face).cloned(),
+        }
+    }
+}
+
+impl Transform for MeshBuffers {
+    fn transform(&self, transformation: &Affine3) -> Self {
+        MeshBuffers {
+            positions: self
+                .positions
+                .iter()
+                .map(|&position| transformation.transform_point(&position.into()).into())
+                .collect(),
+            normals: self
+                .normals
+                .iter()
+                .map(|&normal| transformation.transform_normal(&normal.into()).into())
+                .collect(),
+            uvs: self.uvs.clone(),
+            faces: self.faces.clone(),
+            materials: self.materials.clone(),
+            opacity: self.opacity.clone(),
+        }
+    }
+}
+
+/// A single face of a mesh, which refers to the mesh's buffers instead of storing its own
+/// vertices
+///
+/// This is much smaller than a [Triangle](Triangle), which matters for meshes with many
+/// thousands of faces.
+#[derive(Clone, Debug)]
+pub struct MeshTriangle {
+    buffers: Arc<MeshBuffers>,
+    face: usize,
+}
+
+impl MeshTriangle {
+    pub fn new(buffers: Arc<MeshBuffers>, face: usize) -> MeshTriangle {
+        assert!(face < buffers.faces.len());
+        MeshTriangle { buffers, face }
+    }
+
+    /// A primitive for every face in `buffers`
+    pub fn all_faces(buffers: &Arc<MeshBuffers>) -> Vec<Arc<dyn Primitive>> {
+        (0..buffers.faces.len())
+            .map(|face| {
+                Arc::new(MeshTriangle::new(Arc::clone(buffers), face)) as Arc<dyn Primitive>
+            })
+            .collect()
+    }
+}
+
+impl Intersect for MeshTriangle {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        let face = &self.buffers.faces[self.face];
+        let (vertices, normals, uvs) = self.buffers.corners(face);
+        intersect_triangle(
+            &vertices,
+            &normals,
+            &uvs,
+            &self.buffers.materials[face.material as usize],
+            self.buffers.opacity(face).map(Arc::as_ref),
+            ray,
+        )
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes<Option<IntersectionInfo>> {
+        let (vertices, _, _) = self.buffers.corners(&self.buffers.faces[self.face]);
+        packet
+            .masked(&packet_candidates(&vertices, packet))
+            .map_active(|ray| self.intersect(ray))
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        self.buffers
+            .validate_face(&self.buffers.faces[self.face], validator);
+    }
+}
+
+impl HasBoundingBox for MeshTriangle {
+    fn bounding_box(&self) -> BoundingBox {
+        let (vertices, _, _) = self.buffers.corners(&self.buffers.faces[self.face]);
+        BoundingBox::from_points(&vertices)
+    }
+}
+
+impl Primitive for MeshTriangle {
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(self.buffers.triangle(self.face).transform(transformation))
+    }
+}
+
+impl SampleSurface for MeshTriangle {
+    fn surface_area(&self) -> f64 {
+        self.buffers.triangle(self.face).surface_area()
+    }
+
+    fn sample_surface(&self, rng: &mut dyn RngCore) -> SurfaceSample {
+        self.buffers.triangle(self.face).sample_surface(rng)
+    }
+}
+
+/// A triangle mesh with its own [QuadBoundingVolumeHierarchy](QuadBoundingVolumeHierarchy)
+pub struct TriangleMesh {
+    buffers: Arc<MeshBuffers>,
+    bvh: QuadBoundingVolumeHierarchy,
+}
+
+impl TriangleMesh {
+    pub fn new(buffers: MeshBuffers) -> TriangleMesh {
+        let buffers = Arc::new(buffers);
+        let bvh = QuadBoundingVolumeHierarchy::build(&mut MeshTriangle::all_faces(&buffers));
+        TriangleMesh { buffers, bvh }
+    }
+
+    pub fn buffers(&self) -> &Arc<MeshBuffers> {
+        &self.buffers
+    }
+}
+
+impl Intersect for TriangleMesh {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        self.bvh.intersect(ray)
+    }
+
+    fn intersect_any(&self, ray: &Ray, max_distance: f64) -> bool {
+        self.bvh.intersect_any(ray, max_distance)
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes<Option<IntersectionInfo>> {
+        self.bvh.intersect_packet(packet)
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        if self.buffers.faces.is_empty() {
+            validator.report(SceneIssue::EmptyAggregate);
+        }
+        for face in &self.buffers.faces {
+            self.buffers.validate_face(face, validator);
+        }
+    }
+}
+
+impl HasBoundingBox for TriangleMesh {
+    fn bounding_box(&self) -> BoundingBox {
+        self.bvh.bounding_box()
+    }
+}
+
+impl Aggregate for TriangleMesh {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+    use crate::raycasting::RAY_PACKET_WIDTH;
+
+    /// A unit square in the XY plane, made of two triangles that share an edge
+    fn square() -> MeshBuffers {
+        MeshBuffers {
+            positions: vec![
+                Vec3::new(0.0, 0.0, 0.0).into(),
+                Vec3::new(1.0, 0.0, 0.0).into(),
+                Vec3::new(1.0, 1.0, 0.0).into(),
+                Vec3::new(0.0, 1.0, 0.0).into(),
+            ],
+            normals: vec![Vec3::unit_z().into(); 4],
+            uvs: vec![
+                Vec2::new(0.0, 0.0),
+                Vec2::new(1.0, 0.0),
+                Vec2::new(1.0, 1.0),
+                Vec2::new(0.0, 1.0),
+            ],
+            faces: vec![
+                MeshFace {
+                    vertices: [0, 1, 2],
+                    material: 0,
+                },
+                MeshFace {
+                    vertices: [0, 2, 3],
+                    material: 0,
+                },
+            ],
+            materials: vec![Arc::new(LambertianMaterial::new_dummy())],
+            opacity: vec![],
+        }
+    }
+
+    #[test]
+    fn mesh_triangle_intersects_like_triangle() {
+        let buffers = Arc::new(square());
+        for face in 0..2 {
+            let target = MeshTriangle::new(Arc::clone(&buffers), face);
+            let triangle = buffers.triangle(face);
+            for &(x, y) in [(0.75, 0.25), (0.25, 0.75), (1.5, 0.5)].iter() {
+                let ray = Ray::new(Vec3::new(x, y, -1.0), Vec3::unit_z());
+                let expected = triangle
+                    .intersect(&ray)
+                    .map(|info| (info.location, info.uv));
+                let actual = target.intersect(&ray).map(|info| (info.location, info.uv));
+                assert!(actual == expected);
+            }
+        }
+    }
+
+    #[test]
+    fn mesh_finds_nearest_face() {
+        let target = TriangleMesh::new(square());
+        let ray = Ray::new(Vec3::new(0.25, 0.75, -1.0), Vec3::unit_z());
+        let info = target.intersect(&ray).unwrap();
+        assert!((info.location - Vec3::new(0.25, 0.75, 0.0)).norm() < 0.000000001);
+        assert!((info.uv.x() - 0.25).abs() < 0.000000001);
+        assert!(!target.intersect_any(&ray, 0.5));
+        let miss = Ray::new(Vec3::new(1.25, 0.75, -1.0), Vec3::unit_z());
+        assert!(target.intersect(&miss).is_none());
+    }
+
+    #[test]
+    fn masked_faces_let_rays_through_to_faces_behind() {
+        use crate::textures::Gradient;
+
+        let mut buffers = square();
+        let behind = square().transform(&Affine3::translation(&Vec3::new(0.0, 0.0, 1.0)));
+        buffers.positions.extend(behind.positions);
+        buffers.normals.extend(behind.normals);
+        buffers.uvs.extend(behind.uvs);
+        buffers
+            .faces
+            .extend(behind.faces.iter().map(|face| MeshFace {
+                vertices: face.vertices.map(|i| i + 4),
+                material: 1,
+            }));
+        buffers
+            .materials
+            .push(Arc::new(LambertianMaterial::new_dummy()));
+        // Only the front square is masked, and only where u is below a half
+        let mask = Gradient::new(Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0));
+        buffers.opacity = vec![Some(Arc::new(OpacityMask::new(Arc::new(mask), 0.5)))];
+        let target = TriangleMesh::new(buffers);
+        let through = Ray::new(Vec3::new(0.25, 0.75, -1.0), Vec3::unit_z());
+        assert!((target.intersect(&through).unwrap().location.z() - 1.0).abs() < 0.000000001);
+        assert!(!target.intersect_any(&through, 1.5));
+        let solid = Ray::new(Vec3::new(0.75, 0.25, -1.0), Vec3::unit_z());
+        assert!(target.intersect(&solid).unwrap().location.z().abs() < 0.000000001);
+        assert!(target.intersect_any(&solid, 1.5));
+    }
+
+    #[test]
+    fn packet_hits_match_single_rays() {
+        let mut buffers = square();
+        for i in 1..8 {
+            let offset = Vec3::new(i as f64 * 0.75, (i % 3) as f64 * 0.5, i as f64 * 0.25);
+            let copy = square().transform(&Affine3::translation(&offset));
+            let base = buffers.positions.len() as u32;
+            buffers.positions.extend(copy.positions);
+            buffers.normals.extend(copy.normals);
+            buffers.uvs.extend(copy.uvs);
+            buffers.faces.extend(copy.faces.iter().map(|face| MeshFace {
+                vertices: face.vertices.map(|i| i + base),
+                material: 0,
+            }));
+        }
+        let target = TriangleMesh::new(buffers);
+        // A fan of rays from one point, including some along shared edges and some that
+        // miss everything
+        let rays: Vec<Ray> = (0..64)
+            .map(|i| {
+                let (x, y) = ((i % 8) as f64 * 0.8 - 0.5, (i / 8) as f64 * 0.25 - 0.25);
+                Ray::new(Vec3::new(0.5, 0.5, -2.0), Vec3::new(x, y, 2.0).normalize())
+            })
+            .chain(std::iter::once(Ray::new(
+                Vec3::new(0.5, 0.5, -1.0),
+                Vec3::unit_z(),
+            )))
+            .collect();
+        for rays in rays.chunks(RAY_PACKET_WIDTH) {
+            let hits = target.intersect_packet(&RayPacket::new(rays));
+            for (ray, hit) in rays.iter().zip(hits.iter()) {
+                let expected = target.intersect(ray).map(|info| (info.distance, info.uv));
+                assert!(hit.as_ref().map(|info| (info.distance, info.uv)) == expected);
+            }
+        }
+    }
+
+    #[test]
+    fn transformed_buffers_move_vertices() {
+        let translation = Vec3::new(1.0, 2.0, 3.0);
+        let target = square().transform(&Affine3::translation(&translation));
+        assert!(target.position(2) == Vec3::new(2.0, 3.0, 3.0));
+        assert!(target.normal(2) == Vec3::unit_z());
+        let moved = MeshTriangle::new(Arc::new(square()), 0)
+            .transform_primitive(&Affine3::translation(&translation));
+        let bounds = moved.bounding_box();
+        assert!(bounds.bounds[2].get_min() == 3.0);
+    }
+}
@@ -1,4 +1,10 @@
-use super::{Aggregate, BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Primitive, Ray};
+use crate::validation::{SceneIssue, SceneValidator};
+
+use super::ray_packet::keep_nearest_in_each_lane;
+use super::{
+    Aggregate, BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Lanes, Primitive, Ray,
+    RayPacket,
+};
 
 impl HasBoundingBox for Vec<Box<dyn Primitive>> {
     fn bounding_box(&self) -> BoundingBox {
@@ -8,16 +14,61 @@ impl HasBoundingBox for Vec<Box<dyn Primitive>> {
     }
 }
 
+/// Primitives are tested against their bounding boxes first, which is much cheaper than a
+/// full intersection test for most of them, and skipped if the ray misses the box or only
+/// reaches it beyond the nearest intersection found so far.
 impl Intersect for Vec<Box<dyn Primitive>> {
     fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
-        self.iter()
-            .flat_map(|primitive| primitive.intersect(ray))
-            .min_by(
-                |a, b| match PartialOrd::partial_cmp(&a.distance, &b.distance) {
-                    None => std::cmp::Ordering::Less,
-                    Some(ordering) => ordering,
-                },
-            )
+        let mut nearest: Option<IntersectionInfo> = None;
+        for primitive in self {
+            let inside = primitive.bounding_box().distances_inside(ray);
+            if inside.is_empty()
+                || nearest
+                    .as_ref()
+                    .is_some_and(|info| info.distance < inside.get_min())
+            {
+                continue;
+            }
+            if let Some(info) = primitive.intersect(ray) {
+                let is_nearer = match &nearest {
+                    Some(current) => info.distance < current.distance,
+                    None => true,
+                };
+                if is_nearer {
+                    nearest = Some(info);
+                }
+            }
+        }
+        nearest
+    }
+
+    fn intersect_any(&self, ray: &Ray, max_distance: f64) -> bool {
+        self.iter().any(|primitive| {
+            let inside = primitive.bounding_box().distances_inside(ray);
+            !inside.is_empty()
+                && inside.get_min() < max_distance
+                && primitive.intersect_any(ray, max_distance)
+        })
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes<Option<IntersectionInfo>> {
+        let mut nearest = Default::default();
+        for primitive in self {
+            let packet = packet.masked(&packet.intersect_bounds(&primitive.bounding_box()));
+            if packet.any_active() {
+                keep_nearest_in_each_lane(&mut nearest, primitive.intersect_packet(&packet));
+            }
+        }
+        nearest
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        if self.is_empty() {
+            validator.report(SceneIssue::EmptyAggregate);
+        }
+        for primitive in self {
+            primitive.validate(validator);
+        }
     }
 }
 
@@ -42,6 +93,84 @@ impl Intersect for Vec<Box<dyn Aggregate>> {
                 },
             )
     }
+
+    fn intersect_any(&self, ray: &Ray, max_distance: f64) -> bool {
+        self.iter()
+            .any(|aggregate| aggregate.intersect_any(ray, max_distance))
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes<Option<IntersectionInfo>> {
+        let mut nearest = Default::default();
+        for aggregate in self {
+            keep_nearest_in_each_lane(&mut nearest, aggregate.intersect_packet(packet));
+        }
+        nearest
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        if self.is_empty() {
+            validator.report(SceneIssue::EmptyAggregate);
+        }
+        for aggregate in self {
+            aggregate.validate(validator);
+        }
+    }
 }
 
 impl Aggregate for Vec<Box<dyn Aggregate>> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+    use crate::math::Vec3;
+    use crate::raycasting::{Plane, Sphere};
+
+    use std::sync::Arc;
+
+    fn spheres_along_z() -> Vec<Box<dyn Primitive>> {
+        let material = Arc::new(LambertianMaterial::new_dummy());
+        // Listed farthest first, and with a large sphere whose box starts before the
+        // nearest hit but whose surface is beyond it
+        vec![
+            Box::new(Sphere::new(
+                Vec3::new(0.0, 0.0, 20.0),
+                1.0,
+                material.clone(),
+            )),
+            Box::new(Sphere::new(Vec3::new(0.0, 4.5, 8.5), 5.0, material.clone())),
+            Box::new(Sphere::new(Vec3::new(0.0, 0.0, 5.0), 1.0, material.clone())),
+            Box::new(Sphere::new(Vec3::new(6.0, 0.0, 5.0), 1.0, material.clone())),
+            Box::new(Plane::new(Vec3::unit_z(), 30.0, material)),
+        ]
+    }
+
+    #[test]
+    fn finds_nearest_intersection() {
+        let target = spheres_along_z();
+        let ray = Ray::new(Vec3::zeros(), Vec3::unit_z());
+        let expected = target
+            .iter()
+            .filter_map(|primitive| primitive.intersect(&ray))
+            .map(|info| info.distance)
+            .fold(f64::INFINITY, f64::min);
+        assert!(target.intersect(&ray).unwrap().distance == expected);
+        assert!((expected - 4.0).abs() < 0.000000001);
+        let rays = [ray.clone(), Ray::new(Vec3::zeros(), -Vec3::unit_z())];
+        let packet = RayPacket::new(&rays);
+        let hits = target.intersect_packet(&packet);
+        assert!(hits[0].as_ref().unwrap().distance == expected);
+        assert!(hits[1].is_none());
+    }
+
+    #[test]
+    fn intersect_any_respects_max_distance() {
+        let target = spheres_along_z();
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -10.0), Vec3::unit_z());
+        assert!(!target.intersect_any(&ray, 13.5));
+        assert!(target.intersect_any(&ray, 14.5));
+        let miss = Ray::new(Vec3::new(0.0, 0.0, -10.0), -Vec3::unit_z());
+        assert!(!target.intersect_any(&miss, f64::INFINITY));
+    }
+}
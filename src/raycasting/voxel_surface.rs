@@ -0,0 +1,168 @@
+use crate::materials::Material;
+use crate::math::{Affine3, OrthonormalBasis, Vec2, Vec3};
+use crate::util::float_error::gamma;
+use crate::util::VoxelGrid;
+
+use super::{
+    Aggregate, BoundingBox, HasBoundingBox, Instance, Intersect, IntersectionInfo, Primitive, Ray,
+};
+
+use std::sync::Arc;
+
+/// Number of times the interval around a crossing is halved to find the surface
+const BISECTION_STEPS: i32 = 32;
+
+/// The surface where the values in a [VoxelGrid](VoxelGrid) cross a threshold, so a density
+/// grid can be rendered as a solid, or a grid of distances as the shape they describe
+///
+/// Rays are marched through the grid in steps of half a voxel until the value crosses
+/// `threshold`, and the crossing is then found by bisection, so features smaller than
+/// half a voxel can be missed. Normals point from higher values towards lower ones. There
+/// are no natural surface coordinates, so `uv` is always zero.
+#[derive(Clone)]
+pub struct VoxelSurface {
+    grid: Arc<VoxelGrid>,
+    threshold: f64,
+    material: Arc<dyn Material>,
+}
+
+impl VoxelSurface {
+    pub fn new(grid: Arc<VoxelGrid>, threshold: f64, material: Arc<dyn Material>) -> VoxelSurface {
+        VoxelSurface {
+            grid,
+            threshold,
+            material,
+        }
+    }
+
+    fn is_inside(&self, point: &Vec3) -> bool {
+        self.grid.value_at(point) >= self.threshold
+    }
+
+    /// The gradient of the grid's values at `point`, by central differences
+    fn gradient(&self, point: &Vec3) -> Vec3 {
+        let h = 0.01 * self.grid.voxel_size();
+        let difference = |axis: Vec3| {
+            self.grid.value_at(&(point + axis * h)) - self.grid.value_at(&(*point - axis * h))
+        };
+        Vec3::new(
+            difference(Vec3::unit_x()),
+            difference(Vec3::unit_y()),
+            difference(Vec3::unit_z()),
+        )
+    }
+}
+
+impl Intersect for VoxelSurface {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        let range = self.grid.bounding_box().distances_inside(ray);
+        if range.is_empty() {
+            return None;
+        }
+        let step = 0.5 * self.grid.voxel_size();
+        let mut before = range.get_min();
+        let started_inside = self.is_inside(&ray.point_at(before));
+        let (mut before, mut after) = loop {
+            if before >= range.get_max() {
+                return None;
+            }
+            let after = (before + step).min(range.get_max());
+            if self.is_inside(&ray.point_at(after)) != started_inside {
+                break (before, after);
+            }
+            before = after;
+        };
+        for _ in 0..BISECTION_STEPS {
+            let middle = 0.5 * (before + after);
+            if self.is_inside(&ray.point_at(middle)) == started_inside {
+                before = middle;
+            } else {
+                after = middle;
+            }
+        }
+        let distance = after;
+        let location = ray.point_at(distance);
+        let gradient = self.gradient(&location);
+        if gradient.norm_squared() == 0.0 {
+            return None;
+        }
+        let normal = -gradient.normalize();
+        let OrthonormalBasis {
+            tangent, cotangent, ..
+        } = OrthonormalBasis::from_normal(&normal);
+        // The crossing is only known to lie within the last bisected interval
+        let location_error = Vec3::new(1.0, 1.0, 1.0) * (after - before)
+            + (ray.origin.abs() + (ray.direction * distance).abs()) * gamma(3);
+        Some(IntersectionInfo {
+            distance,
+            location,
+            location_error,
+            normal,
+            tangent,
+            cotangent,
+            retro: -ray.direction,
+            uv: Vec2::new(0.0, 0.0),
+            material: Arc::clone(&self.material),
+            object_id: 0,
+            footprint: None,
+            time: 0.0,
+        })
+    }
+}
+
+impl HasBoundingBox for VoxelSurface {
+    fn bounding_box(&self) -> BoundingBox {
+        self.grid.bounding_box()
+    }
+}
+
+impl Primitive for VoxelSurface {
+    /// The grid can't be transformed directly, so the result is an [Instance](Instance) of it
+    fn transform_primitive(&self, transformation: &Affine3) -> Arc<dyn Primitive> {
+        Arc::new(Instance::new(Arc::new(self.clone()), *transformation))
+    }
+}
+
+impl Aggregate for VoxelSurface {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+
+    /// A ball whose density is one at the centre and zero at a radius of one
+    fn test_surface(threshold: f64) -> VoxelSurface {
+        let grid = VoxelGrid::from_fn(Vec3::new(-1.5, -1.5, -1.5), 0.1, [30, 30, 30], |point| {
+            (1.0 - point.norm()).max(0.0)
+        });
+        VoxelSurface::new(
+            Arc::new(grid),
+            threshold,
+            Arc::new(LambertianMaterial::new_dummy()),
+        )
+    }
+
+    #[test]
+    fn surface_is_at_threshold() {
+        let target = test_surface(0.5);
+        let info = target
+            .intersect(&Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::unit_z()))
+            .unwrap();
+        assert!((info.location - Vec3::new(0.0, 0.0, -0.5)).norm() < 0.02);
+        assert!(info.normal.dot(&-Vec3::unit_z()) > 0.99);
+        assert!(target
+            .intersect(&Ray::new(Vec3::new(0.6, 0.0, -5.0), Vec3::unit_z()))
+            .is_none());
+    }
+
+    #[test]
+    fn rays_starting_inside_find_way_out() {
+        let target = test_surface(0.5);
+        let info = target
+            .intersect(&Ray::new(Vec3::zeros(), Vec3::unit_x()))
+            .unwrap();
+        assert!((info.location - Vec3::new(0.5, 0.0, 0.0)).norm() < 0.02);
+        assert!(info.normal.dot(&Vec3::unit_x()) > 0.99);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::validation::SceneValidator;
+
+use super::{
+    Aggregate, BoundingBox, HasBoundingBox, Intersect, IntersectionInfo, Lanes, Ray, RayPacket,
+};
+
+/// Gives every intersection with `object` the ID `object_id`
+///
+/// IDs are written to [ID buffers](crate::aovs::render_id_buffers), which compositors use
+/// to build a mask for each object. Zero is left for objects without an ID, and for the
+/// background. Wrapping an object that already contains objects with IDs replaces them.
+#[derive(Clone, Debug)]
+pub struct WithObjectId<T: Aggregate> {
+    pub object: T,
+    pub object_id: u32,
+}
+
+impl<T: Aggregate> WithObjectId<T> {
+    pub fn new(object: T, object_id: u32) -> WithObjectId<T> {
+        WithObjectId { object, object_id }
+    }
+}
+
+impl<T: Aggregate> Intersect for WithObjectId<T> {
+    fn intersect(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        self.object.intersect(ray).map(|info| IntersectionInfo {
+            object_id: self.object_id,
+            ..info
+        })
+    }
+
+    fn intersect_any(&self, ray: &Ray, max_distance: f64) -> bool {
+        self.object.intersect_any(ray, max_distance)
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> Lanes<Option<IntersectionInfo>> {
+        self.object.intersect_packet(packet).map(|info| {
+            info.map(|info| IntersectionInfo {
+                object_id: self.object_id,
+                ..info
+            })
+        })
+    }
+
+    fn validate(&self, validator: &mut SceneValidator) {
+        self.object.validate(validator)
+    }
+}
+
+impl<T: Aggregate> HasBoundingBox for WithObjectId<T> {
+    fn bounding_box(&self) -> BoundingBox {
+        self.object.bounding_box()
+    }
+}
+
+impl<T: Aggregate> Aggregate for WithObjectId<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+    use crate::math::Vec3;
+    use crate::raycasting::{Primitive, Sphere};
+
+    use std::sync::Arc;
+
+    #[test]
+    fn intersections_carry_object_id() {
+        let sphere: Box<dyn Primitive> = Box::new(Sphere::new(
+            Vec3::new(0.0, 0.0, 5.0),
+            1.0,
+            Arc::new(LambertianMaterial::new_dummy()),
+        ));
+        let target = WithObjectId::new(vec![sphere], 7);
+        let ray = Ray::new(Vec3::zeros(), Vec3::unit_z());
+        let info = target.intersect(&ray).unwrap();
+        assert!(info.object_id == 7);
+        assert!((info.distance - 4.0).abs() < 0.000000001);
+        assert!(target.object[0].intersect(&ray).unwrap().object_id == 0);
+    }
+}
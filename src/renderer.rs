@@ -0,0 +1,214 @@
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+
+use crate::accumulation_buffer::AccumulationBuffer;
+use crate::camera::{filter_footprint, partial_render_scene, RenderSettings};
+use crate::error::VanrijnError;
+use crate::scene::Scene;
+use crate::util::{Tile, TileOrder, TileScheduler, TileSequencer};
+
+use std::sync::mpsc;
+
+/// The image to produce when calling [render()](render), and how to divide the work
+///
+/// The size of the tiles and the seed are taken from the [RenderSettings](RenderSettings).
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub width: usize,
+    pub height: usize,
+    pub tile_order: TileOrder,
+
+    /// Number of worker threads, or `None` to use one per CPU
+    pub threads: Option<usize>,
+
+    /// Deliver tiles in the order they were scheduled, rather than as they finish
+    ///
+    /// Pixels are seeded by their position, so every tile is the same whichever thread
+    /// renders it; with this set, merging the tiles as they're delivered also gives the
+    /// same image, down to the last bit, every time.
+    pub deterministic: bool,
+}
+
+impl RenderOptions {
+    pub fn new(width: usize, height: usize) -> RenderOptions {
+        RenderOptions {
+            width,
+            height,
+            tile_order: TileOrder::default(),
+            threads: None,
+            deterministic: false,
+        }
+    }
+}
+
+/// Render `scene`, passing each tile to `on_tile` as soon as it's finished
+///
+/// The tiles are rendered on a pool of worker threads created for the render, but
+/// `on_tile` is always called on the calling thread, so it doesn't need to be `Send`. It's
+/// called once for each tile, in whatever order the tiles finish, unless the options ask
+/// for a [deterministic](RenderOptions::deterministic) order, and `render()` returns once
+/// every tile has been delivered.
+///
+/// The tile passed to `on_tile` gives the pixels covered by the buffer. With a
+/// reconstruction filter wider than a pixel the buffer also covers a margin around the
+/// tile that was rendered, which overlaps its neighbours, so tiles should be combined with
+/// [merge_tile()](AccumulationBuffer::merge_tile) rather than copied.
+///
+/// # Examples
+///
+/// ```
+/// # use vanrijn::accumulation_buffer::AccumulationBuffer;
+/// # use vanrijn::camera::{Lens, PerspectiveCamera, RenderSettings};
+/// # use vanrijn::math::Vec3;
+/// # use vanrijn::renderer::{render, RenderOptions};
+/// # use vanrijn::scene::Scene;
+/// # let scene = Scene::builder(Box::new(PerspectiveCamera::new(
+/// #     Vec3::new(0.0, 0.0, 0.0),
+/// #     Lens::Pinhole,
+/// # )))
+/// # .build();
+/// let options = RenderOptions::new(64, 48);
+/// let mut image = AccumulationBuffer::new(options.width, options.height);
+/// render(&scene, &RenderSettings::default(), &options, |tile, tile_image| {
+///     image.merge_tile(&tile, tile_image);
+///     // display the partially-rendered image
+/// })
+/// .unwrap();
+/// ```
+pub fn render<F: FnMut(Tile, &AccumulationBuffer)>(
+    scene: &Scene,
+    settings: &RenderSettings,
+    options: &RenderOptions,
+    mut on_tile: F,
+) -> Result<(), VanrijnError> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(0))
+        .build()?;
+    let (width, height) = (options.width, options.height);
+    let scheduler = TileScheduler::new(width, height, settings.tile_size, options.tile_order);
+    let scheduler = &scheduler;
+    let (tile_tx, tile_rx) = mpsc::channel();
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            pool.install(move || {
+                (0..rayon::current_num_threads())
+                    .into_par_iter()
+                    .for_each_with(tile_tx, |tile_tx, _| {
+                        while let Some((index, tile)) = scheduler.next_indexed_tile() {
+                            let rendered_tile = partial_render_scene(
+                                scene,
+                                tile,
+                                height,
+                                width,
+                                settings.seed,
+                                settings,
+                            );
+                            let footprint =
+                                filter_footprint(&tile, width, height, settings.filter.as_ref());
+                            // The receiver only goes away if `on_tile` panicked, in which
+                            // case there's nobody left to deliver tiles to
+                            if tile_tx.send((index, footprint, rendered_tile)).is_err() {
+                                break;
+                            }
+                        }
+                    })
+            })
+        });
+        let mut sequencer = TileSequencer::new();
+        for (index, footprint, rendered_tile) in tile_rx {
+            if options.deterministic {
+                for (footprint, rendered_tile) in sequencer.push(index, (footprint, rendered_tile))
+                {
+                    on_tile(footprint, &rendered_tile);
+                }
+            } else {
+                on_tile(footprint, &rendered_tile);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+
+    use crate::math::Vec3;
+    use crate::progressive_renderer::ProgressiveRenderer;
+
+    use std::sync::{Arc, Mutex};
+
+    fn empty_scene() -> Scene {
+        Scene::builder(Box::new(PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Lens::Pinhole,
+        )))
+        .build()
+    }
+
+    #[test]
+    fn every_tile_is_delivered_once() {
+        let scene = empty_scene();
+        let settings = RenderSettings {
+            tile_size: 4,
+            ..RenderSettings::default()
+        };
+        let options = RenderOptions {
+            threads: Some(3),
+            ..RenderOptions::new(10, 7)
+        };
+        let mut tiles = Vec::new();
+        render(&scene, &settings, &options, |tile, image| {
+            assert!(image.width() == tile.width() && image.height() == tile.height());
+            tiles.push((tile.start_column, tile.start_row));
+        })
+        .unwrap();
+        tiles.sort_unstable();
+        assert!(tiles == vec![(0, 0), (0, 4), (4, 0), (4, 4), (8, 0), (8, 4)]);
+    }
+
+    #[test]
+    fn deterministic_tiles_are_delivered_in_schedule_order() {
+        let scene = empty_scene();
+        let settings = RenderSettings {
+            tile_size: 2,
+            ..RenderSettings::default()
+        };
+        let options = RenderOptions {
+            threads: Some(4),
+            deterministic: true,
+            ..RenderOptions::new(11, 9)
+        };
+        let mut tiles = Vec::new();
+        render(&scene, &settings, &options, |tile, _| tiles.push(tile)).unwrap();
+        let scheduler = TileScheduler::new(11, 9, 2, options.tile_order);
+        let expected: Vec<Tile> = (&scheduler).collect();
+        assert!(tiles == expected);
+    }
+
+    #[test]
+    fn merged_tiles_match_progressive_pass() {
+        let scene = empty_scene();
+        let settings = RenderSettings {
+            tile_size: 3,
+            seed: 7,
+            ..RenderSettings::default()
+        };
+        let options = RenderOptions::new(8, 5);
+        let mut image = AccumulationBuffer::new(options.width, options.height);
+        render(&scene, &settings, &options, |tile, tile_image| {
+            image.merge_tile(&tile, tile_image)
+        })
+        .unwrap();
+
+        let expected = Arc::new(Mutex::new(AccumulationBuffer::new(8, 5)));
+        ProgressiveRenderer::new(&scene, Arc::clone(&expected), settings).render_pass();
+        let expected = expected.lock().unwrap();
+        let (mut expected_bytes, mut actual_bytes) = (Vec::new(), Vec::new());
+        expected.write_to(&mut expected_bytes).unwrap();
+        image.write_to(&mut actual_bytes).unwrap();
+        assert!(expected_bytes == actual_bytes);
+    }
+}
@@ -1,21 +1,377 @@
-use super::raycasting::{IntersectionInfo, Ray};
+use super::colour::PhotonPacket;
+use super::lights::{Light, LightSample};
+use super::math::Vec3;
+use super::raycasting::{
+    Aggregate, Footprint, IntersectionInfo, Lanes, Ray, RayPacket, RAY_PACKET_WIDTH,
+};
 use super::scene::Scene;
+use super::statistics;
+use super::util::Interval;
 
-pub struct Sampler<'a> {
+use rand::RngCore;
+
+use std::sync::Arc;
+
+/// Finds what rays hit in a scene, for [integrators](crate::integrators::Integrator)
+///
+/// Integrators only see the scene through this trait, so tests can give them a sampler
+/// that returns whatever intersections they need, and other ways of tracing rays can be
+/// used without changing them. [SceneSampler](SceneSampler) traces rays against the
+/// objects of a [Scene](Scene) directly.
+pub trait Sampler: Sync {
+    /// The scene being sampled, for its lights, environment, medium and materials
+    fn scene(&self) -> &Scene;
+
+    /// The nearest intersection of `ray` with anything in the scene
+    ///
+    /// The intersection's [time](IntersectionInfo::time) should be the ray's, and if the
+    /// ray carries a [RayDifferential](crate::raycasting::RayDifferential), its
+    /// [footprint](IntersectionInfo::footprint) should be filled in from it.
+    fn sample(&self, ray: &Ray) -> Option<IntersectionInfo>;
+
+    /// The nearest intersection of each of `rays` with anything in the scene
+    ///
+    /// The results must be the same as from [sample()](Sampler::sample), which is what the
+    /// default uses for each ray in turn.
+    ///
+    /// # Panics
+    ///
+    /// If there are more than [RAY_PACKET_WIDTH](crate::raycasting::RAY_PACKET_WIDTH) rays.
+    fn sample_packet(&self, rays: &[Ray]) -> Lanes<Option<IntersectionInfo>> {
+        assert!(rays.len() <= RAY_PACKET_WIDTH);
+        std::array::from_fn(|lane| rays.get(lane).and_then(|ray| self.sample(ray)))
+    }
+
+    /// Test if anything in the scene is hit by `ray` closer than `max_distance`
+    ///
+    /// This is much cheaper than [sample()](Sampler::sample) when only visibility is
+    /// needed, such as for shadow rays.
+    fn is_occluded(&self, ray: &Ray, max_distance: f64) -> bool;
+
+    /// Choose a direction from `location` towards `light`, one of the scene's
+    /// [lights](Scene::lights), and find the light arriving along it
+    ///
+    /// By default this is [Light::sample_incident()](Light::sample_incident).
+    fn sample_light(
+        &self,
+        light: &dyn Light,
+        location: &Vec3,
+        packet: &PhotonPacket,
+        rng: &mut dyn RngCore,
+    ) -> Option<LightSample> {
+        light.sample_incident(location, packet, rng)
+    }
+}
+
+/// A [Sampler](Sampler) that intersects rays with each of the scene's objects in turn
+pub struct SceneSampler<'a> {
     pub scene: &'a Scene,
 }
 
-impl<'a> Sampler<'a> {
-    pub fn sample(&self, ray: &Ray) -> Option<IntersectionInfo> {
+impl<'a> Sampler for SceneSampler<'a> {
+    fn scene(&self) -> &Scene {
+        self.scene
+    }
+
+    fn sample(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        statistics::count_ray_cast();
+        if !self.scene.clipping_planes.is_empty() {
+            return self.sample_clipped(ray);
+        }
+        let (object, info) = self.nearest(ray)?;
+        Some(with_ray_details(object, ray, info))
+    }
+
+    /// The rays are traced together as a [RayPacket](RayPacket), which is quicker than
+    /// [sampling](Sampler::sample) them one at a time when they are close together, such
+    /// as camera rays for neighbouring pixels.
+    fn sample_packet(&self, rays: &[Ray]) -> Lanes<Option<IntersectionInfo>> {
+        if !self.scene.clipping_planes.is_empty() {
+            assert!(rays.len() <= RAY_PACKET_WIDTH);
+            return std::array::from_fn(|lane| rays.get(lane).and_then(|ray| self.sample(ray)));
+        }
+        for _ in rays {
+            statistics::count_ray_cast();
+        }
+        let packet = RayPacket::new(rays);
+        let mut nearest: Lanes<Option<(&dyn Aggregate, IntersectionInfo)>> = Default::default();
+        for object in &self.scene.objects {
+            let hits = object.intersect_packet(&packet);
+            for (lane, hit) in IntoIterator::into_iter(hits).enumerate() {
+                if let Some(info) = hit {
+                    let is_nearer = match &nearest[lane] {
+                        None => true,
+                        Some((_, current)) => current.distance > info.distance,
+                    };
+                    if is_nearer {
+                        nearest[lane] = Some((object.as_ref(), info));
+                    }
+                }
+            }
+        }
+        let mut lanes = IntoIterator::into_iter(nearest);
+        std::array::from_fn(|lane| {
+            let (object, info) = lanes.next().flatten()?;
+            Some(with_ray_details(object, &rays[lane], info))
+        })
+    }
+
+    fn is_occluded(&self, ray: &Ray, max_distance: f64) -> bool {
+        statistics::count_shadow_ray();
+        if !self.scene.clipping_planes.is_empty() {
+            return self
+                .sample_clipped(ray)
+                .is_some_and(|info| info.distance < max_distance);
+        }
+        self.scene
+            .objects
+            .iter()
+            .any(|object| object.intersect_any(ray, max_distance))
+    }
+}
+
+impl<'a> SceneSampler<'a> {
+    /// The nearest intersection of `ray` with any of the scene's objects, and the object
+    fn nearest(&self, ray: &Ray) -> Option<(&dyn Aggregate, IntersectionInfo)> {
         self.scene
             .objects
             .iter()
-            .flat_map(|object| object.intersect(ray))
+            .flat_map(|object| object.intersect(ray).map(|info| (object.as_ref(), info)))
             .min_by(
-                |a, b| match PartialOrd::partial_cmp(&a.distance, &b.distance) {
+                |(_, a), (_, b)| match PartialOrd::partial_cmp(&a.distance, &b.distance) {
                     None => std::cmp::Ordering::Less,
                     Some(ordering) => ordering,
                 },
             )
     }
+
+    /// The nearest intersection of `ray` with what's left of the scene after the
+    /// [clipping planes](Scene::clipping_planes) have cut it away
+    ///
+    /// The planes leave a convex region, so the ray is kept over a single span. If that
+    /// starts at one of the planes, the ray is traced on from there, and if the first
+    /// surface it meets faces away from it, the ray was inside an object when it crossed
+    /// the plane, and hits the plane's cap instead.
+    fn sample_clipped(&self, ray: &Ray) -> Option<IntersectionInfo> {
+        let (kept, entry_plane) = self.scene.clipping_planes.iter().fold(
+            (Interval::new(0.0, f64::INFINITY), None),
+            |(kept, entry_plane), plane| {
+                let interval = plane.kept_interval(ray);
+                if interval.get_min() > kept.get_min() {
+                    (kept.intersection(interval), Some(plane))
+                } else {
+                    (kept.intersection(interval), entry_plane)
+                }
+            },
+        );
+        if kept.is_empty() {
+            return None;
+        }
+        let start = kept.get_min();
+        let nearest = if start > 0.0 {
+            let continued = Ray {
+                origin: ray.point_at(start),
+                ..ray.clone()
+            };
+            self.nearest(&continued).map(|(object, info)| {
+                let info = IntersectionInfo {
+                    distance: info.distance + start,
+                    ..info
+                };
+                (object, info)
+            })
+        } else {
+            self.nearest(ray)
+        };
+        let is_inside = nearest
+            .as_ref()
+            .is_some_and(|(_, info)| info.normal.dot(&ray.direction) > 0.0);
+        if let Some(cap) = entry_plane
+            .filter(|_| is_inside)
+            .and_then(|plane| plane.cap_intersection(ray, start))
+        {
+            return Some(cap);
+        }
+        let (object, info) = nearest.filter(|(_, info)| info.distance <= kept.get_max())?;
+        Some(with_ray_details(object, ray, info))
+    }
+}
+
+/// `info`, where `ray` hit `object`, with its [time](IntersectionInfo::time) filled in
+/// from the ray, and its [footprint](IntersectionInfo::footprint) from the ray's
+/// differential, if it has one
+fn with_ray_details(object: &dyn Aggregate, ray: &Ray, info: IntersectionInfo) -> IntersectionInfo {
+    let footprint = ray.differential.and_then(|differential| {
+        let footprint = differential.footprint(&info.location, &info.normal)?;
+        // Surface coordinates are found by following the neighbouring rays to the
+        // surface itself, so that any parameterization is handled. If either of them
+        // hits something else there's no useful estimate and the texture is point
+        // sampled.
+        let neighbour_uv = |origin: Vec3, direction: Vec3| {
+            object
+                .intersect(&Ray::new(origin, direction))
+                .filter(|neighbour| Arc::ptr_eq(&neighbour.material, &info.material))
+                .map(|neighbour| neighbour.uv)
+        };
+        match (
+            neighbour_uv(differential.x_origin, differential.x_direction),
+            neighbour_uv(differential.y_origin, differential.y_direction),
+        ) {
+            (Some(x_uv), Some(y_uv)) => Some(Footprint {
+                duv_dx: x_uv - info.uv,
+                duv_dy: y_uv - info.uv,
+                ..footprint
+            }),
+            _ => Some(footprint),
+        }
+    });
+    IntersectionInfo {
+        footprint,
+        time: ray.time,
+        ..info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Camera, Lens, PerspectiveCamera};
+    use crate::materials::LambertianMaterial;
+    use crate::materials::Material;
+    use crate::raycasting::{ClippingPlane, Intersect, Plane, Primitive, RayDifferential, Sphere};
+
+    fn camera() -> Box<dyn Camera> {
+        Box::new(PerspectiveCamera::new(Vec3::zeros(), Lens::Pinhole))
+    }
+
+    /// Traces rays against a single plane of its own, rather than the scene's objects
+    struct PlaneSampler {
+        scene: Scene,
+        plane: Plane,
+    }
+
+    impl Sampler for PlaneSampler {
+        fn scene(&self) -> &Scene {
+            &self.scene
+        }
+
+        fn sample(&self, ray: &Ray) -> Option<IntersectionInfo> {
+            self.plane.intersect(ray)
+        }
+
+        fn is_occluded(&self, ray: &Ray, max_distance: f64) -> bool {
+            self.plane.intersect_any(ray, max_distance)
+        }
+    }
+
+    #[test]
+    fn default_sample_packet_matches_single_rays() {
+        let target = PlaneSampler {
+            scene: Scene::builder(camera()).build(),
+            plane: Plane::new(
+                Vec3::unit_z(),
+                5.0,
+                Arc::new(LambertianMaterial::new_dummy()),
+            ),
+        };
+        let rays = [
+            Ray::new(Vec3::zeros(), Vec3::unit_z()),
+            Ray::new(Vec3::zeros(), -Vec3::unit_z()),
+            Ray::new(Vec3::zeros(), Vec3::new(1.0, 0.0, 1.0)),
+        ];
+        let hits = target.sample_packet(&rays);
+        for (ray, hit) in rays.iter().zip(hits.iter()) {
+            let expected = target.sample(ray).map(|info| info.distance);
+            assert!(hit.as_ref().map(|info| info.distance) == expected);
+        }
+        assert!(hits[rays.len()..].iter().all(Option::is_none));
+        assert!(target.is_occluded(&rays[0], 6.0) && !target.is_occluded(&rays[0], 4.0));
+    }
+
+    #[test]
+    fn footprint_has_surface_coordinate_derivatives() {
+        let scene = Scene::builder(camera())
+            .with_object(Box::new(vec![Box::new(Plane::new(
+                Vec3::new(0.0, 0.0, -1.0),
+                -10.0,
+                Arc::new(LambertianMaterial::new_dummy()),
+            )) as Box<dyn Primitive>]))
+            .build();
+        let target = SceneSampler { scene: &scene };
+        let ray = Ray::new(Vec3::zeros(), Vec3::unit_z()).with_differential(RayDifferential {
+            x_origin: Vec3::zeros(),
+            x_direction: Vec3::new(0.01, 0.0, 1.0).normalize(),
+            y_origin: Vec3::zeros(),
+            y_direction: Vec3::new(0.0, 0.01, 1.0).normalize(),
+        });
+        let footprint = target.sample(&ray).unwrap().footprint.unwrap();
+        // Plane surface coordinates are distances along its tangents
+        assert!((footprint.duv_dx.dot(&footprint.duv_dx).sqrt() - 0.1).abs() < 0.000001);
+        assert!((footprint.duv_dy.dot(&footprint.duv_dy).sqrt() - 0.1).abs() < 0.000001);
+        assert!(target
+            .sample(&Ray::new(Vec3::zeros(), Vec3::unit_z()))
+            .unwrap()
+            .footprint
+            .is_none());
+    }
+
+    #[test]
+    fn spawned_rays_keep_time_of_ray() {
+        let scene = Scene::builder(camera())
+            .with_object(Box::new(vec![Box::new(Plane::new(
+                Vec3::unit_z(),
+                5.0,
+                Arc::new(LambertianMaterial::new_dummy()),
+            )) as Box<dyn Primitive>]))
+            .build();
+        let target = SceneSampler { scene: &scene };
+        let ray = Ray::new(Vec3::zeros(), Vec3::unit_z()).with_time(1.5);
+        let info = target.sample(&ray).unwrap();
+        assert!(info.time == 1.5);
+        assert!(info.spawn_ray(&-Vec3::unit_z()).time == 1.5);
+    }
+
+    fn sectioned_sphere(cap: Option<Arc<dyn Material>>) -> Scene {
+        Scene::builder(camera())
+            .with_object(Box::new(vec![Box::new(Sphere::new(
+                Vec3::zeros(),
+                1.0,
+                Arc::new(LambertianMaterial::new_dummy()),
+            )) as Box<dyn Primitive>]))
+            .with_clipping_plane(ClippingPlane::new(Vec3::zeros(), -Vec3::unit_z(), cap))
+            .build()
+    }
+
+    #[test]
+    fn clipping_plane_cuts_away_near_half_of_sphere() {
+        let scene = sectioned_sphere(None);
+        let target = SceneSampler { scene: &scene };
+        // The inside of the far half is seen through the cut
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::unit_z());
+        assert!((target.sample(&ray).unwrap().distance - 6.0).abs() < 0.000001);
+        assert!(!target.is_occluded(&ray, 5.5) && target.is_occluded(&ray, 6.5));
+        // Rays from the kept side aren't affected until they reach the plane
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), -Vec3::unit_z());
+        assert!((target.sample(&ray).unwrap().distance - 4.0).abs() < 0.000001);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.5), -Vec3::unit_z());
+        assert!(target.sample(&ray).is_none());
+        let hits = target.sample_packet(&[Ray::new(Vec3::new(0.0, 0.0, 5.0), -Vec3::unit_z())]);
+        assert!((hits[0].as_ref().unwrap().distance - 4.0).abs() < 0.000001);
+    }
+
+    #[test]
+    fn clipping_plane_cap_fills_in_cut() {
+        let cap: Arc<dyn Material> = Arc::new(LambertianMaterial::new_dummy());
+        let scene = sectioned_sphere(Some(Arc::clone(&cap)));
+        let target = SceneSampler { scene: &scene };
+        let ray = Ray::new(Vec3::new(0.5, 0.0, -5.0), Vec3::unit_z());
+        let info = target.sample(&ray).unwrap();
+        assert!((info.distance - 5.0).abs() < 0.000001);
+        assert!(Arc::ptr_eq(&info.material, &cap));
+        assert!(info.normal.dot(&ray.direction) < 0.0);
+        // Outside the sphere there's nothing to cap
+        let ray = Ray::new(Vec3::new(2.0, 0.0, -5.0), Vec3::unit_z());
+        assert!(target.sample(&ray).is_none());
+    }
 }
@@ -1,8 +1,251 @@
-use crate::math::Vec3;
+use crate::camera::Camera;
+use crate::error::VanrijnError;
+use crate::lights::{EnvironmentLight, Light, Portal, SkyGradient};
+use crate::materials::{Material, MaterialLibrary};
+use crate::media::Medium;
 
-use crate::raycasting::Aggregate;
+use crate::raycasting::{Aggregate, BoundingBox, ClippingPlane};
+use crate::validation::{SceneIssue, SceneValidator};
+
+use std::sync::Arc;
 
 pub struct Scene {
-    pub camera_location: Vec3,
+    /// The camera the scene is viewed through
+    pub camera: Box<dyn Camera>,
     pub objects: Vec<Box<dyn Aggregate>>,
+    pub environment: Box<dyn EnvironmentLight>,
+
+    /// Lights that integrators sample directly, in addition to the environment
+    pub lights: Vec<Box<dyn Light>>,
+
+    /// Openings through which the environment is sampled, if the scene is enclosed
+    pub portals: Vec<Portal>,
+
+    /// The medium filling the space between objects, such as fog, or `None` for a vacuum
+    pub medium: Option<Box<dyn Medium>>,
+
+    /// Planes that cut away part of the scene, for section views
+    pub clipping_planes: Vec<ClippingPlane>,
+
+    /// Materials that objects in the scene can share by name
+    pub materials: MaterialLibrary,
+}
+
+impl Scene {
+    /// Start building a scene viewed through `camera`
+    pub fn builder(camera: Box<dyn Camera>) -> SceneBuilder {
+        SceneBuilder {
+            scene: Scene {
+                camera,
+                objects: vec![],
+                environment: Box::new(SkyGradient::new()),
+                lights: vec![],
+                portals: vec![],
+                medium: None,
+                clipping_planes: vec![],
+                materials: MaterialLibrary::new(),
+            },
+        }
+    }
+
+    /// A box containing every object in the scene
+    ///
+    /// Unbounded objects, such as [planes](crate::raycasting::Plane), make the box infinite.
+    pub fn bounding_box(&self) -> BoundingBox {
+        self.objects
+            .iter()
+            .fold(BoundingBox::empty(), |bounds, object| {
+                bounds.union(&object.bounding_box())
+            })
+    }
+
+    /// The material called `name` in the scene's [library](Scene::materials)
+    pub fn material(&self, name: &str) -> Option<Arc<dyn Material>> {
+        self.materials.get(name)
+    }
+
+    /// Check the objects and materials in the scene for mistakes
+    ///
+    /// Returns the issues found if none of them are [errors](SceneIssue::is_error), so they
+    /// can be shown as warnings, or an [InvalidScene](VanrijnError::InvalidScene) error with
+    /// all of the issues otherwise.
+    pub fn validate(&self) -> Result<Vec<SceneIssue>, VanrijnError> {
+        let mut validator = SceneValidator::new();
+        for object in &self.objects {
+            object.validate(&mut validator);
+        }
+        for plane in &self.clipping_planes {
+            if let Some(cap) = plane.cap() {
+                validator.check_material(cap);
+            }
+        }
+        for name in self.materials.names() {
+            if let Some(material) = self.materials.get(name) {
+                validator.check_material(&material);
+            }
+        }
+        let issues = validator.into_issues();
+        if issues.iter().any(SceneIssue::is_error) {
+            Err(VanrijnError::InvalidScene(issues))
+        } else {
+            Ok(issues)
+        }
+    }
+}
+
+/// Builds a [Scene](Scene) a piece at a time
+///
+/// Scenes are lit by a [SkyGradient](SkyGradient), with no other lights and no medium, unless
+/// told otherwise.
+/// Materials are added before the objects that use them, so objects can look them up with
+/// [material()](SceneBuilder::material).
+pub struct SceneBuilder {
+    scene: Scene,
+}
+
+impl SceneBuilder {
+    /// Add `material` to the scene's library as `name`, replacing any material already
+    /// called that
+    pub fn with_material(mut self, name: &str, material: Arc<dyn Material>) -> SceneBuilder {
+        self.scene.materials.insert(name, material);
+        self
+    }
+
+    /// The material added as `name`
+    ///
+    /// # Panics
+    ///
+    /// If no material called `name` has been added.
+    pub fn material(&self, name: &str) -> Arc<dyn Material> {
+        self.scene
+            .material(name)
+            .unwrap_or_else(|| panic!("No material called \"{}\".", name))
+    }
+
+    pub fn with_object(mut self, object: Box<dyn Aggregate>) -> SceneBuilder {
+        self.scene.objects.push(object);
+        self
+    }
+
+    pub fn with_environment(mut self, environment: Box<dyn EnvironmentLight>) -> SceneBuilder {
+        self.scene.environment = environment;
+        self
+    }
+
+    pub fn with_light(mut self, light: Box<dyn Light>) -> SceneBuilder {
+        self.scene.lights.push(light);
+        self
+    }
+
+    pub fn with_portal(mut self, portal: Portal) -> SceneBuilder {
+        self.scene.portals.push(portal);
+        self
+    }
+
+    pub fn with_medium(mut self, medium: Box<dyn Medium>) -> SceneBuilder {
+        self.scene.medium = Some(medium);
+        self
+    }
+
+    pub fn with_clipping_plane(mut self, plane: ClippingPlane) -> SceneBuilder {
+        self.scene.clipping_planes.push(plane);
+        self
+    }
+
+    pub fn build(self) -> Scene {
+        self.scene
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::camera::{Lens, PerspectiveCamera};
+    use crate::materials::LambertianMaterial;
+    use crate::math::Vec3;
+    use crate::raycasting::{Primitive, Ray, Sphere};
+
+    fn camera() -> Box<dyn Camera> {
+        Box::new(PerspectiveCamera::new(Vec3::zeros(), Lens::Pinhole))
+    }
+
+    #[test]
+    fn objects_share_named_material() {
+        let builder = Scene::builder(camera())
+            .with_material("dummy", Arc::new(LambertianMaterial::new_dummy()));
+        let spheres: Vec<Box<dyn Primitive>> = (0..2)
+            .map(|i| {
+                Box::new(Sphere::new(
+                    Vec3::new(i as f64 * 3.0, 0.0, 5.0),
+                    1.0,
+                    builder.material("dummy"),
+                )) as Box<dyn Primitive>
+            })
+            .collect();
+        let target = builder.with_object(Box::new(spheres)).build();
+        let material = target.material("dummy").unwrap();
+        for x in [0.0, 3.0].iter() {
+            let ray = Ray::new(Vec3::new(*x, 0.0, 0.0), Vec3::unit_z());
+            let info = target.objects[0].intersect(&ray).unwrap();
+            assert!(Arc::ptr_eq(&info.material, &material));
+        }
+    }
+
+    #[test]
+    fn bounding_box_contains_all_objects() {
+        let material: Arc<dyn Material> = Arc::new(LambertianMaterial::new_dummy());
+        let sphere_at = |x: f64| -> Box<dyn Aggregate> {
+            Box::new(vec![Box::new(Sphere::new(
+                Vec3::new(x, 0.0, 5.0),
+                1.0,
+                Arc::clone(&material),
+            )) as Box<dyn Primitive>])
+        };
+        let target = Scene::builder(camera())
+            .with_object(sphere_at(0.0))
+            .with_object(sphere_at(3.0))
+            .build();
+        let bounds = target.bounding_box();
+        assert!(bounds.contains_point(Vec3::new(-0.9, 0.0, 5.0)));
+        assert!(bounds.contains_point(Vec3::new(3.9, 0.9, 5.0)));
+        assert!(!bounds.contains_point(Vec3::new(4.1, 0.0, 5.0)));
+    }
+
+    #[test]
+    fn validate_separates_warnings_from_errors() {
+        let sphere = |radius: f64, material: LambertianMaterial| -> Box<dyn Aggregate> {
+            Box::new(vec![Box::new(Sphere::new(
+                Vec3::new(0.0, 0.0, 5.0),
+                radius,
+                Arc::new(material),
+            )) as Box<dyn Primitive>])
+        };
+        let target = Scene::builder(camera())
+            .with_object(sphere(0.0, LambertianMaterial::new_dummy()))
+            .build();
+        assert!(matches!(
+            target.validate().as_deref(),
+            Ok([SceneIssue::ZeroRadiusSphere { .. }])
+        ));
+        let target = Scene::builder(camera())
+            .with_object(sphere(
+                1.0,
+                LambertianMaterial {
+                    diffuse_strength: -0.5,
+                    ..LambertianMaterial::new_dummy()
+                },
+            ))
+            .build();
+        assert!(matches!(
+            target.validate(),
+            Err(VanrijnError::InvalidScene(issues)) if issues.len() == 1
+        ));
+    }
+
+    #[test]
+    #[should_panic]
+    fn missing_material_panics() {
+        Scene::builder(camera()).material("missing");
+    }
 }
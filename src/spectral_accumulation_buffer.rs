@@ -0,0 +1,307 @@
+use crate::accumulation_buffer::PhotonAccumulator;
+use crate::colour::cie_1931::colour_matching_functions;
+use crate::colour::{ColourXyz, Photon, LONGEST_VISIBLE_WAVELENGTH, SHORTEST_VISIBLE_WAVELENGTH};
+use crate::error::VanrijnError;
+use crate::math::Vec3;
+use crate::util::Tile;
+
+use std::fs::File;
+use std::io::{BufWriter, Error, Write};
+use std::path::Path;
+
+/// The sums kept for each band of each pixel
+#[derive(Clone, Copy, Debug, Default)]
+struct BandSums {
+    /// Sum of the weighted intensities of photons in the band
+    intensity: f64,
+
+    /// Sum of the weighted squares of the intensities of photons in the band
+    intensity_squared: f64,
+}
+
+/// Accumulates samples into wavelength bands, rather than into an
+/// [XYZ colour](crate::accumulation_buffer::AccumulationBuffer), for hyperspectral images
+///
+/// The visible range is split into `band_count` bands of equal width, and each pixel keeps
+/// an estimate of the mean spectral radiance in each band, along with its variance, so the
+/// noise at each wavelength can be judged. Photons are expected to have their intensities
+/// divided by the probability density of their wavelength, as the renderer does; each band
+/// then averages the photons that fell into it over all of the pixel's samples, which is
+/// unbiased however the wavelengths were chosen.
+#[derive(Clone, Debug)]
+pub struct SpectralAccumulationBuffer {
+    width: usize,
+    height: usize,
+    band_count: usize,
+    weights: Vec<f64>,
+    squared_weights: Vec<f64>,
+    bands: Vec<BandSums>,
+
+    /// Number of samples dropped because they were NaN or infinite
+    invalid_samples: u64,
+}
+
+impl SpectralAccumulationBuffer {
+    pub fn new(width: usize, height: usize, band_count: usize) -> SpectralAccumulationBuffer {
+        assert!(band_count > 0);
+        SpectralAccumulationBuffer {
+            width,
+            height,
+            band_count,
+            weights: vec![0.0; width * height],
+            squared_weights: vec![0.0; width * height],
+            bands: vec![Default::default(); width * height * band_count],
+            invalid_samples: 0,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The number of samples that were dropped because their intensity or weight was NaN
+    /// or infinite
+    pub fn invalid_samples(&self) -> u64 {
+        self.invalid_samples
+    }
+
+    pub fn band_count(&self) -> usize {
+        self.band_count
+    }
+
+    fn band_width(&self) -> f64 {
+        (LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH) / self.band_count as f64
+    }
+
+    /// The shortest and longest wavelengths in `band`, in nanometres
+    pub fn band_wavelengths(&self, band: usize) -> (f64, f64) {
+        let start = SHORTEST_VISIBLE_WAVELENGTH + band as f64 * self.band_width();
+        (start, start + self.band_width())
+    }
+
+    /// The band containing `wavelength`, if it's visible
+    fn band_at(&self, wavelength: f64) -> Option<usize> {
+        if !(SHORTEST_VISIBLE_WAVELENGTH..=LONGEST_VISIBLE_WAVELENGTH).contains(&wavelength) {
+            return None;
+        }
+        let band = ((wavelength - SHORTEST_VISIBLE_WAVELENGTH) / self.band_width()) as usize;
+        Some(band.min(self.band_count - 1))
+    }
+
+    fn pixel_index(&self, row: usize, column: usize) -> usize {
+        assert!(row < self.height && column < self.width);
+        row * self.width + column
+    }
+
+    /// The mean spectral radiance in `band` at the pixel, per nanometre
+    pub fn band_value(&self, row: usize, column: usize, band: usize) -> f64 {
+        let pixel = self.pixel_index(row, column);
+        if self.weights[pixel] == 0.0 {
+            return 0.0;
+        }
+        self.bands[pixel * self.band_count + band].intensity
+            / (self.weights[pixel] * self.band_width())
+    }
+
+    /// The variance of the estimate given by [band_value()](Self::band_value)
+    ///
+    /// This shrinks as more samples are added, in proportion to the effective number of
+    /// samples given their weights.
+    pub fn band_variance(&self, row: usize, column: usize, band: usize) -> f64 {
+        let pixel = self.pixel_index(row, column);
+        let weight = self.weights[pixel];
+        if weight == 0.0 {
+            return 0.0;
+        }
+        let mean = self.band_value(row, column, band);
+        let mean_square = self.bands[pixel * self.band_count + band].intensity_squared
+            / (weight * self.band_width() * self.band_width());
+        let sample_variance = (mean_square - mean * mean).max(0.0);
+        sample_variance * self.squared_weights[pixel] / (weight * weight)
+    }
+
+    /// The colour of the pixel, by integrating its bands against the colour matching
+    /// functions
+    ///
+    /// This gives the same result as an [AccumulationBuffer] of the same samples, apart
+    /// from the detail lost within each band.
+    ///
+    /// [AccumulationBuffer]: crate::accumulation_buffer::AccumulationBuffer
+    pub fn colour(&self, row: usize, column: usize) -> ColourXyz {
+        let first = SHORTEST_VISIBLE_WAVELENGTH as usize;
+        let last = LONGEST_VISIBLE_WAVELENGTH as usize;
+        let total = (first..=last)
+            .map(|wavelength| {
+                let wavelength = wavelength as f64;
+                colour_matching_functions(wavelength)
+                    * self.band_value(row, column, self.band_at(wavelength).unwrap())
+            })
+            .fold(Vec3::zeros(), |a, b| a + b);
+        // Each band value is per nanometre, and the sum covers the whole visible range
+        ColourXyz {
+            values: total
+                * ((LONGEST_VISIBLE_WAVELENGTH - SHORTEST_VISIBLE_WAVELENGTH)
+                    / (last - first + 1) as f64),
+        }
+    }
+
+    /// Add `src`, which covers `tile` of this image, to the image
+    ///
+    /// See [merge_tile()](crate::accumulation_buffer::AccumulationBuffer::merge_tile).
+    pub fn merge_tile(&mut self, tile: &Tile, src: &SpectralAccumulationBuffer) {
+        assert!(tile.width() == src.width());
+        assert!(tile.height() == src.height());
+        assert!(self.band_count == src.band_count);
+        self.invalid_samples += src.invalid_samples;
+        for i in 0..tile.height() {
+            for j in 0..tile.width() {
+                let src_pixel = src.pixel_index(i, j);
+                let dst_pixel = self.pixel_index(tile.start_row + i, tile.start_column + j);
+                self.weights[dst_pixel] += src.weights[src_pixel];
+                self.squared_weights[dst_pixel] += src.squared_weights[src_pixel];
+                for band in 0..self.band_count {
+                    let src_band = src.bands[src_pixel * src.band_count + band];
+                    let dst_band = &mut self.bands[dst_pixel * self.band_count + band];
+                    dst_band.intensity += src_band.intensity;
+                    dst_band.intensity_squared += src_band.intensity_squared;
+                }
+            }
+        }
+    }
+
+    /// Write the band values as an ENVI image, which most remote sensing and spectral
+    /// imaging software can read
+    ///
+    /// The data are written to `filename` as 32-bit floats, one band after another, and
+    /// the header describing them, including the centre wavelength of each band, is
+    /// written alongside with ".hdr" added to the name.
+    pub fn write_envi(&self, filename: &Path) -> Result<(), VanrijnError> {
+        self.write_envi_files(filename)
+            .map_err(VanrijnError::image(filename))
+    }
+
+    fn write_envi_files(&self, filename: &Path) -> Result<(), Error> {
+        let mut data = BufWriter::new(File::create(filename)?);
+        for band in 0..self.band_count {
+            for row in 0..self.height {
+                for column in 0..self.width {
+                    data.write_all(&(self.band_value(row, column, band) as f32).to_le_bytes())?;
+                }
+            }
+        }
+        data.flush()?;
+        let mut header_name = filename.as_os_str().to_owned();
+        header_name.push(".hdr");
+        let mut header = BufWriter::new(File::create(header_name)?);
+        writeln!(header, "ENVI")?;
+        writeln!(header, "samples = {}", self.width)?;
+        writeln!(header, "lines = {}", self.height)?;
+        writeln!(header, "bands = {}", self.band_count)?;
+        writeln!(header, "header offset = 0")?;
+        writeln!(header, "file type = ENVI Standard")?;
+        writeln!(header, "data type = 4")?;
+        writeln!(header, "interleave = bsq")?;
+        writeln!(header, "byte order = 0")?;
+        writeln!(header, "wavelength units = Nanometers")?;
+        let centres: Vec<String> = (0..self.band_count)
+            .map(|band| {
+                let (start, end) = self.band_wavelengths(band);
+                format!("{}", 0.5 * (start + end))
+            })
+            .collect();
+        writeln!(header, "wavelength = {{{}}}", centres.join(", "))?;
+        header.flush()
+    }
+}
+
+impl PhotonAccumulator for SpectralAccumulationBuffer {
+    fn update_pixel(&mut self, row: usize, column: usize, photon: &Photon, weight: f64) {
+        if !photon.intensity.is_finite() || !weight.is_finite() {
+            self.invalid_samples += 1;
+            return;
+        }
+        let pixel = self.pixel_index(row, column);
+        self.weights[pixel] += weight;
+        self.squared_weights[pixel] += weight * weight;
+        if let Some(band) = self.band_at(photon.wavelength) {
+            let sums = &mut self.bands[pixel * self.band_count + band];
+            sums.intensity += weight * photon.intensity;
+            sums.intensity_squared += weight * photon.intensity * photon.intensity;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::accumulation_buffer::AccumulationBuffer;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// A photon of a flat spectrum with `radiance` per nanometre, weighted as the renderer
+    /// weights its samples
+    fn photon(wavelength: f64, radiance: f64) -> Photon {
+        Photon {
+            wavelength,
+            intensity: radiance * Photon::random_wavelength_pdf(wavelength),
+        }
+    }
+
+    #[test]
+    fn bands_estimate_spectral_radiance() {
+        let mut target = SpectralAccumulationBuffer::new(2, 1, 4);
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20000 {
+            let wavelength = Photon::random_wavelength(&mut rng).wavelength;
+            // Brighter at long wavelengths
+            let radiance = if wavelength > 560.0 { 2.0 } else { 1.0 };
+            target.update_pixel(0, 1, &photon(wavelength, radiance), 1.0);
+        }
+        assert!((target.band_value(0, 1, 0) - 1.0).abs() < 0.05);
+        assert!((target.band_value(0, 1, 3) - 2.0).abs() < 0.1);
+        assert!(target.band_value(0, 0, 0) == 0.0);
+        // All the light in a band is of the same brightness, but the band only catches
+        // some of the photons
+        assert!(target.band_variance(0, 1, 0) > 0.0);
+        assert!(target.band_variance(0, 1, 0) < 0.001);
+    }
+
+    #[test]
+    fn colour_matches_xyz_accumulation() {
+        let mut target = SpectralAccumulationBuffer::new(1, 1, 36);
+        let mut expected = AccumulationBuffer::new(1, 1);
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20000 {
+            let photon = photon(Photon::random_wavelength(&mut rng).wavelength, 1.0);
+            target.update_pixel(0, 0, &photon, 0.5);
+            expected.update_pixel(0, 0, &photon, 0.5);
+        }
+        let expected = expected.colour(0, 0);
+        let actual = target.colour(0, 0);
+        assert!((actual.values - expected.values).norm() < 0.02 * expected.values.norm());
+    }
+
+    #[test]
+    fn merging_tiles_combines_samples() {
+        let mut target = SpectralAccumulationBuffer::new(3, 3, 2);
+        let mut tile_image = SpectralAccumulationBuffer::new(2, 1, 2);
+        tile_image.update_pixel(0, 1, &photon(400.0, 1.0), 1.0);
+        target.update_pixel(1, 2, &photon(700.0, 3.0), 1.0);
+        let tile = Tile {
+            start_column: 1,
+            end_column: 3,
+            start_row: 1,
+            end_row: 2,
+        };
+        target.merge_tile(&tile, &tile_image);
+        // Each sample only lands in one band, but each band covers half the spectrum
+        assert!((target.band_value(1, 2, 0) - 1.0).abs() < 0.000_001);
+        assert!((target.band_value(1, 2, 1) - 3.0).abs() < 0.000_001);
+    }
+}
@@ -0,0 +1,128 @@
+//! Counts of the work done while rendering
+//!
+//! Counting is only done when the crate is built with the `statistics` feature. Without it
+//! the counting functions do nothing, and every count reads as zero, so they cost nothing
+//! in normal renders.
+//!
+//! Each thread keeps its own counts, so counting doesn't need any synchronization. The
+//! renderers [take](take_thread_statistics) them after each tile, and report the totals for
+//! each pass in [PassStatistics](crate::progressive_renderer::PassStatistics).
+
+#[cfg(feature = "statistics")]
+use std::cell::Cell;
+
+/// Whether the crate was built to gather statistics
+pub const ENABLED: bool = cfg!(feature = "statistics");
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RayStatistics {
+    /// Rays for which the nearest intersection was found
+    pub rays_cast: u64,
+
+    /// Rays that were only tested for whether anything blocked them
+    pub shadow_rays: u64,
+
+    /// Nodes of bounding volume hierarchies whose bounds were tested
+    pub bvh_nodes_visited: u64,
+
+    /// Ray-triangle intersection tests
+    pub triangle_tests: u64,
+}
+
+impl RayStatistics {
+    pub fn add(&self, other: &RayStatistics) -> RayStatistics {
+        RayStatistics {
+            rays_cast: self.rays_cast + other.rays_cast,
+            shadow_rays: self.shadow_rays + other.shadow_rays,
+            bvh_nodes_visited: self.bvh_nodes_visited + other.bvh_nodes_visited,
+            triangle_tests: self.triangle_tests + other.triangle_tests,
+        }
+    }
+}
+
+#[cfg(feature = "statistics")]
+thread_local! {
+    static THREAD_STATISTICS: Cell<RayStatistics> = Cell::new(RayStatistics::default());
+}
+
+#[cfg(feature = "statistics")]
+#[inline]
+fn count<F: FnOnce(&mut RayStatistics)>(f: F) {
+    THREAD_STATISTICS.with(|statistics| {
+        let mut counts = statistics.get();
+        f(&mut counts);
+        statistics.set(counts);
+    });
+}
+
+#[cfg(not(feature = "statistics"))]
+#[inline(always)]
+fn count<F: FnOnce(&mut RayStatistics)>(_f: F) {}
+
+#[inline]
+pub fn count_ray_cast() {
+    count(|counts| counts.rays_cast += 1);
+}
+
+#[inline]
+pub fn count_shadow_ray() {
+    count(|counts| counts.shadow_rays += 1);
+}
+
+#[inline]
+pub fn count_bvh_node_visited() {
+    count(|counts| counts.bvh_nodes_visited += 1);
+}
+
+#[inline]
+pub fn count_triangle_test() {
+    count(|counts| counts.triangle_tests += 1);
+}
+
+/// The counts gathered on the calling thread since they were last taken, without resetting
+/// them
+pub fn thread_statistics() -> RayStatistics {
+    #[cfg(feature = "statistics")]
+    {
+        THREAD_STATISTICS.with(|statistics| statistics.get())
+    }
+    #[cfg(not(feature = "statistics"))]
+    {
+        RayStatistics::default()
+    }
+}
+
+/// The counts gathered on the calling thread since they were last taken, resetting them to
+/// zero
+pub fn take_thread_statistics() -> RayStatistics {
+    #[cfg(feature = "statistics")]
+    {
+        THREAD_STATISTICS.with(|statistics| statistics.replace(RayStatistics::default()))
+    }
+    #[cfg(not(feature = "statistics"))]
+    {
+        RayStatistics::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_are_reset_when_taken() {
+        take_thread_statistics();
+        count_ray_cast();
+        count_ray_cast();
+        count_triangle_test();
+        let counts = take_thread_statistics();
+        if ENABLED {
+            assert!(counts.rays_cast == 2);
+            assert!(counts.triangle_tests == 1);
+            assert!(counts.shadow_rays == 0);
+        } else {
+            assert!(counts == RayStatistics::default());
+        }
+        assert!(take_thread_statistics() == RayStatistics::default());
+    }
+}
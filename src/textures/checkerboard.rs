@@ -0,0 +1,129 @@
+use crate::colour::Spectrum;
+use crate::math::Vec2;
+use crate::raycasting::Footprint;
+
+use super::Texture;
+
+/// Alternating squares of two textures
+///
+/// The square with its corner at the origin of surface coordinates is `even`. Lookups
+/// over a footprint average the two textures by how much of each the footprint covers,
+/// which stops distant checkerboards from shimmering.
+#[derive(Debug)]
+pub struct Checkerboard<A: Texture = Spectrum, B: Texture = Spectrum> {
+    pub even: A,
+    pub odd: B,
+
+    /// The width of each square, in surface coordinates
+    pub square_size: f64,
+}
+
+impl<A: Texture, B: Texture> Checkerboard<A, B> {
+    pub fn new(even: A, odd: B, square_size: f64) -> Checkerboard<A, B> {
+        Checkerboard {
+            even,
+            odd,
+            square_size,
+        }
+    }
+
+    fn is_odd(&self, uv: &Vec2) -> bool {
+        let square = (uv.x() / self.square_size).floor() + (uv.y() / self.square_size).floor();
+        square.rem_euclid(2.0) == 1.0
+    }
+}
+
+/// The integral from zero to `x` of a function that is one over odd intervals and zero
+/// over even ones
+fn odd_integral(x: f64) -> f64 {
+    let half = x * 0.5;
+    half.floor() + 2.0 * (half - half.floor() - 0.5).max(0.0)
+}
+
+/// The fraction of the interval `centre ± half_width` that is covered by odd intervals
+fn odd_fraction(centre: f64, half_width: f64) -> f64 {
+    (odd_integral(centre + half_width) - odd_integral(centre - half_width)) / (2.0 * half_width)
+}
+
+impl<A: Texture, B: Texture> Texture for Checkerboard<A, B> {
+    fn value(&self, uv: &Vec2, wavelength: f64) -> f64 {
+        if self.is_odd(uv) {
+            self.odd.value(uv, wavelength)
+        } else {
+            self.even.value(uv, wavelength)
+        }
+    }
+
+    fn filtered_value(&self, uv: &Vec2, footprint: &Footprint, wavelength: f64) -> f64 {
+        // Box filter the pattern over the bounding square of the footprint
+        let (s, t) = (uv.x() / self.square_size, uv.y() / self.square_size);
+        let ds = footprint.duv_dx.x().abs().max(footprint.duv_dy.x().abs()) / self.square_size;
+        let dt = footprint.duv_dx.y().abs().max(footprint.duv_dy.y().abs()) / self.square_size;
+        if (s - ds).floor() == (s + ds).floor() && (t - dt).floor() == (t + dt).floor() {
+            return if self.is_odd(uv) {
+                self.odd.filtered_value(uv, footprint, wavelength)
+            } else {
+                self.even.filtered_value(uv, footprint, wavelength)
+            };
+        }
+        let fraction = |centre: f64, half_width: f64| {
+            if half_width > 0.0 {
+                odd_fraction(centre, half_width)
+            } else {
+                centre.floor().rem_euclid(2.0)
+            }
+        };
+        let (s_odd, t_odd) = (fraction(s, ds), fraction(t, dt));
+        // A square is odd when exactly one of its coordinates is
+        let odd_area = s_odd + t_odd - 2.0 * s_odd * t_odd;
+        self.even.filtered_value(uv, footprint, wavelength) * (1.0 - odd_area)
+            + self.odd.filtered_value(uv, footprint, wavelength) * odd_area
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::math::Vec3;
+
+    fn footprint(width: f64) -> Footprint {
+        Footprint {
+            dp_dx: Vec3::zeros(),
+            dp_dy: Vec3::zeros(),
+            duv_dx: Vec2::new(width, 0.0),
+            duv_dy: Vec2::new(0.0, width),
+            x_direction: Vec3::unit_z(),
+            y_direction: Vec3::unit_z(),
+        }
+    }
+
+    fn target() -> Checkerboard {
+        Checkerboard::new(Spectrum::grey(1.0), Spectrum::grey(0.0), 0.5)
+    }
+
+    #[test]
+    fn squares_alternate() {
+        let target = target();
+        assert!(target.value(&Vec2::new(0.25, 0.25), 550.0) == 1.0);
+        assert!(target.value(&Vec2::new(0.75, 0.25), 550.0) == 0.0);
+        assert!(target.value(&Vec2::new(0.75, 0.75), 550.0) == 1.0);
+        assert!(target.value(&Vec2::new(-0.25, 0.25), 550.0) == 0.0);
+    }
+
+    #[test]
+    fn small_footprint_is_point_sampled() {
+        let target = target();
+        let uv = Vec2::new(0.75, 0.25);
+        assert!(target.filtered_value(&uv, &footprint(0.01), 550.0) == 0.0);
+    }
+
+    #[test]
+    fn large_footprint_averages_squares() {
+        let target = target();
+        for &(u, v) in [(0.25, 0.25), (0.6, 0.1), (3.3, -1.7)].iter() {
+            let value = target.filtered_value(&Vec2::new(u, v), &footprint(5.0), 550.0);
+            assert!((value - 0.5).abs() < 0.05);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use crate::math::Vec2;
+
+use super::Texture;
+
+/// A grey ramp from zero at `start` to one at `end`, in surface coordinates
+///
+/// The value is constant across the ramp, and clamped beyond its ends. It's most useful
+/// as the factor of a [Mix](super::Mix).
+#[derive(Debug)]
+pub struct Gradient {
+    pub start: Vec2,
+    pub end: Vec2,
+}
+
+impl Gradient {
+    pub fn new(start: Vec2, end: Vec2) -> Gradient {
+        Gradient { start, end }
+    }
+}
+
+impl Texture for Gradient {
+    fn value(&self, uv: &Vec2, _wavelength: f64) -> f64 {
+        let axis = self.end - self.start;
+        ((*uv - self.start).dot(&axis) / axis.dot(&axis)).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramps_between_ends() {
+        let target = Gradient::new(Vec2::new(0.0, 0.0), Vec2::new(0.0, 2.0));
+        assert!(target.value(&Vec2::new(5.0, 0.0), 550.0) == 0.0);
+        assert!((target.value(&Vec2::new(-1.0, 0.5), 550.0) - 0.25).abs() < 0.000000001);
+        assert!(target.value(&Vec2::new(0.0, 3.0), 550.0) == 1.0);
+        assert!(target.value(&Vec2::new(0.0, -3.0), 550.0) == 0.0);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::colour::{ColourRgbF, Spectrum};
+use crate::error::VanrijnError;
+use crate::image::ImageRgbF;
+use crate::math::Vec2;
+use crate::raycasting::Footprint;
+
+use super::{MipMap, MipMapFilter, Texture};
+
+use std::path::Path;
+
+/// A texture defined by a linear RGB image
+///
+/// The image covers the unit square in surface coordinates, with `v` pointing up the
+/// image, and repeats outside of it. Point lookups are bilinearly filtered, and lookups
+/// over a footprint use a [MipMap](MipMap) of the image.
+#[derive(Debug)]
+pub struct ImageTexture {
+    mip_map: MipMap,
+    filter: MipMapFilter,
+}
+
+impl ImageTexture {
+    pub fn new(image: ImageRgbF) -> ImageTexture {
+        ImageTexture {
+            mip_map: MipMap::new(image),
+            filter: MipMapFilter::Ewa,
+        }
+    }
+
+    /// Load an sRGB PNG image to use as a texture
+    pub fn read_png(filename: &Path) -> Result<ImageTexture, VanrijnError> {
+        Ok(ImageTexture::new(ImageRgbF::read_png(filename)?))
+    }
+
+    /// Use `filter` for lookups over a footprint, instead of the default of
+    /// [MipMapFilter::Ewa](MipMapFilter::Ewa)
+    pub fn with_filter(self, filter: MipMapFilter) -> ImageTexture {
+        ImageTexture { filter, ..self }
+    }
+
+    /// The bilinearly-interpolated colour at surface coordinates `uv`
+    pub fn colour_at(&self, uv: &Vec2) -> ColourRgbF {
+        self.mip_map.bilinear(0, uv)
+    }
+
+    /// The colour averaged over `footprint` around surface coordinates `uv`
+    pub fn filtered_colour_at(&self, uv: &Vec2, footprint: &Footprint) -> ColourRgbF {
+        self.mip_map
+            .filter(self.filter, uv, &footprint.duv_dx, &footprint.duv_dy)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, uv: &Vec2, wavelength: f64) -> f64 {
+        Spectrum::reflection_from_linear_rgb(&self.colour_at(uv))
+            .intensity_at_wavelength(wavelength)
+    }
+
+    fn filtered_value(&self, uv: &Vec2, footprint: &Footprint, wavelength: f64) -> f64 {
+        Spectrum::reflection_from_linear_rgb(&self.filtered_colour_at(uv, footprint))
+            .intensity_at_wavelength(wavelength)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::math::Vec3;
+
+    fn checkerboard() -> ImageTexture {
+        let mut image = ImageRgbF::new(2, 2);
+        image.set_colour(0, 0, ColourRgbF::new(1.0, 1.0, 1.0));
+        image.set_colour(1, 1, ColourRgbF::new(1.0, 1.0, 1.0));
+        ImageTexture::new(image)
+    }
+
+    fn nearly_equal(a: &ColourRgbF, b: &Vec3) -> bool {
+        (a.values - *b).norm() < 0.000000001
+    }
+
+    #[test]
+    fn pixel_centres_return_pixel_colour() {
+        let target = checkerboard();
+        // Row 0 is the top of the image, which is v = 1
+        assert!(nearly_equal(
+            &target.colour_at(&Vec2::new(0.25, 0.75)),
+            &Vec3::new(1.0, 1.0, 1.0)
+        ));
+        assert!(nearly_equal(
+            &target.colour_at(&Vec2::new(0.75, 0.75)),
+            &Vec3::zeros()
+        ));
+    }
+
+    #[test]
+    fn lookup_between_pixels_is_interpolated() {
+        let target = checkerboard();
+        assert!(nearly_equal(
+            &target.colour_at(&Vec2::new(0.5, 0.75)),
+            &Vec3::new(0.5, 0.5, 0.5)
+        ));
+    }
+
+    #[test]
+    fn texture_repeats() {
+        let target = checkerboard();
+        assert!(nearly_equal(
+            &target.colour_at(&Vec2::new(2.25, -0.25)),
+            &target.colour_at(&Vec2::new(0.25, 0.75)).values
+        ));
+    }
+
+    #[test]
+    fn filtered_lookup_averages_over_footprint() {
+        let target = checkerboard();
+        let footprint = Footprint {
+            dp_dx: Vec3::zeros(),
+            dp_dy: Vec3::zeros(),
+            duv_dx: Vec2::new(1.0, 0.0),
+            duv_dy: Vec2::new(0.0, 1.0),
+            x_direction: Vec3::unit_z(),
+            y_direction: Vec3::unit_z(),
+        };
+        assert!(nearly_equal(
+            &target.filtered_colour_at(&Vec2::new(0.25, 0.75), &footprint),
+            &Vec3::new(0.5, 0.5, 0.5)
+        ));
+    }
+}
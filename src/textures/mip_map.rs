@@ -0,0 +1,326 @@
+use crate::colour::ColourRgbF;
+use crate::image::ImageRgbF;
+use crate::math::Vec2;
+
+/// How a [MipMap](MipMap) is averaged over a footprint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MipMapFilter {
+    /// Blend bilinear lookups in the two levels nearest the size of the footprint
+    ///
+    /// This is cheap, but the footprint is treated as square, so surfaces seen at a
+    /// glancing angle are blurred more than they need to be.
+    Trilinear,
+
+    /// Weight the texels under the elliptical footprint with a Gaussian
+    ///
+    /// See P. Heckbert, "Fundamentals of Texture Mapping and Image Warping", 1989.
+    Ewa,
+}
+
+/// Ellipses are made less eccentric than this, to bound the number of texels averaged
+const MAX_ANISOTROPY: f64 = 8.0;
+
+/// Falloff of the Gaussian used by [MipMapFilter::Ewa](MipMapFilter::Ewa)
+const EWA_ALPHA: f64 = 2.0;
+
+/// A texture image along with successively halved copies of it
+///
+/// Looking a texture up at a single point aliases when a pixel covers many texels, which
+/// shows up as shimmering on distant detailed surfaces such as checkerboard floors. The
+/// smaller levels hold the image already averaged over larger areas, so any footprint can
+/// be filtered by reading only a few texels of the right level.
+///
+/// Like [ImageTexture](super::ImageTexture), the image covers the unit square with `v`
+/// pointing up the image, and repeats outside of it.
+#[derive(Debug)]
+pub struct MipMap {
+    levels: Vec<ImageRgbF>,
+}
+
+/// Half the size of `image`, rounded up, with each pixel the average of the 2x2 block it
+/// covers; blocks at odd edges wrap around, since the texture repeats
+fn downsample(image: &ImageRgbF) -> ImageRgbF {
+    let (width, height) = (image.get_width(), image.get_height());
+    let mut result = ImageRgbF::new(width.div_ceil(2), height.div_ceil(2));
+    for row in 0..result.get_height() {
+        for column in 0..result.get_width() {
+            let mut sum = ColourRgbF::new(0.0, 0.0, 0.0);
+            for (dr, dc) in [(0, 0), (0, 1), (1, 0), (1, 1)].iter() {
+                sum = sum + image.get_colour((2 * row + dr) % height, (2 * column + dc) % width);
+            }
+            result.set_colour(row, column, sum * 0.25);
+        }
+    }
+    result
+}
+
+fn lerp(a: ColourRgbF, b: ColourRgbF, t: f64) -> ColourRgbF {
+    a * (1.0 - t) + b * t
+}
+
+impl MipMap {
+    pub fn new(image: ImageRgbF) -> MipMap {
+        assert!(image.get_width() > 0 && image.get_height() > 0);
+        let mut levels = vec![image];
+        loop {
+            let last = levels.last().unwrap();
+            if last.get_width() == 1 && last.get_height() == 1 {
+                break;
+            }
+            let next = downsample(last);
+            levels.push(next);
+        }
+        MipMap { levels }
+    }
+
+    /// The number of levels, including the full-size image
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// The image at `level`, where level 0 is the full-size image
+    pub fn level(&self, level: usize) -> &ImageRgbF {
+        &self.levels[level]
+    }
+
+    /// The texel at `row` and `column` of `level`, wrapping around outside the image
+    fn texel(&self, level: usize, row: i64, column: i64) -> ColourRgbF {
+        let image = &self.levels[level];
+        image.get_colour(
+            row.rem_euclid(image.get_height() as i64) as usize,
+            column.rem_euclid(image.get_width() as i64) as usize,
+        )
+    }
+
+    /// Position of `uv` in the texels of `level`, with texel centres at integer
+    /// coordinates
+    fn texel_position(&self, level: usize, uv: &Vec2) -> (f64, f64) {
+        let image = &self.levels[level];
+        (
+            uv.x().rem_euclid(1.0) * image.get_width() as f64 - 0.5,
+            (1.0 - uv.y().rem_euclid(1.0)) * image.get_height() as f64 - 0.5,
+        )
+    }
+
+    /// The level whose texels are `width` across, in surface coordinates, as a fraction
+    /// between the two nearest levels
+    fn level_for_width(&self, width: f64) -> f64 {
+        let size = self.levels[0].get_width().max(self.levels[0].get_height()) as f64;
+        (width * size)
+            .log2()
+            .max(0.0)
+            .min((self.levels.len() - 1) as f64)
+    }
+
+    /// The bilinearly-interpolated colour of `level` at surface coordinates `uv`
+    pub fn bilinear(&self, level: usize, uv: &Vec2) -> ColourRgbF {
+        let (x, y) = self.texel_position(level, uv);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (column, row) = (x0 as i64, y0 as i64);
+        lerp(
+            lerp(
+                self.texel(level, row, column),
+                self.texel(level, row, column + 1),
+                fx,
+            ),
+            lerp(
+                self.texel(level, row + 1, column),
+                self.texel(level, row + 1, column + 1),
+                fx,
+            ),
+            fy,
+        )
+    }
+
+    /// The colour averaged over a square `width` across, in surface coordinates, centred
+    /// on `uv`
+    pub fn trilinear(&self, uv: &Vec2, width: f64) -> ColourRgbF {
+        let level = self.level_for_width(width);
+        let lower = level.floor() as usize;
+        if lower + 1 >= self.levels.len() {
+            return self.bilinear(lower, uv);
+        }
+        lerp(
+            self.bilinear(lower, uv),
+            self.bilinear(lower + 1, uv),
+            level - lower as f64,
+        )
+    }
+
+    /// The colour averaged over the ellipse centred on `uv` with axes `duv_dx` and
+    /// `duv_dy`, in surface coordinates
+    pub fn ewa(&self, uv: &Vec2, duv_dx: &Vec2, duv_dy: &Vec2) -> ColourRgbF {
+        let (mut major, mut minor) = (*duv_dx, *duv_dy);
+        if minor.dot(&minor) > major.dot(&major) {
+            std::mem::swap(&mut major, &mut minor);
+        }
+        let major_length = major.dot(&major).sqrt();
+        let mut minor_length = minor.dot(&minor).sqrt();
+        if minor_length == 0.0 {
+            return self.bilinear(0, uv);
+        }
+        // Widening the minor axis limits how many texels are read, at the cost of some
+        // blurring across very thin ellipses
+        if minor_length * MAX_ANISOTROPY < major_length {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor *= scale;
+            minor_length *= scale;
+        }
+        let level = self.level_for_width(minor_length);
+        let lower = level.floor() as usize;
+        if lower + 1 >= self.levels.len() {
+            return self.ewa_in_level(lower, uv, &major, &minor);
+        }
+        lerp(
+            self.ewa_in_level(lower, uv, &major, &minor),
+            self.ewa_in_level(lower + 1, uv, &major, &minor),
+            level - lower as f64,
+        )
+    }
+
+    fn ewa_in_level(&self, level: usize, uv: &Vec2, axis_0: &Vec2, axis_1: &Vec2) -> ColourRgbF {
+        let image = &self.levels[level];
+        let (width, height) = (image.get_width() as f64, image.get_height() as f64);
+        let (s, t) = self.texel_position(level, uv);
+        // Rows run down the image, against v
+        let (s0, t0) = (axis_0.x() * width, -axis_0.y() * height);
+        let (s1, t1) = (axis_1.x() * width, -axis_1.y() * height);
+
+        // The implicit equation of the ellipse, a s² + b st + c t² < 1, with each axis
+        // grown by a texel so that it always covers at least one texel centre
+        let mut a = t0 * t0 + t1 * t1 + 1.0;
+        let mut b = -2.0 * (s0 * t0 + s1 * t1);
+        let mut c = s0 * s0 + s1 * s1 + 1.0;
+        let one_over_f = 1.0 / (a * c - b * b * 0.25);
+        a *= one_over_f;
+        b *= one_over_f;
+        c *= one_over_f;
+
+        let determinant = 4.0 * a * c - b * b;
+        let s_extent = (c / determinant).sqrt() * 2.0;
+        let t_extent = (a / determinant).sqrt() * 2.0;
+        let mut sum = ColourRgbF::new(0.0, 0.0, 0.0);
+        let mut total_weight = 0.0;
+        for row in (t - t_extent).ceil() as i64..=(t + t_extent).floor() as i64 {
+            let dt = row as f64 - t;
+            for column in (s - s_extent).ceil() as i64..=(s + s_extent).floor() as i64 {
+                let ds = column as f64 - s;
+                let r2 = a * ds * ds + b * ds * dt + c * dt * dt;
+                if r2 < 1.0 {
+                    let weight = (-EWA_ALPHA * r2).exp() - (-EWA_ALPHA).exp();
+                    sum = sum + self.texel(level, row, column) * weight;
+                    total_weight += weight;
+                }
+            }
+        }
+        if total_weight > 0.0 {
+            sum * (1.0 / total_weight)
+        } else {
+            self.bilinear(level, uv)
+        }
+    }
+
+    /// The colour averaged over the footprint with axes `duv_dx` and `duv_dy`, using
+    /// `filter`
+    pub fn filter(
+        &self,
+        filter: MipMapFilter,
+        uv: &Vec2,
+        duv_dx: &Vec2,
+        duv_dy: &Vec2,
+    ) -> ColourRgbF {
+        match filter {
+            MipMapFilter::Trilinear => {
+                let width = duv_dx
+                    .x()
+                    .abs()
+                    .max(duv_dx.y().abs())
+                    .max(duv_dy.x().abs())
+                    .max(duv_dy.y().abs());
+                self.trilinear(uv, width)
+            }
+            MipMapFilter::Ewa => self.ewa(uv, duv_dx, duv_dy),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::math::Vec3;
+
+    /// An 8x8 texel checkerboard of white and black
+    fn checkerboard() -> MipMap {
+        let mut image = ImageRgbF::new(8, 8);
+        for row in 0..8 {
+            for column in 0..8 {
+                if (row + column) % 2 == 0 {
+                    image.set_colour(row, column, ColourRgbF::new(1.0, 1.0, 1.0));
+                }
+            }
+        }
+        MipMap::new(image)
+    }
+
+    fn nearly_equal(a: &ColourRgbF, b: &Vec3, tolerance: f64) -> bool {
+        (a.values - *b).norm() < tolerance
+    }
+
+    #[test]
+    fn levels_halve_down_to_single_texel() {
+        let target = MipMap::new(ImageRgbF::new(5, 3));
+        let sizes: Vec<_> = (0..target.level_count())
+            .map(|level| {
+                let image = target.level(level);
+                (image.get_width(), image.get_height())
+            })
+            .collect();
+        assert!(sizes == vec![(5, 3), (3, 2), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn small_footprint_matches_full_size_image() {
+        let target = checkerboard();
+        let uv = Vec2::new(1.0 / 16.0, 15.0 / 16.0);
+        let tiny = Vec2::new(0.000001, 0.0);
+        assert!(nearly_equal(
+            &target.trilinear(&uv, 0.000001),
+            &Vec3::new(1.0, 1.0, 1.0),
+            0.000001
+        ));
+        assert!(nearly_equal(
+            &target.ewa(&uv, &tiny, &Vec2::new(0.0, 0.000001)),
+            &Vec3::new(1.0, 1.0, 1.0),
+            0.000001
+        ));
+    }
+
+    #[test]
+    fn large_footprint_averages_checkerboard() {
+        let target = checkerboard();
+        let uv = Vec2::new(1.0 / 16.0, 15.0 / 16.0);
+        let grey = Vec3::new(0.5, 0.5, 0.5);
+        assert!(nearly_equal(&target.trilinear(&uv, 0.5), &grey, 0.000001));
+        assert!(nearly_equal(
+            &target.ewa(&uv, &Vec2::new(0.5, 0.0), &Vec2::new(0.0, 0.5)),
+            &grey,
+            0.000001
+        ));
+    }
+
+    #[test]
+    fn ewa_averages_along_thin_footprint() {
+        let target = checkerboard();
+        let uv = Vec2::new(1.0 / 16.0, 15.0 / 16.0);
+        // A footprint stretched along the rows, as on a floor seen at a glancing angle.
+        // It covers alternating texels, so the average is grey, but EWA reads a finer
+        // level than trilinear filtering, which blurs the whole image to grey.
+        let along = Vec2::new(0.2, 0.0);
+        let across = Vec2::new(0.0, 0.03);
+        let ewa = target.ewa(&uv, &along, &across);
+        assert!(nearly_equal(&ewa, &Vec3::new(0.5, 0.5, 0.5), 0.1));
+        assert!(target.level_for_width(0.03) < target.level_for_width(0.2));
+    }
+}
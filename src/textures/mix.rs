@@ -0,0 +1,57 @@
+use crate::colour::Spectrum;
+use crate::math::Vec2;
+use crate::raycasting::Footprint;
+
+use super::Texture;
+
+/// A blend of two textures, `low` where `factor` is zero and `high` where it's one
+///
+/// `factor` is clamped to between zero and one. Grey textures such as
+/// [PerlinNoise](super::PerlinNoise) and [Gradient](super::Gradient) make good factors.
+#[derive(Debug)]
+pub struct Mix<F: Texture, A: Texture = Spectrum, B: Texture = Spectrum> {
+    pub factor: F,
+    pub low: A,
+    pub high: B,
+}
+
+impl<F: Texture, A: Texture, B: Texture> Mix<F, A, B> {
+    pub fn new(factor: F, low: A, high: B) -> Mix<F, A, B> {
+        Mix { factor, low, high }
+    }
+}
+
+impl<F: Texture, A: Texture, B: Texture> Texture for Mix<F, A, B> {
+    fn value(&self, uv: &Vec2, wavelength: f64) -> f64 {
+        let factor = self.factor.value(uv, wavelength).clamp(0.0, 1.0);
+        self.low.value(uv, wavelength) * (1.0 - factor) + self.high.value(uv, wavelength) * factor
+    }
+
+    fn filtered_value(&self, uv: &Vec2, footprint: &Footprint, wavelength: f64) -> f64 {
+        let factor = self
+            .factor
+            .filtered_value(uv, footprint, wavelength)
+            .clamp(0.0, 1.0);
+        self.low.filtered_value(uv, footprint, wavelength) * (1.0 - factor)
+            + self.high.filtered_value(uv, footprint, wavelength) * factor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::textures::Gradient;
+
+    #[test]
+    fn blends_by_factor() {
+        let target = Mix::new(
+            Gradient::new(Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0)),
+            Spectrum::grey(0.2),
+            Spectrum::grey(0.6),
+        );
+        assert!((target.value(&Vec2::new(0.0, 0.0), 550.0) - 0.2).abs() < 0.000000001);
+        assert!((target.value(&Vec2::new(0.5, 0.0), 550.0) - 0.4).abs() < 0.000000001);
+        assert!((target.value(&Vec2::new(2.0, 0.0), 550.0) - 0.6).abs() < 0.000000001);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::colour::Spectrum;
+use crate::math::Vec2;
+use crate::raycasting::Footprint;
+
+use std::fmt::Debug;
+
+pub mod checkerboard;
+pub use checkerboard::Checkerboard;
+
+pub mod gradient;
+pub use gradient::Gradient;
+
+pub mod image_texture;
+pub use image_texture::ImageTexture;
+
+pub mod mip_map;
+pub use mip_map::{MipMap, MipMapFilter};
+
+pub mod mix;
+pub use mix::Mix;
+
+pub mod noise;
+pub use noise::{PerlinNoise, WorleyNoise};
+
+pub mod opacity_mask;
+pub use opacity_mask::OpacityMask;
+
+pub mod uv_debug;
+pub use uv_debug::UvDebug;
+
+/// A spectral quantity, such as albedo, which varies over a surface
+///
+/// Textures are looked up using the surface coordinates of an intersection (see
+/// [IntersectionInfo](crate::raycasting::IntersectionInfo)).
+pub trait Texture: Debug + Sync + Send {
+    /// The value of the texture at surface coordinates `uv`, for light of `wavelength`
+    fn value(&self, uv: &Vec2, wavelength: f64) -> f64;
+
+    /// The value of the texture averaged over `footprint`, the area around `uv` seen
+    /// through one pixel
+    ///
+    /// Textures that don't have enough detail to alias can use the default, which looks
+    /// up `uv` alone.
+    fn filtered_value(&self, uv: &Vec2, _footprint: &Footprint, wavelength: f64) -> f64 {
+        self.value(uv, wavelength)
+    }
+}
+
+/// A spectrum is a texture with the same value everywhere
+impl Texture for Spectrum {
+    fn value(&self, _uv: &Vec2, wavelength: f64) -> f64 {
+        self.intensity_at_wavelength(wavelength)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn spectrum_texture_is_constant(u: f64, v: f64) -> bool {
+        let target = Spectrum::grey(0.5);
+        target.value(&Vec2::new(u, v), 550.0) == 0.5
+    }
+}
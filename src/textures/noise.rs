@@ -0,0 +1,232 @@
+use crate::math::Vec2;
+use crate::raycasting::Footprint;
+
+use super::Texture;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+/// A shuffled table of the integers below 256, for hashing lattice points
+#[derive(Clone)]
+struct Permutation([u8; 256]);
+
+impl Permutation {
+    fn new(seed: u64) -> Permutation {
+        let mut table = [0u8; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            *entry = i as u8;
+        }
+        table.shuffle(&mut StdRng::seed_from_u64(seed));
+        Permutation(table)
+    }
+
+    /// A pseudo-random number below 256 for the lattice point `(x, y)`
+    fn hash(&self, x: i64, y: i64) -> u8 {
+        let Permutation(table) = self;
+        table[(table[x.rem_euclid(256) as usize] as i64 + y).rem_euclid(256) as usize]
+    }
+}
+
+impl std::fmt::Debug for Permutation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Permutation")
+    }
+}
+
+/// Fractal gradient noise, as a grey texture with values between zero and one
+///
+/// Several octaves of K. Perlin's "Improving Noise" (SIGGRAPH 2002) are summed, each at
+/// twice the frequency and half the amplitude of the last. Lookups over a footprint leave
+/// out the octaves that are too fine to resolve, rather than letting them alias. The
+/// pattern repeats every 256 units of `frequency * uv`.
+#[derive(Clone, Debug)]
+pub struct PerlinNoise {
+    /// Number of features per unit of surface coordinates in the first octave
+    pub frequency: f64,
+    pub octaves: u32,
+    permutation: Permutation,
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+impl PerlinNoise {
+    /// Noise with a pattern chosen by `seed`
+    pub fn new(frequency: f64, octaves: u32, seed: u64) -> PerlinNoise {
+        PerlinNoise {
+            frequency,
+            octaves,
+            permutation: Permutation::new(seed),
+        }
+    }
+
+    /// A single octave of noise at `(x, y)`, between about -1 and 1
+    fn noise(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let gradient = |dx: i64, dy: i64| {
+            // Eight directions, to the edges and corners of a square
+            let (gx, gy) = match self.permutation.hash(x0 + dx, y0 + dy) % 8 {
+                0 => (1.0, 1.0),
+                1 => (-1.0, 1.0),
+                2 => (1.0, -1.0),
+                3 => (-1.0, -1.0),
+                4 => (1.0, 0.0),
+                5 => (-1.0, 0.0),
+                6 => (0.0, 1.0),
+                _ => (0.0, -1.0),
+            };
+            gx * (fx - dx as f64) + gy * (fy - dy as f64)
+        };
+        let (u, v) = (fade(fx), fade(fy));
+        lerp(
+            lerp(gradient(0, 0), gradient(1, 0), u),
+            lerp(gradient(0, 1), gradient(1, 1), u),
+            v,
+        )
+    }
+
+    /// The sum of the first `octaves` octaves at `uv`, scaled to between zero and one
+    fn fractal(&self, uv: &Vec2, octaves: u32) -> f64 {
+        // Scaled by the amplitude of all the octaves, so that leaving some out doesn't
+        // change the range
+        let total_amplitude: f64 = (0..self.octaves).map(|i| 0.5f64.powi(i as i32)).sum();
+        let mut frequency = self.frequency;
+        let mut amplitude = 1.0;
+        let mut sum = 0.0;
+        for _ in 0..octaves.min(self.octaves) {
+            sum += amplitude * self.noise(uv.x() * frequency, uv.y() * frequency);
+            frequency *= 2.0;
+            amplitude *= 0.5;
+        }
+        (0.5 + 0.5 * sum / total_amplitude).clamp(0.0, 1.0)
+    }
+}
+
+impl Texture for PerlinNoise {
+    fn value(&self, uv: &Vec2, _wavelength: f64) -> f64 {
+        self.fractal(uv, self.octaves)
+    }
+
+    fn filtered_value(&self, uv: &Vec2, footprint: &Footprint, _wavelength: f64) -> f64 {
+        let width = footprint
+            .duv_dx
+            .dot(&footprint.duv_dx)
+            .max(footprint.duv_dy.dot(&footprint.duv_dy))
+            .sqrt();
+        // An octave averages out to zero over a footprint wider than half its features
+        let resolved = if width > 0.0 {
+            (0.5 / (width * self.frequency)).log2().floor().max(-1.0) as i64 + 1
+        } else {
+            self.octaves as i64
+        };
+        self.fractal(uv, resolved.clamp(0, self.octaves as i64) as u32)
+    }
+}
+
+/// Cellular noise, as a grey texture with values between zero and one
+///
+/// The value is the distance to the nearest of a set of randomly scattered points, one
+/// in each cell of a grid, following S. Worley, "A Cellular Texture Basis Function"
+/// (SIGGRAPH 1996). It looks like cells or cracked stone. The pattern repeats every 256
+/// units of `frequency * uv`.
+#[derive(Clone, Debug)]
+pub struct WorleyNoise {
+    /// Number of cells per unit of surface coordinates
+    pub frequency: f64,
+    permutation: Permutation,
+}
+
+impl WorleyNoise {
+    /// Noise with a pattern chosen by `seed`
+    pub fn new(frequency: f64, seed: u64) -> WorleyNoise {
+        WorleyNoise {
+            frequency,
+            permutation: Permutation::new(seed),
+        }
+    }
+
+    /// The scattered point in the cell with its corner at `(x, y)`
+    fn feature_point(&self, x: i64, y: i64) -> (f64, f64) {
+        let h = self.permutation.hash(x, y);
+        let offset_x = (h as f64 + 0.5) / 256.0;
+        let offset_y = (self.permutation.hash(y + 101, x + h as i64) as f64 + 0.5) / 256.0;
+        (x as f64 + offset_x, y as f64 + offset_y)
+    }
+}
+
+impl Texture for WorleyNoise {
+    fn value(&self, uv: &Vec2, _wavelength: f64) -> f64 {
+        let (x, y) = (uv.x() * self.frequency, uv.y() * self.frequency);
+        let (cell_x, cell_y) = (x.floor() as i64, y.floor() as i64);
+        let mut nearest = f64::INFINITY;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (px, py) = self.feature_point(cell_x + dx, cell_y + dy);
+                nearest = nearest.min((px - x) * (px - x) + (py - y) * (py - y));
+            }
+        }
+        nearest.sqrt().min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::math::Vec3;
+
+    fn samples() -> impl Iterator<Item = Vec2> {
+        (0..400).map(|i| Vec2::new(i as f64 * 0.0731, (i % 37) as f64 * 0.1193))
+    }
+
+    #[test]
+    fn perlin_noise_is_in_range_and_varies() {
+        let target = PerlinNoise::new(4.0, 4, 1);
+        let values: Vec<_> = samples().map(|uv| target.value(&uv, 550.0)).collect();
+        assert!(values.iter().all(|&v| (0.0..=1.0).contains(&v)));
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        assert!((mean - 0.5).abs() < 0.1);
+        assert!(values.iter().any(|&v| (v - mean).abs() > 0.1));
+    }
+
+    #[test]
+    fn perlin_noise_depends_on_seed() {
+        let uv = Vec2::new(0.3, 0.7);
+        let a = PerlinNoise::new(4.0, 4, 1);
+        let b = PerlinNoise::new(4.0, 4, 1);
+        let c = PerlinNoise::new(4.0, 4, 2);
+        assert!(a.value(&uv, 550.0) == b.value(&uv, 550.0));
+        assert!(samples().any(|uv| a.value(&uv, 550.0) != c.value(&uv, 550.0)));
+    }
+
+    #[test]
+    fn wide_footprint_flattens_perlin_noise() {
+        let target = PerlinNoise::new(4.0, 4, 1);
+        let footprint = Footprint {
+            dp_dx: Vec3::zeros(),
+            dp_dy: Vec3::zeros(),
+            duv_dx: Vec2::new(10.0, 0.0),
+            duv_dy: Vec2::new(0.0, 10.0),
+            x_direction: Vec3::unit_z(),
+            y_direction: Vec3::unit_z(),
+        };
+        assert!(samples().all(|uv| target.filtered_value(&uv, &footprint, 550.0) == 0.5));
+    }
+
+    #[test]
+    fn worley_noise_is_zero_at_feature_points() {
+        let target = WorleyNoise::new(2.0, 3);
+        let (x, y) = target.feature_point(1, -2);
+        assert!(target.value(&Vec2::new(x / 2.0, y / 2.0), 550.0) < 0.000000001);
+        assert!(samples().all(|uv| (0.0..=1.0).contains(&target.value(&uv, 550.0))));
+        assert!(samples().any(|uv| target.value(&uv, 550.0) > 0.2));
+    }
+}
@@ -0,0 +1,56 @@
+use crate::error::VanrijnError;
+use crate::image::ImageRgbF;
+use crate::math::Vec2;
+
+use super::{ImageTexture, Texture};
+
+use std::path::Path;
+use std::sync::Arc;
+
+/// Cuts holes in a surface wherever a texture is below a threshold
+///
+/// This is how leaves, fences and similar are usually modelled: a few flat triangles with
+/// an alpha channel, instead of geometry for every detail. Rays pass straight through the
+/// cut-out parts, so they cast no shadows either.
+///
+/// The texture is looked up in the middle of the visible spectrum, so grey textures work
+/// as expected.
+#[derive(Clone, Debug)]
+pub struct OpacityMask {
+    pub texture: Arc<dyn Texture>,
+    pub threshold: f64,
+}
+
+impl OpacityMask {
+    pub fn new(texture: Arc<dyn Texture>, threshold: f64) -> OpacityMask {
+        OpacityMask { texture, threshold }
+    }
+
+    /// A mask from the alpha channel of a PNG image
+    pub fn read_png(filename: &Path, threshold: f64) -> Result<OpacityMask, VanrijnError> {
+        let texture = ImageTexture::new(ImageRgbF::read_png_alpha(filename)?);
+        Ok(OpacityMask::new(Arc::new(texture), threshold))
+    }
+
+    /// Whether the surface is there at surface coordinates `uv`
+    pub fn is_opaque(&self, uv: &Vec2) -> bool {
+        self.texture.value(uv, 550.0) >= self.threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::ColourRgbF;
+
+    #[test]
+    fn grey_image_is_cut_at_threshold() {
+        let mut image = ImageRgbF::new(2, 1);
+        image.set_colour(0, 0, ColourRgbF::new(0.2, 0.2, 0.2));
+        image.set_colour(0, 1, ColourRgbF::new(0.8, 0.8, 0.8));
+        let target = OpacityMask::new(Arc::new(ImageTexture::new(image)), 0.5);
+        assert!(!target.is_opaque(&Vec2::new(0.1, 0.5)));
+        assert!(target.is_opaque(&Vec2::new(0.9, 0.5)));
+    }
+}
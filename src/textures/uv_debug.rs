@@ -0,0 +1,41 @@
+use crate::colour::{ColourRgbF, Spectrum};
+use crate::math::Vec2;
+
+use super::Texture;
+
+/// Shows surface coordinates as colour, with `u` in red and `v` in green
+///
+/// Coordinates repeat every unit, so each unit square goes from black at its origin to
+/// yellow at the opposite corner. This makes it easy to see how a
+/// [Primitive](crate::raycasting::Primitive) or mesh is parameterized.
+#[derive(Debug, Default)]
+pub struct UvDebug;
+
+impl UvDebug {
+    pub fn new() -> UvDebug {
+        UvDebug
+    }
+}
+
+impl Texture for UvDebug {
+    fn value(&self, uv: &Vec2, wavelength: f64) -> f64 {
+        let colour = ColourRgbF::new(uv.x().rem_euclid(1.0), uv.y().rem_euclid(1.0), 0.0);
+        Spectrum::reflection_from_linear_rgb(&colour).intensity_at_wavelength(wavelength)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn red_increases_with_u() {
+        let target = UvDebug::new();
+        // Long wavelengths are red
+        let low = target.value(&Vec2::new(0.1, 0.0), 650.0);
+        let high = target.value(&Vec2::new(0.9, 0.0), 650.0);
+        let repeated = target.value(&Vec2::new(1.9, 0.0), 650.0);
+        assert!(low < high);
+        assert!((high - repeated).abs() < 0.000001);
+    }
+}
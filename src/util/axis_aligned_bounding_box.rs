@@ -73,6 +73,34 @@ impl BoundingBox {
         }
     }
 
+    /// The point in the middle of the box
+    pub fn centre(&self) -> Vec3 {
+        Vec3::new(
+            0.5 * (self.bounds[0].get_min() + self.bounds[0].get_max()),
+            0.5 * (self.bounds[1].get_min() + self.bounds[1].get_max()),
+            0.5 * (self.bounds[2].get_min() + self.bounds[2].get_max()),
+        )
+    }
+
+    /// The vector from the lowest corner of the box to the highest
+    pub fn diagonal(&self) -> Vec3 {
+        Vec3::new(
+            self.bounds[0].get_max() - self.bounds[0].get_min(),
+            self.bounds[1].get_max() - self.bounds[1].get_min(),
+            self.bounds[2].get_max() - self.bounds[2].get_min(),
+        )
+    }
+
+    /// Whether the box reaches infinity along any axis, as for a
+    /// [plane](crate::raycasting::Plane)
+    ///
+    /// An [empty](BoundingBox::empty) box isn't unbounded.
+    pub fn is_unbounded(&self) -> bool {
+        self.bounds.iter().any(|interval| {
+            interval.get_min() == f64::NEG_INFINITY || interval.get_max() == f64::INFINITY
+        })
+    }
+
     pub fn largest_dimension(&self) -> usize {
         let (dimension, _) = self
             .bounds
@@ -142,6 +170,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn only_infinite_boxes_are_unbounded() {
+        assert!(!BoundingBox::empty().is_unbounded());
+        assert!(!BoundingBox::from_corners(Vec3::zeros(), Vec3::new(1.0, 2.0, 3.0)).is_unbounded());
+        assert!(
+            BoundingBox::from_corners(Vec3::zeros(), Vec3::new(1.0, f64::INFINITY, 3.0))
+                .is_unbounded()
+        );
+    }
+
     #[quickcheck]
     fn union_with_self_yields_self(a: Vec3, b: Vec3) -> bool {
         let target = BoundingBox::from_corners(a, b);
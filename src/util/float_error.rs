@@ -0,0 +1,91 @@
+//! Bounds on floating-point rounding error
+//!
+//! Intersection points are never exactly on the surface, so a ray leaving one can hit the
+//! same surface again straight away. Rather than moving rays along by a fixed distance,
+//! which is too little for large scenes and too much for small details, each intersection
+//! keeps a conservative bound on the error in its location, and new rays start just far
+//! enough from the surface to be clear of it. This follows section 3.9 of *Physically
+//! Based Rendering*, third edition.
+
+use crate::math::{Affine3, Mat3, Vec3};
+
+/// A bound on the relative error of a result after `n` floating-point operations
+///
+/// Each operation rounds by at most half a unit in the last place, so the error grows to
+/// at most `nε / (1 - nε)`, where ε is [f64::EPSILON] / 2.
+pub fn gamma(n: u32) -> f64 {
+    let n_epsilon = n as f64 * f64::EPSILON * 0.5;
+    n_epsilon / (1.0 - n_epsilon)
+}
+
+/// A bound on the error in each coordinate of the point `origin + offset`, where `offset`
+/// is a ray direction scaled by the distance to a plane through `point_on_plane`
+///
+/// Rounding in the distance moves the point along the ray, which can be a long way for
+/// grazing rays, but only the part of the error that takes the point off the plane
+/// matters when [offsetting](offset_ray_origin) rays, and this bounds that part.
+pub fn ray_plane_point_error(origin: &Vec3, offset: &Vec3, point_on_plane: &Vec3) -> Vec3 {
+    (origin.abs() + offset.abs() + point_on_plane.abs()) * gamma(7)
+}
+
+/// A bound on the error in each coordinate of `transformation.transform_point(point)`,
+/// where `point` itself already had an error of up to `error`
+pub fn transformed_point_error(transformation: &Affine3, point: &Vec3, error: &Vec3) -> Vec3 {
+    let linear = transformation.get_linear();
+    let abs_linear = Mat3::from_rows(
+        &linear.get_row(0).abs(),
+        &linear.get_row(1).abs(),
+        &linear.get_row(2).abs(),
+    );
+    (abs_linear * point.abs() + transformation.get_translation().abs()) * gamma(3)
+        + abs_linear * *error * (1.0 + gamma(3))
+}
+
+/// The origin for a ray leaving a surface at `location` in `direction`
+///
+/// `location` is moved along `normal`, towards the side that `direction` leaves from, by
+/// just enough that the whole box of points within `error` of it is behind the new origin.
+/// The result is then rounded away from the surface, so that rounding the sum can't undo
+/// the offset.
+pub fn offset_ray_origin(location: &Vec3, error: &Vec3, normal: &Vec3, direction: &Vec3) -> Vec3 {
+    let distance = normal.abs().dot(error);
+    let offset = if direction.dot(normal) < 0.0 {
+        *normal * -distance
+    } else {
+        *normal * distance
+    };
+    let moved = *location + offset;
+    let round_away = |axis: usize| {
+        if offset[axis] > 0.0 {
+            moved[axis].next_up()
+        } else if offset[axis] < 0.0 {
+            moved[axis].next_down()
+        } else {
+            moved[axis]
+        }
+    };
+    Vec3::new(round_away(0), round_away(1), round_away(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gamma_grows_with_operations() {
+        assert!(gamma(1) >= f64::EPSILON * 0.5);
+        assert!(gamma(2) > gamma(1));
+        assert!(gamma(7) < 0.000_000_000_000_001);
+    }
+
+    #[test]
+    fn offset_origin_is_clear_of_error_box_on_the_leaving_side() {
+        let location = Vec3::new(1000.0, 0.0, 3.0);
+        let error = Vec3::new(0.001, 0.001, 0.001);
+        let normal = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let outwards = offset_ray_origin(&location, &error, &normal, &Vec3::unit_x());
+        assert!((outwards - location).dot(&normal) >= normal.abs().dot(&error));
+        let inwards = offset_ray_origin(&location, &error, &normal, &-Vec3::unit_x());
+        assert!((inwards - location).dot(&normal) <= -normal.abs().dot(&error));
+    }
+}
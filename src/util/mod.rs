@@ -1,13 +1,19 @@
 mod interval;
 pub use interval::Interval;
 
-pub mod algebra_utils;
 pub mod array2d;
 pub use array2d::Array2D;
 pub mod axis_aligned_bounding_box;
 pub mod binary_tree;
+mod cancellation_token;
+pub use cancellation_token::CancellationToken;
+pub mod float_error;
 pub mod morton;
 pub mod normalizer;
 mod tile_iterator;
 pub use tile_iterator::{Tile, TileIterator};
+mod tile_scheduler;
+pub use tile_scheduler::{TileOrder, TileScheduler, TileSequencer};
 pub mod polyhedra;
+pub mod voxel_grid;
+pub use voxel_grid::VoxelGrid;
@@ -1,7 +1,7 @@
 use itertools::izip;
 
 use crate::materials::Material;
-use crate::math::Vec3;
+use crate::math::{Vec2, Vec3};
 use crate::raycasting::{Primitive, Triangle};
 
 use std::sync::Arc;
@@ -18,7 +18,13 @@ pub fn triangulate_polygon(
             Arc::new(Triangle {
                 vertices: [hinge, *a, *b],
                 normals: [*normal, *normal, *normal],
+                uvs: [
+                    Vec2::new(0.0, 0.0),
+                    Vec2::new(1.0, 0.0),
+                    Vec2::new(0.0, 1.0),
+                ],
                 material: Arc::clone(&material),
+                opacity: None,
             }) as Arc<dyn Primitive>
         })
         .collect()
@@ -0,0 +1,286 @@
+use super::{Tile, TileIterator};
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The order in which a [TileScheduler](TileScheduler) hands out tiles
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TileOrder {
+    /// Left to right, then top to bottom, as produced by [TileIterator](TileIterator)
+    RowMajor,
+
+    /// Rings of tiles spiralling outwards from the centre of the image
+    ///
+    /// The subject of an image is usually near the centre, so this shows the most
+    /// interesting part of a preview first.
+    #[default]
+    Spiral,
+
+    /// Along a Hilbert curve
+    ///
+    /// Consecutive tiles are always neighbours, which keeps the parts of the scene being
+    /// rendered at the same time close together.
+    Hilbert,
+}
+
+/// Hands out the tiles of an image to any number of worker threads
+///
+/// Tiles are handed out one at a time, in the chosen order, to whichever worker asks next.
+/// Workers that finish quickly simply take more tiles, so the work stays balanced even
+/// when some tiles are much more expensive to render than others.
+#[derive(Debug)]
+pub struct TileScheduler {
+    tiles: Vec<Tile>,
+    next_tile: AtomicUsize,
+}
+
+/// Position of a tile in the grid of tiles, as (column, row)
+fn grid_position(tile: &Tile, tile_size: usize) -> (usize, usize) {
+    (tile.start_column / tile_size, tile.start_row / tile_size)
+}
+
+/// Index of the point `(x, y)` along a Hilbert curve filling a `size` by `size` grid
+///
+/// `size` must be a power of two.
+fn hilbert_index(size: usize, x: usize, y: usize) -> usize {
+    let (mut x, mut y) = (x, y);
+    let mut index = 0;
+    let mut s = size / 2;
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        index += s * s * ((3 * rx) ^ ry);
+        // Rotate the quadrant so the curve inside it has the right orientation
+        if ry == 0 {
+            if rx == 1 {
+                x = size - 1 - x;
+                y = size - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+impl TileScheduler {
+    pub fn new(
+        total_width: usize,
+        total_height: usize,
+        tile_size: usize,
+        order: TileOrder,
+    ) -> TileScheduler {
+        let mut tiles: Vec<Tile> =
+            TileIterator::new(total_width, total_height, tile_size).collect();
+        let columns = total_width.div_ceil(tile_size);
+        let rows = total_height.div_ceil(tile_size);
+        match order {
+            TileOrder::RowMajor => {}
+            TileOrder::Spiral => {
+                let centre_column = (columns as f64 - 1.0) / 2.0;
+                let centre_row = (rows as f64 - 1.0) / 2.0;
+                tiles.sort_by_cached_key(|tile| {
+                    let (column, row) = grid_position(tile, tile_size);
+                    let dx = column as f64 - centre_column;
+                    let dy = row as f64 - centre_row;
+                    let ring = dx.abs().max(dy.abs()).ceil() as usize;
+                    // Going clockwise once around each ring turns the rings into a spiral
+                    let angle = dy.atan2(dx);
+                    (ring, (angle * 1_000_000.0) as i64)
+                });
+            }
+            TileOrder::Hilbert => {
+                let size = columns.max(rows).next_power_of_two();
+                tiles.sort_by_cached_key(|tile| {
+                    let (column, row) = grid_position(tile, tile_size);
+                    hilbert_index(size, column, row)
+                });
+            }
+        }
+        TileScheduler {
+            tiles,
+            next_tile: AtomicUsize::new(0),
+        }
+    }
+
+    /// The next tile to render, or `None` once every tile has been handed out
+    ///
+    /// This may be called from several threads at once; each tile is only ever returned
+    /// once.
+    pub fn next_tile(&self) -> Option<Tile> {
+        self.next_indexed_tile().map(|(_, tile)| tile)
+    }
+
+    /// As [next_tile()](TileScheduler::next_tile), along with the tile's position in the
+    /// order, starting from zero
+    pub fn next_indexed_tile(&self) -> Option<(usize, Tile)> {
+        let index = self.next_tile.fetch_add(1, Ordering::Relaxed);
+        self.tiles.get(index).map(|&tile| (index, tile))
+    }
+
+    /// Total number of tiles in the image
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+}
+
+impl Iterator for &TileScheduler {
+    type Item = Tile;
+
+    fn next(&mut self) -> Option<Tile> {
+        self.next_tile()
+    }
+}
+
+/// Puts rendered tiles back into the order a [TileScheduler](TileScheduler) handed them out
+///
+/// Tiles finish in an order that depends on how the threads happen to run. Where tiles
+/// overlap, as their filter footprints do, the order they're merged in changes the
+/// rounding of the result, so merging them in the order they were scheduled is what makes
+/// two renders with the same seed byte-for-byte identical.
+#[derive(Debug)]
+pub struct TileSequencer<T> {
+    next_index: usize,
+    pending: BTreeMap<usize, T>,
+}
+
+impl<T> Default for TileSequencer<T> {
+    fn default() -> Self {
+        TileSequencer {
+            next_index: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> TileSequencer<T> {
+    pub fn new() -> TileSequencer<T> {
+        TileSequencer::default()
+    }
+
+    /// Add the tile at `index` in the schedule, and return every tile that's now ready, in
+    /// order
+    ///
+    /// A tile is ready once all of the tiles before it have been added.
+    pub fn push(&mut self, index: usize, tile: T) -> Vec<T> {
+        self.pending.insert(index, tile);
+        let mut ready = vec![];
+        while let Some(tile) = self.pending.remove(&self.next_index) {
+            ready.push(tile);
+            self.next_index += 1;
+        }
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+
+    fn all_orders() -> [TileOrder; 3] {
+        [TileOrder::RowMajor, TileOrder::Spiral, TileOrder::Hilbert]
+    }
+
+    #[quickcheck]
+    fn scheduler_includes_all_coordinates_exactly_once(
+        width: usize,
+        height: usize,
+        tile_size: usize,
+    ) -> TestResult {
+        let max_size = 10000;
+        // Check size of width and height first, since width*height might overflow.
+        if width > max_size || height > max_size || width * height > max_size {
+            return TestResult::discard();
+        }
+        if tile_size == 0 {
+            return TestResult::discard();
+        }
+
+        for &order in all_orders().iter() {
+            let target = TileScheduler::new(width, height, tile_size, order);
+            let mut index_counts = vec![0; width * height];
+            for tile in &target {
+                for column in tile.start_column..tile.end_column {
+                    for row in tile.start_row..tile.end_row {
+                        index_counts[row * width + column] += 1;
+                    }
+                }
+            }
+            if !index_counts.iter().all(|&elem| elem == 1) {
+                return TestResult::failed();
+            }
+        }
+        TestResult::passed()
+    }
+
+    #[test]
+    fn spiral_starts_at_centre() {
+        let target = TileScheduler::new(50, 30, 10, TileOrder::Spiral);
+        let first = target.next_tile().unwrap();
+        assert!(first.start_column == 20 && first.start_row == 10);
+    }
+
+    #[test]
+    fn spiral_finishes_each_ring_before_starting_the_next() {
+        let target = TileScheduler::new(50, 50, 10, TileOrder::Spiral);
+        let rings: Vec<usize> = (&target)
+            .map(|tile| {
+                let (column, row) = grid_position(&tile, 10);
+                (column as isize - 2).abs().max((row as isize - 2).abs()) as usize
+            })
+            .collect();
+        assert!(rings.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn consecutive_hilbert_tiles_are_neighbours() {
+        let target = TileScheduler::new(80, 80, 10, TileOrder::Hilbert);
+        let positions: Vec<(usize, usize)> =
+            (&target).map(|tile| grid_position(&tile, 10)).collect();
+        assert!(positions.len() == 64);
+        assert!(positions.windows(2).all(|pair| {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            (x0 as isize - x1 as isize).abs() + (y0 as isize - y1 as isize).abs() == 1
+        }));
+    }
+
+    #[test]
+    fn tiles_are_shared_between_threads_without_repeats() {
+        let target = TileScheduler::new(100, 100, 3, TileOrder::Spiral);
+        let mut tiles: Vec<(usize, usize)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (&target)
+                            .map(|tile| (tile.start_column, tile.start_row))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect()
+        });
+        assert!(tiles.len() == target.len());
+        tiles.sort_unstable();
+        tiles.dedup();
+        assert!(tiles.len() == target.len());
+    }
+
+    #[test]
+    fn sequencer_releases_tiles_in_schedule_order() {
+        let mut target = TileSequencer::new();
+        assert!(target.push(2, 'c').is_empty());
+        assert!(target.push(1, 'b').is_empty());
+        assert!(target.push(0, 'a') == vec!['a', 'b', 'c']);
+        assert!(target.push(3, 'd') == vec!['d']);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::math::Vec3;
+
+use super::axis_aligned_bounding_box::BoundingBox;
+
+use std::collections::HashMap;
+
+/// Number of voxels along each side of a block
+const BLOCK_SIZE: i64 = 8;
+
+const BLOCK_VOXELS: usize = (BLOCK_SIZE * BLOCK_SIZE * BLOCK_SIZE) as usize;
+
+/// A sparse grid of values, such as the density of a cloud
+///
+/// Like OpenVDB, voxels are stored in blocks of 8×8×8, and blocks where every voxel is zero
+/// aren't stored at all, so large, mostly empty volumes take little memory. Voxel `(i, j, k)`
+/// is the cube from `origin + (i, j, k) * voxel_size` to one voxel further along each axis.
+/// Values are interpolated trilinearly between voxel centres, and are zero outside the
+/// stored blocks.
+#[derive(Clone, Debug)]
+pub struct VoxelGrid {
+    origin: Vec3,
+    voxel_size: f64,
+    blocks: HashMap<[i64; 3], Box<[f64; BLOCK_VOXELS]>>,
+    max_value: f64,
+    min_index: [i64; 3],
+    max_index: [i64; 3],
+}
+
+impl VoxelGrid {
+    /// An empty grid
+    pub fn new(origin: Vec3, voxel_size: f64) -> VoxelGrid {
+        VoxelGrid {
+            origin,
+            voxel_size,
+            blocks: HashMap::new(),
+            max_value: 0.0,
+            min_index: [i64::MAX; 3],
+            max_index: [i64::MIN; 3],
+        }
+    }
+
+    /// A grid of `resolution` voxels from `origin`, with each voxel set to the value of `f`
+    /// at its centre
+    pub fn from_fn<F: Fn(&Vec3) -> f64>(
+        origin: Vec3,
+        voxel_size: f64,
+        resolution: [usize; 3],
+        f: F,
+    ) -> VoxelGrid {
+        let mut grid = VoxelGrid::new(origin, voxel_size);
+        for k in 0..resolution[2] as i64 {
+            for j in 0..resolution[1] as i64 {
+                for i in 0..resolution[0] as i64 {
+                    let value = f(&grid.voxel_centre([i, j, k]));
+                    if value != 0.0 {
+                        grid.set([i, j, k], value);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    pub fn voxel_size(&self) -> f64 {
+        self.voxel_size
+    }
+
+    /// The largest value in the grid, or zero if it's empty
+    pub fn max_value(&self) -> f64 {
+        self.max_value
+    }
+
+    /// Number of blocks of voxels that are stored
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn block_and_offset(index: [i64; 3]) -> ([i64; 3], usize) {
+        let block = index.map(|i| i.div_euclid(BLOCK_SIZE));
+        let [x, y, z] = index.map(|i| i.rem_euclid(BLOCK_SIZE));
+        (block, (x + BLOCK_SIZE * (y + BLOCK_SIZE * z)) as usize)
+    }
+
+    /// The value of the voxel at `index`
+    pub fn get(&self, index: [i64; 3]) -> f64 {
+        let (block, offset) = Self::block_and_offset(index);
+        self.blocks.get(&block).map_or(0.0, |values| values[offset])
+    }
+
+    /// Set the value of the voxel at `index`
+    ///
+    /// The maximum is kept for finding bounds on the values, so it isn't reduced by
+    /// overwriting the largest value with a smaller one.
+    pub fn set(&mut self, index: [i64; 3], value: f64) {
+        let (block, offset) = Self::block_and_offset(index);
+        if value == 0.0 && !self.blocks.contains_key(&block) {
+            return;
+        }
+        self.blocks
+            .entry(block)
+            .or_insert_with(|| Box::new([0.0; BLOCK_VOXELS]))[offset] = value;
+        self.max_value = self.max_value.max(value);
+        self.min_index = [0, 1, 2].map(|axis| self.min_index[axis].min(index[axis]));
+        self.max_index = [0, 1, 2].map(|axis| self.max_index[axis].max(index[axis]));
+    }
+
+    fn voxel_centre(&self, index: [i64; 3]) -> Vec3 {
+        self.origin
+            + Vec3::new(
+                index[0] as f64 + 0.5,
+                index[1] as f64 + 0.5,
+                index[2] as f64 + 0.5,
+            ) * self.voxel_size
+    }
+
+    /// The value at `point`, interpolated from the nearest voxel centres
+    pub fn value_at(&self, point: &Vec3) -> f64 {
+        let position = (*point - self.origin) * (1.0 / self.voxel_size);
+        let below = [0, 1, 2].map(|axis| (position[axis] - 0.5).floor());
+        let fraction = [0, 1, 2].map(|axis| position[axis] - 0.5 - below[axis]);
+        let below = below.map(|i| i as i64);
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let weight = (0..3)
+                .map(|axis| {
+                    if offset[axis] == 1 {
+                        fraction[axis]
+                    } else {
+                        1.0 - fraction[axis]
+                    }
+                })
+                .product::<f64>();
+            if weight > 0.0 {
+                value += weight
+                    * self.get([
+                        below[0] + offset[0],
+                        below[1] + offset[1],
+                        below[2] + offset[2],
+                    ]);
+            }
+        }
+        value
+    }
+
+    /// A box containing every voxel that has been set, or an empty box if none have
+    pub fn bounding_box(&self) -> BoundingBox {
+        if self.blocks.is_empty() {
+            return BoundingBox::empty();
+        }
+        let corner = |index: [i64; 3]| {
+            self.origin
+                + Vec3::new(index[0] as f64, index[1] as f64, index[2] as f64) * self.voxel_size
+        };
+        BoundingBox::from_corners(
+            corner(self.min_index),
+            corner(self.max_index.map(|i| i + 1)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unset_voxels_are_zero_and_take_no_space() {
+        let mut target = VoxelGrid::new(Vec3::zeros(), 1.0);
+        target.set([3, -20, 100], 2.0);
+        target.set([1000, 0, 0], 0.0);
+        assert!(target.get([3, -20, 100]) == 2.0);
+        assert!(target.get([4, -20, 100]) == 0.0);
+        assert!(target.get([1000, 0, 0]) == 0.0);
+        assert!(target.block_count() == 1);
+        assert!(target.max_value() == 2.0);
+    }
+
+    #[test]
+    fn values_are_interpolated_between_voxel_centres() {
+        let mut target = VoxelGrid::new(Vec3::zeros(), 0.5);
+        target.set([0, 0, 0], 1.0);
+        target.set([1, 0, 0], 3.0);
+        assert!((target.value_at(&Vec3::new(0.25, 0.25, 0.25)) - 1.0).abs() < 0.000_000_001);
+        assert!((target.value_at(&Vec3::new(0.5, 0.25, 0.25)) - 2.0).abs() < 0.000_000_001);
+        // Halfway towards the unset neighbour in y
+        assert!((target.value_at(&Vec3::new(0.25, 0.5, 0.25)) - 0.5).abs() < 0.000_000_001);
+    }
+
+    #[test]
+    fn bounding_box_covers_set_voxels() {
+        let target = VoxelGrid::from_fn(Vec3::new(1.0, 2.0, 3.0), 0.1, [20, 20, 20], |point| {
+            if (*point - Vec3::new(2.0, 3.0, 4.0)).norm() < 0.5 {
+                1.0
+            } else {
+                0.0
+            }
+        });
+        let bounds = target.bounding_box();
+        assert!(bounds.contains_point(Vec3::new(2.0, 3.0, 4.0)));
+        assert!(bounds.contains_point(Vec3::new(1.55, 2.55, 3.55)));
+        assert!(!bounds.contains_point(Vec3::new(1.45, 3.0, 4.0)));
+    }
+}
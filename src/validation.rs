@@ -0,0 +1,179 @@
+//! Checking a scene for mistakes that would otherwise only show up as black or NaN pixels
+//!
+//! [Scene::validate()](crate::scene::Scene::validate) walks every object in the scene with
+//! a [SceneValidator](SceneValidator), which each object and material reports problems to
+//! through [Intersect::validate()](crate::raycasting::Intersect::validate) and
+//! [Material::validate()](crate::materials::Material::validate).
+
+use crate::materials::Material;
+use crate::math::Vec3;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// Normals whose length differs from one by more than this are reported
+const NORMAL_LENGTH_TOLERANCE: f64 = 0.001;
+
+/// A problem found in a scene
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneIssue {
+    /// A vertex or centre has a NaN or infinite coordinate, which makes the object's
+    /// bounding box, and any pixels that see it, NaN
+    NonFiniteVertex(Vec3),
+
+    /// A triangle with no area, which rays will never hit
+    DegenerateTriangle([Vec3; 3]),
+
+    /// A vertex normal that isn't of unit length, which makes shading too bright or dark
+    UnnormalizedNormal(Vec3),
+
+    /// A sphere with a radius that isn't positive, which rays will never hit
+    ZeroRadiusSphere { centre: Vec3, radius: f64 },
+
+    /// A material strength that is negative, which adds negative light to the image
+    NegativeStrength { parameter: &'static str, value: f64 },
+
+    /// An aggregate with nothing in it
+    EmptyAggregate,
+}
+
+impl SceneIssue {
+    /// Whether the issue makes the rendered image wrong, rather than just wasting time or
+    /// probably not being what was intended
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            SceneIssue::NonFiniteVertex(_) | SceneIssue::NegativeStrength { .. }
+        )
+    }
+}
+
+impl fmt::Display for SceneIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let point = |v: &Vec3| format!("({}, {}, {})", v.x(), v.y(), v.z());
+        match self {
+            SceneIssue::NonFiniteVertex(v) => write!(f, "non-finite vertex {}", point(v)),
+            SceneIssue::DegenerateTriangle(vertices) => write!(
+                f,
+                "degenerate triangle {}, {}, {}",
+                point(&vertices[0]),
+                point(&vertices[1]),
+                point(&vertices[2])
+            ),
+            SceneIssue::UnnormalizedNormal(normal) => {
+                write!(f, "normal {} has length {}", point(normal), normal.norm())
+            }
+            SceneIssue::ZeroRadiusSphere { centre, radius } => {
+                write!(f, "sphere at {} has radius {}", point(centre), radius)
+            }
+            SceneIssue::NegativeStrength { parameter, value } => {
+                write!(f, "material has {} of {}", parameter, value)
+            }
+            SceneIssue::EmptyAggregate => write!(f, "empty aggregate"),
+        }
+    }
+}
+
+/// Collects the [issues](SceneIssue) found while walking a scene
+#[derive(Debug, Default)]
+pub struct SceneValidator {
+    issues: Vec<SceneIssue>,
+
+    /// Addresses of the materials already checked, so that materials shared by many
+    /// objects are only reported once
+    checked_materials: HashSet<usize>,
+}
+
+impl SceneValidator {
+    pub fn new() -> SceneValidator {
+        SceneValidator::default()
+    }
+
+    pub fn report(&mut self, issue: SceneIssue) {
+        self.issues.push(issue);
+    }
+
+    /// Check `material`, unless it has already been checked
+    pub fn check_material(&mut self, material: &Arc<dyn Material>) {
+        if self
+            .checked_materials
+            .insert(Arc::as_ptr(material) as *const () as usize)
+        {
+            material.validate(self);
+        }
+    }
+
+    /// Report `value` if it's negative or NaN
+    pub fn check_strength(&mut self, parameter: &'static str, value: f64) {
+        if value < 0.0 || value.is_nan() {
+            self.report(SceneIssue::NegativeStrength { parameter, value });
+        }
+    }
+
+    pub fn check_vertex(&mut self, vertex: &Vec3) {
+        if [vertex.x(), vertex.y(), vertex.z()]
+            .iter()
+            .any(|coordinate| !coordinate.is_finite())
+        {
+            self.report(SceneIssue::NonFiniteVertex(*vertex));
+        }
+    }
+
+    pub fn check_normal(&mut self, normal: &Vec3) {
+        let error = (normal.norm() - 1.0).abs();
+        if error > NORMAL_LENGTH_TOLERANCE || error.is_nan() {
+            self.report(SceneIssue::UnnormalizedNormal(*normal));
+        }
+    }
+
+    /// Check the vertices of a triangle, and that it isn't degenerate
+    pub fn check_triangle(&mut self, vertices: &[Vec3; 3]) {
+        vertices.iter().for_each(|vertex| self.check_vertex(vertex));
+        let area = (vertices[1] - vertices[0])
+            .cross(&(vertices[2] - vertices[0]))
+            .norm();
+        if area == 0.0 {
+            self.report(SceneIssue::DegenerateTriangle(*vertices));
+        }
+    }
+
+    /// The issues reported, in the order they were found
+    pub fn into_issues(self) -> Vec<SceneIssue> {
+        self.issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::materials::LambertianMaterial;
+
+    #[test]
+    fn shared_materials_are_checked_once() {
+        let material: Arc<dyn Material> = Arc::new(LambertianMaterial {
+            diffuse_strength: -1.0,
+            ..LambertianMaterial::new_dummy()
+        });
+        let mut target = SceneValidator::new();
+        target.check_material(&material);
+        target.check_material(&Arc::clone(&material));
+        assert!(
+            target.into_issues()
+                == vec![SceneIssue::NegativeStrength {
+                    parameter: "diffuse_strength",
+                    value: -1.0
+                }]
+        );
+    }
+
+    #[test]
+    fn degenerate_triangles_are_warnings() {
+        let mut target = SceneValidator::new();
+        let point = Vec3::new(1.0, 2.0, 3.0);
+        target.check_triangle(&[point, point, Vec3::zeros()]);
+        let issues = target.into_issues();
+        assert!(issues.len() == 1 && !issues[0].is_error());
+    }
+}